@@ -0,0 +1,280 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use expression_engine::{
+    create_context, parse_arena, parse_expression, Compiler, Context, EvalOptions, Map, Value, Vm,
+};
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+
+// Roughly 1KB of mixed arithmetic: "1 * 2 + 3 - 4 / 5 + ...".
+fn arithmetic_expression() -> String {
+    let ops = ["*", "+", "-", "/", "+"];
+    let mut s = String::from("1");
+    let mut i = 2;
+    while s.len() < 1024 {
+        s.push_str(&format!(" {} {}", ops[i % ops.len()], i));
+        i += 1;
+    }
+    s
+}
+
+fn chain_expression() -> String {
+    let mut stmts = vec!["a = 0".to_string()];
+    for i in 1..50 {
+        stmts.push(format!("a += {} * 2 - 1", i));
+    }
+    stmts.join("; ")
+}
+
+fn deep_expression() -> String {
+    vec!["1"; 1000].join(" + ")
+}
+
+fn literal_expression() -> String {
+    let list: Vec<String> = (0..1000).map(|i| i.to_string()).collect();
+    let map: Vec<String> = (0..1000).map(|i| format!("'k{}': {}", i, i)).collect();
+    format!("[[{}], {{{}}}]", list.join(", "), map.join(", "))
+}
+
+fn function_expression() -> String {
+    let calls: Vec<String> = (0..20)
+        .map(|i| format!("max(a, {}, min(b, {}))", i, i + 1))
+        .collect();
+    calls.join(" + ")
+}
+
+// 50 calls mixing inner functions with one defined on the context.
+fn function_rule_50() -> String {
+    let calls: Vec<String> = (0..25)
+        .map(|i| format!("max(a, {}) + score(b, {})", i, i))
+        .collect();
+    calls.join(" + ")
+}
+
+fn rule_context() -> Context {
+    create_context!(
+        "a" => 3,
+        "b" => 7,
+        "c" => "haha",
+        "d" => true,
+        "score" => Arc::new(|params| Ok(params[1].clone()))
+    )
+}
+
+fn bench_parse(c: &mut Criterion) {
+    let arithmetic = arithmetic_expression();
+    c.bench_function("parse_arithmetic_1kb", |b| {
+        b.iter(|| parse_expression(black_box(&arithmetic)))
+    });
+    let chain = chain_expression();
+    c.bench_function("parse_chain_50", |b| {
+        b.iter(|| parse_expression(black_box(&chain)))
+    });
+}
+
+fn bench_exec(c: &mut Criterion) {
+    let deep = deep_expression();
+    let ast = parse_expression(&deep).unwrap();
+    c.bench_function("exec_deep_binary_1000", |b| {
+        b.iter(|| ast.exec(&mut Context::new()))
+    });
+    c.bench_function("exec_iterative_deep_binary_1000", |b| {
+        b.iter(|| ast.exec_iterative(&mut Context::new()))
+    });
+    // The same with a cancellation token that is read every few hundred
+    // nodes, which should cost next to nothing.
+    let token = Arc::new(AtomicBool::new(false));
+    c.bench_function("exec_deep_binary_1000_cancellable", |b| {
+        b.iter(|| {
+            let mut ctx = Context::new();
+            ctx.set_cancellation(Some(token.clone()));
+            ast.exec(&mut ctx)
+        })
+    });
+
+    let literal = literal_expression();
+    let ast = parse_expression(&literal).unwrap();
+    c.bench_function("exec_list_map_1000", |b| {
+        b.iter(|| ast.exec(&mut Context::new()))
+    });
+
+    let functions = function_expression();
+    let ast = parse_expression(&functions).unwrap();
+    c.bench_function("exec_function_calls_20", |b| {
+        b.iter(|| ast.exec(&mut rule_context()))
+    });
+}
+
+fn bench_precompiled(c: &mut Criterion) {
+    let input = "a > 2 && (c beginWith 'ha') ? [a, b, a * b] : d";
+    let ast = parse_expression(input).unwrap();
+    assert_eq!(
+        ast.exec(&mut rule_context()).unwrap(),
+        Value::List(vec![3.into(), 7.into(), 21.into()])
+    );
+    c.bench_function("exec_precompiled_fresh_context", |b| {
+        b.iter(|| ast.exec(&mut rule_context()))
+    });
+}
+
+fn bench_vm(c: &mut Criterion) {
+    let arithmetic = arithmetic_expression();
+    let ast = parse_expression(&arithmetic).unwrap();
+    c.bench_function("exec_arithmetic_1kb", |b| {
+        b.iter(|| ast.exec(&mut Context::new()))
+    });
+    let program = Compiler::compile(&ast).unwrap();
+    c.bench_function("vm_arithmetic_1kb", |b| {
+        b.iter(|| Vm::run(&program, &mut Context::new()))
+    });
+
+    let rule = function_rule_50();
+    let ast = parse_expression(&rule).unwrap();
+    c.bench_function("exec_function_calls_50", |b| {
+        b.iter(|| ast.exec(&mut rule_context()))
+    });
+    let program = Compiler::compile(&ast).unwrap();
+    c.bench_function("vm_function_calls_50", |b| {
+        b.iter(|| Vm::run(&program, &mut rule_context()))
+    });
+
+    let functions = function_expression();
+    let program = Compiler::compile(&parse_expression(&functions).unwrap()).unwrap();
+    c.bench_function("vm_function_calls_20", |b| {
+        b.iter(|| Vm::run(&program, &mut rule_context()))
+    });
+}
+
+fn bench_map(c: &mut Criterion) {
+    let m: Map = (0..10_000)
+        .map(|i| (Value::from(format!("k{}", i)), Value::from(i)))
+        .collect();
+    let mut reversed = m.clone();
+    reversed.reverse();
+    let ctx = || create_context!("m" => Value::Map(m.clone()), "r" => Value::Map(reversed.clone()));
+
+    let ast = parse_expression("'k9999' in m").unwrap();
+    let mut lookup_ctx = ctx();
+    c.bench_function("exec_map_lookup_10k", |b| {
+        b.iter(|| ast.exec(&mut lookup_ctx))
+    });
+
+    let ast = parse_expression("m == r").unwrap();
+    let mut eq_ctx = ctx();
+    c.bench_function("exec_map_eq_10k", |b| b.iter(|| ast.exec(&mut eq_ctx)));
+}
+
+fn bench_print(c: &mut Criterion) {
+    // About 10k nodes: 2500 calls of `f(a, 1)` summed up.
+    let input = vec!["f(a, 1)"; 2500].join(" + ");
+    let ast = parse_expression(&input).unwrap();
+    c.bench_function("expr_10k_nodes", |b| b.iter(|| ast.expr()));
+}
+
+// The same large expressions as boxed `ExprAST`s and as an `ExprArena`.
+fn bench_arena(c: &mut Criterion) {
+    let arithmetic = arithmetic_expression();
+    c.bench_function("parse_arena_arithmetic_1kb", |b| {
+        b.iter(|| parse_arena(black_box(&arithmetic)))
+    });
+    let literal = literal_expression();
+    c.bench_function("parse_list_map_1000", |b| {
+        b.iter(|| parse_expression(black_box(&literal)))
+    });
+    c.bench_function("parse_arena_list_map_1000", |b| {
+        b.iter(|| parse_arena(black_box(&literal)))
+    });
+
+    let ast = parse_expression(&arithmetic).unwrap();
+    let arena = parse_arena(&arithmetic).unwrap();
+    c.bench_function("clone_arithmetic_1kb", |b| b.iter(|| ast.clone()));
+    c.bench_function("clone_arena_arithmetic_1kb", |b| b.iter(|| arena.clone()));
+    c.bench_function("exec_arena_arithmetic_1kb", |b| {
+        b.iter(|| arena.exec(&mut Context::new()))
+    });
+
+    let ast = parse_expression(&literal).unwrap();
+    let arena = parse_arena(&literal).unwrap();
+    c.bench_function("clone_list_map_1000", |b| b.iter(|| ast.clone()));
+    c.bench_function("clone_arena_list_map_1000", |b| b.iter(|| arena.clone()));
+    c.bench_function("exec_arena_list_map_1000", |b| {
+        b.iter(|| arena.exec(&mut Context::new()))
+    });
+}
+
+// Euclidean distances, in `Decimal` and with `EvalOptions::float_math`.
+fn bench_float_math(c: &mut Criterion) {
+    let input = vec!["sqrt(x * x + y * y) / 3"; 100].join(" + ");
+    let ast = parse_expression(&input).unwrap();
+    let ctx = |float_math| {
+        let mut ctx = Context::with_options(EvalOptions {
+            float_math,
+            ..EvalOptions::default()
+        });
+        ctx.set_variable("x", Value::from(3.7));
+        ctx.set_variable("y", Value::from(12.25));
+        ctx
+    };
+    let mut decimal_ctx = ctx(false);
+    c.bench_function("exec_sqrt_100_decimal", |b| {
+        b.iter(|| ast.exec(&mut decimal_ctx))
+    });
+    let mut float_ctx = ctx(true);
+    c.bench_function("exec_sqrt_100_float", |b| {
+        b.iter(|| ast.exec(&mut float_ctx))
+    });
+}
+
+// A rule over 50 sensor readings, re-evaluated on every tick after 3 of
+// them change, against one context updated in place and against a context
+// rebuilt from all 50.
+fn bench_ticks(c: &mut Criterion) {
+    let readings: Vec<(String, Value)> = (0..50)
+        .map(|i| (format!("sensor{}", i), Value::from(i)))
+        .collect();
+    let rule = "sensor1 > 20 && sensor2 < 40 || max(sensor7, sensor12) * 2 >= sensor30 + 5 \
+                ? sensor1 - sensor2 : sensor49";
+    let ast = parse_expression(rule).unwrap();
+    let changes = |tick: i64| {
+        [
+            ("sensor1", Value::from(tick % 50)),
+            ("sensor2", Value::from(tick % 30)),
+            ("sensor7", Value::from(tick % 10)),
+        ]
+    };
+
+    let mut ctx = Context::new();
+    ctx.reserve(readings.len());
+    ctx.update_from(readings.iter().cloned());
+    let mut tick = 0;
+    c.bench_function("exec_tick_update_3_of_50", |b| {
+        b.iter(|| {
+            tick += 1;
+            ctx.update_from(changes(tick));
+            ast.exec(&mut ctx)
+        })
+    });
+    let mut tick = 0;
+    c.bench_function("exec_tick_rebuild_50", |b| {
+        b.iter(|| {
+            tick += 1;
+            let mut ctx = Context::new();
+            ctx.update_from(readings.iter().cloned());
+            ctx.update_from(changes(tick));
+            ast.exec(&mut ctx)
+        })
+    });
+}
+
+criterion_group!(
+    benches,
+    bench_parse,
+    bench_exec,
+    bench_precompiled,
+    bench_vm,
+    bench_map,
+    bench_arena,
+    bench_print,
+    bench_float_math,
+    bench_ticks
+);
+criterion_main!(benches);
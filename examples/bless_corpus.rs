@@ -0,0 +1,73 @@
+//! Rewrites the snapshots of the expression corpus in `tests/corpus`.
+//!
+//! ```text
+//! cargo run --example bless_corpus            # every *.expr file
+//! cargo run --example bless_corpus strings    # only strings.expr
+//! ```
+//!
+//! `safe_mode.snap`, the expressions that fail only under
+//! `EvalOptions::safe()`, is always rewritten.
+//!
+//! Run it after adding expressions or changing behavior on purpose, and
+//! review the diff of the `*.snap` files before committing it.
+#[path = "../tests/corpus/harness.rs"]
+mod harness;
+
+use std::fs;
+use std::path::Path;
+use std::process::ExitCode;
+
+fn main() -> ExitCode {
+    let names: Vec<String> = std::env::args().skip(1).collect();
+    let files = match harness::expr_files() {
+        Ok(files) => files,
+        Err(err) => {
+            eprintln!("error: {}: {}", harness::corpus_dir().display(), err);
+            return ExitCode::FAILURE;
+        }
+    };
+    let mut status = ExitCode::SUCCESS;
+    for path in files {
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        if !names.is_empty() && !names.iter().any(|name| *name == stem) {
+            continue;
+        }
+        let result = fs::read_to_string(&path)
+            .map_err(|err| err.to_string())
+            .and_then(|source| harness::render(&source));
+        if !bless(&path, &harness::snap_path(&path), result) {
+            status = ExitCode::FAILURE;
+        }
+    }
+    // Lists the expressions of every file, so it's rewritten whenever one
+    // may have changed.
+    let snap = harness::safe_mode_snap_path();
+    if !bless(&harness::corpus_dir(), &snap, harness::render_safe_mode()) {
+        status = ExitCode::FAILURE;
+    }
+    status
+}
+
+// Writes `result` to `snap` if it differs, returning whether that worked.
+fn bless(source: &Path, snap: &Path, result: Result<String, String>) -> bool {
+    let rendered = match result {
+        Ok(rendered) => rendered,
+        Err(msg) => {
+            eprintln!("error: {}: {}", source.display(), msg);
+            return false;
+        }
+    };
+    if fs::read_to_string(snap).ok().as_deref() == Some(rendered.as_str()) {
+        return true;
+    }
+    match fs::write(snap, rendered) {
+        Ok(()) => {
+            println!("blessed {}", snap.display());
+            true
+        }
+        Err(err) => {
+            eprintln!("error: {}: {}", snap.display(), err);
+            false
+        }
+    }
+}
@@ -0,0 +1,177 @@
+//! An interactive shell for trying out expressions.
+//!
+//! ```text
+//! cargo run --example repl
+//! > a = 3
+//! > a + 1
+//! 4 (Number)
+//! ```
+//!
+//! Variables assigned on one line stay available on the following ones.
+//! Besides expressions, the shell understands `:vars`, `:funcs`,
+//! `:ast <expr>`, `:help` and `:quit`.
+use expression_engine::{list_with_metadata, parse_expression, validate, Context, Error, Value};
+use std::io::{self, BufRead, Write};
+
+const HELP: &str = "\
+<expr>        evaluate an expression
+:vars         list the variables of the context
+:funcs        list the available functions, help('name') describes one
+:ast <expr>   print the parsed expression
+:help         print this message
+:quit         leave the shell";
+
+fn main() -> io::Result<()> {
+    let stdin = io::stdin();
+    run(stdin.lock(), io::stdout(), true)
+}
+
+fn run(input: impl BufRead, mut output: impl Write, prompt: bool) -> io::Result<()> {
+    let mut ctx = Context::new();
+    let mut lines = input.lines();
+    loop {
+        if prompt {
+            write!(output, "> ")?;
+            output.flush()?;
+        }
+        let line = match lines.next() {
+            Some(line) => line?,
+            None => return Ok(()),
+        };
+        let line = line.trim();
+        match line.split_once(' ').unwrap_or((line, "")) {
+            ("", _) => {}
+            (":quit", _) => return Ok(()),
+            (":help", _) => writeln!(output, "{}", HELP)?,
+            (":vars", _) => print_vars(&ctx, &mut output)?,
+            (":funcs", _) => print_funcs(&ctx, &mut output)?,
+            (":ast", expr) => match parse_expression(expr) {
+                Ok(ast) => writeln!(output, "{}", ast.dump())?,
+                Err(err) => print_error(expr, &err, &mut output)?,
+            },
+            _ => evaluate(line, &mut ctx, &mut output)?,
+        }
+    }
+}
+
+// Unknown functions are reported with a suggestion before anything runs.
+fn evaluate(line: &str, ctx: &mut Context, output: &mut impl Write) -> io::Result<()> {
+    let errors = validate(line, ctx);
+    if !errors.is_empty() {
+        for err in errors {
+            print_error(line, &err, output)?;
+        }
+        return Ok(());
+    }
+    match parse_expression(line).and_then(|ast| ast.exec(ctx)) {
+        Ok(Value::None) => Ok(()),
+        Ok(value) => writeln!(output, "{} ({})", value, value.type_name()),
+        Err(err) => print_error(line, &err, output),
+    }
+}
+
+fn print_vars(ctx: &Context, output: &mut impl Write) -> io::Result<()> {
+    for (name, value) in ctx.variables() {
+        writeln!(output, "{} = {} ({})", name, value, value.type_name())?;
+    }
+    Ok(())
+}
+
+// Context functions have no metadata and shadow inner ones of the same name.
+fn print_funcs(ctx: &Context, output: &mut impl Write) -> io::Result<()> {
+    let mut funcs: Vec<String> = ctx
+        .function_names()
+        .into_iter()
+        .map(|name| format!("{}(...)", name))
+        .collect();
+    funcs.extend(
+        list_with_metadata()
+            .into_iter()
+            .filter(|metadata| ctx.get(&metadata.name).is_none())
+            .map(|metadata| metadata.signature()),
+    );
+    funcs.sort();
+    for func in funcs {
+        writeln!(output, "{}", func)?;
+    }
+    Ok(())
+}
+
+fn print_error(line: &str, err: &Error, output: &mut impl Write) -> io::Result<()> {
+    if let Some(position) = err.position() {
+        let column = line[..position.min(line.len())].chars().count();
+        writeln!(output, "  {}", line)?;
+        writeln!(output, "  {}^", " ".repeat(column))?;
+    }
+    writeln!(output, "error: {}", err)
+}
+
+#[cfg(all(test, feature = "default"))]
+mod tests {
+    use super::run;
+
+    fn session(input: &str) -> String {
+        let mut output = Vec::new();
+        run(input.as_bytes(), &mut output, false).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn test_session() {
+        let output = session(
+            "a = 3\n\
+             a + 1\n\
+             b = [a, 'x', a > 2]\n\
+             :vars\n\
+             {'k': 1.5}\n\
+             :quit\n\
+             a\n",
+        );
+        assert_eq!(
+            output,
+            "4 (Number)\n\
+             a = 3 (Number)\n\
+             b = [3, 'x', true] (List)\n\
+             {'k': 1.5} (Map)\n"
+        );
+    }
+
+    #[test]
+    fn test_ast() {
+        let output = session(":ast 1 + a\n");
+        assert_eq!(
+            output,
+            "Binary AST: Op: +, Lhs: Literal AST: Number: 1, Rhs: Reference AST: reference: a\n"
+        );
+    }
+
+    #[test]
+    fn test_funcs() {
+        let output = session(":funcs\n");
+        assert!(output.contains("\nmath.max(...values: Number)\n"));
+        assert!(output.contains("\nmath.round(x: Number, dp?: Number, mode?: String)\n"));
+    }
+
+    #[test]
+    fn test_unknown_function() {
+        let output = session("roud(1.5)\nhelp('round')\n");
+        assert_eq!(
+            output,
+            "  roud(1.5)\n  ^\n\
+             error: unknown function 'roud', did you mean 'round' or 'ord'?\n\
+             'math.round(x: Number, dp?: Number, mode?: String)\n\
+             Rounds x to dp decimal places, 0 by default. mode is one of half_up (the default), half_down, half_even, up, down, ceiling or floor.' (String)\n"
+        );
+    }
+
+    #[test]
+    fn test_error_caret() {
+        let output = session("1 + 'abc\n");
+        assert_eq!(
+            output,
+            "  1 + 'abc\n          ^\nerror: unterminated string: 8\n"
+        );
+        let output = session("1 + true\n");
+        assert_eq!(output, "error: should be number: in '1 + true'\n");
+    }
+}
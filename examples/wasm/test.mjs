@@ -0,0 +1,26 @@
+// Smoke test for the wasm bindings.
+//
+// Build the package first from the repository root:
+//   cargo rustc --lib --release --target wasm32-unknown-unknown --crate-type cdylib --features wasm
+//   wasm-bindgen --target nodejs --out-dir pkg target/wasm32-unknown-unknown/release/expression_engine.wasm
+// then run:
+//   node examples/wasm/test.mjs
+import assert from "node:assert/strict";
+import { createRequire } from "node:module";
+
+const require = createRequire(import.meta.url);
+const engine = require("../../pkg/expression_engine.js");
+
+assert.equal(engine.evaluate("a + 1", '{"a": 2}'), '"3"');
+assert.equal(engine.evaluate("0.1 + 0.2", "{}"), '"0.3"');
+assert.deepEqual(JSON.parse(engine.evaluate("[a, b > 1]", '{"a": "x", "b": 2}')), ["x", true]);
+
+const compiled = engine.compile("price * qty");
+assert.equal(compiled.evaluate('{"price": 2.5, "qty": 4}'), '"10.0"');
+assert.equal(compiled.expr(), "price * qty");
+assert.throws(() => engine.compile("(1"));
+
+assert.deepEqual(engine.validate("1 + 2"), []);
+assert.deepEqual(engine.validate("'abc"), [{ message: "unterminated string: 4", position: 4 }]);
+
+console.log("wasm bindings ok");
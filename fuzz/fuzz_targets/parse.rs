@@ -0,0 +1,11 @@
+//! Parses any input into a tree and into an arena, which must give an
+//! error rather than panic.
+#![no_main]
+
+use expression_engine::{parse_arena, parse_expression};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|input: &str| {
+    let _ = parse_expression(input);
+    let _ = parse_arena(input);
+});
@@ -0,0 +1,16 @@
+//! Parses any input, prints what parsed with `expr()` and parses that
+//! again.
+#![no_main]
+
+use expression_engine::parse_expression;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|input: &str| {
+    if let Ok(ast) = parse_expression(input) {
+        let text = ast.expr();
+        let reparsed = parse_expression(&text);
+        if let Ok(reparsed) = &reparsed {
+            let _ = reparsed.expr();
+        }
+    }
+});
@@ -0,0 +1,17 @@
+//! Prints an arbitrary tree, parses the text and compiles and prints what
+//! parsed.
+#![no_main]
+
+use expression_engine::{parse_expression, Compiler, ExprAST, ExprArena};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|ast: ExprAST| {
+    let text = ast.expr();
+    let _ = ast.dump();
+    let _ = Compiler::compile(&ast);
+    let _ = ExprArena::from(&ast);
+    let reparsed = parse_expression(&text);
+    if let Ok(reparsed) = &reparsed {
+        let _ = reparsed.expr();
+    }
+});
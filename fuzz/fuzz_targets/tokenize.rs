@@ -0,0 +1,13 @@
+//! Tokenizes any input, which must end in a token or an error.
+#![no_main]
+
+use expression_engine::Tokenizer;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|input: &str| {
+    for token in Tokenizer::new(input).spanned_with_trivia() {
+        if token.is_err() {
+            break;
+        }
+    }
+});
@@ -0,0 +1,72 @@
+#ifndef EXPRESSION_ENGINE_H
+#define EXPRESSION_ENGINE_H
+
+/* Generated by cbindgen from src/ffi.rs, do not edit by hand. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * A compiled expression, parsed once by `expr_compile` and run by the VM on
+ * each evaluation. It is never mutated after `expr_compile`, so one handle
+ * may be evaluated from several threads at the same time.
+ */
+typedef struct ExprHandle ExprHandle;
+
+/**
+ * Compiles `expr`, returning a handle to release with `expr_free`, or null
+ * on failure, in which case `expr_last_error` describes the problem.
+ *
+ * # Safety
+ *
+ * `expr` must be null or a valid nul-terminated string.
+ */
+struct ExprHandle *expr_compile(const char *expr);
+
+/**
+ * Evaluates a compiled expression against a context given as a JSON object
+ * (`ctx_json` may be null for an empty context). Returns 0 and stores the
+ * JSON result in `out_json` on success; returns -1 and stores the message in
+ * `out_err` on failure. Either output pointer may be null if not wanted.
+ *
+ * # Safety
+ *
+ * `handle` must come from `expr_compile` and not have been freed; `ctx_json`
+ * must be null or a valid nul-terminated string; `out_json` and `out_err`
+ * must be null or valid for writes.
+ */
+int expr_eval_json(const struct ExprHandle *handle,
+                   const char *ctx_json,
+                   char **out_json,
+                   char **out_err);
+
+/**
+ * Releases a handle returned by `expr_compile`. Null is ignored.
+ *
+ * # Safety
+ *
+ * `handle` must be null or come from `expr_compile`, and must not be used
+ * after this call.
+ */
+void expr_free(struct ExprHandle *handle);
+
+/**
+ * Releases a string returned through `out_json` or `out_err`. Null is ignored.
+ *
+ * # Safety
+ *
+ * `s` must be null or a string handed out by this library, and must not be
+ * used after this call.
+ */
+void expr_string_free(char *s);
+
+/**
+ * Returns the last error raised on the calling thread, or null if there was
+ * none. The string is owned by the library and stays valid until the next
+ * failing call on the same thread.
+ */
+const char *expr_last_error(void);
+
+#endif  /* EXPRESSION_ENGINE_H */
@@ -0,0 +1,173 @@
+use crate::parser::{ExprAST, Literal};
+use crate::tokenizer::string_quotes;
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use arbitrary::{Arbitrary, Result, Unstructured};
+use rust_decimal::Decimal;
+
+// Trees nest at most this deep, which keeps the cases small.
+const MAX_DEPTH: usize = 8;
+// The most elements of a list, params of a call, entries of a map or arms
+// of a match.
+const MAX_ELEMENTS: usize = 4;
+
+const REFERENCES: &[&str] = &["a", "b", "n", "s", "list", "user", "user.name", "user?.age"];
+const FUNCTIONS: &[&str] = &[
+    "max", "min", "sum", "len", "round", "format", "coalesce", "ifnull", "range", "repeat",
+];
+const PREFIX_OPS: &[&str] = &["-", "+", "!", "not", "~"];
+const POSTFIX_OPS: &[&str] = &["++", "--"];
+const INFIX_OPS: &[&str] = &[
+    "+",
+    "-",
+    "*",
+    "/",
+    "%",
+    "<",
+    "<=",
+    ">",
+    ">=",
+    "==",
+    "!=",
+    "&&",
+    "||",
+    "??",
+    "&",
+    "|",
+    "^",
+    "<<",
+    ">>",
+    "in",
+    "contains",
+    "containsAll",
+    "containsAny",
+    "between",
+    "beginWith",
+    "endWith",
+];
+const SETTER_OPS: &[&str] = &[
+    "=", "+=", "-=", "*=", "/=", "%=", "&=", "|=", "^=", "<<=", ">>=",
+];
+
+/// Generates trees the parser could have built: names, operators and
+/// functions come from the built-in ones, assignments only target
+/// references, spreads only appear among the elements of a list or the
+/// params of a call and statements only at the top. Meant for fuzzing the
+/// printer, the parser and evaluation with structured input.
+impl<'a> Arbitrary<'a> for ExprAST<'a> {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        if u.ratio(1, 8)? {
+            let len = u.int_in_range(2..=MAX_ELEMENTS)?;
+            let exprs = (0..len)
+                .map(|_| expr(u, MAX_DEPTH))
+                .collect::<Result<_>>()?;
+            return Ok(ExprAST::Stmt(exprs));
+        }
+        expr(u, MAX_DEPTH)
+    }
+}
+
+fn expr<'a>(u: &mut Unstructured<'a>, depth: usize) -> Result<ExprAST<'a>> {
+    if depth == 0 || u.is_empty() {
+        return leaf(u);
+    }
+    let depth = depth - 1;
+    let ans = match u.int_in_range(0..=10)? {
+        0 | 1 => leaf(u)?,
+        2 => ExprAST::Unary(u.choose(PREFIX_OPS)?, Box::new(expr(u, depth)?)),
+        3 => ExprAST::Postfix(Box::new(expr(u, depth)?), u.choose(POSTFIX_OPS)?),
+        4 => ExprAST::Binary(
+            u.choose(INFIX_OPS)?,
+            Box::new(expr(u, depth)?),
+            Box::new(expr(u, depth)?),
+        ),
+        5 => ExprAST::Binary(
+            u.choose(SETTER_OPS)?,
+            Box::new(ExprAST::Reference(u.choose(REFERENCES)?)),
+            Box::new(expr(u, depth)?),
+        ),
+        6 => ExprAST::Ternary(
+            Box::new(expr(u, depth)?),
+            Box::new(expr(u, depth)?),
+            Box::new(expr(u, depth)?),
+        ),
+        7 => ExprAST::Function(u.choose(FUNCTIONS)?, params(u, depth)?),
+        8 => ExprAST::List(params(u, depth)?),
+        9 => ExprAST::Map(pairs(u, depth)?),
+        _ => {
+            let default = match bool::arbitrary(u)? {
+                true => Some(Box::new(expr(u, depth)?)),
+                false => None,
+            };
+            ExprAST::Match(Box::new(expr(u, depth)?), pairs(u, depth)?, default)
+        }
+    };
+    Ok(ans)
+}
+
+fn leaf<'a>(u: &mut Unstructured<'a>) -> Result<ExprAST<'a>> {
+    let ans = match u.int_in_range(0..=4)? {
+        0 => {
+            let scale = u.int_in_range(0..=3)?;
+            ExprAST::Literal(Literal::Number(Decimal::new(
+                u.int_in_range(0..=100_000)?,
+                scale,
+            )))
+        }
+        1 => ExprAST::Literal(Literal::Bool(bool::arbitrary(u)?)),
+        // Strings no quotes fit can't be printed, and are cut before their
+        // first `"`.
+        2 => {
+            let s = <&str>::arbitrary(u)?;
+            let s = match string_quotes(s, '"') {
+                Some(_) => s,
+                None => s.split('"').next().unwrap_or_default(),
+            };
+            ExprAST::Literal(Literal::String(s))
+        }
+        3 => ExprAST::Literal(Literal::None),
+        _ => ExprAST::Reference(u.choose(REFERENCES)?),
+    };
+    Ok(ans)
+}
+
+fn params<'a>(u: &mut Unstructured<'a>, depth: usize) -> Result<Vec<ExprAST<'a>>> {
+    let len = u.int_in_range(0..=MAX_ELEMENTS)?;
+    (0..len)
+        .map(|_| match u.ratio(1, 5)? {
+            true => Ok(ExprAST::Spread(Box::new(expr(u, depth)?))),
+            false => expr(u, depth),
+        })
+        .collect()
+}
+
+fn pairs<'a>(u: &mut Unstructured<'a>, depth: usize) -> Result<Vec<(ExprAST<'a>, ExprAST<'a>)>> {
+    let len = u.int_in_range(0..=MAX_ELEMENTS)?;
+    (0..len)
+        .map(|_| Ok((expr(u, depth)?, expr(u, depth)?)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser::ExprAST;
+    use arbitrary::{Arbitrary, Unstructured};
+
+    // Every byte string gives a tree, and its text parses without panicking.
+    #[test]
+    fn test_arbitrary_expr() {
+        let mut seed = 0x2545_f491_u32;
+        for len in 0..400 {
+            let data: Vec<u8> = (0..len)
+                .map(|_| {
+                    seed ^= seed << 13;
+                    seed ^= seed >> 17;
+                    seed ^= seed << 5;
+                    seed as u8
+                })
+                .collect();
+            let ast = ExprAST::arbitrary(&mut Unstructured::new(&data)).unwrap();
+            let _ = crate::parse_expression(&ast.expr());
+        }
+    }
+}
@@ -0,0 +1,485 @@
+use crate::context::Context;
+use crate::define::Result;
+use crate::error::Error;
+use crate::function::InnerFunctionManager;
+use crate::operator::{
+    InfixOpConfig, InfixOpManager, InfixOpType, PostfixOpManager, PrefixOpManager,
+};
+use crate::options::ParseOptions;
+use crate::parser::{Builder, ExprAST, Literal, Parser};
+use crate::token::Span;
+use crate::value::{Map, Value};
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+/// The index of a node in an [`ExprArena`].
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct NodeId(u32);
+
+/// A run of sibling nodes, e.g. the params of a call, see
+/// [`ExprArena::children`].
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct Children {
+    start: u32,
+    end: u32,
+}
+
+impl Children {
+    pub fn len(&self) -> usize {
+        (self.end - self.start) as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+}
+
+/// A node of an [`ExprArena`], shaped like the [`ExprAST`] variant of the
+/// same name. The pairs of `Map` and of the arms of `Match` are stored
+/// flat, key then value.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Node<'a> {
+    Literal(Literal<'a>),
+    Unary(&'a str, NodeId),
+    Binary(&'a str, NodeId, NodeId),
+    Postfix(NodeId, &'a str),
+    Ternary(NodeId, NodeId, NodeId),
+    Reference(&'a str),
+    Function(&'a str, Children),
+    List(Children),
+    Map(Children),
+    Match(NodeId, Children, Option<NodeId>),
+    Stmt(Children),
+    Spread(NodeId),
+    None,
+}
+
+/// An expression stored as one `Vec` of nodes that refer to each other by
+/// index, instead of a tree of boxes. Parsing into it with
+/// [`crate::parse_arena`] allocates a handful of times rather than once per
+/// node, cloning it copies two `Vec`s, and dropping it never recurses.
+/// Children always come before their parent.
+///
+/// It converts to and from [`ExprAST`] for everything that takes one.
+#[derive(Clone, PartialEq, Debug)]
+pub struct ExprArena<'a> {
+    nodes: Vec<Node<'a>>,
+    children: Vec<NodeId>,
+    root: NodeId,
+}
+
+impl<'a> ExprArena<'a> {
+    fn new() -> Self {
+        Self {
+            nodes: Vec::new(),
+            children: Vec::new(),
+            root: NodeId(0),
+        }
+    }
+
+    pub(crate) fn parse(input: &'a str) -> Result<Self> {
+        let mut parser = Parser::with_builder(input, Self::new(), ParseOptions::default())?;
+        let root = parser.parse_stmt()?;
+        let mut arena = parser.into_builder();
+        arena.root = root;
+        Ok(arena)
+    }
+
+    pub fn root(&self) -> NodeId {
+        self.root
+    }
+
+    pub fn node(&self, id: NodeId) -> &Node<'a> {
+        &self.nodes[id.0 as usize]
+    }
+
+    pub fn children(&self, children: Children) -> &[NodeId] {
+        &self.children[children.start as usize..children.end as usize]
+    }
+
+    /// The number of nodes.
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    fn push(&mut self, node: Node<'a>) -> NodeId {
+        self.nodes.push(node);
+        NodeId(self.nodes.len() as u32 - 1)
+    }
+
+    fn push_children(&mut self, ids: impl IntoIterator<Item = NodeId>) -> Children {
+        let start = self.children.len() as u32;
+        self.children.extend(ids);
+        Children {
+            start,
+            end: self.children.len() as u32,
+        }
+    }
+
+    /// Evaluates the expression like [`ExprAST::exec`], walking the nodes by
+    /// index. Unlike `exec`, [`ExprAST::exec_iterative`] and the VM, it
+    /// returns the bare error, and it neither memoizes nor calls the trace
+    /// hook.
+    pub fn exec(&self, ctx: &mut Context) -> Result<Value> {
+        self.exec_node(self.root, ctx)
+    }
+
+    fn exec_node(&self, id: NodeId, ctx: &mut Context) -> Result<Value> {
+        ctx.tick()?;
+        match self.node(id) {
+            Node::Literal(Literal::Bool(value)) => Ok(Value::from(*value)),
+            Node::Literal(Literal::Number(value)) => Ok(Value::from(*value)),
+            Node::Literal(Literal::String(value)) => Ok(Value::from(*value)),
+            Node::Literal(Literal::None) => Ok(Value::None),
+            Node::Reference(name) => ctx.value(name),
+            Node::Function(name, params) => {
+                let params = self.exec_params(*params, ctx)?;
+                ctx.check_call(name)?;
+                let ans = match ctx.get_func(name) {
+                    Some(func) => func(params)?,
+                    None => InnerFunctionManager::new().resolve(name, ctx)?(ctx, params)?,
+                };
+                ctx.options().limits.check(ans)
+            }
+            Node::Unary(op, rhs) => {
+                let rhs = ctx
+                    .options()
+                    .prefix_operand(op, self.exec_node(*rhs, ctx)?, ctx);
+                PrefixOpManager::new().get(op)?(rhs)
+            }
+            Node::Binary(op, lhs, rhs) => self.exec_binary(op, *lhs, *rhs, ctx),
+            Node::Postfix(lhs, op) => PostfixOpManager::new().get(op)?(self.exec_node(*lhs, ctx)?),
+            Node::Ternary(condition, lhs, rhs) => {
+                let condition = self.exec_node(*condition, ctx)?;
+                if ctx.options().condition(condition, ctx)? {
+                    return self.exec_node(*lhs, ctx);
+                }
+                self.exec_node(*rhs, ctx)
+            }
+            Node::List(params) => {
+                let list = Value::List(self.exec_params(*params, ctx)?);
+                ctx.options().limits.check(list)
+            }
+            Node::Map(m) => {
+                let mut ans = Map::with_capacity_and_hasher(m.len() / 2, Default::default());
+                for pair in self.children(*m).chunks(2) {
+                    let k = self.exec_node(pair[0], ctx)?;
+                    let v = self.exec_node(pair[1], ctx)?;
+                    ctx.options().insert_key(&mut ans, k, v)?;
+                }
+                ctx.options().limits.check(Value::Map(ans))
+            }
+            Node::Match(scrutinee, arms, default) => {
+                let scrutinee = self.exec_node(*scrutinee, ctx)?;
+                for arm in self.children(*arms).chunks(2) {
+                    if self.exec_node(arm[0], ctx)? == scrutinee {
+                        return self.exec_node(arm[1], ctx);
+                    }
+                }
+                match default {
+                    Some(default) => self.exec_node(*default, ctx),
+                    None => ctx.options().no_match(&scrutinee),
+                }
+            }
+            Node::Stmt(exprs) => {
+                let mut ans = Value::None;
+                for expr in self.children(*exprs) {
+                    ans = self.exec_node(*expr, ctx)?;
+                }
+                Ok(ans)
+            }
+            Node::Spread(expr) => Ok(Value::List(self.exec_node(*expr, ctx)?.spread()?)),
+            Node::None => Ok(Value::None),
+        }
+    }
+
+    fn exec_binary(&self, op: &str, lhs: NodeId, rhs: NodeId, ctx: &mut Context) -> Result<Value> {
+        let InfixOpConfig(_, op_type, _, handler) = InfixOpManager::new().get(op)?;
+        match op_type {
+            InfixOpType::CALC => {
+                let (a, b) = (self.exec_node(lhs, ctx)?, self.exec_node(rhs, ctx)?);
+                ctx.options().infix(op, &handler, a, b, ctx)
+            }
+            InfixOpType::SETTER => {
+                let Node::Reference(name) = self.node(lhs) else {
+                    return Err(Error::NotReferenceExpr);
+                };
+                let a = ctx.target_value(name)?;
+                let b = self.exec_node(rhs, ctx)?;
+                ctx.options().assign(op, &handler, a, b, name, ctx)
+            }
+        }
+    }
+
+    fn exec_params(&self, params: Children, ctx: &mut Context) -> Result<Vec<Value>> {
+        let mut ans = Vec::with_capacity(params.len());
+        for id in self.children(params) {
+            match self.node(*id) {
+                Node::Spread(expr) => ans.extend(self.exec_node(*expr, ctx)?.spread()?),
+                _ => ans.push(self.exec_node(*id, ctx)?),
+            }
+        }
+        Ok(ans)
+    }
+
+    /// Converts back to a tree of boxes. Children come before their parent,
+    /// so one pass in index order builds every subtree before it's needed.
+    pub fn to_expr(&self) -> ExprAST<'a> {
+        let mut built: Vec<Option<ExprAST<'a>>> = Vec::with_capacity(self.nodes.len());
+        let take = |built: &mut Vec<Option<ExprAST<'a>>>, id: &NodeId| {
+            built[id.0 as usize].take().unwrap()
+        };
+        let take_all = |built: &mut Vec<Option<ExprAST<'a>>>, children: &Children| {
+            self.children(*children)
+                .iter()
+                .map(|id| take(built, id))
+                .collect::<Vec<_>>()
+        };
+        let pairs = |exprs: Vec<ExprAST<'a>>| {
+            let mut exprs = exprs.into_iter();
+            let mut ans = Vec::with_capacity(exprs.len() / 2);
+            while let (Some(k), Some(v)) = (exprs.next(), exprs.next()) {
+                ans.push((k, v));
+            }
+            ans
+        };
+        for node in &self.nodes {
+            let expr = match node {
+                Node::Literal(literal) => ExprAST::Literal(literal.clone()),
+                Node::Reference(name) => ExprAST::Reference(name),
+                Node::Unary(op, rhs) => ExprAST::Unary(op, Box::new(take(&mut built, rhs))),
+                Node::Binary(op, lhs, rhs) => {
+                    let lhs = take(&mut built, lhs);
+                    ExprAST::Binary(op, Box::new(lhs), Box::new(take(&mut built, rhs)))
+                }
+                Node::Postfix(lhs, op) => ExprAST::Postfix(Box::new(take(&mut built, lhs)), op),
+                Node::Ternary(condition, lhs, rhs) => ExprAST::Ternary(
+                    Box::new(take(&mut built, condition)),
+                    Box::new(take(&mut built, lhs)),
+                    Box::new(take(&mut built, rhs)),
+                ),
+                Node::Function(name, params) => {
+                    ExprAST::Function(name, take_all(&mut built, params))
+                }
+                Node::List(params) => ExprAST::List(take_all(&mut built, params)),
+                Node::Map(m) => ExprAST::Map(pairs(take_all(&mut built, m))),
+                Node::Match(scrutinee, arms, default) => ExprAST::Match(
+                    Box::new(take(&mut built, scrutinee)),
+                    pairs(take_all(&mut built, arms)),
+                    default.map(|default| Box::new(take(&mut built, &default))),
+                ),
+                Node::Stmt(exprs) => ExprAST::Stmt(take_all(&mut built, exprs)),
+                Node::Spread(expr) => ExprAST::Spread(Box::new(take(&mut built, expr))),
+                Node::None => ExprAST::None,
+            };
+            built.push(Some(expr));
+        }
+        take(&mut built, &self.root)
+    }
+}
+
+impl<'a> From<&ExprAST<'a>> for ExprArena<'a> {
+    // Post-order with an explicit stack, so deep trees don't overflow it: a
+    // node is visited once to queue its children and once more to push it
+    // after their ids.
+    fn from(expr: &ExprAST<'a>) -> Self {
+        let mut arena = Self::new();
+        let mut work = vec![(expr, false)];
+        let mut ids: Vec<NodeId> = Vec::new();
+        while let Some((expr, done)) = work.pop() {
+            let children = expr.children();
+            if !done && !children.is_empty() {
+                work.push((expr, true));
+                work.extend(children.into_iter().rev().map(|child| (child, false)));
+                continue;
+            }
+            let popped = ids.split_off(ids.len() - children.len());
+            let node = match expr {
+                ExprAST::Literal(literal) => Node::Literal(literal.clone()),
+                ExprAST::Reference(name) => Node::Reference(name),
+                ExprAST::None => Node::None,
+                ExprAST::Unary(op, _) => Node::Unary(op, popped[0]),
+                ExprAST::Binary(op, _, _) => Node::Binary(op, popped[0], popped[1]),
+                ExprAST::Postfix(_, op) => Node::Postfix(popped[0], op),
+                ExprAST::Ternary(..) => Node::Ternary(popped[0], popped[1], popped[2]),
+                ExprAST::Spread(_) => Node::Spread(popped[0]),
+                ExprAST::Function(name, _) => Node::Function(name, arena.push_children(popped)),
+                ExprAST::List(_) => Node::List(arena.push_children(popped)),
+                ExprAST::Map(_) => Node::Map(arena.push_children(popped)),
+                ExprAST::Stmt(_) => Node::Stmt(arena.push_children(popped)),
+                ExprAST::Match(_, arms, default) => {
+                    let arms_end = 1 + 2 * arms.len();
+                    let arm_ids = arena.push_children(popped[1..arms_end].iter().copied());
+                    let default = default.as_ref().map(|_| popped[arms_end]);
+                    Node::Match(popped[0], arm_ids, default)
+                }
+            };
+            ids.push(arena.push(node));
+        }
+        arena.root = ids.pop().unwrap();
+        arena
+    }
+}
+
+impl<'a> From<&ExprArena<'a>> for ExprAST<'a> {
+    fn from(arena: &ExprArena<'a>) -> Self {
+        arena.to_expr()
+    }
+}
+
+impl<'a> Builder<'a> for ExprArena<'a> {
+    type Node = NodeId;
+
+    fn literal(&mut self, literal: Literal<'a>) -> NodeId {
+        self.push(Node::Literal(literal))
+    }
+
+    fn reference(&mut self, name: &'a str) -> NodeId {
+        self.push(Node::Reference(name))
+    }
+
+    fn reference_name(&self, node: &NodeId) -> Option<&'a str> {
+        match self.node(*node) {
+            Node::Reference(name) => Some(name),
+            _ => None,
+        }
+    }
+
+    fn unary(&mut self, op: &'a str, rhs: NodeId) -> NodeId {
+        self.push(Node::Unary(op, rhs))
+    }
+
+    fn binary(&mut self, op: &'a str, lhs: NodeId, rhs: NodeId) -> NodeId {
+        self.push(Node::Binary(op, lhs, rhs))
+    }
+
+    fn postfix(&mut self, lhs: NodeId, op: &'a str) -> NodeId {
+        self.push(Node::Postfix(lhs, op))
+    }
+
+    fn ternary(&mut self, condition: NodeId, lhs: NodeId, rhs: NodeId) -> NodeId {
+        self.push(Node::Ternary(condition, lhs, rhs))
+    }
+
+    fn function(&mut self, name: &'a str, params: Vec<NodeId>, _: Span) -> NodeId {
+        let params = self.push_children(params);
+        self.push(Node::Function(name, params))
+    }
+
+    fn list(&mut self, params: Vec<NodeId>) -> NodeId {
+        let params = self.push_children(params);
+        self.push(Node::List(params))
+    }
+
+    fn map(&mut self, m: Vec<(NodeId, NodeId)>) -> NodeId {
+        let m = self.push_children(m.into_iter().flat_map(|(k, v)| [k, v]));
+        self.push(Node::Map(m))
+    }
+
+    fn match_(
+        &mut self,
+        scrutinee: NodeId,
+        arms: Vec<(NodeId, NodeId)>,
+        default: Option<NodeId>,
+    ) -> NodeId {
+        let arms = self.push_children(arms.into_iter().flat_map(|(k, v)| [k, v]));
+        self.push(Node::Match(scrutinee, arms, default))
+    }
+
+    fn stmt(&mut self, exprs: Vec<NodeId>) -> NodeId {
+        let exprs = self.push_children(exprs);
+        self.push(Node::Stmt(exprs))
+    }
+
+    fn spread(&mut self, expr: NodeId) -> NodeId {
+        self.push(Node::Spread(expr))
+    }
+
+    fn none(&mut self) -> NodeId {
+        self.push(Node::None)
+    }
+}
+
+#[cfg(all(test, feature = "default"))]
+mod tests {
+    use super::{ExprArena, Node};
+    use crate::{create_context, parse_arena, parse_expression, Error, ExprAST, Value};
+    use rstest::rstest;
+
+    #[rstest]
+    #[case("1 + 2 * 3")]
+    #[case("a = 2; a += 3; b = a * 2; [a, b]")]
+    #[case("!(a > 1) && not true || b == 'x'")]
+    #[case("a > 1 ? max(a, ...[4, 5]) : min(1, 2)")]
+    #[case("{'k': [1, ...[2, 3]], a + 1: 'v'}")]
+    #[case("match a { 1: 'one', 3: 'three', _: 'other' }")]
+    #[case("match b { 'y': 1 }")]
+    #[case("f(a, b) + c")]
+    #[case("a++ + 1")]
+    #[case("'x' in ['x', 'y'] ? len('abc') : 0")]
+    #[case("")]
+    fn test_arena_exec(#[case] input: &str) {
+        let ctx = || {
+            create_context!(
+                "a" => 3,
+                "b" => "x",
+                "c" => 1,
+                "f" => Arc::new(|params| Ok(Value::from(params.len())))
+            )
+        };
+        let arena = parse_arena(input).unwrap();
+        let ast = parse_expression(input).unwrap();
+        assert_eq!(
+            arena.exec(&mut ctx()).unwrap(),
+            ast.exec(&mut ctx()).unwrap()
+        );
+    }
+
+    #[rstest]
+    #[case("a + 1 - 2")]
+    #[case("x = [1, ...y, {'k': f(1, 2)}]; x")]
+    #[case("match a { 1: b ? c : d, _: e-- }")]
+    fn test_arena_conversion(#[case] input: &str) {
+        let ast = parse_expression(input).unwrap();
+        let arena = parse_arena(input).unwrap();
+        assert_eq!(ExprAST::from(&arena), ast);
+        assert_eq!(ExprArena::from(&ast), arena);
+    }
+
+    #[test]
+    fn test_arena_nodes() {
+        let arena = parse_arena("f(a, 1)").unwrap();
+        assert_eq!(arena.len(), 3);
+        let Node::Function(name, params) = arena.node(arena.root()) else {
+            panic!("not a function: {:?}", arena.node(arena.root()));
+        };
+        assert_eq!(*name, "f");
+        let params = arena.children(*params);
+        assert_eq!(arena.node(params[0]), &Node::Reference("a"));
+        assert_eq!(params.len(), 2);
+    }
+
+    #[test]
+    fn test_arena_errors() {
+        let arena = parse_arena("a - 'x'").unwrap();
+        let err = arena.exec(&mut create_context!("a" => 1)).unwrap_err();
+        assert!(matches!(err, Error::ShouldBeNumber()));
+        assert!(parse_arena("1 = 2").is_err());
+    }
+
+    #[test]
+    fn test_arena_deep() {
+        let input = vec!["1"; 100_000].join(" + ");
+        let arena = parse_arena(&input).unwrap();
+        let ast = ExprAST::from(&arena);
+        assert_eq!(ExprArena::from(&ast), arena);
+        assert_eq!(
+            ast.exec_iterative(&mut create_context!()).unwrap(),
+            Value::from(100_000)
+        );
+    }
+}
@@ -0,0 +1,309 @@
+use crate::define::Result;
+use crate::error::Error;
+use crate::function::{Arity, InnerFunctionManager};
+use crate::options::ParseOptions;
+use crate::parser::{Builder, Literal, Parser};
+use crate::suggest::suggestions;
+use crate::token::Span;
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// Checks the number of params of every call in an expression against the
+/// arity of the function, so that a rule like `round()` is rejected before
+/// it's evaluated rather than on the row that first reaches the call. The
+/// arity of an inner function comes from its [`crate::FunctionMetadata`].
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct ArityCheck {
+    /// The arity of functions the inner functions don't describe, like those
+    /// set on a context, by name. They win over the inner functions.
+    pub signatures: BTreeMap<String, Arity>,
+    /// Whether a call of a function that's neither an inner function nor in
+    /// `signatures` fails with [`Error::UnknownFunction`]. Otherwise it's
+    /// skipped, since a context may provide it later.
+    pub strict: bool,
+}
+
+impl ArityCheck {
+    /// The parse error of `expr`, or an [`Error::WrongFunctionArity`] for
+    /// each call with a number of params its function doesn't take, in the
+    /// order of the calls. A call with a spread, like `max(...list)`, is only
+    /// checked for having too many params besides it.
+    pub fn check(&self, expr: &str) -> Vec<Error> {
+        match calls(expr) {
+            Ok(calls) => self.check_calls(&calls, |_| false),
+            Err(err) => vec![err],
+        }
+    }
+
+    // `skip` tells which calls go to functions whose arity is unknown after
+    // all, like those of the context `crate::validate` is given.
+    pub(crate) fn check_calls(&self, calls: &[Call], skip: impl Fn(&str) -> bool) -> Vec<Error> {
+        let manager = InnerFunctionManager::new();
+        let mut errors = Vec::new();
+        let mut unknown = Vec::new();
+        for call in calls {
+            if skip(call.name) {
+                continue;
+            }
+            let arity = match self.signatures.get(call.name) {
+                Some(arity) => *arity,
+                None if manager.get(call.name).is_ok() => match manager.arity(call.name) {
+                    Some(arity) => arity,
+                    None => continue,
+                },
+                None if self.strict && !unknown.contains(&call.name) => {
+                    unknown.push(call.name);
+                    let names = manager.names();
+                    let known = names.iter().chain(self.signatures.keys());
+                    let close = suggestions(call.name, known.map(String::as_str));
+                    errors.push(Error::UnknownFunction(
+                        call.name.into(),
+                        close,
+                        Some(call.span.0),
+                    ));
+                    continue;
+                }
+                None => continue,
+            };
+            let fits = if call.spread {
+                arity.max().is_none_or(|max| call.params <= max)
+            } else {
+                arity.accepts(call.params)
+            };
+            if !fits {
+                errors.push(Error::WrongFunctionArity {
+                    name: call.name.into(),
+                    expected: arity,
+                    got: call.params,
+                    span: call.span,
+                });
+            }
+        }
+        errors
+    }
+}
+
+pub(crate) struct Call<'a> {
+    pub(crate) name: &'a str,
+    // Not counting spreads.
+    params: usize,
+    spread: bool,
+    pub(crate) span: Span,
+}
+
+// The calls of `expr`, in the order they start.
+pub(crate) fn calls(expr: &str) -> Result<Vec<Call<'_>>> {
+    let mut parser = Parser::with_builder(expr, Calls(Vec::new()), ParseOptions::default())?;
+    parser.parse_stmt()?;
+    let mut calls = parser.into_builder().0;
+    // Inner calls are built first.
+    calls.sort_by_key(|call| call.span.0);
+    Ok(calls)
+}
+
+// Builds nothing but the list of calls. A node only keeps the name of a
+// reference, which the parser checks assignments against, and whether it's
+// a spread.
+struct Calls<'a>(Vec<Call<'a>>);
+
+#[derive(Clone, Copy)]
+enum Node<'a> {
+    Reference(&'a str),
+    Spread,
+    Other,
+}
+
+impl<'a> Builder<'a> for Calls<'a> {
+    type Node = Node<'a>;
+
+    fn literal(&mut self, _: Literal<'a>) -> Node<'a> {
+        Node::Other
+    }
+
+    fn reference(&mut self, name: &'a str) -> Node<'a> {
+        Node::Reference(name)
+    }
+
+    fn reference_name(&self, node: &Node<'a>) -> Option<&'a str> {
+        match node {
+            Node::Reference(name) => Some(name),
+            _ => None,
+        }
+    }
+
+    fn unary(&mut self, _: &'a str, _: Node<'a>) -> Node<'a> {
+        Node::Other
+    }
+
+    fn binary(&mut self, _: &'a str, _: Node<'a>, _: Node<'a>) -> Node<'a> {
+        Node::Other
+    }
+
+    fn postfix(&mut self, _: Node<'a>, _: &'a str) -> Node<'a> {
+        Node::Other
+    }
+
+    fn ternary(&mut self, _: Node<'a>, _: Node<'a>, _: Node<'a>) -> Node<'a> {
+        Node::Other
+    }
+
+    fn function(&mut self, name: &'a str, params: Vec<Node<'a>>, span: Span) -> Node<'a> {
+        let spreads = params
+            .iter()
+            .filter(|param| matches!(param, Node::Spread))
+            .count();
+        self.0.push(Call {
+            name,
+            params: params.len() - spreads,
+            spread: spreads > 0,
+            span,
+        });
+        Node::Other
+    }
+
+    fn list(&mut self, _: Vec<Node<'a>>) -> Node<'a> {
+        Node::Other
+    }
+
+    fn map(&mut self, _: Vec<(Node<'a>, Node<'a>)>) -> Node<'a> {
+        Node::Other
+    }
+
+    fn match_(
+        &mut self,
+        _: Node<'a>,
+        _: Vec<(Node<'a>, Node<'a>)>,
+        _: Option<Node<'a>>,
+    ) -> Node<'a> {
+        Node::Other
+    }
+
+    fn stmt(&mut self, _: Vec<Node<'a>>) -> Node<'a> {
+        Node::Other
+    }
+
+    fn spread(&mut self, _: Node<'a>) -> Node<'a> {
+        Node::Spread
+    }
+
+    fn none(&mut self) -> Node<'a> {
+        Node::Other
+    }
+}
+
+#[cfg(all(test, feature = "default"))]
+mod tests {
+    use super::ArityCheck;
+    use crate::error::Error;
+    use crate::function::Arity;
+    use crate::token::Span;
+    use crate::{create_context, validate, Value};
+    use rstest::rstest;
+    use std::collections::BTreeMap;
+
+    // `(name, expected, got, span)` of each arity error.
+    fn arity_errors(errors: Vec<Error>) -> Vec<(String, Arity, usize, Span)> {
+        errors
+            .into_iter()
+            .map(|err| match err {
+                Error::WrongFunctionArity {
+                    name,
+                    expected,
+                    got,
+                    span,
+                } => (name, expected, got, span),
+                other => panic!("{:?}", other),
+            })
+            .collect()
+    }
+
+    #[rstest]
+    #[case("sqrt(4)", vec![])]
+    #[case("sqrt(4, 2)", vec![("sqrt", Arity::Exact(1), 2, Span(0, 10))])]
+    #[case("1 + math.sqrt()", vec![("math.sqrt", Arity::Exact(1), 0, Span(4, 15))])]
+    #[case("round(1.5, 0, 'floor')", vec![])]
+    #[case("round()", vec![("round", Arity::Range(1, 3), 0, Span(0, 7))])]
+    #[case(
+        "round(1, 2, 'up', 4)",
+        vec![("round", Arity::Range(1, 3), 4, Span(0, 20))]
+    )]
+    #[case("max(1, 2, 3)", vec![])]
+    #[case("sum()", vec![])]
+    #[case("max()", vec![("max", Arity::Variadic(1), 0, Span(0, 5))])]
+    #[case(
+        "x = max(round(), sqrt(1, 2)); x",
+        vec![
+            ("round", Arity::Range(1, 3), 0, Span(8, 15)),
+            ("sqrt", Arity::Exact(1), 2, Span(17, 27)),
+        ]
+    )]
+    #[case("sqrt(...list)", vec![])]
+    #[case("sqrt(1, ...list)", vec![])]
+    #[case("sqrt(1, 2, ...list)", vec![("sqrt", Arity::Exact(1), 2, Span(0, 19))])]
+    #[case("unknown(1, 2)", vec![])]
+    fn test_check(#[case] input: &str, #[case] expected: Vec<(&str, Arity, usize, Span)>) {
+        let expected: Vec<(String, Arity, usize, Span)> = expected
+            .into_iter()
+            .map(|(name, arity, got, span)| (name.to_string(), arity, got, span))
+            .collect();
+        assert_eq!(arity_errors(ArityCheck::default().check(input)), expected);
+    }
+
+    #[test]
+    fn test_signatures() {
+        let check = ArityCheck {
+            signatures: BTreeMap::from([
+                ("score".to_string(), Arity::Exact(2)),
+                ("round".to_string(), Arity::Exact(1)),
+            ]),
+            strict: false,
+        };
+        assert!(check.check("score(a, 1) + round(a)").is_empty());
+        let errors = arity_errors(check.check("score(a) + round(a, 2)"));
+        assert_eq!(
+            errors,
+            [
+                ("score".to_string(), Arity::Exact(2), 1, Span(0, 8)),
+                ("round".to_string(), Arity::Exact(1), 2, Span(11, 22)),
+            ]
+        );
+    }
+
+    #[rstest]
+    #[case("lookup(1) + lookup(2)", &["unknown function 'lookup'"])]
+    #[case("sqrtt(4)", &["unknown function 'sqrtt', did you mean 'sqrt'?"])]
+    #[case("scores(1, 2)", &["unknown function 'scores', did you mean 'score'?"])]
+    #[case(
+        "lookup() + sqrt()",
+        &["unknown function 'lookup'", "'sqrt' takes 1 params, got 0 (at 11)"]
+    )]
+    #[case("score(1, 2) + sqrt(4)", &[])]
+    fn test_strict(#[case] input: &str, #[case] expected: &[&str]) {
+        let check = ArityCheck {
+            signatures: BTreeMap::from([("score".to_string(), Arity::Exact(2))]),
+            strict: true,
+        };
+        let errors: Vec<String> = check.check(input).iter().map(Error::to_string).collect();
+        assert_eq!(errors, expected);
+    }
+
+    #[test]
+    fn test_check_parse_error() {
+        let errors = ArityCheck::default().check("round(1");
+        assert_eq!(errors.len(), 1);
+        assert!(!matches!(errors[0], Error::WrongFunctionArity { .. }));
+    }
+
+    #[test]
+    fn test_validate() {
+        let ctx = create_context!("round" => Arc::new(|_| Ok(Value::None)));
+        // A function of the context shadows the inner one of that name.
+        assert!(validate("round()", &ctx).is_empty());
+        let errors = validate("roud(1) + sqrt()", &ctx);
+        let codes: Vec<&str> = errors.iter().map(Error::code).collect();
+        assert_eq!(codes, ["E_FUNC_UNKNOWN", "E_ARG_WRONG_ARITY"]);
+        assert_eq!(errors[1].position(), Some(10));
+    }
+}
@@ -0,0 +1,266 @@
+use crate::context::Context;
+use crate::define::Result;
+use crate::function::{BoxFuture, InnerFunctionManager};
+use crate::operator::{
+    InfixOpConfig, InfixOpManager, InfixOpType, PostfixOpManager, PrefixOpManager,
+};
+use crate::parser::ExprAST;
+use crate::value::{Map, Value};
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::future::Future;
+
+impl<'a> ExprAST<'a> {
+    /// Evaluates the expression like [`ExprAST::exec`], awaiting the
+    /// functions set with [`Context::set_async_func`]. Sub-expressions that
+    /// call none of them are handed to `exec` as they are, so sync functions
+    /// and operators are still called directly. Calls to async functions
+    /// aren't memoized.
+    pub fn exec_async<'b>(
+        &'b self,
+        ctx: &'b mut Context,
+    ) -> impl Future<Output = Result<Value>> + Send + 'b {
+        self.exec_boxed(ctx)
+    }
+
+    // Boxed, since evaluating a node awaits its children.
+    fn exec_boxed<'b>(&'b self, ctx: &'b mut Context) -> BoxFuture<'b, Result<Value>> {
+        Box::pin(async move {
+            if !self.calls_async(ctx) {
+                return self.exec(ctx);
+            }
+            match self.exec_node_async(ctx).await {
+                Ok(value) => {
+                    if let Some(hook) = ctx.trace_hook() {
+                        hook(self, &value);
+                    }
+                    Ok(value)
+                }
+                Err(err) => Err(err.with_context(|| self.expr())),
+            }
+        })
+    }
+
+    fn calls_async(&self, ctx: &Context) -> bool {
+        let mut stack = vec![self];
+        while let Some(expr) = stack.pop() {
+            if let ExprAST::Function(name, _) = expr {
+                if ctx.get_async_func(name).is_some() {
+                    return true;
+                }
+            }
+            stack.extend(expr.children());
+        }
+        false
+    }
+
+    // Only reached by nodes with an async call below them, which leaves out
+    // literals and references.
+    async fn exec_node_async(&self, ctx: &mut Context) -> Result<Value> {
+        ctx.tick()?;
+        match self {
+            ExprAST::Function(name, exprs) => {
+                let params = exec_params_async(exprs, ctx).await?;
+                ctx.check_call(name)?;
+                let ans = match ctx.get_async_func(name) {
+                    Some(func) => func(params).await?,
+                    None => match ctx.get_func(name) {
+                        Some(func) => func(params)?,
+                        None => InnerFunctionManager::new().resolve(name, ctx)?(ctx, params)?,
+                    },
+                };
+                ctx.options().limits.check(ans)
+            }
+            ExprAST::Unary(op, rhs) => {
+                let rhs = ctx
+                    .options()
+                    .prefix_operand(op, rhs.exec_boxed(ctx).await?, ctx);
+                PrefixOpManager::new().get(op)?(rhs)
+            }
+            ExprAST::Binary(op, lhs, rhs) => {
+                let InfixOpConfig(_, op_type, _, handler) = InfixOpManager::new().get(op)?;
+                match op_type {
+                    InfixOpType::CALC => {
+                        let a = lhs.exec_boxed(ctx).await?;
+                        let b = rhs.exec_boxed(ctx).await?;
+                        ctx.options().infix(op, &handler, a, b, ctx)
+                    }
+                    InfixOpType::SETTER => {
+                        let a = match lhs.as_ref() {
+                            ExprAST::Reference(name) => ctx.target_value(name)?,
+                            _ => lhs.exec_boxed(ctx).await?,
+                        };
+                        let b = rhs.exec_boxed(ctx).await?;
+                        let target = lhs.get_reference_name()?;
+                        ctx.options().assign(op, &handler, a, b, target, ctx)
+                    }
+                }
+            }
+            ExprAST::Postfix(lhs, op) => {
+                PostfixOpManager::new().get(op)?(lhs.exec_boxed(ctx).await?)
+            }
+            ExprAST::Ternary(condition, lhs, rhs) => {
+                let condition = condition.exec_boxed(ctx).await?;
+                if ctx.options().condition(condition, ctx)? {
+                    return lhs.exec_boxed(ctx).await;
+                }
+                rhs.exec_boxed(ctx).await
+            }
+            ExprAST::List(params) => {
+                let list = Value::List(exec_params_async(params, ctx).await?);
+                ctx.options().limits.check(list)
+            }
+            ExprAST::Map(m) => {
+                let mut ans = Map::with_capacity_and_hasher(m.len(), Default::default());
+                for (k, v) in m {
+                    let k = k.exec_boxed(ctx).await?;
+                    let v = v.exec_boxed(ctx).await?;
+                    ctx.options().insert_key(&mut ans, k, v)?;
+                }
+                ctx.options().limits.check(Value::Map(ans))
+            }
+            ExprAST::Match(scrutinee, arms, default) => {
+                let scrutinee = scrutinee.exec_boxed(ctx).await?;
+                for (pattern, value) in arms {
+                    if pattern.exec_boxed(ctx).await? == scrutinee {
+                        return value.exec_boxed(ctx).await;
+                    }
+                }
+                match default {
+                    Some(default) => default.exec_boxed(ctx).await,
+                    None => ctx.options().no_match(&scrutinee),
+                }
+            }
+            ExprAST::Stmt(exprs) => {
+                let mut ans = Value::None;
+                for (i, expr) in exprs.iter().enumerate() {
+                    ans = expr
+                        .exec_boxed(ctx)
+                        .await
+                        .map_err(|err| err.in_statement(i + 1, || expr.expr()))?;
+                }
+                Ok(ans)
+            }
+            ExprAST::Spread(expr) => Ok(Value::List(expr.exec_boxed(ctx).await?.spread()?)),
+            ExprAST::Literal(_) | ExprAST::Reference(_) | ExprAST::None => self.exec(ctx),
+        }
+    }
+}
+
+async fn exec_params_async(params: &[ExprAST<'_>], ctx: &mut Context) -> Result<Vec<Value>> {
+    let mut ans = Vec::with_capacity(params.len());
+    for expr in params {
+        match expr {
+            ExprAST::Spread(_) => ans.extend(expr.exec_boxed(ctx).await?.spread()?),
+            _ => ans.push(expr.exec_boxed(ctx).await?),
+        }
+    }
+    Ok(ans)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::context::Context;
+    use crate::error::Error;
+    use crate::parse_expression;
+    use crate::value::Value;
+    use alloc::sync::Arc;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+    use rstest::rstest;
+
+    // `fetch(key)` stands in for a lookup over the network: it yields to
+    // the runtime before answering. `double` is a plain sync function.
+    fn async_ctx(calls: Arc<AtomicUsize>) -> Context {
+        let mut ctx = Context::new();
+        ctx.set_async_func(
+            "fetch",
+            Arc::new(move |params| {
+                let calls = calls.clone();
+                Box::pin(async move {
+                    tokio::task::yield_now().await;
+                    calls.fetch_add(1, Ordering::SeqCst);
+                    match params[0].clone().string()?.as_str() {
+                        "limit" => Ok(Value::from(100)),
+                        "score" => Ok(Value::from(42)),
+                        _ => Ok(Value::None),
+                    }
+                })
+            }),
+        );
+        ctx.set_func(
+            "double",
+            Arc::new(|params| Ok(Value::from(params[0].clone().integer()? * 2))),
+        );
+        ctx.set_variable("n", 3.into());
+        ctx
+    }
+
+    #[tokio::test]
+    async fn test_exec_async_chain() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let mut ctx = async_ctx(calls.clone());
+        let ast = parse_expression(
+            "limit = fetch('limit'); score = double(fetch('score')) + n; score < limit",
+        )
+        .unwrap();
+        assert_eq!(ast.exec_async(&mut ctx).await.unwrap(), true.into());
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+        assert_eq!(ctx.get_variable("score"), Some(87.into()));
+    }
+
+    #[rstest]
+    #[case("fetch('score')", 42.into())]
+    #[case("double(n) + 1", 7.into())]
+    #[case("[fetch('score'), ...[n, fetch('limit')]]", Value::List(vec![42.into(), 3.into(), 100.into()]))]
+    #[case("fetch('score') > 40 ? 'high' : fetch('limit')", "high".into())]
+    #[case("match fetch('limit') { 42: 'a', 100: 'b' }", "b".into())]
+    #[case("max(fetch('score'), n, -fetch('limit'))", 42.into())]
+    #[case("coalesce(fetch('other'), n)", 3.into())]
+    #[tokio::test]
+    async fn test_exec_async(#[case] input: &str, #[case] output: Value) {
+        let mut ctx = async_ctx(Arc::default());
+        let ast = parse_expression(input).unwrap();
+        assert_eq!(ast.exec_async(&mut ctx).await.unwrap(), output);
+    }
+
+    #[tokio::test]
+    async fn test_exec_async_semantics() {
+        let mut ctx = async_ctx(Arc::default());
+        ctx.set_options(crate::EvalOptions {
+            semantics: crate::SemanticsVersion::V2,
+            ..ctx.options()
+        });
+        let ast = parse_expression("a = b = fetch('score'); [a, b, 'x' + 'y']").unwrap();
+        let ans = ast.exec_async(&mut ctx).await.unwrap();
+        assert_eq!(ans, Value::List(vec![42.into(), 42.into(), "xy".into()]));
+    }
+
+    #[rstest]
+    #[case("fetch('score') + 1")]
+    #[case("fetch")]
+    #[case("fetch.limit")]
+    fn test_exec_sync_calls_async(#[case] input: &str) {
+        let mut ctx = async_ctx(Arc::default());
+        let err = parse_expression(input).unwrap().exec(&mut ctx).unwrap_err();
+        assert!(matches!(err.root(), Error::AsyncFunctionInSync(name) if name == "fetch"));
+    }
+
+    #[tokio::test]
+    async fn test_exec_async_error_context() {
+        let mut ctx = async_ctx(Arc::default());
+        let ast = parse_expression("fetch('other') * 2 + 1").unwrap();
+        let err = ast.exec_async(&mut ctx).await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "should be number: in 'fetch(\"other\") * 2' within 'fetch(\"other\") * 2 + 1'"
+        );
+    }
+
+    #[test]
+    fn test_exec_async_is_send() {
+        fn assert_send<T: Send>(_: T) {}
+        let mut ctx = Context::new();
+        let ast = parse_expression("1").unwrap();
+        assert_send(ast.exec_async(&mut ctx));
+    }
+}
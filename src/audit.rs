@@ -0,0 +1,313 @@
+use crate::context::Context;
+use crate::parser::ExprAST;
+use crate::value::Value;
+use alloc::collections::BTreeSet;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// What an expression was evaluated on and what it gave, built by
+/// [`Context::to_audit_record`] to log a decision and replay it later.
+///
+/// With the `serde` feature it serializes as `{ expr, fingerprint,
+/// variables, functions, result, error }`. Values are written as literals,
+/// e.g. `'gold'` or `[1, 2.50]`, which keep every digit of a number and
+/// the type of every map key, so a record read back holds exactly the
+/// values it was made with.
+#[derive(Clone, PartialEq, Debug)]
+pub struct AuditRecord {
+    /// The expression, as [`ExprAST::expr`] prints it.
+    pub expr: String,
+    /// [`ExprAST::fingerprint`] of the expression.
+    pub fingerprint: u64,
+    /// The variables the expression references, sorted by name. For a path
+    /// like `user.name`, the variable the path starts at, `user`.
+    pub variables: Vec<(String, Value)>,
+    /// The functions of the context it calls or references, by name only.
+    /// A replay has to set them again.
+    pub functions: Vec<String>,
+    /// The value, or the message of the error evaluating failed with.
+    pub result: core::result::Result<Value, String>,
+}
+
+impl AuditRecord {
+    /// A context with just the recorded variables, to replay the
+    /// expression on.
+    pub fn context(&self) -> Context {
+        let mut ctx = Context::new();
+        ctx.update_from(self.variables.iter().map(|(k, v)| (k, v.clone())));
+        ctx
+    }
+}
+
+impl Context {
+    /// Evaluates `ast` like [`ExprAST::exec_readonly`] and records the
+    /// result with the variables it references, see [`AuditRecord`].
+    /// References nothing resolves aren't recorded, so on a replay they are
+    /// missing again.
+    pub fn to_audit_record(&self, ast: &ExprAST) -> AuditRecord {
+        let mut variables = BTreeSet::new();
+        let mut functions = BTreeSet::new();
+        for name in ast.references() {
+            match self.variable_of(name) {
+                Some(variable) => variables.insert(variable),
+                None if self.get_func(name).is_some() => functions.insert(name.to_string()),
+                None => false,
+            };
+        }
+        let mut stack = vec![ast];
+        while let Some(expr) = stack.pop() {
+            if let ExprAST::Function(name, _) = expr {
+                if self.get_func(name).is_some() {
+                    functions.insert(name.to_string());
+                }
+            }
+            stack.extend(expr.children());
+        }
+        AuditRecord {
+            expr: ast.expr(),
+            fingerprint: ast.fingerprint(),
+            variables: variables
+                .into_iter()
+                .filter_map(|name| Some((name.to_string(), self.get_variable(name)?)))
+                .collect(),
+            functions: functions.into_iter().collect(),
+            result: ast.exec_readonly(self).map_err(|err| err.to_string()),
+        }
+    }
+
+    // The variable the reference `name` reads, like `Context::value`
+    // resolves it: `name` itself, or the longest dotted prefix of a path.
+    fn variable_of<'n>(&self, name: &'n str) -> Option<&'n str> {
+        let is_variable = |name: &str| self.get_variable(name).is_some();
+        if is_variable(name) {
+            return Some(name);
+        }
+        name.rmatch_indices('.')
+            .map(|(end, _)| name[..end].strip_suffix('?').unwrap_or(&name[..end]))
+            .find(|root| is_variable(root))
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for AuditRecord {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> core::result::Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+        let literals: Vec<(&str, String)> = self
+            .variables
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.to_string()))
+            .collect();
+        let mut state = serializer.serialize_struct("AuditRecord", 6)?;
+        state.serialize_field("expr", &self.expr)?;
+        state.serialize_field("fingerprint", &self.fingerprint)?;
+        state.serialize_field("variables", &Literals(&literals))?;
+        state.serialize_field("functions", &self.functions)?;
+        let result = self.result.as_ref();
+        state.serialize_field("result", &result.ok().map(Value::to_string))?;
+        state.serialize_field("error", &result.err())?;
+        state.end()
+    }
+}
+
+#[cfg(feature = "serde")]
+struct Literals<'a>(&'a [(&'a str, String)]);
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for Literals<'_> {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> core::result::Result<S::Ok, S::Error> {
+        serializer.collect_map(self.0.iter().map(|(name, literal)| (name, literal)))
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for AuditRecord {
+    fn deserialize<D: serde::Deserializer<'de>>(
+        deserializer: D,
+    ) -> core::result::Result<Self, D::Error> {
+        deserializer.deserialize_map(RecordVisitor)
+    }
+}
+
+#[cfg(feature = "serde")]
+struct RecordVisitor;
+
+#[cfg(feature = "serde")]
+impl<'de> serde::de::Visitor<'de> for RecordVisitor {
+    type Value = AuditRecord;
+
+    fn expecting(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        f.write_str("an audit record")
+    }
+
+    fn visit_map<A: serde::de::MapAccess<'de>>(
+        self,
+        mut map: A,
+    ) -> core::result::Result<AuditRecord, A::Error> {
+        use alloc::collections::BTreeMap;
+        use serde::de::Error;
+        let mut expr = None;
+        let mut fingerprint = None;
+        let mut variables = Vec::new();
+        let mut functions = Vec::new();
+        let (mut result, mut error) = (None, None);
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "expr" => expr = Some(map.next_value::<String>()?),
+                "fingerprint" => fingerprint = Some(map.next_value::<u64>()?),
+                "variables" => {
+                    for (name, literal) in map.next_value::<BTreeMap<String, String>>()? {
+                        variables.push((name, from_literal(&literal).map_err(A::Error::custom)?));
+                    }
+                }
+                "functions" => functions = map.next_value()?,
+                "result" => result = map.next_value::<Option<String>>()?,
+                "error" => error = map.next_value::<Option<String>>()?,
+                _ => return Err(A::Error::unknown_field(&key, FIELDS)),
+            }
+        }
+        let result = match (result, error) {
+            (_, Some(error)) => Err(error),
+            (Some(literal), None) => Ok(from_literal(&literal).map_err(A::Error::custom)?),
+            (None, None) => return Err(A::Error::missing_field("result")),
+        };
+        Ok(AuditRecord {
+            expr: expr.ok_or_else(|| A::Error::missing_field("expr"))?,
+            fingerprint: fingerprint.ok_or_else(|| A::Error::missing_field("fingerprint"))?,
+            variables,
+            functions,
+            result,
+        })
+    }
+}
+
+#[cfg(feature = "serde")]
+const FIELDS: &[&str] = &[
+    "expr",
+    "fingerprint",
+    "variables",
+    "functions",
+    "result",
+    "error",
+];
+
+// A value as its `Display` writes it. Anything else, like a
+// reference or a call, is refused rather than evaluated.
+#[cfg(feature = "serde")]
+fn from_literal(literal: &str) -> core::result::Result<Value, String> {
+    let ast = crate::parse_expression(literal).map_err(|err| err.to_string())?;
+    let mut stack = vec![&ast];
+    while let Some(expr) = stack.pop() {
+        match expr {
+            ExprAST::Literal(_) | ExprAST::List(_) | ExprAST::Map(_) | ExprAST::Unary("-", _) => {
+                stack.extend(expr.children())
+            }
+            _ => return Err(alloc::format!("'{}' is not a literal", literal)),
+        }
+    }
+    ast.exec(&mut Context::new()).map_err(|err| err.to_string())
+}
+
+#[cfg(all(test, feature = "default"))]
+mod tests {
+    use crate::{create_context, parse_expression, Value};
+
+    #[test]
+    fn test_to_audit_record() {
+        let mut ctx = create_context!(
+            "user" => Value::from([("tier".into(), "gold".into())].into_iter().collect::<crate::Map>()),
+            "amount" => 120,
+            "unused" => true,
+            "limit" => Arc::new(|_| Ok(Value::from(100)))
+        );
+        let ast =
+            parse_expression("user.tier == 'gold' && amount > limit && missing == null").unwrap();
+        let record = ctx.to_audit_record(&ast);
+        assert_eq!(record.expr, ast.expr());
+        assert_eq!(record.fingerprint, ast.fingerprint());
+        let names: Vec<&str> = record
+            .variables
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect();
+        assert_eq!(names, ["amount", "user"]);
+        assert_eq!(record.functions, ["limit"]);
+        assert_eq!(record.result, Ok(true.into()));
+        ctx.set_variable("amount", "lots".into());
+        let record = ctx.to_audit_record(&ast);
+        assert_eq!(
+            record.result,
+            Err("can not compare String with Number: in 'amount > limit' \
+                 within 'user.tier == \"gold\" && amount > limit'"
+                .to_string())
+        );
+        // Assignments would change the context.
+        let ast = parse_expression("amount = 1; amount").unwrap();
+        assert!(ctx.to_audit_record(&ast).result.is_err());
+        assert_eq!(ctx.value("amount").unwrap(), "lots".into());
+    }
+
+    #[cfg(all(feature = "serde", feature = "serde_json"))]
+    #[test]
+    fn test_audit_record_replay() {
+        use super::AuditRecord;
+        use alloc::sync::Arc;
+        let user = [
+            ("tier".into(), "gold's".into()),
+            (2.into(), vec![Value::None, (-1).into()].into()),
+        ];
+        let ctx = create_context!(
+            "user" => Value::from(user.into_iter().collect::<crate::Map>()),
+            "rate" => Value::from(rust_decimal::Decimal::from_str_exact("0.1234567890123456789012345").unwrap()),
+            "limit" => Arc::new(|_| Ok(Value::from(100)))
+        );
+        let ast = parse_expression("[rate * limit, user.tier, user]").unwrap();
+        let record = ctx.to_audit_record(&ast);
+        let json = serde_json::to_value(&record).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "expr": "[rate * limit,user.tier,user]",
+                "fingerprint": ast.fingerprint(),
+                "variables": {
+                    "rate": "0.1234567890123456789012345",
+                    "user": "{'tier': \"gold's\", 2: [null, -1]}",
+                },
+                "functions": ["limit"],
+                "result": "[12.3456789012345678901234500, \"gold's\", {'tier': \"gold's\", 2: [null, -1]}]",
+                "error": null,
+            })
+        );
+        let read: AuditRecord = serde_json::from_str(&json.to_string()).unwrap();
+        assert_eq!(read, record);
+        // The replay: the recorded variables, the functions set again and
+        // the recorded expression give the recorded result.
+        let mut replay = read.context();
+        replay.set_func("limit", Arc::new(|_| Ok(Value::from(100))));
+        let ast = parse_expression(&read.expr).unwrap();
+        assert_eq!(ast.fingerprint(), read.fingerprint);
+        assert_eq!(ast.exec(&mut replay).unwrap(), read.result.unwrap());
+    }
+
+    #[cfg(all(feature = "serde", feature = "serde_json"))]
+    #[test]
+    fn test_audit_record_refuses_expressions() {
+        use super::AuditRecord;
+        let json = r#"{"expr": "a", "fingerprint": 1, "variables": {"a": "now()"}, "functions": [], "result": "1", "error": null}"#;
+        let err = serde_json::from_str::<AuditRecord>(json).unwrap_err();
+        assert!(
+            err.to_string().starts_with("'now()' is not a literal"),
+            "{}",
+            err
+        );
+        let json = r#"{"expr": "a", "fingerprint": 1, "variables": {}, "functions": [], "result": null, "error": "failed"}"#;
+        let read = serde_json::from_str::<AuditRecord>(json).unwrap();
+        assert_eq!(read.result, Err("failed".to_string()));
+    }
+}
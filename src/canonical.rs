@@ -0,0 +1,282 @@
+use crate::parser::{ExprAST, Literal};
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::mem;
+
+// Binary operators whose operands can be swapped. `&&` and `||` evaluate
+// both operands, so they qualify too.
+const COMMUTATIVE_OPS: &[&str] = &["+", "*", "==", "!=", "&&", "||", "&", "|", "^"];
+
+impl<'a> ExprAST<'a> {
+    /// Rewrites the tree into a canonical form, so that expressions that
+    /// only differ in the ways below become equal ASTs with the same
+    /// [`ExprAST::fingerprint`], e.g. for deduplicating stored rules.
+    /// Nothing else canonicalizes, so parsing and `fingerprint()` keep the
+    /// tree as written.
+    ///
+    /// + `not x` becomes `!x`, `!true` becomes `false` and `!!x` becomes
+    ///   `x` when `x` is a bool.
+    /// + Negations move inward: `!(x == y)` becomes `x != y` and the other
+    ///   way around, `!(a && b)` becomes `!a || !b` and `!(a || b)` becomes
+    ///   `!a && !b`.
+    /// + `true && x`, `x && true`, `false || x` and `x || false` become `x`
+    ///   when `x` is a bool.
+    /// + The operands of `+`, `*`, `==`, `!=`, `&&`, `||`, `&`, `|` and `^`
+    ///   are ordered by fingerprint, as are the entries of a map literal
+    ///   whose keys are distinct literals.
+    ///
+    /// A bool here is an expression that can only evaluate to one: a bool
+    /// literal, a comparison, a negation, `&&` or `||`. The rewrites keep
+    /// the value of the expression under either [`crate::Truthiness`], for
+    /// the built-in operators. Operands and map entries that assign aren't
+    /// reordered, but calls are assumed to have no side effects. When an
+    /// expression fails, the canonical one fails too, though maybe with
+    /// another error, and a reordered map iterates in its new order.
+    ///
+    /// Trees of any depth are canonicalized without recursing, but operands
+    /// are ordered by the fingerprints of their whole subtrees, so a long
+    /// chain of a commutative operator takes time quadratic in its length.
+    pub fn canonicalize(self) -> ExprAST<'a> {
+        // Post-order with a work stack, so deep trees don't recurse. A node
+        // is entered with its children taken out, which are canonicalized
+        // onto `done` and put back when it's left.
+        let mut work = vec![Step::Enter(self)];
+        let mut done = Vec::new();
+        while let Some(step) = work.pop() {
+            match step {
+                Step::Enter(mut expr) => {
+                    let children: Vec<_> = expr.children_mut().into_iter().map(take).collect();
+                    work.push(Step::Leave(expr, children.len()));
+                    work.extend(children.into_iter().rev().map(Step::Enter));
+                }
+                Step::Leave(mut expr, count) => {
+                    let children = done.split_off(done.len() - count);
+                    for (slot, child) in expr.children_mut().into_iter().zip(children) {
+                        *slot = child;
+                    }
+                    done.push(rewrite(expr));
+                }
+            }
+        }
+        done.pop().unwrap()
+    }
+
+    fn is_bool(&self) -> bool {
+        match self {
+            ExprAST::Literal(Literal::Bool(_)) | ExprAST::Unary("!" | "not", _) => true,
+            ExprAST::Binary(op, ..) => matches!(
+                *op,
+                "==" | "!="
+                    | "<"
+                    | "<="
+                    | ">"
+                    | ">="
+                    | "&&"
+                    | "||"
+                    | "in"
+                    | "between"
+                    | "contains"
+                    | "containsAll"
+                    | "containsAny"
+            ),
+            _ => false,
+        }
+    }
+
+    fn assigns(&self) -> bool {
+        self.setter().is_some()
+    }
+}
+
+enum Step<'a> {
+    Enter(ExprAST<'a>),
+    // A node whose children are the last ones done.
+    Leave(ExprAST<'a>, usize),
+}
+
+enum Negation<'a> {
+    Negate(ExprAST<'a>),
+    // The last two negated operands, joined by the operator.
+    Join(&'a str),
+}
+
+// The tree has a `Drop` impl, so nodes are taken apart in place.
+fn take<'a>(expr: &mut ExprAST<'a>) -> ExprAST<'a> {
+    mem::replace(expr, ExprAST::None)
+}
+
+// The canonical form of a node whose children are canonical.
+fn rewrite(mut expr: ExprAST) -> ExprAST {
+    match &mut expr {
+        ExprAST::Unary("!" | "not", rhs) => return negate(take(rhs)),
+        ExprAST::Binary(op, lhs, rhs) => return binary(op, take(lhs), take(rhs)),
+        ExprAST::Map(m) if is_sortable(m) => m.sort_by_cached_key(|(k, _)| k.fingerprint()),
+        _ => {}
+    }
+    expr
+}
+
+// The canonical `!expr`, for a canonical `expr`. Negations move into
+// `&&` and `||` with a work stack, which joins the negated operands.
+fn negate(expr: ExprAST) -> ExprAST {
+    let mut work = vec![Negation::Negate(expr)];
+    let mut done = Vec::new();
+    while let Some(step) = work.pop() {
+        let mut expr = match step {
+            Negation::Negate(expr) => expr,
+            Negation::Join(op) => {
+                let (rhs, lhs) = (done.pop().unwrap(), done.pop().unwrap());
+                done.push(binary(op, lhs, rhs));
+                continue;
+            }
+        };
+        match &mut expr {
+            ExprAST::Literal(Literal::Bool(value)) => *value = !*value,
+            ExprAST::Unary("!", rhs) if rhs.is_bool() => expr = take(rhs),
+            ExprAST::Binary(op @ ("==" | "!="), ..) => *op = if *op == "==" { "!=" } else { "==" },
+            ExprAST::Binary(op @ ("&&" | "||"), lhs, rhs) => {
+                let op = if *op == "&&" { "||" } else { "&&" };
+                work.extend([
+                    Negation::Join(op),
+                    Negation::Negate(take(rhs)),
+                    Negation::Negate(take(lhs)),
+                ]);
+                continue;
+            }
+            _ => expr = ExprAST::Unary("!", Box::new(expr)),
+        }
+        done.push(expr);
+    }
+    done.pop().unwrap()
+}
+
+// The canonical `lhs op rhs`, for canonical operands.
+fn binary<'a>(op: &'a str, lhs: ExprAST<'a>, rhs: ExprAST<'a>) -> ExprAST<'a> {
+    use ExprAST::Literal as Lit;
+    use Literal::Bool;
+    match (op, &lhs, &rhs) {
+        ("&&", Lit(Bool(true)), _) | ("||", Lit(Bool(false)), _) if rhs.is_bool() => {
+            return rhs;
+        }
+        ("&&", _, Lit(Bool(true))) | ("||", _, Lit(Bool(false))) if lhs.is_bool() => {
+            return lhs;
+        }
+        _ => {}
+    }
+    let swap = COMMUTATIVE_OPS.contains(&op)
+        && lhs.fingerprint() > rhs.fingerprint()
+        && !lhs.assigns()
+        && !rhs.assigns();
+    match swap {
+        true => ExprAST::Binary(op, Box::new(rhs), Box::new(lhs)),
+        false => ExprAST::Binary(op, Box::new(lhs), Box::new(rhs)),
+    }
+}
+
+// Entries can be reordered when no key is repeated, which would decide
+// the winning value by position, and no value assigns.
+fn is_sortable(m: &[(ExprAST, ExprAST)]) -> bool {
+    let mut keys = Vec::with_capacity(m.len());
+    for (k, v) in m {
+        if !matches!(k, ExprAST::Literal(_)) || v.assigns() {
+            return false;
+        }
+        keys.push(k.fingerprint());
+    }
+    keys.sort_unstable();
+    keys.windows(2).all(|pair| pair[0] != pair[1])
+}
+
+#[cfg(all(test, feature = "default"))]
+mod tests {
+    use crate::{create_context, parse_expression, Context, EvalOptions, Truthiness, Value};
+    use rstest::rstest;
+
+    fn canonical(input: &str) -> crate::ExprAST<'_> {
+        parse_expression(input).unwrap().canonicalize()
+    }
+
+    fn context() -> Context {
+        create_context!(
+            "a" => true,
+            "b" => false,
+            "c" => true,
+            "x" => 3,
+            "y" => 4,
+            "p" => 2,
+            "k" => 1,
+            "list" => Value::List(vec![1.into(), 3.into()])
+        )
+    }
+
+    #[rstest]
+    #[case("x + y", "y + x")]
+    #[case("x * (y + p)", "(p + y) * x")]
+    #[case("!(x == y)", "x != y")]
+    #[case("!(x != y)", "x == y")]
+    #[case("!!(x < y)", "x < y")]
+    #[case("!true", "false")]
+    #[case("true && p > 1", "p > 1")]
+    #[case("(p > 1) || false", "p > 1")]
+    #[case("!(a && b)", "!a || !b")]
+    #[case("!(a || x == y)", "y != x && !a")]
+    #[case("x not in list", "!(x in list)")]
+    #[case("a == b && c", "c && b == a")]
+    #[case("{'b': 1, 'a': x + y}", "{'a': y + x, 'b': 1}")]
+    #[case("max(y + x, 2 * 1)", "max(x + y, 1 * 2)")]
+    #[case("match k {1: x + y, _: !!(x > 1)}", "match k {1: y + x, _: x > 1}")]
+    #[case("z = x + y; z * 2", "z = y + x; 2 * z")]
+    fn test_canonicalize_equivalent(#[case] a: &str, #[case] b: &str) {
+        let (lhs, rhs) = (canonical(a), canonical(b));
+        assert_eq!(lhs, rhs, "{} and {}", lhs, rhs);
+        assert_eq!(lhs.fingerprint(), rhs.fingerprint());
+        for input in [a, b] {
+            let original = parse_expression(input).unwrap().exec(&mut context());
+            let canonical = canonical(input).exec(&mut context());
+            assert_eq!(original.unwrap(), canonical.unwrap(), "{}", input);
+        }
+    }
+
+    #[rstest]
+    #[case("!(x && b)")]
+    #[case("!(p || 0) == !!empty")]
+    #[case("!(x < y || list)")]
+    fn test_canonicalize_coerce(#[case] input: &str) {
+        let options = EvalOptions {
+            truthiness: Truthiness::Coerce,
+            ..EvalOptions::default()
+        };
+        let exec = |ast: crate::ExprAST| {
+            let mut ctx = context();
+            ctx.set_options(options);
+            ast.exec(&mut ctx).unwrap()
+        };
+        let original = exec(parse_expression(input).unwrap());
+        assert_eq!(exec(canonical(input)), original, "{}", input);
+    }
+
+    #[rstest]
+    #[case("x - y", "y - x")]
+    #[case("x / y", "y / x")]
+    #[case("!!x", "x")]
+    #[case("true && x", "x")]
+    #[case("x < y", "!(x >= y)")]
+    #[case("[x, y]", "[y, x]")]
+    #[case("max(x, y)", "max(y, x)")]
+    #[case("{'a': 1, 'a': 2}", "{'a': 2, 'a': 1}")]
+    #[case("{(k): 1, 'a': 2}", "{'a': 2, (k): 1}")]
+    #[case("(x = 1) + x", "x + (x = 1)")]
+    #[case("{'a': x = 1, 'b': x}", "{'b': x, 'a': x = 1}")]
+    fn test_canonicalize_different(#[case] a: &str, #[case] b: &str) {
+        assert_ne!(canonical(a), canonical(b));
+    }
+
+    #[test]
+    fn test_canonicalize_deep() {
+        let input = vec!["x"; 100_000].join(" - ");
+        assert_eq!(canonical(&input), parse_expression(&input).unwrap());
+        let input = format!("!({})", vec!["a"; 1_000].join(" && "));
+        let negated = vec!["!a"; 1_000].join(" || ");
+        assert_eq!(canonical(&input), canonical(&negated));
+    }
+}
@@ -0,0 +1,197 @@
+use crate::define::Result;
+use crate::error::Error;
+use crate::function::{Arity, FunctionMetadata, InnerFunctionManager};
+use crate::value::Value;
+use base64::Engine;
+use md5::Md5;
+use sha2::{Digest, Sha256};
+use std::fmt::Write;
+use std::sync::Arc;
+
+impl InnerFunctionManager {
+    pub(crate) fn init_codecs(&mut self) {
+        self.builtin(
+            FunctionMetadata::new(
+                "sha256",
+                Arity::Exact(1),
+                &[("s", "String")],
+                "The SHA-256 digest of s, in hex.",
+            )
+            .deterministic(),
+            Arc::new(|params| Ok(Value::String(to_hex(&Sha256::digest(string(params)?))))),
+        );
+
+        self.builtin(
+            FunctionMetadata::new(
+                "md5",
+                Arity::Exact(1),
+                &[("s", "String")],
+                "The MD5 digest of s, in hex.",
+            )
+            .deterministic(),
+            Arc::new(|params| Ok(Value::String(to_hex(&Md5::digest(string(params)?))))),
+        );
+
+        self.builtin(
+            FunctionMetadata::new(
+                "base64Encode",
+                Arity::Exact(1),
+                &[("s", "String")],
+                "s encoded as standard base64.",
+            )
+            .deterministic(),
+            Arc::new(|params| {
+                let s = string(params)?;
+                Ok(Value::String(
+                    base64::engine::general_purpose::STANDARD.encode(s),
+                ))
+            }),
+        );
+
+        self.builtin(
+            FunctionMetadata::new(
+                "base64Decode",
+                Arity::Exact(1),
+                &[("s", "String")],
+                "The string a standard base64 s encodes.",
+            )
+            .deterministic(),
+            Arc::new(|params| {
+                let bytes = base64::engine::general_purpose::STANDARD
+                    .decode(string(params)?)
+                    .map_err(|err| Error::InvalidBase64(err.to_string()))?;
+                String::from_utf8(bytes)
+                    .map(Value::String)
+                    .map_err(|err| Error::InvalidBase64(err.to_string()))
+            }),
+        );
+
+        self.builtin(
+            FunctionMetadata::new(
+                "hex",
+                Arity::Exact(1),
+                &[("s", "String")],
+                "The UTF-8 bytes of s, in hex.",
+            )
+            .deterministic(),
+            Arc::new(|params| Ok(Value::String(to_hex(string(params)?.as_bytes())))),
+        );
+
+        self.builtin(
+            FunctionMetadata::new(
+                "urlEncode",
+                Arity::Exact(1),
+                &[("s", "String")],
+                "s percent-encoded, keeping only the RFC 3986 unreserved characters.",
+            )
+            .deterministic(),
+            Arc::new(|params| Ok(Value::String(url_encode(&string(params)?)))),
+        );
+
+        // Not deterministic: two calls with the same params return different
+        // values.
+        self.builtin(
+            FunctionMetadata::new("uuid", Arity::Exact(0), &[], "A random version 4 UUID."),
+            Arc::new(|params| {
+                if !params.is_empty() {
+                    return Err(Error::ParamInvalid());
+                }
+                Ok(Value::String(uuid::Uuid::new_v4().to_string()))
+            }),
+        );
+    }
+}
+
+fn string(params: Vec<Value>) -> Result<String> {
+    if params.len() != 1 {
+        return Err(Error::ParamInvalid());
+    }
+    params.into_iter().next().unwrap().string()
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    let mut ans = String::with_capacity(bytes.len() * 2);
+    for byte in bytes {
+        write!(ans, "{:02x}", byte).unwrap();
+    }
+    ans
+}
+
+// Percent-encodes everything except the RFC 3986 unreserved characters.
+fn url_encode(s: &str) -> String {
+    let mut ans = String::with_capacity(s.len());
+    for byte in s.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                ans.push(byte as char)
+            }
+            _ => write!(ans, "%{:02X}", byte).unwrap(),
+        }
+    }
+    ans
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{create_context, execute, Error, Value};
+    use rstest::rstest;
+
+    #[rstest]
+    #[case(
+        "sha256('')",
+        "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+    )]
+    #[case(
+        "sha256('abc')",
+        "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+    )]
+    #[case("md5('')", "d41d8cd98f00b204e9800998ecf8427e")]
+    #[case(
+        "md5('The quick brown fox jumps over the lazy dog')",
+        "9e107d9d372bb6826bd81d3542a419d6"
+    )]
+    #[case("base64Encode('hello world')", "aGVsbG8gd29ybGQ=")]
+    #[case("base64Decode('aGVsbG8gd29ybGQ=')", "hello world")]
+    #[case("base64Decode(base64Encode('héllo'))", "héllo")]
+    #[case("hex('abc')", "616263")]
+    #[case("hex('')", "")]
+    #[case("urlEncode('a b&c=d/é~')", "a%20b%26c%3Dd%2F%C3%A9~")]
+    fn test_codecs(#[case] input: &str, #[case] output: &str) {
+        assert_eq!(
+            execute(input, create_context!()).unwrap(),
+            Value::from(output)
+        );
+    }
+
+    #[test]
+    fn test_uuid() {
+        let a = execute("uuid()", create_context!())
+            .unwrap()
+            .string()
+            .unwrap();
+        let b = execute("uuid()", create_context!())
+            .unwrap()
+            .string()
+            .unwrap();
+        assert_eq!(a.len(), 36);
+        assert_eq!(&a[14..15], "4");
+        assert_ne!(a, b);
+    }
+
+    #[rstest]
+    #[case("base64Decode('not base64!')")]
+    #[case("base64Decode('/w==')")]
+    fn test_base64_decode_error(#[case] input: &str) {
+        let err = execute(input, create_context!()).unwrap_err();
+        assert!(matches!(err.root(), Error::InvalidBase64(_)));
+    }
+
+    #[rstest]
+    #[case("hex(1)")]
+    #[case("sha256(true)")]
+    #[case("urlEncode([1])")]
+    fn test_codecs_not_string(#[case] input: &str) {
+        let err = execute(input, create_context!()).unwrap_err();
+        assert!(matches!(err.root(), Error::ShouldBeString()));
+    }
+}
@@ -0,0 +1,103 @@
+// The `std` types the crate needs, or their stand-ins on `no_std + alloc`
+// targets. The stand-ins keep the `std` signatures, so call sites don't
+// depend on the feature.
+use core::hash::Hasher;
+
+#[cfg(feature = "std")]
+pub(crate) use once_cell::sync::OnceCell;
+#[cfg(feature = "std")]
+pub(crate) use std::collections::hash_map::DefaultHasher;
+#[cfg(feature = "std")]
+pub(crate) use std::collections::{HashMap, HashSet};
+#[cfg(feature = "std")]
+pub use std::sync::Mutex;
+
+#[cfg(not(feature = "std"))]
+pub(crate) use hashbrown::{HashMap, HashSet};
+
+#[cfg(feature = "std")]
+type BuildHasher = std::collections::hash_map::RandomState;
+#[cfg(not(feature = "std"))]
+type BuildHasher = hashbrown::DefaultHashBuilder;
+
+/// An insertion-ordered map, with the default hasher of the target.
+pub type IndexMap<K, V> = indexmap::IndexMap<K, V, BuildHasher>;
+
+// Hashes that have to agree across hasher instances, like the entries of a
+// map value, need an unkeyed hasher.
+#[cfg(not(feature = "std"))]
+pub(crate) type DefaultHasher = Fnv;
+
+/// A spin lock on targets without threads.
+#[cfg(not(feature = "std"))]
+#[derive(Default)]
+pub struct Mutex<T>(spin::Mutex<T>);
+
+#[cfg(not(feature = "std"))]
+impl<T> Mutex<T> {
+    pub const fn new(value: T) -> Self {
+        Self(spin::Mutex::new(value))
+    }
+
+    // A spin lock can't be poisoned, the `Result` only mirrors
+    // `std::sync::Mutex::lock`.
+    pub fn lock(&self) -> Result<spin::MutexGuard<'_, T>, core::convert::Infallible> {
+        Ok(self.0.lock())
+    }
+}
+
+#[cfg(not(feature = "std"))]
+pub(crate) struct OnceCell<T>(spin::Once<T>);
+
+#[cfg(not(feature = "std"))]
+impl<T> OnceCell<T> {
+    pub(crate) const fn new() -> Self {
+        Self(spin::Once::new())
+    }
+
+    pub(crate) fn get_or_init(&self, f: impl FnOnce() -> T) -> &T {
+        self.0.call_once(f)
+    }
+}
+
+// 64-bit FNV-1a, which is fully specified, unlike `DefaultHasher`.
+pub(crate) struct Fnv(u64);
+
+impl Default for Fnv {
+    fn default() -> Self {
+        Self(0xcbf29ce484222325)
+    }
+}
+
+impl Fnv {
+    #[cfg(not(feature = "std"))]
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    pub(crate) fn tag(&mut self, tag: u8) {
+        self.write(&[tag]);
+    }
+
+    pub(crate) fn len(&mut self, len: usize) {
+        self.write(&(len as u64).to_le_bytes());
+    }
+
+    pub(crate) fn str(&mut self, s: &str) {
+        self.len(s.len());
+        self.write(s.as_bytes());
+    }
+}
+
+impl Hasher for Fnv {
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(0x100000001b3);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
@@ -0,0 +1,454 @@
+use crate::compat::HashMap;
+use crate::define::Result;
+use crate::error::Error;
+use crate::function::{ContextFunction, InnerFunctionManager};
+use crate::operator::{
+    InfixOpFunc, InfixOpManager, InfixOpType, PostfixOpFunc, PostfixOpManager, PrefixOpFunc,
+    PrefixOpManager,
+};
+use crate::parser::{ExprAST, Literal};
+use crate::token::Span;
+use crate::value::Value;
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Instruction {
+    // Pushes `constants[i]`.
+    Const(usize),
+    // Pushes the value of the context entry named `names[i]`.
+    Load(usize),
+    // Like `Load`, for the target of an assignment, so a dotted path that
+    // doesn't exist yet loads `None`.
+    LoadTarget(usize),
+    PushNone,
+    // Discards the top of the stack, used between chain statements.
+    Pop,
+    Prefix(usize),
+    Postfix(usize),
+    Infix(usize),
+    // Applies `infix[i]` and stores the result into `names[j]`. `None` means
+    // the target isn't a reference and fails at run time, like `exec` does.
+    Assign(usize, Option<usize>),
+    // Calls `functions[i]` with the top `n` values as its params.
+    Call(usize, usize),
+    List(usize),
+    Map(usize),
+    // Pops a value and appends it to the list below it.
+    Append,
+    // Pops a list and appends its elements to the list below it, failing if
+    // the value isn't a list. Used for `...expr`.
+    Extend,
+    // Fails if the list on top of the stack is over the limits, once a list
+    // literal has been built up by `Append` and `Extend`.
+    CheckLimits,
+    // Calls `functions[i]` with the elements of the list on top of the stack
+    // as its params, for calls that spread a list.
+    CallList(usize),
+    Jump(usize),
+    // Pops a condition and jumps if it's false.
+    JumpIfFalse(usize),
+    // Pops a match pattern and jumps if it differs from the scrutinee below
+    // it, otherwise pops the scrutinee too.
+    JumpIfNoMatch(usize),
+    // Pops the scrutinee of a match that no arm matched and pushes `None`, or
+    // fails if `EvalOptions::strict_match` is set.
+    NoMatch,
+}
+
+/// A flat instruction sequence compiled from an [`ExprAST`], with operator
+/// handlers and inner functions resolved up front. Run it with
+/// [`crate::vm::Vm::run`].
+pub struct Program {
+    pub(crate) instructions: Vec<Instruction>,
+    pub(crate) constants: Vec<Value>,
+    pub(crate) names: Vec<String>,
+    // Prefix and infix handlers keep their op, which decides whether the
+    // operands are coerced under `Truthiness::Coerce`.
+    pub(crate) prefix: Vec<(String, Arc<PrefixOpFunc>)>,
+    pub(crate) postfix: Vec<Arc<PostfixOpFunc>>,
+    pub(crate) infix: Vec<(String, Arc<InfixOpFunc>)>,
+    // One entry per distinct name. Context functions shadow inner ones, so
+    // only the inner function can be resolved here; the context is consulted
+    // once per run, and inner functions registered after compiling are looked
+    // up by name.
+    pub(crate) functions: Vec<(String, Option<Arc<ContextFunction>>)>,
+    // What errors are reported against: the text of the expression, the
+    // node each instruction was compiled from, by index into `nodes`, and
+    // the nodes themselves.
+    text: String,
+    origins: Vec<usize>,
+    nodes: Vec<Node>,
+}
+
+// A node of the compiled expression: where its text is, the index of its
+// parent, and its index among the statements of the parent when that is a
+// chain.
+struct Node {
+    span: Span,
+    parent: Option<usize>,
+    statement: Option<usize>,
+}
+
+impl Program {
+    pub fn instructions(&self) -> &[Instruction] {
+        &self.instructions
+    }
+
+    // Wraps an error raised by the instruction at `pc` the way `exec` does
+    // as the error comes up through the node it was compiled from and the
+    // nodes above that.
+    pub(crate) fn wrap_error(&self, err: Error, pc: usize) -> Error {
+        let text = |node: usize| {
+            let Span(start, end) = self.nodes[node].span;
+            self.text[start..end].to_string()
+        };
+        let mut node = self.origins[pc];
+        let mut err = err.with_context(|| text(node));
+        while let Some(parent) = self.nodes[node].parent {
+            if let Some(i) = self.nodes[node].statement {
+                err = err.in_statement(i + 1, || text(node));
+            }
+            err = err.with_context(|| text(parent));
+            node = parent;
+        }
+        err
+    }
+}
+
+// A unit of pending work for `Compiler::compile`, with the index of the node
+// it's for. The tasks for a node run in the order its instructions go.
+enum Task<'e, 'a> {
+    Expr(&'e ExprAST<'a>),
+    Emit(Instruction),
+    Prefix(&'a str, Arc<PrefixOpFunc>),
+    Postfix(Arc<PostfixOpFunc>),
+    Infix(&'a str, Arc<InfixOpFunc>),
+    // Carries the name of the target, if it's a reference.
+    Assign(&'a str, Arc<InfixOpFunc>, Option<&'a str>),
+    Call(&'a str, usize),
+    CallList(&'a str),
+    // Emits a jump whose target isn't known yet, keeping where it is in
+    // `labels[i]`.
+    Jump(Instruction, usize),
+    // Points the jump kept in `labels[i]` at the next instruction.
+    Patch(usize),
+}
+
+pub struct Compiler {
+    program: Program,
+    name_slots: HashMap<String, usize>,
+    function_slots: HashMap<String, usize>,
+    // The span of each node in `program.text`, keyed by the node's address.
+    spans: BTreeMap<usize, Span>,
+    labels: Vec<usize>,
+}
+
+impl Compiler {
+    /// Compiles an expression into a [`Program`]. Operators are resolved at
+    /// this point, so ones registered afterwards are not seen by the program.
+    /// The tree is walked with a work stack, so it can be as deep as
+    /// [`ExprAST::exec_iterative`] allows.
+    pub fn compile(expr: &ExprAST) -> Result<Program> {
+        let (text, spans) = expr.layout();
+        let mut compiler = Compiler {
+            program: Program {
+                instructions: Vec::new(),
+                constants: Vec::new(),
+                names: Vec::new(),
+                prefix: Vec::new(),
+                postfix: Vec::new(),
+                infix: Vec::new(),
+                functions: Vec::new(),
+                text,
+                origins: Vec::new(),
+                nodes: Vec::new(),
+            },
+            name_slots: HashMap::new(),
+            function_slots: HashMap::new(),
+            spans,
+            labels: Vec::new(),
+        };
+        let mut work = vec![compiler.child(expr, None)];
+        while let Some((node, task)) = work.pop() {
+            compiler.run(node, task, &mut work)?;
+        }
+        Ok(compiler.program)
+    }
+
+    fn run<'e, 'a>(
+        &mut self,
+        node: usize,
+        task: Task<'e, 'a>,
+        work: &mut Vec<(usize, Task<'e, 'a>)>,
+    ) -> Result<()> {
+        match task {
+            Task::Expr(expr) => {
+                let tasks = self.expand(node, expr)?;
+                work.extend(tasks.into_iter().rev());
+            }
+            Task::Emit(instruction) => {
+                self.emit(node, instruction);
+            }
+            Task::Prefix(op, handler) => {
+                self.program.prefix.push((op.to_string(), handler));
+                self.emit(node, Instruction::Prefix(self.program.prefix.len() - 1));
+            }
+            Task::Postfix(handler) => {
+                self.program.postfix.push(handler);
+                self.emit(node, Instruction::Postfix(self.program.postfix.len() - 1));
+            }
+            Task::Infix(op, handler) => {
+                self.program.infix.push((op.to_string(), handler));
+                self.emit(node, Instruction::Infix(self.program.infix.len() - 1));
+            }
+            Task::Assign(op, handler, target) => {
+                self.program.infix.push((op.to_string(), handler));
+                let handler = self.program.infix.len() - 1;
+                let target = target.map(|name| self.name_slot(name));
+                self.emit(node, Instruction::Assign(handler, target));
+            }
+            Task::Call(name, len) => {
+                let slot = self.function_slot(name);
+                self.emit(node, Instruction::Call(slot, len));
+            }
+            Task::CallList(name) => {
+                let slot = self.function_slot(name);
+                self.emit(node, Instruction::CallList(slot));
+            }
+            Task::Jump(jump, label) => self.labels[label] = self.emit(node, jump),
+            Task::Patch(label) => self.patch(self.labels[label]),
+        }
+        Ok(())
+    }
+
+    // The tasks that compile `expr`, the node at index `node`. Instructions
+    // that come before any of its children are emitted on the spot.
+    fn expand<'e, 'a>(
+        &mut self,
+        node: usize,
+        expr: &'e ExprAST<'a>,
+    ) -> Result<Vec<(usize, Task<'e, 'a>)>> {
+        use ExprAST::*;
+        let mut tasks = Vec::new();
+        match expr {
+            Literal(literal) => {
+                self.program.constants.push(literal_value(literal));
+                self.emit(node, Instruction::Const(self.program.constants.len() - 1));
+            }
+            Reference(name) => {
+                let slot = self.name_slot(name);
+                self.emit(node, Instruction::Load(slot));
+            }
+            None => {
+                self.emit(node, Instruction::PushNone);
+            }
+            Unary(op, rhs) => {
+                let handler = PrefixOpManager::new().get(op)?;
+                tasks.push(self.child(rhs, Some(node)));
+                tasks.push((node, Task::Prefix(op, handler)));
+            }
+            Postfix(lhs, op) => {
+                let handler = PostfixOpManager::new().get(op)?;
+                tasks.push(self.child(lhs, Some(node)));
+                tasks.push((node, Task::Postfix(handler)));
+            }
+            Binary(op, lhs, rhs) => {
+                let config = InfixOpManager::new().get(op)?;
+                match (&config.1, lhs.as_ref()) {
+                    (InfixOpType::SETTER, Reference(name)) => {
+                        let slot = self.name_slot(name);
+                        self.emit(node, Instruction::LoadTarget(slot));
+                    }
+                    _ => tasks.push(self.child(lhs, Some(node))),
+                }
+                tasks.push(self.child(rhs, Some(node)));
+                let task = match config.1 {
+                    InfixOpType::CALC => Task::Infix(op, config.3),
+                    InfixOpType::SETTER => {
+                        let target = match lhs.as_ref() {
+                            Reference(name) => Some(*name),
+                            _ => Option::None,
+                        };
+                        Task::Assign(op, config.3, target)
+                    }
+                };
+                tasks.push((node, task));
+            }
+            Ternary(condition, lhs, rhs) => {
+                let (to_rhs, to_end) = (self.label(), self.label());
+                tasks.push(self.child(condition, Some(node)));
+                tasks.push((node, Task::Jump(Instruction::JumpIfFalse(0), to_rhs)));
+                tasks.push(self.child(lhs, Some(node)));
+                tasks.push((node, Task::Jump(Instruction::Jump(0), to_end)));
+                tasks.push((node, Task::Patch(to_rhs)));
+                tasks.push(self.child(rhs, Some(node)));
+                tasks.push((node, Task::Patch(to_end)));
+            }
+            Function(name, exprs) if has_spread(exprs) => {
+                self.spread_list(node, exprs, &mut tasks);
+                tasks.push((node, Task::CallList(name)));
+            }
+            Function(name, exprs) => {
+                for expr in exprs {
+                    tasks.push(self.child(expr, Some(node)));
+                }
+                tasks.push((node, Task::Call(name, exprs.len())));
+            }
+            List(exprs) if has_spread(exprs) => {
+                self.spread_list(node, exprs, &mut tasks);
+                tasks.push((node, Task::Emit(Instruction::CheckLimits)));
+            }
+            List(exprs) => {
+                for expr in exprs {
+                    tasks.push(self.child(expr, Some(node)));
+                }
+                tasks.push((node, Task::Emit(Instruction::List(exprs.len()))));
+            }
+            Spread(inner) => {
+                self.emit(node, Instruction::List(0));
+                tasks.push(self.child(inner, Some(node)));
+                tasks.push((node, Task::Emit(Instruction::Extend)));
+            }
+            Map(m) => {
+                for (k, v) in m {
+                    tasks.push(self.child(k, Some(node)));
+                    tasks.push(self.child(v, Some(node)));
+                }
+                tasks.push((node, Task::Emit(Instruction::Map(m.len()))));
+            }
+            Match(scrutinee, arms, default) => {
+                tasks.push(self.child(scrutinee, Some(node)));
+                let mut to_end = Vec::with_capacity(arms.len());
+                for (pattern, value) in arms {
+                    let (to_next, end) = (self.label(), self.label());
+                    tasks.push(self.child(pattern, Some(node)));
+                    tasks.push((node, Task::Jump(Instruction::JumpIfNoMatch(0), to_next)));
+                    tasks.push(self.child(value, Some(node)));
+                    tasks.push((node, Task::Jump(Instruction::Jump(0), end)));
+                    tasks.push((node, Task::Patch(to_next)));
+                    to_end.push(end);
+                }
+                match default {
+                    Some(default) => {
+                        tasks.push((node, Task::Emit(Instruction::Pop)));
+                        tasks.push(self.child(default, Some(node)));
+                    }
+                    Option::None => tasks.push((node, Task::Emit(Instruction::NoMatch))),
+                }
+                tasks.extend(to_end.into_iter().map(|end| (node, Task::Patch(end))));
+            }
+            Stmt(exprs) => {
+                if exprs.is_empty() {
+                    self.emit(node, Instruction::PushNone);
+                }
+                for (i, expr) in exprs.iter().enumerate() {
+                    if i > 0 {
+                        tasks.push((node, Task::Emit(Instruction::Pop)));
+                    }
+                    let (statement, task) = self.child(expr, Some(node));
+                    self.program.nodes[statement].statement = Some(i);
+                    tasks.push((statement, task));
+                }
+            }
+        }
+        Ok(tasks)
+    }
+
+    // Lists with spread elements are built up one element at a time. A
+    // spread element is a node of its own, which `Extend` belongs to.
+    fn spread_list<'e, 'a>(
+        &mut self,
+        node: usize,
+        exprs: &'e [ExprAST<'a>],
+        tasks: &mut Vec<(usize, Task<'e, 'a>)>,
+    ) {
+        self.emit(node, Instruction::List(0));
+        for expr in exprs {
+            match expr {
+                ExprAST::Spread(inner) => {
+                    let (spread, _) = self.child(expr, Some(node));
+                    tasks.push(self.child(inner, Some(spread)));
+                    tasks.push((spread, Task::Emit(Instruction::Extend)));
+                }
+                _ => {
+                    tasks.push(self.child(expr, Some(node)));
+                    tasks.push((node, Task::Emit(Instruction::Append)));
+                }
+            }
+        }
+    }
+
+    // Adds `expr` to the nodes, returning the task that compiles it.
+    fn child<'e, 'a>(
+        &mut self,
+        expr: &'e ExprAST<'a>,
+        parent: Option<usize>,
+    ) -> (usize, Task<'e, 'a>) {
+        let address = expr as *const ExprAST as usize;
+        self.program.nodes.push(Node {
+            span: self.spans.get(&address).copied().unwrap_or(Span(0, 0)),
+            parent,
+            statement: Option::None,
+        });
+        (self.program.nodes.len() - 1, Task::Expr(expr))
+    }
+
+    fn label(&mut self) -> usize {
+        self.labels.push(0);
+        self.labels.len() - 1
+    }
+
+    fn emit(&mut self, node: usize, instruction: Instruction) -> usize {
+        self.program.instructions.push(instruction);
+        self.program.origins.push(node);
+        self.program.instructions.len() - 1
+    }
+
+    fn patch(&mut self, at: usize) {
+        let target = self.program.instructions.len();
+        match &mut self.program.instructions[at] {
+            Instruction::Jump(to)
+            | Instruction::JumpIfFalse(to)
+            | Instruction::JumpIfNoMatch(to) => *to = target,
+            _ => unreachable!(),
+        }
+    }
+
+    fn name_slot(&mut self, name: &str) -> usize {
+        if let Some(slot) = self.name_slots.get(name) {
+            return *slot;
+        }
+        self.program.names.push(name.to_string());
+        self.name_slots
+            .insert(name.to_string(), self.program.names.len() - 1);
+        self.program.names.len() - 1
+    }
+
+    fn function_slot(&mut self, name: &str) -> usize {
+        if let Some(slot) = self.function_slots.get(name) {
+            return *slot;
+        }
+        self.program
+            .functions
+            .push((name.to_string(), InnerFunctionManager::new().get(name).ok()));
+        self.function_slots
+            .insert(name.to_string(), self.program.functions.len() - 1);
+        self.program.functions.len() - 1
+    }
+}
+
+fn has_spread(exprs: &[ExprAST]) -> bool {
+    exprs.iter().any(|expr| matches!(expr, ExprAST::Spread(_)))
+}
+
+fn literal_value(literal: &Literal) -> Value {
+    match literal {
+        Literal::Number(value) => Value::from(*value),
+        Literal::Bool(value) => Value::from(*value),
+        Literal::String(value) => Value::from(*value),
+        Literal::None => Value::None,
+    }
+}
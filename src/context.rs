@@ -1,68 +1,839 @@
+use crate::compat::{HashMap, HashSet, IndexMap, Mutex};
 use crate::define::Result;
-use crate::function::InnerFunction;
-use crate::value::Value;
+use crate::error::Error;
+use crate::function::{
+    AsyncFunction, CollisionPolicy, ContextFunction, FunctionLibrary, InnerFunction,
+    InnerFunctionManager,
+};
+use crate::options::{EvalOptions, Limits, MissingReferencePolicy};
+use crate::provider::Providers;
+#[cfg(feature = "std")]
+use crate::provider::{ClockProvider, EnvProvider};
+use crate::random::Rng;
+use crate::suggest::suggestions;
+use crate::trace::TraceHook;
+use crate::value::{Map, Value};
+use crate::warning::Warning;
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
 use core::clone::Clone;
-use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
+use core::fmt;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 
 #[derive(Clone)]
 pub enum ContextValue {
     Variable(Value),
     Function(Arc<InnerFunction>),
+    AsyncFunction(Arc<AsyncFunction>),
+    /// A function that reads the context it's called from, like the ones
+    /// of [`crate::register_context_function`].
+    ContextFunction(Arc<ContextFunction>),
 }
 
-pub struct Context(pub Arc<Mutex<HashMap<String, ContextValue>>>);
+pub struct Context {
+    /// The variables and functions set on this context, by name, without
+    /// those of its base. Variables inserted here directly rather than
+    /// through the context aren't counted for [`Limits::max_variables`].
+    pub entries: Arc<Mutex<HashMap<String, ContextValue>>>,
+    // How many of the entries are variables, updated as they're set and
+    // removed under the lock of the entries, and shared with them.
+    variables: Arc<AtomicUsize>,
+    options: EvalOptions,
+    // The variables written since the last `take_dirty`, only allocated once
+    // tracking is enabled.
+    dirty: Option<IndexMap<String, Value>>,
+    providers: Providers,
+    trace_hook: Option<Arc<TraceHook>>,
+    // What lookups of names this context doesn't have fall through to.
+    base: Option<Arc<Context>>,
+    cancellation: Option<Cancellation>,
+    // Decides what missing references are and records them.
+    missing: MissingReferences,
+    warnings: Warnings,
+}
+
+// How many nodes are evaluated between two reads of the token: often enough
+// to stop within microseconds, rarely enough not to show in benchmarks.
+const CANCEL_CHECK_INTERVAL: usize = 256;
+
+struct Cancellation {
+    token: Arc<AtomicBool>,
+    ticks: usize,
+}
+
+// Missing references are recorded from `&self`, hence the lock, which a
+// read-only view shares.
+#[derive(Default)]
+struct MissingReferences {
+    policy: MissingReferencePolicy,
+    names: Arc<Mutex<Vec<String>>>,
+}
+
+// Also recorded from `&self` and shared by a view. Each warning is paired
+// with the address of the node `exec` was evaluating when it was raised,
+// once that node is done.
+#[derive(Default)]
+struct Warnings(Arc<Mutex<Vec<Raised>>>);
+
+type Raised = (Warning, Option<usize>);
 
 impl Context {
     pub fn new() -> Self {
-        Context(Arc::new(Mutex::new(HashMap::new())))
+        Self::with_options(EvalOptions::default())
+    }
+
+    pub fn with_options(options: EvalOptions) -> Self {
+        Context {
+            entries: Arc::new(Mutex::new(HashMap::new())),
+            variables: Arc::new(AtomicUsize::new(0)),
+            options,
+            dirty: None,
+            providers: Providers::default(),
+            trace_hook: None,
+            base: None,
+            cancellation: None,
+            missing: MissingReferences::default(),
+            warnings: Warnings::default(),
+        }
+    }
+
+    /// An empty context layered on `base`: variables and functions this
+    /// context doesn't have are read from `base`, while everything set goes
+    /// into this context, so one base can be shared by many of them, e.g. one
+    /// per request or thread. A name set here shadows the one in `base`. The
+    /// options, providers and missing reference policy start as those of
+    /// `base`.
+    pub fn with_base(base: Arc<Context>) -> Self {
+        let mut ctx = Self::with_options(base.options);
+        ctx.providers = base.providers.clone();
+        ctx.missing.policy = base.missing.policy.clone();
+        ctx.base = Some(base);
+        ctx
+    }
+
+    // A context on the same variables, functions, base and generator, with
+    // its own tick count, to evaluate through `&self`.
+    pub(crate) fn view(&self) -> Self {
+        let mut providers = self.providers.clone();
+        providers.rng = self.providers.rng.as_ref().map(Rng::share);
+        let cancellation = self.cancellation.as_ref().map(|cancellation| Cancellation {
+            token: cancellation.token.clone(),
+            ticks: 0,
+        });
+        Context {
+            entries: self.entries.clone(),
+            variables: self.variables.clone(),
+            options: self.options,
+            dirty: None,
+            providers,
+            trace_hook: self.trace_hook.clone(),
+            base: self.base.clone(),
+            cancellation,
+            missing: MissingReferences {
+                policy: self.missing.policy.clone(),
+                names: self.missing.names.clone(),
+            },
+            warnings: Warnings(self.warnings.0.clone()),
+        }
+    }
+
+    pub fn options(&self) -> EvalOptions {
+        self.options
+    }
+
+    pub fn set_options(&mut self, options: EvalOptions) {
+        self.options = options;
+    }
+
+    /// Replaces the resource limits in the options.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.options.limits = limits;
+    }
+
+    /// Replaces the system clock that `now()` reads. With both the clock and
+    /// the environment fixed, e.g. with [`crate::FixedClock`] and
+    /// [`crate::FixedEnv`], evaluating an expression is reproducible.
+    #[cfg(feature = "std")]
+    pub fn set_clock(&mut self, clock: Arc<dyn ClockProvider>) {
+        self.providers.clock = Some(clock);
+    }
+
+    /// Replaces the process environment that `env(name)` reads.
+    #[cfg(feature = "std")]
+    pub fn set_env(&mut self, env: Arc<dyn EnvProvider>) {
+        self.providers.env = Some(env);
+    }
+
+    /// Seeds the generator `random()` and `randInt()` draw from, so the
+    /// same expressions draw the same numbers. Without a seed they draw from
+    /// the OS, and fail with [`Error::NoRngSeed`] without `std`. A layered
+    /// context continues from the state of its base when it's created.
+    pub fn set_rng_seed(&mut self, seed: u64) {
+        self.providers.rng = Some(Rng::new(seed));
+    }
+
+    #[cfg(feature = "builtin-math")]
+    pub(crate) fn next_random(&self) -> Result<u64> {
+        match &self.providers.rng {
+            Some(rng) => Ok(rng.next()),
+            None => crate::random::entropy(),
+        }
+    }
+
+    /// Sets the hook [`crate::ExprAST::exec`] calls with every node it
+    /// evaluates successfully and the node's value, children before their
+    /// parent. `None` removes it.
+    pub fn set_trace_hook(&mut self, hook: Option<Arc<TraceHook>>) {
+        self.trace_hook = hook;
+    }
+
+    pub fn trace_hook(&self) -> Option<&Arc<TraceHook>> {
+        self.trace_hook.as_ref()
+    }
+
+    /// Sets the token that cancels evaluation: once another thread stores
+    /// `true` in it, evaluating fails with [`Error::Cancelled`] within a few
+    /// hundred nodes. `None` removes it. A layered context doesn't inherit
+    /// the token of its base.
+    pub fn set_cancellation(&mut self, token: Option<Arc<AtomicBool>>) {
+        self.cancellation = token.map(|token| Cancellation { token, ticks: 0 });
+    }
+
+    /// Whether the cancellation token is set, for functions registered with
+    /// [`crate::register_context_function`] to stop their own loops.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancellation
+            .as_ref()
+            .is_some_and(|cancellation| cancellation.token.load(Ordering::Relaxed))
+    }
+
+    // Called for every node evaluated; only reads the token every
+    // CANCEL_CHECK_INTERVAL calls.
+    pub(crate) fn tick(&mut self) -> Result<()> {
+        if let Some(cancellation) = &mut self.cancellation {
+            cancellation.ticks += 1;
+            if cancellation.ticks % CANCEL_CHECK_INTERVAL == 0
+                && cancellation.token.load(Ordering::Relaxed)
+            {
+                return Err(Error::Cancelled);
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "std")]
+    pub fn clock(&self) -> &dyn ClockProvider {
+        self.providers.clock()
+    }
+
+    #[cfg(feature = "std")]
+    pub fn env(&self) -> &dyn EnvProvider {
+        self.providers.env()
+    }
+
+    /// Decides what references nothing in the context resolves evaluate to.
+    /// [`EvalOptions::strict_references`] still makes them fail.
+    pub fn set_missing_reference_policy(&mut self, policy: MissingReferencePolicy) {
+        self.missing.policy = policy;
+    }
+
+    /// The references nothing resolved since the context was created or
+    /// since the last call, in the order they were first missed, whatever
+    /// the policy. Assignment targets that don't exist yet aren't included.
+    pub fn take_missing_references(&self) -> Vec<String> {
+        core::mem::take(&mut *self.missing.names.lock().unwrap())
+    }
+
+    /// Records a warning under [`EvalOptions::collect_warnings`], for
+    /// functions registered with [`crate::register_context_function`] to
+    /// report something that shouldn't fail the evaluation.
+    pub fn warn(&self, code: &'static str, message: impl Into<String>) {
+        if self.options.collect_warnings {
+            let warning = Warning {
+                code,
+                message: message.into(),
+                span: None,
+            };
+            self.warnings.0.lock().unwrap().push((warning, None));
+        }
+    }
+
+    /// The warnings recorded since the context was created or since the
+    /// last call, in the order they were raised. Those of
+    /// [`crate::ExprAST::exec_with_warnings`] are returned by it instead.
+    pub fn take_warnings(&self) -> Vec<Warning> {
+        let warnings = core::mem::take(&mut *self.warnings.0.lock().unwrap());
+        warnings.into_iter().map(|(warning, _)| warning).collect()
+    }
+
+    // Pairs the warnings raised since a node was last done with `node`,
+    // which is done now. Children are done before their parent, so that's
+    // the node that raised them.
+    pub(crate) fn finish_warnings(&self, node: usize) {
+        let mut warnings = self.warnings.0.lock().unwrap();
+        for (_, raised_by) in warnings.iter_mut().rev() {
+            if raised_by.is_some() {
+                break;
+            }
+            *raised_by = Some(node);
+        }
+    }
+
+    pub(crate) fn warning_count(&self) -> usize {
+        self.warnings.0.lock().unwrap().len()
+    }
+
+    // The warnings raised after the first `start`, with their nodes.
+    pub(crate) fn split_warnings(&self, start: usize) -> Vec<Raised> {
+        let mut warnings = self.warnings.0.lock().unwrap();
+        let start = start.min(warnings.len());
+        warnings.split_off(start)
+    }
+
+    /// Starts recording the variables set through this context, for
+    /// [`Context::take_dirty`].
+    pub fn enable_tracking(&mut self) {
+        self.dirty.get_or_insert_with(IndexMap::default);
+    }
+
+    /// The variables set since tracking was enabled or since the last call,
+    /// in the order they were first set, each with its latest value. A
+    /// dotted assignment like `user.name = 'Ada'` records the whole `user`.
+    /// Empty when tracking isn't enabled.
+    pub fn take_dirty(&mut self) -> Vec<(String, Value)> {
+        match &mut self.dirty {
+            Some(dirty) => core::mem::take(dirty).into_iter().collect(),
+            None => Vec::new(),
+        }
+    }
+
+    fn mark_dirty(&mut self, name: &str, value: &Value) {
+        if let Some(dirty) = &mut self.dirty {
+            match dirty.get_mut(name) {
+                Some(entry) => *entry = value.clone(),
+                None => {
+                    dirty.insert(name.to_string(), value.clone());
+                }
+            }
+        }
     }
 
     pub fn set_func(&mut self, name: &str, func: Arc<InnerFunction>) {
         self.set(name, ContextValue::Function(func.clone()));
     }
 
+    /// Sets a function that reads the context it's called from.
+    pub fn set_context_func(&mut self, name: &str, func: Arc<ContextFunction>) {
+        self.set(name, ContextValue::ContextFunction(func));
+    }
+
+    /// Sets the functions of `library` on this context, see
+    /// [`CollisionPolicy`] for those whose name a variable or a function of
+    /// the context, or of its base, already has. The context keeps no
+    /// metadata, so they aren't deterministic and `help` doesn't list them.
+    pub fn register_library(
+        &mut self,
+        library: &dyn FunctionLibrary,
+        policy: CollisionPolicy,
+    ) -> Result<()> {
+        let functions = library.functions();
+        if policy == CollisionPolicy::Error {
+            let mut names = HashSet::new();
+            for (metadata, _) in &functions {
+                if self.get(&metadata.name).is_some() || !names.insert(metadata.name.as_str()) {
+                    return Err(Error::FunctionExists(metadata.name.clone()));
+                }
+            }
+        }
+        for (metadata, func) in functions {
+            if policy != CollisionPolicy::Skip || self.get(&metadata.name).is_none() {
+                self.set_context_func(&metadata.name, func);
+            }
+        }
+        Ok(())
+    }
+
+    /// Sets a function called as `namespace.name`, e.g. `text.shout(s)`, and
+    /// with `alias` also as the bare `name`.
+    pub fn set_namespaced_func(
+        &mut self,
+        namespace: &str,
+        name: &str,
+        func: Arc<InnerFunction>,
+        alias: bool,
+    ) {
+        if alias {
+            self.set_func(name, func.clone());
+        }
+        self.set_func(&format!("{}.{}", namespace, name), func);
+    }
+
+    /// Sets a function that [`crate::ExprAST::exec_async`] awaits. The other
+    /// ways of evaluating fail with [`Error::AsyncFunctionInSync`] when they
+    /// call it.
+    #[cfg(feature = "async")]
+    pub fn set_async_func(&mut self, name: &str, func: Arc<AsyncFunction>) {
+        self.set(name, ContextValue::AsyncFunction(func));
+    }
+
+    #[cfg(feature = "async")]
+    pub(crate) fn get_async_func(&self, name: &str) -> Option<Arc<AsyncFunction>> {
+        match self.get(name)? {
+            ContextValue::AsyncFunction(func) => Some(func),
+            _ => None,
+        }
+    }
+
     pub fn set_variable(&mut self, name: &str, value: Value) {
         self.set(name, ContextValue::Variable(value));
     }
 
+    /// Sets every variable of `variables`, like [`Context::set_variable`]
+    /// does one at a time, e.g. the readings that changed since the last
+    /// evaluation. Replacing a variable reuses its entry, so updating the
+    /// same context over and over allocates nothing but the values.
+    pub fn update_from<K: AsRef<str>>(&mut self, variables: impl IntoIterator<Item = (K, Value)>) {
+        for (name, value) in variables {
+            self.set_variable(name.as_ref(), value);
+        }
+    }
+
+    /// Makes room for `additional` more variables and functions, so setting
+    /// them doesn't grow the table on the way.
+    pub fn reserve(&mut self, additional: usize) {
+        self.entries.lock().unwrap().reserve(additional);
+    }
+
+    // The current value of an assignment target, which is `None` rather than
+    // an error when it doesn't exist yet or the map a dotted path leads to
+    // lacks the last keys, since assigning creates them.
+    // A default from the missing reference policy is the current value, so
+    // `count += 1` counts from it.
+    pub(crate) fn target_value(&self, name: &str) -> Result<Value> {
+        match self.resolve(name) {
+            Some(Err(Error::MissingKey(..))) => Ok(Value::None),
+            Some(ans) => ans,
+            None => match &self.missing.policy {
+                MissingReferencePolicy::Default(value) => Ok(value.clone()),
+                _ => Ok(Value::None),
+            },
+        }
+    }
+
+    /// Sets `path` like [`Context::set_variable`], except that a path with
+    /// dots that isn't itself a variable, like `user.address.city`, sets a
+    /// key in nested maps, creating the root variable and any missing maps
+    /// along the way. The root is the longest dotted prefix that is a
+    /// variable, mirroring [`Context::value`]. Fails if a prefix holds
+    /// something other than a map. A root from the base is copied into this
+    /// context before it's changed. Fails with [`Error::AssignmentForbidden`]
+    /// under [`EvalOptions::forbid_assignment`], like the assignments of an
+    /// expression, which go through here.
+    pub fn set_path(&mut self, path: &str, value: Value) -> Result<()> {
+        if self.options.forbid_assignment {
+            return Err(Error::AssignmentForbidden(path.to_string()));
+        }
+        if !path.contains('.') || self.contains(path) {
+            return self.assign(path, value);
+        }
+        let end = path
+            .rmatch_indices('.')
+            .map(|(end, _)| end)
+            .find(|end| self.contains(&path[..*end]))
+            .unwrap_or_else(|| path.find('.').unwrap());
+        let mut root = match self.get(&path[..end]) {
+            Some(ContextValue::Variable(root)) => root,
+            Some(
+                ContextValue::Function(_)
+                | ContextValue::AsyncFunction(_)
+                | ContextValue::ContextFunction(_),
+            ) => return Err(Error::NotAMap(path[..end].to_string(), "Function")),
+            None => Value::Map(Map::default()),
+        };
+        insert_path(&mut root, path, end, value)?;
+        self.assign(&path[..end], root)
+    }
+
+    // `set_variable`, for assignments made by an expression, which the
+    // limits in the options apply to.
+    fn assign(&mut self, name: &str, value: Value) -> Result<()> {
+        let limits = self.options.limits;
+        let value = limits.check(value)?;
+        if limits.max_variables.is_some() && !self.contains(name) {
+            limits.check_variables(self.variables.load(Ordering::Relaxed) + 1)?;
+        }
+        self.set_variable(name, value);
+        Ok(())
+    }
+
     pub fn set(&mut self, name: &str, v: ContextValue) {
-        self.0.lock().unwrap().insert(name.to_string(), v);
+        if let ContextValue::Variable(value) = &v {
+            self.mark_dirty(name, value);
+        }
+        let variable = matches!(v, ContextValue::Variable(_));
+        // Only a new name needs its own copy of the key.
+        let mut entries = self.entries.lock().unwrap();
+        let was_variable = match entries.get_mut(name) {
+            Some(entry) => matches!(core::mem::replace(entry, v), ContextValue::Variable(_)),
+            None => {
+                entries.insert(name.to_string(), v);
+                false
+            }
+        };
+        match (was_variable, variable) {
+            (false, true) => self.variables.fetch_add(1, Ordering::Relaxed),
+            (true, false) => self.variables.fetch_sub(1, Ordering::Relaxed),
+            _ => 0,
+        };
     }
 
+    /// The function called `name`. For an async one, that is a function
+    /// failing with [`Error::AsyncFunctionInSync`], so that evaluating
+    /// without awaiting reports it. One that reads the context reads this
+    /// one, as it is now.
     pub fn get_func(&self, name: &str) -> Option<Arc<InnerFunction>> {
         let value = self.get(name)?;
         match value {
             ContextValue::Function(func) => Some(func.clone()),
+            ContextValue::ContextFunction(func) => {
+                let ctx = self.view();
+                Some(Arc::new(move |params| func(&ctx, params)))
+            }
+            ContextValue::AsyncFunction(_) => {
+                let name = name.to_string();
+                Some(Arc::new(move |_| {
+                    Err(Error::AsyncFunctionInSync(name.clone()))
+                }))
+            }
             ContextValue::Variable(_) => None,
         }
     }
 
+    // Under `EvalOptions::deterministic_only`, fails for a function of the
+    // context or an inner function that isn't deterministic. Unknown names
+    // are left to the lookup, which suggests others. Warns of a call of a
+    // deprecated inner function.
+    pub(crate) fn check_call(&self, name: &str) -> Result<()> {
+        let functions = InnerFunctionManager::new();
+        if self.options.collect_warnings && self.get_func(name).is_none() {
+            if let Some(note) = functions.deprecation(name) {
+                let message = format!("'{}' is deprecated: {}", name, note);
+                self.warn("W_DEPRECATED_FUNCTION", message);
+            }
+        }
+        if !self.options.deterministic_only {
+            return Ok(());
+        }
+        if self.get_func(name).is_some()
+            || (functions.get(name).is_ok() && !functions.is_deterministic(name))
+        {
+            return Err(Error::NonDeterministicCall(name.to_string()));
+        }
+        Ok(())
+    }
+
     pub fn get_variable(&self, name: &str) -> Option<Value> {
         let value = self.get(name)?;
         match value {
             ContextValue::Variable(v) => Some(v.clone()),
-            ContextValue::Function(_) => None,
+            _ => None,
+        }
+    }
+
+    /// The variables and their values, sorted by name. The context sits
+    /// behind a lock, so this is a snapshot: the pairs are copies, and
+    /// changes made while iterating don't show up.
+    pub fn variables(&self) -> impl Iterator<Item = (String, Value)> {
+        self.visible(|value| match value {
+            ContextValue::Variable(v) => Some(v.clone()),
+            _ => None,
+        })
+        .into_iter()
+    }
+
+    /// The names of the variables, sorted.
+    pub fn variable_names(&self) -> Vec<String> {
+        self.names(|value| matches!(value, ContextValue::Variable(_)))
+    }
+
+    /// The names of the functions, sorted.
+    pub fn function_names(&self) -> Vec<String> {
+        self.names(|value| !matches!(value, ContextValue::Variable(_)))
+    }
+
+    fn names(&self, filter: impl Fn(&ContextValue) -> bool) -> Vec<String> {
+        self.visible(|value| filter(value).then_some(()))
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect()
+    }
+
+    // The entries that aren't shadowed by a layer above, sorted by name and
+    // mapped through `f`, which drops those it returns `None` for.
+    fn visible<T>(&self, f: impl Fn(&ContextValue) -> Option<T>) -> Vec<(String, T)> {
+        let mut seen = HashSet::new();
+        let mut ans = Vec::new();
+        let mut layer = Some(self);
+        while let Some(ctx) = layer {
+            for (name, value) in ctx.entries.lock().unwrap().iter() {
+                if seen.insert(name.clone()) {
+                    ans.extend(f(value).map(|value| (name.clone(), value)));
+                }
+            }
+            layer = ctx.base.as_deref();
+        }
+        ans.sort_by(|(a, _), (b, _)| a.cmp(b));
+        ans
+    }
+
+    // Applies `f` to the entry `name` of the topmost layer that has one.
+    fn find<T>(&self, name: &str, f: impl FnOnce(&ContextValue) -> T) -> Option<T> {
+        let mut layer = self;
+        loop {
+            if let Some(value) = layer.entries.lock().unwrap().get(name) {
+                return Some(f(value));
+            }
+            layer = layer.base.as_deref()?;
+        }
+    }
+
+    /// Whether a variable or function is called `name`, here or in the base.
+    /// Dotted paths into maps don't count.
+    pub fn contains(&self, name: &str) -> bool {
+        self.find(name, |_| ()).is_some()
+    }
+
+    /// Removes the variable `name` and returns its value. A function of
+    /// that name is left alone, and so is the base, whose variable of the
+    /// same name shows through again. Tracking records the removal with
+    /// what the reference now evaluates to.
+    pub fn remove_variable(&mut self, name: &str) -> Option<Value> {
+        let mut binding = self.entries.lock().unwrap();
+        let value = match binding.remove(name)? {
+            ContextValue::Variable(value) => value,
+            func => {
+                binding.insert(name.to_string(), func);
+                return None;
+            }
+        };
+        self.variables.fetch_sub(1, Ordering::Relaxed);
+        drop(binding);
+        self.mark_removed(name);
+        Some(value)
+    }
+
+    /// Removes every variable and function of this context, leaving the
+    /// base alone. The options, providers and trace hook stay, and tracking
+    /// records each removed variable like [`Context::remove_variable`].
+    pub fn clear(&mut self) {
+        let mut entries = self.entries.lock().unwrap();
+        self.variables.store(0, Ordering::Relaxed);
+        let removed: Vec<String> = core::mem::take(&mut *entries)
+            .into_iter()
+            .filter(|(_, value)| matches!(value, ContextValue::Variable(_)))
+            .map(|(name, _)| name)
+            .collect();
+        drop(entries);
+        for name in removed {
+            self.mark_removed(&name);
+        }
+    }
+
+    fn mark_removed(&mut self, name: &str) {
+        let value = self.get_variable(name).unwrap_or(Value::None);
+        self.mark_dirty(name, &value);
+    }
+
+    /// Copies every variable and function `other` provides, its base
+    /// included, into this context. Those of `other` win over existing ones
+    /// of the same name.
+    pub fn merge(&mut self, other: &Context) {
+        for (name, value) in other.visible(|value| Some(value.clone())) {
+            self.set(&name, value);
         }
     }
 
     pub fn get(&self, name: &str) -> Option<ContextValue> {
-        let binding = self.0.lock().unwrap();
-        let value = binding.get(name)?;
-        Some(value.clone())
+        self.find(name, ContextValue::clone)
     }
 
+    /// The value of the reference `name`. A name that isn't in the context
+    /// but contains dots, like `user.address.city`, is looked up as a path:
+    /// the longest dotted prefix that is in the context is the root, and the
+    /// remaining segments are keys into nested maps. A key after `?.`
+    /// instead of `.`, like in `user?.address?.city`, is null-safe: when the
+    /// value before it is `None` or a map without the key, the whole path is
+    /// `None`. Names nothing resolves are recorded for
+    /// [`Context::take_missing_references`] and evaluate as the
+    /// [`MissingReferencePolicy`] says, `Value::None` by default, or fail
+    /// with [`Error::ReferenceNotExist`] under
+    /// [`EvalOptions::strict_references`].
     pub fn value(&self, name: &str) -> Result<Value> {
-        let binding = self.0.lock().unwrap();
-        if binding.get(name).is_none() {
-            return Ok(Value::None);
+        if let Some(ans) = self.resolve(name) {
+            return ans;
         }
-        let value = binding.get(name).unwrap();
-        match value {
-            ContextValue::Variable(v) => Ok(v.clone()),
+        let mut names = self.missing.names.lock().unwrap();
+        if !names.iter().any(|missing| missing == name) {
+            names.push(name.to_string());
+        }
+        drop(names);
+        match &self.missing.policy {
+            _ if self.options.strict_references => Err(self.not_exist(name)),
+            MissingReferencePolicy::Error => Err(self.not_exist(name)),
+            MissingReferencePolicy::None => Ok(Value::None),
+            MissingReferencePolicy::Default(value) => Ok(value.clone()),
+        }
+    }
+
+    fn not_exist(&self, name: &str) -> Error {
+        let names = self.visible(|_| Some(()));
+        let suggestions = suggestions(name, names.iter().map(|(name, _)| name.as_str()));
+        Error::ReferenceNotExist(name.to_string(), suggestions)
+    }
+
+    // The value of `name` as a variable, function or path, or `None` when
+    // nothing resolves it.
+    fn resolve(&self, name: &str) -> Option<Result<Value>> {
+        let found = self.read(name, |value| Ok(value.clone()));
+        if found.is_some() {
+            return found;
+        }
+        for (end, _) in name.rmatch_indices('.') {
+            let root = name[..end].strip_suffix('?').unwrap_or(&name[..end]);
+            let found = self.read(root, |value| lookup_path(value, name, end));
+            if found.is_some() {
+                return found;
+            }
+        }
+        None
+    }
+
+    // Applies `f` to the value of the entry `name`, which for a function is
+    // what calling it without params returns. A function is cloned out of
+    // the entries and called once their lock is released, since one that
+    // reads the context locks them again.
+    fn read(&self, name: &str, f: impl Fn(&Value) -> Result<Value>) -> Option<Result<Value>> {
+        let func = match self.find(name, |value| match value {
+            ContextValue::Variable(v) => Ok(f(v)),
+            func => Err(func.clone()),
+        })? {
+            Ok(ans) => return Some(ans),
+            Err(func) => func,
+        };
+        let value = match func {
             ContextValue::Function(func) => func(Vec::new()),
+            ContextValue::ContextFunction(func) => func(self, Vec::new()),
+            ContextValue::AsyncFunction(_) | ContextValue::Variable(_) => {
+                Err(Error::AsyncFunctionInSync(name.to_string()))
+            }
+        };
+        Some(value.and_then(|value| f(&value)))
+    }
+}
+
+// Closures can't be printed, so functions only show their names.
+impl fmt::Debug for Context {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Context")
+            .field("variables", &DebugMap(self.variables().collect()))
+            .field("functions", &self.function_names())
+            .field("options", &self.options)
+            .finish()
+    }
+}
+
+// Functions can't be serialized either, so they're listed by name.
+#[cfg(feature = "serde")]
+impl serde::Serialize for Context {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> core::result::Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+        let variables: Vec<(String, Value)> = self.variables().collect();
+        let mut state = serializer.serialize_struct("Context", 2)?;
+        state.serialize_field("variables", &SerializeMap(&variables))?;
+        state.serialize_field("functions", &self.function_names())?;
+        state.end()
+    }
+}
+
+#[cfg(feature = "serde")]
+struct SerializeMap<'a>(&'a [(String, Value)]);
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for SerializeMap<'_> {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> core::result::Result<S::Ok, S::Error> {
+        serializer.collect_map(self.0.iter().map(|(name, value)| (name, value)))
+    }
+}
+
+struct DebugMap(Vec<(String, Value)>);
+
+impl fmt::Debug for DebugMap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map()
+            .entries(self.0.iter().map(|(name, value)| (name, value)))
+            .finish()
+    }
+}
+
+// Walks the keys of `path` after the byte offset `start` into `root`,
+// creating missing maps, and sets the last one to `value`. Nothing is
+// created unless the whole path can be set.
+fn insert_path(root: &mut Value, path: &str, start: usize, value: Value) -> Result<()> {
+    let mut target = root;
+    let mut end = start;
+    let mut keys = path[start + 1..].split('.').peekable();
+    while let Some(key) = keys.next() {
+        let m = match target {
+            Value::Map(m) => m,
+            other => return Err(Error::NotAMap(path[..end].to_string(), other.type_name())),
+        };
+        if keys.peek().is_none() {
+            m.insert(Value::from(key), value);
+            return Ok(());
         }
+        target = m
+            .entry(Value::from(key))
+            .or_insert_with(|| Value::Map(Map::default()));
+        end += key.len() + 1;
+    }
+    Ok(())
+}
+
+// Walks the keys of `path` after the byte offset `start` into `root`. A
+// `?` ending a segment makes the access after it null-safe, which only
+// forgives `None` and missing keys, not values that aren't maps.
+fn lookup_path(root: &Value, path: &str, start: usize) -> Result<Value> {
+    let mut value = root;
+    let mut end = start;
+    for segment in path[start + 1..].split('.') {
+        let key = segment.strip_suffix('?').unwrap_or(segment);
+        let prefix = path[..end].strip_suffix('?');
+        let child = match value {
+            Value::Map(m) => m.get(&Value::from(key)),
+            _ => None,
+        };
+        value = match (child, prefix) {
+            (Some(child), _) => child,
+            (None, Some(_)) if matches!(value, Value::None | Value::Map(_)) => {
+                return Ok(Value::None)
+            }
+            (None, Some(prefix)) => {
+                return Err(Error::NotAMap(prefix.to_string(), value.type_name()))
+            }
+            (None, None) => {
+                return Err(Error::MissingKey(path[..end].to_string(), key.to_string()))
+            }
+        };
+        end += segment.len() + 1;
     }
+    Ok(value.clone())
 }
 
 ///
@@ -98,9 +869,578 @@ macro_rules! create_context {
     (($ctx:expr)) => {};
 
     ($($tt:tt)*) => {{
-        use std::sync::Arc;
+        #[allow(unused_imports)]
+        use $crate::__private::Arc;
+        #[allow(unused_mut)]
         let mut ctx = $crate::Context::new();
         $crate::create_context!((&mut ctx) $($tt)*);
         ctx
     }};
 }
+
+#[cfg(all(test, feature = "default"))]
+mod tests {
+    use super::Context;
+    use crate::compiler::Compiler;
+    use crate::error::Error;
+    use crate::options::{EvalOptions, MissingReferencePolicy};
+    use crate::parse_expression;
+    use crate::value::{Map, Value};
+    use crate::vm::Vm;
+    use rstest::rstest;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_names() {
+        let ctx = create_context!(
+            "b" => 1,
+            "a" => 2,
+            "g" => Arc::new(|_| Ok(Value::None)),
+            "f" => Arc::new(|_| Ok(Value::None))
+        );
+        assert_eq!(ctx.variable_names(), ["a", "b"]);
+        assert_eq!(ctx.function_names(), ["f", "g"]);
+        assert!(crate::Context::new().variable_names().is_empty());
+    }
+
+    #[test]
+    fn test_variables() {
+        let mut ctx = create_context!(
+            "b" => 1,
+            "a" => "x",
+            "c" => 3,
+            "f" => Arc::new(|_| Ok(Value::None))
+        );
+        // Sorted by name, whatever the insertion order.
+        let variables: Vec<(String, Value)> = ctx.variables().collect();
+        assert_eq!(
+            variables,
+            [
+                ("a".to_string(), "x".into()),
+                ("b".to_string(), 1.into()),
+                ("c".to_string(), 3.into()),
+            ]
+        );
+        ctx.set_variable("aa", Value::None);
+        assert_eq!(ctx.variables().nth(1).unwrap().0, "aa");
+    }
+
+    #[test]
+    fn test_update_from() {
+        let mut ctx = create_context!("a" => 1, "b" => 2);
+        ctx.reserve(2);
+        ctx.enable_tracking();
+        ctx.update_from([("b", Value::from(3)), ("c", Value::from(4))]);
+        ctx.update_from(vec![
+            ("a".to_string(), Value::from(5)),
+            ("b".to_string(), 6.into()),
+        ]);
+        assert_eq!(ctx.variable_names(), ["a", "b", "c"]);
+        assert_eq!(ctx.get_variable("b"), Some(6.into()));
+        // Replaced entries keep their place in the order they were first set.
+        let dirty: Vec<String> = ctx.take_dirty().into_iter().map(|(name, _)| name).collect();
+        assert_eq!(dirty, ["b", "c", "a"]);
+    }
+
+    #[test]
+    fn test_contains_and_remove() {
+        let mut ctx = create_context!("a" => 1, "f" => Arc::new(|_| Ok(Value::None)));
+        assert!(ctx.contains("a") && ctx.contains("f") && !ctx.contains("b"));
+        ctx.enable_tracking();
+        assert_eq!(ctx.remove_variable("f"), None);
+        assert_eq!(ctx.remove_variable("b"), None);
+        assert!(ctx.contains("f"));
+        assert_eq!(ctx.remove_variable("a"), Some(1.into()));
+        assert!(!ctx.contains("a"));
+        assert_eq!(ctx.remove_variable("a"), None);
+        assert_eq!(ctx.take_dirty(), [("a".to_string(), Value::None)]);
+        assert_eq!(
+            parse_expression("a").unwrap().exec(&mut ctx).unwrap(),
+            Value::None
+        );
+    }
+
+    #[test]
+    fn test_clear() {
+        let mut ctx = create_context!("a" => 1, "f" => Arc::new(|_| Ok(Value::None)));
+        ctx.enable_tracking();
+        ctx.clear();
+        assert!(ctx.variable_names().is_empty() && ctx.function_names().is_empty());
+        assert_eq!(ctx.take_dirty(), [("a".to_string(), Value::None)]);
+        ctx.set_variable("a", 2.into());
+        assert_eq!(ctx.value("a").unwrap(), 2.into());
+    }
+
+    #[test]
+    fn test_debug() {
+        let ctx = create_context!(
+            "b" => 1,
+            "a" => "x",
+            "f" => Arc::new(|_| Ok(Value::None))
+        );
+        let debug = format!("{:?}", ctx);
+        assert!(
+            debug.starts_with(&format!(
+                "Context {{ variables: {{\"a\": {:?}, \"b\": {:?}}}, functions: [\"f\"], options: ",
+                Value::from("x"),
+                Value::from(1)
+            )),
+            "{}",
+            debug
+        );
+    }
+
+    #[cfg(all(feature = "serde", feature = "serde_json"))]
+    #[test]
+    fn test_serialize() {
+        let ctx = create_context!(
+            "b" => 1.5,
+            "a" => vec![Value::from("x"), Value::None],
+            "f" => Arc::new(|_| Ok(Value::None))
+        );
+        assert_eq!(
+            serde_json::to_value(&ctx).unwrap(),
+            serde_json::json!({"variables": {"a": ["x", null], "b": 1.5}, "functions": ["f"]})
+        );
+    }
+
+    fn base() -> Arc<Context> {
+        Arc::new(create_context!(
+            "rate" => 2,
+            "user" => Value::Map(Map::from_iter([("name".into(), "Ada".into())])),
+            "f" => Arc::new(|_| Ok(1.into()))
+        ))
+    }
+
+    #[test]
+    fn test_with_base_fallthrough() {
+        let mut ctx = Context::with_base(base());
+        ctx.set_variable("qty", 3.into());
+        let ast = parse_expression("[qty * rate + f(), user.name, rate]").unwrap();
+        assert_eq!(
+            ast.exec(&mut ctx).unwrap(),
+            Value::List(vec![7.into(), "Ada".into(), 2.into()])
+        );
+        assert!(ctx.contains("rate") && ctx.contains("qty"));
+        assert_eq!(ctx.variable_names(), ["qty", "rate", "user"]);
+        assert_eq!(ctx.function_names(), ["f"]);
+    }
+
+    #[test]
+    fn test_with_base_shadowing() {
+        let base = base();
+        let mut ctx = Context::with_base(base.clone());
+        ctx.set_variable("rate", 5.into());
+        ctx.set_func("f", Arc::new(|_| Ok(10.into())));
+        ctx.set_variable("user.name", "shadow".into());
+        let ast = parse_expression("[rate, f(), user.name]").unwrap();
+        assert_eq!(
+            ast.exec(&mut ctx).unwrap(),
+            Value::List(vec![5.into(), 10.into(), "shadow".into()])
+        );
+        // A variable can shadow a function and the other way around.
+        ctx.set_variable("f", 4.into());
+        assert_eq!(ctx.function_names(), Vec::<String>::new());
+        assert_eq!(ctx.get_variable("f"), Some(4.into()));
+        // Removing the local variable uncovers the base one.
+        assert_eq!(ctx.remove_variable("rate"), Some(5.into()));
+        assert_eq!(ctx.value("rate").unwrap(), 2.into());
+        ctx.clear();
+        assert_eq!(ctx.value("user.name").unwrap(), "Ada".into());
+        assert_eq!(ctx.function_names(), ["f"]);
+    }
+
+    #[test]
+    fn test_with_base_writes_stay_local() {
+        let base = base();
+        let mut ctx = Context::with_base(base.clone());
+        ctx.enable_tracking();
+        let ast = parse_expression("rate = rate + 1; user.name = 'Bob'; user.age = 36").unwrap();
+        ast.exec(&mut ctx).unwrap();
+        ctx.set_path("user.address.city", "Oslo".into()).unwrap();
+        assert_eq!(ctx.value("rate").unwrap(), 3.into());
+        assert_eq!(ctx.value("user.name").unwrap(), "Bob".into());
+        assert_eq!(ctx.value("user.address.city").unwrap(), "Oslo".into());
+        assert_eq!(ctx.remove_variable("rate"), Some(3.into()));
+        assert_eq!(
+            ctx.take_dirty().first(),
+            Some(&("rate".to_string(), 2.into()))
+        );
+        assert_eq!(base.value("rate").unwrap(), 2.into());
+        assert_eq!(
+            base.value("user").unwrap(),
+            Value::Map(Map::from_iter([("name".into(), "Ada".into())]))
+        );
+        assert_eq!(base.variable_names(), ["rate", "user"]);
+    }
+
+    #[test]
+    fn test_with_base_threads() {
+        let base = base();
+        let handles: Vec<_> = (0..4)
+            .map(|i| {
+                let base = base.clone();
+                std::thread::spawn(move || {
+                    let mut ctx = Context::with_base(base);
+                    ctx.set_variable("qty", i.into());
+                    let ast = parse_expression("rate = qty * rate; rate").unwrap();
+                    ast.exec(&mut ctx).unwrap()
+                })
+            })
+            .collect();
+        let ans: Vec<Value> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+        assert_eq!(ans, [0.into(), 2.into(), 4.into(), 6.into()]);
+        assert_eq!(base.value("rate").unwrap(), 2.into());
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut ctx = create_context!("rate" => 1, "own" => 0);
+        let mut other = Context::with_base(base());
+        other.set_variable("extra", true.into());
+        ctx.merge(&other);
+        assert_eq!(ctx.variable_names(), ["extra", "own", "rate", "user"]);
+        assert_eq!(ctx.value("rate").unwrap(), 2.into());
+        assert_eq!(ctx.function_names(), ["f"]);
+        other.set_variable("rate", 9.into());
+        assert_eq!(ctx.value("rate").unwrap(), 2.into());
+    }
+
+    #[test]
+    fn test_set_path() {
+        let mut ctx = create_context!("f" => Arc::new(|_| Ok(Value::None)));
+        ctx.set_path("user.address.city", "Oslo".into()).unwrap();
+        ctx.set_path("user.name", "Ada".into()).unwrap();
+        ctx.set_path("flat", 1.into()).unwrap();
+        let mut address = Map::default();
+        address.insert("city".into(), "Oslo".into());
+        assert_eq!(
+            ctx.value("user.address").unwrap(),
+            Value::Map(address.clone())
+        );
+        assert_eq!(ctx.value("user.name").unwrap(), "Ada".into());
+        assert_eq!(ctx.value("flat").unwrap(), 1.into());
+
+        // An exact name is set flat, like `set_variable` would.
+        ctx.set_variable("a.b", 1.into());
+        ctx.set_path("a.b", 2.into()).unwrap();
+        assert_eq!(ctx.get_variable("a.b"), Some(2.into()));
+        assert_eq!(ctx.get_variable("a"), None);
+
+        let err = ctx.set_path("f.x", 1.into()).unwrap_err();
+        assert!(matches!(err, Error::NotAMap(path, "Function") if path == "f"));
+        let err = ctx.set_path("user.name.first", 1.into()).unwrap_err();
+        assert!(matches!(err, Error::NotAMap(path, "String") if path == "user.name"));
+    }
+
+    #[test]
+    fn test_take_dirty() {
+        let ast = parse_expression("a=1; b=a+1; a+=1").unwrap();
+        let program = Compiler::compile(&ast).unwrap();
+        let expected = vec![("a".to_string(), 2.into()), ("b".to_string(), 2.into())];
+        for run in 0..3 {
+            let mut ctx = create_context!("c" => 1);
+            ctx.enable_tracking();
+            match run {
+                0 => ast.exec(&mut ctx).map(|_| ()),
+                1 => ast.exec_iterative(&mut ctx).map(|_| ()),
+                _ => Vm::run(&program, &mut ctx).map(|_| ()),
+            }
+            .unwrap();
+            assert_eq!(ctx.take_dirty(), expected);
+            assert_eq!(ctx.take_dirty(), vec![]);
+        }
+
+        let mut ctx = create_context!();
+        ctx.set_variable("a", 1.into());
+        assert_eq!(ctx.take_dirty(), vec![]);
+    }
+
+    #[test]
+    fn test_take_dirty_path() {
+        let mut ctx = create_context!();
+        ctx.enable_tracking();
+        ctx.set_path("user.name", "Ada".into()).unwrap();
+        ctx.set_path("user.age", 36.into()).unwrap();
+        let mut user = Map::default();
+        user.insert("name".into(), "Ada".into());
+        user.insert("age".into(), 36.into());
+        assert_eq!(ctx.take_dirty(), vec![("user".to_string(), user.into())]);
+    }
+
+    // Runs `input` with every backend, each against a fresh context with
+    // `policy`, and checks they agree, returning the root of the error.
+    fn exec_with_policy(input: &str, policy: MissingReferencePolicy) -> Result<Value, String> {
+        let ast = parse_expression(input).unwrap();
+        let program = Compiler::compile(&ast).unwrap();
+        let ctx = || {
+            let mut ctx = create_context!("count" => 1);
+            ctx.set_missing_reference_policy(policy.clone());
+            ctx
+        };
+        let ans = ast.exec(&mut ctx());
+        let iterative = ast
+            .exec_iterative(&mut ctx())
+            .map_err(|err| err.to_string());
+        let vm = Vm::run(&program, &mut ctx()).map_err(|err| err.to_string());
+        let full = ans
+            .as_ref()
+            .map(Value::clone)
+            .map_err(|err| err.to_string());
+        assert_eq!(full, iterative);
+        assert_eq!(full, vm);
+        ans.map_err(|err| err.root().to_string())
+    }
+
+    #[rstest]
+    #[case("missing", Ok(Value::None))]
+    #[case("missing ?? 5", Ok(5.into()))]
+    #[case("count.x", Err("count exists but has no key 'x'"))]
+    #[case("missing = 2; missing", Ok(2.into()))]
+    #[case("missing += 1", Err("should be number"))]
+    fn test_missing_none(#[case] input: &str, #[case] expected: Result<Value, &str>) {
+        let ans = exec_with_policy(input, MissingReferencePolicy::None);
+        assert_eq!(ans, expected.map_err(String::from));
+    }
+
+    #[rstest]
+    #[case("counts", Err("reference not exist: 'counts' — did you mean 'count'?"))]
+    #[case("missing ?? 5", Err("reference not exist: 'missing'"))]
+    #[case("missing = 2; missing", Ok(2.into()))]
+    #[case("missing += 1", Err("should be number"))]
+    #[case("count + 1", Ok(2.into()))]
+    fn test_missing_error(#[case] input: &str, #[case] expected: Result<Value, &str>) {
+        let ans = exec_with_policy(input, MissingReferencePolicy::Error);
+        assert_eq!(ans, expected.map_err(String::from));
+    }
+
+    #[rstest]
+    #[case("missing", Ok(0.into()))]
+    #[case("missing + count", Ok(1.into()))]
+    #[case("user.name", Ok(0.into()))]
+    #[case("missing = 2; missing", Ok(2.into()))]
+    #[case("missing += 1; missing += 1; missing", Ok(2.into()))]
+    #[case("count += 1; count", Ok(2.into()))]
+    fn test_missing_default(#[case] input: &str, #[case] expected: Result<Value, &str>) {
+        let ans = exec_with_policy(input, MissingReferencePolicy::Default(0.into()));
+        assert_eq!(ans, expected.map_err(String::from));
+    }
+
+    #[test]
+    fn test_strict_references_override_policy() {
+        let mut ctx = Context::with_options(EvalOptions {
+            strict_references: true,
+            ..EvalOptions::default()
+        });
+        ctx.set_missing_reference_policy(MissingReferencePolicy::Default(0.into()));
+        assert!(ctx.value("missing").is_err());
+    }
+
+    #[rstest]
+    #[case(MissingReferencePolicy::None)]
+    #[case(MissingReferencePolicy::Error)]
+    #[case(MissingReferencePolicy::Default(0.into()))]
+    fn test_take_missing_references(#[case] policy: MissingReferencePolicy) {
+        let mut ctx = create_context!("a" => 1);
+        ctx.set_missing_reference_policy(policy);
+        for input in ["b ?? a", "c + b", "x = 1; x + a", "user?.name"] {
+            let _ = parse_expression(input).unwrap().exec(&mut ctx);
+        }
+        assert_eq!(ctx.take_missing_references(), ["b", "c", "user?.name"]);
+        assert!(ctx.take_missing_references().is_empty());
+    }
+
+    #[test]
+    fn test_missing_references_shared() {
+        let mut base = create_context!("a" => 1);
+        base.set_missing_reference_policy(MissingReferencePolicy::Default(7.into()));
+        let base = Arc::new(base);
+        let ast = parse_expression("a + b").unwrap();
+        assert_eq!(ast.exec_readonly(&base).unwrap(), 8.into());
+        assert_eq!(base.take_missing_references(), ["b"]);
+        let mut layered = Context::with_base(base.clone());
+        assert_eq!(ast.exec(&mut layered).unwrap(), 8.into());
+        assert_eq!(layered.take_missing_references(), ["b"]);
+        assert!(base.take_missing_references().is_empty());
+    }
+
+    #[rstest]
+    #[case("max(count, 2) + sum(1, 2)", Ok(5.into()))]
+    #[case("math.min(count, 2)", Ok(1.into()))]
+    #[case("count > 0 ? 'a' : 'b'", Ok("a".into()))]
+    #[case(
+        "count = 2",
+        Err("can not assign to 'count', assignments are forbidden by the options")
+    )]
+    #[case(
+        "user.name = 'x'",
+        Err("can not assign to 'user.name', assignments are forbidden by the options")
+    )]
+    #[case(
+        "1 + random()",
+        Err("can not call 'random', only deterministic functions are allowed by the options")
+    )]
+    #[case(
+        "f(1)",
+        Err("can not call 'f', only deterministic functions are allowed by the options")
+    )]
+    #[case("count ? 1 : 2", Err("should be bool, got Number"))]
+    fn test_safe_options(#[case] input: &str, #[case] expected: Result<Value, &str>) {
+        let ast = parse_expression(input).unwrap();
+        let program = Compiler::compile(&ast).unwrap();
+        let ctx = || {
+            let mut ctx = create_context!("count" => 1, "f" => Arc::new(|_| Ok(Value::None)));
+            ctx.set_options(EvalOptions::safe());
+            ctx
+        };
+        let ans = ast.exec(&mut ctx());
+        let iterative = ast
+            .exec_iterative(&mut ctx())
+            .map_err(|err| err.to_string());
+        let vm = Vm::run(&program, &mut ctx()).map_err(|err| err.to_string());
+        let full = ans
+            .as_ref()
+            .map(Value::clone)
+            .map_err(|err| err.to_string());
+        assert_eq!(full, iterative);
+        assert_eq!(full, vm);
+        let ans = ans.map_err(|err| err.root().to_string());
+        assert_eq!(ans, expected.map_err(String::from));
+    }
+
+    #[test]
+    fn test_register_library() {
+        use crate::function::{CollisionPolicy, ContextFunction, FunctionLibrary};
+        use crate::{Arity, FunctionMetadata};
+        // `twice(name)` doubles the variable `name` of the context.
+        struct Library(&'static str);
+        impl FunctionLibrary for Library {
+            fn functions(&self) -> Vec<(FunctionMetadata, Arc<ContextFunction>)> {
+                let twice: Arc<ContextFunction> = Arc::new(|ctx, params| {
+                    let value = ctx.value(&params[0].clone().string()?)?;
+                    Ok((value.decimal()? * rust_decimal::Decimal::TWO).into())
+                });
+                let metadata = FunctionMetadata::new(self.0, Arity::Exact(1), &[], "");
+                vec![(metadata, twice)]
+            }
+        }
+        let base = Arc::new(create_context!("twice" => 1));
+        let mut ctx = Context::with_base(base.clone());
+        ctx.set_variable("n", 21.into());
+        let err = ctx.register_library(&Library("twice"), CollisionPolicy::Error);
+        assert!(matches!(err, Err(Error::FunctionExists(name)) if name == "twice"));
+        ctx.register_library(&Library("twice"), CollisionPolicy::Skip)
+            .unwrap();
+        assert_eq!(ctx.value("twice").unwrap(), 1.into());
+        ctx.register_library(&Library("twice"), CollisionPolicy::Override)
+            .unwrap();
+        ctx.register_library(&Library("double"), CollisionPolicy::Error)
+            .unwrap();
+        let ast = parse_expression("[twice('n'), double('n')]").unwrap();
+        assert_eq!(
+            ast.exec(&mut ctx).unwrap(),
+            vec![42.into(), 42.into()].into()
+        );
+        let program = Compiler::compile(&ast).unwrap();
+        assert_eq!(
+            Vm::run(&program, &mut ctx).unwrap(),
+            vec![42.into(), 42.into()].into()
+        );
+        assert_eq!(base.value("twice").unwrap(), 1.into());
+    }
+
+    #[test]
+    fn test_safe_options_leave_context() {
+        let mut ctx = create_context!("count" => 1);
+        ctx.set_options(EvalOptions::safe());
+        let err = parse_expression("count += 1")
+            .unwrap()
+            .exec(&mut ctx)
+            .unwrap_err();
+        assert!(matches!(err.root(), Error::AssignmentForbidden(path) if path == "count"));
+        assert_eq!(ctx.value("count").unwrap(), 1.into());
+        assert_eq!(EvalOptions::permissive(), EvalOptions::default());
+    }
+
+    #[test]
+    fn test_context_function_reference() {
+        let mut ctx = create_context!("rate" => 2, "user" => Value::Map(Map::default()));
+        ctx.set_context_func("total", Arc::new(|ctx, _| ctx.value("rate")));
+        ctx.set_context_func(
+            "profile",
+            Arc::new(|ctx, _| {
+                let name = ctx.value("user.name")?;
+                Ok(Value::Map(Map::from_iter([("name".into(), name)])))
+            }),
+        );
+        ctx.set_path("user.name", "Ada".into()).unwrap();
+        let ast = parse_expression("[total, total(), profile.name]").unwrap();
+        let program = Compiler::compile(&ast).unwrap();
+        let expected = Value::List(vec![2.into(), 2.into(), "Ada".into()]);
+        assert_eq!(ast.exec(&mut ctx).unwrap(), expected);
+        assert_eq!(ast.exec_iterative(&mut ctx).unwrap(), expected);
+        assert_eq!(Vm::run(&program, &mut ctx).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_max_variables_counts_variables() {
+        let base = Arc::new(create_context!("rate" => 2));
+        let mut ctx = Context::with_base(base);
+        ctx.set_func("f", Arc::new(|_| Ok(Value::None)));
+        ctx.set_limits(crate::options::Limits {
+            max_variables: Some(2),
+            ..Default::default()
+        });
+        let ast = parse_expression("a = f(); b = rate; a = b; b").unwrap();
+        assert_eq!(ast.exec(&mut ctx).unwrap(), 2.into());
+        let err = parse_expression("c = 1")
+            .unwrap()
+            .exec(&mut ctx)
+            .unwrap_err();
+        assert!(matches!(
+            err.root(),
+            Error::ResourceLimitExceeded {
+                kind: "variables",
+                limit: 2
+            }
+        ));
+    }
+
+    #[test]
+    fn test_max_variables_count_kept() {
+        let mut ctx = create_context!("a" => 1, "b" => 2);
+        ctx.set_limits(crate::options::Limits {
+            max_variables: Some(2),
+            ..Default::default()
+        });
+        let exceeded = |ctx: &mut Context, path: &str| {
+            let err = ctx.set_path(path, Value::None).unwrap_err();
+            matches!(
+                err,
+                Error::ResourceLimitExceeded {
+                    kind: "variables",
+                    ..
+                }
+            )
+        };
+        assert!(exceeded(&mut ctx, "c"));
+        ctx.remove_variable("a");
+        ctx.set_path("c", Value::None).unwrap();
+        ctx.set_func("b", Arc::new(|_| Ok(Value::None)));
+        ctx.set_path("d", Value::None).unwrap();
+        assert!(exceeded(&mut ctx, "e"));
+        ctx.clear();
+        ctx.set_path("e", Value::None).unwrap();
+        ctx.set_path("f", Value::None).unwrap();
+        assert!(exceeded(&mut ctx, "g"));
+        // Setting many variables takes linear time.
+        ctx.set_limits(crate::options::Limits {
+            max_variables: Some(100_000),
+            ..Default::default()
+        });
+        for i in 2..100_000 {
+            ctx.set_path(&format!("v{}", i), Value::None).unwrap();
+        }
+        assert!(exceeded(&mut ctx, "w"));
+    }
+}
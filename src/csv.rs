@@ -0,0 +1,267 @@
+use crate::context::Context;
+use crate::define::Result;
+use crate::error::Error;
+use crate::tokenizer::parse_number;
+use crate::value::{Map, Value};
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::mem;
+
+/// How [`Value::from_csv_row_with_options`] reads a row.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct CsvOptions {
+    /// What separates the fields, `,` by default.
+    pub delimiter: char,
+    /// Whether an unquoted field that reads as a number or a bool, like
+    /// `12.50` or `TRUE`, becomes one. On by default; off, every field is a
+    /// string, e.g. to keep the leading zeros of `007`.
+    pub detect_types: bool,
+}
+
+impl Default for CsvOptions {
+    fn default() -> Self {
+        Self {
+            delimiter: ',',
+            detect_types: true,
+        }
+    }
+}
+
+impl Value {
+    /// The fields of the CSV row `row` as a map from the column names of
+    /// `header`, which need as many fields. A field can be quoted with
+    /// `"`, to hold the delimiter, line breaks or a `""` for a quote, and
+    /// is then a string. An unquoted empty field is `None`, and an
+    /// unquoted field that reads as a number or a bool is one.
+    pub fn from_csv_row(header: &[&str], row: &str, delimiter: char) -> Result<Value> {
+        let options = CsvOptions {
+            delimiter,
+            ..CsvOptions::default()
+        };
+        Value::from_csv_row_with_options(header, row, options)
+    }
+
+    /// Reads a row like [`Value::from_csv_row`], with [`CsvOptions`].
+    pub fn from_csv_row_with_options(
+        header: &[&str],
+        row: &str,
+        options: CsvOptions,
+    ) -> Result<Value> {
+        let fields = split_row(row, options.delimiter)?;
+        if fields.len() != header.len() {
+            return Err(Error::InvalidCsv(format!(
+                "expected {} fields, got {}",
+                header.len(),
+                fields.len()
+            )));
+        }
+        let mut ans = Map::with_capacity_and_hasher(header.len(), Default::default());
+        for (name, field) in header.iter().zip(fields) {
+            let value = match field {
+                Field::Quoted(text) => Value::String(text),
+                Field::Plain(text) if text.is_empty() => Value::None,
+                Field::Plain(text) if options.detect_types => detect_type(text),
+                Field::Plain(text) => Value::String(text),
+            };
+            if ans.insert(Value::from(*name), value).is_some() {
+                return Err(Error::InvalidCsv(format!("duplicate column '{}'", name)));
+            }
+        }
+        Ok(Value::Map(ans))
+    }
+}
+
+impl Context {
+    /// Sets each field of a CSV row as the variable of its column, read
+    /// like [`Value::from_csv_row`].
+    pub fn set_from_csv_row(&mut self, header: &[&str], row: &str, delimiter: char) -> Result<()> {
+        if let Value::Map(m) = Value::from_csv_row(header, row, delimiter)? {
+            for (k, v) in m {
+                self.set_variable(&k.string()?, v);
+            }
+        }
+        Ok(())
+    }
+}
+
+enum Field {
+    Plain(String),
+    Quoted(String),
+}
+
+#[derive(Clone, Copy)]
+enum State {
+    // At the start of a field.
+    Start,
+    Plain,
+    Quoted,
+    // On a quote inside a quoted field, which either closes it or, with
+    // another quote, stands for one.
+    QuoteInQuoted,
+}
+
+// The fields of a row, by a state machine over its chars. One line break
+// ending the row is left out.
+fn split_row(row: &str, delimiter: char) -> Result<Vec<Field>> {
+    if matches!(delimiter, '"' | '\r' | '\n') {
+        return Err(Error::InvalidCsv(format!(
+            "{:?} can't be a delimiter",
+            delimiter
+        )));
+    }
+    let row = row.strip_suffix('\n').unwrap_or(row);
+    let row = row.strip_suffix('\r').unwrap_or(row);
+    let malformed = |what: &str, at: usize| Error::InvalidCsv(format!("{} at {}", what, at));
+    let mut fields = Vec::new();
+    let mut text = String::new();
+    let mut state = State::Start;
+    for (i, ch) in row.char_indices() {
+        state = match (state, ch) {
+            (State::Quoted, '"') => State::QuoteInQuoted,
+            (State::Quoted, _) => {
+                text.push(ch);
+                State::Quoted
+            }
+            (State::QuoteInQuoted, '"') => {
+                text.push('"');
+                State::Quoted
+            }
+            (State::QuoteInQuoted, _) if ch == delimiter => {
+                fields.push(Field::Quoted(mem::take(&mut text)));
+                State::Start
+            }
+            (State::QuoteInQuoted, _) => return Err(malformed("text after a closing quote", i)),
+            (State::Start | State::Plain, _) if ch == delimiter => {
+                fields.push(Field::Plain(mem::take(&mut text)));
+                State::Start
+            }
+            (State::Start, '"') => State::Quoted,
+            (State::Plain, '"') => return Err(malformed("a quote in an unquoted field", i)),
+            (_, '\r' | '\n') => return Err(malformed("a line break outside quotes", i)),
+            _ => {
+                text.push(ch);
+                State::Plain
+            }
+        };
+    }
+    match state {
+        State::Quoted => return Err(malformed("an unterminated quoted field", row.len())),
+        State::QuoteInQuoted => fields.push(Field::Quoted(text)),
+        State::Start | State::Plain => fields.push(Field::Plain(text)),
+    }
+    Ok(fields)
+}
+
+// A number written like a literal, with an optional sign, or a bool in any
+// case. Anything else, a number too large for a `Decimal` included, stays
+// a string.
+fn detect_type(text: String) -> Value {
+    if text.eq_ignore_ascii_case("true") || text.eq_ignore_ascii_case("false") {
+        return Value::Bool(text.eq_ignore_ascii_case("true"));
+    }
+    let (negative, unsigned) = match text.strip_prefix('-') {
+        Some(unsigned) => (true, unsigned),
+        None => (false, text.strip_prefix('+').unwrap_or(&text)),
+    };
+    match parse_number(unsigned) {
+        Some(number) if negative => Value::Number(-number),
+        Some(number) => Value::Number(number),
+        None => Value::String(text),
+    }
+}
+
+#[cfg(all(test, feature = "default"))]
+mod tests {
+    use super::CsvOptions;
+    use crate::{create_context, execute, Map, Value};
+    use rstest::rstest;
+    use rust_decimal::Decimal;
+    use std::str::FromStr;
+
+    fn number(s: &str) -> Value {
+        Value::Number(Decimal::from_str(s).unwrap())
+    }
+
+    #[rstest]
+    #[case("ada,36,true", vec!["ada".into(), 36.into(), true.into()])]
+    #[case("\"Lovelace, Ada\",-1.50,FALSE", vec!["Lovelace, Ada".into(), number("-1.50"), false.into()])]
+    #[case("\"say \"\"hi\"\"\",.5,True", vec!["say \"hi\"".into(), number("0.5"), true.into()])]
+    #[case(",,", vec![Value::None, Value::None, Value::None])]
+    #[case("\"\",\"36\",\"true\"", vec!["".into(), "36".into(), "true".into()])]
+    #[case("\"a\nb\",1e3,007", vec!["a\nb".into(), 1000.into(), 7.into()])]
+    #[case(" x , 1 ,+2", vec![" x ".into(), " 1 ".into(), 2.into()])]
+    #[case("a,b,c\r\n", vec!["a".into(), "b".into(), "c".into()])]
+    #[case("x,99999999999999999999999999999,1.", vec![
+        "x".into(), "99999999999999999999999999999".into(), "1.".into(),
+    ])]
+    fn test_from_csv_row(#[case] row: &str, #[case] values: Vec<Value>) {
+        let header = ["name", "n", "ok"];
+        let expected = Value::Map(Map::from_iter(
+            header.iter().map(|name| Value::from(*name)).zip(values),
+        ));
+        assert_eq!(Value::from_csv_row(&header, row, ',').unwrap(), expected);
+    }
+
+    #[rstest]
+    #[case(';', "a;\"b;c\";1")]
+    #[case('\t', "a\t\"b;c\"\t1")]
+    #[case('|', "a|b;c|1")]
+    fn test_from_csv_row_delimiter(#[case] delimiter: char, #[case] row: &str) {
+        let ans = Value::from_csv_row(&["x", "y", "z"], row, delimiter).unwrap();
+        let expected = Map::from_iter([
+            ("x".into(), "a".into()),
+            ("y".into(), "b;c".into()),
+            ("z".into(), 1.into()),
+        ]);
+        assert_eq!(ans, Value::Map(expected));
+    }
+
+    #[test]
+    fn test_from_csv_row_strings() {
+        let options = CsvOptions {
+            detect_types: false,
+            ..CsvOptions::default()
+        };
+        let ans = Value::from_csv_row_with_options(&["zip", "ok", "none"], "007,true,", options);
+        let expected = Map::from_iter([
+            ("zip".into(), "007".into()),
+            ("ok".into(), "true".into()),
+            ("none".into(), Value::None),
+        ]);
+        assert_eq!(ans.unwrap(), Value::Map(expected));
+    }
+
+    #[rstest]
+    #[case(&["a", "b"], "1,2,3", ',', "expected 2 fields, got 3")]
+    #[case(&["a", "b"], "1", ',', "expected 2 fields, got 1")]
+    #[case(&["a", "b"], "", ';', "expected 2 fields, got 1")]
+    #[case(&["a", "b"], "\"1,2", ',', "an unterminated quoted field at 4")]
+    #[case(&["a", "b"], "\"1\"x,2", ',', "text after a closing quote at 3")]
+    #[case(&["a", "b"], "1\"2\",3", ',', "a quote in an unquoted field at 1")]
+    #[case(&["a", "b"], "1\n,2", ',', "a line break outside quotes at 1")]
+    #[case(&["a", "a"], "1,2", ',', "duplicate column 'a'")]
+    #[case(&["a", "b"], "1\"2", '"', "'\"' can't be a delimiter")]
+    fn test_from_csv_row_malformed(
+        #[case] header: &[&str],
+        #[case] row: &str,
+        #[case] delimiter: char,
+        #[case] message: &str,
+    ) {
+        let err = Value::from_csv_row(header, row, delimiter).unwrap_err();
+        assert_eq!(err.code(), "E_ARG_INVALID_CSV");
+        assert_eq!(err.to_string(), format!("invalid csv: {}", message));
+    }
+
+    #[test]
+    fn test_set_from_csv_row() {
+        let mut ctx = create_context!("fee" => 2);
+        ctx.set_from_csv_row(&["sku", "price", "qty"], "\"A-1\",12.50,3", ',')
+            .unwrap();
+        assert_eq!(ctx.value("sku").unwrap(), "A-1".into());
+        let ans = execute("price * qty + fee", ctx).unwrap();
+        assert_eq!(ans, number("39.50"));
+        let mut ctx = create_context!();
+        assert!(ctx.set_from_csv_row(&["a"], "1,2", ',').is_err());
+        assert_eq!(ctx.get_variable("a"), None);
+    }
+}
@@ -1,6 +1,7 @@
-use once_cell::sync::OnceCell;
-use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
+use crate::compat::{HashMap, Mutex, OnceCell};
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
 
 #[derive(Hash, Eq, PartialEq)]
 enum DescriptorKey {
@@ -13,8 +14,12 @@ enum DescriptorKey {
     LIST,
     MAP,
     CHAIN,
+    MATCH,
 }
 
+// Only the tests set descriptors so far; everything else reads the
+// defaults.
+#[cfg_attr(not(test), allow(dead_code))]
 #[derive(Clone)]
 enum Descriptor {
     UNARY(Arc<UnaryDescriptor>),
@@ -26,6 +31,7 @@ enum Descriptor {
     LIST(Arc<ListDescriptor>),
     MAP(Arc<MapDescriptor>),
     CHAIN(Arc<ChainDescriptor>),
+    MATCH(Arc<MatchDescriptor>),
 }
 
 type UnaryDescriptor = dyn Fn(String, String) -> String + Send + Sync + 'static;
@@ -37,6 +43,8 @@ type ReferenceDescriptor = dyn Fn(String) -> String + Send + Sync + 'static;
 type ListDescriptor = dyn Fn(Vec<String>) -> String + Send + Sync + 'static;
 type MapDescriptor = dyn Fn(Vec<(String, String)>) -> String + Send + Sync + 'static;
 type ChainDescriptor = dyn Fn(Vec<String>) -> String + Send + Sync + 'static;
+type MatchDescriptor =
+    dyn Fn(String, Vec<(String, String)>, Option<String>) -> String + Send + Sync + 'static;
 
 pub struct DescriptorManager {
     store: &'static Mutex<HashMap<DescriptorKey, Descriptor>>,
@@ -49,6 +57,7 @@ impl DescriptorManager {
         DescriptorManager { store }
     }
 
+    #[cfg_attr(not(test), allow(dead_code))]
     fn set(&mut self, key: DescriptorKey, value: Descriptor) {
         let mut binding = self.store.lock().unwrap();
         binding.insert(key, value);
@@ -56,13 +65,10 @@ impl DescriptorManager {
 
     fn get(&self, key: DescriptorKey) -> Option<Descriptor> {
         let binding = self.store.lock().unwrap();
-        let value = binding.get(&key);
-        if value.is_none() {
-            return None;
-        }
-        Some(value.unwrap().clone())
+        binding.get(&key).cloned()
     }
 
+    #[cfg_attr(not(test), allow(dead_code))]
     pub fn set_unary_descriptor(&mut self, op: String, descriptor: Arc<UnaryDescriptor>) {
         let key = DescriptorKey::UNARY(op);
         let value = Descriptor::UNARY(descriptor);
@@ -81,6 +87,7 @@ impl DescriptorManager {
         }
     }
 
+    #[cfg_attr(not(test), allow(dead_code))]
     pub fn set_binary_descriptor(&mut self, op: String, descriptor: Arc<BinaryDescriptor>) {
         let key = DescriptorKey::BINARY(op);
         let value = Descriptor::BINARY(descriptor);
@@ -88,7 +95,7 @@ impl DescriptorManager {
     }
 
     pub fn get_binary_descriptor(&self, op: String) -> Arc<BinaryDescriptor> {
-        let key = DescriptorKey::UNARY(op);
+        let key = DescriptorKey::BINARY(op);
         let v = self.get(key);
         if v.is_none() {
             return Arc::new(default_binary_descriptor);
@@ -99,6 +106,7 @@ impl DescriptorManager {
         }
     }
 
+    #[cfg_attr(not(test), allow(dead_code))]
     pub fn set_postfix_descriptor(&mut self, op: String, descriptor: Arc<UnaryDescriptor>) {
         let key = DescriptorKey::POSTFIX(op);
         let value = Descriptor::POSTFIX(descriptor);
@@ -117,6 +125,7 @@ impl DescriptorManager {
         }
     }
 
+    #[cfg_attr(not(test), allow(dead_code))]
     pub fn set_ternary_descriptor(&mut self, descriptor: Arc<TernaryDescriptor>) {
         let key = DescriptorKey::TERNARY;
         let value = Descriptor::TERNARY(descriptor);
@@ -135,6 +144,7 @@ impl DescriptorManager {
         }
     }
 
+    #[cfg_attr(not(test), allow(dead_code))]
     pub fn set_function_descriptor(&mut self, name: String, descriptor: Arc<FunctionDescriptor>) {
         let key = DescriptorKey::FUNCTION(name);
         let value = Descriptor::FUNCTION(descriptor);
@@ -153,6 +163,7 @@ impl DescriptorManager {
         }
     }
 
+    #[cfg_attr(not(test), allow(dead_code))]
     pub fn set_reference_descriptor(&mut self, name: String, descriptor: Arc<ReferenceDescriptor>) {
         let key = DescriptorKey::REFERENCE(name);
         let value = Descriptor::REFERENCE(descriptor);
@@ -171,6 +182,7 @@ impl DescriptorManager {
         }
     }
 
+    #[cfg_attr(not(test), allow(dead_code))]
     pub fn set_list_descriptor(&mut self, descriptor: Arc<ListDescriptor>) {
         let key = DescriptorKey::LIST;
         let value = Descriptor::LIST(descriptor);
@@ -189,6 +201,7 @@ impl DescriptorManager {
         }
     }
 
+    #[cfg_attr(not(test), allow(dead_code))]
     pub fn set_map_descriptor(&mut self, descriptor: Arc<MapDescriptor>) {
         let key = DescriptorKey::MAP;
         let value = Descriptor::MAP(descriptor);
@@ -207,6 +220,7 @@ impl DescriptorManager {
         }
     }
 
+    #[cfg_attr(not(test), allow(dead_code))]
     pub fn set_chain_descriptor(&mut self, descriptor: Arc<ChainDescriptor>) {
         let key = DescriptorKey::CHAIN;
         let value = Descriptor::CHAIN(descriptor);
@@ -224,6 +238,25 @@ impl DescriptorManager {
             _ => Arc::new(default_chain_descriptor),
         }
     }
+
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub fn set_match_descriptor(&mut self, descriptor: Arc<MatchDescriptor>) {
+        let key = DescriptorKey::MATCH;
+        let value = Descriptor::MATCH(descriptor);
+        self.set(key, value)
+    }
+
+    pub fn get_match_descriptor(&self) -> Arc<MatchDescriptor> {
+        let key = DescriptorKey::MATCH;
+        let v = self.get(key);
+        if v.is_none() {
+            return Arc::new(default_match_descriptor);
+        }
+        match v.unwrap() {
+            Descriptor::MATCH(f) => f.clone(),
+            _ => Arc::new(default_match_descriptor),
+        }
+    }
 }
 
 fn default_unary_descriptor(op: String, rhs: String) -> String {
@@ -266,6 +299,21 @@ fn default_chain_descriptor(params: Vec<String>) -> String {
     params.join(";")
 }
 
+fn default_match_descriptor(
+    scrutinee: String,
+    arms: Vec<(String, String)>,
+    default: Option<String>,
+) -> String {
+    let mut tmp = Vec::new();
+    for (pattern, value) in arms {
+        tmp.push(pattern + ":" + &value)
+    }
+    if let Some(default) = default {
+        tmp.push("_:".to_string() + &default)
+    }
+    "match ".to_string() + &scrutinee + " {" + &tmp.join(",") + "}"
+}
+
 #[cfg(test)]
 mod tests {
     use super::default_binary_descriptor;
@@ -273,6 +321,7 @@ mod tests {
     use super::default_function_descriptor;
     use super::default_list_descriptor;
     use super::default_map_descriptor;
+    use super::default_match_descriptor;
     use super::default_postfix_descriptor;
     use super::default_reference_descriptor;
     use super::default_ternary_descriptor;
@@ -289,6 +338,7 @@ mod tests {
             .set_function_descriptor("haha".to_string(), Arc::new(default_function_descriptor));
         DescriptorManager::new().set_list_descriptor(Arc::new(default_list_descriptor));
         DescriptorManager::new().set_map_descriptor(Arc::new(default_map_descriptor));
+        DescriptorManager::new().set_match_descriptor(Arc::new(default_match_descriptor));
         DescriptorManager::new()
             .set_reference_descriptor("haha".to_string(), Arc::new(default_reference_descriptor));
         DescriptorManager::new().set_ternary_descriptor(Arc::new(default_ternary_descriptor));
@@ -1,20 +1,52 @@
-use std::fmt;
+use crate::function::Arity;
+use crate::token::Span;
+use alloc::boxed::Box;
+use alloc::string::String;
+#[cfg(feature = "serde")]
+use alloc::string::ToString;
+use alloc::vec::Vec;
+use core::fmt;
 
 #[derive(Debug)]
 pub enum Error {
-    InvalidNumber(String),
+    // The number as written and, in an expression, where it starts.
+    InvalidNumber(String, Option<usize>),
     UnexpectedEOF(usize),
     UnterminatedString(usize),
     InvalidBool(usize),
+    // The start of a SETTER operator whose left side can't be assigned to.
+    InvalidAssignTarget(usize),
+    // The start of the first token nested deeper than the parser allows.
+    NestingTooDeep(usize),
+    // A token longer than `ParseOptions` allows, by its start and the limit;
+    // `kind` is "token" or "string".
+    TokenTooLong {
+        start: usize,
+        kind: &'static str,
+        limit: usize,
+    },
+    // An input longer than `ParseOptions::max_input_len`, by its length and
+    // the limit. Its position is the limit, the first byte past it.
+    InputTooLarge {
+        len: usize,
+        limit: usize,
+    },
     NotSupportedChar(usize, char),
-    ReferenceNotExist(String),
+    // The unresolved name and up to three close names that do resolve, here
+    // and in the other lookup errors.
+    ReferenceNotExist(String, Vec<String>),
     FunctionNotExist(String),
-    NotSupportedOp(String),
+    // The start of an operator used between operands that isn't a binary
+    // one.
+    NotSupportedOp(usize, String),
+    // The start of a word where an operator should be, and the word
+    // operators that are registered.
+    UnknownWordOp(usize, String, Vec<String>),
     InfixOpNotRegistered(String),
     PrefixOpNotRegistered(String),
-    InnerFunctionNotRegistered(String),
+    InnerFunctionNotRegistered(String, Vec<String>),
     ShouldBeNumber(),
-    ShouldBeBool(),
+    ShouldBeBool(&'static str),
     ShouldBeList(),
     ShouldBeMap(),
     ParamInvalid(),
@@ -25,11 +57,344 @@ pub enum Error {
     UnexpectedToken(),
     NotReferenceExpr,
     NoOpenDelim,
-    NoCloseDelim,
+    // Where the close delimiter was expected: the start of the token found
+    // instead, or the end of the input.
+    NoCloseDelim(usize),
     InvalidOp(String),
     InvalidInteger,
     InvalidFloat,
     ExpectBinOpToken,
+    InvalidJson(String),
+    InvalidBase64(String),
+    InvalidFormat(String),
+    // A CSV row that can't be read, and why.
+    InvalidCsv(String),
+    // A string that doesn't parse as the quantity, like "duration" or
+    // "size", and the string.
+    InvalidQuantity(&'static str, String),
+    ListTooLong(usize),
+    NoLength(&'static str),
+    NoMatchingArm(String),
+    NotSpreadable(&'static str),
+    // The dotted reference prefix that resolved and the key it lacks.
+    MissingKey(String, String),
+    // A dotted assignment target whose prefix holds something other than a
+    // map, and the type it holds.
+    NotAMap(String, &'static str),
+    // A runtime error together with the `expr()` text of the smallest
+    // sub-expression that failed and of its parent.
+    WithContext {
+        inner: Box<Error>,
+        snippet: String,
+        parent: Option<String>,
+    },
+    // An error of a statement of a chain, by its 1-based index and its
+    // `expr()` text.
+    InStatement {
+        index: usize,
+        statement: String,
+        inner: Box<Error>,
+    },
+    NotComparable(&'static str, &'static str),
+    // A value converted to a Rust type that can't hold it, by the type of
+    // the value and the Rust type.
+    NotConvertible(&'static str, &'static str),
+    // A NaN, an infinity or a float outside the range of a `Decimal`.
+    UnrepresentableFloat(f64),
+    // An arithmetic operator whose result doesn't fit a `Decimal`, by the
+    // operator.
+    NumberOverflow(&'static str),
+    DivisionByZero,
+    // A call to a function nothing provides and, when it was found in an
+    // expression rather than called, where the call starts.
+    UnknownFunction(String, Vec<String>, Option<usize>),
+    // A function of a library whose name a function or an alias already
+    // has, under `CollisionPolicy::Error`.
+    FunctionExists(String),
+    // The lower and upper bound of a `between`, as literals.
+    InvertedBounds(String, String),
+    // The count of a `<<` or `>>` outside `0..64`.
+    ShiftOutOfRange(i64),
+    // One of the `Limits` in the options was crossed; `kind` is "elements",
+    // "chars" or "variables".
+    ResourceLimitExceeded {
+        kind: &'static str,
+        limit: usize,
+    },
+    // An async function called by something other than `exec_async`.
+    AsyncFunctionInSync(String),
+    // A key a map literal has twice, as a literal.
+    DuplicateMapKey(String),
+    // The cancellation token of the context was set.
+    Cancelled,
+    // A random number was drawn without `std` from a context without a seed.
+    NoRngSeed,
+    // An assignment, by its operator, in an expression given to
+    // `exec_readonly`.
+    ReadonlyAssignment(String),
+    // An assignment, by its target, under `EvalOptions::forbid_assignment`.
+    AssignmentForbidden(String),
+    // A call of a function that isn't deterministic, by its name, under
+    // `EvalOptions::deterministic_only`.
+    NonDeterministicCall(String),
+    // A call with a number of params the function doesn't take, found
+    // before evaluating, with the span of the call.
+    WrongFunctionArity {
+        name: String,
+        expected: Arity,
+        got: usize,
+        span: Span,
+    },
+    // Raised by user functions, see `Error::custom`. Evaluation passes it
+    // through untouched so the host can get its own error back.
+    Custom {
+        message: String,
+        source: Option<Box<dyn core::error::Error + Send + Sync>>,
+    },
+}
+
+impl Error {
+    /// An error for registered functions to return, shown as `message`.
+    pub fn custom(message: impl Into<String>) -> Error {
+        Error::Custom {
+            message: message.into(),
+            source: None,
+        }
+    }
+
+    /// Like [`Error::custom`], but carries `source` so the caller can recover
+    /// it with [`Error::downcast_ref`] once evaluation fails.
+    pub fn custom_with_source(
+        message: impl Into<String>,
+        source: impl core::error::Error + Send + Sync + 'static,
+    ) -> Error {
+        Error::Custom {
+            message: message.into(),
+            source: Some(Box::new(source)),
+        }
+    }
+
+    /// The source of a [`Error::Custom`] if it is a `T`.
+    pub fn downcast_ref<T: core::error::Error + 'static>(&self) -> Option<&T> {
+        match self.root() {
+            Error::Custom {
+                source: Some(source),
+                ..
+            } => source.downcast_ref(),
+            _ => None,
+        }
+    }
+
+    pub fn position(&self) -> Option<usize> {
+        use Error::*;
+        match self {
+            UnexpectedEOF(start)
+            | UnterminatedString(start)
+            | InvalidBool(start)
+            | NotSupportedChar(start, _)
+            | NotSupportedOp(start, _)
+            | UnknownWordOp(start, _, _)
+            | InvalidAssignTarget(start)
+            | NestingTooDeep(start)
+            | NoCloseDelim(start)
+            | TokenTooLong { start, .. } => Some(*start),
+            InvalidNumber(_, start) | UnknownFunction(_, _, start) => *start,
+            InputTooLarge { limit, .. } => Some(*limit),
+            WrongFunctionArity { span, .. } => Some(span.0),
+            WithContext { inner, .. } | InStatement { inner, .. } => inner.position(),
+            _ => None,
+        }
+    }
+
+    /// A stable identifier for the kind of error, meant for programs to
+    /// branch on. Unlike the message it never changes between releases.
+    /// Context added while the error propagated is ignored.
+    pub fn code(&self) -> &'static str {
+        use Error::*;
+        match self {
+            InvalidNumber(_, _) => "E_PARSE_INVALID_NUMBER",
+            UnexpectedEOF(_) => "E_PARSE_UNEXPECTED_EOF",
+            UnterminatedString(_) => "E_PARSE_UNTERMINATED_STRING",
+            InvalidBool(_) => "E_PARSE_INVALID_BOOL",
+            InvalidAssignTarget(_) => "E_PARSE_INVALID_ASSIGN_TARGET",
+            NestingTooDeep(_) => "E_PARSE_NESTING_TOO_DEEP",
+            NotSupportedChar(_, _) => "E_PARSE_UNSUPPORTED_CHAR",
+            ReferenceNotExist(_, _) => "E_REF_NOT_FOUND",
+            FunctionNotExist(_) => "E_FUNC_NOT_FOUND",
+            NotSupportedOp(_, _) => "E_PARSE_UNSUPPORTED_OP",
+            UnknownWordOp(_, _, _) => "E_PARSE_UNKNOWN_WORD_OP",
+            InfixOpNotRegistered(_) => "E_REF_INFIX_OP_NOT_FOUND",
+            PrefixOpNotRegistered(_) => "E_REF_PREFIX_OP_NOT_FOUND",
+            InnerFunctionNotRegistered(_, _) => "E_FUNC_NOT_REGISTERED",
+            ShouldBeNumber() => "E_TYPE_EXPECTED_NUMBER",
+            ShouldBeBool(_) => "E_TYPE_EXPECTED_BOOL",
+            ShouldBeList() => "E_TYPE_EXPECTED_LIST",
+            ShouldBeMap() => "E_TYPE_EXPECTED_MAP",
+            ParamInvalid() => "E_ARG_INVALID",
+            ShouldBeString() => "E_TYPE_EXPECTED_STRING",
+            InvalidTernaryExprNeedColon() => "E_PARSE_TERNARY_MISSING_COLON",
+            ExpectedOpNotExist(_) => "E_PARSE_EXPECTED_TOKEN",
+            WrongContextValueType() => "E_TYPE_CONTEXT_VALUE",
+            UnexpectedToken() => "E_PARSE_UNEXPECTED_TOKEN",
+            NotReferenceExpr => "E_PARSE_NOT_REFERENCE",
+            NoOpenDelim => "E_PARSE_NO_OPEN_DELIM",
+            NoCloseDelim(_) => "E_PARSE_NO_CLOSE_DELIM",
+            InvalidOp(_) => "E_PARSE_INVALID_OP",
+            InvalidInteger => "E_TYPE_INVALID_INTEGER",
+            InvalidFloat => "E_TYPE_INVALID_FLOAT",
+            ExpectBinOpToken => "E_PARSE_EXPECTED_BINARY_OP",
+            InvalidJson(_) => "E_ARG_INVALID_JSON",
+            InvalidBase64(_) => "E_ARG_INVALID_BASE64",
+            InvalidCsv(_) => "E_ARG_INVALID_CSV",
+            InvalidFormat(_) => "E_ARG_INVALID_FORMAT",
+            InvalidQuantity(_, _) => "E_ARG_INVALID_QUANTITY",
+            ListTooLong(_) => "E_LIMIT_LIST_TOO_LONG",
+            NoLength(_) => "E_TYPE_NO_LENGTH",
+            NoMatchingArm(_) => "E_MATCH_NO_ARM",
+            NotSpreadable(_) => "E_TYPE_NOT_SPREADABLE",
+            MissingKey(_, _) => "E_REF_MISSING_KEY",
+            NotAMap(_, _) => "E_TYPE_NOT_A_MAP",
+            WithContext { inner, .. } | InStatement { inner, .. } => inner.code(),
+            NotComparable(_, _) => "E_TYPE_NOT_COMPARABLE",
+            NotConvertible(_, _) => "E_TYPE_NOT_CONVERTIBLE",
+            UnrepresentableFloat(_) => "E_TYPE_UNREPRESENTABLE_FLOAT",
+            NumberOverflow(_) => "E_TYPE_NUMBER_OVERFLOW",
+            DivisionByZero => "E_ARG_DIVISION_BY_ZERO",
+            UnknownFunction(_, _, _) => "E_FUNC_UNKNOWN",
+            FunctionExists(_) => "E_FUNC_EXISTS",
+            InvertedBounds(_, _) => "E_ARG_INVERTED_BOUNDS",
+            ShiftOutOfRange(_) => "E_ARG_SHIFT_OUT_OF_RANGE",
+            ResourceLimitExceeded { .. } => "E_LIMIT_RESOURCE_EXCEEDED",
+            TokenTooLong { .. } => "E_LIMIT_TOKEN_TOO_LONG",
+            InputTooLarge { .. } => "E_LIMIT_INPUT_TOO_LARGE",
+            AsyncFunctionInSync(_) => "E_FUNC_ASYNC_IN_SYNC",
+            DuplicateMapKey(_) => "E_MAP_DUPLICATE_KEY",
+            Cancelled => "E_CANCELLED",
+            NoRngSeed => "E_RNG_NOT_SEEDED",
+            ReadonlyAssignment(_) => "E_READONLY_ASSIGNMENT",
+            AssignmentForbidden(_) => "E_LIMIT_ASSIGNMENT_FORBIDDEN",
+            NonDeterministicCall(_) => "E_LIMIT_NON_DETERMINISTIC_CALL",
+            WrongFunctionArity { .. } => "E_ARG_WRONG_ARITY",
+            Custom { .. } => "E_CUSTOM",
+        }
+    }
+
+    /// The broad class the error belongs to, see [`ErrorCategory`].
+    pub fn category(&self) -> ErrorCategory {
+        use Error::*;
+        match self {
+            InvalidNumber(_, _)
+            | UnexpectedEOF(_)
+            | UnterminatedString(_)
+            | InvalidBool(_)
+            | InvalidAssignTarget(_)
+            | NestingTooDeep(_)
+            | NotSupportedChar(_, _)
+            | NotSupportedOp(_, _)
+            | UnknownWordOp(_, _, _)
+            | InvalidTernaryExprNeedColon()
+            | ExpectedOpNotExist(_)
+            | UnexpectedToken()
+            | NotReferenceExpr
+            | NoOpenDelim
+            | NoCloseDelim(_)
+            | InvalidOp(_)
+            | ExpectBinOpToken => ErrorCategory::Syntax,
+            ReferenceNotExist(_, _)
+            | FunctionNotExist(_)
+            | InfixOpNotRegistered(_)
+            | PrefixOpNotRegistered(_)
+            | InnerFunctionNotRegistered(_, _)
+            | MissingKey(_, _)
+            | UnknownFunction(_, _, _)
+            | FunctionExists(_) => ErrorCategory::Reference,
+            ShouldBeNumber()
+            | ShouldBeBool(_)
+            | ShouldBeList()
+            | ShouldBeMap()
+            | ShouldBeString()
+            | WrongContextValueType()
+            | InvalidInteger
+            | InvalidFloat
+            | NoLength(_)
+            | NotSpreadable(_)
+            | NotAMap(_, _)
+            | NotComparable(_, _)
+            | NotConvertible(_, _)
+            | UnrepresentableFloat(_)
+            | NumberOverflow(_) => ErrorCategory::Type,
+            ParamInvalid()
+            | InvalidJson(_)
+            | InvalidBase64(_)
+            | InvalidCsv(_)
+            | InvalidFormat(_)
+            | InvalidQuantity(_, _)
+            | InvertedBounds(_, _)
+            | ShiftOutOfRange(_)
+            | DivisionByZero
+            | WrongFunctionArity { .. } => ErrorCategory::Argument,
+            ListTooLong(_)
+            | ResourceLimitExceeded { .. }
+            | TokenTooLong { .. }
+            | InputTooLarge { .. }
+            | AssignmentForbidden(_)
+            | NonDeterministicCall(_) => ErrorCategory::Limit,
+            NoMatchingArm(_)
+            | AsyncFunctionInSync(_)
+            | DuplicateMapKey(_)
+            | Cancelled
+            | NoRngSeed
+            | ReadonlyAssignment(_)
+            | Custom { .. } => ErrorCategory::Runtime,
+            WithContext { inner, .. } | InStatement { inner, .. } => inner.category(),
+        }
+    }
+
+    /// The error without the context added while it propagated.
+    pub fn root(&self) -> &Error {
+        match self {
+            Error::WithContext { inner, .. } | Error::InStatement { inner, .. } => inner.root(),
+            other => other,
+        }
+    }
+
+    // Called by every expression an error propagates through: the first
+    // records itself as the snippet and the second as the parent, outer
+    // ones leave the error alone so `snippet` only runs twice. Custom errors
+    // belong to the host and are never wrapped.
+    pub(crate) fn with_context(self, snippet: impl FnOnce() -> String) -> Error {
+        match self {
+            Error::WithContext {
+                inner,
+                snippet: inner_snippet,
+                parent: None,
+            } => Error::WithContext {
+                inner,
+                snippet: inner_snippet,
+                parent: Some(snippet()),
+            },
+            err
+            @ (Error::WithContext { .. } | Error::InStatement { .. } | Error::Custom { .. }) => err,
+            err => Error::WithContext {
+                inner: Box::new(err),
+                snippet: snippet(),
+                parent: None,
+            },
+        }
+    }
+
+    // Called by a chain on the error of one of its statements. Custom errors
+    // aren't wrapped here either.
+    pub(crate) fn in_statement(self, index: usize, statement: impl FnOnce() -> String) -> Error {
+        match self {
+            err @ Error::Custom { .. } => err,
+            err => Error::InStatement {
+                index,
+                statement: statement(),
+                inner: Box::new(err),
+            },
+        }
+    }
 }
 
 #[cfg(not(tarpaulin_include))]
@@ -37,21 +402,40 @@ impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         use Error::*;
         match self {
-            InvalidNumber(s) => write!(f, "invalid number: {}", s),
+            InvalidNumber(s, _) => write!(f, "invalid number: {}", s),
             UnexpectedEOF(start) => write!(f, "unexpected eof: {}", start),
             UnterminatedString(start) => write!(f, "unterminated string: {}", start),
             InvalidBool(start) => write!(f, "invalid bool: {}", start),
+            InvalidAssignTarget(start) => write!(f, "invalid assignment target: {}", start),
+            NestingTooDeep(start) => write!(f, "nested too deep: {}", start),
             NotSupportedChar(start, ch) => write!(f, "not supported char: {}, {}", start, ch),
-            ReferenceNotExist(name) => write!(f, "reference not exist: {}", name),
+            ReferenceNotExist(name, suggestions) => {
+                write!(f, "reference not exist: '{}'", name)?;
+                if !suggestions.is_empty() {
+                    write!(f, " — did you mean {}?", Candidates(suggestions))?;
+                }
+                Ok(())
+            }
             FunctionNotExist(name) => write!(f, "function not exist: {}", name),
-            NotSupportedOp(op) => write!(f, "not supported op: {}", op),
+            NotSupportedOp(start, op) => write!(f, "not supported op: {}, {}", start, op),
+            UnknownWordOp(start, word, known) => write!(
+                f,
+                "'{}' is not a known operator (at {}); known word operators: {}",
+                word,
+                start,
+                known.join(", ")
+            ),
             InfixOpNotRegistered(op) => write!(f, "binary op not registered: {}", op),
             PrefixOpNotRegistered(op) => write!(f, "unary op not registered: {}", op),
-            InnerFunctionNotRegistered(name) => {
-                write!(f, "inner function not registered: {}", name)
+            InnerFunctionNotRegistered(name, suggestions) => {
+                write!(f, "inner function not registered: {}", name)?;
+                if !suggestions.is_empty() {
+                    write!(f, " — did you mean {}?", Candidates(suggestions))?;
+                }
+                Ok(())
             }
             ShouldBeNumber() => write!(f, "should be number"),
-            ShouldBeBool() => write!(f, "should be bool"),
+            ShouldBeBool(got) => write!(f, "should be bool, got {}", got),
             ShouldBeList() => write!(f, "should be list"),
             ShouldBeMap() => write!(f, "should be map"),
             InvalidTernaryExprNeedColon() => write!(f, "invalid ternary expr needs colon"),
@@ -62,11 +446,626 @@ impl fmt::Display for Error {
             UnexpectedToken() => write!(f, "unexpected token"),
             NotReferenceExpr => write!(f, "not reference expr"),
             NoOpenDelim => write!(f, "no open delim"),
-            NoCloseDelim => write!(f, "no close delim"),
+            NoCloseDelim(_) => write!(f, "no close delim"),
             InvalidOp(op) => write!(f, "invalid op {}", op),
             InvalidInteger => write!(f, "invalid integer"),
             InvalidFloat => write!(f, "invalid float"),
             ExpectBinOpToken => write!(f, "expect bin op token"),
+            InvalidJson(msg) => write!(f, "invalid json: {}", msg),
+            InvalidBase64(msg) => write!(f, "invalid base64: {}", msg),
+            InvalidCsv(msg) => write!(f, "invalid csv: {}", msg),
+            InvalidFormat(msg) => write!(f, "invalid format: {}", msg),
+            InvalidQuantity(kind, input) => write!(f, "invalid {}: '{}'", kind, input),
+            ListTooLong(max) => write!(f, "list would exceed the limit of {} elements", max),
+            NoLength(got) => write!(f, "{} has no length", got),
+            NoMatchingArm(value) => write!(f, "no match arm for {}", value),
+            NotSpreadable(got) => write!(f, "can not spread {}, only a list", got),
+            MissingKey(path, key) => write!(f, "{} exists but has no key '{}'", path, key),
+            NotAMap(path, got) => write!(f, "{} is {}, not a map", path, got),
+            WithContext {
+                inner,
+                snippet,
+                parent,
+            } => {
+                write!(f, "{}: in '{}'", inner, snippet)?;
+                match parent {
+                    Some(parent) => write!(f, " within '{}'", parent),
+                    None => Ok(()),
+                }
+            }
+            InStatement { index, inner, .. } => {
+                write!(f, "error in statement {}: {}", index, inner)
+            }
+            NotComparable(lhs, rhs) => write!(f, "can not compare {} with {}", lhs, rhs),
+            NotConvertible(got, rust_type) => write!(f, "can not convert {} to {}", got, rust_type),
+            UnrepresentableFloat(value) => {
+                write!(f, "float {} can not be represented as a number", value)
+            }
+            NumberOverflow(op) => write!(f, "the result of '{}' is too large for a number", op),
+            DivisionByZero => write!(f, "division by zero"),
+            UnknownFunction(name, suggestions, _) => {
+                write!(f, "unknown function '{}'", name)?;
+                if !suggestions.is_empty() {
+                    write!(f, ", did you mean {}?", Candidates(suggestions))?;
+                }
+                Ok(())
+            }
+            FunctionExists(name) => write!(f, "function '{}' is already registered", name),
+            InvertedBounds(lower, upper) => {
+                write!(
+                    f,
+                    "lower bound {} is greater than upper bound {}",
+                    lower, upper
+                )
+            }
+            ShiftOutOfRange(count) => write!(f, "can not shift by {}, only 0 to 63", count),
+            ResourceLimitExceeded { kind, limit } => {
+                write!(f, "exceeded the limit of {} {}", limit, kind)
+            }
+            TokenTooLong { start, kind, limit } => {
+                write!(f, "{} longer than {} bytes: {}", kind, limit, start)
+            }
+            InputTooLarge { len, limit } => {
+                write!(f, "input of {} bytes is longer than {}", len, limit)
+            }
+            AsyncFunctionInSync(name) => {
+                write!(f, "function '{}' is async, evaluate with exec_async", name)
+            }
+            DuplicateMapKey(key) => write!(f, "duplicate map key {}", key),
+            Cancelled => write!(f, "evaluation cancelled"),
+            NoRngSeed => write!(
+                f,
+                "random numbers need a seed, set with Context::set_rng_seed"
+            ),
+            ReadonlyAssignment(op) => {
+                write!(f, "'{}' assigns, evaluate with exec instead", op)
+            }
+            AssignmentForbidden(name) => write!(
+                f,
+                "can not assign to '{}', assignments are forbidden by the options",
+                name
+            ),
+            NonDeterministicCall(name) => write!(
+                f,
+                "can not call '{}', only deterministic functions are allowed by the options",
+                name
+            ),
+            WrongFunctionArity {
+                name,
+                expected,
+                got,
+                span,
+            } => write!(
+                f,
+                "'{}' takes {} params, got {} (at {})",
+                name, expected, got, span.0
+            ),
+            Custom { message, .. } => write!(f, "{}", message),
+        }
+    }
+}
+
+// Quoted names joined like `'a', 'b' or 'c'`.
+struct Candidates<'a>(&'a [String]);
+
+impl fmt::Display for Candidates<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, name) in self.0.iter().enumerate() {
+            match i {
+                0 => {}
+                _ if i + 1 == self.0.len() => f.write_str(" or ")?,
+                _ => f.write_str(", ")?,
+            }
+            write!(f, "'{}'", name)?;
         }
+        Ok(())
+    }
+}
+
+impl core::error::Error for Error {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        match self {
+            Error::WithContext { inner, .. } | Error::InStatement { inner, .. } => {
+                Some(inner.as_ref())
+            }
+            Error::Custom {
+                source: Some(source),
+                ..
+            } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+/// The broad class of an [`Error`], for callers that only need to tell
+/// mistakes in the expression from problems with the data it ran against.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ErrorCategory {
+    /// The expression could not be tokenized or parsed.
+    Syntax,
+    /// A reference, function or operator could not be found.
+    Reference,
+    /// A value had the wrong type for where it was used.
+    Type,
+    /// A function was called with invalid params.
+    Argument,
+    /// A configured limit such as [`crate::EvalOptions::max_list_len`] was hit.
+    Limit,
+    /// Any other failure while evaluating.
+    Runtime,
+}
+
+impl ErrorCategory {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Syntax => "syntax",
+            Self::Reference => "reference",
+            Self::Type => "type",
+            Self::Argument => "argument",
+            Self::Limit => "limit",
+            Self::Runtime => "runtime",
+        }
+    }
+}
+
+#[cfg(not(tarpaulin_include))]
+impl fmt::Display for ErrorCategory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+// Serialized as `{ code, message, position, details }`, where `details`
+// holds the variant's own fields and the context of a `WithContext`, or is
+// null when there are none.
+#[cfg(feature = "serde")]
+impl serde::Serialize for Error {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> core::result::Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+        let details = self.details();
+        let mut state = serializer.serialize_struct("Error", 4)?;
+        state.serialize_field("code", self.code())?;
+        state.serialize_field("message", &self.to_string())?;
+        state.serialize_field("position", &self.position())?;
+        if details.is_empty() {
+            state.serialize_field("details", &None::<()>)?;
+        } else {
+            state.serialize_field("details", &Details(&details))?;
+        }
+        state.end()
+    }
+}
+
+#[cfg(feature = "serde")]
+struct Details<'a>(&'a [(&'static str, String)]);
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for Details<'_> {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> core::result::Result<S::Ok, S::Error> {
+        use serde::ser::SerializeMap;
+        let mut map = serializer.serialize_map(Some(self.0.len()))?;
+        for (key, value) in self.0 {
+            map.serialize_entry(key, value)?;
+        }
+        map.end()
+    }
+}
+
+#[cfg(feature = "serde")]
+impl Error {
+    fn details(&self) -> Vec<(&'static str, String)> {
+        use Error::*;
+        match self {
+            NotSupportedChar(_, ch) => vec![("char", ch.to_string())],
+            FunctionNotExist(name)
+            | AsyncFunctionInSync(name)
+            | NonDeterministicCall(name)
+            | FunctionExists(name) => {
+                vec![("name", name.clone())]
+            }
+            AssignmentForbidden(path) => vec![("path", path.clone())],
+            ReferenceNotExist(name, suggestions)
+            | InnerFunctionNotRegistered(name, suggestions)
+            | UnknownFunction(name, suggestions, _) => {
+                let mut details = vec![("name", name.clone())];
+                if !suggestions.is_empty() {
+                    details.push(("suggestions", suggestions.join(", ")));
+                }
+                details
+            }
+            UnknownWordOp(_, word, known) => {
+                vec![("word", word.clone()), ("known", known.join(", "))]
+            }
+            NotSupportedOp(_, op)
+            | ReadonlyAssignment(op)
+            | InfixOpNotRegistered(op)
+            | PrefixOpNotRegistered(op)
+            | ExpectedOpNotExist(op)
+            | InvalidOp(op) => vec![("op", op.clone())],
+            NumberOverflow(op) => vec![("op", op.to_string())],
+            InvalidNumber(value, _) | NoMatchingArm(value) => vec![("value", value.clone())],
+            DuplicateMapKey(key) => vec![("key", key.clone())],
+            InvalidJson(reason)
+            | InvalidBase64(reason)
+            | InvalidCsv(reason)
+            | InvalidFormat(reason) => {
+                vec![("reason", reason.clone())]
+            }
+            InvalidQuantity(kind, input) => {
+                vec![("kind", kind.to_string()), ("value", input.clone())]
+            }
+            ShouldBeBool(got) | NoLength(got) | NotSpreadable(got) => {
+                vec![("got", got.to_string())]
+            }
+            ListTooLong(max) => vec![("limit", max.to_string())],
+            MissingKey(path, key) => vec![("path", path.clone()), ("key", key.clone())],
+            NotAMap(path, got) => vec![("path", path.clone()), ("got", got.to_string())],
+            NotComparable(lhs, rhs) => vec![("lhs", lhs.to_string()), ("rhs", rhs.to_string())],
+            NotConvertible(got, rust_type) => {
+                vec![("got", got.to_string()), ("type", rust_type.to_string())]
+            }
+            UnrepresentableFloat(value) => vec![("value", value.to_string())],
+            InvertedBounds(lower, upper) => {
+                vec![("lower", lower.clone()), ("upper", upper.clone())]
+            }
+            ShiftOutOfRange(count) => vec![("count", count.to_string())],
+            WrongFunctionArity {
+                name,
+                expected,
+                got,
+                span,
+            } => vec![
+                ("name", name.clone()),
+                ("expected", expected.to_string()),
+                ("got", got.to_string()),
+                ("end", span.1.to_string()),
+            ],
+            ResourceLimitExceeded { kind, limit } | TokenTooLong { kind, limit, .. } => {
+                vec![("kind", kind.to_string()), ("limit", limit.to_string())]
+            }
+            InputTooLarge { len, limit } => {
+                vec![("len", len.to_string()), ("limit", limit.to_string())]
+            }
+            WithContext {
+                inner,
+                snippet,
+                parent,
+            } => {
+                let mut details = inner.details();
+                details.push(("snippet", snippet.clone()));
+                if let Some(parent) = parent {
+                    details.push(("parent", parent.clone()));
+                }
+                details
+            }
+            InStatement {
+                index,
+                statement,
+                inner,
+            } => {
+                let mut details = inner.details();
+                details.push(("statement", index.to_string()));
+                details.push(("statement_text", statement.clone()));
+                details
+            }
+            _ => vec![],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Error, ErrorCategory};
+    use crate::function::Arity;
+    use crate::token::Span;
+    use std::collections::HashSet;
+
+    // Codes are part of the public contract: this list only ever grows, and
+    // an entry changing means a caller somewhere stops matching.
+    fn all_errors() -> Vec<(Error, &'static str, ErrorCategory)> {
+        use ErrorCategory::*;
+        vec![
+            (
+                Error::InvalidNumber("1.2.3".into(), Some(0)),
+                "E_PARSE_INVALID_NUMBER",
+                Syntax,
+            ),
+            (Error::UnexpectedEOF(0), "E_PARSE_UNEXPECTED_EOF", Syntax),
+            (
+                Error::UnterminatedString(0),
+                "E_PARSE_UNTERMINATED_STRING",
+                Syntax,
+            ),
+            (Error::InvalidBool(0), "E_PARSE_INVALID_BOOL", Syntax),
+            (
+                Error::InvalidAssignTarget(0),
+                "E_PARSE_INVALID_ASSIGN_TARGET",
+                Syntax,
+            ),
+            (
+                Error::NotSupportedChar(0, '@'),
+                "E_PARSE_UNSUPPORTED_CHAR",
+                Syntax,
+            ),
+            (
+                Error::ReferenceNotExist("a".into(), vec![]),
+                "E_REF_NOT_FOUND",
+                Reference,
+            ),
+            (
+                Error::FunctionNotExist("f".into()),
+                "E_FUNC_NOT_FOUND",
+                Reference,
+            ),
+            (
+                Error::NotSupportedOp(0, "@".into()),
+                "E_PARSE_UNSUPPORTED_OP",
+                Syntax,
+            ),
+            (
+                Error::UnknownWordOp(2, "foo".into(), vec!["in".into()]),
+                "E_PARSE_UNKNOWN_WORD_OP",
+                Syntax,
+            ),
+            (
+                Error::InfixOpNotRegistered("+".into()),
+                "E_REF_INFIX_OP_NOT_FOUND",
+                Reference,
+            ),
+            (
+                Error::PrefixOpNotRegistered("-".into()),
+                "E_REF_PREFIX_OP_NOT_FOUND",
+                Reference,
+            ),
+            (
+                Error::InnerFunctionNotRegistered("f".into(), vec![]),
+                "E_FUNC_NOT_REGISTERED",
+                Reference,
+            ),
+            (Error::ShouldBeNumber(), "E_TYPE_EXPECTED_NUMBER", Type),
+            (Error::ShouldBeBool("Number"), "E_TYPE_EXPECTED_BOOL", Type),
+            (Error::ShouldBeList(), "E_TYPE_EXPECTED_LIST", Type),
+            (Error::ShouldBeMap(), "E_TYPE_EXPECTED_MAP", Type),
+            (Error::ParamInvalid(), "E_ARG_INVALID", Argument),
+            (Error::ShouldBeString(), "E_TYPE_EXPECTED_STRING", Type),
+            (
+                Error::InvalidTernaryExprNeedColon(),
+                "E_PARSE_TERNARY_MISSING_COLON",
+                Syntax,
+            ),
+            (
+                Error::ExpectedOpNotExist(")".into()),
+                "E_PARSE_EXPECTED_TOKEN",
+                Syntax,
+            ),
+            (Error::WrongContextValueType(), "E_TYPE_CONTEXT_VALUE", Type),
+            (Error::UnexpectedToken(), "E_PARSE_UNEXPECTED_TOKEN", Syntax),
+            (Error::NotReferenceExpr, "E_PARSE_NOT_REFERENCE", Syntax),
+            (Error::NoOpenDelim, "E_PARSE_NO_OPEN_DELIM", Syntax),
+            (Error::NoCloseDelim(2), "E_PARSE_NO_CLOSE_DELIM", Syntax),
+            (Error::InvalidOp("@".into()), "E_PARSE_INVALID_OP", Syntax),
+            (Error::InvalidInteger, "E_TYPE_INVALID_INTEGER", Type),
+            (Error::InvalidFloat, "E_TYPE_INVALID_FLOAT", Type),
+            (
+                Error::ExpectBinOpToken,
+                "E_PARSE_EXPECTED_BINARY_OP",
+                Syntax,
+            ),
+            (
+                Error::InvalidJson("eof".into()),
+                "E_ARG_INVALID_JSON",
+                Argument,
+            ),
+            (
+                Error::InvalidBase64("padding".into()),
+                "E_ARG_INVALID_BASE64",
+                Argument,
+            ),
+            (
+                Error::InvalidCsv("expected 2 fields, got 3".into()),
+                "E_ARG_INVALID_CSV",
+                Argument,
+            ),
+            (
+                Error::InvalidFormat("{".into()),
+                "E_ARG_INVALID_FORMAT",
+                Argument,
+            ),
+            (
+                Error::InvalidQuantity("duration", "5 parsecs".into()),
+                "E_ARG_INVALID_QUANTITY",
+                Argument,
+            ),
+            (Error::ListTooLong(10), "E_LIMIT_LIST_TOO_LONG", Limit),
+            (Error::NoLength("Number"), "E_TYPE_NO_LENGTH", Type),
+            (Error::NoMatchingArm("1".into()), "E_MATCH_NO_ARM", Runtime),
+            (
+                Error::NotSpreadable("Number"),
+                "E_TYPE_NOT_SPREADABLE",
+                Type,
+            ),
+            (
+                Error::MissingKey("a".into(), "b".into()),
+                "E_REF_MISSING_KEY",
+                Reference,
+            ),
+            (
+                Error::NotAMap("a".into(), "Number"),
+                "E_TYPE_NOT_A_MAP",
+                Type,
+            ),
+            (
+                Error::NotComparable("Number", "String"),
+                "E_TYPE_NOT_COMPARABLE",
+                Type,
+            ),
+            (
+                Error::NotConvertible("Number", "bool"),
+                "E_TYPE_NOT_CONVERTIBLE",
+                Type,
+            ),
+            (
+                Error::UnrepresentableFloat(f64::NAN),
+                "E_TYPE_UNREPRESENTABLE_FLOAT",
+                Type,
+            ),
+            (Error::NumberOverflow("*"), "E_TYPE_NUMBER_OVERFLOW", Type),
+            (Error::DivisionByZero, "E_ARG_DIVISION_BY_ZERO", Argument),
+            (
+                Error::UnknownFunction("roud".into(), vec!["round".into()], Some(0)),
+                "E_FUNC_UNKNOWN",
+                Reference,
+            ),
+            (
+                Error::FunctionExists("geo.distance".into()),
+                "E_FUNC_EXISTS",
+                Reference,
+            ),
+            (
+                Error::InvertedBounds("10".into(), "1".into()),
+                "E_ARG_INVERTED_BOUNDS",
+                Argument,
+            ),
+            (
+                Error::ShiftOutOfRange(64),
+                "E_ARG_SHIFT_OUT_OF_RANGE",
+                Argument,
+            ),
+            (
+                Error::ResourceLimitExceeded {
+                    kind: "elements",
+                    limit: 10,
+                },
+                "E_LIMIT_RESOURCE_EXCEEDED",
+                Limit,
+            ),
+            (
+                Error::AsyncFunctionInSync("fetch".into()),
+                "E_FUNC_ASYNC_IN_SYNC",
+                Runtime,
+            ),
+            (
+                Error::DuplicateMapKey("'a'".into()),
+                "E_MAP_DUPLICATE_KEY",
+                Runtime,
+            ),
+            (Error::Cancelled, "E_CANCELLED", Runtime),
+            (Error::NoRngSeed, "E_RNG_NOT_SEEDED", Runtime),
+            (
+                Error::ReadonlyAssignment("=".into()),
+                "E_READONLY_ASSIGNMENT",
+                Runtime,
+            ),
+            (
+                Error::AssignmentForbidden("x".into()),
+                "E_LIMIT_ASSIGNMENT_FORBIDDEN",
+                Limit,
+            ),
+            (
+                Error::NonDeterministicCall("now".into()),
+                "E_LIMIT_NON_DETERMINISTIC_CALL",
+                Limit,
+            ),
+            (Error::NestingTooDeep(0), "E_PARSE_NESTING_TOO_DEEP", Syntax),
+            (
+                Error::TokenTooLong {
+                    start: 0,
+                    kind: "string",
+                    limit: 10,
+                },
+                "E_LIMIT_TOKEN_TOO_LONG",
+                Limit,
+            ),
+            (
+                Error::InputTooLarge { len: 11, limit: 10 },
+                "E_LIMIT_INPUT_TOO_LARGE",
+                Limit,
+            ),
+            (
+                Error::WrongFunctionArity {
+                    name: "round".into(),
+                    expected: Arity::Range(1, 3),
+                    got: 0,
+                    span: Span(0, 7),
+                },
+                "E_ARG_WRONG_ARITY",
+                Argument,
+            ),
+        ]
+    }
+
+    #[test]
+    fn test_error_codes() {
+        let mut seen = HashSet::new();
+        for (err, code, category) in all_errors() {
+            assert_eq!(err.code(), code, "{:?}", err);
+            assert_eq!(err.category(), category, "{:?}", err);
+            assert!(seen.insert(code), "duplicate code {}", code);
+        }
+    }
+
+    #[test]
+    fn test_error_code_with_context() {
+        let err = Error::ShouldBeNumber()
+            .with_context(|| "1 + true".into())
+            .with_context(|| "(1 + true) * 2".into());
+        assert_eq!(err.code(), "E_TYPE_EXPECTED_NUMBER");
+        assert_eq!(err.category(), ErrorCategory::Type);
+        let err = err.in_statement(2, || "a = (1 + true) * 2".into());
+        assert_eq!(err.code(), "E_TYPE_EXPECTED_NUMBER");
+        assert_eq!(err.category(), ErrorCategory::Type);
+        assert!(matches!(err.root(), Error::ShouldBeNumber()));
+    }
+
+    #[cfg(all(feature = "serde", feature = "serde_json"))]
+    #[test]
+    fn test_error_serialize() {
+        let err = Error::NotSupportedChar(3, '@');
+        assert_eq!(
+            serde_json::to_value(&err).unwrap(),
+            serde_json::json!({
+                "code": "E_PARSE_UNSUPPORTED_CHAR",
+                "message": "not supported char: 3, @",
+                "position": 3,
+                "details": {"char": "@"},
+            })
+        );
+
+        let err = Error::ReferenceNotExist("a".into(), vec![]).with_context(|| "a + 1".into());
+        assert_eq!(
+            serde_json::to_value(&err).unwrap(),
+            serde_json::json!({
+                "code": "E_REF_NOT_FOUND",
+                "message": "reference not exist: 'a': in 'a + 1'",
+                "position": null,
+                "details": {"name": "a", "snippet": "a + 1"},
+            })
+        );
+
+        let err = Error::ReferenceNotExist("b".into(), vec![])
+            .with_context(|| "b".into())
+            .in_statement(2, || "b".into());
+        assert_eq!(
+            serde_json::to_value(&err).unwrap()["details"],
+            serde_json::json!({"name": "b", "snippet": "b", "statement": "2", "statement_text": "b"})
+        );
+
+        let err = Error::ReferenceNotExist("totl".into(), vec!["total".into(), "tot".into()]);
+        assert_eq!(
+            serde_json::to_value(&err).unwrap()["details"],
+            serde_json::json!({"name": "totl", "suggestions": "total, tot"})
+        );
+
+        let err = Error::ShouldBeNumber();
+        assert_eq!(
+            serde_json::to_value(&err).unwrap()["details"],
+            serde_json::Value::Null
+        );
     }
 }
@@ -0,0 +1,385 @@
+use crate::compat::{DefaultHasher, HashMap};
+use crate::context::Context;
+use crate::define::Result;
+use crate::function::InnerFunctionManager;
+use crate::operator::{
+    InfixOpConfig, InfixOpManager, InfixOpType, PostfixOpManager, PrefixOpManager,
+};
+use crate::options::EvalOptions;
+use crate::parser::ExprAST;
+use crate::value::{Map, Value};
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::hash::{Hash, Hasher};
+
+/// Walks trees of [`ExprAST`] to evaluate them. [`ExprAST::exec`] builds a
+/// default one for each call; build one up front to evaluate many
+/// expressions with it.
+///
+/// Each evaluation keeps the results cached by [`EvalOptions::memoize`] to
+/// itself, so evaluations through one evaluator, even from several threads,
+/// never share them. It evaluates with the global operator and function
+/// registries, so an operator or function registered after it was built is
+/// still found. With [`Evaluator::with_options`] it evaluates with options
+/// of its own, in place of those of the context.
+pub struct Evaluator {
+    options: Option<EvalOptions>,
+    infix: InfixOpManager,
+    prefix: PrefixOpManager,
+    postfix: PostfixOpManager,
+    functions: InnerFunctionManager,
+}
+
+// The state of a single evaluation.
+struct Evaluation<'v> {
+    evaluator: &'v Evaluator,
+    // Call results by the hash of their subtree, while `memoize` is on.
+    memo: HashMap<u64, Value>,
+    memoize: bool,
+}
+
+impl Default for Evaluator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Evaluator {
+    /// An evaluator that evaluates with the options of each context.
+    pub fn new() -> Self {
+        Evaluator {
+            options: None,
+            infix: InfixOpManager::new(),
+            prefix: PrefixOpManager::new(),
+            postfix: PostfixOpManager::new(),
+            functions: InnerFunctionManager::new(),
+        }
+    }
+
+    /// An evaluator that evaluates with `options`, whatever the options of
+    /// the context are. The context keeps its own once it's done.
+    pub fn with_options(options: EvalOptions) -> Self {
+        Evaluator {
+            options: Some(options),
+            ..Self::new()
+        }
+    }
+
+    /// The options it evaluates with, `None` for those of the context.
+    pub fn options(&self) -> Option<EvalOptions> {
+        self.options
+    }
+
+    /// Evaluates `ast` against `ctx`, like [`ExprAST::exec`].
+    pub fn evaluate(&self, ast: &ExprAST, ctx: &mut Context) -> Result<Value> {
+        let options = ctx.options();
+        if let Some(own) = self.options {
+            ctx.set_options(own);
+        }
+        let mut evaluation = Evaluation {
+            evaluator: self,
+            memo: HashMap::new(),
+            memoize: ctx.options().memoize,
+        };
+        let ans = evaluation.exec(ast, ctx);
+        if self.options.is_some() {
+            ctx.set_options(options);
+        }
+        ans
+    }
+}
+
+impl Evaluation<'_> {
+    fn exec(&mut self, expr: &ExprAST, ctx: &mut Context) -> Result<Value> {
+        match self.exec_node(expr, ctx) {
+            Ok(value) => {
+                if let Some(hook) = ctx.trace_hook() {
+                    hook(expr, &value);
+                }
+                if ctx.options().collect_warnings {
+                    ctx.finish_warnings(expr as *const ExprAST as usize);
+                }
+                Ok(value)
+            }
+            Err(err) => Err(err.with_context(|| expr.expr())),
+        }
+    }
+
+    fn exec_node(&mut self, expr: &ExprAST, ctx: &mut Context) -> Result<Value> {
+        use ExprAST::*;
+        ctx.tick()?;
+        match expr {
+            Literal(literal) => expr.exec_literal(literal.clone()),
+            Reference(name) => expr.exec_reference(name, ctx),
+            Function(name, exprs) => self.exec_function(expr, name, exprs, ctx),
+            Unary(op, rhs) => self.exec_unary(op, rhs, ctx),
+            Binary(op, lhs, rhs) => self.exec_binary(op, lhs, rhs, ctx),
+            Postfix(lhs, op) => self.evaluator.postfix.get(op)?(self.exec(lhs, ctx)?),
+            Ternary(condition, lhs, rhs) => self.exec_ternary(condition, lhs, rhs, ctx),
+            List(params) => self.exec_list(params, ctx),
+            Stmt(exprs) => self.exec_chain(exprs, ctx),
+            Map(m) => self.exec_map(m, ctx),
+            Match(scrutinee, arms, default) => self.exec_match(scrutinee, arms, default, ctx),
+            Spread(expr) => Ok(Value::List(self.exec(expr, ctx)?.spread()?)),
+            None => Ok(Value::None),
+        }
+    }
+
+    fn exec_function(
+        &mut self,
+        call: &ExprAST,
+        name: &str,
+        exprs: &[ExprAST],
+        ctx: &mut Context,
+    ) -> Result<Value> {
+        let key = self.memo_key(call, ctx);
+        if let Some(value) = key.and_then(|key| self.memo.get(&key)) {
+            return Ok(value.clone());
+        }
+        let params = self.exec_params(exprs, ctx)?;
+        ctx.check_call(name)?;
+        let ans = match ctx.get_func(name) {
+            Some(func) => func(params),
+            None => self.evaluator.functions.resolve(name, ctx)?(ctx, params),
+        }?;
+        let ans = ctx.options().limits.check(ans)?;
+        if let Some(key) = key {
+            self.memo.insert(key, ans.clone());
+        }
+        Ok(ans)
+    }
+
+    // Identical subtrees hash alike, so the hash is the cache key of a call
+    // whose result can only change through an assignment.
+    fn memo_key(&self, call: &ExprAST, ctx: &Context) -> Option<u64> {
+        if !self.memoize || !self.is_pure(call, ctx) {
+            return None;
+        }
+        let mut hasher = DefaultHasher::new();
+        call.hash(&mut hasher);
+        Some(hasher.finish())
+    }
+
+    fn is_pure(&self, expr: &ExprAST, ctx: &Context) -> bool {
+        let mut stack = vec![expr];
+        while let Some(expr) = stack.pop() {
+            match expr {
+                ExprAST::Function(name, params) => {
+                    if ctx.get_func(name).is_some()
+                        || !self.evaluator.functions.is_deterministic(name)
+                    {
+                        return false;
+                    }
+                    stack.extend(params);
+                }
+                // Reading a context function calls it.
+                ExprAST::Reference(name) => {
+                    if ctx.get_func(name).is_some() {
+                        return false;
+                    }
+                }
+                ExprAST::Binary(op, lhs, rhs) => {
+                    if self.evaluator.infix.is_setter(op) {
+                        return false;
+                    }
+                    stack.extend([lhs.as_ref(), rhs.as_ref()]);
+                }
+                _ => stack.extend(expr.children()),
+            }
+        }
+        true
+    }
+
+    fn exec_unary(&mut self, op: &str, rhs: &ExprAST, ctx: &mut Context) -> Result<Value> {
+        let rhs = ctx.options().prefix_operand(op, self.exec(rhs, ctx)?, ctx);
+        self.evaluator.prefix.get(op)?(rhs)
+    }
+
+    fn exec_binary(
+        &mut self,
+        op: &str,
+        lhs: &ExprAST,
+        rhs: &ExprAST,
+        ctx: &mut Context,
+    ) -> Result<Value> {
+        let InfixOpConfig(_, op_type, _, handler) = self.evaluator.infix.get(op)?;
+        match op_type {
+            InfixOpType::CALC => {
+                let (a, b) = (self.exec(lhs, ctx)?, self.exec(rhs, ctx)?);
+                ctx.options().infix(op, &handler, a, b, ctx)
+            }
+            InfixOpType::SETTER => {
+                let a = match lhs {
+                    ExprAST::Reference(name) => ctx.target_value(name)?,
+                    _ => self.exec(lhs, ctx)?,
+                };
+                let b = self.exec(rhs, ctx)?;
+                let target = lhs.get_reference_name()?;
+                // Cached results may have read the old value.
+                self.memo.clear();
+                ctx.options().assign(op, &handler, a, b, target, ctx)
+            }
+        }
+    }
+
+    fn exec_ternary(
+        &mut self,
+        condition: &ExprAST,
+        lhs: &ExprAST,
+        rhs: &ExprAST,
+        ctx: &mut Context,
+    ) -> Result<Value> {
+        let value = self.exec(condition, ctx)?;
+        let holds = ctx.options().condition(value, ctx)?;
+        // Before a branch is done, which would take the warnings.
+        if ctx.options().collect_warnings {
+            ctx.finish_warnings(condition as *const ExprAST as usize);
+        }
+        if holds {
+            return self.exec(lhs, ctx);
+        }
+        self.exec(rhs, ctx)
+    }
+
+    fn exec_list(&mut self, params: &[ExprAST], ctx: &mut Context) -> Result<Value> {
+        let list = Value::List(self.exec_params(params, ctx)?);
+        ctx.options().limits.check(list)
+    }
+
+    fn exec_params(&mut self, params: &[ExprAST], ctx: &mut Context) -> Result<Vec<Value>> {
+        let mut ans = Vec::with_capacity(params.len());
+        for expr in params {
+            match expr {
+                ExprAST::Spread(_) => ans.extend(self.exec(expr, ctx)?.spread()?),
+                _ => ans.push(self.exec(expr, ctx)?),
+            }
+        }
+        Ok(ans)
+    }
+
+    fn exec_chain(&mut self, params: &[ExprAST], ctx: &mut Context) -> Result<Value> {
+        let mut ans = Value::None;
+        for (i, expr) in params.iter().enumerate() {
+            ans = self
+                .exec(expr, ctx)
+                .map_err(|err| err.in_statement(i + 1, || expr.expr()))?;
+        }
+        Ok(ans)
+    }
+
+    fn exec_map(&mut self, m: &[(ExprAST, ExprAST)], ctx: &mut Context) -> Result<Value> {
+        let mut ans = Map::with_capacity_and_hasher(m.len(), Default::default());
+        for (k, v) in m {
+            let k = self.exec(k, ctx)?;
+            let v = self.exec(v, ctx)?;
+            ctx.options().insert_key(&mut ans, k, v)?;
+        }
+        ctx.options().limits.check(Value::Map(ans))
+    }
+
+    // Arms are tried in order with `==` semantics and only the patterns up
+    // to the first match are evaluated.
+    fn exec_match(
+        &mut self,
+        scrutinee: &ExprAST,
+        arms: &[(ExprAST, ExprAST)],
+        default: &Option<Box<ExprAST>>,
+        ctx: &mut Context,
+    ) -> Result<Value> {
+        let scrutinee = self.exec(scrutinee, ctx)?;
+        for (pattern, value) in arms {
+            if self.exec(pattern, ctx)? == scrutinee {
+                return self.exec(value, ctx);
+            }
+        }
+        match default {
+            Some(default) => self.exec(default, ctx),
+            None => ctx.options().no_match(&scrutinee),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "default"))]
+mod tests {
+    use super::Evaluator;
+    use crate::{
+        create_context, parse_expression, register_deterministic_function, register_function,
+        register_infix_op, EvalOptions, InfixOpAssociativity, InfixOpType, Truthiness, Value,
+    };
+    use alloc::sync::Arc;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+    use rstest::rstest;
+
+    #[rstest]
+    #[case("a + b * 2", 11.into())]
+    #[case("c = a; c += b; [c, c > 6]", vec![7.into(), true.into()].into())]
+    #[case("match b {4: max(a, b), _: 0}", 4.into())]
+    #[case("", Value::None)]
+    fn test_evaluate(#[case] input: &str, #[case] output: Value) {
+        let evaluator = Evaluator::new();
+        let ast = parse_expression(input).unwrap();
+        let mut ctx = create_context!("a" => 3, "b" => 4);
+        assert_eq!(evaluator.evaluate(&ast, &mut ctx).unwrap(), output);
+        // The same evaluator again, like `exec` with a new one.
+        let mut ctx = create_context!("a" => 3, "b" => 4);
+        assert_eq!(evaluator.evaluate(&ast, &mut ctx).unwrap(), output);
+        let mut ctx = create_context!("a" => 3, "b" => 4);
+        assert_eq!(ast.exec(&mut ctx).unwrap(), output);
+    }
+
+    #[test]
+    fn test_evaluate_registered_later() {
+        let evaluator = Evaluator::new();
+        register_infix_op(
+            "evaluator_minus",
+            100,
+            InfixOpType::CALC,
+            InfixOpAssociativity::LEFT,
+            Arc::new(|left, right| Ok(Value::from(left.integer()? - right.integer()?))),
+        );
+        register_function("evaluator_seven", Arc::new(|_| Ok(Value::from(7))));
+        let ast = parse_expression("evaluator_seven() evaluator_minus 2").unwrap();
+        let ans = evaluator.evaluate(&ast, &mut create_context!());
+        assert_eq!(ans.unwrap(), 5.into());
+    }
+
+    #[test]
+    fn test_evaluate_with_options() {
+        let options = EvalOptions {
+            truthiness: Truthiness::Coerce,
+            ..EvalOptions::default()
+        };
+        let evaluator = Evaluator::with_options(options);
+        assert_eq!(evaluator.options(), Some(options));
+        assert_eq!(Evaluator::new().options(), None);
+        let ast = parse_expression("n ? 'some' : 'none'").unwrap();
+        let mut ctx = create_context!("n" => 2);
+        assert_eq!(evaluator.evaluate(&ast, &mut ctx).unwrap(), "some".into());
+        // The context keeps its own options.
+        assert_eq!(ctx.options(), EvalOptions::default());
+        assert!(ast.exec(&mut ctx).is_err());
+        assert!(Evaluator::new().evaluate(&ast, &mut ctx).is_err());
+    }
+
+    #[test]
+    fn test_evaluate_memo_per_evaluation() {
+        static CALLS: AtomicUsize = AtomicUsize::new(0);
+        register_deterministic_function(
+            "evaluator_triple",
+            Arc::new(|params| {
+                CALLS.fetch_add(1, Ordering::SeqCst);
+                Ok(Value::from(params[0].clone().integer()? * 3))
+            }),
+        );
+        let evaluator = Evaluator::with_options(EvalOptions {
+            memoize: true,
+            ..EvalOptions::default()
+        });
+        let ast = parse_expression("evaluator_triple(a) + evaluator_triple(a)").unwrap();
+        for (a, output) in [(1, 6), (2, 12)] {
+            let mut ctx = create_context!("a" => a);
+            assert_eq!(evaluator.evaluate(&ast, &mut ctx).unwrap(), output.into());
+        }
+        assert_eq!(CALLS.load(Ordering::SeqCst), 2);
+    }
+}
@@ -0,0 +1,251 @@
+use crate::compat::{HashMap, Mutex};
+use crate::context::Context;
+use crate::parser::ExprAST;
+use crate::trace::{address, TraceHook};
+use crate::value::Value;
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::fmt;
+
+/// The branch of a ternary that was evaluated.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Branch {
+    Then,
+    Else,
+}
+
+impl Branch {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Then => "then",
+            Self::Else => "else",
+        }
+    }
+}
+
+/// A report of how an expression was evaluated, built by
+/// [`ExprAST::explain`]. It mirrors the AST: every node has an explanation,
+/// with `value` left empty for the ones that weren't evaluated.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Explanation {
+    pub expr: String,
+    pub value: Option<Value>,
+    /// Only set on ternaries that were evaluated.
+    pub branch: Option<Branch>,
+    /// Only set on the root, when the evaluation failed.
+    pub error: Option<String>,
+    pub children: Vec<Explanation>,
+}
+
+impl<'a> ExprAST<'a> {
+    /// Evaluates the expression and explains the result, e.g. that
+    /// `age >= 18 && country in ['NO', 'SE']` is false because `age` is 16.
+    /// The `Display` form is an indented report, one node per line.
+    pub fn explain(&self, ctx: &mut Context) -> Explanation {
+        let values: Arc<Mutex<HashMap<usize, Value>>> = Arc::default();
+        let sink = values.clone();
+        let hook: Arc<TraceHook> = Arc::new(move |expr, value| {
+            sink.lock().unwrap().insert(address(expr), value.clone());
+        });
+        let ans = self.exec_with_hook(ctx, hook);
+        let values = core::mem::take(&mut *values.lock().unwrap());
+        let mut explanation = Explanation::new(self, &values);
+        if let Err(err) = ans {
+            explanation.error = Some(err.root().to_string());
+        }
+        explanation
+    }
+}
+
+impl Explanation {
+    fn new(expr: &ExprAST, values: &HashMap<usize, Value>) -> Self {
+        let value = values.get(&address(expr)).cloned();
+        let branch = match expr {
+            ExprAST::Ternary(_, lhs, rhs) if value.is_some() => {
+                if values.contains_key(&address(lhs)) {
+                    Some(Branch::Then)
+                } else if values.contains_key(&address(rhs)) {
+                    Some(Branch::Else)
+                } else {
+                    None
+                }
+            }
+            _ => None,
+        };
+        Self {
+            expr: expr.expr(),
+            value,
+            branch,
+            error: None,
+            children: expr
+                .children()
+                .into_iter()
+                .map(|child| Self::new(child, values))
+                .collect(),
+        }
+    }
+
+    fn write(&self, f: &mut fmt::Formatter<'_>, depth: usize) -> fmt::Result {
+        write!(f, "{:indent$}{}", "", self.expr, indent = depth * 2)?;
+        match &self.value {
+            Some(value) => write!(f, " => {}", value)?,
+            None => write!(f, " (not evaluated)")?,
+        }
+        if let Some(branch) = self.branch {
+            write!(f, " [{}]", branch.as_str())?;
+        }
+        if let Some(error) = &self.error {
+            write!(f, " error: {}", error)?;
+        }
+        writeln!(f)?;
+        for child in &self.children {
+            child.write(f, depth + 1)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Explanation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.write(f, 0)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for Explanation {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> core::result::Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+        let mut state = serializer.serialize_struct("Explanation", 6)?;
+        state.serialize_field("expr", &self.expr)?;
+        state.serialize_field("evaluated", &self.value.is_some())?;
+        state.serialize_field("value", &self.value)?;
+        state.serialize_field("branch", &self.branch.map(|branch| branch.as_str()))?;
+        state.serialize_field("error", &self.error)?;
+        state.serialize_field("children", &self.children)?;
+        state.end()
+    }
+}
+
+#[cfg(all(test, feature = "default"))]
+mod tests {
+    use super::Branch;
+    use crate::{create_context, parse_expression, Value};
+
+    #[test]
+    fn test_explain_rule() {
+        let ast = parse_expression("age >= 18 && country in ['NO', 'SE']").unwrap();
+        let explanation = ast.explain(&mut create_context!("age" => 16, "country" => "NO"));
+        assert_eq!(explanation.value, Some(false.into()));
+        assert_eq!(explanation.children.len(), 2);
+        assert_eq!(
+            explanation.to_string(),
+            "\
+age >= 18 && country in [\"NO\",\"SE\"] => false
+  age >= 18 => false
+    age => 16
+    18 => 18
+  country in [\"NO\",\"SE\"] => true
+    country => 'NO'
+    [\"NO\",\"SE\"] => ['NO', 'SE']
+      \"NO\" => 'NO'
+      \"SE\" => 'SE'
+"
+        );
+    }
+
+    #[test]
+    fn test_explain_ternary() {
+        let ast = parse_expression("total > 100 ? total * 0.9 : total + shipping").unwrap();
+        let explanation = ast.explain(&mut create_context!("total" => 50, "shipping" => 5));
+        assert_eq!(explanation.branch, Some(Branch::Else));
+        assert_eq!(explanation.children[1].value, None);
+        assert_eq!(
+            explanation.to_string(),
+            "\
+total > 100 ? total * 0.9 : total + shipping => 55 [else]
+  total > 100 => false
+    total => 50
+    100 => 100
+  total * 0.9 (not evaluated)
+    total (not evaluated)
+    0.9 (not evaluated)
+  total + shipping => 55
+    total => 50
+    shipping => 5
+"
+        );
+    }
+
+    #[test]
+    fn test_explain_error() {
+        let ast = parse_expression("a > 1 ? 'x' : a - 'y'").unwrap();
+        let explanation = ast.explain(&mut create_context!("a" => 1));
+        assert_eq!(explanation.value, None);
+        assert_eq!(explanation.error.as_deref(), Some("should be number"));
+        assert_eq!(explanation.children[0].value, Some(false.into()));
+    }
+
+    #[cfg(all(feature = "serde", feature = "serde_json"))]
+    #[test]
+    fn test_explain_serialize() {
+        let ast = parse_expression("a > 1 ? 'big' : 'small'").unwrap();
+        let explanation = ast.explain(&mut create_context!("a" => 2));
+        assert_eq!(
+            serde_json::to_value(&explanation).unwrap(),
+            serde_json::json!({
+                "expr": "a > 1 ? \"big\" : \"small\"",
+                "evaluated": true,
+                "value": "big",
+                "branch": "then",
+                "error": null,
+                "children": [
+                    {
+                        "expr": "a > 1",
+                        "evaluated": true,
+                        "value": true,
+                        "branch": null,
+                        "error": null,
+                        "children": [
+                            {
+                                "expr": "a",
+                                "evaluated": true,
+                                "value": 2,
+                                "branch": null,
+                                "error": null,
+                                "children": [],
+                            },
+                            {
+                                "expr": "1",
+                                "evaluated": true,
+                                "value": 1,
+                                "branch": null,
+                                "error": null,
+                                "children": [],
+                            },
+                        ],
+                    },
+                    {
+                        "expr": "\"big\"",
+                        "evaluated": true,
+                        "value": "big",
+                        "branch": null,
+                        "error": null,
+                        "children": [],
+                    },
+                    {
+                        "expr": "\"small\"",
+                        "evaluated": false,
+                        "value": null,
+                        "branch": null,
+                        "error": null,
+                        "children": [],
+                    },
+                ],
+            })
+        );
+    }
+}
@@ -0,0 +1,289 @@
+//! C ABI for embedding the engine from other languages, enabled by the `ffi`
+//! feature. The matching header is `include/expression_engine.h`, generated
+//! with `cbindgen --config cbindgen.toml --output include/expression_engine.h`.
+//! The shared library is built with
+//! `cargo rustc --lib --release --crate-type cdylib --features ffi`.
+//!
+//! Contexts are passed in and results are returned as JSON strings. Every
+//! string returned by this module must be released with `expr_string_free`.
+//! Panics never cross the boundary: they are caught and reported as errors.
+use crate::compiler::{Compiler, Program};
+use crate::context::Context;
+use crate::define::Result;
+use crate::parse_expression;
+use crate::vm::Vm;
+use std::cell::RefCell;
+use std::ffi::{c_char, c_int, CStr, CString};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::ptr;
+
+/// A compiled expression, parsed once by `expr_compile` and run by the VM on
+/// each evaluation. It is never mutated after `expr_compile`, so one handle
+/// may be evaluated from several threads at the same time.
+pub struct ExprHandle {
+    program: Program,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(msg: String) {
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(to_c_string(msg)));
+}
+
+fn to_c_string(s: String) -> CString {
+    CString::new(s.replace('\0', "\\0")).unwrap_or_default()
+}
+
+fn panic_message(err: Box<dyn std::any::Any + Send>) -> String {
+    let msg = match err.downcast_ref::<&str>() {
+        Some(msg) => msg.to_string(),
+        None => err.downcast_ref::<String>().cloned().unwrap_or_default(),
+    };
+    format!("panic: {}", msg)
+}
+
+unsafe fn read_str<'a>(s: *const c_char, name: &str) -> std::result::Result<&'a str, String> {
+    if s.is_null() {
+        return Err(format!("{} is null", name));
+    }
+    CStr::from_ptr(s)
+        .to_str()
+        .map_err(|_| format!("{} is not valid utf-8", name))
+}
+
+fn eval_json(handle: &ExprHandle, ctx_json: Option<&str>) -> Result<String> {
+    let mut ctx = Context::new();
+    if let Some(json) = ctx_json {
+        if !json.trim().is_empty() {
+            ctx.set_variables_from_json(json)?;
+        }
+    }
+    Ok(Vm::run(&handle.program, &mut ctx)?.to_json())
+}
+
+/// Compiles `expr`, returning a handle to release with `expr_free`, or null
+/// on failure, in which case `expr_last_error` describes the problem.
+///
+/// # Safety
+///
+/// `expr` must be null or a valid nul-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn expr_compile(expr: *const c_char) -> *mut ExprHandle {
+    let ans = catch_unwind(|| {
+        let ast = parse_expression(read_str(expr, "expr")?).map_err(|err| err.to_string())?;
+        let program = Compiler::compile(&ast).map_err(|err| err.to_string())?;
+        Ok(ExprHandle { program })
+    });
+    match ans {
+        Ok(Ok(handle)) => Box::into_raw(Box::new(handle)),
+        Ok(Err(msg)) => {
+            set_last_error(msg);
+            ptr::null_mut()
+        }
+        Err(err) => {
+            set_last_error(panic_message(err));
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Evaluates a compiled expression against a context given as a JSON object
+/// (`ctx_json` may be null for an empty context). Returns 0 and stores the
+/// JSON result in `out_json` on success; returns -1 and stores the message in
+/// `out_err` on failure. Either output pointer may be null if not wanted.
+///
+/// # Safety
+///
+/// `handle` must come from `expr_compile` and not have been freed; `ctx_json`
+/// must be null or a valid nul-terminated string; `out_json` and `out_err`
+/// must be null or valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn expr_eval_json(
+    handle: *const ExprHandle,
+    ctx_json: *const c_char,
+    out_json: *mut *mut c_char,
+    out_err: *mut *mut c_char,
+) -> c_int {
+    let ans = catch_unwind(AssertUnwindSafe(|| {
+        let handle = handle
+            .as_ref()
+            .ok_or_else(|| "handle is null".to_string())?;
+        let ctx_json = if ctx_json.is_null() {
+            None
+        } else {
+            Some(read_str(ctx_json, "ctx_json")?)
+        };
+        eval_json(handle, ctx_json).map_err(|err| err.to_string())
+    }));
+    let ans = match ans {
+        Ok(ans) => ans,
+        Err(err) => Err(panic_message(err)),
+    };
+    match ans {
+        Ok(json) => {
+            if !out_json.is_null() {
+                *out_json = to_c_string(json).into_raw();
+            }
+            0
+        }
+        Err(msg) => {
+            set_last_error(msg.clone());
+            if !out_err.is_null() {
+                *out_err = to_c_string(msg).into_raw();
+            }
+            -1
+        }
+    }
+}
+
+/// Releases a handle returned by `expr_compile`. Null is ignored.
+///
+/// # Safety
+///
+/// `handle` must be null or come from `expr_compile`, and must not be used
+/// after this call.
+#[no_mangle]
+pub unsafe extern "C" fn expr_free(handle: *mut ExprHandle) {
+    if !handle.is_null() {
+        drop(Box::from_raw(handle));
+    }
+}
+
+/// Releases a string returned through `out_json` or `out_err`. Null is ignored.
+///
+/// # Safety
+///
+/// `s` must be null or a string handed out by this library, and must not be
+/// used after this call.
+#[no_mangle]
+pub unsafe extern "C" fn expr_string_free(s: *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s));
+    }
+}
+
+/// Returns the last error raised on the calling thread, or null if there was
+/// none. The string is owned by the library and stays valid until the next
+/// failing call on the same thread.
+#[no_mangle]
+pub extern "C" fn expr_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| match &*last.borrow() {
+        Some(msg) => msg.as_ptr(),
+        None => ptr::null(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+
+    unsafe fn eval(handle: *const ExprHandle, ctx: &str) -> std::result::Result<String, String> {
+        let ctx = CString::new(ctx).unwrap();
+        let (mut out, mut err) = (ptr::null_mut(), ptr::null_mut());
+        let code = expr_eval_json(handle, ctx.as_ptr(), &mut out, &mut err);
+        let take = |s: *mut c_char| {
+            let ans = CStr::from_ptr(s).to_str().unwrap().to_string();
+            expr_string_free(s);
+            ans
+        };
+        if code == 0 {
+            Ok(take(out))
+        } else {
+            Err(take(err))
+        }
+    }
+
+    #[test]
+    fn test_compile_and_eval() {
+        unsafe {
+            let expr = CString::new("a + b * 2").unwrap();
+            let handle = expr_compile(expr.as_ptr());
+            assert!(!handle.is_null());
+            assert_eq!(eval(handle, "{\"a\": 1, \"b\": 2.5}").unwrap(), "6.0");
+            assert!(eval(handle, "{\"a\": true, \"b\": 1}").is_err());
+            assert!(eval(handle, "[]").is_err());
+            let (mut out, mut err) = (ptr::null_mut(), ptr::null_mut());
+            assert_eq!(expr_eval_json(handle, ptr::null(), &mut out, &mut err), -1);
+            expr_string_free(err);
+            expr_free(handle);
+        }
+    }
+
+    #[test]
+    fn test_compile_error() {
+        unsafe {
+            let expr = CString::new("(1 + ").unwrap();
+            assert!(expr_compile(expr.as_ptr()).is_null());
+            let msg = CStr::from_ptr(expr_last_error()).to_str().unwrap();
+            assert!(!msg.is_empty());
+            assert!(expr_compile(ptr::null()).is_null());
+            let msg = CStr::from_ptr(expr_last_error()).to_str().unwrap();
+            assert_eq!(msg, "expr is null");
+        }
+    }
+
+    // Compiling and running don't recurse, so a long chain can't overflow
+    // the stack, which `catch_unwind` couldn't stop.
+    #[test]
+    fn test_compile_deep() {
+        let input = vec!["1"; 100_000].join("+");
+        unsafe {
+            let expr = CString::new(input).unwrap();
+            let handle = expr_compile(expr.as_ptr());
+            assert!(!handle.is_null());
+            assert_eq!(eval(handle, "{}").unwrap(), "100000");
+            expr_free(handle);
+        }
+    }
+
+    #[test]
+    fn test_null_handle() {
+        unsafe {
+            let mut err = ptr::null_mut();
+            assert_eq!(
+                expr_eval_json(ptr::null(), ptr::null(), ptr::null_mut(), &mut err),
+                -1
+            );
+            assert_eq!(CStr::from_ptr(err).to_str().unwrap(), "handle is null");
+            expr_string_free(err);
+            expr_free(ptr::null_mut());
+        }
+    }
+
+    #[test]
+    fn test_panic_is_caught() {
+        crate::register_function("ffi_panic", Arc::new(|_| panic!("boom")));
+        unsafe {
+            let expr = CString::new("ffi_panic()").unwrap();
+            let handle = expr_compile(expr.as_ptr());
+            assert_eq!(eval(handle, "{}").unwrap_err(), "panic: boom");
+            expr_free(handle);
+        }
+    }
+
+    #[test]
+    fn test_concurrent_eval() {
+        struct Shared(*mut ExprHandle);
+        unsafe impl Send for Shared {}
+        unsafe impl Sync for Shared {}
+
+        let expr = CString::new("x * x").unwrap();
+        let handle = Arc::new(Shared(unsafe { expr_compile(expr.as_ptr()) }));
+        let workers: Vec<_> = (0..4)
+            .map(|i| {
+                let handle = handle.clone();
+                thread::spawn(move || unsafe {
+                    let ctx = format!("{{\"x\": {}}}", i);
+                    eval(handle.0, &ctx).unwrap()
+                })
+            })
+            .collect();
+        let ans: Vec<_> = workers.into_iter().map(|w| w.join().unwrap()).collect();
+        assert_eq!(ans, vec!["0", "1", "4", "9"]);
+        unsafe { expr_free(handle.0) };
+    }
+}
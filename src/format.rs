@@ -0,0 +1,460 @@
+use crate::define::Result;
+use crate::error::Error;
+use crate::value::Value;
+use alloc::string::{String, ToString};
+use core::str::FromStr;
+use rust_decimal::{Decimal, RoundingStrategy};
+
+/// How a number is rounded when it has more decimal places than wanted.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Rounding {
+    /// Halves round away from zero: `2.5` becomes `3` and `-2.5` becomes `-3`.
+    #[default]
+    HalfUp,
+    /// Halves round toward zero: `2.5` becomes `2` and `-2.5` becomes `-2`.
+    HalfDown,
+    /// Halves round to the even neighbour: `2.5` becomes `2`, `3.5` becomes `4`.
+    HalfEven,
+    /// Always away from zero.
+    Up,
+    /// Always toward zero, i.e. truncation.
+    Down,
+    /// Always toward positive infinity.
+    Ceiling,
+    /// Always toward negative infinity.
+    Floor,
+}
+
+impl Rounding {
+    fn strategy(self) -> RoundingStrategy {
+        use Rounding::*;
+        match self {
+            HalfUp => RoundingStrategy::MidpointAwayFromZero,
+            HalfDown => RoundingStrategy::MidpointTowardZero,
+            HalfEven => RoundingStrategy::MidpointNearestEven,
+            Up => RoundingStrategy::AwayFromZero,
+            Down => RoundingStrategy::ToZero,
+            Ceiling => RoundingStrategy::ToPositiveInfinity,
+            Floor => RoundingStrategy::ToNegativeInfinity,
+        }
+    }
+}
+
+impl FromStr for Rounding {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        use Rounding::*;
+        match s {
+            "half_up" => Ok(HalfUp),
+            "half_down" => Ok(HalfDown),
+            "half_even" => Ok(HalfEven),
+            "up" => Ok(Up),
+            "down" => Ok(Down),
+            "ceiling" => Ok(Ceiling),
+            "floor" => Ok(Floor),
+            _ => Err(Error::ParamInvalid()),
+        }
+    }
+}
+
+/// Controls how [`Value::to_formatted_string`] renders a number.
+#[derive(Clone, PartialEq, Debug)]
+pub struct NumberFormat {
+    /// Exact number of decimal places, padding with zeros if needed. `None`
+    /// keeps every significant digit and drops trailing zeros.
+    pub decimal_places: Option<u32>,
+    pub thousands_separator: Option<char>,
+    pub decimal_separator: char,
+    pub rounding: Rounding,
+}
+
+impl Default for NumberFormat {
+    fn default() -> Self {
+        Self {
+            decimal_places: None,
+            thousands_separator: None,
+            decimal_separator: '.',
+            rounding: Rounding::default(),
+        }
+    }
+}
+
+impl NumberFormat {
+    /// Builds a format from a spreadsheet-style pattern such as `#,##0.00`:
+    /// a `,` in the integer part turns on `,` thousands separators and the
+    /// number of `0`s after the `.` sets the decimal places.
+    pub fn from_pattern(pattern: &str) -> Result<Self> {
+        let (integer, fraction) = match pattern.split_once('.') {
+            Some((integer, fraction)) => (integer, Some(fraction)),
+            None => (pattern, None),
+        };
+        if integer.is_empty() || !integer.chars().all(|ch| matches!(ch, '#' | '0' | ',')) {
+            return Err(Error::ParamInvalid());
+        }
+        let decimal_places = match fraction {
+            Some(fraction) if fraction.chars().all(|ch| ch == '0') => Some(fraction.len() as u32),
+            Some(_) => return Err(Error::ParamInvalid()),
+            None => Some(0),
+        };
+        Ok(Self {
+            decimal_places,
+            thousands_separator: integer.contains(',').then_some(','),
+            ..Self::default()
+        })
+    }
+
+    pub fn format(&self, value: Decimal) -> String {
+        let value = match self.decimal_places {
+            Some(dp) => {
+                let mut value = round(value, dp, self.rounding);
+                value.rescale(dp);
+                value
+            }
+            None => value.normalize(),
+        };
+        let digits = value.abs().to_string();
+        let (integer, fraction) = match digits.split_once('.') {
+            Some((integer, fraction)) => (integer, Some(fraction)),
+            None => (digits.as_str(), None),
+        };
+        let mut ans = String::new();
+        if value.is_sign_negative() && !value.is_zero() {
+            ans.push('-');
+        }
+        for (i, ch) in integer.chars().enumerate() {
+            if i > 0 && (integer.len() - i) % 3 == 0 {
+                if let Some(separator) = self.thousands_separator {
+                    ans.push(separator);
+                }
+            }
+            ans.push(ch);
+        }
+        // A number too large for `dp` more digits keeps fewer of them after
+        // `rescale`, so the rest are padded with zeros.
+        let fraction = fraction.unwrap_or_default();
+        let width = self.decimal_places.map_or(fraction.len(), |dp| dp as usize);
+        if width > 0 {
+            ans.push(self.decimal_separator);
+            ans.push_str(fraction);
+            ans.extend(core::iter::repeat_n('0', width - fraction.len()));
+        }
+        ans
+    }
+
+    /// Formats `value` times 100 followed by `%`, so `0.125` with one
+    /// decimal place is `12.5%`. Fails with [`Error::InvalidFormat`] when
+    /// the percentage is too large for a `Decimal`.
+    pub fn format_percent(&self, value: Decimal) -> Result<String> {
+        let percent = value.checked_mul(Decimal::ONE_HUNDRED).ok_or_else(|| {
+            Error::InvalidFormat(format!("{} is too large for a percentage", value))
+        })?;
+        Ok(format!("{}%", self.format(percent)))
+    }
+}
+
+pub fn round(value: Decimal, dp: u32, rounding: Rounding) -> Decimal {
+    value.round_dp_with_strategy(dp, rounding.strategy())
+}
+
+impl Value {
+    pub fn to_formatted_string(&self, format: &NumberFormat) -> Result<String> {
+        match self {
+            Self::Number(val) => Ok(format.format(*val)),
+            _ => Err(Error::ShouldBeNumber()),
+        }
+    }
+}
+
+/// Fills in a `format()` template. `{}` takes the next param, `{i}` takes
+/// `params[i]` and a `:.n` spec, as in `{:.2}` or `{1:.2}`, rounds a number
+/// to `n` decimal places. `{{` and `}}` are literal braces. Every param has
+/// to be used by some placeholder.
+#[cfg_attr(not(feature = "builtin-string"), allow(dead_code))]
+pub fn format_template(template: &str, params: &[Value]) -> Result<String> {
+    let mut ans = String::with_capacity(template.len());
+    let mut used = vec![false; params.len()];
+    let mut next = 0;
+    let mut chars = template.chars();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '{' => {
+                let mut placeholder = String::new();
+                loop {
+                    match chars.next() {
+                        Some('{') if placeholder.is_empty() => {
+                            ans.push('{');
+                            break;
+                        }
+                        Some('}') => {
+                            let (index, spec) = match placeholder.split_once(':') {
+                                Some((index, spec)) => (index, Some(spec)),
+                                None => (placeholder.as_str(), None),
+                            };
+                            let index = if index.is_empty() {
+                                next += 1;
+                                next - 1
+                            } else {
+                                index.parse().map_err(|_| {
+                                    Error::InvalidFormat(format!(
+                                        "invalid placeholder {{{}}}",
+                                        placeholder
+                                    ))
+                                })?
+                            };
+                            let value = params.get(index).ok_or_else(|| {
+                                Error::InvalidFormat(format!(
+                                    "placeholder {{{}}} refers to argument {} but only {} given",
+                                    placeholder,
+                                    index,
+                                    params.len()
+                                ))
+                            })?;
+                            used[index] = true;
+                            ans.push_str(&format_placeholder(value, spec)?);
+                            break;
+                        }
+                        Some(ch) => placeholder.push(ch),
+                        None => return Err(Error::InvalidFormat("unclosed {".to_string())),
+                    }
+                }
+            }
+            '}' => match chars.next() {
+                Some('}') => ans.push('}'),
+                _ => return Err(Error::InvalidFormat("unmatched }".to_string())),
+            },
+            ch => ans.push(ch),
+        }
+    }
+    if let Some(index) = used.iter().position(|used| !used) {
+        return Err(Error::InvalidFormat(format!(
+            "argument {} is not used by any placeholder",
+            index
+        )));
+    }
+    Ok(ans)
+}
+
+fn format_placeholder(value: &Value, spec: Option<&str>) -> Result<String> {
+    let spec = match spec {
+        Some(spec) => spec,
+        None => {
+            return Ok(match value {
+                Value::String(val) => val.clone(),
+                other => other.to_string(),
+            })
+        }
+    };
+    let decimal_places = spec
+        .strip_prefix('.')
+        .and_then(|dp| dp.parse::<u32>().ok())
+        .filter(|dp| *dp <= 28)
+        .ok_or_else(|| Error::InvalidFormat(format!("unknown format spec :{}", spec)))?;
+    match value {
+        Value::Number(val) => Ok(NumberFormat {
+            decimal_places: Some(decimal_places),
+            ..NumberFormat::default()
+        }
+        .format(*val)),
+        other => Err(Error::InvalidFormat(format!(
+            "format spec :{} needs a Number, got {}",
+            spec,
+            other.type_name()
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format_template, round, NumberFormat, Rounding};
+    use crate::value::{Map, Value};
+    use rstest::rstest;
+    use rust_decimal::Decimal;
+    use std::str::FromStr;
+
+    #[rstest]
+    #[case("2.5", Rounding::HalfUp, "3")]
+    #[case("-2.5", Rounding::HalfUp, "-3")]
+    #[case("2.5", Rounding::HalfDown, "2")]
+    #[case("-2.5", Rounding::HalfDown, "-2")]
+    #[case("2.5", Rounding::HalfEven, "2")]
+    #[case("3.5", Rounding::HalfEven, "4")]
+    #[case("-3.5", Rounding::HalfEven, "-4")]
+    #[case("2.1", Rounding::Up, "3")]
+    #[case("-2.1", Rounding::Up, "-3")]
+    #[case("2.9", Rounding::Down, "2")]
+    #[case("-2.9", Rounding::Down, "-2")]
+    #[case("-2.1", Rounding::Ceiling, "-2")]
+    #[case("-2.1", Rounding::Floor, "-3")]
+    fn test_round(#[case] input: &str, #[case] rounding: Rounding, #[case] output: &str) {
+        let value = Decimal::from_str(input).unwrap();
+        assert_eq!(round(value, 0, rounding).to_string(), output);
+    }
+
+    #[rstest]
+    #[case("1234567.891", None, None, "1234567.891")]
+    #[case("1.50", None, None, "1.5")]
+    #[case("1.5", Some(2), None, "1.50")]
+    #[case("1234567.891", Some(2), Some(','), "1,234,567.89")]
+    #[case("-1234567.895", Some(2), Some(','), "-1,234,567.90")]
+    #[case("-0.001", Some(2), None, "0.00")]
+    #[case("999", Some(0), Some(','), "999")]
+    #[case("-100000", Some(0), Some(','), "-100,000")]
+    #[case(
+        "79228162514264337593543950335",
+        Some(3),
+        None,
+        "79228162514264337593543950335.000"
+    )]
+    fn test_format(
+        #[case] input: &str,
+        #[case] decimal_places: Option<u32>,
+        #[case] thousands_separator: Option<char>,
+        #[case] output: &str,
+    ) {
+        let format = NumberFormat {
+            decimal_places,
+            thousands_separator,
+            ..NumberFormat::default()
+        };
+        let value = Value::Number(Decimal::from_str(input).unwrap());
+        assert_eq!(value.to_formatted_string(&format).unwrap(), output);
+    }
+
+    #[test]
+    fn test_format_locale_separators() {
+        let format = NumberFormat {
+            decimal_places: Some(2),
+            thousands_separator: Some('.'),
+            decimal_separator: ',',
+            rounding: Rounding::HalfEven,
+        };
+        let value = Value::Number(Decimal::from_str("-1234567.125").unwrap());
+        assert_eq!(value.to_formatted_string(&format).unwrap(), "-1.234.567,12");
+    }
+
+    #[rstest]
+    #[case("0.125", 1, "12.5%")]
+    #[case("0.125", 0, "13%")]
+    #[case("-0.125", 0, "-13%")]
+    #[case("1.5", 2, "150.00%")]
+    #[case("-0.00001", 2, "0.00%")]
+    #[case("792281625142643375935439503.35", 0, "79228162514264337593543950335%")]
+    fn test_format_percent(#[case] input: &str, #[case] decimal_places: u32, #[case] output: &str) {
+        let format = NumberFormat {
+            decimal_places: Some(decimal_places),
+            ..NumberFormat::default()
+        };
+        let value = Decimal::from_str(input).unwrap();
+        assert_eq!(format.format_percent(value).unwrap(), output);
+        let err = format.format_percent(Decimal::MAX).unwrap_err();
+        assert!(
+            matches!(err, crate::error::Error::InvalidFormat(_)),
+            "{:?}",
+            err
+        );
+    }
+
+    #[rstest]
+    #[case(Value::from(4.35 * 100.0), "435")]
+    #[case(Value::from(0.1 + 0.2), "0.3")]
+    #[case(Value::from(-1.0 / 3.0), "-0.333333333333333")]
+    #[case(Value::from(0.1f32 + 0.2f32), "0.3")]
+    fn test_from_float(#[case] input: Value, #[case] output: &str) {
+        assert_eq!(input.decimal().unwrap().to_string(), output);
+    }
+
+    #[rstest]
+    #[case(f64::NAN)]
+    #[case(f64::INFINITY)]
+    #[case(f64::NEG_INFINITY)]
+    #[case(1e30)]
+    fn test_try_from_f64_error(#[case] input: f64) {
+        let err = Value::try_from_f64(input).unwrap_err();
+        assert_eq!(err.code(), "E_TYPE_UNREPRESENTABLE_FLOAT");
+        assert_eq!(Value::from(input), Value::from(0));
+    }
+
+    #[rstest]
+    #[case(4.35 * 100.0, 435.0)]
+    #[case(0.1 + 0.2, 0.3)]
+    #[case(-1.0 / 3.0, -0.333333333333333)]
+    #[case(1e-20, 1e-20)]
+    #[case(123456789.123456, 123456789.123456)]
+    fn test_f64_round_trip(#[case] input: f64, #[case] output: f64) {
+        let value = Value::try_from_f64(input).unwrap();
+        assert_eq!(value, Value::from(input));
+        assert_eq!(value.to_f64().unwrap(), output);
+    }
+
+    #[test]
+    fn test_to_f64() {
+        let value = Value::Number(Decimal::from_str("0.1234567890123456789").unwrap());
+        assert_eq!(value.to_f64().unwrap(), 0.12345678901234568);
+        assert!(Value::from("1").to_f64().is_err());
+    }
+
+    #[rstest]
+    #[case("#,##0.00", Some(2), Some(','))]
+    #[case("0.0", Some(1), None)]
+    #[case("#,###", Some(0), Some(','))]
+    fn test_from_pattern(
+        #[case] pattern: &str,
+        #[case] decimal_places: Option<u32>,
+        #[case] thousands_separator: Option<char>,
+    ) {
+        let format = NumberFormat::from_pattern(pattern).unwrap();
+        assert_eq!(format.decimal_places, decimal_places);
+        assert_eq!(format.thousands_separator, thousands_separator);
+    }
+
+    #[rstest]
+    #[case("")]
+    #[case("0.0#")]
+    #[case("abc")]
+    fn test_from_pattern_error(#[case] pattern: &str) {
+        assert!(NumberFormat::from_pattern(pattern).is_err());
+    }
+
+    #[rstest]
+    #[case("Order {} total {:.2}", vec![7.into(), Value::from(12.5)], "Order 7 total 12.50")]
+    #[case("{1} before {0}", vec!["a".into(), "b".into()], "b before a")]
+    #[case("{0}{0}{}", vec!["x".into()], "xxx")]
+    #[case("{} and {0:.1}", vec![Value::from(2.25)], "2.25 and 2.3")]
+    #[case("{:.0}", vec![Value::from(-2.5)], "-3")]
+    #[case("no placeholders", vec![], "no placeholders")]
+    #[case("{{}}", vec![], "{}")]
+    #[case("{{{}}}", vec![1.into()], "{1}")]
+    #[case("{{0}} is {0}", vec![1.into()], "{0} is 1")]
+    #[case("}}{{", vec![], "}{")]
+    #[case("{}", vec![vec![1.into(), "a".into(), vec![true.into()].into()].into()], "[1, 'a', [true]]")]
+    #[case("{}", vec![Value::Map(Map::from_iter([("k".into(), "it's".into()), (1.into(), Value::None)]))], "{'k': \"it's\", 1: null}")]
+    #[case("{}", vec![Value::List(vec!["it's \"x\"".into()])], "['''it's \"x\"''']")]
+    #[case("{} {}", vec![true.into(), Value::None], "true null")]
+    fn test_format_template(
+        #[case] template: &str,
+        #[case] params: Vec<Value>,
+        #[case] output: &str,
+    ) {
+        assert_eq!(format_template(template, &params).unwrap(), output);
+    }
+
+    #[rstest]
+    #[case("{} {}", vec![1.into()], "placeholder {} refers to argument 1 but only 1 given")]
+    #[case("{2}", vec![1.into()], "placeholder {2} refers to argument 2 but only 1 given")]
+    #[case("{}", vec![1.into(), 2.into()], "argument 1 is not used by any placeholder")]
+    #[case("{:x}", vec![1.into()], "unknown format spec :x")]
+    #[case("{:.}", vec![1.into()], "unknown format spec :.")]
+    #[case("{:.2}", vec!["a".into()], "format spec :.2 needs a Number, got String")]
+    #[case("{a}", vec![1.into()], "invalid placeholder {a}")]
+    #[case("{", vec![], "unclosed {")]
+    #[case("a } b", vec![], "unmatched }")]
+    fn test_format_template_error(
+        #[case] template: &str,
+        #[case] params: Vec<Value>,
+        #[case] message: &str,
+    ) {
+        let err = format_template(template, &params).unwrap_err();
+        assert_eq!(err.to_string(), format!("invalid format: {}", message));
+    }
+}
@@ -1,86 +1,1319 @@
+use crate::compat::{HashMap, HashSet, Mutex, OnceCell};
+use crate::context::Context;
 use crate::define::Result;
 use crate::error::Error;
+use crate::suggest::suggestions;
 use crate::value::Value;
-use once_cell::sync::OnceCell;
-use rust_decimal::Decimal;
-use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::fmt;
+use core::future::Future;
+use core::pin::Pin;
 
 pub type InnerFunction = dyn Fn(Vec<Value>) -> Result<Value> + Send + Sync + 'static;
 
+/// An inner function that can also read the context it's evaluated against.
+pub type ContextFunction = dyn Fn(&Context, Vec<Value>) -> Result<Value> + Send + Sync + 'static;
+
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// A function that only [`crate::ExprAST::exec_async`] can call, which
+/// awaits it.
+pub type AsyncFunction =
+    dyn Fn(Vec<Value>) -> BoxFuture<'static, Result<Value>> + Send + Sync + 'static;
+
+/// How many params a function takes.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Arity {
+    Exact(usize),
+    /// Between the two, inclusive. The params past the first are optional.
+    Range(usize, usize),
+    /// At least that many, the last param repeats.
+    Variadic(usize),
+}
+
+impl Arity {
+    /// Whether a call with `count` params fits.
+    pub fn accepts(&self, count: usize) -> bool {
+        match *self {
+            Arity::Exact(n) => count == n,
+            Arity::Range(min, max) => (min..=max).contains(&count),
+            Arity::Variadic(min) => count >= min,
+        }
+    }
+
+    /// The most params a call may have, `None` for a variadic function.
+    pub fn max(&self) -> Option<usize> {
+        match *self {
+            Arity::Exact(n) | Arity::Range(_, n) => Some(n),
+            Arity::Variadic(_) => None,
+        }
+    }
+}
+
+#[cfg(not(tarpaulin_include))]
+impl fmt::Display for Arity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Arity::Exact(n) => write!(f, "{}", n),
+            Arity::Range(min, max) => write!(f, "{} to {}", min, max),
+            Arity::Variadic(min) => write!(f, "at least {}", min),
+        }
+    }
+}
+
+/// What [`crate::list_with_metadata`] and the `help` built-in know about a
+/// function.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct FunctionMetadata {
+    pub name: String,
+    pub arity: Arity,
+    /// The name and type of each param. Types are a [`Value::type_name`] or
+    /// `Any`.
+    pub params: Vec<(String, String)>,
+    pub description: String,
+    /// Whether the result only depends on the params, see
+    /// [`crate::register_deterministic_function`].
+    pub deterministic: bool,
+    /// What to call instead, if the function is deprecated. Calls of it
+    /// still work, but raise a `W_DEPRECATED_FUNCTION` warning, see
+    /// [`crate::Warning`].
+    pub deprecated: Option<String>,
+}
+
+impl FunctionMetadata {
+    pub fn new(name: &str, arity: Arity, params: &[(&str, &str)], description: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            arity,
+            params: params
+                .iter()
+                .map(|(name, ty)| (name.to_string(), ty.to_string()))
+                .collect(),
+            description: description.to_string(),
+            deterministic: false,
+            deprecated: None,
+        }
+    }
+
+    pub fn deterministic(mut self) -> Self {
+        self.deterministic = true;
+        self
+    }
+
+    /// Marks the function deprecated, with a note like `use formatNumber`.
+    pub fn deprecated(mut self, note: &str) -> Self {
+        self.deprecated = Some(note.to_string());
+        self
+    }
+
+    /// The call with its params, e.g. `round(x: Number, dp?: Number)` or
+    /// `max(...values: Number)`, and `f(...)` for a variadic function whose
+    /// params aren't named.
+    pub fn signature(&self) -> String {
+        let (required, variadic) = match self.arity {
+            Arity::Exact(n) => (n, false),
+            Arity::Range(min, _) => (min, false),
+            Arity::Variadic(min) => (min, true),
+        };
+        if variadic && self.params.is_empty() {
+            return format!("{}(...)", self.name);
+        }
+        let params: Vec<String> = self
+            .params
+            .iter()
+            .enumerate()
+            .map(|(i, (name, ty))| {
+                let spread = if variadic && i + 1 == self.params.len() {
+                    "..."
+                } else {
+                    ""
+                };
+                let optional = if i >= required && spread.is_empty() {
+                    "?"
+                } else {
+                    ""
+                };
+                format!("{}{}{}: {}", spread, name, optional, ty)
+            })
+            .collect();
+        format!("{}({})", self.name, params.join(", "))
+    }
+
+    /// What comes before the last dot of the name, e.g. `math` for
+    /// `math.sqrt`, or `None` for a name without one.
+    pub fn namespace(&self) -> Option<&str> {
+        self.name.rsplit_once('.').map(|(namespace, _)| namespace)
+    }
+}
+
+// Every inner function is stored as a `ContextFunction`, plain ones simply
+// ignore the context.
 pub struct InnerFunctionManager {
-    pub store: &'static Mutex<HashMap<String, Arc<InnerFunction>>>,
+    pub store: &'static Mutex<HashMap<String, Arc<ContextFunction>>>,
+    // Names registered with `register_deterministic`, whose calls
+    // `EvalOptions::memoize` may cache.
+    deterministic: &'static Mutex<HashSet<String>>,
+    metadata: &'static Mutex<HashMap<String, FunctionMetadata>>,
+    // Other names functions are called by, like `round` for `math.round`.
+    aliases: &'static Mutex<HashMap<String, String>>,
+    // Where `register_with_metadata` puts functions while the built-ins of
+    // a namespace are registered.
+    namespace: Option<&'static str>,
 }
 
 impl InnerFunctionManager {
     pub fn new() -> Self {
-        static STORE: OnceCell<Mutex<HashMap<String, Arc<InnerFunction>>>> = OnceCell::new();
+        static STORE: OnceCell<Mutex<HashMap<String, Arc<ContextFunction>>>> = OnceCell::new();
+        static DETERMINISTIC: OnceCell<Mutex<HashSet<String>>> = OnceCell::new();
+        static METADATA: OnceCell<Mutex<HashMap<String, FunctionMetadata>>> = OnceCell::new();
+        static ALIASES: OnceCell<Mutex<HashMap<String, String>>> = OnceCell::new();
+        static INITED: OnceCell<()> = OnceCell::new();
         let store = STORE.get_or_init(|| Mutex::new(HashMap::new()));
-        InnerFunctionManager { store: store }
+        let deterministic = DETERMINISTIC.get_or_init(|| Mutex::new(HashSet::new()));
+        let metadata = METADATA.get_or_init(|| Mutex::new(HashMap::new()));
+        let aliases = ALIASES.get_or_init(|| Mutex::new(HashMap::new()));
+        let mut manager = InnerFunctionManager {
+            store,
+            deterministic,
+            metadata,
+            aliases,
+            namespace: None,
+        };
+        // The built-ins are there from the first use on, so a function can't
+        // be looked up before they are and a host's override of one can't be
+        // replaced by it later.
+        INITED.get_or_init(|| manager.init());
+        manager
+    }
+
+    // Registers exactly the built-ins whose feature is enabled.
+    fn init(&mut self) {
+        self.init_help();
+        #[cfg(feature = "builtin-math")]
+        self.namespaced("math", |manager| {
+            manager.add_library(&MathLibrary, CollisionPolicy::Override);
+            manager.init_random();
+        });
+        #[cfg(feature = "builtin-string")]
+        self.namespaced("str", |manager| {
+            manager.init_format();
+            manager.init_strings();
+        });
+        #[cfg(feature = "builtin-string")]
+        self.namespaced("units", Self::init_units);
+        #[cfg(feature = "builtin-logic")]
+        self.add_library(&LogicLibrary, CollisionPolicy::Override);
+        #[cfg(feature = "builtin-list")]
+        self.namespaced("list", Self::init_list_constructors);
+        #[cfg(feature = "builtin-env")]
+        self.init_providers();
+        #[cfg(feature = "codecs")]
+        self.namespaced("codec", Self::init_codecs);
+        #[cfg(feature = "serde_json")]
+        self.init_json();
+    }
+
+    // Registers the built-ins `init` adds under `namespace`, e.g. `min` as
+    // `math.min`, keeping the bare names as aliases.
+    #[cfg_attr(
+        not(any(
+            feature = "builtin-math",
+            feature = "builtin-string",
+            feature = "builtin-list",
+            feature = "builtin-env",
+            feature = "codecs"
+        )),
+        allow(dead_code)
+    )]
+    pub(crate) fn namespaced(&mut self, namespace: &'static str, init: impl FnOnce(&mut Self)) {
+        self.namespace = Some(namespace);
+        init(self);
+        self.namespace = None;
     }
 
-    pub fn init(&mut self) {
-        self.register(
-            "min",
+    // Reads the metadata the other built-ins register with.
+    fn init_help(&mut self) {
+        self.builtin(
+            FunctionMetadata::new(
+                "help",
+                Arity::Range(0, 1),
+                &[("name", "String")],
+                "The signature of every function, or the signature and description of the one called name.",
+            ),
+            Arc::new(|params| {
+                let manager = InnerFunctionManager::new();
+                let name = match <[Value; 1]>::try_from(params) {
+                    Ok([name]) => name.string()?,
+                    Err(params) if params.is_empty() => {
+                        let signatures = manager
+                            .list_with_metadata()
+                            .iter()
+                            .map(|metadata| Value::String(metadata.signature()))
+                            .collect();
+                        return Ok(Value::List(signatures));
+                    }
+                    Err(_) => return Err(Error::ParamInvalid()),
+                };
+                if manager.get(&name).is_err() {
+                    let suggestions = suggestions(&name, manager.names().iter().map(String::as_str));
+                    return Err(Error::UnknownFunction(name, suggestions, None));
+                }
+                let metadata = manager.metadata(&name);
+                if metadata.description.is_empty() {
+                    return Ok(Value::String(metadata.signature()));
+                }
+                Ok(Value::String(format!(
+                    "{}\n{}",
+                    metadata.signature(),
+                    metadata.description
+                )))
+            }),
+        );
+    }
+
+    #[cfg(feature = "builtin-string")]
+    fn init_format(&mut self) {
+        use crate::format::{format_template, NumberFormat};
+        self.builtin(
+            FunctionMetadata::new(
+                "format_number",
+                Arity::Range(2, 3),
+                &[("x", "Number"), ("pattern", "String"), ("mode", "String")],
+                "Formats x with a spreadsheet-style pattern such as '#,##0.00', rounding with mode like round does.",
+            ),
+            Arc::new(|params| {
+                if params.len() < 2 || params.len() > 3 {
+                    return Err(Error::ParamInvalid());
+                }
+                let mut params = params.into_iter();
+                let value = params.next().unwrap();
+                let mut format = NumberFormat::from_pattern(&params.next().unwrap().string()?)?;
+                format.rounding = rounding(params.next())?;
+                Ok(Value::String(value.to_formatted_string(&format)?))
+            }),
+        );
+
+        self.builtin(
+            FunctionMetadata::new(
+                "formatNumber",
+                Arity::Range(2, 4),
+                &[
+                    ("x", "Number"),
+                    ("dp", "Number"),
+                    ("decimal", "String"),
+                    ("thousands", "String"),
+                ],
+                "Formats x with exactly dp decimal places, rounding half up. The decimal separator is '.' and there's no thousands separator unless given, e.g. formatNumber(x, 2, ',', '.').",
+            ),
+            Arc::new(|params| {
+                if params.len() < 2 || params.len() > 4 {
+                    return Err(Error::ParamInvalid());
+                }
+                let mut params = params.into_iter();
+                let value = params.next().unwrap().decimal()?;
+                let mut format = NumberFormat {
+                    decimal_places: Some(decimal_places(params.next().unwrap())?),
+                    ..NumberFormat::default()
+                };
+                if let Some(decimal) = params.next() {
+                    format.decimal_separator = separator(decimal)?.ok_or(Error::ParamInvalid())?;
+                }
+                if let Some(thousands) = params.next() {
+                    format.thousands_separator = separator(thousands)?;
+                }
+                if format.thousands_separator == Some(format.decimal_separator) {
+                    return Err(Error::ParamInvalid());
+                }
+                Ok(Value::String(format.format(value)))
+            }),
+        );
+
+        self.builtin(
+            FunctionMetadata::new(
+                "formatPercent",
+                Arity::Exact(2),
+                &[("x", "Number"), ("dp", "Number")],
+                "Formats x as a percentage with exactly dp decimal places, rounding half up, so formatPercent(0.125, 1) is '12.5%'.",
+            ),
+            Arc::new(|params| {
+                let [value, dp] = <[Value; 2]>::try_from(params).map_err(|_| Error::ParamInvalid())?;
+                let format = NumberFormat {
+                    decimal_places: Some(decimal_places(dp)?),
+                    ..NumberFormat::default()
+                };
+                Ok(Value::String(format.format_percent(value.decimal()?)?))
+            }),
+        );
+
+        self.builtin(
+            FunctionMetadata::new(
+                "format",
+                Arity::Variadic(1),
+                &[("template", "String"), ("args", "Any")],
+                "Fills the {} placeholders of template with args, {0} by index and {:.2} rounded to two places.",
+            ),
             Arc::new(|params| {
-                let mut min = None;
-                for param in params.into_iter() {
-                    let num = param.decimal()?;
-                    if min.is_none() || num < min.unwrap() {
-                        min = Some(num);
+                let mut params = params.into_iter();
+                let template = params.next().ok_or(Error::ParamInvalid())?.string()?;
+                Ok(Value::String(format_template(
+                    &template,
+                    &params.collect::<Vec<_>>(),
+                )?))
+            }),
+        );
+    }
+
+    // Strings are handled as chars, i.e. Unicode scalar values, rather than
+    // graphemes.
+    #[cfg(feature = "builtin-string")]
+    fn init_strings(&mut self) {
+        for (name, side) in [("padLeft", "start"), ("padRight", "end")] {
+            let description = format!(
+                "s padded at the {} with ch, a space by default, to n chars. Longer strings are kept as they are.",
+                side
+            );
+            self.register_with_metadata(
+                FunctionMetadata::new(
+                    name,
+                    Arity::Range(2, 3),
+                    &[("s", "String"), ("n", "Number"), ("ch", "String")],
+                    &description,
+                )
+                .deterministic(),
+                Arc::new(move |ctx, params| {
+                    if params.len() < 2 || params.len() > 3 {
+                        return Err(Error::ParamInvalid());
+                    }
+                    let mut params = params.into_iter();
+                    let s = params.next().unwrap().string()?;
+                    let n = count(params.next().unwrap())?;
+                    let ch = match params.next() {
+                        Some(ch) => separator(ch)?.ok_or(Error::ParamInvalid())?,
+                        None => ' ',
+                    };
+                    let padding = n.saturating_sub(s.chars().count());
+                    if padding == 0 {
+                        return Ok(Value::String(s));
                     }
+                    let bytes = padding.checked_mul(ch.len_utf8());
+                    check_new_string(
+                        ctx,
+                        bytes.and_then(|bytes| bytes.checked_add(s.len())),
+                        || n,
+                    )?;
+                    let padding: String = core::iter::repeat_n(ch, padding).collect();
+                    Ok(Value::String(match name {
+                        "padLeft" => padding + &s,
+                        _ => s + &padding,
+                    }))
+                }),
+            );
+        }
+
+        for (name, verb, operator) in [
+            ("startsWith", "starts", "beginWith"),
+            ("endsWith", "ends", "endWith"),
+        ] {
+            let description = format!(
+                "Whether s {} with affix, like s {} affix. With ignore_case true both are lowercased first.",
+                verb, operator
+            );
+            self.builtin(
+                FunctionMetadata::new(
+                    name,
+                    Arity::Range(2, 3),
+                    &[
+                        ("s", "String"),
+                        ("affix", "String"),
+                        ("ignore_case", "Bool"),
+                    ],
+                    &description,
+                )
+                .deterministic(),
+                Arc::new(move |params| {
+                    if params.len() < 2 || params.len() > 3 {
+                        return Err(Error::ParamInvalid());
+                    }
+                    let mut params = params.into_iter();
+                    let mut s = params.next().unwrap().string()?;
+                    let mut affix = params.next().unwrap().string()?;
+                    if params.next().map(Value::bool).transpose()? == Some(true) {
+                        s = s.to_lowercase();
+                        affix = affix.to_lowercase();
+                    }
+                    Ok(Value::from(match name {
+                        "startsWith" => s.starts_with(&affix),
+                        _ => s.ends_with(&affix),
+                    }))
+                }),
+            );
+        }
+
+        self.builtin(
+            FunctionMetadata::new(
+                "capitalize",
+                Arity::Exact(1),
+                &[("s", "String")],
+                "s with its first char uppercased and the rest lowercased.",
+            )
+            .deterministic(),
+            Arc::new(|params| {
+                let [s] = <[Value; 1]>::try_from(params).map_err(|_| Error::ParamInvalid())?;
+                Ok(Value::String(capitalize(&s.string()?)))
+            }),
+        );
+
+        self.builtin(
+            FunctionMetadata::new(
+                "title",
+                Arity::Exact(1),
+                &[("s", "String")],
+                "s with every word capitalized, words being separated by whitespace, which is kept as it is.",
+            )
+            .deterministic(),
+            Arc::new(|params| {
+                let [s] = <[Value; 1]>::try_from(params).map_err(|_| Error::ParamInvalid())?;
+                let s = s.string()?;
+                let mut ans = String::with_capacity(s.len());
+                let mut rest = s.as_str();
+                while !rest.is_empty() {
+                    let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+                    ans.push_str(&capitalize(&rest[..end]));
+                    let space = rest[end..]
+                        .find(|ch: char| !ch.is_whitespace())
+                        .map_or(rest.len(), |len| end + len);
+                    ans.push_str(&rest[end..space]);
+                    rest = &rest[space..];
                 }
-                Ok(Value::Number(min.unwrap()))
+                Ok(Value::String(ans))
+            }),
+        );
+
+        self.builtin(
+            FunctionMetadata::new(
+                "compareIgnoreCase",
+                Arity::Exact(2),
+                &[("a", "String"), ("b", "String")],
+                "-1, 0 or 1 as a sorts before, the same as or after b once both are lowercased.",
+            )
+            .deterministic(),
+            Arc::new(|params| {
+                let [a, b] = <[Value; 2]>::try_from(params).map_err(|_| Error::ParamInvalid())?;
+                let (a, b) = (a.string()?.to_lowercase(), b.string()?.to_lowercase());
+                Ok(Value::from(a.cmp(&b) as i64))
             }),
         );
 
-        self.register(
-            "max",
+        self.builtin(
+            FunctionMetadata::new(
+                "reverse",
+                Arity::Exact(1),
+                &[("x", "Any")],
+                "The chars of a string, or the elements of a list, in reverse order.",
+            )
+            .deterministic(),
             Arc::new(|params| {
-                let mut max = None;
-                for param in params.into_iter() {
-                    let num = param.decimal()?;
-                    if max.is_none() || num > max.unwrap() {
-                        max = Some(num);
+                let [x] = <[Value; 1]>::try_from(params).map_err(|_| Error::ParamInvalid())?;
+                match x {
+                    Value::String(s) => Ok(Value::String(s.chars().rev().collect())),
+                    Value::List(mut list) => {
+                        list.reverse();
+                        Ok(Value::List(list))
                     }
+                    _ => Err(Error::ShouldBeString()),
                 }
-                Ok(Value::Number(max.unwrap()))
             }),
         );
 
-        self.register(
-            "sum",
+        // Like `len` and `reverse`, these work on Unicode scalar values, so
+        // an emoji made of several of them, like a flag, is several chars.
+        self.register_with_metadata(
+            FunctionMetadata::new(
+                "chars",
+                Arity::Exact(1),
+                &[("s", "String")],
+                "The chars of s, each as a string of one char.",
+            )
+            .deterministic(),
+            Arc::new(|ctx, params| {
+                let [s] = <[Value; 1]>::try_from(params).map_err(|_| Error::ParamInvalid())?;
+                let s = s.string()?;
+                let max = ctx.options().max_list_len;
+                if s.chars().count() > max {
+                    return Err(Error::ListTooLong(max));
+                }
+                Ok(Value::List(
+                    s.chars().map(|ch| Value::String(ch.into())).collect(),
+                ))
+            }),
+        );
+
+        self.builtin(
+            FunctionMetadata::new(
+                "ord",
+                Arity::Exact(1),
+                &[("ch", "String")],
+                "The code point of ch, a string of one char.",
+            )
+            .deterministic(),
             Arc::new(|params| {
-                let mut ans = Decimal::ZERO;
-                for param in params.into_iter() {
-                    ans += param.decimal()?;
+                let [ch] = <[Value; 1]>::try_from(params).map_err(|_| Error::ParamInvalid())?;
+                match separator(ch)? {
+                    Some(ch) => Ok(Value::from(ch as u32)),
+                    None => Err(Error::ParamInvalid()),
                 }
-                Ok(Value::Number(ans))
             }),
         );
 
-        self.register(
-            "mul",
+        self.builtin(
+            FunctionMetadata::new(
+                "chr",
+                Arity::Exact(1),
+                &[("n", "Number")],
+                "The char with the code point n, as a string of one char.",
+            )
+            .deterministic(),
             Arc::new(|params| {
-                let mut ans = Decimal::ONE;
-                for param in params.into_iter() {
-                    ans *= param.decimal()?;
+                let [n] = <[Value; 1]>::try_from(params).map_err(|_| Error::ParamInvalid())?;
+                let ch = u32::try_from(n.integer()?)
+                    .ok()
+                    .and_then(char::from_u32)
+                    .ok_or(Error::ParamInvalid())?;
+                Ok(Value::String(ch.into()))
+            }),
+        );
+    }
+
+    #[cfg(feature = "builtin-list")]
+    fn init_list_constructors(&mut self) {
+        use rust_decimal::Decimal;
+        // Strings count chars, not bytes, so `len('héllo')` is 5.
+        let len: Arc<InnerFunction> = Arc::new(|params| {
+            if params.len() != 1 {
+                return Err(Error::ParamInvalid());
+            }
+            let len = match &params[0] {
+                Value::String(val) => val.chars().count(),
+                Value::List(val) => val.len(),
+                Value::Map(val) => val.len(),
+                other => return Err(Error::NoLength(other.type_name())),
+            };
+            Ok(Value::from(len))
+        });
+        for name in ["len", "size"] {
+            self.builtin(
+                FunctionMetadata::new(
+                    name,
+                    Arity::Exact(1),
+                    &[("x", "Any")],
+                    "The number of elements of a list or map, or of chars of a string.",
+                )
+                .deterministic(),
+                len.clone(),
+            );
+        }
+
+        self.register_with_metadata(
+            FunctionMetadata::new(
+                "range",
+                Arity::Range(1, 3),
+                &[("start", "Number"), ("end", "Number"), ("step", "Number")],
+                "The numbers from start up to but excluding end, called as range(end), range(start, end) or range(start, end, step). start is 0 and step 1 by default.",
+            ),
+            Arc::new(|ctx, params| {
+                let (start, end, step) = match params.len() {
+                    1 => (Decimal::ZERO, params[0].clone().decimal()?, Decimal::ONE),
+                    2 | 3 => (
+                        params[0].clone().decimal()?,
+                        params[1].clone().decimal()?,
+                        match params.get(2) {
+                            Some(step) => step.clone().decimal()?,
+                            None => Decimal::ONE,
+                        },
+                    ),
+                    _ => return Err(Error::ParamInvalid()),
+                };
+                if step.is_zero() {
+                    return Err(Error::ParamInvalid());
+                }
+                let max = ctx.options().max_list_len;
+                // Checked in Decimal, a huge range can't fit a usize.
+                let len = ((end - start) / step).ceil().max(Decimal::ZERO);
+                if len > Decimal::from(max) {
+                    return Err(Error::ListTooLong(max));
+                }
+                let mut ans = Vec::new();
+                let mut value = start;
+                while (step.is_sign_positive() && value < end)
+                    || (step.is_sign_negative() && value > end)
+                {
+                    ans.push(Value::Number(value));
+                    value += step;
+                }
+                Ok(Value::List(ans))
+            }),
+        );
+
+        self.register_with_metadata(
+            FunctionMetadata::new(
+                "repeat",
+                Arity::Exact(2),
+                &[("x", "Any"), ("n", "Number")],
+                "A string x repeated n times, or a list of n copies of anything else.",
+            ),
+            Arc::new(|ctx, params| {
+                let [x, n] = <[Value; 2]>::try_from(params).map_err(|_| Error::ParamInvalid())?;
+                let n = count(n)?;
+                if let Value::String(s) = x {
+                    check_new_string(ctx, s.len().checked_mul(n), || s.chars().count() * n)?;
+                    return Ok(Value::String(s.repeat(n)));
+                }
+                let max = ctx.options().max_list_len;
+                if n > max {
+                    return Err(Error::ListTooLong(max));
                 }
-                Ok(Value::Number(ans))
+                Ok(Value::List(vec![x; n]))
             }),
         );
     }
 
     pub fn register(&mut self, name: &str, f: Arc<InnerFunction>) {
+        self.register_with_context(name, Arc::new(move |_, params| f(params)));
+    }
+
+    pub fn register_with_context(&mut self, name: &str, f: Arc<ContextFunction>) {
+        self.aliases.lock().unwrap().remove(name);
+        self.deterministic.lock().unwrap().remove(name);
+        self.metadata.lock().unwrap().remove(name);
         self.store.lock().unwrap().insert(name.to_string(), f);
     }
 
-    pub fn get(&self, name: &str) -> Result<Arc<InnerFunction>> {
+    /// Registers a function under `metadata.name`, as deterministic if the
+    /// metadata says so.
+    pub fn register_with_metadata(
+        &mut self,
+        mut metadata: FunctionMetadata,
+        f: Arc<ContextFunction>,
+    ) {
+        let bare = self.namespace.map(|_| {
+            let name = self.qualified(&metadata.name);
+            core::mem::replace(&mut metadata.name, name)
+        });
+        let name = metadata.name.clone();
+        self.register_with_context(&name, f);
+        if metadata.deterministic {
+            self.deterministic.lock().unwrap().insert(name.clone());
+        }
+        self.metadata.lock().unwrap().insert(name.clone(), metadata);
+        if let Some(bare) = bare {
+            self.register_alias(&bare, &name);
+        }
+    }
+
+    /// Registers the functions of `library`, see [`CollisionPolicy`] for
+    /// those whose name is taken, by a function or an alias. With
+    /// [`CollisionPolicy::Error`], a name the library has twice is taken
+    /// too.
+    pub fn register_library(
+        &mut self,
+        library: &dyn FunctionLibrary,
+        policy: CollisionPolicy,
+    ) -> Result<()> {
+        if policy == CollisionPolicy::Error {
+            let mut names = HashSet::new();
+            for (metadata, _) in library.functions() {
+                let name = self.qualified(&metadata.name);
+                if self.is_taken(&name) || !names.insert(name.clone()) {
+                    return Err(Error::FunctionExists(name));
+                }
+            }
+        }
+        self.add_library(library, policy);
+        Ok(())
+    }
+
+    fn add_library(&mut self, library: &dyn FunctionLibrary, policy: CollisionPolicy) {
+        for (metadata, f) in library.functions() {
+            if policy == CollisionPolicy::Skip && self.is_taken(&self.qualified(&metadata.name)) {
+                continue;
+            }
+            self.register_with_metadata(metadata, f);
+        }
+    }
+
+    // The name `name` is registered as, in the namespace being registered.
+    fn qualified(&self, name: &str) -> String {
+        match self.namespace {
+            Some(namespace) => format!("{}.{}", namespace, name),
+            None => name.to_string(),
+        }
+    }
+
+    fn is_taken(&self, name: &str) -> bool {
+        self.store.lock().unwrap().contains_key(name)
+            || self.aliases.lock().unwrap().contains_key(name)
+    }
+
+    /// Makes `alias` call the function registered as `name`, e.g. `sqrt`
+    /// for `math.sqrt`. Registering a function as `alias` later replaces
+    /// the alias.
+    pub fn register_alias(&mut self, alias: &str, name: &str) {
+        self.store.lock().unwrap().remove(alias);
+        self.deterministic.lock().unwrap().remove(alias);
+        self.metadata.lock().unwrap().remove(alias);
+        self.aliases
+            .lock()
+            .unwrap()
+            .insert(alias.to_string(), name.to_string());
+    }
+
+    // The name `name` is an alias of, or `name` itself.
+    fn canonical(&self, name: &str) -> String {
+        match self.aliases.lock().unwrap().get(name) {
+            Some(name) => name.clone(),
+            None => name.to_string(),
+        }
+    }
+
+    // The built-ins that ignore the context.
+    pub(crate) fn builtin(&mut self, metadata: FunctionMetadata, f: Arc<InnerFunction>) {
+        self.register_with_metadata(metadata, Arc::new(move |_, params| f(params)));
+    }
+
+    /// Registers a function whose result only depends on its params, so
+    /// that with `EvalOptions::memoize` identical calls are made once.
+    pub fn register_deterministic(&mut self, name: &str, f: Arc<InnerFunction>) {
+        self.register(name, f);
+        self.deterministic.lock().unwrap().insert(name.to_string());
+    }
+
+    pub fn is_deterministic(&self, name: &str) -> bool {
+        let name = self.canonical(name);
+        self.deterministic.lock().unwrap().contains(&name)
+    }
+
+    /// The names functions can be called by, aliases included, sorted.
+    pub fn names(&self) -> Vec<String> {
+        let mut ans: Vec<String> = self.store.lock().unwrap().keys().cloned().collect();
+        ans.extend(self.aliases.lock().unwrap().keys().cloned());
+        ans.sort();
+        ans
+    }
+
+    /// The metadata of every function, sorted by name, which lists each
+    /// function once under the name it was registered as rather than its
+    /// aliases. Functions registered without any only have their name and
+    /// `deterministic` set.
+    pub fn list_with_metadata(&self) -> Vec<FunctionMetadata> {
+        let mut names: Vec<String> = self.store.lock().unwrap().keys().cloned().collect();
+        names.sort();
+        names.iter().map(|name| self.metadata(name)).collect()
+    }
+
+    /// [`Self::list_with_metadata`] grouped by
+    /// [`FunctionMetadata::namespace`], with the functions outside any
+    /// namespace under `""`.
+    pub fn list_by_namespace(&self) -> BTreeMap<String, Vec<FunctionMetadata>> {
+        let mut ans: BTreeMap<String, Vec<FunctionMetadata>> = BTreeMap::new();
+        for metadata in self.list_with_metadata() {
+            let namespace = metadata.namespace().unwrap_or_default().to_string();
+            ans.entry(namespace).or_default().push(metadata);
+        }
+        ans
+    }
+
+    /// The arity of the function `name` or of what it's an alias of, if it
+    /// was registered with metadata.
+    pub fn arity(&self, name: &str) -> Option<Arity> {
+        let name = self.canonical(name);
+        let metadata = self.metadata.lock().unwrap();
+        metadata.get(&name).map(|metadata| metadata.arity)
+    }
+
+    // The note of the function `name` or of what it's an alias of, if it's
+    // deprecated.
+    pub(crate) fn deprecation(&self, name: &str) -> Option<String> {
+        let name = self.canonical(name);
+        let metadata = self.metadata.lock().unwrap();
+        metadata.get(&name)?.deprecated.clone()
+    }
+
+    fn metadata(&self, name: &str) -> FunctionMetadata {
+        let name = &self.canonical(name);
+        match self.metadata.lock().unwrap().get(name) {
+            Some(metadata) => metadata.clone(),
+            None => FunctionMetadata {
+                deterministic: self.is_deterministic(name),
+                ..FunctionMetadata::new(name, Arity::Variadic(0), &[], "")
+            },
+        }
+    }
+
+    /// The functions `name` is likely a typo of, among the registered ones
+    /// and those set on `ctx`, e.g. `round` for `roud`.
+    pub(crate) fn suggestions(&self, name: &str, ctx: &Context) -> Vec<String> {
+        let mut names = self.names();
+        names.extend(ctx.function_names());
+        suggestions(name, names.iter().map(String::as_str))
+    }
+
+    pub fn get(&self, name: &str) -> Result<Arc<ContextFunction>> {
+        let canonical = self.canonical(name);
         let binding = self.store.lock().unwrap();
-        let ans = binding.get(name);
+        let ans = binding.get(&canonical);
         if ans.is_none() {
-            return Err(Error::InnerFunctionNotRegistered(String::from(name)));
+            return Err(Error::InnerFunctionNotRegistered(
+                String::from(name),
+                Vec::new(),
+            ));
         }
         Ok(ans.unwrap().clone())
     }
+
+    // `get`, for a call that `ctx` didn't provide either, so a failure
+    // suggests from both.
+    pub(crate) fn resolve(&self, name: &str, ctx: &Context) -> Result<Arc<ContextFunction>> {
+        self.get(name).map_err(|_| {
+            Error::InnerFunctionNotRegistered(String::from(name), self.suggestions(name, ctx))
+        })
+    }
+}
+
+/// A bundle of functions registered together, e.g. by a crate that adds
+/// its own, with [`crate::register_library`] or
+/// [`crate::Context::register_library`].
+pub trait FunctionLibrary {
+    /// The functions, each registered under `metadata.name` and as
+    /// deterministic if the metadata says so.
+    fn functions(&self) -> Vec<(FunctionMetadata, Arc<ContextFunction>)>;
+}
+
+/// What registering a library does with a function whose name is taken.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum CollisionPolicy {
+    /// Fail with [`Error::FunctionExists`] before registering any of them.
+    #[default]
+    Error,
+    /// Keep what has the name.
+    Skip,
+    /// Replace what has the name.
+    Override,
+}
+
+/// The built-ins of the `math` namespace but `random`: `min`, `max`, `sum`,
+/// `mul`, `round`, `clamp`, `sign`, `isInteger`, `trunc`, `frac`, `sqrt`,
+/// `exp`, `ln` and `pow`.
+#[cfg(feature = "builtin-math")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MathLibrary;
+
+/// The built-ins for missing values: `coalesce`, `ifnull`, `isNull`,
+/// `isNotNull` and `default`. Missing references evaluate to `None` before
+/// any function sees them, so `coalesce(missing, 1)` and
+/// `ifnull(missing, 1)` work even though params are evaluated eagerly.
+#[cfg(feature = "builtin-logic")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LogicLibrary;
+
+// Collects the functions of a built-in library through the calls
+// `InnerFunctionManager` registers the other built-ins with.
+#[cfg(any(feature = "builtin-math", feature = "builtin-logic"))]
+#[derive(Default)]
+struct Bundle(Vec<(FunctionMetadata, Arc<ContextFunction>)>);
+
+#[cfg(any(feature = "builtin-math", feature = "builtin-logic"))]
+impl Bundle {
+    fn builtin(&mut self, metadata: FunctionMetadata, f: Arc<InnerFunction>) {
+        self.register_with_metadata(metadata, Arc::new(move |_, params| f(params)));
+    }
+
+    fn register_with_metadata(&mut self, metadata: FunctionMetadata, f: Arc<ContextFunction>) {
+        self.0.push((metadata, f));
+    }
+}
+
+#[cfg(feature = "builtin-math")]
+impl FunctionLibrary for MathLibrary {
+    fn functions(&self) -> Vec<(FunctionMetadata, Arc<ContextFunction>)> {
+        use crate::format::round;
+        use rust_decimal::Decimal;
+        let mut lib = Bundle::default();
+        lib.builtin(
+            FunctionMetadata::new(
+                "min",
+                Arity::Variadic(1),
+                &[("values", "Number")],
+                "The smallest of the numbers, given as params or as one list.",
+            )
+            .deterministic(),
+            Arc::new(|params| {
+                let min = numbers(params)?.into_iter().min();
+                Ok(Value::Number(min.ok_or(Error::ParamInvalid())?))
+            }),
+        );
+
+        lib.builtin(
+            FunctionMetadata::new(
+                "max",
+                Arity::Variadic(1),
+                &[("values", "Number")],
+                "The largest of the numbers, given as params or as one list.",
+            )
+            .deterministic(),
+            Arc::new(|params| {
+                let max = numbers(params)?.into_iter().max();
+                Ok(Value::Number(max.ok_or(Error::ParamInvalid())?))
+            }),
+        );
+
+        lib.builtin(
+            FunctionMetadata::new(
+                "sum",
+                Arity::Variadic(0),
+                &[("values", "Number")],
+                "The sum of the numbers, given as params or as one list.",
+            )
+            .deterministic(),
+            Arc::new(|params| Ok(Value::Number(numbers(params)?.into_iter().sum()))),
+        );
+
+        lib.builtin(
+            FunctionMetadata::new(
+                "mul",
+                Arity::Variadic(0),
+                &[("values", "Number")],
+                "The product of the numbers, given as params or as one list.",
+            )
+            .deterministic(),
+            Arc::new(|params| Ok(Value::Number(numbers(params)?.into_iter().product()))),
+        );
+
+        lib.builtin(
+            FunctionMetadata::new(
+                "round",
+                Arity::Range(1, 3),
+                &[("x", "Number"), ("dp", "Number"), ("mode", "String")],
+                "Rounds x to dp decimal places, 0 by default. mode is one of half_up (the default), half_down, half_even, up, down, ceiling or floor.",
+            ),
+            Arc::new(|params| {
+                if params.is_empty() || params.len() > 3 {
+                    return Err(Error::ParamInvalid());
+                }
+                let mut params = params.into_iter();
+                let value = params.next().unwrap().decimal()?;
+                let dp = match params.next() {
+                    Some(dp) => decimal_places(dp)?,
+                    None => 0,
+                };
+                let rounding = rounding(params.next())?;
+                Ok(Value::Number(round(value, dp, rounding)))
+            }),
+        );
+
+        lib.builtin(
+            FunctionMetadata::new(
+                "clamp",
+                Arity::Exact(3),
+                &[("x", "Number"), ("lo", "Number"), ("hi", "Number")],
+                "x, raised to lo or lowered to hi when it's outside them. lo can't be greater than hi.",
+            )
+            .deterministic(),
+            Arc::new(|params| {
+                let [x, lo, hi] =
+                    <[Value; 3]>::try_from(params).map_err(|_| Error::ParamInvalid())?;
+                let (x, lo, hi) = (x.decimal()?, lo.decimal()?, hi.decimal()?);
+                if lo > hi {
+                    return Err(Error::InvertedBounds(lo.to_string(), hi.to_string()));
+                }
+                Ok(Value::Number(x.clamp(lo, hi)))
+            }),
+        );
+
+        // `trunc` rounds toward zero and `frac` keeps the sign of x, so
+        // `trunc(x) + frac(x)` is x, negative or not.
+        for (name, description, f) in [
+            (
+                "sign",
+                "-1, 0 or 1, as x is negative, zero or positive.",
+                sign as fn(Decimal) -> Value,
+            ),
+            ("isInteger", "Whether x has no fractional part.", |x| {
+                Value::from(x.fract().is_zero())
+            }),
+            ("trunc", "x without its fractional part.", |x| {
+                Value::Number(unsigned_zero(x.trunc()))
+            }),
+            (
+                "frac",
+                "The fractional part of x, with the sign of x.",
+                |x| Value::Number(unsigned_zero(x.fract())),
+            ),
+        ] {
+            lib.builtin(
+                FunctionMetadata::new(name, Arity::Exact(1), &[("x", "Number")], description)
+                    .deterministic(),
+                Arc::new(move |params| {
+                    let [x] = <[Value; 1]>::try_from(params).map_err(|_| Error::ParamInvalid())?;
+                    Ok(f(x.decimal()?))
+                }),
+            );
+        }
+
+        // Computed in f64 under `EvalOptions::float_math`, so they read the
+        // options, but the result still only depends on the params.
+        for (name, params, description) in [
+            ("sqrt", &[("x", "Number")][..], "The square root of x."),
+            ("exp", &[("x", "Number")], "e raised to the power of x."),
+            ("ln", &[("x", "Number")], "The natural logarithm of x."),
+            (
+                "pow",
+                &[("x", "Number"), ("y", "Number")],
+                "x raised to the power of y.",
+            ),
+        ] {
+            let arity = params.len();
+            lib.register_with_metadata(
+                FunctionMetadata::new(name, Arity::Exact(arity), params, description)
+                    .deterministic(),
+                Arc::new(move |ctx, params| {
+                    if params.len() != arity {
+                        return Err(Error::ParamInvalid());
+                    }
+                    let mut params = params.into_iter();
+                    let x = params.next().unwrap().decimal()?;
+                    let y = params.next().map(Value::decimal).transpose()?;
+                    math(name, x, y.unwrap_or_default(), ctx)
+                }),
+            );
+        }
+        lib.0
+    }
+}
+
+#[cfg(feature = "builtin-logic")]
+impl FunctionLibrary for LogicLibrary {
+    fn functions(&self) -> Vec<(FunctionMetadata, Arc<ContextFunction>)> {
+        let mut lib = Bundle::default();
+        lib.builtin(
+            FunctionMetadata::new(
+                "coalesce",
+                Arity::Variadic(0),
+                &[("values", "Any")],
+                "The first value that isn't None, or None.",
+            ),
+            Arc::new(|params| {
+                Ok(params
+                    .into_iter()
+                    .find(|param| *param != Value::None)
+                    .unwrap_or(Value::None))
+            }),
+        );
+
+        lib.builtin(
+            FunctionMetadata::new(
+                "ifnull",
+                Arity::Exact(2),
+                &[("x", "Any"), ("fallback", "Any")],
+                "fallback if x is None, otherwise x.",
+            ),
+            Arc::new(|params| {
+                if params.len() != 2 {
+                    return Err(Error::ParamInvalid());
+                }
+                let mut params = params.into_iter();
+                match params.next().unwrap() {
+                    Value::None => Ok(params.next().unwrap()),
+                    value => Ok(value),
+                }
+            }),
+        );
+
+        for (name, null, description) in [
+            ("isNull", true, "Whether x is None."),
+            ("isNotNull", false, "Whether x isn't None."),
+        ] {
+            lib.builtin(
+                FunctionMetadata::new(name, Arity::Exact(1), &[("x", "Any")], description)
+                    .deterministic(),
+                Arc::new(move |params| {
+                    let [x] = <[Value; 1]>::try_from(params).map_err(|_| Error::ParamInvalid())?;
+                    Ok(Value::from((x == Value::None) == null))
+                }),
+            );
+        }
+
+        lib.register_with_metadata(
+            FunctionMetadata::new(
+                "default",
+                Arity::Exact(2),
+                &[("name", "String"), ("fallback", "Any")],
+                "The variable called name, or fallback if it's missing or None.",
+            ),
+            Arc::new(|ctx, params| {
+                if params.len() != 2 {
+                    return Err(Error::ParamInvalid());
+                }
+                let mut params = params.into_iter();
+                let name = params.next().unwrap().string()?;
+                let fallback = params.next().unwrap();
+                if ctx.get(&name).is_none() {
+                    return Ok(fallback);
+                }
+                match ctx.value(&name)? {
+                    Value::None => Ok(fallback),
+                    value => Ok(value),
+                }
+            }),
+        );
+        lib.0
+    }
+}
+
+// The numbers to aggregate, given either as the params themselves or as a
+// single list param, e.g. `sum(1, 2)` or `sum([1, 2])`.
+#[cfg(feature = "builtin-math")]
+fn numbers(params: Vec<Value>) -> Result<Vec<rust_decimal::Decimal>> {
+    let params = match <[Value; 1]>::try_from(params) {
+        Ok([Value::List(list)]) => list,
+        Ok([param]) => vec![param],
+        Err(params) => params,
+    };
+    params.into_iter().map(Value::decimal).collect()
+}
+
+#[cfg(feature = "builtin-math")]
+fn sign(x: rust_decimal::Decimal) -> Value {
+    Value::from(match x.cmp(&rust_decimal::Decimal::ZERO) {
+        core::cmp::Ordering::Less => -1,
+        core::cmp::Ordering::Equal => 0,
+        core::cmp::Ordering::Greater => 1,
+    })
+}
+
+// `Decimal` keeps the sign of a zero, which would print `trunc(-0.5)` as
+// `-0`.
+#[cfg(feature = "builtin-math")]
+fn unsigned_zero(x: rust_decimal::Decimal) -> rust_decimal::Decimal {
+    match x.is_zero() {
+        true => rust_decimal::Decimal::ZERO,
+        false => x,
+    }
+}
+
+// `sqrt`, `exp`, `ln` and `pow`. Inputs outside the domain, like the root
+// of a negative, are invalid params in both modes. Without `std` there's
+// no f64 `sqrt` or `ln`, so `float` is ignored.
+#[cfg(feature = "builtin-math")]
+#[cfg_attr(not(feature = "std"), allow(unused_variables))]
+fn math(
+    name: &str,
+    x: rust_decimal::Decimal,
+    y: rust_decimal::Decimal,
+    ctx: &Context,
+) -> Result<Value> {
+    use rust_decimal::{Decimal, MathematicalOps};
+    // `checked_exp` stops at 2e-8, short of what a `Decimal` can hold.
+    const TOLERANCE: Decimal = Decimal::from_parts(1, 0, 0, false, 24);
+    let invalid = match name {
+        "sqrt" => x < Decimal::ZERO,
+        "ln" => x <= Decimal::ZERO,
+        "pow" => x < Decimal::ZERO && !y.fract().is_zero(),
+        _ => false,
+    };
+    if invalid {
+        return Err(Error::ParamInvalid());
+    }
+    #[cfg(feature = "std")]
+    if ctx.options().float_math {
+        use rust_decimal::prelude::ToPrimitive;
+        let (x, y) = (
+            x.to_f64().unwrap_or(f64::NAN),
+            y.to_f64().unwrap_or(f64::NAN),
+        );
+        let ans = match name {
+            "sqrt" => x.sqrt(),
+            "exp" => x.exp(),
+            "ln" => x.ln(),
+            _ => x.powf(y),
+        };
+        return crate::options::float_value(ans, ctx);
+    }
+    let ans = match name {
+        "sqrt" => x.sqrt(),
+        "exp" => x.checked_exp_with_tolerance(TOLERANCE),
+        "ln" => x.checked_ln(),
+        _ if x.is_zero() || y.fract().is_zero() => x.checked_powd(y),
+        _ => x
+            .checked_ln()
+            .and_then(|ln| ln.checked_mul(y))
+            .and_then(|ln| ln.checked_exp_with_tolerance(TOLERANCE)),
+    };
+    Ok(Value::Number(ans.ok_or(Error::ParamInvalid())?.normalize()))
+}
+
+#[cfg(any(feature = "builtin-math", feature = "builtin-string"))]
+fn decimal_places(value: Value) -> Result<u32> {
+    let dp = value.integer()?;
+    if !(0..=28).contains(&dp) {
+        return Err(Error::ParamInvalid());
+    }
+    Ok(dp as u32)
+}
+
+#[cfg(any(feature = "builtin-math", feature = "builtin-string"))]
+fn rounding(value: Option<Value>) -> Result<crate::format::Rounding> {
+    use crate::format::Rounding;
+    use core::str::FromStr;
+    match value {
+        Some(value) => Rounding::from_str(&value.string()?),
+        None => Ok(Rounding::default()),
+    }
+}
+
+// A separator param of `formatNumber`, or the padding of `padLeft`: a
+// single char, or none for `''`.
+#[cfg(feature = "builtin-string")]
+fn separator(value: Value) -> Result<Option<char>> {
+    let value = value.string()?;
+    let mut chars = value.chars();
+    match (chars.next(), chars.next()) {
+        (ch, None) => Ok(ch),
+        _ => Err(Error::ParamInvalid()),
+    }
+}
+
+#[cfg(feature = "builtin-string")]
+fn capitalize(s: &str) -> String {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(first) => first
+            .to_uppercase()
+            .chain(chars.as_str().to_lowercase().chars())
+            .collect(),
+        None => String::new(),
+    }
+}
+
+// A count of chars or copies, which can't be negative.
+#[cfg(any(feature = "builtin-string", feature = "builtin-list"))]
+fn count(value: Value) -> Result<usize> {
+    usize::try_from(value.integer()?).map_err(|_| Error::ParamInvalid())
+}
+
+// Checks a string against the limits before it's built, since it could be
+// huge, given its size in bytes unless that overflows.
+#[cfg(any(feature = "builtin-string", feature = "builtin-list"))]
+fn check_new_string(
+    ctx: &Context,
+    bytes: Option<usize>,
+    chars: impl FnOnce() -> usize,
+) -> Result<()> {
+    match bytes {
+        Some(bytes) if isize::try_from(bytes).is_ok() => ctx.options().limits.check_chars(chars()),
+        _ => Err(Error::ParamInvalid()),
+    }
 }
@@ -0,0 +1,256 @@
+use crate::context::Context;
+use crate::define::Result;
+use crate::error::Error;
+use crate::function::{Arity, FunctionMetadata, InnerFunctionManager};
+use crate::value::{Map, Value};
+use rust_decimal::prelude::*;
+use serde_json::Number;
+use std::sync::Arc;
+
+// How much of the offending input a parse error quotes.
+const PREVIEW_CHARS: usize = 40;
+
+impl TryFrom<serde_json::Value> for Value {
+    type Error = Error;
+
+    fn try_from(value: serde_json::Value) -> Result<Self> {
+        use serde_json::Value as Json;
+        match value {
+            Json::Null => Ok(Value::None),
+            Json::Bool(val) => Ok(Value::Bool(val)),
+            Json::Number(val) => json_number(&val),
+            Json::String(val) => Ok(Value::String(val)),
+            Json::Array(values) => Ok(Value::List(
+                values
+                    .into_iter()
+                    .map(Value::try_from)
+                    .collect::<Result<_>>()?,
+            )),
+            Json::Object(m) => {
+                let mut ans = Map::with_capacity(m.len());
+                for (k, v) in m {
+                    ans.insert(Value::String(k), Value::try_from(v)?);
+                }
+                Ok(Value::Map(ans))
+            }
+        }
+    }
+}
+
+impl From<Value> for serde_json::Value {
+    fn from(value: Value) -> Self {
+        use serde_json::Value as Json;
+        match value {
+            Value::None => Json::Null,
+            Value::Bool(val) => Json::Bool(val),
+            Value::Number(val) => Number::from_str(&val.to_string())
+                .map(Json::Number)
+                .unwrap_or(Json::Null),
+            Value::String(val) => Json::String(val),
+            Value::List(values) => Json::Array(values.into_iter().map(Json::from).collect()),
+            Value::Map(m) => {
+                let mut ans = serde_json::Map::new();
+                for (k, v) in m {
+                    ans.insert(json_key(k), Json::from(v));
+                }
+                Json::Object(ans)
+            }
+        }
+    }
+}
+
+fn json_number(val: &Number) -> Result<Value> {
+    let s = val.to_string();
+    Decimal::from_str(&s)
+        .or_else(|_| Decimal::from_scientific(&s))
+        .map(Value::Number)
+        .map_err(|_| Error::InvalidNumber(s, None))
+}
+
+// JSON object keys are always strings, so non-string map keys are written
+// in their JSON form, e.g. the key `1` becomes `"1"`.
+pub(crate) fn json_key(key: Value) -> String {
+    match key {
+        Value::String(val) => val,
+        other => serde_json::Value::from(other).to_string(),
+    }
+}
+
+fn preview(input: &str) -> String {
+    let mut chars = input.chars();
+    let mut ans: String = chars.by_ref().take(PREVIEW_CHARS).collect();
+    if chars.next().is_some() {
+        ans.push_str("...");
+    }
+    ans
+}
+
+impl Value {
+    pub fn from_json(input: &str) -> Result<Value> {
+        let value: serde_json::Value = serde_json::from_str(input)
+            .map_err(|err| Error::InvalidJson(format!("{} in '{}'", err, preview(input))))?;
+        Value::try_from(value)
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::Value::from(self.clone()).to_string()
+    }
+}
+
+impl Context {
+    /// Sets every entry of a JSON object as a variable of the context.
+    pub fn set_variables_from_json(&mut self, input: &str) -> Result<()> {
+        match Value::from_json(input)? {
+            Value::Map(m) => {
+                for (k, v) in m {
+                    self.set_variable(&k.string()?, v);
+                }
+                Ok(())
+            }
+            _ => Err(Error::ShouldBeMap()),
+        }
+    }
+}
+
+impl InnerFunctionManager {
+    pub(crate) fn init_json(&mut self) {
+        self.builtin(
+            FunctionMetadata::new(
+                "jsonParse",
+                Arity::Exact(1),
+                &[("json", "String")],
+                "The value a JSON string holds.",
+            )
+            .deterministic(),
+            Arc::new(|params| {
+                if params.len() != 1 {
+                    return Err(Error::ParamInvalid());
+                }
+                Value::from_json(&params.into_iter().next().unwrap().string()?)
+            }),
+        );
+
+        self.builtin(
+            FunctionMetadata::new(
+                "jsonStringify",
+                Arity::Exact(1),
+                &[("x", "Any")],
+                "x as a JSON string.",
+            )
+            .deterministic(),
+            Arc::new(|params| {
+                if params.len() != 1 {
+                    return Err(Error::ParamInvalid());
+                }
+                Ok(Value::String(params[0].to_json()))
+            }),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::context::Context;
+    use crate::error::Error;
+    use crate::value::{Map, Value};
+    use crate::{create_context, execute};
+    use rstest::rstest;
+
+    #[rstest]
+    #[case("null", Value::None)]
+    #[case("true", true.into())]
+    #[case("12", 12.into())]
+    #[case("-1.25", Value::from(-1.25))]
+    #[case("1e3", 1000.into())]
+    #[case("\"haha\"", "haha".into())]
+    #[case("[1,\"a\",[]]", vec![1.into(), "a".into(), Value::List(vec![])].into())]
+    #[case("{\"a\":{\"b\":false}}", Value::Map(Map::from_iter([(
+        "a".into(),
+        Value::Map(Map::from_iter([("b".into(), false.into())])),
+    )])))]
+    fn test_from_json(#[case] input: &str, #[case] output: Value) {
+        assert_eq!(Value::from_json(input).unwrap(), output);
+    }
+
+    #[rstest]
+    #[case(Value::None, "null")]
+    #[case(Value::from(1.5), "1.5")]
+    #[case(
+        Value::from("79228162514264337593543950335"),
+        "\"79228162514264337593543950335\""
+    )]
+    #[case(Value::Map(Map::from_iter([(1.into(), true.into()), ("b".into(), vec![Value::None].into())])), "{\"1\":true,\"b\":[null]}")]
+    fn test_to_json(#[case] input: Value, #[case] output: &str) {
+        assert_eq!(input.to_json(), output);
+    }
+
+    #[test]
+    fn test_number_round_trip() {
+        let input = "79228162514264337593543950335";
+        let value = Value::from_json(input).unwrap();
+        assert_eq!(value.to_json(), input);
+    }
+
+    #[rstest]
+    #[case("{")]
+    #[case("1e400")]
+    fn test_from_json_error(#[case] input: &str) {
+        assert!(Value::from_json(input).is_err());
+    }
+
+    #[test]
+    fn test_set_variables_from_json() {
+        let mut ctx = Context::new();
+        ctx.set_variables_from_json("{\"a\":1,\"b\":\"x\"}")
+            .unwrap();
+        assert_eq!(ctx.value("a").unwrap(), 1.into());
+        assert_eq!(ctx.value("b").unwrap(), "x".into());
+        assert!(ctx.set_variables_from_json("[1]").is_err());
+    }
+
+    #[rstest]
+    #[case("jsonParse('{\"items\":[{\"price\":12.5}]}')", Value::Map(Map::from_iter([(
+        "items".into(),
+        vec![Value::Map(Map::from_iter([("price".into(), Value::from(12.5))]))].into(),
+    )])))]
+    #[case("'price' in jsonParse('{\"price\":1}')", true.into())]
+    #[case("jsonParse('79228162514264337593543950335') - 1", Value::from("79228162514264337593543950334".parse::<rust_decimal::Decimal>().unwrap()))]
+    #[case("jsonStringify({'a': [1, 2.50, missing, 'x']})", "{\"a\":[1,2.50,null,\"x\"]}".into())]
+    #[case("jsonStringify(jsonParse('79228162514264337593543950335'))", "79228162514264337593543950335".into())]
+    #[case("jsonStringify(jsonParse('-79228162514264337593543950335'))", "-79228162514264337593543950335".into())]
+    fn test_json_functions(#[case] input: &str, #[case] output: Value) {
+        assert_eq!(execute(input, create_context!()).unwrap(), output);
+    }
+
+    #[test]
+    fn test_json_parse_error() {
+        let input = "jsonParse('{\"items\": [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12,]}')";
+        let err = execute(input, create_context!()).unwrap_err();
+        match err.root() {
+            Error::InvalidJson(msg) => {
+                assert!(
+                    msg.starts_with("trailing comma at line 1 column"),
+                    "{}",
+                    msg
+                );
+                assert!(
+                    msg.ends_with("in '{\"items\": [1, 2, 3, 4, 5, 6, 7, 8, 9, 10...'"),
+                    "{}",
+                    msg
+                );
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(execute("jsonParse(1)", create_context!()).is_err());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serialize_value() {
+        let value = execute("{'a': [1, 1.50, true, None], 2: 'b'}", create_context!()).unwrap();
+        assert_eq!(
+            serde_json::to_value(&value).unwrap(),
+            serde_json::json!({"a": [1, 1.5, true, null], "2": "b"})
+        );
+    }
+}
@@ -1,4 +1,4 @@
-use crate::operator::{InfixOpManager, PostfixOpManager, PrefixOpManager};
+use crate::operator::{longest_op, InfixOpManager, PostfixOpManager, PrefixOpManager};
 
 pub fn is_prefix_op(op: &str) -> bool {
     PrefixOpManager::new().exist(op)
@@ -16,8 +16,63 @@ pub fn is_ternary_op(op: &str) -> bool {
     op == "?" || op == ":"
 }
 
+// The built-in operators that cargo features can leave out. They're still
+// tokenized when disabled, so that using one fails as not registered
+// instead of being split into other tokens.
+const FEATURE_OPS: [&str; 32] = [
+    "=",
+    "+=",
+    "-=",
+    "*=",
+    "/=",
+    "%=",
+    "<<=",
+    ">>=",
+    "&=",
+    "^=",
+    "|=",
+    "||",
+    "&&",
+    "??",
+    "|",
+    "^",
+    "&",
+    "<<",
+    ">>",
+    "~",
+    "beginWith",
+    "endWith",
+    "in",
+    "contains",
+    "containsAll",
+    "containsAny",
+    "!",
+    "not",
+    "AND",
+    "OR",
+    "++",
+    "--",
+];
+
 pub fn is_op(op: &str) -> bool {
-    is_prefix_op(op) || is_infix_op(op) || is_postfix_op(op) || is_ternary_op(op)
+    is_prefix_op(op)
+        || is_infix_op(op)
+        || is_postfix_op(op)
+        || is_ternary_op(op)
+        || FEATURE_OPS.contains(&op)
+}
+
+// No op is longer, so a longer run of chars without whitespace can't be
+// one.
+pub fn max_op_len() -> usize {
+    FEATURE_OPS
+        .iter()
+        .map(|op| op.len())
+        .fold(longest_op(), usize::max)
+}
+
+pub fn is_disabled_op(op: &str) -> bool {
+    FEATURE_OPS.contains(&op) && !is_prefix_op(op) && !is_infix_op(op) && !is_postfix_op(op)
 }
 
 pub fn is_not(op: &str) -> bool {
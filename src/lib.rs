@@ -9,8 +9,27 @@
 //! + Pre-defined Operators Support (Common boolean, numeric and string operators)
 //! + Support function and operators registration
 //! + Support operator redirection
+#![allow(clippy::upper_case_acronyms)]
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+#[macro_use]
+extern crate alloc;
+// The cdylib needs the allocator and panic handler of `std`, so it's still
+// linked on targets that have it.
+#[cfg(all(not(any(feature = "std", test)), not(target_os = "none")))]
+extern crate std;
+
+#[cfg(feature = "arbitrary")]
+mod arbitrary;
+mod arena;
+mod arity;
+#[cfg(feature = "async")]
+mod async_exec;
+mod audit;
+mod canonical;
+mod compat;
 mod define;
 mod error;
+mod evaluator;
 mod parser;
 #[macro_use]
 mod function;
@@ -20,10 +39,65 @@ mod token;
 mod tokenizer;
 #[macro_use]
 mod value;
+#[cfg(feature = "codecs")]
+mod codecs;
+mod compiler;
 mod context;
+mod csv;
 mod descriptor;
-mod init;
-use std::sync::Arc;
+mod explain;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+mod format;
+#[cfg(feature = "serde_json")]
+mod json;
+mod lint;
+mod metrics;
+mod options;
+mod provider;
+mod random;
+mod readonly;
+mod rename;
+mod source;
+mod suggest;
+pub mod testing;
+mod trace;
+#[cfg(feature = "builtin-string")]
+mod units;
+mod vm;
+mod warning;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+// Paths used by the exported macros, which can't name `alloc` in crates
+// that don't declare it.
+#[doc(hidden)]
+pub mod __private {
+    pub use alloc::sync::Arc;
+}
+
+/// The types and functions most uses of the engine need, so that
+/// `use expression_engine::prelude::*;` is enough to parse an expression,
+/// fill a context and evaluate against it, by walking the tree or on the VM.
+///
+/// ```rust
+/// use expression_engine::prelude::*;
+/// let ast = parse_expression("a * 2 > b").unwrap();
+/// let program = Compiler::compile(&ast).unwrap();
+/// let mut ctx = create_context!("a" => 3, "b" => 5);
+/// assert_eq!(Vm::run(&program, &mut ctx).unwrap(), Value::from(true));
+/// assert_eq!(ast.exec(&mut ctx).unwrap(), Value::from(true));
+/// ```
+pub mod prelude {
+    pub use crate::{
+        create_context, execute, parse_expression, Compiler, Context, Error, EvalOptions, ExprAST,
+        Program, Result, Value, Vm,
+    };
+}
 
 /// ## Usage
 ///
@@ -54,11 +128,101 @@ pub fn execute(expr: &str, mut ctx: context::Context) -> Result<Value> {
 /// let ast = parse_expression(input);
 /// assert!(ast.is_ok());
 /// ```
-pub fn parse_expression(expr: &str) -> Result<ExprAST> {
-    init();
+pub fn parse_expression(expr: &str) -> Result<ExprAST<'_>> {
     parser::Parser::new(expr)?.parse_stmt()
 }
 
+/// ## Usage
+///
+/// Parses like [`parse_expression`], with [`ParseOptions`]. With
+/// `newline_statements` on, a line break ends a complete statement, so
+/// statements can go one per line without `;`.
+///
+/// ``` rust
+/// use expression_engine::{create_context, parse_expression_with_options, ParseOptions, Value};
+/// let options = ParseOptions { newline_statements: true, ..Default::default() };
+/// let ast = parse_expression_with_options("a = 3\nb = a +\n  1\n-b", options).unwrap();
+/// assert_eq!(ast.expr(), "a = 3;b = a + 1;- b");
+/// assert_eq!(ast.exec(&mut create_context!()).unwrap(), Value::from(-4));
+/// ```
+pub fn parse_expression_with_options(expr: &str, options: ParseOptions) -> Result<ExprAST<'_>> {
+    parser::Parser::with_options(expr, options)?.parse_stmt()
+}
+
+/// ## Usage
+///
+/// You can find where each top-level statement is without building the
+/// tree via this method, e.g. to check again only the statement an edit
+/// touched. A `;` inside a string doesn't end a statement, and a statement
+/// the input ends inside of runs to the end and is marked unterminated
+///
+/// ``` rust
+/// use expression_engine::statement_spans;
+/// let input = "a = 'x;y'; f(a,\n  b); (c";
+/// let spans = statement_spans(input).unwrap();
+/// let texts: Vec<&str> = spans.iter().map(|s| &input[s.span.0..s.span.1]).collect();
+/// assert_eq!(texts, ["a = 'x;y'", "f(a,\n  b)", "(c"]);
+/// assert!(!spans[1].unterminated && spans[2].unterminated);
+/// ```
+pub fn statement_spans(expr: &str) -> Result<Vec<StatementSpan>> {
+    parser::Parser::statement_spans(expr)
+}
+
+/// ## Usage
+///
+/// Finds statements like [`statement_spans`], with [`ParseOptions`], so
+/// that with `newline_statements` on a line break can end one
+///
+/// ``` rust
+/// use expression_engine::{statement_spans_with_options, ParseOptions};
+/// let options = ParseOptions { newline_statements: true, ..Default::default() };
+/// let spans = statement_spans_with_options("a = 1\nb = a +\n  1", options).unwrap();
+/// let ranges: Vec<(usize, usize)> = spans.iter().map(|s| (s.span.0, s.span.1)).collect();
+/// assert_eq!(ranges, [(0, 5), (6, 17)]);
+/// ```
+pub fn statement_spans_with_options(
+    expr: &str,
+    options: ParseOptions,
+) -> Result<Vec<StatementSpan>> {
+    parser::Parser::statement_spans_with_options(expr, options)
+}
+
+/// ## Usage
+///
+/// Parses like [`parse_expression`], into an [`ExprArena`] instead of a tree
+/// of boxes. It's cheaper to build, clone and drop, and evaluates the same.
+///
+/// ``` rust
+/// use expression_engine::{create_context, parse_arena, ExprAST, Value};
+/// let arena = parse_arena("a * 2 + max(1, 4)").unwrap();
+/// assert_eq!(arena.exec(&mut create_context!("a" => 3)).unwrap(), Value::from(10));
+/// assert_eq!(ExprAST::from(&arena).expr(), "a * 2 + max(1,4)");
+/// ```
+pub fn parse_arena(expr: &str) -> Result<ExprArena<'_>> {
+    arena::ExprArena::parse(expr)
+}
+
+/// ## Usage
+///
+/// Parses like [`parse_expression`], keeping the input and where each node
+/// is in it, so that a rule can be shown back exactly as it was written
+///
+/// ``` rust
+/// use expression_engine::{parse_with_source, ExprAST};
+/// let expression = parse_with_source("price  *  max(rate , 1)").unwrap();
+/// let ExprAST::Binary(_, _, rate) = expression.ast() else { unreachable!() };
+/// assert_eq!(expression.source_of(rate), Some("max(rate , 1)"));
+/// assert_eq!(rate.expr(), "max(rate,1)");
+/// ```
+pub fn parse_with_source(expr: &str) -> Result<Expression<'_>> {
+    parse_with_source_options(expr, ParseOptions::default())
+}
+
+/// [`parse_with_source`], with [`ParseOptions`].
+pub fn parse_with_source_options(expr: &str, options: ParseOptions) -> Result<Expression<'_>> {
+    source::Expression::parse(expr, options)
+}
+
 /// ## Usage
 ///
 /// You can register some inner functions in advance via this method
@@ -74,10 +238,259 @@ pub fn parse_expression(expr: &str) -> Result<ExprAST> {
 /// ```
 pub fn register_function(name: &str, handler: Arc<function::InnerFunction>) {
     use crate::function::InnerFunctionManager;
-    init();
     InnerFunctionManager::new().register(name, handler);
 }
 
+/// ## Usage
+///
+/// You can register inner functions that read the context they're evaluated
+/// against via this method
+///
+/// ``` rust
+/// use std::sync::Arc;
+/// use expression_engine::{register_context_function, create_context, execute, Value};
+/// register_context_function(
+///     "has",
+///     Arc::new(|ctx, params| {
+///         let name = params[0].clone().string()?;
+///         Ok(Value::from(ctx.get(&name).is_some()))
+///     }),
+/// );
+/// let ans = execute("has('a')", create_context!("a" => 1));
+/// assert_eq!(ans.unwrap(), Value::from(true));
+/// ```
+pub fn register_context_function(name: &str, handler: Arc<function::ContextFunction>) {
+    use crate::function::InnerFunctionManager;
+    InnerFunctionManager::new().register_with_context(name, handler);
+}
+
+/// ## Usage
+///
+/// You can register inner functions whose result only depends on their
+/// params via this method, so that identical calls are made once per
+/// evaluation when [`EvalOptions::memoize`] is on
+///
+/// ``` rust
+/// use std::sync::Arc;
+/// use expression_engine::{
+///     create_context, execute, register_deterministic_function, EvalOptions, Value,
+/// };
+/// register_deterministic_function(
+///     "square",
+///     Arc::new(|params| {
+///         let n = params[0].clone().integer()?;
+///         Ok(Value::from(n * n))
+///     }),
+/// );
+/// let mut ctx = create_context!("n" => 3);
+/// ctx.set_options(EvalOptions { memoize: true, ..EvalOptions::default() });
+/// let ans = execute("square(n) + square(n)", ctx);
+/// assert_eq!(ans.unwrap(), Value::from(18));
+/// ```
+pub fn register_deterministic_function(name: &str, handler: Arc<function::InnerFunction>) {
+    use crate::function::InnerFunctionManager;
+    InnerFunctionManager::new().register_deterministic(name, handler);
+}
+
+/// ## Usage
+///
+/// You can list the names of the registered inner functions via this method
+///
+/// ``` rust
+/// use expression_engine::registered_functions;
+/// let names = registered_functions();
+/// assert!(names.contains(&"max".to_string()));
+/// ```
+pub fn registered_functions() -> Vec<String> {
+    use crate::function::InnerFunctionManager;
+    InnerFunctionManager::new().names()
+}
+
+/// ## Usage
+///
+/// You can register inner functions along with their signature and
+/// description via this method, which the `help` function then shows. The
+/// function is deterministic if the metadata says so.
+///
+/// ``` rust
+/// use std::sync::Arc;
+/// use expression_engine::{
+///     create_context, execute, register_function_with_metadata, Arity, FunctionMetadata, Value,
+/// };
+/// register_function_with_metadata(
+///     FunctionMetadata::new("double", Arity::Exact(1), &[("x", "Number")], "Twice x."),
+///     Arc::new(|_, params| Ok(Value::from(params[0].clone().decimal()? * rust_decimal::Decimal::TWO))),
+/// );
+/// let ans = execute("help('double')", create_context!());
+/// assert_eq!(ans.unwrap(), Value::from("double(x: Number)\nTwice x."));
+/// ```
+pub fn register_function_with_metadata(
+    metadata: FunctionMetadata,
+    handler: Arc<function::ContextFunction>,
+) {
+    use crate::function::InnerFunctionManager;
+    InnerFunctionManager::new().register_with_metadata(metadata, handler);
+}
+
+/// ## Usage
+///
+/// You can list the registered inner functions with their metadata via
+/// this method
+///
+/// ``` rust
+/// use expression_engine::{list_with_metadata, Arity};
+/// let functions = list_with_metadata();
+/// let round = functions.iter().find(|f| f.name == "math.round").unwrap();
+/// assert_eq!(round.arity, Arity::Range(1, 3));
+/// assert_eq!(round.signature(), "math.round(x: Number, dp?: Number, mode?: String)");
+/// ```
+pub fn list_with_metadata() -> Vec<FunctionMetadata> {
+    use crate::function::InnerFunctionManager;
+    InnerFunctionManager::new().list_with_metadata()
+}
+
+/// ## Usage
+///
+/// You can list the registered inner functions grouped by namespace via
+/// this method. Built-ins are registered under namespaces like `math` and
+/// `str`, and those outside any are under `""`
+///
+/// ``` rust
+/// use expression_engine::functions_by_namespace;
+/// let namespaces = functions_by_namespace();
+/// let math = &namespaces["math"];
+/// assert!(math.iter().any(|f| f.name == "math.sqrt"));
+/// assert!(namespaces[""].iter().any(|f| f.name == "help"));
+/// ```
+pub fn functions_by_namespace() -> BTreeMap<String, Vec<FunctionMetadata>> {
+    use crate::function::InnerFunctionManager;
+    InnerFunctionManager::new().list_by_namespace()
+}
+
+/// ## Usage
+///
+/// You can make an inner function callable by another name via this
+/// method, e.g. a bare name for one registered under a namespace
+///
+/// ``` rust
+/// use std::sync::Arc;
+/// use expression_engine::{
+///     create_context, execute, register_function, register_function_alias, Value,
+/// };
+/// register_function("text.shout", Arc::new(|params| {
+///     Ok(Value::from(params[0].clone().string()?.to_uppercase()))
+/// }));
+/// register_function_alias("shout", "text.shout");
+/// let ans = execute("text.shout('hi') == shout('hi')", create_context!());
+/// assert_eq!(ans.unwrap(), Value::from(true));
+/// ```
+pub fn register_function_alias(alias: &str, name: &str) {
+    use crate::function::InnerFunctionManager;
+    InnerFunctionManager::new().register_alias(alias, name);
+}
+
+/// ## Usage
+///
+/// You can register a whole [`FunctionLibrary`] as inner functions via this
+/// method, choosing with a [`CollisionPolicy`] what happens to the names
+/// that are already taken
+///
+/// ``` rust
+/// use std::sync::Arc;
+/// use expression_engine::{
+///     create_context, execute, register_library, Arity, CollisionPolicy, ContextFunction,
+///     FunctionLibrary, FunctionMetadata, Value,
+/// };
+/// struct Greetings;
+/// impl FunctionLibrary for Greetings {
+///     fn functions(&self) -> Vec<(FunctionMetadata, Arc<ContextFunction>)> {
+///         vec![(
+///             FunctionMetadata::new("hello", Arity::Exact(1), &[("name", "String")], "Greets."),
+///             Arc::new(|_, params| Ok(Value::from(format!("hello {}", params[0].clone().string()?)))),
+///         )]
+///     }
+/// }
+/// register_library(&Greetings, CollisionPolicy::Error).unwrap();
+/// let ans = execute("hello('bob')", create_context!());
+/// assert_eq!(ans.unwrap(), Value::from("hello bob"));
+/// let err = register_library(&Greetings, CollisionPolicy::Error).unwrap_err();
+/// assert_eq!(err.code(), "E_FUNC_EXISTS");
+/// assert!(register_library(&Greetings, CollisionPolicy::Skip).is_ok());
+/// ```
+pub fn register_library(library: &dyn FunctionLibrary, policy: CollisionPolicy) -> Result<()> {
+    use crate::function::InnerFunctionManager;
+    InnerFunctionManager::new().register_library(library, policy)
+}
+
+/// ## Usage
+///
+/// You can check an expression without evaluating it via this method. It
+/// returns the parse error, or an [`Error::UnknownFunction`] for each
+/// function that neither `ctx` nor the inner functions provide, positioned
+/// at its first call and suggesting
+/// up to three close names among both, followed by an
+/// [`Error::WrongFunctionArity`] for each call of an inner function with a
+/// number of params it doesn't take, see [`ArityCheck`]
+///
+/// ``` rust
+/// use expression_engine::{create_context, validate};
+/// let errors = validate("roud(a, 2)", &create_context!());
+/// assert_eq!(errors[0].to_string(), "unknown function 'roud', did you mean 'round' or 'ord'?");
+/// assert!(validate("round(a, 2)", &create_context!()).is_empty());
+/// let errors = validate("a + round()", &create_context!());
+/// assert_eq!(errors[0].to_string(), "'round' takes 1 to 3 params, got 0 (at 4)");
+/// ```
+pub fn validate(expr: &str, ctx: &context::Context) -> Vec<Error> {
+    use crate::function::InnerFunctionManager;
+    if let Err(err) = parse_expression(expr) {
+        return vec![err];
+    }
+    let calls = match arity::calls(expr) {
+        Ok(calls) => calls,
+        Err(err) => return vec![err],
+    };
+    let manager = InnerFunctionManager::new();
+    let mut unknown = Vec::new();
+    let mut errors = Vec::new();
+    for call in &calls {
+        if ctx.get_func(call.name).is_none()
+            && manager.get(call.name).is_err()
+            && !unknown.contains(&call.name)
+        {
+            unknown.push(call.name);
+            let suggestions = manager.suggestions(call.name, ctx);
+            errors.push(Error::UnknownFunction(
+                call.name.into(),
+                suggestions,
+                Some(call.span.0),
+            ));
+        }
+    }
+    errors.extend(ArityCheck::default().check_calls(&calls, |name| ctx.get_func(name).is_some()));
+    errors
+}
+
+/// ## Usage
+///
+/// You can check an expression for likely mistakes without evaluating it
+/// via this method, e.g. in CI over a repository of rules. Each
+/// [`LintDiagnostic`] names its [`LintRule`], and [`LintConfig`] turns
+/// rules off. Spans are into [`ExprAST::expr`], in order
+///
+/// ``` rust
+/// use expression_engine::{lint, parse_expression, LintConfig, LintRule};
+/// let ast = parse_expression("a = 1; b = 2; (b = 3) ? b : b == b").unwrap();
+/// let found = lint(&ast, &LintConfig::default());
+/// let rules: Vec<&str> = found.iter().map(|d| d.rule.id()).collect();
+/// assert_eq!(rules, ["unused-assignment", "assignment-in-condition", "self-comparison"]);
+/// assert_eq!(found[1].to_string(), "assignment-in-condition: 'b = 3' assigns in a condition, did you mean '=='? (at 12)");
+/// let config = LintConfig { unused_assignment: false, ..LintConfig::default() };
+/// assert_eq!(lint(&ast, &config)[0].rule, LintRule::AssignmentInCondition);
+/// ```
+pub fn lint(ast: &ExprAST, config: &LintConfig) -> Vec<LintDiagnostic> {
+    lint::lint(ast, config)
+}
+
 /// ## Usage
 ///
 /// You can register some prefix operators in advance via this method
@@ -100,7 +513,6 @@ pub fn register_function(name: &str, handler: Arc<function::InnerFunction>) {
 /// ```
 pub fn register_prefix_op(op: &str, handler: Arc<operator::PrefixOpFunc>) {
     use crate::operator::PrefixOpManager;
-    init();
     PrefixOpManager::new().register(op, handler);
 }
 
@@ -126,7 +538,6 @@ pub fn register_prefix_op(op: &str, handler: Arc<operator::PrefixOpFunc>) {
 /// ```
 pub fn register_postfix_op(op: &str, handler: Arc<operator::PostfixOpFunc>) {
     use crate::operator::PostfixOpManager;
-    init();
     PostfixOpManager::new().register(op, handler);
 }
 
@@ -157,28 +568,137 @@ pub fn register_infix_op(
     handler: Arc<operator::InfixOpFunc>,
 ) {
     use crate::operator::InfixOpManager;
-    init();
     InfixOpManager::new().register(op, precedence, op_type, associativity, handler);
 }
 
-fn init() {
-    use crate::init::init;
-    init();
+/// ## Usage
+///
+/// Adds an implementation of a registered infix operator for a pair of
+/// operand types, which is picked before the one the operator was
+/// registered with. That one still handles every other pair. Fails with
+/// [`Error::InfixOpNotRegistered`] for an operator that isn't registered.
+///
+/// ``` rust
+/// use std::sync::Arc;
+/// use expression_engine::{register_typed_infix_op, create_context, execute, Value, ValueType};
+/// register_typed_infix_op(
+///     "*",
+///     ValueType::String,
+///     ValueType::Number,
+///     Arc::new(|left, right| Ok(Value::from(left.string()?.repeat(right.integer()? as usize)))),
+/// )
+/// .unwrap();
+/// assert_eq!(execute("'ab' * 3", create_context!()).unwrap(), Value::from("ababab"));
+/// assert_eq!(execute("2 * 3", create_context!()).unwrap(), Value::from(6));
+/// ```
+pub fn register_typed_infix_op(
+    op: &str,
+    lhs: ValueType,
+    rhs: ValueType,
+    handler: Arc<operator::InfixOpFunc>,
+) -> Result<()> {
+    use crate::operator::InfixOpManager;
+    InfixOpManager::new().register_typed(op, lhs, rhs, handler)
+}
+
+/// ## Usage
+///
+/// You can list the infix operators with their precedence and
+/// associativity via this method, tightest-binding first. Operators
+/// registered with [`register_infix_op`] are included
+///
+/// ``` rust
+/// use expression_engine::{precedence_table, InfixOpAssociativity};
+/// let table = precedence_table();
+/// let and = table.iter().find(|(op, _, _)| op == "&&").unwrap();
+/// let eq = table.iter().find(|(op, _, _)| op == "==").unwrap();
+/// assert!(eq.1 > and.1);
+/// assert_eq!(and.2, InfixOpAssociativity::LEFT);
+/// ```
+pub fn precedence_table() -> Vec<(String, i32, InfixOpAssociativity)> {
+    use crate::operator::InfixOpManager;
+    InfixOpManager::new().precedence_table()
 }
 
 pub type Value = value::Value;
+pub type ValueType = value::ValueType;
+pub type Map = value::Map;
+pub type NumberFormat = format::NumberFormat;
+pub type Rounding = format::Rounding;
 pub type Context = context::Context;
+pub type ContextValue = context::ContextValue;
+pub type CsvOptions = csv::CsvOptions;
+pub type EvalOptions = options::EvalOptions;
+pub type MathContext = options::MathContext;
+pub type ParseOptions = options::ParseOptions;
+pub type AstMetrics = metrics::AstMetrics;
+pub type ComplexityLimits = metrics::ComplexityLimits;
+pub type LintConfig = lint::LintConfig;
+pub type LintDiagnostic = lint::LintDiagnostic;
+pub type LintRule = lint::LintRule;
+pub type Warning = warning::Warning;
+pub type AuditRecord = audit::AuditRecord;
+pub type ArityCheck = arity::ArityCheck;
+pub type LimitViolation = metrics::LimitViolation;
+pub type FunctionMetadata = function::FunctionMetadata;
+pub type Arity = function::Arity;
+pub type Truthiness = options::Truthiness;
+pub type SemanticsVersion = options::SemanticsVersion;
+pub type MissingReferencePolicy = options::MissingReferencePolicy;
+pub type Limits = options::Limits;
+pub type Error = error::Error;
+pub type ErrorCategory = error::ErrorCategory;
 pub type Result<T> = define::Result<T>;
 pub type ExprAST<'a> = parser::ExprAST<'a>;
+pub type Evaluator = evaluator::Evaluator;
+pub type Literal<'a> = parser::Literal<'a>;
+pub type StatementSpan = parser::StatementSpan;
+pub type ExprArena<'a> = arena::ExprArena<'a>;
+pub type Expression<'a> = source::Expression<'a>;
+pub type Node<'a> = arena::Node<'a>;
+pub type NodeId = arena::NodeId;
+pub type Children = arena::Children;
 pub type InfixOpType = operator::InfixOpType;
 pub type InfixOpAssociativity = operator::InfixOpAssociativity;
+pub type Tokenizer<'a> = tokenizer::Tokenizer<'a>;
+pub type SpannedTokens<'a> = tokenizer::SpannedTokens<'a>;
+pub type SpannedToken<'a> = token::SpannedToken<'a>;
+pub type TokenKind = token::TokenKind;
+pub type Span = token::Span;
+pub type Compiler = compiler::Compiler;
+pub type Program = compiler::Program;
+pub type Instruction = compiler::Instruction;
+pub type Vm = vm::Vm;
+pub type TraceEvent = trace::TraceEvent;
+pub type Explanation = explain::Explanation;
+pub type Branch = explain::Branch;
+pub type TraceHook = trace::TraceHook;
+pub type AsyncFunction = function::AsyncFunction;
+pub type ContextFunction = function::ContextFunction;
+pub type BoxFuture<'a, T> = function::BoxFuture<'a, T>;
+pub type CollisionPolicy = function::CollisionPolicy;
+pub use function::FunctionLibrary;
+#[cfg(feature = "builtin-logic")]
+pub use function::LogicLibrary;
+#[cfg(feature = "builtin-math")]
+pub use function::MathLibrary;
+#[cfg(feature = "std")]
+pub use provider::{ClockProvider, EnvProvider, FixedClock, FixedEnv, ProcessEnv, SystemClock};
 
-#[cfg(test)]
+#[cfg(all(test, feature = "default"))]
 mod tests {
     use crate::{
-        create_context, execute, parse_expression, register_function, register_infix_op,
-        register_postfix_op, register_prefix_op, InfixOpAssociativity, InfixOpType, Value,
+        create_context, execute, functions_by_namespace, list_with_metadata, parse_expression,
+        precedence_table, register_deterministic_function, register_function,
+        register_function_alias, register_function_with_metadata, register_infix_op,
+        register_library, register_postfix_op, register_prefix_op, register_typed_infix_op,
+        registered_functions, validate, Arity, CollisionPolicy, Compiler, Context, ContextFunction,
+        Error, EvalOptions, FunctionLibrary, FunctionMetadata, InfixOpAssociativity, InfixOpType,
+        LogicLibrary, MathLibrary, Value, ValueType, Vm,
     };
+    use rstest::rstest;
+    use std::fmt;
+    use std::sync::atomic::{AtomicUsize, Ordering};
     use std::sync::Arc;
     #[test]
     fn test_execute() {
@@ -192,6 +712,60 @@ mod tests {
         assert_eq!(ans, 21.into())
     }
 
+    #[test]
+    fn test_precedence_table() {
+        use crate::operator::InfixOpAssociativity::*;
+        let expected = [
+            ("%", 120, LEFT),
+            ("*", 120, LEFT),
+            ("/", 120, LEFT),
+            ("+", 110, LEFT),
+            ("-", 110, LEFT),
+            ("<<", 100, LEFT),
+            (">>", 100, LEFT),
+            ("<", 65, LEFT),
+            ("<=", 65, LEFT),
+            (">", 65, LEFT),
+            (">=", 65, LEFT),
+            ("beginWith", 65, LEFT),
+            ("between", 65, LEFT),
+            ("contains", 65, LEFT),
+            ("containsAll", 65, LEFT),
+            ("containsAny", 65, LEFT),
+            ("endWith", 65, LEFT),
+            ("in", 65, LEFT),
+            ("!=", 60, LEFT),
+            ("==", 60, LEFT),
+            ("&", 58, LEFT),
+            ("^", 56, LEFT),
+            ("|", 54, LEFT),
+            ("&&", 50, LEFT),
+            ("||", 40, LEFT),
+            ("??", 30, LEFT),
+            ("%=", 20, RIGHT),
+            ("&=", 20, RIGHT),
+            ("*=", 20, RIGHT),
+            ("+=", 20, RIGHT),
+            ("-=", 20, RIGHT),
+            ("/=", 20, RIGHT),
+            ("<<=", 20, RIGHT),
+            ("=", 20, RIGHT),
+            (">>=", 20, RIGHT),
+            ("^=", 20, RIGHT),
+            ("|=", 20, RIGHT),
+        ];
+        // Other tests register operators of their own.
+        let table: Vec<_> = precedence_table()
+            .into_iter()
+            .filter(|(op, _, _)| expected.iter().any(|(name, _, _)| name == op))
+            .collect();
+        let expected: Vec<_> = expected
+            .iter()
+            .map(|(op, precedence, associativity)| (op.to_string(), *precedence, *associativity))
+            .collect();
+        assert_eq!(table, expected);
+    }
+
     #[test]
     fn test_parse_expression() {
         let input = "a + 3*2+test()+[1,2,3,'haha']";
@@ -200,7 +774,7 @@ mod tests {
 
     #[test]
     fn test_register_function() {
-        register_function("test", Arc::new(|_| return Ok(Value::from("test"))));
+        register_function("test", Arc::new(|_| Ok(Value::from("test"))));
         let input = "test()";
         let ans = execute(input, create_context!());
         assert!(ans.is_ok());
@@ -257,4 +831,474 @@ mod tests {
         assert!(ans.is_ok());
         assert_eq!(ans.unwrap(), Value::from(89));
     }
+
+    #[test]
+    fn test_register_typed_infix_op() {
+        register_typed_infix_op(
+            "+",
+            ValueType::List,
+            ValueType::List,
+            Arc::new(|left, right| {
+                let mut list = left.list()?;
+                list.extend(right.list()?);
+                Ok(Value::List(list))
+            }),
+        )
+        .unwrap();
+        let ctx = || create_context!("a" => vec![Value::from(1)], "n" => 2);
+        let ast = parse_expression("a + [2, n] + a").unwrap();
+        let expected = Value::from(vec![1.into(), 2.into(), 2.into(), 1.into()]);
+        assert_eq!(ast.exec(&mut ctx()).unwrap(), expected);
+        let program = Compiler::compile(&ast).unwrap();
+        assert_eq!(Vm::run(&program, &mut ctx()).unwrap(), expected);
+        // Numbers keep their implementation, and other pairs still fail.
+        assert_eq!(execute("n + 1.5", ctx()).unwrap(), Value::from(3.5));
+        let err = execute("a + 1", ctx()).unwrap_err();
+        assert!(matches!(err.root(), Error::ShouldBeNumber()));
+
+        let err = register_typed_infix_op(
+            "+++",
+            ValueType::List,
+            ValueType::List,
+            Arc::new(|_, _| Ok(Value::None)),
+        )
+        .unwrap_err();
+        assert_eq!(err.code(), "E_REF_INFIX_OP_NOT_FOUND");
+    }
+
+    #[derive(Debug, PartialEq)]
+    struct InsufficientFunds {
+        missing: i64,
+    }
+
+    impl fmt::Display for InsufficientFunds {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "missing {}", self.missing)
+        }
+    }
+
+    impl std::error::Error for InsufficientFunds {}
+
+    #[test]
+    fn test_register_function_custom_error() {
+        register_function(
+            "withdraw",
+            Arc::new(|params| {
+                let amount = params[0].clone().integer()?;
+                Err(Error::custom_with_source(
+                    "withdraw failed",
+                    InsufficientFunds {
+                        missing: amount - 10,
+                    },
+                ))
+            }),
+        );
+        for (input, message, missing) in [
+            ("1 + withdraw(25)", "withdraw failed", Some(15)),
+            ("[withdraw(12)]", "withdraw failed", Some(2)),
+            ("1 + fail()", "ctx function failed", None),
+        ] {
+            let ctx = create_context!(
+                "fail" => Arc::new(|_| Err(Error::custom("ctx function failed")))
+            );
+            let err = execute(input, ctx).unwrap_err();
+            assert!(matches!(err, Error::Custom { .. }), "{:?}", err);
+            assert_eq!(err.to_string(), message);
+            assert_eq!(
+                err.downcast_ref::<InsufficientFunds>(),
+                missing
+                    .map(|missing| InsufficientFunds { missing })
+                    .as_ref()
+            );
+        }
+    }
+
+    fn memoized(ctx: Context) -> Context {
+        let mut ctx = ctx;
+        ctx.set_options(EvalOptions {
+            memoize: true,
+            ..EvalOptions::default()
+        });
+        ctx
+    }
+
+    #[test]
+    fn test_memoize() {
+        static CALLS: AtomicUsize = AtomicUsize::new(0);
+        register_deterministic_function(
+            "countedDouble",
+            Arc::new(|params| {
+                CALLS.fetch_add(1, Ordering::SeqCst);
+                Ok(Value::from(params[0].clone().integer()? * 2))
+            }),
+        );
+        let input = "countedDouble(a + 1) + countedDouble(a + 1)";
+        for (memoize, calls) in [(true, 1), (false, 2)] {
+            CALLS.store(0, Ordering::SeqCst);
+            let mut ctx = create_context!("a" => 2);
+            if memoize {
+                ctx = memoized(ctx);
+            }
+            assert_eq!(execute(input, ctx).unwrap(), Value::from(12));
+            assert_eq!(CALLS.load(Ordering::SeqCst), calls);
+        }
+    }
+
+    #[test]
+    fn test_memoize_cleared_by_assignment() {
+        static CALLS: AtomicUsize = AtomicUsize::new(0);
+        register_deterministic_function(
+            "memoDouble",
+            Arc::new(|params| {
+                CALLS.fetch_add(1, Ordering::SeqCst);
+                Ok(Value::from(params[0].clone().integer()? * 2))
+            }),
+        );
+        let input = "a = 1; x = memoDouble(a); a = 2; x + memoDouble(a)";
+        let ans = execute(input, memoized(create_context!())).unwrap();
+        assert_eq!(ans, Value::from(6));
+        assert_eq!(CALLS.load(Ordering::SeqCst), 2);
+    }
+
+    // The middle of a chained comparison is in both comparisons.
+    #[test]
+    fn test_memoize_comparison_chain() {
+        static CALLS: AtomicUsize = AtomicUsize::new(0);
+        register_deterministic_function(
+            "chainDouble",
+            Arc::new(|params| {
+                CALLS.fetch_add(1, Ordering::SeqCst);
+                Ok(Value::from(params[0].clone().integer()? * 2))
+            }),
+        );
+        let input = "0 < chainDouble(a) <= 10";
+        for (memoize, calls) in [(true, 1), (false, 2)] {
+            CALLS.store(0, Ordering::SeqCst);
+            let mut ctx = create_context!("a" => 2);
+            if memoize {
+                ctx = memoized(ctx);
+            }
+            assert_eq!(execute(input, ctx).unwrap(), Value::from(true));
+            assert_eq!(CALLS.load(Ordering::SeqCst), calls);
+        }
+    }
+
+    #[test]
+    fn test_memoize_impure() {
+        static CALLS: AtomicUsize = AtomicUsize::new(0);
+        register_function(
+            "memoCounter",
+            Arc::new(|_| Ok(Value::from(CALLS.fetch_add(1, Ordering::SeqCst) as i64))),
+        );
+        let ans = execute("memoCounter() + memoCounter()", memoized(create_context!()));
+        assert_eq!(ans.unwrap(), Value::from(1));
+
+        // Shadowed by the context, and read through a reference.
+        static SHADOWED: AtomicUsize = AtomicUsize::new(0);
+        register_deterministic_function("memoShadowed", Arc::new(|_| Ok(Value::from(0))));
+        let ctx = memoized(create_context!(
+            "memoShadowed" => Arc::new(|_| {
+                Ok(Value::from(SHADOWED.fetch_add(1, Ordering::SeqCst) as i64))
+            })
+        ));
+        let input = "max(memoShadowed()) + max(memoShadowed()) + len([memoShadowed, memoShadowed])";
+        assert_eq!(execute(input, ctx).unwrap(), Value::from(3));
+        assert_eq!(SHADOWED.load(Ordering::SeqCst), 4);
+    }
+
+    #[test]
+    fn test_help() {
+        let ans = execute("help()", create_context!())
+            .unwrap()
+            .list()
+            .unwrap();
+        for signature in [
+            "help(name?: String)",
+            "list.len(x: Any)",
+            "math.max(...values: Number)",
+            "date.now()",
+            "math.pow(x: Number, y: Number)",
+            "list.range(start: Number, end?: Number, step?: Number)",
+        ] {
+            assert!(ans.contains(&Value::from(signature)), "{}", signature);
+        }
+        let ans = execute("help('ifnull')", create_context!()).unwrap();
+        assert_eq!(
+            ans,
+            "ifnull(x: Any, fallback: Any)\nfallback if x is None, otherwise x.".into()
+        );
+
+        register_function("helpUndocumented", Arc::new(|_| Ok(Value::None)));
+        let ans = execute("help('helpUndocumented')", create_context!()).unwrap();
+        assert_eq!(ans, "helpUndocumented(...)".into());
+
+        let err = execute("help('ifnul')", create_context!()).unwrap_err();
+        assert_eq!(
+            err.root().to_string(),
+            "unknown function 'ifnul', did you mean 'ifnull'?"
+        );
+        assert!(execute("help(1)", create_context!()).is_err());
+        assert!(execute("help('a', 'b')", create_context!()).is_err());
+    }
+
+    #[test]
+    fn test_list_with_metadata() {
+        let functions = list_with_metadata();
+        for name in [
+            "math.min",
+            "math.round",
+            "math.sqrt",
+            "str.format",
+            "coalesce",
+            "default",
+            "list.len",
+            "list.repeat",
+            "env",
+        ] {
+            let metadata = functions.iter().find(|f| f.name == name).unwrap();
+            assert!(!metadata.description.is_empty(), "{}", name);
+        }
+        // The flags match what memoization uses.
+        let deterministic: Vec<&str> = functions
+            .iter()
+            .filter(|f| f.deterministic)
+            .map(|f| f.name.as_str())
+            .collect();
+        for name in [
+            "math.min",
+            "math.max",
+            "math.sum",
+            "math.mul",
+            "list.len",
+            "list.size",
+            "math.sqrt",
+            "math.pow",
+        ] {
+            assert!(deterministic.contains(&name), "{}", name);
+        }
+        for name in ["math.round", "list.range", "date.now", "codec.uuid", "help"] {
+            assert!(!deterministic.contains(&name), "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_register_function_with_metadata() {
+        static CALLS: AtomicUsize = AtomicUsize::new(0);
+        register_function_with_metadata(
+            FunctionMetadata::new("metaTwice", Arity::Exact(1), &[("x", "Number")], "Twice x.")
+                .deterministic(),
+            Arc::new(|_, params| {
+                CALLS.fetch_add(1, Ordering::SeqCst);
+                Ok(Value::from(params[0].clone().integer()? * 2))
+            }),
+        );
+        let ans = execute("metaTwice(2) + metaTwice(2)", memoized(create_context!()));
+        assert_eq!(ans.unwrap(), Value::from(8));
+        assert_eq!(CALLS.load(Ordering::SeqCst), 1);
+        let ans = execute("help('metaTwice')", create_context!()).unwrap();
+        assert_eq!(ans, "metaTwice(x: Number)\nTwice x.".into());
+
+        // Registering again without metadata drops it.
+        register_function("metaTwice", Arc::new(|_| Ok(Value::None)));
+        let metadata = list_with_metadata()
+            .into_iter()
+            .find(|f| f.name == "metaTwice")
+            .unwrap();
+        assert_eq!(metadata.description, "");
+        assert!(!metadata.deterministic);
+    }
+
+    #[rstest]
+    #[case("math.sqrt(16) + sqrt(9)", 7.into())]
+    #[case("str.capitalize('ada') == capitalize('ada')", true.into())]
+    #[case("list.len([1, 2]) == len([3, 4])", true.into())]
+    #[case("math.max(a.b, 2)", 3.into())]
+    #[case("text.shout(a.c)", "HI".into())]
+    #[case("shout('x')", "X".into())]
+    fn test_namespaced_functions(#[case] input: &str, #[case] expected: Value) {
+        let mut ctx = create_context!("a" => Value::Map(
+            [("b".into(), 3.into()), ("c".into(), "hi".into())].into_iter().collect()
+        ));
+        ctx.set_namespaced_func(
+            "text",
+            "shout",
+            Arc::new(|params| Ok(Value::from(params[0].clone().string()?.to_uppercase()))),
+            true,
+        );
+        assert_eq!(execute(input, ctx).unwrap(), expected);
+    }
+
+    #[rstest]
+    // A dotted name right before `(` is a namespaced call...
+    #[case(
+        "a.b(c)",
+        "Function AST: name: a.b, params: [Reference AST: reference: c,]"
+    )]
+    // ...while a member in parens is a value, and without first-class
+    // functions the `(c)` after it is the next statement.
+    #[case(
+        "(a.b)(c)",
+        "Chain AST: Reference AST: reference: a.b;Reference AST: reference: c;"
+    )]
+    fn test_namespaced_call_or_member(#[case] input: &str, #[case] dump: &str) {
+        assert_eq!(parse_expression(input).unwrap().dump(), dump);
+    }
+
+    #[test]
+    fn test_function_alias() {
+        static CALLS: AtomicUsize = AtomicUsize::new(0);
+        register_deterministic_function(
+            "aliasNs.count",
+            Arc::new(|_| Ok(CALLS.fetch_add(1, Ordering::SeqCst).into())),
+        );
+        register_function_alias("aliasCount", "aliasNs.count");
+        // The alias shares the function's flags and metadata.
+        let ans = execute("aliasCount() + aliasCount()", memoized(create_context!()));
+        assert_eq!(ans.unwrap(), 0.into());
+        let ans = execute("help('aliasCount')", create_context!()).unwrap();
+        assert_eq!(ans, "aliasNs.count(...)".into());
+        let listed: Vec<String> = list_with_metadata().into_iter().map(|f| f.name).collect();
+        assert!(listed.contains(&"aliasNs.count".to_string()));
+        assert!(!listed.contains(&"aliasCount".to_string()));
+        assert!(registered_functions().contains(&"aliasCount".to_string()));
+
+        // Registering under the alias replaces it.
+        register_function("aliasCount", Arc::new(|_| Ok("own".into())));
+        let ans = execute("aliasCount()", create_context!());
+        assert_eq!(ans.unwrap(), "own".into());
+    }
+
+    #[test]
+    fn test_functions_by_namespace() {
+        let namespaces = functions_by_namespace();
+        for (namespace, name) in [
+            ("math", "math.round"),
+            ("math", "math.random"),
+            ("str", "str.padLeft"),
+            ("list", "list.range"),
+            ("date", "date.now"),
+            ("", "help"),
+            ("", "coalesce"),
+        ] {
+            let functions = &namespaces[namespace];
+            assert!(functions.iter().any(|f| f.name == name), "{}", name);
+            assert!(functions
+                .iter()
+                .all(|f| f.namespace().unwrap_or_default() == namespace));
+        }
+    }
+
+    // Functions returning a constant each.
+    struct Constants(Vec<(&'static str, i32)>);
+
+    impl FunctionLibrary for Constants {
+        fn functions(&self) -> Vec<(FunctionMetadata, Arc<ContextFunction>)> {
+            self.0
+                .iter()
+                .map(|&(name, value)| {
+                    let f: Arc<ContextFunction> = Arc::new(move |_, _| Ok(value.into()));
+                    (FunctionMetadata::new(name, Arity::Exact(0), &[], ""), f)
+                })
+                .collect()
+        }
+    }
+
+    #[test]
+    fn test_register_library() {
+        let eval = |input: &str| execute(input, create_context!()).unwrap();
+        let first = Constants(vec![("libTest.a", 1), ("libTest.b", 2)]);
+        register_library(&first, CollisionPolicy::Error).unwrap();
+        assert_eq!(eval("libTest.a() + libTest.b()"), 3.into());
+        assert_eq!(eval("help('libTest.b')"), "libTest.b()".into());
+
+        let second = Constants(vec![("libTest.b", 20), ("libTest.c", 30)]);
+        let err = register_library(&second, CollisionPolicy::Error).unwrap_err();
+        assert!(matches!(&err, Error::FunctionExists(name) if name == "libTest.b"));
+        assert_eq!(err.code(), "E_FUNC_EXISTS");
+        assert!(!registered_functions().contains(&"libTest.c".to_string()));
+        register_library(&second, CollisionPolicy::Skip).unwrap();
+        assert_eq!(
+            eval("[libTest.b(), libTest.c()]"),
+            vec![2.into(), 30.into()].into()
+        );
+        register_library(&second, CollisionPolicy::Override).unwrap();
+        assert_eq!(eval("libTest.b()"), 20.into());
+
+        // Aliases and names a library has twice are taken too.
+        register_function_alias("libTestAlias", "libTest.a");
+        let err = register_library(
+            &Constants(vec![("libTestAlias", 3)]),
+            CollisionPolicy::Error,
+        );
+        assert!(matches!(err, Err(Error::FunctionExists(name)) if name == "libTestAlias"));
+        let twice = Constants(vec![("libTest.d", 4), ("libTest.d", 5)]);
+        let err = register_library(&twice, CollisionPolicy::Error);
+        assert!(matches!(err, Err(Error::FunctionExists(name)) if name == "libTest.d"));
+        assert!(!registered_functions().contains(&"libTest.d".to_string()));
+    }
+
+    #[test]
+    fn test_builtin_libraries() {
+        // The built-ins of the enabled features are registered through their
+        // libraries, the math ones in the `math` namespace.
+        let namespaces = functions_by_namespace();
+        for (library, namespace) in [
+            (&MathLibrary as &dyn FunctionLibrary, "math"),
+            (&LogicLibrary, ""),
+        ] {
+            for (metadata, _) in library.functions() {
+                let name = match namespace {
+                    "" => metadata.name.clone(),
+                    _ => format!("{}.{}", namespace, metadata.name),
+                };
+                let built_in = namespaces[namespace].iter().find(|f| f.name == name);
+                assert_eq!(
+                    built_in.map(|f| f.deterministic),
+                    Some(metadata.deterministic)
+                );
+            }
+            // So registering them again collides, with the bare aliases of
+            // the math ones too.
+            let err = register_library(library, CollisionPolicy::Error);
+            assert!(matches!(err, Err(Error::FunctionExists(_))));
+            register_library(library, CollisionPolicy::Skip).unwrap();
+        }
+        let err = register_library(&Constants(vec![("sqrt", 0)]), CollisionPolicy::Error);
+        assert!(matches!(err, Err(Error::FunctionExists(name)) if name == "sqrt"));
+        let ans = execute("[sqrt(16), coalesce(null, 1)]", create_context!());
+        assert_eq!(ans.unwrap(), vec![4.into(), 1.into()].into());
+    }
+
+    #[rstest]
+    #[case("round(a, 2) + f(1)", &[])]
+    #[case("roud(a, 2)", &["unknown function 'roud', did you mean 'round' or 'ord'?"])]
+    #[case("[summ(1), summ(2), xyzzy()]", &[
+        "unknown function 'summ', did you mean 'sum'?",
+        "unknown function 'xyzzy'",
+    ])]
+    #[case("a ? mx(1) : 0", &["unknown function 'mx', did you mean 'max'?"])]
+    #[case("fn(1) + ff(2)", &[
+        "unknown function 'fn', did you mean 'f' or 'ln'?",
+        "unknown function 'ff', did you mean 'f'?",
+    ])]
+    #[case("round(", &["no close delim"])]
+    fn test_validate(#[case] input: &str, #[case] errors: &[&str]) {
+        let ctx = create_context!("f" => Arc::new(|_| Ok(Value::None)));
+        let ans: Vec<String> = validate(input, &ctx).iter().map(Error::to_string).collect();
+        assert_eq!(ans, errors);
+    }
+
+    #[rstest]
+    #[case("[summ(1), summ(2), xyzzy()]", &[Some(1), Some(19)])]
+    #[case("a ? mx(1) : 0", &[Some(4)])]
+    #[case("round(", &[Some(6)])]
+    #[case("(1 2", &[Some(3)])]
+    #[case("a + round()", &[Some(4)])]
+    fn test_validate_position(#[case] input: &str, #[case] positions: &[Option<usize>]) {
+        let ans: Vec<_> = validate(input, &create_context!())
+            .iter()
+            .map(Error::position)
+            .collect();
+        assert_eq!(ans, positions);
+    }
 }
@@ -0,0 +1,341 @@
+use crate::function::InnerFunctionManager;
+use crate::operator::InfixOpManager;
+use crate::parser::ExprAST;
+use crate::token::Span;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+
+/// A rule of [`crate::lint`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LintRule {
+    /// A comparison of a value to itself, like `a == a`, which is always
+    /// true or always false.
+    SelfComparison,
+    /// An assignment in the condition of a ternary, like `a = 1 ? x : y`,
+    /// which is usually a typo of `==`.
+    AssignmentInCondition,
+    /// A ternary whose condition has no references or calls, so it always
+    /// takes the same branch.
+    ConstantCondition,
+    /// A statement of a chain that sets a variable no later statement reads.
+    /// The last statement isn't one, its value is the result of the chain.
+    UnusedAssignment,
+    /// A call of a deterministic inner function with only constant params,
+    /// which could be precomputed.
+    ConstantCall,
+}
+
+impl LintRule {
+    /// The id diagnostics of the rule are reported under, e.g.
+    /// `self-comparison`.
+    pub fn id(&self) -> &'static str {
+        match self {
+            Self::SelfComparison => "self-comparison",
+            Self::AssignmentInCondition => "assignment-in-condition",
+            Self::ConstantCondition => "constant-condition",
+            Self::UnusedAssignment => "unused-assignment",
+            Self::ConstantCall => "constant-call",
+        }
+    }
+}
+
+/// Which rules [`crate::lint`] checks, all of them by default.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct LintConfig {
+    pub self_comparison: bool,
+    pub assignment_in_condition: bool,
+    pub constant_condition: bool,
+    pub unused_assignment: bool,
+    pub constant_call: bool,
+}
+
+impl Default for LintConfig {
+    fn default() -> Self {
+        Self {
+            self_comparison: true,
+            assignment_in_condition: true,
+            constant_condition: true,
+            unused_assignment: true,
+            constant_call: true,
+        }
+    }
+}
+
+impl LintConfig {
+    /// Whether `rule` is checked.
+    pub fn enabled(&self, rule: LintRule) -> bool {
+        match rule {
+            LintRule::SelfComparison => self.self_comparison,
+            LintRule::AssignmentInCondition => self.assignment_in_condition,
+            LintRule::ConstantCondition => self.constant_condition,
+            LintRule::UnusedAssignment => self.unused_assignment,
+            LintRule::ConstantCall => self.constant_call,
+        }
+    }
+}
+
+/// A finding of [`crate::lint`]. A tree doesn't keep the text it was
+/// parsed from, so the span is of the node in [`ExprAST::expr`].
+#[derive(Clone, PartialEq, Debug)]
+pub struct LintDiagnostic {
+    pub rule: LintRule,
+    pub message: String,
+    pub span: Span,
+}
+
+impl fmt::Display for LintDiagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} (at {})",
+            self.rule.id(),
+            self.message,
+            self.span.0
+        )
+    }
+}
+
+const COMPARISONS: [&str; 6] = ["==", "!=", "<", "<=", ">", ">="];
+
+pub(crate) fn lint(ast: &ExprAST, config: &LintConfig) -> Vec<LintDiagnostic> {
+    let infix = InfixOpManager::new();
+    let functions = InnerFunctionManager::new();
+    let (_, spans) = ast.layout();
+    let mut found = Vec::new();
+    let mut report = |rule: LintRule, node: &ExprAST, message: String| {
+        if config.enabled(rule) {
+            found.push(LintDiagnostic {
+                rule,
+                message,
+                span: spans[&(node as *const ExprAST as usize)],
+            });
+        }
+    };
+    let mut stack = vec![ast];
+    while let Some(expr) = stack.pop() {
+        match expr {
+            ExprAST::Binary(op, lhs, rhs)
+                if COMPARISONS.contains(op) && lhs == rhs && is_pure(lhs, &infix) =>
+            {
+                report(
+                    LintRule::SelfComparison,
+                    expr,
+                    format!("'{}' is compared to itself", lhs),
+                );
+            }
+            ExprAST::Ternary(condition, ..) => {
+                if is_constant(condition, &infix) {
+                    report(
+                        LintRule::ConstantCondition,
+                        condition,
+                        format!("the condition '{}' is constant", condition),
+                    );
+                }
+                // Through `&&`, `||` and `!`, an operand is a condition too.
+                let mut conditions = vec![condition.as_ref()];
+                while let Some(condition) = conditions.pop() {
+                    match condition {
+                        ExprAST::Binary(op, lhs, rhs) if *op == "&&" || *op == "||" => {
+                            conditions.extend([lhs.as_ref(), rhs.as_ref()])
+                        }
+                        ExprAST::Unary("!", rhs) => conditions.push(rhs),
+                        ExprAST::Binary(op, ..) if infix.is_setter(op) => {
+                            let hint = match *op {
+                                "=" => ", did you mean '=='?",
+                                _ => "",
+                            };
+                            report(
+                                LintRule::AssignmentInCondition,
+                                condition,
+                                format!("'{}' assigns in a condition{}", condition, hint),
+                            );
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            ExprAST::Stmt(exprs) => {
+                for (i, stmt) in exprs.iter().enumerate().take(exprs.len().saturating_sub(1)) {
+                    if let ExprAST::Binary(op, target, _) = stmt {
+                        if let ExprAST::Reference(name) = target.as_ref() {
+                            if infix.is_setter(op) && !read_later(&exprs[i + 1..], name) {
+                                report(
+                                    LintRule::UnusedAssignment,
+                                    stmt,
+                                    format!("'{}' is set but never read later", name),
+                                );
+                            }
+                        }
+                    }
+                }
+            }
+            ExprAST::Function(name, params)
+                if !params.is_empty()
+                    && functions.is_deterministic(name)
+                    && params.iter().all(|param| is_constant(param, &infix)) =>
+            {
+                report(
+                    LintRule::ConstantCall,
+                    expr,
+                    format!(
+                        "'{}' only has constant params, it could be precomputed",
+                        expr
+                    ),
+                );
+            }
+            _ => {}
+        }
+        stack.extend(expr.children().into_iter().rev());
+    }
+    found.sort_by_key(|diagnostic| (diagnostic.span.0, diagnostic.span.1));
+    found
+}
+
+// Whether evaluating `expr` twice gives the same value: it doesn't call a
+// function, which could be random, nor assign.
+fn is_pure(expr: &ExprAST, infix: &InfixOpManager) -> bool {
+    let mut stack = vec![expr];
+    while let Some(expr) = stack.pop() {
+        match expr {
+            ExprAST::Function(..) => return false,
+            ExprAST::Binary(op, ..) if infix.is_setter(op) => return false,
+            _ => stack.extend(expr.children()),
+        }
+    }
+    true
+}
+
+// Whether `expr` is made of literals only, so its value is known before
+// it's evaluated.
+fn is_constant(expr: &ExprAST, infix: &InfixOpManager) -> bool {
+    let mut stack = vec![expr];
+    while let Some(expr) = stack.pop() {
+        match expr {
+            ExprAST::Reference(_) | ExprAST::Function(..) | ExprAST::Stmt(_) | ExprAST::None => {
+                return false
+            }
+            ExprAST::Binary(op, ..) if infix.is_setter(op) => return false,
+            _ => stack.extend(expr.children()),
+        }
+    }
+    true
+}
+
+// Whether one of `stmts` reads the variable `name` before a statement sets it
+// again with a plain `=`.
+fn read_later(stmts: &[ExprAST], name: &str) -> bool {
+    for stmt in stmts {
+        if reads(stmt, name) {
+            return true;
+        }
+        if let ExprAST::Binary("=", target, _) = stmt {
+            if matches!(target.as_ref(), ExprAST::Reference(target) if *target == name) {
+                return false;
+            }
+        }
+    }
+    false
+}
+
+// Whether `expr` reads the variable `name`. The target of a plain `=` is
+// written, not read, but `+=` and the like read it too.
+fn reads(expr: &ExprAST, name: &str) -> bool {
+    let mut stack = vec![expr];
+    while let Some(expr) = stack.pop() {
+        match expr {
+            ExprAST::Reference(reference) if *reference == name => return true,
+            ExprAST::Binary("=", target, value)
+                if matches!(target.as_ref(), ExprAST::Reference(_)) =>
+            {
+                stack.push(value)
+            }
+            _ => stack.extend(expr.children()),
+        }
+    }
+    false
+}
+
+#[cfg(all(test, feature = "default"))]
+mod tests {
+    use super::{LintConfig, LintRule};
+    use crate::{lint, parse_expression};
+    use rstest::rstest;
+
+    fn diagnostics(input: &str, config: &LintConfig) -> Vec<(LintRule, String)> {
+        let ast = parse_expression(input).unwrap();
+        let expr = ast.expr();
+        lint(&ast, config)
+            .into_iter()
+            .map(|d| (d.rule, expr[d.span.0..d.span.1].to_string()))
+            .collect()
+    }
+
+    #[rstest]
+    #[case("a == a", LintRule::SelfComparison, "a == a")]
+    #[case("x + (a.b >= a.b)", LintRule::SelfComparison, "a.b >= a.b")]
+    #[case("(x = 1) ? a : b", LintRule::AssignmentInCondition, "x = 1")]
+    #[case("y && !(x += 1) ? a : b", LintRule::AssignmentInCondition, "x += 1")]
+    #[case("1 > 2 ? a : b", LintRule::ConstantCondition, "1 > 2")]
+    #[case("f(true ? a : b)", LintRule::ConstantCondition, "true")]
+    #[case("a = 1; b = 2; b", LintRule::UnusedAssignment, "a = 1")]
+    #[case("a = 1; a = 2; a", LintRule::UnusedAssignment, "a = 1")]
+    #[case("x + pow(2, 10)", LintRule::ConstantCall, "pow(2,10)")]
+    #[case("max([1, 2], -3)", LintRule::ConstantCall, "max([1,2],- 3)")]
+    fn test_lint(#[case] input: &str, #[case] rule: LintRule, #[case] span: &str) {
+        let all = LintConfig::default();
+        assert_eq!(diagnostics(input, &all), [(rule, span.to_string())]);
+        let mut config = all;
+        match rule {
+            LintRule::SelfComparison => config.self_comparison = false,
+            LintRule::AssignmentInCondition => config.assignment_in_condition = false,
+            LintRule::ConstantCondition => config.constant_condition = false,
+            LintRule::UnusedAssignment => config.unused_assignment = false,
+            LintRule::ConstantCall => config.constant_call = false,
+        }
+        assert_eq!(diagnostics(input, &config), []);
+    }
+
+    #[rstest]
+    #[case("a == b")]
+    #[case("a == -a")]
+    #[case("random() == random()")]
+    #[case("x == 1 ? a : b")]
+    #[case("(x = 1) + 1 > 0 ? x : b")]
+    #[case("x ? (a = 1) : b")]
+    #[case("a = 1; b = a + 1; b")]
+    #[case("a = 1; a += 1; a")]
+    #[case("a = 1")]
+    #[case("pow(x, 2)")]
+    #[case("round(1.25, 1)")]
+    #[case("random(1, 10)")]
+    #[case("max()")]
+    fn test_lint_clean(#[case] input: &str) {
+        assert_eq!(diagnostics(input, &LintConfig::default()), []);
+    }
+
+    #[test]
+    fn test_lint_deep() {
+        let input = format!("(a == a){}", " + x".repeat(100_000));
+        let found = diagnostics(&input, &LintConfig::default());
+        assert_eq!(found, [(LintRule::SelfComparison, "a == a".to_string())]);
+    }
+
+    #[test]
+    fn test_lint_order_and_display() {
+        let ast = parse_expression("t = sqrt(-1) ; t == t ? 1 : 2").unwrap();
+        let found: Vec<String> = lint(&ast, &LintConfig::default())
+            .iter()
+            .map(|d| d.to_string())
+            .collect();
+        assert_eq!(
+            found,
+            [
+                "constant-call: 'sqrt(- 1)' only has constant params, it could be precomputed (at 4)",
+                "self-comparison: 't' is compared to itself (at 14)",
+            ]
+        );
+    }
+}
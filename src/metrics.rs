@@ -0,0 +1,288 @@
+use crate::operator::InfixOpManager;
+use crate::parser::ExprAST;
+use alloc::collections::BTreeSet;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+
+/// The size of an expression, from [`ExprAST::metrics`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct AstMetrics {
+    /// Every node of the tree, statements and spreads included.
+    pub nodes: usize,
+    /// The nodes on the longest path from the root to a leaf, so a lone
+    /// literal has depth 1.
+    pub depth: usize,
+    /// The distinct names referenced, assignment targets included.
+    pub references: usize,
+    /// The function calls, each call counted even if it repeats another.
+    pub function_calls: usize,
+    /// Whether any operator assigns to a variable, like `=` or `+=`.
+    pub has_assignment: bool,
+}
+
+/// Budgets an expression has to fit before it's evaluated, each off when
+/// `None`. Meant for checking user-authored rules ahead of time, together
+/// with [`crate::validate`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct ComplexityLimits {
+    pub max_nodes: Option<usize>,
+    pub max_depth: Option<usize>,
+    pub max_references: Option<usize>,
+    pub max_function_calls: Option<usize>,
+    /// Whether expressions that assign to a variable are rejected.
+    pub forbid_assignment: bool,
+}
+
+/// A budget of [`ComplexityLimits`] an expression goes over, with the
+/// limit and the actual count.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LimitViolation {
+    Nodes { limit: usize, actual: usize },
+    Depth { limit: usize, actual: usize },
+    References { limit: usize, actual: usize },
+    FunctionCalls { limit: usize, actual: usize },
+    Assignment,
+}
+
+impl fmt::Display for LimitViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use LimitViolation::*;
+        let (what, limit, actual) = match self {
+            Nodes { limit, actual } => ("nodes", limit, actual),
+            Depth { limit, actual } => ("depth", limit, actual),
+            References { limit, actual } => ("distinct references", limit, actual),
+            FunctionCalls { limit, actual } => ("function calls", limit, actual),
+            Assignment => return f.write_str("assignments are not allowed"),
+        };
+        write!(f, "{} {} exceeds the limit of {}", what, actual, limit)
+    }
+}
+
+impl<'a> ExprAST<'a> {
+    /// Counts the nodes, depth, distinct references and function calls of
+    /// the tree in one walk, with a work stack so deep trees don't recurse.
+    pub fn metrics(&self) -> AstMetrics {
+        let infix = InfixOpManager::new();
+        let mut metrics = AstMetrics::default();
+        let mut references = BTreeSet::new();
+        let mut stack = vec![(self, 1)];
+        while let Some((expr, depth)) = stack.pop() {
+            metrics.nodes += 1;
+            metrics.depth = metrics.depth.max(depth);
+            match expr {
+                ExprAST::Reference(name) => {
+                    references.insert(*name);
+                }
+                ExprAST::Function(..) => metrics.function_calls += 1,
+                ExprAST::Binary(op, ..) if infix.is_setter(op) => metrics.has_assignment = true,
+                _ => {}
+            }
+            stack.extend(expr.children().into_iter().map(|child| (child, depth + 1)));
+        }
+        metrics.references = references.len();
+        metrics
+    }
+
+    /// The distinct names referenced, sorted, assignment targets included.
+    /// A path like `user.name` is one name. Function calls aren't
+    /// references, but a name that turns out to be a function of the
+    /// context is.
+    pub fn references(&self) -> Vec<&'a str> {
+        let mut references = BTreeSet::new();
+        let mut stack = vec![self];
+        while let Some(expr) = stack.pop() {
+            if let ExprAST::Reference(name) = expr {
+                references.insert(*name);
+            }
+            stack.extend(expr.children());
+        }
+        references.into_iter().collect()
+    }
+}
+
+impl ComplexityLimits {
+    /// Every budget `metrics` goes over, in the order of the fields.
+    pub fn check(&self, metrics: &AstMetrics) -> Result<(), Vec<LimitViolation>> {
+        use LimitViolation::*;
+        let over = |limit: Option<usize>, actual: usize| limit.filter(|&limit| actual > limit);
+        let mut violations = Vec::new();
+        if let Some(limit) = over(self.max_nodes, metrics.nodes) {
+            violations.push(Nodes {
+                limit,
+                actual: metrics.nodes,
+            });
+        }
+        if let Some(limit) = over(self.max_depth, metrics.depth) {
+            violations.push(Depth {
+                limit,
+                actual: metrics.depth,
+            });
+        }
+        if let Some(limit) = over(self.max_references, metrics.references) {
+            violations.push(References {
+                limit,
+                actual: metrics.references,
+            });
+        }
+        if let Some(limit) = over(self.max_function_calls, metrics.function_calls) {
+            violations.push(FunctionCalls {
+                limit,
+                actual: metrics.function_calls,
+            });
+        }
+        if self.forbid_assignment && metrics.has_assignment {
+            violations.push(Assignment);
+        }
+        match violations.is_empty() {
+            true => Ok(()),
+            false => Err(violations),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "default"))]
+mod tests {
+    use super::{AstMetrics, ComplexityLimits, LimitViolation};
+    use crate::parse_expression;
+    use crate::parser::{ExprAST, Literal};
+    use rstest::rstest;
+    use rust_decimal::Decimal;
+
+    fn num(n: i64) -> ExprAST<'static> {
+        ExprAST::Literal(Literal::Number(Decimal::from(n)))
+    }
+
+    fn metrics(
+        nodes: usize,
+        depth: usize,
+        references: usize,
+        function_calls: usize,
+        has_assignment: bool,
+    ) -> AstMetrics {
+        AstMetrics {
+            nodes,
+            depth,
+            references,
+            function_calls,
+            has_assignment,
+        }
+    }
+
+    #[rstest]
+    #[case(num(1), metrics(1, 1, 0, 0, false))]
+    #[case(ExprAST::None, metrics(1, 1, 0, 0, false))]
+    #[case(
+        ExprAST::Binary(
+            "+",
+            Box::new(ExprAST::Reference("a")),
+            Box::new(ExprAST::Reference("a"))
+        ),
+        metrics(3, 2, 1, 0, false)
+    )]
+    #[case(
+        ExprAST::Stmt(vec![
+            ExprAST::Binary("=", Box::new(ExprAST::Reference("a")), Box::new(num(1))),
+            ExprAST::Function("f", vec![
+                ExprAST::Spread(Box::new(ExprAST::List(vec![ExprAST::Reference("b"), num(2)]))),
+                ExprAST::Function("g", vec![ExprAST::Reference("a")]),
+            ]),
+        ]),
+        metrics(11, 5, 2, 2, true)
+    )]
+    #[case(
+        ExprAST::Match(
+            Box::new(ExprAST::Reference("x")),
+            vec![(num(1), ExprAST::Map(vec![(num(2), ExprAST::Reference("y"))]))],
+            Some(Box::new(ExprAST::Ternary(
+                Box::new(ExprAST::Reference("x")),
+                Box::new(num(3)),
+                Box::new(ExprAST::Postfix(Box::new(ExprAST::Reference("z")), "++")),
+            ))),
+        ),
+        metrics(11, 4, 3, 0, false)
+    )]
+    fn test_metrics(#[case] ast: ExprAST, #[case] expected: AstMetrics) {
+        assert_eq!(ast.metrics(), expected);
+    }
+
+    #[rstest]
+    #[case(&format!("{}1", "- ".repeat(100)), metrics(101, 101, 0, 0, false))]
+    #[case(&format!("{}a{}", "[".repeat(100), "]".repeat(100)), metrics(101, 101, 1, 0, false))]
+    #[case(&format!("a{}", " + f(a)".repeat(10_000)), metrics(30_001, 10_002, 1, 10_000, false))]
+    #[case("x = y += max(a, b, a)", metrics(8, 4, 4, 1, true))]
+    fn test_metrics_parsed(#[case] input: &str, #[case] expected: AstMetrics) {
+        let ast = parse_expression(input).unwrap();
+        assert_eq!(ast.metrics(), expected);
+    }
+
+    #[rstest]
+    #[case("1 + f(2)", &[])]
+    #[case("b + a.x + b + a?.y", &["a.x", "a?.y", "b"])]
+    #[case("x = y += max(a, b, a)", &["a", "b", "x", "y"])]
+    #[case("match k { c: [d], _: {(e): 1} }", &["c", "d", "e", "k"])]
+    fn test_references(#[case] input: &str, #[case] expected: &[&str]) {
+        assert_eq!(parse_expression(input).unwrap().references(), expected);
+    }
+
+    #[test]
+    fn test_check() {
+        let limits = ComplexityLimits {
+            max_nodes: Some(10),
+            max_depth: Some(3),
+            max_references: Some(2),
+            max_function_calls: Some(1),
+            forbid_assignment: true,
+        };
+        assert_eq!(limits.check(&metrics(10, 3, 2, 1, false)), Ok(()));
+        assert_eq!(
+            ComplexityLimits::default().check(&metrics(99, 99, 99, 99, true)),
+            Ok(())
+        );
+        let violations = limits.check(&metrics(11, 4, 3, 2, true)).unwrap_err();
+        assert_eq!(
+            violations,
+            [
+                LimitViolation::Nodes {
+                    limit: 10,
+                    actual: 11
+                },
+                LimitViolation::Depth {
+                    limit: 3,
+                    actual: 4
+                },
+                LimitViolation::References {
+                    limit: 2,
+                    actual: 3
+                },
+                LimitViolation::FunctionCalls {
+                    limit: 1,
+                    actual: 2
+                },
+                LimitViolation::Assignment,
+            ]
+        );
+        let messages: Vec<String> = violations.iter().map(|v| v.to_string()).collect();
+        assert_eq!(
+            messages,
+            [
+                "nodes 11 exceeds the limit of 10",
+                "depth 4 exceeds the limit of 3",
+                "distinct references 3 exceeds the limit of 2",
+                "function calls 2 exceeds the limit of 1",
+                "assignments are not allowed",
+            ]
+        );
+        let ast = parse_expression("a = b + c").unwrap();
+        assert_eq!(
+            limits.check(&ast.metrics()),
+            Err(vec![
+                LimitViolation::References {
+                    limit: 2,
+                    actual: 3
+                },
+                LimitViolation::Assignment,
+            ])
+        );
+    }
+}
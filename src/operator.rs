@@ -1,11 +1,13 @@
+use crate::compat::{HashMap, Mutex, OnceCell};
 use crate::define::Result;
 use crate::error::Error;
-use crate::value::Value;
-use once_cell::sync::OnceCell;
-use rust_decimal::prelude::FromPrimitive;
+use crate::value::{Value, ValueType};
+use alloc::borrow::Cow;
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicUsize, Ordering};
 use rust_decimal::Decimal;
-use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
 
 pub type InfixOpFunc = dyn Fn(Value, Value) -> Result<Value> + Send + Sync + 'static;
 
@@ -19,7 +21,7 @@ pub enum InfixOpType {
     SETTER,
 }
 
-#[derive(Clone, PartialEq)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum InfixOpAssociativity {
     LEFT,
     RIGHT,
@@ -35,6 +37,30 @@ pub struct InfixOpConfig(
 
 pub struct InfixOpManager {
     store: &'static Mutex<HashMap<String, InfixOpConfig>>,
+    // The operators with implementations for pairs of types, whose handler
+    // in `store` dispatches on the types of its operands.
+    typed: &'static Mutex<HashMap<String, TypedImpls>>,
+}
+
+#[derive(Clone)]
+struct TypedImpls {
+    impls: Vec<(ValueType, ValueType, Arc<InfixOpFunc>)>,
+    // What the operator was registered with, for the other pairs.
+    fallback: Arc<InfixOpFunc>,
+}
+
+impl TypedImpls {
+    fn dispatch(self) -> Arc<InfixOpFunc> {
+        Arc::new(move |left, right| {
+            let types = (left.value_type(), right.value_type());
+            let f = self
+                .impls
+                .iter()
+                .find(|(lhs, rhs, _)| (*lhs, *rhs) == types)
+                .map_or(&self.fallback, |(_, _, f)| f);
+            f(left, right)
+        })
+    }
 }
 
 pub struct PrefixOpManager {
@@ -48,89 +74,104 @@ pub struct PostfixOpManager {
 impl InfixOpManager {
     pub fn new() -> Self {
         static STORE: OnceCell<Mutex<HashMap<String, InfixOpConfig>>> = OnceCell::new();
+        static TYPED: OnceCell<Mutex<HashMap<String, TypedImpls>>> = OnceCell::new();
+        static INITED: OnceCell<()> = OnceCell::new();
         let store = STORE.get_or_init(|| Mutex::new(HashMap::new()));
-        InfixOpManager { store: store }
+        let typed = TYPED.get_or_init(|| Mutex::new(HashMap::new()));
+        let mut manager = InfixOpManager { store, typed };
+        INITED.get_or_init(|| manager.init());
+        manager
     }
 
-    pub fn init(&mut self) {
-        use InfixOpAssociativity::*;
-        use InfixOpType::*;
-        self.register("=", 20, SETTER, RIGHT, Arc::new(|_, right| Ok(right)));
-
-        for op in vec!["+=", "-=", "*=", "/=", "%="] {
-            self.register(
-                op,
-                20,
-                SETTER,
-                RIGHT,
-                Arc::new(move |left, right| {
-                    let (mut a, b) = (left.decimal()?, right.decimal()?);
-                    match op {
-                        "+=" => a += b,
-                        "-=" => a -= b,
-                        "*=" => a *= b,
-                        "/=" => a /= b,
-                        "%=" => a %= b,
-                        _ => (),
-                    }
-                    Ok(Value::Number(a))
-                }),
-            );
-        }
+    // Arithmetic and comparisons are always registered, the rest only when
+    // their feature is enabled.
+    fn init(&mut self) {
+        self.init_arithmetic();
+        self.init_comparison();
+        #[cfg(feature = "ops-assign")]
+        self.init_assign();
+        #[cfg(feature = "ops-bitwise")]
+        self.init_bitwise();
+        #[cfg(feature = "builtin-logic")]
+        self.init_logic();
+        #[cfg(feature = "builtin-string")]
+        self.init_string();
+        #[cfg(feature = "builtin-list")]
+        self.init_list();
+    }
 
-        for op in vec!["<<=", ">>=", "&=", "^=", "|="] {
+    // Only implemented for two numbers, any other pair is an error.
+    fn init_arithmetic(&mut self) {
+        for (op, precedence) in [("+", 110), ("-", 110), ("*", 120), ("/", 120), ("%", 120)] {
             self.register(
                 op,
-                20,
-                SETTER,
-                RIGHT,
-                Arc::new(move |left, right| {
-                    let (mut a, b) = (left.integer()?, right.integer()?);
-                    match op {
-                        "<<=" => a <<= b,
-                        ">>=" => a >>= b,
-                        "&=" => a &= b,
-                        "^=" => a ^= b,
-                        "|=" => a |= b,
-                        _ => (),
-                    }
-                    Ok(Value::from(a))
-                }),
+                precedence,
+                InfixOpType::CALC,
+                InfixOpAssociativity::LEFT,
+                Arc::new(|_, _| Err(Error::ShouldBeNumber())),
             );
+            let numbers: Arc<InfixOpFunc> = Arc::new(move |left, right| {
+                Ok(Value::from(arithmetic(
+                    op,
+                    left.decimal()?,
+                    right.decimal()?,
+                )?))
+            });
+            self.register_typed(op, ValueType::Number, ValueType::Number, numbers)
+                .unwrap();
         }
+    }
 
-        for (op, precedence) in vec![("||", 40), ("&&", 50)] {
+    // Like in C, ordering comparisons, and the tests for membership and
+    // prefixes that read like them, bind tighter than `==` and `!=`, so
+    // `a < b == c < d` compares two bools.
+    fn init_comparison(&mut self) {
+        use InfixOpAssociativity::*;
+        use InfixOpType::*;
+        // Numbers and strings compare directly, every other pair goes
+        // through `Value::partial_cmp`.
+        for op in ["<", "<=", ">", ">="] {
+            let holds = move |ordering: core::cmp::Ordering| match op {
+                "<" => ordering.is_lt(),
+                "<=" => ordering.is_le(),
+                ">" => ordering.is_gt(),
+                _ => ordering.is_ge(),
+            };
             self.register(
                 op,
-                precedence,
+                65,
                 CALC,
                 LEFT,
                 Arc::new(move |left, right| {
-                    let (mut a, b) = (left.bool()?, right.bool()?);
-                    match op {
-                        "||" => a = a || b,
-                        "&&" => a = a && b,
-                        _ => (),
-                    }
-                    Ok(Value::from(a))
+                    let ordering = left
+                        .partial_cmp(&right)
+                        .ok_or_else(|| Error::NotComparable(left.type_name(), right.type_name()))?;
+                    Ok(Value::from(holds(ordering)))
                 }),
             );
+            let numbers: Arc<InfixOpFunc> = Arc::new(move |left, right| {
+                Ok(Value::from(holds(left.decimal()?.cmp(&right.decimal()?))))
+            });
+            self.register_typed(op, ValueType::Number, ValueType::Number, numbers)
+                .unwrap();
+            let strings: Arc<InfixOpFunc> = Arc::new(move |left, right| {
+                Ok(Value::from(holds(left.string()?.cmp(&right.string()?))))
+            });
+            self.register_typed(op, ValueType::String, ValueType::String, strings)
+                .unwrap();
         }
 
-        for op in vec!["<", "<=", ">", ">="] {
+        for op in ["==", "!="] {
             self.register(
                 op,
                 60,
                 CALC,
                 LEFT,
                 Arc::new(move |left, right| {
-                    let (a, b) = (left.decimal()?, right.decimal()?);
                     let mut value = false;
                     match op {
-                        "<" => value = a < b,
-                        "<=" => value = a <= b,
-                        ">" => value = a > b,
-                        ">=" => value = a >= b,
+                        "==" => value = left == right,
+                        "!=" => value = left != right,
                         _ => (),
                     }
                     Ok(Value::from(value))
@@ -138,59 +179,94 @@ impl InfixOpManager {
             );
         }
 
-        for op in vec!["==", "!="] {
+        // `x between 1 and 10` parses to `x between [1, 10]`. Both bounds
+        // are inclusive, and inverted ones are an error rather than being
+        // swapped, since they're more likely a mistake than intended.
+        self.register(
+            "between",
+            65,
+            CALC,
+            LEFT,
+            Arc::new(|value, bounds| {
+                let [lower, upper] =
+                    <[Value; 2]>::try_from(bounds.list()?).map_err(|_| Error::ParamInvalid())?;
+                let cmp = |a: &Value, b: &Value| {
+                    a.partial_cmp(b)
+                        .ok_or_else(|| Error::NotComparable(a.type_name(), b.type_name()))
+                };
+                if cmp(&lower, &upper)?.is_gt() {
+                    return Err(Error::InvertedBounds(lower.to_string(), upper.to_string()));
+                }
+                Ok(Value::from(
+                    cmp(&value, &lower)?.is_ge() && cmp(&value, &upper)?.is_le(),
+                ))
+            }),
+        );
+    }
+
+    #[cfg(feature = "ops-assign")]
+    fn init_assign(&mut self) {
+        use InfixOpAssociativity::*;
+        use InfixOpType::*;
+        self.register("=", 20, SETTER, RIGHT, Arc::new(|_, right| Ok(right)));
+
+        for op in ["+=", "-=", "*=", "/=", "%="] {
             self.register(
                 op,
-                60,
-                CALC,
-                LEFT,
+                20,
+                SETTER,
+                RIGHT,
                 Arc::new(move |left, right| {
-                    let mut value = false;
-                    match op {
-                        "==" => value = left == right,
-                        "!=" => value = left != right,
-                        _ => (),
-                    }
-                    Ok(Value::from(value))
+                    Ok(Value::Number(arithmetic(
+                        op,
+                        left.decimal()?,
+                        right.decimal()?,
+                    )?))
                 }),
             );
         }
+    }
 
-        for (op, precedence) in vec![("|", 70), ("^", 80), ("&", 90), ("<<", 100), (">>", 100)] {
+    #[cfg(feature = "ops-bitwise")]
+    fn init_bitwise(&mut self) {
+        use InfixOpAssociativity::*;
+        use InfixOpType::*;
+        for (op, precedence) in [("|", 54), ("^", 56), ("&", 58), ("<<", 100), (">>", 100)] {
             self.register(
                 op,
                 precedence,
                 CALC,
                 LEFT,
-                Arc::new(move |left, right| {
-                    let (mut a, b) = (left.integer()?, right.integer()?);
-                    match op {
-                        "|" => a |= b,
-                        "^" => a ^= b,
-                        "&" => a &= b,
-                        "<<" => a <<= b,
-                        ">>" => a >>= b,
-                        _ => (),
-                    }
-                    Ok(Value::from(a))
-                }),
+                Arc::new(move |left, right| bitwise(op, left, right)),
             );
         }
 
-        for (op, precedence) in vec![("+", 110), ("-", 110), ("*", 120), ("/", 120), ("%", 120)] {
+        // Compound assignments need both features.
+        #[cfg(feature = "ops-assign")]
+        for op in ["<<=", ">>=", "&=", "^=", "|="] {
+            self.register(
+                op,
+                20,
+                SETTER,
+                RIGHT,
+                Arc::new(move |left, right| bitwise(&op[..op.len() - 1], left, right)),
+            );
+        }
+    }
+
+    #[cfg(feature = "builtin-logic")]
+    fn init_logic(&mut self) {
+        for (op, precedence) in [("||", 40), ("&&", 50)] {
             self.register(
                 op,
                 precedence,
-                CALC,
-                LEFT,
+                InfixOpType::CALC,
+                InfixOpAssociativity::LEFT,
                 Arc::new(move |left, right| {
-                    let (mut a, b) = (left.decimal()?, right.decimal()?);
+                    let (mut a, b) = (left.bool()?, right.bool()?);
                     match op {
-                        "+" => a += b,
-                        "-" => a -= b,
-                        "*" => a *= b,
-                        "/" => a /= b,
-                        "%" => a %= b,
+                        "||" => a = a || b,
+                        "&&" => a = a && b,
                         _ => (),
                     }
                     Ok(Value::from(a))
@@ -198,41 +274,94 @@ impl InfixOpManager {
             );
         }
 
+        // Like `ifnull`, both sides are evaluated.
         self.register(
-            "beginWith",
-            200,
+            "??",
+            30,
+            InfixOpType::CALC,
+            InfixOpAssociativity::LEFT,
+            Arc::new(|left, right| match left {
+                Value::None => Ok(right),
+                left => Ok(left),
+            }),
+        );
+    }
+
+    // Elements are compared with `==`, and a map stands for its keys. The
+    // elements of the right side of `containsAll` and `containsAny` are
+    // taken as a set: duplicates don't change the result, and every list
+    // contains all of `[]` but none of it.
+    #[cfg(feature = "builtin-list")]
+    fn init_list(&mut self) {
+        use InfixOpAssociativity::*;
+        use InfixOpType::*;
+        self.register(
+            "in",
+            65,
+            CALC,
+            LEFT,
+            Arc::new(|left, right| Ok(contains(right, &left)?.into())),
+        );
+
+        self.register(
+            "contains",
+            65,
+            CALC,
+            LEFT,
+            Arc::new(|left, right| Ok(contains(left, &right)?.into())),
+        );
+
+        // A single value on the right is a list of one, as for `contains`.
+        self.register(
+            "containsAll",
+            65,
             CALC,
             LEFT,
             Arc::new(|left, right| {
-                let (a, b) = (left.string()?, right.string()?);
-                Ok(Value::from(a.starts_with(&b)))
+                let elements = match right {
+                    Value::List(elements) => elements,
+                    element => alloc::vec![element],
+                };
+                let left = keys_or_list(left)?;
+                Ok(elements.iter().all(|e| left.contains(e)).into())
             }),
         );
 
         self.register(
-            "endWith",
-            200,
+            "containsAny",
+            65,
+            CALC,
+            LEFT,
+            Arc::new(|left, right| {
+                let (left, elements) = (keys_or_list(left)?, right.list()?);
+                Ok(elements.iter().any(|e| left.contains(e)).into())
+            }),
+        );
+    }
+
+    #[cfg(feature = "builtin-string")]
+    fn init_string(&mut self) {
+        use InfixOpAssociativity::*;
+        use InfixOpType::*;
+        self.register(
+            "beginWith",
+            65,
             CALC,
             LEFT,
             Arc::new(|left, right| {
                 let (a, b) = (left.string()?, right.string()?);
-                Ok(Value::from(a.ends_with(&b)))
+                Ok(Value::from(a.starts_with(&b)))
             }),
         );
 
         self.register(
-            "in",
-            200,
-            InfixOpType::CALC,
-            InfixOpAssociativity::LEFT,
+            "endWith",
+            65,
+            CALC,
+            LEFT,
             Arc::new(|left, right| {
-                let list = right.list()?;
-                for item in list {
-                    if item == left {
-                        return Ok(true.into());
-                    }
-                }
-                Ok(false.into())
+                let (a, b) = (left.string()?, right.string()?);
+                Ok(Value::from(a.ends_with(&b)))
             }),
         );
     }
@@ -245,69 +374,134 @@ impl InfixOpManager {
         op_associativity: InfixOpAssociativity,
         f: Arc<InfixOpFunc>,
     ) {
-        self.store.lock().unwrap().insert(
-            op.to_string(),
-            InfixOpConfig(precidence, op_type, op_associativity, f),
-        );
+        note_op_len(op);
+        let key = op_key(op).into_owned();
+        let mut store = self.store.lock().unwrap();
+        self.typed.lock().unwrap().remove(&key);
+        store.insert(key, InfixOpConfig(precidence, op_type, op_associativity, f));
     }
 
-    pub fn get_handler(&self, op: &str) -> Result<Arc<InfixOpFunc>> {
-        Ok(self.get(op)?.3)
+    /// Registers `f` as `op` for a left operand of type `lhs` and a right one
+    /// of type `rhs`, replacing an earlier one for the same pair. The handler
+    /// `op` was registered with still applies to every other pair, and
+    /// registering `op` again drops the typed ones.
+    pub fn register_typed(
+        &mut self,
+        op: &str,
+        lhs: ValueType,
+        rhs: ValueType,
+        f: Arc<InfixOpFunc>,
+    ) -> Result<()> {
+        let key = op_key(op);
+        let mut store = self.store.lock().unwrap();
+        let config = store
+            .get_mut(key.as_ref())
+            .ok_or_else(|| Error::InfixOpNotRegistered(op.to_string()))?;
+        let mut typed = self.typed.lock().unwrap();
+        let impls = typed.entry(key.into_owned()).or_insert_with(|| TypedImpls {
+            impls: Vec::new(),
+            fallback: config.3.clone(),
+        });
+        impls.impls.retain(|(l, r, _)| (*l, *r) != (lhs, rhs));
+        impls.impls.push((lhs, rhs, f));
+        config.3 = impls.clone().dispatch();
+        Ok(())
     }
 
-    pub fn get_precidence(&self, op: &str) -> (i32, i32) {
-        let ans = self.get(op);
-        if ans.is_err() {
-            return (-1, -1);
-        }
-        let config = ans.unwrap();
+    // The left and right binding power of `op`, or `None` when it isn't
+    // registered.
+    pub fn get_precidence(&self, op: &str) -> Option<(i32, i32)> {
+        let config = self.get(op).ok()?;
         let l_bp = config.0;
-        let mut r_bp = 0;
-        if config.2 == InfixOpAssociativity::LEFT {
-            r_bp = l_bp + 1;
-        } else if config.2 == InfixOpAssociativity::RIGHT {
-            r_bp = l_bp - 1;
-        }
-        (l_bp, r_bp)
-    }
-
-    pub fn get_op_type(&self, op: &str) -> Result<InfixOpType> {
-        Ok(self.get(op)?.1)
+        let r_bp = match config.2 {
+            InfixOpAssociativity::LEFT => l_bp + 1,
+            InfixOpAssociativity::RIGHT => l_bp - 1,
+        };
+        Some((l_bp, r_bp))
     }
 
     pub fn get(&self, op: &str) -> Result<InfixOpConfig> {
         let binding = self.store.lock().unwrap();
-        let ans = binding.get(op);
+        let ans = binding.get(op_key(op).as_ref());
         if ans.is_none() {
             return Err(Error::InfixOpNotRegistered(op.to_string()));
         }
         Ok(ans.unwrap().clone())
     }
 
-    pub fn operators(&self) -> Vec<(String, i32)> {
-        let mut ans = vec![];
+    // The registered operators, tightest-binding first and by name among
+    // equals.
+    pub fn precedence_table(&self) -> Vec<(String, i32, InfixOpAssociativity)> {
         let binding = self.store.lock().unwrap();
-        for (op, InfixOpConfig(precedence, _, _, _)) in binding.iter() {
-            ans.push((op.clone(), precedence.clone()));
-        }
-        ans.sort_by(|a, b| a.1.cmp(&b.1));
+        let mut ans: Vec<_> = binding
+            .iter()
+            .map(|(op, InfixOpConfig(precedence, _, associativity, _))| {
+                (op.clone(), *precedence, *associativity)
+            })
+            .collect();
+        ans.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
         ans
     }
 
     pub fn exist(&self, op: &str) -> bool {
         let binding = self.store.lock().unwrap();
-        binding.get(op).is_some()
+        binding.get(op_key(op).as_ref()).is_some()
+    }
+
+    // The registered operators that start with a letter, like `in`, in the
+    // order of the precedence table.
+    pub fn word_ops(&self) -> Vec<String> {
+        self.precedence_table()
+            .into_iter()
+            .map(|(op, _, _)| op)
+            .filter(|op| op.starts_with(|ch: char| ch.is_alphabetic()))
+            .collect()
+    }
+
+    pub fn is_setter(&self, op: &str) -> bool {
+        let binding = self.store.lock().unwrap();
+        matches!(
+            binding.get(op_key(op).as_ref()),
+            Some(InfixOpConfig(_, InfixOpType::SETTER, _, _))
+        )
+    }
+}
+
+// Words of a multi-word operator may be separated by any whitespace in an
+// expression; the store keeps them joined by single spaces. Whitespace
+// around an operator is never part of it.
+fn op_key(op: &str) -> Cow<'_, str> {
+    if op.contains(char::is_whitespace) && op.trim() == op {
+        Cow::Owned(op.split_whitespace().collect::<Vec<_>>().join(" "))
+    } else {
+        Cow::Borrowed(op)
     }
 }
 
+// The length in bytes of the longest operator registered with any of the
+// managers, so the tokenizer can rule out longer runs of chars without
+// looking them up.
+static LONGEST_OP: AtomicUsize = AtomicUsize::new(0);
+
+fn note_op_len(op: &str) {
+    LONGEST_OP.fetch_max(op_key(op).len(), Ordering::Relaxed);
+}
+
+pub fn longest_op() -> usize {
+    LONGEST_OP.load(Ordering::Relaxed)
+}
+
 impl PrefixOpManager {
     pub fn new() -> Self {
         static STORE: OnceCell<Mutex<HashMap<String, Arc<PrefixOpFunc>>>> = OnceCell::new();
+        static INITED: OnceCell<()> = OnceCell::new();
         let store = STORE.get_or_init(|| Mutex::new(HashMap::new()));
-        PrefixOpManager { store: store }
+        let mut manager = PrefixOpManager { store };
+        INITED.get_or_init(|| manager.init());
+        manager
     }
 
-    pub fn init(&mut self) {
+    fn init(&mut self) {
         self.register(
             "-",
             Arc::new(|param| {
@@ -330,12 +524,21 @@ impl PrefixOpManager {
             }),
         );
 
+        #[cfg(feature = "ops-bitwise")]
+        self.register("~", Arc::new(|param| Ok(Value::from(!param.integer()?))));
+
+        #[cfg(feature = "builtin-logic")]
+        self.init_logic();
+    }
+
+    #[cfg(feature = "builtin-logic")]
+    fn init_logic(&mut self) {
         self.register(
             "!",
             Arc::new(|param| {
                 let a = match param {
                     Value::Bool(value) => !value,
-                    _ => return Err(Error::ShouldBeBool()),
+                    other => return Err(Error::ShouldBeBool(other.type_name())),
                 };
                 Ok(Value::Bool(a))
             }),
@@ -346,7 +549,7 @@ impl PrefixOpManager {
             Arc::new(|param| {
                 let a = match param {
                     Value::Bool(value) => !value,
-                    _ => return Err(Error::ShouldBeBool()),
+                    other => return Err(Error::ShouldBeBool(other.type_name())),
                 };
                 Ok(Value::Bool(a))
             }),
@@ -380,6 +583,7 @@ impl PrefixOpManager {
     }
 
     pub fn register(&mut self, op: &str, f: Arc<PrefixOpFunc>) {
+        note_op_len(op);
         self.store.lock().unwrap().insert(op.to_string(), f);
     }
 
@@ -401,11 +605,22 @@ impl PrefixOpManager {
 impl PostfixOpManager {
     pub fn new() -> Self {
         static STORE: OnceCell<Mutex<HashMap<String, Arc<PrefixOpFunc>>>> = OnceCell::new();
+        static INITED: OnceCell<()> = OnceCell::new();
         let store = STORE.get_or_init(|| Mutex::new(HashMap::new()));
-        Self { store: store }
+        let mut manager = Self { store };
+        INITED.get_or_init(|| manager.init());
+        manager
+    }
+
+    fn init(&mut self) {
+        #[cfg(feature = "builtin-math")]
+        self.init_math();
     }
 
-    pub fn init(&mut self) {
+    #[cfg(feature = "builtin-math")]
+    fn init_math(&mut self) {
+        use rust_decimal::prelude::FromPrimitive;
+        use rust_decimal::Decimal;
         self.register(
             "++",
             Arc::new(|param| {
@@ -430,6 +645,7 @@ impl PostfixOpManager {
     }
 
     pub fn register(&mut self, op: &str, f: Arc<PostfixOpFunc>) {
+        note_op_len(op);
         self.store.lock().unwrap().insert(op.to_string(), f);
     }
 
@@ -448,14 +664,222 @@ impl PostfixOpManager {
     }
 }
 
+// `+`, `-`, `*`, `/` and `%`, or their compound assignments like `+=`. A
+// result too large for a `Decimal` is an error rather than a panic.
+fn arithmetic(op: &'static str, a: Decimal, b: Decimal) -> Result<Decimal> {
+    let ans = match op.trim_end_matches('=') {
+        "+" => a.checked_add(b),
+        "-" => a.checked_sub(b),
+        "*" => a.checked_mul(b),
+        "/" | "%" if b.is_zero() => return Err(Error::DivisionByZero),
+        "/" => a.checked_div(b),
+        "%" => a.checked_rem(b),
+        _ => unreachable!(),
+    };
+    ans.ok_or(Error::NumberOverflow(op))
+}
+
+#[cfg(feature = "builtin-list")]
+fn contains(collection: Value, element: &Value) -> Result<bool> {
+    match collection {
+        Value::Map(m) => Ok(m.contains_key(element)),
+        _ => Ok(collection.list()?.contains(element)),
+    }
+}
+
+#[cfg(feature = "builtin-list")]
+fn keys_or_list(collection: Value) -> Result<Vec<Value>> {
+    match collection {
+        Value::Map(m) => Ok(m.into_keys().collect()),
+        _ => collection.list(),
+    }
+}
+
+// Both operands must be integral and fit an `i64`, see `Value::integer`.
+// Bits shifted out are dropped, but a shift count outside `0..64` is an
+// error.
+#[cfg(feature = "ops-bitwise")]
+fn bitwise(op: &str, left: Value, right: Value) -> Result<Value> {
+    let (a, b) = (left.integer()?, right.integer()?);
+    let shift = || {
+        u32::try_from(b)
+            .ok()
+            .filter(|b| *b < i64::BITS)
+            .ok_or(Error::ShiftOutOfRange(b))
+    };
+    Ok(Value::from(match op {
+        "|" => a | b,
+        "^" => a ^ b,
+        "&" => a & b,
+        "<<" => a << shift()?,
+        ">>" => a >> shift()?,
+        _ => unreachable!(),
+    }))
+}
+
 #[cfg(test)]
 mod tetst {
+    use crate::error::Error;
     use crate::operator::InfixOpManager;
+    use crate::value::{Map, Value};
+    use crate::{create_context, execute};
+    use rstest::rstest;
+
+    fn compare(op: &str, left: Value, right: Value) -> crate::define::Result<Value> {
+        InfixOpManager::new().get(op).unwrap().3(left, right)
+    }
+
+    fn samples() -> Vec<Value> {
+        vec![
+            1.into(),
+            "a".into(),
+            true.into(),
+            vec![1.into()].into(),
+            Value::Map(Map::from_iter([("a".into(), 1.into())])),
+            Value::None,
+        ]
+    }
+
+    #[rstest]
+    #[case(1.into(), 2.into(), Some(true))]
+    #[case(2.into(), Value::from(1.5), Some(false))]
+    #[case("a".into(), "b".into(), Some(true))]
+    #[case("b".into(), "ab".into(), Some(false))]
+    #[case(false.into(), true.into(), Some(true))]
+    #[case(true.into(), true.into(), Some(false))]
+    #[case(vec![1.into(), 2.into()].into(), vec![1.into(), 3.into()].into(), Some(true))]
+    #[case(vec![1.into()].into(), vec![1.into(), 0.into()].into(), Some(true))]
+    #[case(vec![1.into()].into(), vec!["a".into()].into(), None)]
+    #[case(Value::Map(Map::default()), Value::Map(Map::from_iter([("a".into(), 1.into())])), None)]
+    #[case(Value::None, Value::None, Some(false))]
+    fn test_less_than_same_type(
+        #[case] left: Value,
+        #[case] right: Value,
+        #[case] output: Option<bool>,
+    ) {
+        match output {
+            Some(output) => assert_eq!(compare("<", left, right).unwrap(), output.into()),
+            None => assert!(compare("<", left, right).is_err()),
+        }
+    }
+
+    #[test]
+    fn test_compare_every_type_pair() {
+        for (i, left) in samples().into_iter().enumerate() {
+            for (j, right) in samples().into_iter().enumerate() {
+                let eq = compare("==", left.clone(), right.clone()).unwrap();
+                let ne = compare("!=", left.clone(), right.clone()).unwrap();
+                assert_eq!(eq, (i == j).into());
+                assert_eq!(ne, (i != j).into());
+                let lt = compare("<", left.clone(), right.clone());
+                if i == j {
+                    assert_eq!(lt.unwrap(), false.into());
+                } else {
+                    match lt {
+                        Err(Error::NotComparable(lhs, rhs)) => {
+                            assert_eq!((lhs, rhs), (left.type_name(), right.type_name()))
+                        }
+                        _ => panic!("{} < {} should not compare", left, right),
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_register_typed() {
+        use super::{InfixOpAssociativity::LEFT, InfixOpType::CALC};
+        use crate::value::ValueType::{Number, String};
+        use std::sync::Arc;
+        let mut manager = InfixOpManager::new();
+        let apply =
+            |left: Value, right: Value| InfixOpManager::new().get("typed").unwrap().3(left, right);
+        manager.register("typed", 10, CALC, LEFT, Arc::new(|_, _| Ok("any".into())));
+        manager
+            .register_typed(
+                "typed",
+                Number,
+                Number,
+                Arc::new(|_, _| Ok("numbers".into())),
+            )
+            .unwrap();
+        manager
+            .register_typed(
+                "typed",
+                String,
+                Number,
+                Arc::new(|_, _| Ok("string".into())),
+            )
+            .unwrap();
+        assert_eq!(apply(1.into(), 2.into()).unwrap(), "numbers".into());
+        assert_eq!(apply("a".into(), 2.into()).unwrap(), "string".into());
+        assert_eq!(apply(2.into(), "a".into()).unwrap(), "any".into());
+
+        // The latest one for a pair wins, and registering the operator again
+        // starts over.
+        manager
+            .register_typed("typed", Number, Number, Arc::new(|_, _| Ok("again".into())))
+            .unwrap();
+        assert_eq!(apply(1.into(), 2.into()).unwrap(), "again".into());
+        manager.register("typed", 10, CALC, LEFT, Arc::new(|_, _| Ok("plain".into())));
+        assert_eq!(apply(1.into(), 2.into()).unwrap(), "plain".into());
+
+        let err = manager
+            .register_typed("untyped", Number, Number, Arc::new(|_, _| Ok(Value::None)))
+            .unwrap_err();
+        assert!(matches!(err, Error::InfixOpNotRegistered(op) if op == "untyped"));
+    }
+
+    #[test]
+    fn test_arithmetic_needs_numbers() {
+        for op in ["+", "-", "*", "/", "%"] {
+            for (left, right) in [
+                (Value::from("a"), Value::from(1)),
+                (1.into(), "a".into()),
+                (vec![1.into()].into(), vec![1.into()].into()),
+                (Value::None, 1.into()),
+            ] {
+                let err = compare(op, left, right).unwrap_err();
+                assert!(matches!(err, Error::ShouldBeNumber()), "{}", op);
+            }
+        }
+    }
+
     #[test]
     fn test_operators() {
-        let result = InfixOpManager::new().operators();
-        for (op, precedence) in result {
-            println!("|{}| {}||", op, precedence)
+        let result = InfixOpManager::new().precedence_table();
+        for (op, precedence, associativity) in result {
+            println!("|{}| {}|{:?}|", op, precedence, associativity)
+        }
+    }
+
+    // Passes with any combination of features: built-ins that are left out
+    // fail as not registered instead of failing to parse.
+    #[rstest]
+    #[case(true, "1 + 2 * 3 - 4 / 2 % 3 >= 4 != (1 == 2)", "")]
+    #[case(true, "-(1) < +2", "")]
+    #[case(cfg!(feature = "ops-assign"), "a = 1; a += 2; a", "E_REF_INFIX_OP_NOT_FOUND")]
+    #[case(cfg!(feature = "ops-bitwise"), "6 & 3 | 1 << 2", "E_REF_INFIX_OP_NOT_FOUND")]
+    #[case(
+        cfg!(all(feature = "ops-bitwise", feature = "ops-assign")),
+        "a = 6; a &= 3",
+        "E_REF_INFIX_OP_NOT_FOUND"
+    )]
+    #[case(cfg!(feature = "builtin-logic"), "1 < 2 && 2 < 3", "E_REF_INFIX_OP_NOT_FOUND")]
+    #[case(cfg!(feature = "builtin-logic"), "!(1 < 2)", "E_REF_PREFIX_OP_NOT_FOUND")]
+    #[case(cfg!(feature = "builtin-logic"), "coalesce(a, 1)", "E_FUNC_NOT_REGISTERED")]
+    #[case(cfg!(feature = "builtin-string"), "'ab' beginWith 'a'", "E_REF_INFIX_OP_NOT_FOUND")]
+    #[case(cfg!(feature = "builtin-string"), "format('{}', 1)", "E_FUNC_NOT_REGISTERED")]
+    #[case(cfg!(feature = "builtin-list"), "1 in [1]", "E_REF_INFIX_OP_NOT_FOUND")]
+    #[case(cfg!(feature = "builtin-list"), "len(range(3))", "E_FUNC_NOT_REGISTERED")]
+    #[case(cfg!(feature = "builtin-math"), "max(1, 2)", "E_FUNC_NOT_REGISTERED")]
+    #[case(cfg!(feature = "builtin-math"), "1++", "E_REF_PREFIX_OP_NOT_FOUND")]
+    #[case(cfg!(feature = "builtin-env"), "now()", "E_FUNC_NOT_REGISTERED")]
+    fn test_feature_builtins(#[case] enabled: bool, #[case] input: &str, #[case] code: &str) {
+        let ans = execute(input, create_context!());
+        match enabled {
+            true => assert!(ans.is_ok(), "{}: {:?}", input, ans),
+            false => assert_eq!(ans.unwrap_err().code(), code, "{}", input),
         }
     }
 }
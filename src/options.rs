@@ -0,0 +1,507 @@
+use crate::context::Context;
+use crate::define::Result;
+use crate::error::Error;
+use crate::format::{round, Rounding};
+use crate::operator::InfixOpFunc;
+use crate::value::{Map, Value};
+use alloc::string::ToString;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use rust_decimal::prelude::{Decimal, ToPrimitive};
+
+/// How values are treated where a bool is expected: ternary conditions and
+/// the operands of `&&`, `||`, `!`, `not`, `AND` and `OR`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Truthiness {
+    /// Only `Value::Bool` is accepted, anything else is an error.
+    #[default]
+    Strict,
+    /// Any value is accepted and converted with [`Value::truthy`].
+    Coerce,
+}
+
+/// Which semantics an expression is evaluated with, for
+/// [`EvalOptions::semantics`]. Changes that would alter the results of
+/// existing rules land in the next version first, so that rules can be
+/// checked with [`crate::testing::compare_semantics`] before switching.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum SemanticsVersion {
+    /// The semantics of the releases so far.
+    #[default]
+    V1,
+    /// Every change from V1 so far:
+    ///
+    /// + An assignment, like `a = 1` or `a += 1`, evaluates to the value it
+    ///   assigns instead of `None`, so `a = b = 1` sets both to 1.
+    /// + `+` and `+=` on two strings join them, instead of failing with
+    ///   [`Error::ShouldBeNumber`]. Like `float_math`, this skips the
+    ///   registered handler of `+`.
+    ///
+    /// Changes to the grammar, like the precedence of prefix `not`, apply
+    /// to every version, since parsing happens before any options are known.
+    V2,
+}
+
+/// What a reference nothing in the context resolves evaluates to, see
+/// [`crate::Context::set_missing_reference_policy`].
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub enum MissingReferencePolicy {
+    /// `Value::None`, so `??` and `ifnull` can supply a fallback.
+    #[default]
+    None,
+    /// An [`Error::ReferenceNotExist`] suggesting the names it's likely a
+    /// typo of, like with [`EvalOptions::strict_references`].
+    Error,
+    /// The given value.
+    Default(Value),
+}
+
+/// Options that change how an expression is evaluated against a
+/// [`crate::Context`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct EvalOptions {
+    pub truthiness: Truthiness,
+    /// The most elements `range()` and `repeat()` may produce.
+    pub max_list_len: usize,
+    /// Whether a `match` without a `_` arm fails when no arm matches,
+    /// instead of evaluating to `None`.
+    pub strict_match: bool,
+    /// Whether a reference nothing in the context resolves fails with
+    /// [`Error::ReferenceNotExist`], suggesting the names it's likely a typo
+    /// of, instead of evaluating to `None`. Assigning to a new variable
+    /// still works. Overrides the [`MissingReferencePolicy`] of the context.
+    pub strict_references: bool,
+    /// Whether a map literal that ends up with the same key twice, like
+    /// `{'a': 1, 'a': 2}` or `{1 + 1: 'x', 2: 'y'}`, fails with
+    /// [`Error::DuplicateMapKey`]. Otherwise the last value wins, at the
+    /// position of the first.
+    pub strict_map_keys: bool,
+    /// Whether [`crate::ExprAST::exec`] reuses the result of a function call
+    /// for identical calls later in the same evaluation. Only calls whose
+    /// subtree is pure are cached: every function in it was registered with
+    /// [`crate::register_deterministic_function`] and isn't shadowed by the
+    /// context, and there are no assignments. Any assignment clears the
+    /// cache. `exec_iterative` and the VM don't memoize.
+    pub memoize: bool,
+    /// Whether `+`, `-`, `*`, `/` and `%` on two numbers, and the built-in
+    /// `sqrt`, `exp`, `ln` and `pow`, compute in `f64` instead of `Decimal`.
+    /// Faster, at the cost of float rounding. Results are converted back
+    /// with [`Value::try_from_f64`], so they are still numbers that compare
+    /// with any other, and a NaN or infinity fails with
+    /// [`Error::UnrepresentableFloat`]. The float path skips the registered
+    /// handler, so it ignores an override of those operators.
+    pub float_math: bool,
+    /// Bounds on what an expression may build, for evaluating untrusted
+    /// ones. None are set by default.
+    pub limits: Limits,
+    /// Whether an assignment, like `x = 1` or `x += 1`, fails with
+    /// [`Error::AssignmentForbidden`] instead of changing the context.
+    pub forbid_assignment: bool,
+    /// Whether calling a function that isn't deterministic fails with
+    /// [`Error::NonDeterministicCall`]. Only inner functions registered as
+    /// deterministic may be called, which rules out `now()`, `random()` and
+    /// the functions set on the context.
+    pub deterministic_only: bool,
+    /// How the results of arithmetic on numbers are rounded, see
+    /// [`MathContext`].
+    pub math: MathContext,
+    /// Whether evaluating records a [`crate::Warning`] for what works but
+    /// is likely unintended: a float result a number can't hold exactly
+    /// under `float_math`, `==` or `!=` on values of two different types
+    /// other than `None`, a call of a deprecated function, and a value
+    /// other than a bool converted under [`Truthiness::Coerce`]. See
+    /// [`crate::ExprAST::exec_with_warnings`].
+    pub collect_warnings: bool,
+    /// Which semantics to evaluate with, [`SemanticsVersion::V1`] by
+    /// default.
+    pub semantics: SemanticsVersion,
+}
+
+/// How `+`, `-`, `*`, `/` and `%` on two numbers, and their compound
+/// assignments like `+=`, round their results, for [`EvalOptions::math`].
+/// Each result is rounded as soon as it's computed, so a chain of
+/// operations never carries more places than `max_scale` from one step to
+/// the next. `float_math` skips it.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct MathContext {
+    /// The most decimal places a result keeps. `None`, the default, keeps
+    /// every digit a `Decimal` can hold.
+    pub max_scale: Option<u32>,
+    /// The most decimal places the quotient of `/` keeps, 28 by default,
+    /// which is the most a `Decimal` has. A quotient with a large integer
+    /// part has fewer, as a `Decimal` holds 28 or 29 significant digits.
+    pub division_scale: u32,
+    /// How the digits past the scale are rounded away.
+    pub rounding: Rounding,
+}
+
+/// Resource limits enforced while evaluating, each off when `None`. Crossing
+/// one fails with [`Error::ResourceLimitExceeded`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct Limits {
+    /// The most elements a list or map built by a literal, an operator or a
+    /// function call may hold, counting those of the lists and maps nested
+    /// in it. A map entry is one element.
+    pub max_elements: Option<usize>,
+    /// The most chars a string built by an operator or a function call may
+    /// have, e.g. by `+` or `format()`.
+    pub max_string_len: Option<usize>,
+    /// The most variables assignments may leave in the context. Variables
+    /// the host sets with [`crate::Context::set_variable`] count, but aren't
+    /// checked. On a layered context, those of the base don't count.
+    pub max_variables: Option<usize>,
+}
+
+/// Options that change how an expression is parsed, for
+/// [`crate::parse_expression_with_options`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ParseOptions {
+    /// Whether a line break ends a statement like `;` does, when the
+    /// expression before it is complete: not inside parentheses, brackets or
+    /// braces and not right after an operator. A line can then start a new
+    /// statement with `-x` or `(a)`, and `f` on one line with `(x)` on the
+    /// next is no call. Otherwise line breaks are whitespace.
+    pub newline_statements: bool,
+    /// The longest input, in bytes, that parses, 1 MiB by default. A longer
+    /// one fails with [`Error::InputTooLarge`] before any of it is read.
+    pub max_input_len: usize,
+    /// The longest token, in bytes, other than a string literal, like a
+    /// name, a number or a name between backticks, 4 KiB by default.
+    pub max_token_len: usize,
+    /// The longest string literal, in bytes between its quotes, 256 KiB by
+    /// default. Both token limits fail with [`Error::TokenTooLong`] as soon
+    /// as the tokenizer reads past them, at the start of the token.
+    pub max_string_len: usize,
+}
+
+impl Default for EvalOptions {
+    fn default() -> Self {
+        Self {
+            truthiness: Truthiness::default(),
+            max_list_len: 100_000,
+            strict_match: false,
+            strict_references: false,
+            strict_map_keys: false,
+            memoize: false,
+            float_math: false,
+            limits: Limits::default(),
+            forbid_assignment: false,
+            deterministic_only: false,
+            math: MathContext::default(),
+            collect_warnings: false,
+            semantics: SemanticsVersion::default(),
+        }
+    }
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        Self {
+            newline_statements: false,
+            max_input_len: 1 << 20,
+            max_token_len: 4 << 10,
+            max_string_len: 256 << 10,
+        }
+    }
+}
+
+impl Default for MathContext {
+    fn default() -> Self {
+        Self {
+            max_scale: None,
+            division_scale: 28,
+            rounding: Rounding::default(),
+        }
+    }
+}
+
+impl MathContext {
+    fn apply(&self, op: &str, value: Value) -> Value {
+        let scale = match op.trim_end_matches('=') {
+            "/" => Some(
+                self.max_scale
+                    .map_or(self.division_scale, |max| max.min(self.division_scale)),
+            ),
+            "+" | "-" | "*" | "%" => self.max_scale,
+            _ => None,
+        };
+        match (scale, value) {
+            (Some(scale), Value::Number(n)) => Value::Number(round(n, scale, self.rounding)),
+            (_, value) => value,
+        }
+    }
+}
+
+impl EvalOptions {
+    /// The profile for untrusted expressions, like rules kept in a database:
+    /// no assignments, only deterministic functions, [`Truthiness::Strict`],
+    /// and [`Limits`] of 10 000 elements and 100 000 chars. The parser
+    /// already bounds nesting, see [`crate::ComplexityLimits`] to bound the
+    /// size of a rule before accepting it.
+    pub fn safe() -> Self {
+        Self {
+            truthiness: Truthiness::Strict,
+            limits: Limits {
+                max_elements: Some(10_000),
+                max_string_len: Some(100_000),
+                max_variables: None,
+            },
+            forbid_assignment: true,
+            deterministic_only: true,
+            ..Self::default()
+        }
+    }
+
+    /// The defaults, spelled out next to [`EvalOptions::safe`].
+    pub fn permissive() -> Self {
+        Self::default()
+    }
+
+    pub(crate) fn no_match(&self, scrutinee: &Value) -> Result<Value> {
+        if self.strict_match {
+            return Err(Error::NoMatchingArm(scrutinee.to_string()));
+        }
+        Ok(Value::None)
+    }
+
+    // Adds an entry of a map literal, once its key is evaluated, since keys
+    // that are written differently can still be equal.
+    pub(crate) fn insert_key(&self, map: &mut Map, key: Value, value: Value) -> Result<()> {
+        if self.strict_map_keys && map.contains_key(&key) {
+            return Err(Error::DuplicateMapKey(key.to_string()));
+        }
+        map.insert(key, value);
+        Ok(())
+    }
+
+    pub(crate) fn condition(&self, value: Value, ctx: &Context) -> Result<bool> {
+        match self.truthiness {
+            Truthiness::Strict => value.bool(),
+            Truthiness::Coerce => {
+                if self.collect_warnings && !matches!(value, Value::Bool(_)) {
+                    let message = format!("a {} is used as a condition", value.type_name());
+                    ctx.warn("W_TRUTHINESS_COERCION", message);
+                }
+                Ok(value.truthy())
+            }
+        }
+    }
+
+    // The operators are registered like any other and only accept bools, so
+    // under `Truthiness::Coerce` their operands are converted beforehand.
+    pub(crate) fn prefix_operand(&self, op: &str, value: Value, ctx: &Context) -> Value {
+        if self.truthiness == Truthiness::Strict {
+            return value;
+        }
+        match (op, value) {
+            ("!" | "not", value) => {
+                self.coercion_warning(op, [&value], ctx);
+                Value::Bool(value.truthy())
+            }
+            ("AND" | "OR", Value::List(list)) => {
+                self.coercion_warning(op, &list, ctx);
+                Value::List(list.into_iter().map(|v| Value::Bool(v.truthy())).collect())
+            }
+            (_, value) => value,
+        }
+    }
+
+    // Applies a CALC operator, the one place every backend goes through.
+    pub(crate) fn infix(
+        &self,
+        op: &str,
+        handler: &Arc<InfixOpFunc>,
+        lhs: Value,
+        rhs: Value,
+        ctx: &Context,
+    ) -> Result<Value> {
+        if self.float_math {
+            if let (Value::Number(a), Value::Number(b)) = (&lhs, &rhs) {
+                if let Some(ans) = float_op(op, a, b) {
+                    return float_value(ans, ctx);
+                }
+            }
+        }
+        if self.collect_warnings && matches!(op, "==" | "!=") {
+            let (a, b) = (lhs.value_type(), rhs.value_type());
+            if a != b && lhs != Value::None && rhs != Value::None {
+                let message = format!(
+                    "'{}' compares a {} with a {}, which are never equal",
+                    op,
+                    lhs.type_name(),
+                    rhs.type_name()
+                );
+                ctx.warn("W_MIXED_TYPE_COMPARISON", message);
+            }
+        }
+        let ans = match self.concat(op, &lhs, &rhs) {
+            Some(ans) => ans,
+            None => {
+                let (lhs, rhs) = self.infix_operands(op, lhs, rhs, ctx);
+                self.math.apply(op, handler(lhs, rhs)?)
+            }
+        };
+        self.limits.check(ans)
+    }
+
+    // Applies a SETTER operator and assigns the result to `target`, giving
+    // the value of the assignment. Every backend goes through here too.
+    pub(crate) fn assign(
+        &self,
+        op: &str,
+        handler: &Arc<InfixOpFunc>,
+        lhs: Value,
+        rhs: Value,
+        target: &str,
+        ctx: &mut Context,
+    ) -> Result<Value> {
+        let value = match self.concat(op, &lhs, &rhs) {
+            Some(value) => value,
+            None => self.math.apply(op, handler(lhs, rhs)?),
+        };
+        match self.semantics {
+            SemanticsVersion::V1 => ctx.set_path(target, value).map(|_| Value::None),
+            SemanticsVersion::V2 => {
+                ctx.set_path(target, value.clone())?;
+                Ok(value)
+            }
+        }
+    }
+
+    // `+` or `+=` on two strings, which join them under V2.
+    fn concat(&self, op: &str, lhs: &Value, rhs: &Value) -> Option<Value> {
+        match (self.semantics, op, lhs, rhs) {
+            (SemanticsVersion::V2, "+" | "+=", Value::String(a), Value::String(b)) => {
+                Some(Value::String(format!("{}{}", a, b)))
+            }
+            _ => None,
+        }
+    }
+
+    fn infix_operands(&self, op: &str, lhs: Value, rhs: Value, ctx: &Context) -> (Value, Value) {
+        if self.truthiness == Truthiness::Strict || !matches!(op, "&&" | "||") {
+            return (lhs, rhs);
+        }
+        self.coercion_warning(op, [&lhs, &rhs], ctx);
+        (Value::Bool(lhs.truthy()), Value::Bool(rhs.truthy()))
+    }
+
+    // One warning for the operands of `op` that aren't bools, if any are.
+    fn coercion_warning<'v>(
+        &self,
+        op: &str,
+        operands: impl IntoIterator<Item = &'v Value>,
+        ctx: &Context,
+    ) {
+        if !self.collect_warnings {
+            return;
+        }
+        let mut others = operands
+            .into_iter()
+            .filter(|v| !matches!(v, Value::Bool(_)));
+        if let Some(value) = others.next() {
+            let message = format!("'{}' converts a {} to a bool", op, value.type_name());
+            ctx.warn("W_TRUTHINESS_COERCION", message);
+        }
+    }
+}
+
+// A float result as a number, with a warning when the number is further
+// from it than rounding to the 15 significant digits `try_from_f64` keeps
+// can explain, e.g. for a float too small for the 28 decimal places of a
+// `Decimal`.
+pub(crate) fn float_value(ans: f64, ctx: &Context) -> Result<Value> {
+    let value = Value::try_from_f64(ans)?;
+    if ctx.options().collect_warnings && (value.to_f64()? - ans).abs() > ans.abs() * 1e-14 {
+        let message = format!("the float {:e} is {} as a number", ans, value);
+        ctx.warn("W_LOSSY_FLOAT", message);
+    }
+    Ok(value)
+}
+
+impl Limits {
+    // Passes `value` through, unless it's a list, map or string that crosses
+    // a limit.
+    pub(crate) fn check(&self, value: Value) -> Result<Value> {
+        self.check_ref(&value)?;
+        Ok(value)
+    }
+
+    pub(crate) fn check_ref(&self, value: &Value) -> Result<()> {
+        if self.max_elements.is_some() || self.max_string_len.is_some() {
+            self.elements(value)?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn check_variables(&self, count: usize) -> Result<()> {
+        match self.max_variables {
+            Some(limit) if count > limit => Err(Error::ResourceLimitExceeded {
+                kind: "variables",
+                limit,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    // The elements in `value` and everything nested in it, failing as soon
+    // as the count or a string crosses its limit.
+    fn elements(&self, value: &Value) -> Result<usize> {
+        let (mut count, nested): (usize, Vec<&Value>) = match value {
+            Value::String(s) => {
+                self.check_string(s)?;
+                return Ok(0);
+            }
+            Value::List(list) => (list.len(), list.iter().collect()),
+            Value::Map(m) => (m.len(), m.iter().flat_map(|(k, v)| [k, v]).collect()),
+            _ => return Ok(0),
+        };
+        self.check_elements(count)?;
+        for value in nested {
+            count += self.elements(value)?;
+            self.check_elements(count)?;
+        }
+        Ok(count)
+    }
+
+    fn check_elements(&self, count: usize) -> Result<()> {
+        match self.max_elements {
+            Some(limit) if count > limit => Err(Error::ResourceLimitExceeded {
+                kind: "elements",
+                limit,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    fn check_string(&self, s: &str) -> Result<()> {
+        match self.max_string_len {
+            // A string has at most as many chars as bytes.
+            Some(limit) if s.len() > limit => self.check_chars(s.chars().count()),
+            _ => Ok(()),
+        }
+    }
+
+    // For a function to check the length of a string before building it.
+    pub(crate) fn check_chars(&self, count: usize) -> Result<()> {
+        match self.max_string_len {
+            Some(limit) if count > limit => Err(Error::ResourceLimitExceeded {
+                kind: "chars",
+                limit,
+            }),
+            _ => Ok(()),
+        }
+    }
+}
+
+fn float_op(op: &str, a: &Decimal, b: &Decimal) -> Option<f64> {
+    let (a, b) = (a.to_f64()?, b.to_f64()?);
+    match op {
+        "+" => Some(a + b),
+        "-" => Some(a - b),
+        "*" => Some(a * b),
+        "/" => Some(a / b),
+        "%" => Some(a % b),
+        _ => None,
+    }
+}
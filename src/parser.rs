@@ -1,20 +1,35 @@
+use crate::compat::Fnv;
 use crate::context::Context;
 use crate::define::*;
 use crate::descriptor::DescriptorManager;
 use crate::error::Error;
+use crate::evaluator::Evaluator;
 use crate::function::InnerFunctionManager;
-use crate::operator::{InfixOpManager, InfixOpType, PostfixOpManager, PrefixOpManager};
-use crate::token::{DelimTokenType, Token};
-use crate::tokenizer::Tokenizer;
-use crate::value::Value;
+use crate::keyword;
+use crate::operator::{
+    InfixOpConfig, InfixOpFunc, InfixOpManager, InfixOpType, PostfixOpManager, PrefixOpManager,
+};
+use crate::options::{EvalOptions, ParseOptions};
+use crate::token::{DelimTokenType, Span, Token};
+use crate::tokenizer::{name_quotes, string_quotes, Tokenizer};
+use crate::value::{Map, Value};
+use crate::warning::Warning;
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::fmt::{self, Write as _};
+use core::hash::{Hash, Hasher};
 use rust_decimal::prelude::*;
-use std::fmt;
 
-#[derive(Clone, PartialEq, Eq, Debug)]
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
 pub enum Literal<'a> {
     Number(Decimal),
     Bool(bool),
     String(&'a str),
+    /// `null` or `none`, which evaluates to `Value::None`.
+    None,
 }
 
 #[cfg(not(tarpaulin_include))]
@@ -25,216 +40,702 @@ impl<'a> fmt::Display for Literal<'a> {
             Number(value) => write!(f, "Number: {}", value.clone()),
             Bool(value) => write!(f, "Bool: {}", value.clone()),
             String(value) => write!(f, "String: {}", *value),
+            None => write!(f, "None"),
         }
     }
 }
 
-#[derive(Clone, PartialEq, Eq, Debug)]
+#[derive(Debug)]
 pub enum ExprAST<'a> {
     Literal(Literal<'a>),
     Unary(&'a str, Box<ExprAST<'a>>),
     Binary(&'a str, Box<ExprAST<'a>>, Box<ExprAST<'a>>),
-    Postfix(Box<ExprAST<'a>>, String),
+    Postfix(Box<ExprAST<'a>>, &'a str),
     Ternary(Box<ExprAST<'a>>, Box<ExprAST<'a>>, Box<ExprAST<'a>>),
     Reference(&'a str),
     Function(&'a str, Vec<ExprAST<'a>>),
     List(Vec<ExprAST<'a>>),
     Map(Vec<(ExprAST<'a>, ExprAST<'a>)>),
+    // The scrutinee, the `pattern: value` arms and the optional `_` arm.
+    Match(
+        Box<ExprAST<'a>>,
+        Vec<(ExprAST<'a>, ExprAST<'a>)>,
+        Option<Box<ExprAST<'a>>>,
+    ),
     Stmt(Vec<ExprAST<'a>>),
+    // `...expr` inside a list literal or the params of a call, whose list
+    // elements are inlined into the surrounding one.
+    Spread(Box<ExprAST<'a>>),
     None,
 }
 
+// The derived drop glue recurses once per level of nesting, which overflows
+// the stack on the same deep trees `exec_iterative` is meant for, so nodes are
+// detached onto a heap stack and dropped one at a time instead.
+impl<'a> Drop for ExprAST<'a> {
+    fn drop(&mut self) {
+        let mut stack = Vec::new();
+        self.detach_children(&mut stack);
+        while let Some(mut expr) = stack.pop() {
+            expr.detach_children(&mut stack);
+        }
+    }
+}
+
+// Like dropping, the derived `Clone`, `PartialEq` and `Hash` would recurse
+// once per level, so they go through the nodes with a work stack too.
+impl<'a> Clone for ExprAST<'a> {
+    fn clone(&self) -> Self {
+        // The nodes in pre-order, each without its children. A node's
+        // children come after it, so going backwards they're all built, in
+        // order, by the time the node is reached.
+        let mut nodes = Vec::new();
+        let mut stack = vec![self];
+        while let Some(expr) = stack.pop() {
+            nodes.push(expr.clone_node());
+            stack.extend(expr.children().into_iter().rev());
+        }
+        let mut built: Vec<ExprAST<'a>> = Vec::new();
+        while let Some(mut node) = nodes.pop() {
+            for child in node.children_mut() {
+                *child = built.pop().unwrap();
+            }
+            built.push(node);
+        }
+        built.pop().unwrap()
+    }
+}
+
+impl<'a> PartialEq for ExprAST<'a> {
+    fn eq(&self, other: &Self) -> bool {
+        let mut stack = vec![(self, other)];
+        while let Some((lhs, rhs)) = stack.pop() {
+            if !lhs.node_eq(rhs) {
+                return false;
+            }
+            stack.extend(lhs.children().into_iter().zip(rhs.children()));
+        }
+        true
+    }
+}
+
+impl<'a> Eq for ExprAST<'a> {}
+
+impl<'a> Hash for ExprAST<'a> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        let mut stack = vec![self];
+        while let Some(expr) = stack.pop() {
+            core::mem::discriminant(expr).hash(state);
+            match expr {
+                ExprAST::Literal(literal) => literal.hash(state),
+                ExprAST::Unary(op, _) | ExprAST::Binary(op, ..) | ExprAST::Postfix(_, op) => {
+                    op.hash(state)
+                }
+                ExprAST::Reference(name) => name.hash(state),
+                ExprAST::Function(name, exprs) => {
+                    name.hash(state);
+                    exprs.len().hash(state);
+                }
+                ExprAST::List(exprs) | ExprAST::Stmt(exprs) => exprs.len().hash(state),
+                ExprAST::Map(m) => m.len().hash(state),
+                ExprAST::Match(_, arms, default) => {
+                    arms.len().hash(state);
+                    default.is_some().hash(state);
+                }
+                ExprAST::Ternary(..) | ExprAST::Spread(_) | ExprAST::None => {}
+            }
+            stack.extend(expr.children().into_iter().rev());
+        }
+    }
+}
+
+// A unit of pending work for `ExprAST::exec_iterative`, with the node it
+// belongs to, which errors are reported against.
+type Work<'e, 'a> = (&'e ExprAST<'a>, Frame<'e, 'a>);
+
+// Every frame except `Eval` pops the values of its node's already evaluated
+// children off the value stack and pushes its own result.
+enum Frame<'e, 'a> {
+    Eval,
+    Unary(&'a str),
+    // Carries the assignment target when the operator is a SETTER.
+    Binary(&'a str, Arc<InfixOpFunc>, Option<&'e ExprAST<'a>>),
+    Postfix(&'a str),
+    Ternary(&'e ExprAST<'a>, &'e ExprAST<'a>),
+    // Both take the param exprs to tell which values are spread.
+    Function(&'a str, &'e [ExprAST<'a>]),
+    List(&'e [ExprAST<'a>]),
+    Spread,
+    Map(usize),
+    // The scrutinee is on top of the value stack while its arms are tried.
+    MatchArm(
+        &'e [(ExprAST<'a>, ExprAST<'a>)],
+        Option<&'e ExprAST<'a>>,
+        usize,
+    ),
+    // Compares the pattern of the arm on top of the value stack against the
+    // scrutinee below it.
+    MatchTest(
+        &'e [(ExprAST<'a>, ExprAST<'a>)],
+        Option<&'e ExprAST<'a>>,
+        usize,
+    ),
+    Chain(usize),
+}
+
+// The node-by-node dump of `ExprAST::dump`.
+struct Dump<'e, 'a>(&'e ExprAST<'a>);
+
 #[cfg(not(tarpaulin_include))]
-impl<'a> fmt::Display for ExprAST<'a> {
+impl<'e, 'a> fmt::Display for Dump<'e, 'a> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            Self::Literal(val) => write!(f, "Literal AST: {}", val.clone()),
-            Self::Unary(op, rhs) => {
-                write!(f, "Unary AST: Op: {}, Rhs: {}", op, rhs.clone())
+        use ExprAST::*;
+        match self.0 {
+            Literal(val) => write!(f, "Literal AST: {}", val),
+            Unary(op, rhs) => {
+                write!(f, "Unary AST: Op: {}, Rhs: {}", op, Dump(rhs))
             }
-            Self::Binary(op, lhs, rhs) => write!(
+            Binary(op, lhs, rhs) => write!(
                 f,
                 "Binary AST: Op: {}, Lhs: {}, Rhs: {}",
                 op,
-                lhs.clone(),
-                rhs.clone()
+                Dump(lhs),
+                Dump(rhs)
             ),
-            Self::Postfix(lhs, op) => {
-                write!(f, "Postfix AST: Lhs: {}, Op: {}", lhs.clone(), op.clone(),)
+            Postfix(lhs, op) => {
+                write!(f, "Postfix AST: Lhs: {}, Op: {}", Dump(lhs), op)
             }
-            Self::Ternary(condition, lhs, rhs) => write!(
+            Ternary(condition, lhs, rhs) => write!(
                 f,
                 "Ternary AST: Condition: {}, Lhs: {}, Rhs: {}",
-                condition.clone(),
-                lhs.clone(),
-                rhs.clone()
+                Dump(condition),
+                Dump(lhs),
+                Dump(rhs)
             ),
-            Self::Reference(name) => write!(f, "Reference AST: reference: {}", name),
-            Self::Function(name, params) => {
+            Reference(name) => write!(f, "Reference AST: reference: {}", name),
+            Function(name, params) => {
                 let mut s = "[".to_string();
-                for param in params.into_iter() {
-                    s.push_str(format!("{},", param.clone()).as_str());
+                for param in params.iter() {
+                    s.push_str(format!("{},", Dump(param)).as_str());
                 }
                 s.push(']');
                 write!(f, "Function AST: name: {}, params: {}", name, s)
             }
-            Self::List(params) => {
+            List(params) => {
                 let mut s = "[".to_string();
-                for param in params.into_iter() {
-                    s.push_str(format!("{},", param.clone()).as_str());
+                for param in params.iter() {
+                    s.push_str(format!("{},", Dump(param)).as_str());
                 }
                 s.push(']');
                 write!(f, "List AST: params: {}", s)
             }
-            Self::Map(m) => {
+            Map(m) => {
                 let mut s = String::new();
                 for (k, v) in m {
-                    s.push_str(format!("({} {}), ", k.clone(), v.clone()).as_str());
+                    s.push_str(format!("({} {}), ", Dump(k), Dump(v)).as_str());
                 }
                 write!(f, "Map AST: {}", s)
             }
-            Self::Stmt(exprs) => {
+            Match(scrutinee, arms, default) => {
+                let mut s = String::new();
+                for (pattern, value) in arms {
+                    s.push_str(format!("({} {}), ", Dump(pattern), Dump(value)).as_str());
+                }
+                if let Some(default) = default {
+                    s.push_str(format!("(_ {}), ", Dump(default)).as_str());
+                }
+                write!(f, "Match AST: Scrutinee: {}, Arms: {}", Dump(scrutinee), s)
+            }
+            Stmt(exprs) => {
                 let mut s = String::new();
                 for expr in exprs {
-                    s.push_str(format!("{};", expr.clone()).as_str());
+                    s.push_str(format!("{};", Dump(expr)).as_str());
                 }
                 write!(f, "Chain AST: {}", s)
             }
-            Self::None => write!(f, "None"),
+            Spread(expr) => write!(f, "Spread AST: {}", Dump(expr)),
+            None => write!(f, "None"),
         }
     }
 }
 
 impl<'a> ExprAST<'a> {
+    /// Evaluates the expression with a default [`Evaluator`]. Errors are
+    /// wrapped in [`Error::WithContext`] with the text of the sub-expression
+    /// that failed and of its parent; [`Error::root`] gets the underlying
+    /// error back.
     pub fn exec(&self, ctx: &mut Context) -> Result<Value> {
-        use ExprAST::*;
-        match self {
-            Literal(literal) => self.exec_literal(literal.clone()),
-            Reference(name) => self.exec_reference(name, ctx),
-            Function(name, exprs) => self.exec_function(name, exprs.clone(), ctx),
-            Unary(op, rhs) => self.exec_unary(op, rhs, ctx),
-            Binary(op, lhs, rhs) => self.exec_binary(op, lhs, rhs, ctx),
-            Postfix(lhs, op) => self.exec_postfix(lhs, op.clone(), ctx),
-            Ternary(condition, lhs, rhs) => self.exec_ternary(condition, lhs, rhs, ctx),
-            List(params) => self.exec_list(params.clone(), ctx),
-            Stmt(exprs) => self.exec_chain(exprs.clone(), ctx),
-            Map(m) => self.exec_map(m.clone(), ctx),
-            None => Ok(Value::None),
+        Evaluator::new().evaluate(self, ctx)
+    }
+
+    /// Evaluates the expression like [`ExprAST::exec`] with
+    /// [`EvalOptions::collect_warnings`] set, and returns the warnings
+    /// raised along the way with the value, each with the span of the node
+    /// that raised it in [`ExprAST::expr`]. The options of `ctx` are left
+    /// as they were.
+    pub fn exec_with_warnings(&self, ctx: &mut Context) -> Result<(Value, Vec<Warning>)> {
+        let options = ctx.options();
+        ctx.set_options(EvalOptions {
+            collect_warnings: true,
+            ..options
+        });
+        let start = ctx.warning_count();
+        let ans = self.exec(ctx);
+        ctx.set_options(options);
+        let raised = ctx.split_warnings(start);
+        let (_, spans) = self.layout();
+        let warnings = raised
+            .into_iter()
+            .map(|(warning, node)| Warning {
+                span: node.and_then(|node| spans.get(&node).copied()),
+                ..warning
+            })
+            .collect();
+        Ok((ans?, warnings))
+    }
+
+    /// Evaluates each statement of a chain like [`ExprAST::exec`], but goes
+    /// on past the ones that fail, e.g. for a batch script where one bad
+    /// statement shouldn't stop the rest. Returns the value of the last
+    /// statement, or every failure as an [`Error::InStatement`] if any
+    /// failed. An expression that isn't a chain is its only statement.
+    pub fn exec_chain_collect(&self, ctx: &mut Context) -> core::result::Result<Value, Vec<Error>> {
+        let stmts = match self {
+            ExprAST::Stmt(exprs) => exprs.as_slice(),
+            expr => core::slice::from_ref(expr),
+        };
+        let mut ans = Value::None;
+        let mut errors = Vec::new();
+        for (i, expr) in stmts.iter().enumerate() {
+            match expr.exec(ctx) {
+                Ok(value) => ans = value,
+                Err(err) => errors.push(err.in_statement(i + 1, || expr.expr())),
+            }
         }
+        match errors.is_empty() {
+            true => Ok(ans),
+            false => Err(errors),
+        }
+    }
+
+    /// Evaluates the expression and converts the result to `T`, e.g. `bool`,
+    /// `Decimal`, `String` or `i64`. A result of another type fails with
+    /// [`Error::NotConvertible`], naming its type and `T`, and so does a
+    /// number an integer type can't hold without rounding.
+    pub fn eval_as<T: TryFrom<Value, Error = Error>>(&self, ctx: &mut Context) -> Result<T> {
+        T::try_from(self.exec(ctx)?).map_err(|err| err.with_context(|| self.expr()))
     }
 
-    fn exec_literal(&self, literal: Literal<'a>) -> Result<Value> {
+    pub(crate) fn exec_literal(&self, literal: Literal<'a>) -> Result<Value> {
         match literal {
             Literal::Bool(value) => Ok(Value::from(value)),
             Literal::Number(value) => Ok(Value::from(value)),
             Literal::String(value) => Ok(Value::from(value)),
+            Literal::None => Ok(Value::None),
         }
     }
 
-    fn exec_reference(&self, name: &'a str, ctx: &Context) -> Result<Value> {
+    pub(crate) fn exec_reference(&self, name: &'a str, ctx: &Context) -> Result<Value> {
         ctx.value(name)
     }
 
-    fn exec_function(
-        &self,
-        name: &'a str,
-        exprs: Vec<ExprAST<'a>>,
-        ctx: &mut Context,
-    ) -> Result<Value> {
-        let mut params: Vec<Value> = Vec::new();
-        for expr in exprs.into_iter() {
-            params.push(expr.exec(ctx)?)
-        }
-        match ctx.get_func(name) {
-            Some(func) => func(params),
-            None => self.redirect_inner_function(name, params),
+    // The direct sub-expressions, in source order.
+    pub(crate) fn children(&self) -> Vec<&ExprAST<'a>> {
+        use ExprAST::*;
+        match self {
+            Unary(_, rhs) | Postfix(rhs, _) | Spread(rhs) => vec![rhs],
+            Binary(_, lhs, rhs) => vec![lhs, rhs],
+            Ternary(condition, lhs, rhs) => vec![condition, lhs, rhs],
+            Function(_, exprs) | List(exprs) | Stmt(exprs) => exprs.iter().collect(),
+            Map(m) => m.iter().flat_map(|(k, v)| [k, v]).collect(),
+            Match(scrutinee, arms, default) => {
+                let mut ans = vec![scrutinee.as_ref()];
+                ans.extend(arms.iter().flat_map(|(k, v)| [k, v]));
+                ans.extend(default.as_deref());
+                ans
+            }
+            Literal(_) | Reference(_) | None => Vec::new(),
         }
     }
 
-    fn redirect_inner_function(&self, name: &str, params: Vec<Value>) -> Result<Value> {
-        InnerFunctionManager::new().get(name)?(params)
+    // `children`, mutably.
+    pub(crate) fn children_mut(&mut self) -> Vec<&mut ExprAST<'a>> {
+        use ExprAST::*;
+        match self {
+            Unary(_, rhs) | Postfix(rhs, _) | Spread(rhs) => vec![rhs.as_mut()],
+            Binary(_, lhs, rhs) => vec![lhs.as_mut(), rhs.as_mut()],
+            Ternary(condition, lhs, rhs) => vec![condition.as_mut(), lhs.as_mut(), rhs.as_mut()],
+            Function(_, exprs) | List(exprs) | Stmt(exprs) => exprs.iter_mut().collect(),
+            Map(m) => m.iter_mut().flat_map(|(k, v)| [k, v]).collect(),
+            Match(scrutinee, arms, default) => {
+                let mut ans = vec![scrutinee.as_mut()];
+                ans.extend(arms.iter_mut().flat_map(|(k, v)| [k, v]));
+                ans.extend(default.as_deref_mut());
+                ans
+            }
+            Literal(_) | Reference(_) | None => Vec::new(),
+        }
     }
 
-    fn exec_unary(&self, op: &'a str, rhs: &ExprAST, ctx: &mut Context) -> Result<Value> {
-        PrefixOpManager::new().get(&op)?(rhs.exec(ctx)?)
+    // Calls `f` on every node, parents first, with a work stack so deep
+    // trees don't recurse.
+    pub(crate) fn walk_mut(&mut self, mut f: impl FnMut(&mut ExprAST<'a>)) {
+        let mut stack = vec![self];
+        while let Some(expr) = stack.pop() {
+            f(expr);
+            stack.extend(expr.children_mut().into_iter().rev());
+        }
     }
 
-    fn exec_binary(
+    fn redirect_inner_function(
         &self,
-        op: &'a str,
-        lhs: &ExprAST<'a>,
-        rhs: &ExprAST<'a>,
-        ctx: &mut Context,
+        name: &str,
+        params: Vec<Value>,
+        ctx: &Context,
     ) -> Result<Value> {
-        match InfixOpManager::new().get_op_type(&op)? {
-            InfixOpType::CALC => {
-                InfixOpManager::new().get_handler(&op)?(lhs.exec(ctx)?, rhs.exec(ctx)?)
+        InnerFunctionManager::new().resolve(name, ctx)?(ctx, params)
+    }
+
+    /// Evaluates the expression like [`ExprAST::exec`], but walks the tree
+    /// with an explicit work stack instead of native recursion, so that very
+    /// deep expressions (e.g. machine-generated sums with many thousands of
+    /// terms) can't exhaust the thread stack.
+    pub fn exec_iterative(&self, ctx: &mut Context) -> Result<Value> {
+        let mut work = vec![(self, Frame::Eval)];
+        let mut values: Vec<Value> = Vec::new();
+        while let Some((node, frame)) = work.pop() {
+            node.step(frame, &mut work, &mut values, ctx)
+                .map_err(|err| self.wrap_error(err, node))?;
+        }
+        Ok(values.pop().unwrap_or(Value::None))
+    }
+
+    // Runs a frame of `exec_iterative` that belongs to this node.
+    fn step<'e>(
+        &'e self,
+        frame: Frame<'e, 'a>,
+        work: &mut Vec<Work<'e, 'a>>,
+        values: &mut Vec<Value>,
+        ctx: &mut Context,
+    ) -> Result<()> {
+        match frame {
+            Frame::Eval => {
+                ctx.tick()?;
+                self.expand(work, values, ctx)?
+            }
+            Frame::Unary(op) => {
+                let rhs = ctx.options().prefix_operand(op, values.pop().unwrap(), ctx);
+                values.push(PrefixOpManager::new().get(op)?(rhs)?);
+            }
+            Frame::Binary(op, handler, lhs) => {
+                let rhs = values.pop().unwrap();
+                let lhs_value = values.pop().unwrap();
+                match lhs {
+                    Some(lhs) => {
+                        let target = lhs.get_reference_name()?;
+                        let options = ctx.options();
+                        values.push(options.assign(op, &handler, lhs_value, rhs, target, ctx)?);
+                    }
+                    None => values.push(ctx.options().infix(op, &handler, lhs_value, rhs, ctx)?),
+                }
+            }
+            Frame::Postfix(op) => {
+                let lhs = values.pop().unwrap();
+                values.push(PostfixOpManager::new().get(op)?(lhs)?);
+            }
+            Frame::Ternary(lhs, rhs) => {
+                if ctx.options().condition(values.pop().unwrap(), ctx)? {
+                    work.push((lhs, Frame::Eval));
+                } else {
+                    work.push((rhs, Frame::Eval));
+                }
+            }
+            Frame::Function(name, exprs) => {
+                let params = flatten(exprs, values.split_off(values.len() - exprs.len()));
+                ctx.check_call(name)?;
+                let ans = match ctx.get_func(name) {
+                    Some(func) => func(params)?,
+                    None => self.redirect_inner_function(name, params, ctx)?,
+                };
+                values.push(ctx.options().limits.check(ans)?);
+            }
+            Frame::List(exprs) => {
+                let list = flatten(exprs, values.split_off(values.len() - exprs.len()));
+                values.push(ctx.options().limits.check(Value::List(list))?);
+            }
+            Frame::Spread => {
+                let list = values.pop().unwrap().spread()?;
+                values.push(Value::List(list));
             }
-            InfixOpType::SETTER => {
-                let (a, b) = (lhs.exec(ctx)?, rhs.exec(ctx)?);
-                ctx.set_variable(
-                    lhs.get_reference_name()?,
-                    InfixOpManager::new().get_handler(&op)?(a, b)?,
-                );
-                Ok(Value::None)
+            Frame::Map(len) => {
+                let mut flat = values.split_off(values.len() - 2 * len).into_iter();
+                let mut m = Map::with_capacity_and_hasher(len, Default::default());
+                while let (Some(k), Some(v)) = (flat.next(), flat.next()) {
+                    ctx.options().insert_key(&mut m, k, v)?;
+                }
+                values.push(ctx.options().limits.check(Value::Map(m))?);
+            }
+            Frame::MatchArm(arms, default, i) => match arms.get(i) {
+                Some((pattern, _)) => {
+                    work.push((self, Frame::MatchTest(arms, default, i)));
+                    work.push((pattern, Frame::Eval));
+                }
+                Option::None => {
+                    let scrutinee = values.pop().unwrap();
+                    match default {
+                        Some(default) => work.push((default, Frame::Eval)),
+                        Option::None => values.push(ctx.options().no_match(&scrutinee)?),
+                    }
+                }
+            },
+            Frame::MatchTest(arms, default, i) => {
+                let pattern = values.pop().unwrap();
+                if values.last() == Some(&pattern) {
+                    values.pop();
+                    work.push((&arms[i].1, Frame::Eval));
+                } else {
+                    work.push((self, Frame::MatchArm(arms, default, i + 1)));
+                }
+            }
+            Frame::Chain(len) => {
+                let ans = values
+                    .split_off(values.len() - len)
+                    .pop()
+                    .unwrap_or(Value::None);
+                values.push(ans);
             }
         }
+        Ok(())
     }
 
-    fn exec_postfix(&self, lhs: &ExprAST, op: String, ctx: &mut Context) -> Result<Value> {
-        PostfixOpManager::new().get(&op)?(lhs.exec(ctx)?)
+    // Wraps an error that `node`, a node of this tree, raised under
+    // `exec_iterative` the way `exec` does as the error comes up through the
+    // nodes above it.
+    fn wrap_error(&self, err: Error, node: &ExprAST<'a>) -> Error {
+        let path = self.path_to(node);
+        let mut err = err.with_context(|| node.expr());
+        for pair in path.windows(2).rev() {
+            let (parent, child) = (pair[0], pair[1]);
+            if let ExprAST::Stmt(exprs) = parent {
+                if let Some(i) = exprs.iter().position(|expr| core::ptr::eq(expr, child)) {
+                    err = err.in_statement(i + 1, || child.expr());
+                }
+            }
+            err = err.with_context(|| parent.expr());
+        }
+        err
     }
 
-    fn exec_ternary(
-        &self,
-        condition: &ExprAST,
-        lhs: &ExprAST,
-        rhs: &ExprAST,
+    // The nodes from the root down to `node`, found with a work stack.
+    fn path_to<'e>(&'e self, node: &ExprAST<'a>) -> Vec<&'e ExprAST<'a>> {
+        // The nodes seen so far, each with the index of its parent.
+        let mut seen = vec![(self, usize::MAX)];
+        let mut stack = vec![0];
+        while let Some(i) = stack.pop() {
+            if core::ptr::eq(seen[i].0, node) {
+                let mut path = Vec::new();
+                let mut i = i;
+                while i != usize::MAX {
+                    path.push(seen[i].0);
+                    i = seen[i].1;
+                }
+                path.reverse();
+                return path;
+            }
+            for child in seen[i].0.children() {
+                seen.push((child, i));
+                stack.push(seen.len() - 1);
+            }
+        }
+        Vec::new()
+    }
+
+    // Pushes the continuation for this node followed by its children in
+    // reverse, so that children are evaluated left to right, just like the
+    // recursive evaluator. Leaves are evaluated on the spot.
+    fn expand<'e>(
+        &'e self,
+        work: &mut Vec<Work<'e, 'a>>,
+        values: &mut Vec<Value>,
         ctx: &mut Context,
-    ) -> Result<Value> {
-        match condition.exec(ctx)? {
-            Value::Bool(val) => {
-                if val {
-                    return lhs.exec(ctx);
+    ) -> Result<()> {
+        use ExprAST::*;
+        match self {
+            Literal(literal) => values.push(self.exec_literal(literal.clone())?),
+            Reference(name) => values.push(self.exec_reference(name, ctx)?),
+            None => values.push(Value::None),
+            Unary(op, rhs) => {
+                work.push((self, Frame::Unary(op)));
+                work.push((rhs, Frame::Eval));
+            }
+            Binary(op, lhs, rhs) => {
+                let InfixOpConfig(_, op_type, _, handler) = InfixOpManager::new().get(op)?;
+                let target = match op_type {
+                    InfixOpType::CALC => Option::None,
+                    InfixOpType::SETTER => Some(lhs.as_ref()),
+                };
+                work.push((self, Frame::Binary(op, handler, target)));
+                work.push((rhs, Frame::Eval));
+                match (target, lhs.as_ref()) {
+                    (Some(_), Reference(name)) => values.push(ctx.target_value(name)?),
+                    _ => work.push((lhs, Frame::Eval)),
+                }
+            }
+            Postfix(lhs, op) => {
+                work.push((self, Frame::Postfix(op)));
+                work.push((lhs, Frame::Eval));
+            }
+            Ternary(condition, lhs, rhs) => {
+                work.push((self, Frame::Ternary(lhs, rhs)));
+                work.push((condition, Frame::Eval));
+            }
+            Function(name, exprs) => {
+                work.push((self, Frame::Function(name, exprs)));
+                work.extend(exprs.iter().rev().map(|expr| (expr, Frame::Eval)));
+            }
+            List(exprs) => {
+                work.push((self, Frame::List(exprs)));
+                work.extend(exprs.iter().rev().map(|expr| (expr, Frame::Eval)));
+            }
+            Spread(expr) => {
+                work.push((self, Frame::Spread));
+                work.push((expr, Frame::Eval));
+            }
+            Map(m) => {
+                work.push((self, Frame::Map(m.len())));
+                for (k, v) in m.iter().rev() {
+                    work.push((v, Frame::Eval));
+                    work.push((k, Frame::Eval));
                 }
-                rhs.exec(ctx)
             }
-            _ => Err(Error::ShouldBeBool()),
+            Match(scrutinee, arms, default) => {
+                work.push((self, Frame::MatchArm(arms, default.as_deref(), 0)));
+                work.push((scrutinee, Frame::Eval));
+            }
+            Stmt(exprs) => {
+                work.push((self, Frame::Chain(exprs.len())));
+                work.extend(exprs.iter().rev().map(|expr| (expr, Frame::Eval)));
+            }
         }
+        Ok(())
     }
 
-    fn exec_list(&self, params: Vec<ExprAST>, ctx: &mut Context) -> Result<Value> {
-        let mut ans = Vec::new();
-        for expr in params {
-            ans.push(expr.exec(ctx)?);
+    // A copy of the node with `None` in place of each child.
+    fn clone_node(&self) -> Self {
+        use ExprAST::*;
+        let none = || Box::new(None);
+        let nones = |len: usize| (0..len).map(|_| None).collect();
+        let pairs = |len: usize| (0..len).map(|_| (None, None)).collect();
+        match self {
+            Literal(literal) => Literal(literal.clone()),
+            Unary(op, _) => Unary(op, none()),
+            Binary(op, _, _) => Binary(op, none(), none()),
+            Postfix(_, op) => Postfix(none(), op),
+            Ternary(..) => Ternary(none(), none(), none()),
+            Reference(name) => Reference(name),
+            Function(name, exprs) => Function(name, nones(exprs.len())),
+            List(exprs) => List(nones(exprs.len())),
+            Map(m) => Map(pairs(m.len())),
+            Match(_, arms, default) => {
+                Match(none(), pairs(arms.len()), default.as_ref().map(|_| none()))
+            }
+            Stmt(exprs) => Stmt(nones(exprs.len())),
+            Spread(_) => Spread(none()),
+            None => None,
         }
-        Ok(Value::List(ans))
     }
 
-    fn exec_chain(&self, params: Vec<ExprAST>, ctx: &mut Context) -> Result<Value> {
-        let mut ans = Value::None;
-        for expr in params {
-            ans = expr.exec(ctx)?;
+    // Whether the nodes are equal, leaving their children aside. Equal nodes
+    // have as many children.
+    fn node_eq(&self, other: &Self) -> bool {
+        use ExprAST::*;
+        match (self, other) {
+            (Literal(lhs), Literal(rhs)) => lhs == rhs,
+            (Unary(lhs, _), Unary(rhs, _))
+            | (Binary(lhs, ..), Binary(rhs, ..))
+            | (Postfix(_, lhs), Postfix(_, rhs))
+            | (Reference(lhs), Reference(rhs)) => lhs == rhs,
+            (Function(lhs, lhs_exprs), Function(rhs, rhs_exprs)) => {
+                lhs == rhs && lhs_exprs.len() == rhs_exprs.len()
+            }
+            (List(lhs), List(rhs)) | (Stmt(lhs), Stmt(rhs)) => lhs.len() == rhs.len(),
+            (Map(lhs), Map(rhs)) => lhs.len() == rhs.len(),
+            (Match(_, lhs, lhs_default), Match(_, rhs, rhs_default)) => {
+                lhs.len() == rhs.len() && lhs_default.is_some() == rhs_default.is_some()
+            }
+            (Ternary(..), Ternary(..)) | (Spread(_), Spread(_)) | (None, None) => true,
+            _ => false,
         }
-        Ok(ans)
     }
 
-    fn exec_map(&self, m: Vec<(ExprAST, ExprAST)>, ctx: &mut Context) -> Result<Value> {
-        let mut ans = Vec::new();
-        for (k, v) in m {
-            ans.push((k.exec(ctx)?, v.exec(ctx)?));
+    fn detach_children(&mut self, stack: &mut Vec<ExprAST<'a>>) {
+        use ExprAST::*;
+        match self {
+            Unary(_, rhs) | Postfix(rhs, _) | Spread(rhs) => rhs.detach_into(stack),
+            Binary(_, lhs, rhs) => {
+                lhs.detach_into(stack);
+                rhs.detach_into(stack);
+            }
+            Ternary(condition, lhs, rhs) => {
+                condition.detach_into(stack);
+                lhs.detach_into(stack);
+                rhs.detach_into(stack);
+            }
+            Function(_, exprs) | List(exprs) | Stmt(exprs) => {
+                exprs.iter_mut().for_each(|expr| expr.detach_into(stack))
+            }
+            Map(m) => m.iter_mut().for_each(|(k, v)| {
+                k.detach_into(stack);
+                v.detach_into(stack);
+            }),
+            Match(scrutinee, arms, default) => {
+                scrutinee.detach_into(stack);
+                arms.iter_mut().for_each(|(pattern, value)| {
+                    pattern.detach_into(stack);
+                    value.detach_into(stack);
+                });
+                if let Some(default) = default {
+                    default.detach_into(stack);
+                }
+            }
+            Literal(_) | Reference(_) | None => {}
+        }
+    }
+
+    // Leaves own nothing that could recurse, so they're left in place to be
+    // dropped by the usual glue.
+    fn detach_into(&mut self, stack: &mut Vec<ExprAST<'a>>) {
+        if !matches!(
+            self,
+            ExprAST::Literal(_) | ExprAST::Reference(_) | ExprAST::None
+        ) {
+            stack.push(core::mem::replace(self, ExprAST::None));
         }
-        Ok(Value::Map(ans))
     }
 
-    fn get_precidence(&self) -> (bool, (i32, i32)) {
+    // How tightly the node holds together as an operand, on the left of an
+    // operator or not. A binary node on the left binds with its right
+    // binding power, and on the right with its left one.
+    fn get_precidence(&self, left: bool) -> Binding {
         match self {
-            ExprAST::Binary(op, _, _) => (true, InfixOpManager::new().get_precidence(op)),
-            _ => (false, (-1, -1)),
+            ExprAST::Ternary(..) => Binding::Ternary,
+            ExprAST::Binary(op, _, _) => {
+                let precidence = InfixOpManager::new().get_precidence(op);
+                Binding::Binary(precidence.map(|(l_bp, r_bp)| if left { r_bp } else { l_bp }))
+            }
+            // An operator after it that binds tighter than `&&` would be
+            // taken into its operand. On the right it's as loose as the
+            // right operand of `&&`.
+            ExprAST::Unary("not", _) => {
+                Binding::Binary(Some(if left { NOT_BP } else { NOT_BP + 1 }))
+            }
+            ExprAST::Unary(..) => Binding::Unary,
+            ExprAST::Postfix(..) => Binding::Postfix,
+            _ => Binding::Atom,
         }
     }
 
-    fn get_reference_name(&self) -> Result<&'a str> {
+    pub(crate) fn get_reference_name(&self) -> Result<&'a str> {
         match self {
             ExprAST::Reference(name) => Ok(name),
             _ => Err(Error::NotReferenceExpr),
@@ -242,123 +743,303 @@ impl<'a> ExprAST<'a> {
     }
 }
 
-impl<'a> ExprAST<'a> {
-    pub fn expr(&self) -> String {
-        match self {
-            Self::Literal(val) => self.literal_expr(val.clone()),
-            Self::Reference(name) => self.reference_expr(name),
-            Self::Function(name, exprs) => self.function_expr(name, exprs.clone()),
-            Self::Unary(op, rhs) => self.unary_expr(op, rhs),
-            Self::Binary(op, lhs, rhs) => self.binary_expr(op, lhs, rhs),
-            Self::Postfix(lhs, op) => self.postfix_expr(lhs, op),
-            Self::Ternary(condition, lhs, rhs) => self.ternary_expr(condition, lhs, rhs),
-            Self::List(params) => self.list_expr(params.clone()),
-            Self::Map(m) => self.map_expr(m.clone()),
-            Self::Stmt(exprs) => self.chain_expr(exprs.clone()),
-            Self::None => "".to_string(),
-        }
-    }
+// From the loosest to the tightest. A ternary is looser than any operator, a
+// binary node binds with the power of its operator, which is `None` and so
+// the lowest when it isn't registered, and unary and postfix operators take
+// a single primary, except `not`, which binds like an operator.
+#[derive(PartialEq, PartialOrd)]
+enum Binding {
+    Ternary,
+    Binary(Option<i32>),
+    Unary,
+    Postfix,
+    Atom,
+}
 
-    fn literal_expr(&self, val: Literal) -> String {
-        use Literal::*;
-        match val {
-            Number(value) => value.to_string(),
-            Bool(value) => {
-                if value {
-                    "true".into()
-                } else {
-                    "false".into()
-                }
-            }
-            String(value) => "\"".to_string() + &value + "\"",
+// The error for a built-in operator whose feature is off, the same one
+// evaluating it would give.
+fn disabled_op(op: &str) -> Error {
+    match op {
+        "++" | "--" | "!" | "not" | "AND" | "OR" | "~" => {
+            Error::PrefixOpNotRegistered(op.to_string())
         }
+        _ => Error::InfixOpNotRegistered(op.to_string()),
     }
+}
+
+// Whether a reference with this name is read as a string when it's a map
+// key, which dotted and null-safe paths are not.
+fn is_bare_key(name: &str) -> bool {
+    !name.contains(['.', '?'])
+}
 
-    fn reference_expr(&self, val: &'a str) -> String {
-        val.to_string()
+// A name that wouldn't read back as one, like `order total` or `in`, is
+// written between backticks.
+fn write_name(name: &str, f: &mut impl fmt::Write) -> fmt::Result {
+    match name_quotes(name) {
+        Some(quotes) => write!(f, "{}{}{}", quotes, name, quotes),
+        None => f.write_str(name),
     }
+}
 
-    fn function_expr(&self, name: &'a str, exprs: Vec<ExprAST>) -> String {
-        let mut ans = name.to_string();
-        ans.push('(');
-        for i in 0..exprs.len() {
-            ans.push_str(&exprs[i].expr());
-            if i < exprs.len() - 1 {
-                ans.push(',');
-            }
+// Whether `a prev b op c` is a chain meaning `a prev b && b op c`, as in
+// maths: both are comparisons of the same precedence.
+fn chains(prev: &str, op: &str) -> bool {
+    [&["<", "<=", ">", ">="][..], &["==", "!="]]
+        .iter()
+        .any(|ops| ops.contains(&prev) && ops.contains(&op))
+}
+
+// Inlines the values of the spread `exprs`, which `Frame::Spread` has
+// already checked are lists.
+fn flatten(exprs: &[ExprAST], values: Vec<Value>) -> Vec<Value> {
+    if !exprs.iter().any(|expr| matches!(expr, ExprAST::Spread(_))) {
+        return values;
+    }
+    let mut ans = Vec::with_capacity(values.len());
+    for (expr, value) in exprs.iter().zip(values) {
+        match (expr, value) {
+            (ExprAST::Spread(_), Value::List(list)) => ans.extend(list),
+            (_, value) => ans.push(value),
         }
-        ans.push(')');
-        ans
     }
+    ans
+}
 
-    fn unary_expr(&self, op: &'a str, rhs: &ExprAST) -> String {
-        op.to_string() + " " + &rhs.expr()
+impl<'a> ExprAST<'a> {
+    /// The expression as source text, the same as the `Display` form.
+    pub fn expr(&self) -> String {
+        let mut s = String::new();
+        self.write_expr(&mut s).unwrap();
+        s
     }
 
-    fn binary_expr(&self, op: &'a str, lhs: &ExprAST, rhs: &ExprAST) -> String {
-        let left = {
-            let (is, precidence) = lhs.get_precidence();
-            let mut tmp: String = lhs.expr();
-            if is && precidence < InfixOpManager::new().get_precidence(op) {
-                tmp = "(".to_string() + &lhs.expr() + &")".to_string();
-            }
-            tmp
-        };
-        let right = {
-            let (is, precidence) = rhs.get_precidence();
-            let mut tmp = rhs.expr();
-            if is && precidence < InfixOpManager::new().get_precidence(op) {
-                tmp = "(".to_string() + &rhs.expr() + &")".to_string();
-            }
-            tmp
-        };
-        left + " " + op + " " + &right
+    /// Writes the expression as source text, without building the text of
+    /// each sub-expression separately.
+    pub fn write_expr(&self, f: &mut impl fmt::Write) -> fmt::Result {
+        self.write_node(&mut Layout {
+            out: f,
+            len: 0,
+            spans: None,
+        })
     }
 
-    fn postfix_expr(&self, lhs: &ExprAST, op: &str) -> String {
-        lhs.expr() + " " + op
+    /// The expression as [`ExprAST::expr`] prints it, with the span of each
+    /// node in that text, keyed by the node's address. A parenthesized node
+    /// spans what's inside the parentheses.
+    pub(crate) fn layout(&self) -> (String, BTreeMap<usize, Span>) {
+        let mut s = String::new();
+        let mut f = Layout {
+            out: &mut s,
+            len: 0,
+            spans: Some(BTreeMap::new()),
+        };
+        self.write_node(&mut f).unwrap();
+        let spans = f.spans.unwrap_or_default();
+        (s, spans)
     }
 
-    fn ternary_expr(&self, condition: &ExprAST, lhs: &ExprAST, rhs: &ExprAST) -> String {
-        condition.expr() + " ? " + &lhs.expr() + " : " + &rhs.expr()
+    // Prints with a stack of what's left to write rather than recursing, as
+    // a long chain like `1 + 1 + ...` nests deeper than the native stack.
+    fn write_node<W: fmt::Write>(&self, f: &mut Layout<W>) -> fmt::Result {
+        let mut stack = vec![Print::Node(self)];
+        while let Some(next) = stack.pop() {
+            match next {
+                Print::Node(node) => {
+                    stack.push(Print::End(node, f.len));
+                    stack.extend(node.parts().into_iter().rev());
+                }
+                Print::Operand(node, left, min) => {
+                    if node.get_precidence(left) < min {
+                        stack.extend([Print::Char(')'), Print::Node(node), Print::Char('(')]);
+                    } else {
+                        stack.push(Print::Node(node));
+                    }
+                }
+                Print::Literal(val) => self.write_literal(val, f)?,
+                Print::Name(name) => write_name(name, f)?,
+                Print::Str(s) => f.write_str(s)?,
+                Print::Char(c) => f.write_char(c)?,
+                Print::End(node, start) => {
+                    if let Some(spans) = &mut f.spans {
+                        spans.insert(node as *const Self as usize, Span(start, f.len));
+                    }
+                }
+            }
+        }
+        Ok(())
     }
 
-    fn list_expr(&self, params: Vec<ExprAST>) -> String {
-        let mut s = String::from("[");
-        for i in 0..params.len() {
-            s.push_str(params[i].expr().as_str());
-            if i < params.len() - 1 {
-                s.push_str(",");
+    // What `write_node` prints for this node, in order.
+    fn parts<'e>(&'e self) -> Vec<Print<'e, 'a>> {
+        match self {
+            Self::Literal(val) => vec![Print::Literal(val)],
+            Self::Reference(name) => vec![Print::Name(name)],
+            Self::Function(name, exprs) => {
+                let mut parts = vec![Print::Name(name), Print::Char('(')];
+                push_separated(&mut parts, exprs, ",");
+                parts.push(Print::Char(')'));
+                parts
+            }
+            Self::Unary(op, rhs) => vec![
+                Print::Str(op),
+                Print::Char(' '),
+                Print::Operand(rhs, false, Binding::Unary),
+            ],
+            Self::Binary(op, lhs, rhs) => {
+                // Operands that bind looser than `op` are parenthesized, and
+                // so is a right operand as loose as `op` when it's
+                // left-associative, like the `b - c` of `a - (b - c)`, and a
+                // left one that would chain with it, like the `a == b` of
+                // `(a == b) == c`.
+                let precidence = InfixOpManager::new().get_precidence(op);
+                let r_bp = precidence.map(|(_, r_bp)| r_bp + 1);
+                let l_bp = match &**lhs {
+                    ExprAST::Binary(lhs_op, ..) if chains(lhs_op, op) => r_bp,
+                    _ => precidence.map(|(l_bp, _)| l_bp),
+                };
+                vec![
+                    Print::Operand(lhs, true, Binding::Binary(l_bp)),
+                    Print::Char(' '),
+                    Print::Str(op),
+                    Print::Char(' '),
+                    Print::Operand(rhs, false, Binding::Binary(r_bp)),
+                ]
+            }
+            Self::Postfix(lhs, op) => vec![
+                Print::Operand(lhs, true, Binding::Atom),
+                Print::Char(' '),
+                Print::Str(op),
+            ],
+            Self::Ternary(condition, lhs, rhs) => vec![
+                Print::Operand(condition, true, Binding::Binary(None)),
+                Print::Str(" ? "),
+                Print::Node(lhs),
+                Print::Str(" : "),
+                Print::Node(rhs),
+            ],
+            Self::List(params) => {
+                let mut parts = vec![Print::Char('[')];
+                push_separated(&mut parts, params, ",");
+                parts.push(Print::Char(']'));
+                parts
+            }
+            Self::Map(m) => {
+                let mut parts = vec![Print::Char('{')];
+                push_pairs(&mut parts, m, true);
+                parts.push(Print::Char('}'));
+                parts
+            }
+            Self::Match(scrutinee, arms, default) => {
+                let mut parts = vec![
+                    Print::Str("match "),
+                    Print::Node(scrutinee),
+                    Print::Str(" {"),
+                ];
+                push_pairs(&mut parts, arms, false);
+                if let Some(default) = default {
+                    if !arms.is_empty() {
+                        parts.push(Print::Char(','));
+                    }
+                    parts.extend([Print::Str("_:"), Print::Node(default)]);
+                }
+                parts.push(Print::Char('}'));
+                parts
+            }
+            Self::Stmt(exprs) => {
+                let mut parts = Vec::new();
+                push_separated(&mut parts, exprs, ";");
+                parts
             }
+            Self::Spread(expr) => vec![Print::Str("..."), Print::Node(expr)],
+            Self::None => Vec::new(),
         }
-        s.push_str("]");
-        s
     }
 
-    fn map_expr(&self, m: Vec<(ExprAST, ExprAST)>) -> String {
-        let mut s = String::from("{");
-        for i in 0..m.len() {
-            let (key, value) = m[i].clone();
-            s.push_str(key.expr().as_str());
-            s.push_str(":");
-            s.push_str(value.expr().as_str());
-            if i < m.len() - 1 {
-                s.push_str(",");
+    fn write_literal(&self, val: &Literal, f: &mut impl fmt::Write) -> fmt::Result {
+        match val {
+            Literal::Number(value) => write!(f, "{}", value),
+            Literal::Bool(value) => write!(f, "{}", value),
+            Literal::String(value) => {
+                let quotes = string_quotes(value, '"').unwrap_or("\"");
+                write!(f, "{}{}{}", quotes, value, quotes)
             }
+            Literal::None => f.write_str("null"),
         }
-        s.push_str("}");
-        s
     }
 
-    fn chain_expr(&self, exprs: Vec<ExprAST>) -> String {
-        let mut s = String::new();
-        for i in 0..exprs.len() {
-            s.push_str(exprs[i].expr().as_str());
-            if i < exprs.len() - 1 {
-                s.push_str(";");
+    /// A node-by-node dump of the tree, e.g. `Binary AST: Op: +, Lhs: ...`,
+    /// which the `Display` impl printed before it printed the expression.
+    pub fn dump(&self) -> String {
+        Dump(self).to_string()
+    }
+}
+
+// Counts the bytes written through it, so the printer knows where each node
+// starts and ends.
+struct Layout<'w, W> {
+    out: &'w mut W,
+    len: usize,
+    spans: Option<BTreeMap<usize, Span>>,
+}
+
+// A piece of the text `write_node` has left to print.
+enum Print<'e, 'a> {
+    Node(&'e ExprAST<'a>),
+    // Parenthesized when it binds looser than the `Binding`.
+    Operand(&'e ExprAST<'a>, bool, Binding),
+    Literal(&'e Literal<'a>),
+    Name(&'e str),
+    Str(&'e str),
+    Char(char),
+    // Closes the span of a node that started at the offset.
+    End(&'e ExprAST<'a>, usize),
+}
+
+fn push_separated<'e, 'a>(
+    parts: &mut Vec<Print<'e, 'a>>,
+    exprs: &'e [ExprAST<'a>],
+    separator: &'e str,
+) {
+    for (i, expr) in exprs.iter().enumerate() {
+        if i > 0 {
+            parts.push(Print::Str(separator));
+        }
+        parts.push(Print::Node(expr));
+    }
+}
+
+// A name that is a map key is parenthesized, or it would read back as a
+// string.
+fn push_pairs<'e, 'a>(
+    parts: &mut Vec<Print<'e, 'a>>,
+    pairs: &'e [(ExprAST<'a>, ExprAST<'a>)],
+    map: bool,
+) {
+    for (i, (key, value)) in pairs.iter().enumerate() {
+        if i > 0 {
+            parts.push(Print::Char(','));
+        }
+        match key {
+            ExprAST::Reference(name) if map && is_bare_key(name) => {
+                parts.extend([Print::Char('('), Print::Node(key), Print::Char(')')])
             }
+            _ => parts.push(Print::Node(key)),
         }
-        s
+        parts.extend([Print::Char(':'), Print::Node(value)]);
+    }
+}
+
+impl<W: fmt::Write> fmt::Write for Layout<'_, W> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.len += s.len();
+        self.out.write_str(s)
+    }
+}
+
+impl<'a> fmt::Display for ExprAST<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.write_expr(f)
     }
 }
 
@@ -376,30 +1057,38 @@ impl<'a> ExprAST<'a> {
                 lhs.describe(),
                 rhs.describe(),
             ),
-            Self::Postfix(lhs, op) => DescriptorManager::new().get_postfix_descriptor(op.clone())(
-                lhs.describe(),
-                op.clone(),
+            Self::Postfix(lhs, op) => DescriptorManager::new()
+                .get_postfix_descriptor(op.to_string())(
+                lhs.describe(), op.to_string()
             ),
             Self::List(values) => DescriptorManager::new().get_list_descriptor()(
-                values.into_iter().map(|v| v.describe()).collect(),
+                values.iter().map(|v| v.describe()).collect(),
             ),
             Self::Map(values) => DescriptorManager::new().get_map_descriptor()(
                 values
-                    .into_iter()
+                    .iter()
                     .map(|value| (value.0.describe(), value.1.describe()))
                     .collect(),
             ),
             Self::Function(name, values) => DescriptorManager::new()
                 .get_function_descriptor(name.to_string())(
                 name.to_string(),
-                values.into_iter().map(|v| v.describe()).collect(),
+                values.iter().map(|v| v.describe()).collect(),
             ),
             Self::Reference(name) => DescriptorManager::new()
                 .get_reference_descriptor(name.to_string())(
                 name.to_string()
             ),
+            Self::Match(scrutinee, arms, default) => DescriptorManager::new()
+                .get_match_descriptor()(
+                scrutinee.describe(),
+                arms.iter()
+                    .map(|(pattern, value)| (pattern.describe(), value.describe()))
+                    .collect(),
+                default.as_ref().map(|default| default.describe()),
+            ),
             Self::Stmt(values) => DescriptorManager::new().get_chain_descriptor()(
-                values.into_iter().map(|v| v.describe()).collect(),
+                values.iter().map(|v| v.describe()).collect(),
             ),
             Self::Ternary(condition, lhs, rhs) => {
                 DescriptorManager::new().get_ternary_descriptor()(
@@ -408,60 +1097,457 @@ impl<'a> ExprAST<'a> {
                     rhs.describe(),
                 )
             }
+            Self::Spread(expr) => "...".to_string() + &expr.describe(),
             Self::None => "".to_string(),
         }
     }
 }
 
-pub struct Parser<'a> {
-    tokenizer: Tokenizer<'a>,
+impl<'a> ExprAST<'a> {
+    /// A hash of the structure of the expression. Unlike [`Hash`], which
+    /// may change between Rust releases, it's the same for identical ASTs
+    /// across program runs, platforms and patch versions of this crate, so
+    /// it can key persistent caches. Whitespace isn't part of the AST, so
+    /// `a+1` and `a + 1` agree, and so do numbers that compare equal, like
+    /// `1.0` and `1.00`.
+    pub fn fingerprint(&self) -> u64 {
+        let mut hasher = Fnv::default();
+        let mut stack = vec![self];
+        // Pre-order, with children pushed in reverse so they pop in order.
+        // Every node starts with a tag and lists are prefixed with their
+        // length, which keeps different trees from writing the same bytes.
+        while let Some(expr) = stack.pop() {
+            match expr {
+                ExprAST::Literal(Literal::Number(value)) => {
+                    hasher.tag(0);
+                    hasher.str(&value.normalize().to_string());
+                }
+                ExprAST::Literal(Literal::Bool(value)) => {
+                    hasher.tag(1);
+                    hasher.tag(*value as u8);
+                }
+                ExprAST::Literal(Literal::String(value)) => {
+                    hasher.tag(2);
+                    hasher.str(value);
+                }
+                ExprAST::Literal(Literal::None) => hasher.tag(15),
+                ExprAST::Unary(op, rhs) => {
+                    hasher.tag(3);
+                    hasher.str(op);
+                    stack.push(rhs);
+                }
+                ExprAST::Binary(op, lhs, rhs) => {
+                    hasher.tag(4);
+                    hasher.str(op);
+                    stack.extend([rhs.as_ref(), lhs.as_ref()]);
+                }
+                ExprAST::Postfix(lhs, op) => {
+                    hasher.tag(5);
+                    hasher.str(op);
+                    stack.push(lhs);
+                }
+                ExprAST::Ternary(condition, lhs, rhs) => {
+                    hasher.tag(6);
+                    stack.extend([rhs.as_ref(), lhs.as_ref(), condition.as_ref()]);
+                }
+                ExprAST::Reference(name) => {
+                    hasher.tag(7);
+                    hasher.str(name);
+                }
+                ExprAST::Function(name, params) => {
+                    hasher.tag(8);
+                    hasher.str(name);
+                    hasher.len(params.len());
+                    stack.extend(params.iter().rev());
+                }
+                ExprAST::List(params) => {
+                    hasher.tag(9);
+                    hasher.len(params.len());
+                    stack.extend(params.iter().rev());
+                }
+                ExprAST::Map(m) => {
+                    hasher.tag(10);
+                    hasher.len(m.len());
+                    stack.extend(m.iter().rev().flat_map(|(k, v)| [v, k]));
+                }
+                ExprAST::Match(scrutinee, arms, default) => {
+                    hasher.tag(11);
+                    hasher.len(arms.len());
+                    hasher.tag(default.is_some() as u8);
+                    stack.extend(default.as_deref());
+                    stack.extend(arms.iter().rev().flat_map(|(k, v)| [v, k]));
+                    stack.push(scrutinee);
+                }
+                ExprAST::Stmt(exprs) => {
+                    hasher.tag(12);
+                    hasher.len(exprs.len());
+                    stack.extend(exprs.iter().rev());
+                }
+                ExprAST::Spread(expr) => {
+                    hasher.tag(13);
+                    stack.push(expr);
+                }
+                ExprAST::None => hasher.tag(14),
+            }
+        }
+        hasher.finish()
+    }
 }
 
-impl<'a> Parser<'a> {
-    fn cur_tok(&self) -> Token {
-        self.tokenizer.cur_token.clone()
+// What the parser builds its nodes with: boxed `ExprAST`s, or the nodes of
+// an `ExprArena`.
+pub(crate) trait Builder<'a> {
+    // Cloned for the middle operand of a chained comparison.
+    type Node: Clone;
+    fn literal(&mut self, literal: Literal<'a>) -> Self::Node;
+    fn reference(&mut self, name: &'a str) -> Self::Node;
+    fn reference_name(&self, node: &Self::Node) -> Option<&'a str>;
+    fn unary(&mut self, op: &'a str, rhs: Self::Node) -> Self::Node;
+    fn binary(&mut self, op: &'a str, lhs: Self::Node, rhs: Self::Node) -> Self::Node;
+    fn postfix(&mut self, lhs: Self::Node, op: &'a str) -> Self::Node;
+    fn ternary(&mut self, condition: Self::Node, lhs: Self::Node, rhs: Self::Node) -> Self::Node;
+    // `span` runs from the name to the closing parenthesis.
+    fn function(&mut self, name: &'a str, params: Vec<Self::Node>, span: Span) -> Self::Node;
+    fn list(&mut self, params: Vec<Self::Node>) -> Self::Node;
+    fn map(&mut self, m: Vec<(Self::Node, Self::Node)>) -> Self::Node;
+    fn match_(
+        &mut self,
+        scrutinee: Self::Node,
+        arms: Vec<(Self::Node, Self::Node)>,
+        default: Option<Self::Node>,
+    ) -> Self::Node;
+    fn stmt(&mut self, exprs: Vec<Self::Node>) -> Self::Node;
+    fn spread(&mut self, expr: Self::Node) -> Self::Node;
+    // What input with no statements at all parses to.
+    fn none(&mut self) -> Self::Node;
+    // Where `node` is in the input, for the nodes that start or end with a
+    // token of their own, like `-a`, `[a]` or a literal. The others run from
+    // their first operand to their last. For a parenthesized node, the span
+    // of the parentheses around it.
+    fn located(&mut self, node: Self::Node, _: Span, _parenthesized: bool) -> Self::Node {
+        node
     }
+}
 
-    pub fn new(input: &'a str) -> Result<Self> {
-        let mut tokenizer = Tokenizer::new(input);
-        tokenizer.next()?;
-        Ok(Self {
-            tokenizer: tokenizer,
-        })
+pub(crate) struct TreeBuilder;
+
+impl<'a> Builder<'a> for TreeBuilder {
+    type Node = ExprAST<'a>;
+
+    fn literal(&mut self, literal: Literal<'a>) -> ExprAST<'a> {
+        ExprAST::Literal(literal)
     }
 
-    fn is_eof(&self) -> bool {
-        self.cur_tok().is_eof()
+    fn reference(&mut self, name: &'a str) -> ExprAST<'a> {
+        ExprAST::Reference(name)
     }
 
-    pub fn next(&mut self) -> Result<Token> {
-        self.tokenizer.next()
+    fn reference_name(&self, node: &ExprAST<'a>) -> Option<&'a str> {
+        match node {
+            ExprAST::Reference(name) => Some(name),
+            _ => None,
+        }
     }
 
-    fn expect(&mut self, expected: &str) -> Result<()> {
-        self.tokenizer.expect(expected)
+    fn unary(&mut self, op: &'a str, rhs: ExprAST<'a>) -> ExprAST<'a> {
+        ExprAST::Unary(op, Box::new(rhs))
     }
 
-    fn parse_token(&mut self) -> Result<ExprAST<'a>> {
-        let token = self.tokenizer.cur_token;
-        match token {
-            Token::Number(val, _) => {
-                self.next()?;
-                Ok(ExprAST::Literal(Literal::Number(val)))
-            }
-            Token::Bool(val, _) => {
-                self.next()?;
-                Ok(ExprAST::Literal(Literal::Bool(val)))
-            }
-            Token::String(val, _) => {
-                self.next()?;
-                Ok(ExprAST::Literal(Literal::String(val)))
-            }
-            Token::Reference(val, _) => {
-                self.next()?;
-                Ok(ExprAST::Reference(val))
+    fn binary(&mut self, op: &'a str, lhs: ExprAST<'a>, rhs: ExprAST<'a>) -> ExprAST<'a> {
+        ExprAST::Binary(op, Box::new(lhs), Box::new(rhs))
+    }
+
+    fn postfix(&mut self, lhs: ExprAST<'a>, op: &'a str) -> ExprAST<'a> {
+        ExprAST::Postfix(Box::new(lhs), op)
+    }
+
+    fn ternary(
+        &mut self,
+        condition: ExprAST<'a>,
+        lhs: ExprAST<'a>,
+        rhs: ExprAST<'a>,
+    ) -> ExprAST<'a> {
+        ExprAST::Ternary(Box::new(condition), Box::new(lhs), Box::new(rhs))
+    }
+
+    fn function(&mut self, name: &'a str, params: Vec<ExprAST<'a>>, _: Span) -> ExprAST<'a> {
+        ExprAST::Function(name, params)
+    }
+
+    fn list(&mut self, params: Vec<ExprAST<'a>>) -> ExprAST<'a> {
+        ExprAST::List(params)
+    }
+
+    fn map(&mut self, m: Vec<(ExprAST<'a>, ExprAST<'a>)>) -> ExprAST<'a> {
+        ExprAST::Map(m)
+    }
+
+    fn match_(
+        &mut self,
+        scrutinee: ExprAST<'a>,
+        arms: Vec<(ExprAST<'a>, ExprAST<'a>)>,
+        default: Option<ExprAST<'a>>,
+    ) -> ExprAST<'a> {
+        ExprAST::Match(Box::new(scrutinee), arms, default.map(Box::new))
+    }
+
+    fn stmt(&mut self, exprs: Vec<ExprAST<'a>>) -> ExprAST<'a> {
+        ExprAST::Stmt(exprs)
+    }
+
+    fn spread(&mut self, expr: ExprAST<'a>) -> ExprAST<'a> {
+        ExprAST::Spread(Box::new(expr))
+    }
+
+    fn none(&mut self) -> ExprAST<'a> {
+        ExprAST::None
+    }
+}
+
+// Builds nothing, for finding where statements end. A node only keeps the
+// name of a reference, which the parser checks assignments against.
+struct Skeleton;
+
+impl<'a> Builder<'a> for Skeleton {
+    type Node = Option<&'a str>;
+
+    fn literal(&mut self, _: Literal<'a>) -> Self::Node {
+        None
+    }
+
+    fn reference(&mut self, name: &'a str) -> Self::Node {
+        Some(name)
+    }
+
+    fn reference_name(&self, node: &Self::Node) -> Option<&'a str> {
+        *node
+    }
+
+    fn unary(&mut self, _: &'a str, _: Self::Node) -> Self::Node {
+        None
+    }
+
+    fn binary(&mut self, _: &'a str, _: Self::Node, _: Self::Node) -> Self::Node {
+        None
+    }
+
+    fn postfix(&mut self, _: Self::Node, _: &'a str) -> Self::Node {
+        None
+    }
+
+    fn ternary(&mut self, _: Self::Node, _: Self::Node, _: Self::Node) -> Self::Node {
+        None
+    }
+
+    fn function(&mut self, _: &'a str, _: Vec<Self::Node>, _: Span) -> Self::Node {
+        None
+    }
+
+    fn list(&mut self, _: Vec<Self::Node>) -> Self::Node {
+        None
+    }
+
+    fn map(&mut self, _: Vec<(Self::Node, Self::Node)>) -> Self::Node {
+        None
+    }
+
+    fn match_(
+        &mut self,
+        _: Self::Node,
+        _: Vec<(Self::Node, Self::Node)>,
+        _: Option<Self::Node>,
+    ) -> Self::Node {
+        None
+    }
+
+    fn stmt(&mut self, _: Vec<Self::Node>) -> Self::Node {
+        None
+    }
+
+    fn spread(&mut self, _: Self::Node) -> Self::Node {
+        None
+    }
+
+    fn none(&mut self) -> Self::Node {
+        None
+    }
+}
+
+/// Where a top-level statement is in the input, see
+/// [`crate::statement_spans`].
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct StatementSpan {
+    /// From the start of the first token of the statement to the end of its
+    /// last one.
+    pub span: Span,
+    /// Whether the input ends before the statement does, e.g. inside a
+    /// parenthesis or a string, or right after an operator. The span then
+    /// runs to the end of the input. Only the last statement can be.
+    pub unterminated: bool,
+}
+
+// How deep sub-expressions may nest, e.g. as parentheses, lists, calls or
+// prefix operators, before parsing fails with `Error::NestingTooDeep`
+// rather than overflowing the stack.
+pub(crate) const MAX_NESTING: usize = 128;
+
+// How far the operand of `not` reaches: like the right operand of `&&`, so
+// `not a in xs` is `not (a in xs)` and `not a && b` is `(not a) && b`.
+const NOT_BP: i32 = 51;
+
+pub struct Parser<'a, B = TreeBuilder> {
+    tokenizer: Tokenizer<'a>,
+    builder: B,
+    options: ParseOptions,
+    depth: usize,
+    // How many parentheses, brackets and braces are open.
+    open: usize,
+}
+
+impl<'a> Parser<'a> {
+    pub fn new(input: &'a str) -> Result<Self> {
+        Self::with_options(input, ParseOptions::default())
+    }
+
+    pub fn with_options(input: &'a str, options: ParseOptions) -> Result<Self> {
+        Self::with_builder(input, TreeBuilder, options)
+    }
+
+    /// The span of each top-level statement, found with the same grammar as
+    /// [`crate::parse_expression`] but without building any nodes, so that
+    /// an editor can check again only the statement that changed.
+    pub fn statement_spans(input: &'a str) -> Result<Vec<StatementSpan>> {
+        Self::statement_spans_with_options(input, ParseOptions::default())
+    }
+
+    /// [`Parser::statement_spans`], with [`ParseOptions`]. Running out of
+    /// input ends the last statement as unterminated, any other syntax error
+    /// is returned.
+    pub fn statement_spans_with_options(
+        input: &'a str,
+        options: ParseOptions,
+    ) -> Result<Vec<StatementSpan>> {
+        // The rest of the input from `start`, leading whitespace aside.
+        let rest = |start: usize| {
+            let start = input.len() - input[start..].trim_start().len();
+            StatementSpan {
+                span: Span(start, input.len()),
+                unterminated: true,
+            }
+        };
+        let mut ans = Vec::new();
+        let mut parser = match Parser::with_builder(input, Skeleton, options) {
+            Ok(parser) => parser,
+            Err(Error::UnterminatedString(_)) => return Ok(vec![rest(0)]),
+            Err(err) => return Err(err),
+        };
+        while let Some(Span(start, _)) = parser.cur_tok().span() {
+            match parser.parse_expression() {
+                Ok(_) => {}
+                Err(Error::UnterminatedString(_)) => {
+                    ans.push(rest(start));
+                    break;
+                }
+                Err(_) if parser.is_eof() => {
+                    ans.push(rest(start));
+                    break;
+                }
+                Err(err) => return Err(err),
+            }
+            let end = parser
+                .tokenizer
+                .prev_token
+                .span()
+                .map_or(start, |span| span.1);
+            ans.push(StatementSpan {
+                span: Span(start, end),
+                unterminated: false,
+            });
+            if let Token::Semicolon(_, Span(_, after)) = parser.cur_tok() {
+                match parser.next() {
+                    Ok(_) => {}
+                    Err(Error::UnterminatedString(_)) => {
+                        ans.push(rest(after));
+                        break;
+                    }
+                    Err(err) => return Err(err),
+                }
+            }
+        }
+        Ok(ans)
+    }
+}
+
+impl<'a, B: Builder<'a>> Parser<'a, B> {
+    fn cur_tok(&self) -> Token<'_> {
+        self.tokenizer.cur_token
+    }
+
+    pub(crate) fn with_builder(input: &'a str, builder: B, options: ParseOptions) -> Result<Self> {
+        if input.len() > options.max_input_len {
+            return Err(Error::InputTooLarge {
+                len: input.len(),
+                limit: options.max_input_len,
+            });
+        }
+        let mut tokenizer = Tokenizer::new(input);
+        tokenizer.set_newlines(options.newline_statements);
+        tokenizer.set_limits(options.max_token_len, options.max_string_len);
+        tokenizer.next()?;
+        Ok(Self {
+            tokenizer,
+            builder,
+            options,
+            depth: 0,
+            open: 0,
+        })
+    }
+
+    pub(crate) fn into_builder(self) -> B {
+        self.builder
+    }
+
+    fn is_eof(&self) -> bool {
+        self.cur_tok().is_eof()
+    }
+
+    pub fn next(&mut self) -> Result<Token<'_>> {
+        self.tokenizer.next()
+    }
+
+    fn expect(&mut self, expected: &str) -> Result<()> {
+        self.tokenizer.expect(expected)
+    }
+
+    fn parse_token(&mut self) -> Result<B::Node> {
+        let token = self.tokenizer.cur_token;
+        match token {
+            Token::Number(val, _) => {
+                self.next()?;
+                Ok(self.builder.literal(Literal::Number(val)))
+            }
+            Token::Bool(val, _) => {
+                self.next()?;
+                Ok(self.builder.literal(Literal::Bool(val)))
+            }
+            Token::Null(..) => {
+                self.next()?;
+                Ok(self.builder.literal(Literal::None))
             }
-            Token::Function(name, _) => self.parse_function(name),
+            Token::String(val, _) => {
+                self.next()?;
+                Ok(self.builder.literal(Literal::String(val)))
+            }
+            Token::Reference("match", _) if self.tokenizer.peek()?.starts_expression() => {
+                self.parse_match()
+            }
+            Token::Function("match", _) => self.parse_match(),
+            Token::Reference(val, _) => {
+                self.next()?;
+                Ok(self.builder.reference(val))
+            }
+            Token::Function(name, Span(start, _)) => self.parse_function(name, start),
+            Token::Operator("...", _) => Err(Error::UnexpectedToken()),
             Token::Operator(op, _) => self.parse_unary(op),
             Token::Delim(ty, _) => self.parse_delim(ty),
             Token::EOF => Err(Error::UnexpectedEOF(0)),
@@ -469,7 +1555,7 @@ impl<'a> Parser<'a> {
         }
     }
 
-    pub fn parse_stmt(&mut self) -> Result<ExprAST<'a>> {
+    pub fn parse_stmt(&mut self) -> Result<B::Node> {
         let mut ans = Vec::new();
         loop {
             if self.is_eof() {
@@ -480,31 +1566,111 @@ impl<'a> Parser<'a> {
                 self.next()?;
             }
         }
-        if ans.len() == 1 {
-            return Ok(ans[0].clone());
+        match ans.len() {
+            0 => Ok(self.builder.none()),
+            1 => Ok(ans.pop().unwrap()),
+            _ => Ok(self.builder.stmt(ans)),
+        }
+    }
+
+    pub fn parse_expression(&mut self) -> Result<B::Node> {
+        self.nested(|parser| {
+            let lhs = parser.parse_primary()?;
+            parser.parse_op(0, lhs)
+        })
+    }
+
+    // Every recursion of the parser passes through here.
+    fn nested(&mut self, parse: impl FnOnce(&mut Self) -> Result<B::Node>) -> Result<B::Node> {
+        if self.depth == MAX_NESTING {
+            return Err(Error::NestingTooDeep(self.start()));
         }
-        Ok(ExprAST::Stmt(ans))
+        self.depth += 1;
+        let ans = parse(self);
+        self.depth -= 1;
+        ans
+    }
+
+    // Parses a sub-expression inside parentheses, brackets or braces, where
+    // line breaks never end a statement.
+    fn delimited(&mut self, parse: impl FnOnce(&mut Self) -> Result<B::Node>) -> Result<B::Node> {
+        self.open += 1;
+        let ans = parse(self);
+        self.open -= 1;
+        ans
     }
 
-    pub fn parse_expression(&mut self) -> Result<ExprAST<'a>> {
-        let lhs = self.parse_primary()?;
-        self.parse_op(0, lhs)
+    // Whether a line break before the current token ends the statement.
+    fn line_ends(&self) -> bool {
+        self.options.newline_statements && self.open == 0 && self.tokenizer.newline_before()
     }
 
-    fn parse_primary(&mut self) -> Result<ExprAST<'a>> {
+    fn parse_primary(&mut self) -> Result<B::Node> {
+        let start = self.start();
+        let parenthesized = self.cur_tok().is_open_paren();
         let lhs = self.parse_token()?;
-        if self.tokenizer.cur_token.is_postfix_op_token() {
-            let op = self.tokenizer.cur_token.string();
-            self.next()?;
-            return Ok(ExprAST::Postfix(Box::new(lhs), op.to_string()));
+        let end = self.end(start);
+        let lhs = self.builder.located(lhs, Span(start, end), parenthesized);
+        if let Token::Operator(op, _) = self.tokenizer.cur_token {
+            if self.tokenizer.cur_token.is_postfix_op_token() && !self.line_ends() {
+                self.next()?;
+                let node = self.builder.postfix(lhs, op);
+                return Ok(self.locate(start, node));
+            }
         }
         Ok(lhs)
     }
 
-    fn parse_op(&mut self, exec_prec: i32, mut lhs: ExprAST<'a>) -> Result<ExprAST<'a>> {
+    // Where the current token starts.
+    fn start(&self) -> usize {
+        self.cur_tok().span().map_or(0, |span| span.0)
+    }
+
+    // Where a close delimiter was expected: the token found instead, or the
+    // end of the input.
+    fn close_start(&self) -> usize {
+        match self.cur_tok().span() {
+            Some(span) => span.0,
+            None => self.end(0),
+        }
+    }
+
+    // Where the last token ends.
+    fn end(&self, start: usize) -> usize {
+        self.tokenizer
+            .prev_token
+            .span()
+            .map_or(start, |span| span.1)
+    }
+
+    // Reports `node` as running from `start` to the end of the last token.
+    fn locate(&mut self, start: usize, node: B::Node) -> B::Node {
+        let end = self.end(start);
+        self.builder.located(node, Span(start, end), false)
+    }
+
+    fn parse_op(&mut self, exec_prec: i32, mut lhs: B::Node) -> Result<B::Node> {
         let mut is_not = false;
+        // The last comparison and its right operand, while it's the start or
+        // the middle of a chain.
+        let mut chain: Option<(&str, B::Node)> = None;
         loop {
-            if !self.tokenizer.cur_token.is_op_token() {
+            if !self.tokenizer.cur_token.is_op_token() || self.line_ends() {
+                // A word on the same line right after a whole expression is
+                // a misspelled or missing operator. On the next line it
+                // starts another statement. Deeper calls stop at it, since
+                // `and` ends the lower bound of a `between`.
+                if let Token::Reference(word, span) | Token::Function(word, span) =
+                    self.tokenizer.cur_token
+                {
+                    if exec_prec == 0 && !self.tokenizer.newline_before() {
+                        // A broken token after it, like the rest of `'it\'s'`,
+                        // is the better error.
+                        self.tokenizer.peek()?;
+                        let known = InfixOpManager::new().word_ops();
+                        return Err(Error::UnknownWordOp(span.0, word.into(), known));
+                    }
+                }
                 return Ok(lhs);
             }
             if self.tokenizer.cur_token.is_not_token() {
@@ -520,39 +1686,81 @@ impl<'a> Parser<'a> {
                 let a = self.parse_expression()?;
                 self.expect(":")?;
                 let b = self.parse_expression()?;
-                return Ok(ExprAST::Ternary(Box::new(lhs), Box::new(a), Box::new(b)));
+                return Ok(self.builder.ternary(lhs, a, b));
+            }
+            let (op, start): (&str, usize) = match self.tokenizer.cur_token {
+                Token::Operator(op, span) => (op, span.0),
+                _ => ("", 0),
+            };
+            if keyword::is_disabled_op(op) {
+                return Err(disabled_op(op));
             }
-            let (l_bp, r_bp) = self.get_token_precidence();
+            let Some((l_bp, r_bp)) = self.get_token_precidence() else {
+                // `:` ends the branch of a ternary.
+                if op == ":" {
+                    return Ok(lhs);
+                }
+                return Err(Error::NotSupportedOp(start, op.into()));
+            };
             if l_bp < exec_prec {
                 return Ok(lhs);
             }
-            let op: &str = match self.tokenizer.cur_token {
-                Token::Operator(op, _) => op,
-                _ => "",
-            };
+            // Only references without `?.` can be assigned to; `exec` still
+            // checks, for trees that weren't built by the parser.
+            let assignable = self
+                .builder
+                .reference_name(&lhs)
+                .is_some_and(|name| !name.contains("?."));
+            if !assignable && InfixOpManager::new().is_setter(op) {
+                return Err(Error::InvalidAssignTarget(start));
+            }
             self.next()?;
-            let mut rhs = self.parse_primary()?;
-
-            let (cur_l_bp, _) = self.get_token_precidence();
-            if self.tokenizer.cur_token.is_binop_token() && r_bp < cur_l_bp {
-                rhs = self.parse_op(r_bp, rhs)?;
+            let mut rhs = self.parse_operand(r_bp)?;
+            // `and` isn't an operator, so the lower bound ends before it.
+            if op == "between" && matches!(self.cur_tok(), Token::Reference("and", _)) {
+                self.next()?;
+                let upper = self.parse_operand(r_bp)?;
+                rhs = self.builder.list(vec![rhs, upper]);
             }
-            lhs = ExprAST::Binary(op, Box::new(lhs), Box::new(rhs));
+            let middle = match self.cur_tok() {
+                Token::Operator(next, _) if !is_not && chains(op, next) => Some(rhs.clone()),
+                _ => None,
+            };
+            lhs = match chain.take() {
+                Some((prev, last)) if chains(prev, op) => {
+                    let next = self.builder.binary(op, last, rhs);
+                    self.builder.binary("&&", lhs, next)
+                }
+                _ => self.builder.binary(op, lhs, rhs),
+            };
+            chain = middle.map(|middle| (op, middle));
             if is_not {
-                lhs = ExprAST::Unary("not", Box::new(lhs));
+                lhs = self.builder.unary("not", lhs);
                 is_not = false;
             }
         }
     }
 
-    fn get_token_precidence(&self) -> (i32, i32) {
+    // The right operand of an operator, with the operators after it that
+    // bind tighter than `r_bp`.
+    fn parse_operand(&mut self, r_bp: i32) -> Result<B::Node> {
+        self.nested(|parser| {
+            let rhs = parser.parse_primary()?;
+            match parser.get_token_precidence() {
+                Some((cur_l_bp, _)) if r_bp < cur_l_bp => parser.parse_op(r_bp, rhs),
+                _ => Ok(rhs),
+            }
+        })
+    }
+
+    fn get_token_precidence(&self) -> Option<(i32, i32)> {
         match &self.cur_tok() {
             Token::Operator(op, _) => InfixOpManager::new().get_precidence(op),
-            _ => (-1, -1),
+            _ => None,
         }
     }
 
-    fn parse_delim(&mut self, ty: DelimTokenType) -> Result<ExprAST<'a>> {
+    fn parse_delim(&mut self, ty: DelimTokenType) -> Result<B::Node> {
         use DelimTokenType::*;
         match ty {
             OpenParen => self.parse_open_paren(),
@@ -562,86 +1770,154 @@ impl<'a> Parser<'a> {
         }
     }
 
-    fn parse_open_paren(&mut self) -> Result<ExprAST<'a>> {
+    fn parse_open_paren(&mut self) -> Result<B::Node> {
         self.next()?;
-        let expr = self.parse_expression()?;
+        let expr = self.delimited(Self::parse_expression)?;
         if !self.tokenizer.cur_token.is_close_paren() {
-            return Err(Error::NoCloseDelim);
+            return Err(Error::NoCloseDelim(self.close_start()));
         }
         self.next()?;
         Ok(expr)
     }
 
-    fn parse_open_bracket(&mut self) -> Result<ExprAST<'a>> {
+    fn parse_open_bracket(&mut self) -> Result<B::Node> {
         self.next()?;
         let mut exprs = Vec::new();
         loop {
             if self.is_eof() || self.cur_tok().is_close_bracket() {
                 break;
             }
-            exprs.push(self.parse_expression()?);
+            exprs.push(self.delimited(Self::parse_param)?);
             if !self.cur_tok().is_close_bracket() {
                 self.expect(",")?;
             }
         }
         self.expect("]")?;
-        Ok(ExprAST::List(exprs))
+        Ok(self.builder.list(exprs))
+    }
+
+    // An element of a list literal or a call's params, which may be spread.
+    fn parse_param(&mut self) -> Result<B::Node> {
+        if !self.cur_tok().is_spread_token() {
+            return self.parse_expression();
+        }
+        let start = self.start();
+        self.next()?;
+        let expr = self.parse_expression()?;
+        let node = self.builder.spread(expr);
+        Ok(self.locate(start, node))
     }
 
-    fn parse_open_brace(&mut self) -> Result<ExprAST<'a>> {
+    fn parse_open_brace(&mut self) -> Result<B::Node> {
         self.next()?;
         let mut m = Vec::new();
         loop {
             if self.is_eof() || self.cur_tok().is_close_brace() {
                 break;
             }
-            let k = self.parse_expression()?;
+            let k = self.parse_map_key()?;
             self.expect(":")?;
-            let v = self.parse_expression()?;
+            let v = self.delimited(Self::parse_expression)?;
             m.push((k, v));
             if !self.cur_tok().is_close_brace() {
                 self.expect(",")?;
             }
         }
         self.expect("}")?;
-        Ok(ExprAST::Map(m))
+        Ok(self.builder.map(m))
+    }
+
+    // A lone name before the colon, like `status` in `{status: 'ok'}`, is
+    // the string "status", as in a JavaScript object literal. `(status)`
+    // still reads the variable. So is `null`, which was a name before it
+    // was a literal.
+    fn parse_map_key(&mut self) -> Result<B::Node> {
+        if let Token::Reference(name, _) | Token::Null(name, _) = self.tokenizer.cur_token {
+            if is_bare_key(name) && self.tokenizer.peek()?.is_colon() {
+                let start = self.start();
+                self.next()?;
+                let node = self.builder.literal(Literal::String(name));
+                return Ok(self.locate(start, node));
+            }
+        }
+        self.delimited(Self::parse_expression)
+    }
+
+    // `match` is only a keyword when an expression follows it, so it can
+    // still be used as a variable name. Followed by `(` it always is one, so
+    // `match (a) {...}` isn't read as a call.
+    fn parse_match(&mut self) -> Result<B::Node> {
+        self.next()?;
+        let scrutinee = self.parse_expression()?;
+        if !self.cur_tok().is_open_brace() {
+            return Err(Error::NoOpenDelim);
+        }
+        self.next()?;
+        let mut arms = Vec::new();
+        let mut default = Option::None;
+        loop {
+            if self.is_eof() || self.cur_tok().is_close_brace() {
+                break;
+            }
+            if default.is_some() {
+                return Err(Error::UnexpectedToken());
+            }
+            if let Token::Reference("_", _) = self.cur_tok() {
+                self.next()?;
+                self.expect(":")?;
+                default = Some(self.delimited(Self::parse_expression)?);
+            } else {
+                let pattern = self.delimited(Self::parse_expression)?;
+                self.expect(":")?;
+                arms.push((pattern, self.delimited(Self::parse_expression)?));
+            }
+            if !self.cur_tok().is_close_brace() {
+                self.expect(",")?;
+            }
+        }
+        self.expect("}")?;
+        Ok(self.builder.match_(scrutinee, arms, default))
     }
 
-    fn parse_unary(&mut self, op: &'a str) -> Result<ExprAST<'a>> {
+    // `not` reads as a word of a rule and negates the comparison after it,
+    // while every other prefix operator takes just the primary after it.
+    fn parse_unary(&mut self, op: &'a str) -> Result<B::Node> {
         self.next()?;
-        Ok(ExprAST::Unary(op, Box::new(self.parse_primary()?)))
+        let rhs = match op {
+            "not" => self.parse_operand(NOT_BP)?,
+            _ => self.nested(Self::parse_primary)?,
+        };
+        Ok(self.builder.unary(op, rhs))
     }
 
-    fn parse_function(&mut self, name: &'a str) -> Result<ExprAST<'a>> {
+    fn parse_function(&mut self, name: &'a str, start: usize) -> Result<B::Node> {
         self.next()?;
         self.expect("(")?;
         let mut ans = Vec::new();
-        if self.cur_tok().is_close_paren() {
-            self.next()?;
-            return Ok(ExprAST::Function(name, ans));
-        }
-        let has_right_paren;
         loop {
-            ans.push(self.parse_expression()?);
-            if self.cur_tok().is_close_paren() {
-                has_right_paren = true;
-                self.next()?;
+            if self.is_eof() || self.cur_tok().is_close_paren() {
                 break;
             }
-            self.expect(",")?;
+            ans.push(self.delimited(Self::parse_param)?);
+            if !self.cur_tok().is_close_paren() {
+                self.expect(",")?;
+            }
         }
-        if !has_right_paren {
-            return Err(Error::NoCloseDelim);
+        if !self.cur_tok().is_close_paren() {
+            return Err(Error::NoCloseDelim(self.close_start()));
         }
-        Ok(ExprAST::Function(name, ans))
+        let end = self.tokenizer.cur_token.span().map_or(start, |span| span.1);
+        self.next()?;
+        Ok(self.builder.function(name, ans, Span(start, end)))
     }
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "default"))]
 mod tests {
-    use crate::init::init;
-    use crate::parser::{ExprAST, Literal, Parser};
-    use crate::value::Value;
+    use crate::error::Error;
+    use crate::options::ParseOptions;
+    use crate::parser::{ExprAST, Literal, Parser, MAX_NESTING};
+    use crate::value::{Map, Value};
     use rstest::rstest;
     use rust_decimal::prelude::*;
 
@@ -649,11 +1925,12 @@ mod tests {
     #[case("5", ExprAST::Literal(Literal::Number(Decimal::from_str("5").unwrap_or_default())))]
     #[case("true", ExprAST::Literal(Literal::Bool(true)))]
     #[case("\n false", ExprAST::Literal(Literal::Bool(false)))]
+    #[case("null", ExprAST::Literal(Literal::None))]
+    #[case(" None", ExprAST::Literal(Literal::None))]
     #[case("\n haha", ExprAST::Reference("haha"))]
     #[case("'haha  '", ExprAST::Literal(Literal::String("haha  ")))]
     #[case("!a", ExprAST::Unary("!", Box::new(ExprAST::Reference("a"))))]
     fn test_parse_expression_simple(#[case] input: &str, #[case] output: ExprAST) {
-        init();
         let parser = Parser::new(input);
         assert!(parser.is_ok());
         let expr_ast = parser.unwrap().parse_expression();
@@ -688,19 +1965,18 @@ mod tests {
             Box::new(ExprAST::Literal(Literal::String("hahha"))),
         )
     )]
-    #[case("2=3=4", ExprAST::Binary(
+    #[case("a=b=4", ExprAST::Binary(
         "=",
-        Box::new(ExprAST::Literal(Literal::Number(Decimal::from_i32(2).unwrap_or_default()))),
+        Box::new(ExprAST::Reference("a")),
         Box::new(
             ExprAST::Binary(
                 "=",
-                Box::new(ExprAST::Literal(Literal::Number(Decimal::from_i32(3).unwrap_or_default()))),
+                Box::new(ExprAST::Reference("b")),
                 Box::new(ExprAST::Literal(Literal::Number(Decimal::from_i32(4).unwrap_or_default()))),
             )
         ),
     ))]
     fn test_parse_expression_binary(#[case] input: &str, #[case] output: ExprAST) {
-        init();
         let parser = Parser::new(input);
         assert!(parser.is_ok());
         let expr_ast = parser.unwrap().parse_expression();
@@ -749,7 +2025,6 @@ mod tests {
         ]
     ))]
     fn test_parse_expression_list(#[case] input: &str, #[case] output: ExprAST) {
-        init();
         let parser = Parser::new(input);
         assert!(parser.is_ok());
         let expr_ast = parser.unwrap().parse_expression();
@@ -765,7 +2040,6 @@ mod tests {
         )
     )]
     fn test_parse_expression_ternary(#[case] input: &str, #[case] output: ExprAST) {
-        init();
         let parser = Parser::new(input);
         assert!(parser.is_ok());
         let expr_ast = parser.unwrap().parse_expression();
@@ -788,22 +2062,374 @@ mod tests {
     #[case("true ?")]
     #[case("true ? haha :")]
     #[case("2+ ")]
+    #[case("match a")]
+    #[case("match a 1")]
+    #[case("match a {1 2}")]
+    #[case("match a {1: 2")]
+    #[case("match a {_: 1, 2: 3}")]
+    #[case("match(a)")]
+    #[case("match (a) + 1")]
+    #[case("...a")]
+    #[case("[,]")]
+    #[case("3 = 5")]
+    #[case("f(x) += 1")]
+    #[case("a + b = 1")]
+    #[case("a = 3 = 4")]
+    #[case("'a' = 1")]
+    #[case("[a] = [1]")]
+    #[case("-a *= 2")]
+    #[case("[1,,2]")]
+    #[case("[1,2,,]")]
+    #[case("[1,")]
+    #[case("f(,)")]
+    #[case("f(1,,)")]
+    #[case("f(1,")]
+    #[case("{,}")]
+    #[case("{'a': 1,,}")]
+    #[case("1 + ...a")]
+    #[case("[...]")]
+    #[case("{...a}")]
     fn test_parse_expression_error(#[case] input: &str) {
-        init();
         let parser = Parser::new(input);
         assert!(parser.is_ok());
         let expr_ast = parser.unwrap().parse_expression();
         assert!(expr_ast.is_err());
     }
 
+    #[rstest]
+    #[case("3 = 5", 2)]
+    #[case("f(x) += 1", 5)]
+    #[case("a = 1; b + 1 -= 2", 13)]
+    #[case("null = 1", 5)]
+    fn test_parse_invalid_assign_target(#[case] input: &str, #[case] position: usize) {
+        let err = Parser::new(input).unwrap().parse_stmt().unwrap_err();
+        assert!(matches!(err, Error::InvalidAssignTarget(_)), "{:?}", err);
+        assert_eq!(err.position(), Some(position));
+    }
+
+    // `@@` is only registered as a prefix operator, so it can't sit between
+    // two operands.
+    #[rstest]
+    #[case("a @@ b", 2, "@@")]
+    #[case("1 + 2 @@ 3", 6, "@@")]
+    #[case("(a @@ b)", 3, "@@")]
+    #[case("[1, x = 2 @@ 3]", 10, "@@")]
+    #[case("a ! b", 2, "!")]
+    #[case("a ? b ~ 1 : c", 6, "~")]
+    fn test_parse_unsupported_op(#[case] input: &str, #[case] position: usize, #[case] op: &str) {
+        crate::register_prefix_op("@@", Arc::new(Ok));
+        let err = Parser::new(input).unwrap().parse_stmt().unwrap_err();
+        assert!(
+            matches!(&err, Error::NotSupportedOp(_, o) if o == op),
+            "{:?}",
+            err
+        );
+        assert_eq!(err.position(), Some(position));
+        assert_eq!(
+            err.to_string(),
+            format!("not supported op: {}, {}", position, op)
+        );
+    }
+
+    #[rstest]
+    #[case("name beginsWith 'a'", 5, "beginsWith")]
+    #[case("price qty", 6, "qty")]
+    #[case("price * 2 qty", 10, "qty")]
+    #[case("a foo(b)", 2, "foo")]
+    #[case("[1, a foo b]", 6, "foo")]
+    #[case("x between 1 and 2 andd 3", 18, "andd")]
+    #[case("x = 1 y = 2", 6, "y")]
+    fn test_parse_unknown_word_op(
+        #[case] input: &str,
+        #[case] position: usize,
+        #[case] word: &str,
+    ) {
+        let err = Parser::new(input).unwrap().parse_stmt().unwrap_err();
+        let Error::UnknownWordOp(_, found, known) = &err else {
+            panic!("{:?}", err)
+        };
+        assert_eq!(found, word);
+        for op in ["in", "beginWith", "endWith", "between"] {
+            assert!(known.iter().any(|known| known == op), "{:?}", known);
+        }
+        assert_eq!(err.position(), Some(position));
+        let message = format!(
+            "'{}' is not a known operator (at {}); known word operators: ",
+            word, position
+        );
+        assert!(err.to_string().starts_with(&message), "{}", err);
+    }
+
+    #[rstest]
+    #[case(
+        "'ab' beginWith 'a' && 'ab' endWith 'b'",
+        "\"ab\" beginWith \"a\" && \"ab\" endWith \"b\""
+    )]
+    #[case("a in [1]\nand", "a in [1];and")]
+    #[case("x between 1 and 2", "x between [1,2]")]
+    #[case("a not in b", "not (a in b)")]
+    #[case("a = 1\nb = 2", "a = 1;b = 2")]
+    #[case("a; b", "a;b")]
+    fn test_parse_word_ops(#[case] input: &str, #[case] output: &str) {
+        let ast = Parser::new(input).unwrap().parse_stmt().unwrap();
+        assert_eq!(ast.expr(), output);
+    }
+
+    #[rstest]
+    #[case("@@ b")]
+    #[case("a; @@ b")]
+    #[case("a + @@ b")]
+    #[case("a ? @@ b : c")]
+    fn test_parse_prefix_op(#[case] input: &str) {
+        crate::register_prefix_op("@@", Arc::new(Ok));
+        assert!(Parser::new(input).unwrap().parse_stmt().is_ok());
+    }
+
+    // The tree with every operator application parenthesized.
+    fn grouping(ast: &ExprAST) -> String {
+        match ast {
+            ExprAST::Binary(op, lhs, rhs) => {
+                format!("({} {} {})", grouping(lhs), op, grouping(rhs))
+            }
+            ExprAST::Unary(op, rhs) => format!("({} {})", op, grouping(rhs)),
+            ExprAST::Postfix(lhs, op) => format!("({} {})", grouping(lhs), op),
+            ExprAST::Ternary(condition, lhs, rhs) => format!(
+                "({} ? {} : {})",
+                grouping(condition),
+                grouping(lhs),
+                grouping(rhs)
+            ),
+            ExprAST::List(exprs) => {
+                let exprs: Vec<String> = exprs.iter().map(grouping).collect();
+                format!("[{}]", exprs.join(", "))
+            }
+            other => other.expr(),
+        }
+    }
+
+    #[rstest]
+    #[case("a == b && c == d", "((a == b) && (c == d))")]
+    #[case("a || b && c", "(a || (b && c))")]
+    #[case("a && b || c && d", "((a && b) || (c && d))")]
+    #[case("a < b == c > d", "((a < b) == (c > d))")]
+    #[case("a == b in c", "(a == (b in c))")]
+    #[case("a + b * c - d", "((a + (b * c)) - d)")]
+    #[case("a - b - c", "((a - b) - c)")]
+    #[case("a / b * c % d", "(((a / b) * c) % d)")]
+    #[case("a << b + c", "(a << (b + c))")]
+    #[case("a + b >> c", "((a + b) >> c)")]
+    #[case("a & b == c", "(a & (b == c))")]
+    #[case("a & b && c", "((a & b) && c)")]
+    #[case("a | b ^ c & d", "(a | (b ^ (c & d)))")]
+    #[case("a | b < c", "(a | (b < c))")]
+    #[case("a + 1 in list", "((a + 1) in list)")]
+    #[case("a in list && b", "((a in list) && b)")]
+    #[case("s + t beginWith u", "((s + t) beginWith u)")]
+    #[case(
+        "x between a + 1 and b * 2 || c",
+        "((x between [(a + 1), (b * 2)]) || c)"
+    )]
+    #[case("a ?? b || c", "(a ?? (b || c))")]
+    #[case("a = b = c + 1", "(a = (b = (c + 1)))")]
+    #[case("a += b && c", "(a += (b && c))")]
+    #[case("a = b ?? c", "(a = (b ?? c))")]
+    #[case("!a && -b < c", "((! a) && ((- b) < c))")]
+    #[case("a == b ? c + d : e", "((a == b) ? (c + d) : e)")]
+    #[case("a not in b || c", "((not (a in b)) || c)")]
+    fn test_parse_precedence(#[case] input: &str, #[case] output: &str) {
+        let ast = Parser::new(input).unwrap().parse_stmt().unwrap();
+        assert_eq!(grouping(&ast), output);
+    }
+
+    #[rstest]
+    #[case("a = 3\na + 1", "a = 3;a + 1", "a = 3;a + 1")]
+    #[case("a = 3\r\n\r\na + 1\n", "a = 3;a + 1", "a = 3;a + 1")]
+    #[case("1 +\n  2", "1 + 2", "1 + 2")]
+    #[case("a ?\n b :\n c", "a ? b : c", "a ? b : c")]
+    #[case("a\n-b", "a;- b", "a - b")]
+    // `a ++ b` without the line break, and `b` isn't an operator.
+    #[case("a\n++b", "a;++ b", "")]
+    #[case("f\n(1)", "f;1", "f(1)")]
+    #[case("a++\nb", "a ++;b", "a ++;b")]
+    #[case("(1\n+ 2)\n* 3", "1 + 2;* 3", "(1 + 2) * 3")]
+    #[case("f(1,\n2\n- 3)\n[4]", "f(1,2 - 3);[4]", "f(1,2 - 3);[4]")]
+    #[case("{'a':\n 1\n}\nb", "{\"a\":1};b", "{\"a\":1};b")]
+    #[case(
+        "match a {\n 1: 'x'\n , _: 'y'\n}\nb",
+        "match a {1:\"x\",_:\"y\"};b",
+        "match a {1:\"x\",_:\"y\"};b"
+    )]
+    #[case(
+        "x between 1\n and 2\n+ 1",
+        "x between [1,2];+ 1",
+        "x between [1,2 + 1]"
+    )]
+    #[case("a = 1;\nb", "a = 1;b", "a = 1;b")]
+    fn test_parse_newline_statements(
+        #[case] input: &str,
+        #[case] lines: &str,
+        #[case] default: &str,
+    ) {
+        let options = ParseOptions {
+            newline_statements: true,
+            ..Default::default()
+        };
+        let ast = Parser::with_options(input, options)
+            .unwrap()
+            .parse_stmt()
+            .unwrap();
+        assert_eq!(ast.expr(), lines);
+        match Parser::new(input).unwrap().parse_stmt() {
+            Ok(ast) => assert_eq!(ast.expr(), default),
+            Err(err) => assert!(default.is_empty(), "{}", err),
+        }
+    }
+
+    #[test]
+    fn test_parse_newline_statements_chain() {
+        let options = ParseOptions {
+            newline_statements: true,
+            ..Default::default()
+        };
+        let ast = Parser::with_options("a = 3\na + 1", options)
+            .unwrap()
+            .parse_stmt()
+            .unwrap();
+        assert!(matches!(&ast, ExprAST::Stmt(exprs) if exprs.len() == 2));
+        let ast = Parser::with_options("a = 3 +\n  a", options)
+            .unwrap()
+            .parse_stmt()
+            .unwrap();
+        assert!(matches!(ast, ExprAST::Binary("=", _, _)));
+    }
+
+    #[rstest]
+    #[case("(", "1", ")")]
+    #[case("[", "1", "]")]
+    #[case("f(", "1", ")")]
+    #[case("{", "1", ": 1}")]
+    #[case("!", "true", "")]
+    #[case("a = ", "1", "")]
+    #[case("a ? ", "1", " : 2")]
+    fn test_parse_nesting_limit(#[case] open: &str, #[case] inner: &str, #[case] close: &str) {
+        let nest = |n: usize| open.repeat(n) + inner + &close.repeat(n);
+        assert!(Parser::new(&nest(MAX_NESTING - 1))
+            .unwrap()
+            .parse_stmt()
+            .is_ok());
+        let err = Parser::new(&nest(MAX_NESTING))
+            .unwrap()
+            .parse_stmt()
+            .unwrap_err();
+        assert!(matches!(err, Error::NestingTooDeep(_)), "{:?}", err);
+        assert_eq!(err.position(), Some(open.len() * MAX_NESTING));
+        // Far deeper input fails the same way rather than overflowing.
+        assert!(Parser::new(&nest(100_000)).unwrap().parse_stmt().is_err());
+    }
+
+    #[rstest]
+    #[case("", "x", "", "token", 8)]
+    #[case("", "9", "", "token", 8)]
+    #[case("`", "x", "`", "token", 8)]
+    #[case("'", "x", "'", "string", 12)]
+    #[case("\"\"\"", "x", "\"\"\"", "string", 12)]
+    fn test_parse_token_limits(
+        #[case] open: &str,
+        #[case] fill: &str,
+        #[case] close: &str,
+        #[case] kind: &str,
+        #[case] limit: usize,
+    ) {
+        let options = ParseOptions {
+            max_token_len: 8,
+            max_string_len: 12,
+            ..Default::default()
+        };
+        let input = |len: usize| format!("a + {}{}{}", open, fill.repeat(len), close);
+        let parse = |input: &str| Parser::with_options(input, options)?.parse_stmt().map(drop);
+        assert!(parse(&input(limit)).is_ok());
+        let err = parse(&input(limit + 1)).unwrap_err();
+        assert!(
+            matches!(err, Error::TokenTooLong { start: 4, kind: k, limit: l } if k == kind && l == limit),
+            "{:?}",
+            err
+        );
+        // The limit is found before the end of the token is, or the input.
+        assert!(matches!(
+            parse(&format!("a + {}{}", open, fill.repeat(1000))),
+            Err(Error::TokenTooLong { start: 4, .. })
+        ));
+        assert!(Parser::new(&input(limit + 1)).unwrap().parse_stmt().is_ok());
+    }
+
+    #[test]
+    fn test_parse_input_limit() {
+        let options = ParseOptions {
+            max_input_len: 10,
+            ..Default::default()
+        };
+        assert!(Parser::with_options("1 + 2 + 34", options).is_ok());
+        let err = Parser::with_options("1 + 2 + 345", options).err().unwrap();
+        assert!(matches!(err, Error::InputTooLarge { len: 11, limit: 10 }));
+        assert_eq!(err.position(), Some(10));
+        assert!(matches!(
+            Parser::statement_spans_with_options("1 + 2 + 345", options),
+            Err(Error::InputTooLarge { .. })
+        ));
+    }
+
     #[rstest]
     #[case("+true")]
     #[case("- 'hha'")]
     #[case("! 'haha'")]
     #[case("fasle ++")]
     #[case("'haha' --")]
+    #[case("format('{} {}', 1)")]
+    #[case("format()")]
+    #[case("formatNumber(1, -1)")]
+    #[case("formatNumber(1, 29)")]
+    #[case("formatNumber('1', 2)")]
+    #[case("formatNumber(1, 2, '')")]
+    #[case("formatNumber(1, 2, '..')")]
+    #[case("formatNumber(1, 2, '.', '.')")]
+    #[case("formatPercent(1, -2)")]
+    #[case("formatPercent(0.5)")]
+    #[case("formatPercent(79228162514264337593543950335, 0)")]
+    #[case("range(0, 10, 0)")]
+    #[case("range(0, 100001)")]
+    #[case("range(0, 1000000000000000000000000000)")]
+    #[case("range()")]
+    #[case("repeat(1, -1)")]
+    #[case("repeat('a', -1)")]
+    #[case("repeat('ab', 9223372036854775807)")]
+    #[case("padLeft('x', -1)")]
+    #[case("padLeft('x', 3, 'ab')")]
+    #[case("padLeft('x', 3, '')")]
+    #[case("startsWith('a', 'a', 1)")]
+    #[case("reverse(1)")]
+    #[case("capitalize(1)")]
+    #[case("chars(1)")]
+    #[case("ord('ab')")]
+    #[case("ord('')")]
+    #[case("ord(97)")]
+    #[case("chr(-1)")]
+    #[case("chr(55296)")]
+    #[case("chr(1114112)")]
+    #[case("chr(1.5)")]
+    #[case("repeat(1, 100001)")]
+    #[case("max()")]
+    #[case("sum(['a'])")]
+    #[case("len(1)")]
+    #[case("len(true)")]
+    #[case("len(missing)")]
+    #[case("len('a', 'b')")]
+    #[case("match d { 1: 'one', unknown(): 'fails' }")]
+    #[case("ifnull(1)")]
+    #[case("isNull()")]
+    #[case("isNotNull(1, 2)")]
+    #[case("default(d, 1)")]
+    #[case("default('d')")]
     fn test_execute_error(#[case] input: &str) {
-        init();
         let parser = Parser::new(input);
         assert!(parser.is_ok());
         let expr_ast = parser.unwrap().parse_expression();
@@ -812,7 +2438,10 @@ mod tests {
             "d" => 3,
             "f" => Arc::new(|_| Ok(Value::from(3)))
         );
-        assert!(expr_ast.unwrap().exec(&mut ctx).is_err())
+        let ast = expr_ast.unwrap();
+        assert!(ast.exec(&mut ctx).is_err());
+        let program = Compiler::compile(&ast).unwrap();
+        assert!(Vm::run(&program, &mut ctx).is_err());
     }
 
     #[rstest]
@@ -861,11 +2490,11 @@ mod tests {
     #[case("!a", ExprAST::Unary("!", Box::new(ExprAST::Reference("a"))))]
     #[case("2++", ExprAST::Postfix(
         Box::new(ExprAST::Literal(Literal::Number(2.into()))),
-        "++".to_string(),
+        "++",
     ))]
     #[case("2--", ExprAST::Postfix(
         Box::new(ExprAST::Literal(Literal::Number(2.into()))),
-        "--".to_string(),
+        "--",
     ))]
     #[case("2 not in [2]", ExprAST::Unary(
         "not",
@@ -884,7 +2513,6 @@ mod tests {
             ))
     ))]
     fn test_parse_chain_expression(#[case] input: &str, #[case] output: ExprAST) {
-        init();
         let parser = Parser::new(input);
         assert!(parser.is_ok());
         let expr_ast = parser.unwrap().parse_stmt();
@@ -892,20 +2520,177 @@ mod tests {
         assert_eq!(expr_ast.unwrap(), output);
     }
 
-    #[rstest]
-    #[case("")]
-    #[case(" ")]
+    fn register_word_ops() {
+        use crate::operator::{InfixOpAssociativity, InfixOpManager, InfixOpType};
+        use alloc::sync::Arc;
+        for (op, negate) in [("is not", true), ("not like", true), ("is   like", false)] {
+            InfixOpManager::new().register(
+                op,
+                60,
+                InfixOpType::CALC,
+                InfixOpAssociativity::LEFT,
+                Arc::new(move |left, right| {
+                    let like = left.string()?.contains(&right.string()?);
+                    Ok(Value::from(like != negate))
+                }),
+            );
+        }
+    }
+
+    #[rstest]
+    #[case("not a", ExprAST::Unary("not", Box::new(ExprAST::Reference("a"))))]
+    #[case(
+        "a not like b",
+        ExprAST::Binary(
+            "not like",
+            Box::new(ExprAST::Reference("a")),
+            Box::new(ExprAST::Reference("b")),
+        )
+    )]
+    #[case(
+        "a is\n  not b",
+        ExprAST::Binary(
+            "is\n  not",
+            Box::new(ExprAST::Reference("a")),
+            Box::new(ExprAST::Reference("b")),
+        )
+    )]
+    #[case(
+        "a is like b",
+        ExprAST::Binary(
+            "is like",
+            Box::new(ExprAST::Reference("a")),
+            Box::new(ExprAST::Reference("b")),
+        )
+    )]
+    #[case(
+        "(not a) in b",
+        ExprAST::Binary(
+            "in",
+            Box::new(ExprAST::Unary("not", Box::new(ExprAST::Reference("a")))),
+            Box::new(ExprAST::Reference("b")),
+        )
+    )]
+    #[case(
+        "a not in b",
+        ExprAST::Unary(
+            "not",
+            Box::new(ExprAST::Binary(
+                "in",
+                Box::new(ExprAST::Reference("a")),
+                Box::new(ExprAST::Reference("b")),
+            )),
+        )
+    )]
+    #[case(
+        "is + nothing",
+        ExprAST::Binary(
+            "+",
+            Box::new(ExprAST::Reference("is")),
+            Box::new(ExprAST::Reference("nothing")),
+        )
+    )]
+    fn test_parse_multi_word_op(#[case] input: &str, #[case] output: ExprAST) {
+        register_word_ops();
+        let expr_ast = Parser::new(input).unwrap().parse_stmt();
+        assert_eq!(expr_ast.unwrap(), output);
+    }
+
+    #[rstest]
+    #[case("!a == b", "! a == b", "(!a) == b")]
+    #[case("!a && b", "! a && b", "(!a) && b")]
+    #[case("not a in xs", "not (a in xs)", "not (a in xs)")]
+    #[case("not a == b", "not (a == b)", "not (a == b)")]
+    #[case("not a + 1 > b", "not (a + 1 > b)", "not (a + 1 > b)")]
+    #[case(
+        "not x between 1 and 5",
+        "not (x between [1,5])",
+        "not (x between [1,5])"
+    )]
+    #[case("not a && b", "not a && b", "(not a) && b")]
+    #[case("a || not b && c", "a || not b && c", "a || ((not b) && c)")]
+    #[case("not a ? 1 : 2", "not a ? 1 : 2", "(not a) ? 1 : 2")]
+    #[case("x = not a", "x = not a", "x = (not a)")]
+    #[case("not not a == b", "not (not (a == b))", "not (not (a == b))")]
+    #[case("(not a) in xs", "(not a) in xs", "(not a) in xs")]
+    #[case("x + (not a) == b", "x + (not a) == b", "(x + (not a)) == b")]
+    #[case("a && (not b)", "a && not b", "a && (not b)")]
+    fn test_parse_prefix_precedence(
+        #[case] input: &str,
+        #[case] printed: &str,
+        #[case] grouped: &str,
+    ) {
+        let ast = Parser::new(input).unwrap().parse_stmt().unwrap();
+        assert_eq!(ast.expr(), printed);
+        assert_eq!(ast, Parser::new(grouped).unwrap().parse_stmt().unwrap());
+        assert_eq!(ast, Parser::new(printed).unwrap().parse_stmt().unwrap());
+    }
+
+    #[rstest]
+    #[case("'abc' is not 'b'", false)]
+    #[case("'abc' not like 'x'", true)]
+    #[case("'abc' is  like 'a' && 'a' is not 'b'", true)]
+    fn test_exec_multi_word_op(#[case] input: &str, #[case] output: bool) {
+        register_word_ops();
+        assert_eq!(exec_in(input, || create_context!()).unwrap(), output.into());
+    }
+
+    #[rstest]
+    #[case("")]
+    #[case(" ")]
     fn test_parse_chain_expression_error(#[case] input: &str) {
-        init();
         let parser = Parser::new(input);
         assert!(parser.is_ok());
         let expr_ast = parser.unwrap().parse_expression();
         assert!(expr_ast.is_err());
     }
 
+    #[rstest]
+    #[case("")]
+    #[case(" ")]
+    #[case("\n\t ")]
+    fn test_parse_stmt_empty(#[case] input: &str) {
+        let ast = Parser::new(input).unwrap().parse_stmt().unwrap();
+        assert_eq!(ast, ExprAST::None);
+        assert_eq!(ast.expr(), "");
+        assert_eq!(exec_in(input, Context::new).unwrap(), Value::None);
+        assert_eq!(
+            crate::execute(input, create_context!()).unwrap(),
+            Value::None
+        );
+    }
+
+    #[rstest]
+    #[case("a = 1;", "a = 1")]
+    #[case("a = 1; ", "a = 1")]
+    #[case("a = 1;b;\n", "a = 1;b")]
+    fn test_parse_stmt_trailing_semicolon(#[case] input: &str, #[case] expr: &str) {
+        let ast = Parser::new(input).unwrap().parse_stmt().unwrap();
+        assert_eq!(ast.expr(), expr);
+        assert!(!matches!(&ast, ExprAST::Stmt(exprs) if exprs.contains(&ExprAST::None)));
+    }
+
+    #[rstest]
+    #[case(";")]
+    #[case(" ; ")]
+    #[case("a;;b")]
+    #[case("a = 1;;")]
+    fn test_parse_stmt_empty_statement(#[case] input: &str) {
+        assert!(Parser::new(input).unwrap().parse_stmt().is_err());
+    }
+
+    use crate::arena::ExprArena;
+    use crate::compiler::Compiler;
+    use crate::context::Context;
     use crate::create_context;
+    use crate::define::Result;
+    use crate::format::Rounding;
     use crate::function::InnerFunctionManager;
+    use crate::options::{EvalOptions, Limits, MathContext, SemanticsVersion, Truthiness};
+    use crate::vm::Vm;
+    use std::sync::atomic::{AtomicBool, Ordering};
     use std::sync::Arc;
+    use std::time::{Duration, Instant};
     #[rstest]
     #[case("2", 2.into())]
     #[case("'haha'", "haha".into())]
@@ -922,6 +2707,17 @@ mod tests {
     #[case("2==3", false.into())]
     #[case("100>>3", (100>>3).into())]
     #[case("100<<3", (100<<3).into())]
+    #[case("6&3", (6&3).into())]
+    #[case("6|3", (6|3).into())]
+    #[case("6^3", (6^3).into())]
+    #[case("~5", (!5).into())]
+    #[case("~-1", 0.into())]
+    #[case("~5&7", (!5&7).into())]
+    #[case("1|6&3^4", (1|6&3^4).into())]
+    #[case("(5&3)==1", true.into())]
+    #[case("true&&(6&3)==2||false", true.into())]
+    #[case("2.0<<1", 4.into())]
+    #[case("1<<63>>63", (-1).into())]
     #[case("(2>3)&&true", false.into())]
     #[case("2>3||True", true.into())]
     #[case("d+=3;d", 6.into())]
@@ -940,9 +2736,7 @@ mod tests {
     #[case("[2>3,1+5]", Value::List(
         vec![false.into(),6.into()]
     ))]
-    #[case("{'haha':2, 1+2:2>3}", Value::Map(
-        vec![("haha".into(),2.into()),(3.into(),false.into())]
-    ))]
+    #[case("{'haha':2, 1+2:2>3}", Value::Map(Map::from_iter([("haha".into(),2.into()),(3.into(),false.into())])))]
     #[case("2<=3?'haha':false", "haha".into())]
     #[case("2>=3?'haha':false", false.into())]
     #[case("min(1,2,2+3*5,-10)", (-10).into())]
@@ -964,9 +2758,7 @@ mod tests {
     #[case("[2>3,1+5, true]", 
         vec![false.into(),6.into(), true.into()].into()
     )]
-    #[case("{'haha':2, 1+2:2>3}", Value::Map(
-        vec![("haha".into(),2.into()),(3.into(),false.into())]
-    ))]
+    #[case("{'haha':2, 1+2:2>3}", Value::Map(Map::from_iter([("haha".into(),2.into()),(3.into(),false.into())])))]
     #[case("2<=3?'haha':false", "haha".into())]
     #[case("2>=3?'haha':false", false.into())]
     #[case("a=3;a%=2;a",(3%2).into())]
@@ -976,11 +2768,153 @@ mod tests {
     #[case("+5-2*4",(-3).into())]
     #[case("2-- +3", 4.into())]
     #[case("2++ *3", 9.into())]
+    #[case("{'a':1, 'b':2} == {'b':2, 'a':1}", true.into())]
+    #[case("{'a':1, 'b':2} == {'a':1, 'b':3}", false.into())]
+    #[case("{'a':1, 'b':2, 'a':3}", Value::Map(Map::from_iter([("a".into(),3.into()),("b".into(),2.into())])))]
+    #[case("'b' in {'a':1, 'b':2}", true.into())]
+    #[case("2 in {'a':1, 'b':2}", false.into())]
+    #[case("[{'a':1, 'b':2}] == [{'b':2, 'a':1}]", true.into())]
+    #[case("round(2.345, 2)", Value::from(2.35))]
+    #[case("round(-2.5)", (-3).into())]
+    #[case("round(2.5, 0, 'half_even')", 2.into())]
+    #[case("format_number(-1234567.891, '#,##0.00')", "-1,234,567.89".into())]
+    #[case("format_number(2.5, '0', 'down')", "2".into())]
+    #[case("formatNumber(1234567.891, 2)", "1234567.89".into())]
+    #[case("formatNumber(1234567.895, 2, ',', '.')", "1.234.567,90".into())]
+    #[case("formatNumber(-1234.5, 0, '.', ',')", "-1,235".into())]
+    #[case("formatNumber(2.5, 0)", "3".into())]
+    #[case("formatNumber(1.005, 2)", "1.01".into())]
+    #[case("formatNumber(0, 2)", "0.00".into())]
+    #[case("formatNumber(-0.004, 2)", "0.00".into())]
+    #[case("formatNumber(1234.5, 1, ',', '')", "1234,5".into())]
+    #[case("formatNumber(79228162514264337593543950335, 2, '.', ',')", "79,228,162,514,264,337,593,543,950,335.00".into())]
+    #[case("formatPercent(0.125, 1)", "12.5%".into())]
+    #[case("formatPercent(-0.125, 0)", "-13%".into())]
+    #[case("formatPercent(0.0005, 1)", "0.1%".into())]
+    #[case("formatPercent(0, 2)", "0.00%".into())]
+    #[case("format('{} ({})', formatNumber(1234.5, 2, ',', '.'), formatPercent(0.075, 1))", "1.234,50 (7.5%)".into())]
+    #[case("format('Order {} total {:.2}', d, 12.5)", "Order 3 total 12.50".into())]
+    #[case("format('{{{}}}', [1, 'a'])", "{[1, 'a']}".into())]
+    #[case("sum(range(1, 101)) == 5050", true.into())]
+    #[case("range(1, 4)", vec![1.into(), 2.into(), 3.into()].into())]
+    #[case("range(3)", vec![0.into(), 1.into(), 2.into()].into())]
+    #[case("range(0, 16, 5)", vec![0.into(), 5.into(), 10.into(), 15.into()].into())]
+    #[case("range(3, 0, -1)", vec![3.into(), 2.into(), 1.into()].into())]
+    #[case("range(0, 1, 0.5)", vec![0.into(), Value::from(0.5)].into())]
+    #[case("range(1, 1)", Value::List(vec![]))]
+    #[case("range(3, 0)", Value::List(vec![]))]
+    #[case("repeat(true, 3)", vec![true.into(), true.into(), true.into()].into())]
+    #[case("repeat('ab', 3)", "ababab".into())]
+    #[case("repeat('', 3)", "".into())]
+    #[case("padLeft('7', 3, '0')", "007".into())]
+    #[case("padRight('ab', 4)", "ab  ".into())]
+    #[case("padLeft('héllo', 3)", "héllo".into())]
+    #[case("padLeft('x', 3, '日')", "日日x".into())]
+    #[case("startsWith('Hello', 'He') && !startsWith('Hello', 'he')", true.into())]
+    #[case("startsWith('Hello', 'he', true)", true.into())]
+    #[case("endsWith('ÉTÉ', 'té', true) && endsWith('abc', 'bc', false)", true.into())]
+    #[case("capitalize('hELLO wORLD')", "Hello world".into())]
+    #[case("capitalize('ßa')", "SSa".into())]
+    #[case("capitalize('')", "".into())]
+    #[case("title('hello   wORLD\tfoo-bar')", "Hello   World\tFoo-bar".into())]
+    #[case("compareIgnoreCase('Apple', 'aPPLE')", 0.into())]
+    #[case("compareIgnoreCase('a', 'B')", (-1).into())]
+    #[case("compareIgnoreCase('b', 'A')", 1.into())]
+    #[case("reverse('héllo')", "olléh".into())]
+    #[case("reverse('e\u{301}x')", "x\u{301}e".into())]
+    #[case("reverse([1, 2, 3])", vec![3.into(), 2.into(), 1.into()].into())]
+    #[case("chars('abc')", vec!["a".into(), "b".into(), "c".into()].into())]
+    #[case("chars('')", Value::List(vec![]))]
+    #[case("len(chars('héllo')) == len('héllo')", true.into())]
+    #[case("chars('e\u{301}')", vec!["e".into(), "\u{301}".into()].into())]
+    #[case("chars('👍🏽')", vec!["👍".into(), "🏽".into()].into())]
+    #[case("ord('a')", 97.into())]
+    #[case("ord('é')", 233.into())]
+    #[case("ord('😀')", 128512.into())]
+    #[case("chr(97)", "a".into())]
+    #[case("chr(128512)", "😀".into())]
+    #[case("chr(ord('é')) == 'é'", true.into())]
+    #[case("repeat([1], 0)", Value::List(vec![]))]
+    #[case("max([1, 5, 2]) + min(range(2, 9))", 7.into())]
+    #[case("mul([2, 3])", 6.into())]
+    #[case("len('hello')", 5.into())]
+    #[case("len('héllo')", 5.into())]
+    #[case("len('日本語')", 3.into())]
+    #[case("len('')", 0.into())]
+    #[case("len([1, [2, 3]])", 2.into())]
+    #[case("len([])", 0.into())]
+    #[case("size({'a': 1, 'b': 2})", 2.into())]
+    #[case("size({})", 0.into())]
+    #[case("match d { 1: 'one', 3: 'three', _: 'other' }", "three".into())]
+    #[case("match d { 3: 'first', 3: 'second' }", "first".into())]
+    #[case("match d { 1: 'one', _: 'other' }", "other".into())]
+    #[case("match d { 1: 'one' }", Value::None)]
+    #[case("match d {}", Value::None)]
+    #[case("match d + 1 { 2 * 2: 'four' }", "four".into())]
+    #[case("match [d] { [3]: true, _: false }", true.into())]
+    #[case("match '3' { d: 'number', _: 'string' }", "string".into())]
+    #[case("match d { 3: 'lazy', unknown(): 'never evaluated' }", "lazy".into())]
+    #[case("match d { 3: match f() { 3: 'nested' } }", "nested".into())]
+    #[case("x = match d { 3: 1, _: 2 }; x + 1", 2.into())]
+    #[case("match = 2; match * d", 6.into())]
+    #[case("match (d) { 3: 'three' }", "three".into())]
+    #[case("match(d - 1) * 2 { 4: 'four' }", "four".into())]
+    #[case("coalesce(missing, missing_too, 1, 2)", 1.into())]
+    #[case("coalesce(missing, d)", 3.into())]
+    #[case("coalesce(missing, missing_too)", Value::None)]
+    #[case("coalesce()", Value::None)]
+    #[case("ifnull(missing, 2)", 2.into())]
+    #[case("ifnull(d, 2)", 3.into())]
+    #[case("ifnull(false, 2)", false.into())]
+    #[case("null", Value::None)]
+    #[case("null == none", true.into())]
+    #[case("missing == null", true.into())]
+    #[case("d == null", false.into())]
+    #[case("d != null", true.into())]
+    #[case("0 == null || '' == null || false == null || [] == null", false.into())]
+    #[case("[1, null, 3]", vec![1.into(), Value::None, 3.into()].into())]
+    #[case("[1, null, 3] == [1, None, 3]", true.into())]
+    #[case("null in [1, null]", true.into())]
+    #[case("{'a': null}", Value::Map(Map::from_iter([("a".into(), Value::None)])))]
+    #[case("{(null): 1}", Value::Map(Map::from_iter([(Value::None, 1.into())])))]
+    #[case("d > 1 ? null : d", Value::None)]
+    #[case("x = null; x ?? 'fallback'", "fallback".into())]
+    #[case("coalesce(null, none, d)", 3.into())]
+    #[case("ifnull(null, 2)", 2.into())]
+    #[case("isNull(null)", true.into())]
+    #[case("isNull(missing)", true.into())]
+    #[case("isNull(0)", false.into())]
+    #[case("isNull(match d { 1: 'one' })", true.into())]
+    #[case("isNotNull(d)", true.into())]
+    #[case("isNotNull([null])", true.into())]
+    #[case("isNotNull(None)", false.into())]
+    #[case("default('d', 1)", 3.into())]
+    #[case("default('f', 1)", 3.into())]
+    #[case("default('missing', 1)", 1.into())]
+    #[case("e = [1]; default('e', 1)", vec![1.into()].into())]
     #[case("'a' not in ['a']", false.into())]
     #[case("2 not in ['a', false, true, 1+2]", true.into())]
     #[case("3 not in ['a', false, true, 1+2] || 3>=2", true.into())]
+    #[case("[...[], 1, ...[]]", vec![1.into()].into())]
+    #[case("[1, 2, 3,]", vec![1.into(), 2.into(), 3.into()].into())]
+    #[case("[\n  1,\n  2,\n]", vec![1.into(), 2.into()].into())]
+    #[case("sum(1, 2,)", 3.into())]
+    #[case("f(d,)", 3.into())]
+    #[case("{'a': 1,} == {'a': 1}", true.into())]
+    #[case("[[1,],]", vec![vec![1.into()].into()].into())]
+    #[case("[...[]]", Value::List(vec![]))]
+    #[case(
+        "[...[1, 2], d, ...range(2)]",
+        vec![1.into(), 2.into(), 3.into(), 0.into(), 1.into()].into()
+    )]
+    #[case("[...[[1]]]", vec![vec![1.into()].into()].into())]
+    #[case("e = [1, 2]; [...e, ...e]", vec![1.into(), 2.into(), 1.into(), 2.into()].into())]
+    #[case("sum(...[1, 2], d)", 6.into())]
+    #[case("sum(...[])", 0.into())]
+    #[case("max(...range(5), 2)", 4.into())]
+    #[case("coalesce(...[missing, 2])", 2.into())]
+    #[case("f(...[1, 2])", 3.into())]
     fn test_exec(#[case] input: &str, #[case] output: Value) {
-        init();
         let mut ctx = create_context!(
             "d" => 3,
             "f" => Arc::new(|_| Ok(Value::from(3)))
@@ -999,11 +2933,1184 @@ mod tests {
         }
         assert_eq!(res.clone(), output);
         ast.clone().describe();
+        let mut ctx = create_context!(
+            "d" => 3,
+            "f" => Arc::new(|_| Ok(Value::from(3)))
+        );
+        assert_eq!(ast.exec_iterative(&mut ctx).unwrap(), output);
+        let mut ctx = create_context!(
+            "d" => 3,
+            "f" => Arc::new(|_| Ok(Value::from(3)))
+        );
+        let program = Compiler::compile(&ast).unwrap();
+        assert_eq!(Vm::run(&program, &mut ctx).unwrap(), output);
+    }
+
+    #[test]
+    fn test_exec_iterative_deep() {
+        let input = vec!["1"; 100_000].join("+");
+        let ast = Parser::new(&input).unwrap().parse_stmt().unwrap();
+        let ans = ast.exec_iterative(&mut create_context!());
+        assert_eq!(ans.unwrap(), Value::from(100_000));
+    }
+
+    #[test]
+    fn test_clone_eq_hash_deep() {
+        let input = vec!["1"; 100_000].join("+");
+        let other_input = input.clone() + "+2";
+        let ast = parse(&input);
+        let copy = ast.clone();
+        assert!(ast == copy);
+        assert_eq!(hash(&ast), hash(&copy));
+        assert!(ast != parse(&other_input));
+    }
+
+    #[test]
+    fn test_display_deep() {
+        let input = vec!["1"; 100_000].join("+");
+        let ast = parse(&input);
+        assert_eq!(ast.to_string(), vec!["1"; 100_000].join(" + "));
+        let (text, spans) = ast.layout();
+        assert_eq!(spans.len(), 199_999);
+        assert_eq!(
+            spans[&(&ast as *const ExprAST as usize)],
+            crate::token::Span(0, text.len())
+        );
+    }
+
+    #[rstest]
+    #[case("a + b * c", "a + c * b")]
+    #[case("f(1, 2)", "f(1)")]
+    #[case("[1, [2]]", "[[1], 2]")]
+    #[case("{'a': 1}", "{'a': 1, 'b': 2}")]
+    #[case("match a {1: 2}", "match a {1: 2, _: 3}")]
+    #[case("a ? b : c", "a ? c : b")]
+    #[case("x = 1; x", "x = 1; y")]
+    fn test_clone_eq_hash(#[case] input: &str, #[case] other: &str) {
+        let (ast, other) = (parse(input), parse(other));
+        let copy = ast.clone();
+        assert_eq!(copy, ast);
+        assert_eq!(hash(&copy), hash(&ast));
+        assert_ne!(ast, other);
+        assert_ne!(hash(&ast), hash(&other));
+    }
+
+    // Runs `input` with `v` bound to `value` on every backend and checks
+    // they agree, returning the bare error.
+    fn exec_with_options(input: &str, value: &Value, options: EvalOptions) -> Result<Value> {
+        exec_in(input, || {
+            let mut ctx = Context::with_options(options);
+            ctx.set_variable("v", value.clone());
+            ctx
+        })
+    }
+
+    // Evaluates `input` with every backend, each against a fresh `ctx()`,
+    // and checks they agree. The arena doesn't add context to errors, so its
+    // error is compared with the root of the others and returned.
+    fn exec_in(input: &str, ctx: impl Fn() -> Context) -> Result<Value> {
+        let ast = Parser::new(input).unwrap().parse_stmt().unwrap();
+        let program = Compiler::compile(&ast).unwrap();
+        let ans = ast.exec(&mut ctx());
+        let iterative = ast.exec_iterative(&mut ctx());
+        let vm = Vm::run(&program, &mut ctx());
+        let arena = ExprArena::from(&ast).exec(&mut ctx());
+        assert_eq!(format!("{:?}", ans), format!("{:?}", iterative));
+        assert_eq!(format!("{:?}", ans), format!("{:?}", vm));
+        let ans = ans.as_ref().map_err(crate::error::Error::root);
+        assert_eq!(format!("{:?}", ans), format!("{:?}", arena.as_ref()));
+        arena
+    }
+
+    #[rstest]
+    #[case("0 <= score <= 100", "0 <= score && score <= 100", true.into())]
+    #[case("0 <= score <= 50", "0 <= score && score <= 50", false.into())]
+    #[case("100 < score < 200", "100 < score && score < 200", false.into())]
+    #[case("1 < 2 < 3 < 4", "1 < 2 && 2 < 3 && 3 < 4", true.into())]
+    #[case("1 < 2 < 4 < 3", "1 < 2 && 2 < 4 && 4 < 3", false.into())]
+    #[case("100 >= score > 50 >= 0", "100 >= score && score > 50 && 50 >= 0", true.into())]
+    #[case("1 < score + 1 <= 2 * 50", "1 < score + 1 && score + 1 <= 2 * 50", true.into())]
+    #[case("score == 75 == 75.0", "score == 75 && 75 == 75.0", true.into())]
+    #[case("score != 0 != score", "score != 0 && 0 != score", true.into())]
+    #[case("0 < score < 100 == true", "(0 < score && score < 100) == true", true.into())]
+    #[case("0 < score == 1 < 2", "0 < score == 1 < 2", true.into())]
+    #[case("score == 75 < 100", "score == 75 < 100", false.into())]
+    #[case("(0 < score) == true", "0 < score == true", true.into())]
+    #[case("(score == 75) == true", "(score == 75) == true", true.into())]
+    #[case("(0 < score) <= false", "(0 < score) <= false", false.into())]
+    #[case("0 < score ? 'pos' : 'neg'", "0 < score ? \"pos\" : \"neg\"", "pos".into())]
+    #[case("[0 < score < 10, 0 < score < 100]", "[0 < score && score < 10,0 < score && score < 100]", vec![false.into(), true.into()].into())]
+    fn test_exec_comparison_chain(#[case] input: &str, #[case] expr: &str, #[case] output: Value) {
+        let ast = Parser::new(input).unwrap().parse_stmt().unwrap();
+        assert_eq!(ast.expr(), expr);
+        let ans = exec_in(input, || create_context!("score" => 75)).unwrap();
+        assert_eq!(ans, output);
+    }
+
+    #[rstest]
+    #[case("`order total` * 2", "`order total` * 2", 50.into())]
+    #[case("`in` + 1", "`in` + 1", 2.into())]
+    #[case("`in` in [1, 2]", "`in` in [1,2]", true.into())]
+    #[case("`null` ?? 0", "`null` ?? 0", "set".into())]
+    #[case("`a``b` + ``x`y``", "`a``b` + ``x`y``", 7.into())]
+    #[case("`order total` = `in` + 9; `order total`", "`order total` = `in` + 9;`order total`", 10.into())]
+    #[case("{(`in`): 1, `in`: 2}", "{(`in`):1,\"in\":2}", Value::Map([(1.into(), 1.into()), ("in".into(), 2.into())].into_iter().collect()))]
+    fn test_exec_quoted_names(#[case] input: &str, #[case] expr: &str, #[case] output: Value) {
+        let ast = Parser::new(input).unwrap().parse_stmt().unwrap();
+        assert_eq!(ast.expr(), expr);
+        let ans = exec_in(input, || {
+            let mut ctx = create_context!("in" => 1, "null" => "set");
+            ctx.set_variable("order total", 25.into());
+            ctx.set_variable("a``b", 3.into());
+            ctx.set_variable("x`y", 4.into());
+            ctx
+        });
+        assert_eq!(ans.unwrap(), output);
+    }
+
+    #[test]
+    fn test_exec_comparison_chain_error() {
+        let err = exec_in("0 <= score <= 100", || create_context!()).unwrap_err();
+        assert_eq!(err.to_string(), "can not compare Number with None");
+    }
+
+    #[rstest]
+    #[case("0.1 + 0.2", Value::from(0.3))]
+    #[case("v * 3 - 1", Value::from(0.5))]
+    #[case("10 / 4 % 2", Value::from(0.5))]
+    #[case("sqrt(2) > 1.414", true.into())]
+    #[case("sqrt(v * 32)", 4.into())]
+    #[case("pow(2, 10) > 1000", true.into())]
+    #[case("pow(-2, 3)", (-8).into())]
+    #[case("ln(exp(2))", 2.into())]
+    #[case("v + 1 > 1.4 && [v, 1] < [0.5, 2]", true.into())]
+    fn test_exec_float_math(#[case] input: &str, #[case] output: Value) {
+        let options = EvalOptions {
+            float_math: true,
+            ..EvalOptions::default()
+        };
+        let ans = exec_with_options(input, &Value::from(0.5), options).unwrap();
+        assert_eq!(ans, output);
+        // Decimal math agrees, up to the digits a float has.
+        match exec_with_options(input, &Value::from(0.5), EvalOptions::default()).unwrap() {
+            Value::Number(ans) => {
+                let ans = Value::try_from_f64(ans.to_f64().unwrap()).unwrap();
+                assert_eq!(ans, output);
+            }
+            ans => assert_eq!(ans, output),
+        }
+    }
+
+    // Every difference between the semantics versions, in each backend.
+    #[rstest]
+    #[case("v = 1", Ok(Value::None), Ok(1.into()))]
+    #[case("v += 2", Ok(Value::None), Ok(2.5.into()))]
+    #[case("a = v = 4; [a, v]", Ok(vec![Value::None, 4.into()].into()), Ok(vec![4.into(), 4.into()].into()))]
+    #[case("(v = 2) * 3", Err("should be number"), Ok(6.into()))]
+    #[case("'a' + 'b'", Err("should be number"), Ok("ab".into()))]
+    #[case("s = 'x'; s += 'y'; s", Err("should be number"), Ok("xy".into()))]
+    fn test_exec_semantics(
+        #[case] input: &str,
+        #[case] v1: core::result::Result<Value, &str>,
+        #[case] v2: core::result::Result<Value, &str>,
+    ) {
+        for (semantics, expected) in [(SemanticsVersion::V1, v1), (SemanticsVersion::V2, v2)] {
+            let options = EvalOptions {
+                semantics,
+                ..EvalOptions::default()
+            };
+            let ans = exec_with_options(input, &Value::from(0.5), options);
+            match expected {
+                Ok(value) => assert_eq!(ans.unwrap(), value),
+                Err(message) => assert!(ans.unwrap_err().to_string().starts_with(message)),
+            }
+        }
+    }
+
+    #[rstest]
+    #[case("'a' + 1")]
+    #[case("1 + 'a'")]
+    #[case("[1] + [2]")]
+    #[case("v = 'a' + null")]
+    fn test_exec_semantics_unchanged(#[case] input: &str) {
+        let v2 = EvalOptions {
+            semantics: SemanticsVersion::V2,
+            ..EvalOptions::default()
+        };
+        let ans = exec_with_options(input, &Value::from(0.5), v2).unwrap_err();
+        let v1 = exec_with_options(input, &Value::from(0.5), EvalOptions::default());
+        assert_eq!(ans.to_string(), v1.unwrap_err().to_string());
+    }
+
+    #[rstest]
+    #[case("1 / 3", "0.33")]
+    #[case("1 / 8", "0.12")]
+    #[case("3 / 8", "0.38")]
+    #[case("10 / 3 * 3", "9.99")]
+    #[case("x = 100 / 7; x * 7", "100.03")]
+    #[case("0.125 + 0.01 - 0.005", "0.14")]
+    #[case("v * 1.1 * 1.1 * 1.1", "1.32")]
+    #[case("a = 1.005; a *= 1; a += 0.01; a", "1.01")]
+    #[case("7.555 % 5", "2.56")]
+    #[case("2 * 1.5", "3.0")]
+    fn test_exec_math_context(#[case] input: &str, #[case] output: &str) {
+        let options = EvalOptions {
+            math: MathContext {
+                max_scale: Some(2),
+                rounding: Rounding::HalfEven,
+                ..MathContext::default()
+            },
+            ..EvalOptions::default()
+        };
+        let ans = exec_with_options(input, &Value::from(0.995), options).unwrap();
+        assert_eq!(ans.decimal().unwrap().to_string(), output);
+    }
+
+    #[rstest]
+    #[case("1 / 3", MathContext::default(), "0.3333333333333333333333333333")]
+    #[case(
+        "2000000000 / 3",
+        MathContext::default(),
+        "666666666.66666666666666666667"
+    )]
+    #[case("1 / 3 + 1 / 3", MathContext { division_scale: 4, ..MathContext::default() }, "0.6666")]
+    #[case("2 / 3", MathContext { division_scale: 4, max_scale: Some(6), ..MathContext::default() }, "0.6667")]
+    #[case("2 / 3", MathContext { division_scale: 4, max_scale: Some(1), ..MathContext::default() }, "0.7")]
+    #[case("x = 1; x /= 3; x", MathContext { division_scale: 2, rounding: Rounding::Down, ..MathContext::default() }, "0.33")]
+    fn test_exec_division_scale(
+        #[case] input: &str,
+        #[case] math: MathContext,
+        #[case] output: &str,
+    ) {
+        let options = EvalOptions {
+            math,
+            ..EvalOptions::default()
+        };
+        let ans = exec_with_options(input, &Value::None, options).unwrap();
+        assert_eq!(ans.decimal().unwrap().to_string(), output);
+    }
+
+    // These panicked inside `Decimal` before.
+    #[rstest]
+    #[case(
+        "79228162514264337593543950335 * 2",
+        "the result of '*' is too large for a number"
+    )]
+    #[case(
+        "-79228162514264337593543950335 - 1",
+        "the result of '-' is too large for a number"
+    )]
+    #[case(
+        "x = 79228162514264337593543950335; x += 1",
+        "the result of '+=' is too large for a number"
+    )]
+    #[case("1 / 0", "division by zero")]
+    #[case("5 % (1 - 1)", "division by zero")]
+    #[case("x = 1; x /= 0", "division by zero")]
+    fn test_exec_arithmetic_error(#[case] input: &str, #[case] message: &str) {
+        for math in [
+            MathContext::default(),
+            MathContext {
+                max_scale: Some(2),
+                ..MathContext::default()
+            },
+        ] {
+            let options = EvalOptions {
+                math,
+                ..EvalOptions::default()
+            };
+            let err = exec_with_options(input, &Value::None, options).unwrap_err();
+            assert_eq!(err.to_string(), message);
+        }
+    }
+
+    #[rstest]
+    #[case("1 / v", "float inf can not be represented as a number")]
+    #[case(
+        "pow(10, 30)",
+        "float 1000000000000000000000000000000 can not be represented as a number"
+    )]
+    #[case("sqrt(-1)", "param invalid")]
+    #[case("ln(v)", "param invalid")]
+    #[case("pow(-8, 1 / 3)", "param invalid")]
+    #[case("v + 'a'", "should be number")]
+    fn test_exec_float_math_error(#[case] input: &str, #[case] message: &str) {
+        let options = EvalOptions {
+            float_math: true,
+            ..EvalOptions::default()
+        };
+        let err = exec_with_options(input, &Value::from(0), options).unwrap_err();
+        assert_eq!(err.to_string(), message);
+    }
+
+    #[rstest]
+    #[case("sqrt(2)", "1.4142135623730950488016887242")]
+    #[case("exp(1)", "2.7182818284590452353602874688")]
+    #[case("ln(100)", "4.6051701859880913680359829097")]
+    #[case("pow(2, -2)", "0.25")]
+    #[case("pow(0, 0.5)", "0")]
+    fn test_exec_decimal_math(#[case] input: &str, #[case] output: &str) {
+        let ans = exec_with_options(input, &Value::None, EvalOptions::default()).unwrap();
+        assert_eq!(ans.decimal().unwrap().to_string(), output);
+    }
+
+    #[rstest]
+    #[case("clamp(v, 0, 10)", "5.25", "5.25")]
+    #[case("clamp(v, 0, 10)", "-3", "0")]
+    #[case("math.clamp(v, 0, 10)", "10.5", "10")]
+    #[case("clamp(v, 1.5, 1.5)", "7", "1.5")]
+    #[case("sign(v)", "-0.001", "-1")]
+    #[case("sign(v)", "0.00", "0")]
+    #[case("sign(v)", "42", "1")]
+    #[case("trunc(v)", "2.75", "2")]
+    #[case("trunc(v)", "-2.75", "-2")]
+    #[case("trunc(v)", "-0.5", "0")]
+    #[case("frac(v)", "2.750", "0.750")]
+    #[case("frac(v)", "-2.75", "-0.75")]
+    #[case("frac(v)", "-3", "0")]
+    #[case("math.frac(v) + math.trunc(v)", "-12.345", "-12.345")]
+    fn test_exec_decimal_utilities(#[case] input: &str, #[case] v: &str, #[case] output: &str) {
+        let v = Value::Number(Decimal::from_str(v).unwrap());
+        let ans = exec_with_options(input, &v, EvalOptions::default()).unwrap();
+        assert_eq!(ans.decimal().unwrap().to_string(), output);
+    }
+
+    #[rstest]
+    #[case("0.5 + 0.5", true)]
+    #[case("-4.000", true)]
+    #[case("0", true)]
+    #[case("-0.5", false)]
+    #[case("79228162514264337593543950335", true)]
+    #[case("0.0000000000000000000000000001", false)]
+    fn test_exec_trunc_frac(#[case] x: &str, #[case] integer: bool) {
+        let input = format!(
+            "x = {}; [isInteger(x), trunc(x) + frac(x) == x, sign(frac(x)) * sign(x) >= 0]",
+            x
+        );
+        let ans = exec_in(&input, Context::new).unwrap();
+        assert_eq!(ans, vec![integer.into(), true.into(), true.into()].into());
+    }
+
+    #[rstest]
+    #[case("clamp(1, 10, 0)", "lower bound 10 is greater than upper bound 0")]
+    #[case(
+        "clamp(1, 0.5, -0.5)",
+        "lower bound 0.5 is greater than upper bound -0.5"
+    )]
+    #[case("clamp('a', 0, 1)", "should be number")]
+    #[case("clamp(1, 0)", "param invalid")]
+    #[case("sign(null)", "should be number")]
+    #[case("isInteger('1')", "should be number")]
+    #[case("trunc(1, 2)", "param invalid")]
+    fn test_exec_decimal_utilities_error(#[case] input: &str, #[case] message: &str) {
+        let err = exec_with_options(input, &Value::None, EvalOptions::default()).unwrap_err();
+        assert_eq!(err.to_string(), message);
+    }
+
+    #[rstest]
+    #[case("len(v)", Value::from(1), "Number has no length")]
+    #[case("size(v)", Value::None, "None has no length")]
+    #[case("len(v)", Value::from(true), "Bool has no length")]
+    fn test_exec_len_error(#[case] input: &str, #[case] value: Value, #[case] message: &str) {
+        let err = exec_with_options(input, &value, EvalOptions::default()).unwrap_err();
+        assert_eq!(err.to_string(), message);
+    }
+
+    #[rstest]
+    #[case(
+        "a = 1; v ? 1 : 2",
+        Value::from(3),
+        "error in statement 2: should be bool, got Number: in 'v ? 1 : 2'"
+    )]
+    #[case(
+        "[1 + (2 * v)]",
+        Value::from("x"),
+        "should be number: in '2 * v' within '1 + 2 * v'"
+    )]
+    #[case("v && true", Value::None, "should be bool, got None: in 'v && true'")]
+    #[case(
+        "[1, ...v]",
+        Value::from("x"),
+        "can not spread String, only a list: in '...v' within '[1,...v]'"
+    )]
+    #[case(
+        "max(1, len(v)) > 0",
+        Value::None,
+        "None has no length: in 'len(v)' within 'max(1,len(v))'"
+    )]
+    fn test_exec_error_context(#[case] input: &str, #[case] value: Value, #[case] message: &str) {
+        let ast = Parser::new(input).unwrap().parse_stmt().unwrap();
+        let mut ctx = Context::new();
+        ctx.set_variable("v", value);
+        let err = ast.exec(&mut ctx).unwrap_err();
+        assert_eq!(err.to_string(), message);
+        assert!(!matches!(
+            err.root(),
+            crate::error::Error::WithContext { .. }
+        ));
+    }
+
+    #[test]
+    fn test_exec_chain_statement_error() {
+        let input = "a = 1; b = a + 1; c = b * x; d = c";
+        let ast = Parser::new(input).unwrap().parse_stmt().unwrap();
+        let mut ctx = Context::new();
+        let err = ast.exec(&mut ctx).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "error in statement 3: should be number: in 'b * x' within 'c = b * x'"
+        );
+        match &err {
+            crate::error::Error::InStatement {
+                index, statement, ..
+            } => assert_eq!((*index, statement.as_str()), (3, "c = b * x")),
+            err => panic!("{:?}", err),
+        }
+        assert_eq!(err.code(), "E_TYPE_EXPECTED_NUMBER");
+        assert_eq!(ctx.value("b").unwrap(), Value::from(2));
+        assert!(ctx.get("d").is_none());
+    }
+
+    #[test]
+    fn test_exec_chain_collect() {
+        let input = "a = 1; b = len(a); c = a + 1; d = [1] + c; c";
+        let ast = Parser::new(input).unwrap().parse_stmt().unwrap();
+        let mut ctx = Context::new();
+        let errors = ast.exec_chain_collect(&mut ctx).unwrap_err();
+        let messages: Vec<String> = errors.iter().map(|err| err.to_string()).collect();
+        assert_eq!(
+            messages,
+            [
+                "error in statement 2: Number has no length: in 'len(a)' within 'b = len(a)'",
+                "error in statement 4: should be number: in '[1] + c' within 'd = [1] + c'",
+            ]
+        );
+        assert_eq!(ctx.value("c").unwrap(), Value::from(2));
+
+        let mut ctx = Context::new();
+        let ast = Parser::new("a = 2; a * 3").unwrap().parse_stmt().unwrap();
+        assert_eq!(ast.exec_chain_collect(&mut ctx).unwrap(), Value::from(6));
+        let ast = Parser::new("y + 1").unwrap().parse_stmt().unwrap();
+        let errors = ast.exec_chain_collect(&mut ctx).unwrap_err();
+        assert_eq!(
+            errors[0].to_string(),
+            "error in statement 1: should be number: in 'y + 1'"
+        );
+    }
+
+    #[rstest]
+    #[case("[1, ...v]")]
+    #[case("sum(...v)")]
+    #[case("[...[1], ...(v)]")]
+    fn test_exec_spread_error(#[case] input: &str) {
+        let err = exec_with_options(input, &Value::from(1), EvalOptions::default()).unwrap_err();
+        assert_eq!(err.to_string(), "can not spread Number, only a list");
+        let err = exec_with_options(input, &Value::None, EvalOptions::default()).unwrap_err();
+        assert_eq!(err.to_string(), "can not spread None, only a list");
+    }
+
+    fn user() -> Value {
+        let mut address = Map::default();
+        address.insert("city".into(), "Paris".into());
+        address.insert("zip".into(), 75001.into());
+        let mut user = Map::default();
+        user.insert("name".into(), "Ada".into());
+        user.insert("age".into(), 36.into());
+        user.insert("address".into(), address.into());
+        user.into()
+    }
+
+    #[rstest]
+    #[case("v.name", "Ada".into())]
+    #[case("v.address.city", "Paris".into())]
+    #[case("v.age + 4 * 2", 44.into())]
+    #[case("max(v.age, v.address.zip) - 75000", 1.into())]
+    #[case("len(v.address.city) == 5 ? v.name : ''", "Ada".into())]
+    #[case("[v.name, ...[v.age]]", vec!["Ada".into(), 36.into()].into())]
+    #[case("v.address.city in ['Paris', 'Rome']", true.into())]
+    #[case("len(v.address)", 2.into())]
+    #[case("missing.address.city", Value::None)]
+    fn test_exec_dotted_reference(#[case] input: &str, #[case] output: Value) {
+        let ans = exec_with_options(input, &user(), EvalOptions::default());
+        assert_eq!(ans.unwrap(), output);
+    }
+
+    #[rstest]
+    #[case("v.address.country", "v.address exists but has no key 'country'")]
+    #[case("v.email.domain", "v exists but has no key 'email'")]
+    #[case("v.age.years + 1", "v.age exists but has no key 'years'")]
+    #[case("v.address.city.x", "v.address.city exists but has no key 'x'")]
+    fn test_exec_dotted_reference_error(#[case] input: &str, #[case] message: &str) {
+        let err = exec_with_options(input, &user(), EvalOptions::default()).unwrap_err();
+        assert_eq!(err.to_string(), message);
+    }
+
+    #[rstest]
+    #[case(
+        "user.address.city = 'Oslo'; user.address.zip = 150; user.name = 'Ada'; user",
+        "{'address': {'city': 'Oslo', 'zip': 150}, 'name': 'Ada'}"
+    )]
+    #[case("a.b.c = 1; a.b.c += 2; a.b.c * 2", "6")]
+    #[case("v.address.city = 'Oslo'; v.address", "{'city': 'Oslo', 'zip': 75001}")]
+    #[case("v.age = v.age + 1; [v.age, v.name]", "[37, 'Ada']")]
+    #[case("v.address.geo.lat = 48; len(v.address)", "3")]
+    #[case("v.name = {}; v.name.first = 'Ada'; v.name", "{'first': 'Ada'}")]
+    fn test_exec_dotted_assignment(#[case] input: &str, #[case] output: &str) {
+        let ans = exec_with_options(input, &user(), EvalOptions::default());
+        assert_eq!(ans.unwrap().to_string(), output);
+    }
+
+    #[rstest]
+    #[case("v.age.years = 1", "v.age is Number, not a map")]
+    #[case("v.name.first.initial = 'A'", "v.name is String, not a map")]
+    #[case("a = [1]; a.b = 2", "a is List, not a map")]
+    fn test_exec_dotted_assignment_error(#[case] input: &str, #[case] message: &str) {
+        let err = exec_with_options(input, &user(), EvalOptions::default()).unwrap_err();
+        assert_eq!(err.to_string(), message);
+    }
+
+    #[test]
+    fn test_exec_dotted_reference_exact_name_first() {
+        let mut ctx = create_context!(
+            "v.name" => "exact",
+            "v.address" => 1,
+            "config" => Arc::new(|_| Ok(user()))
+        );
+        ctx.set_variable("v", user());
+        for (input, output) in [
+            ("v.name", Value::from("exact")),
+            ("v.age", 36.into()),
+            ("v.address", 1.into()),
+            ("config.address.zip", 75001.into()),
+        ] {
+            let ast = Parser::new(input).unwrap().parse_stmt().unwrap();
+            assert_eq!(ast.exec(&mut ctx).unwrap(), output);
+        }
+        let ast = Parser::new("v.address.city").unwrap().parse_stmt().unwrap();
+        assert_eq!(
+            ast.exec(&mut ctx).unwrap_err().root().to_string(),
+            "v.address exists but has no key 'city'"
+        );
+    }
+
+    #[test]
+    fn test_exec_strict_match() {
+        let options = EvalOptions {
+            strict_match: true,
+            ..EvalOptions::default()
+        };
+        let err = exec_with_options("match v { 1: 'one' }", &Value::from("x"), options);
+        assert_eq!(err.unwrap_err().to_string(), "no match arm for 'x'");
+        let ans = exec_with_options("match v { 1: 'one', _: 'other' }", &Value::None, options);
+        assert_eq!(ans.unwrap(), "other".into());
+    }
+
+    #[rstest]
+    #[case("{'a': 1, 'a': 2}", &[("a", 2)], "duplicate map key 'a'")]
+    #[case("{'a': 1, 'b': 2, 'a': 3}", &[("a", 3), ("b", 2)], "duplicate map key 'a'")]
+    #[case("{(v): 1, 'b': 2, 'k': 3}", &[("k", 3), ("b", 2)], "duplicate map key 'k'")]
+    #[case("{'a': 1, 'b': 2}", &[("a", 1), ("b", 2)], "")]
+    fn test_exec_duplicate_map_keys(
+        #[case] input: &str,
+        #[case] entries: &[(&str, i32)],
+        #[case] message: &str,
+    ) {
+        let value = Value::from("k");
+        let ans = exec_with_options(input, &value, EvalOptions::default()).unwrap();
+        let expected = entries
+            .iter()
+            .map(|(k, v)| (Value::from(*k), Value::from(*v)));
+        let Value::Map(m) = ans else {
+            panic!("not a map")
+        };
+        assert!(m.iter().eq(Map::from_iter(expected).iter()));
+        let options = EvalOptions {
+            strict_map_keys: true,
+            ..EvalOptions::default()
+        };
+        match exec_with_options(input, &value, options) {
+            Ok(_) => assert_eq!(message, ""),
+            Err(err) => assert_eq!(err.to_string(), message),
+        }
+    }
+
+    #[rstest]
+    #[case("{status: 'ok', retries: 3}", "{\"status\":\"ok\",\"retries\":3}", &[("status".into(), "ok".into()), ("retries".into(), 3.into())])]
+    #[case("{v: v}", "{\"v\":v}", &[("v".into(), "k".into())])]
+    #[case("{(v): 1}", "{(v):1}", &[("k".into(), 1.into())])]
+    #[case("{(v) == 'k': 1}", "{v == \"k\":1}", &[(true.into(), 1.into())])]
+    #[case("{match: 1, None: 2}", "{\"match\":1,\"None\":2}", &[("match".into(), 1.into()), ("None".into(), 2.into())])]
+    #[case("{m.k: 1}", "{m.k:1}", &[("x".into(), 1.into())])]
+    fn test_exec_bare_map_keys(
+        #[case] input: &str,
+        #[case] expr: &str,
+        #[case] entries: &[(Value, Value)],
+    ) {
+        let ctx = || {
+            let mut ctx = Context::new();
+            ctx.set_variable("v", Value::from("k"));
+            ctx.set_variable("m", Value::Map(Map::from_iter([("k".into(), "x".into())])));
+            ctx
+        };
+        let ast = Parser::new(input).unwrap().parse_stmt().unwrap();
+        assert_eq!(ast.expr(), expr);
+        let expected = entries.iter().map(|(k, v)| (k.clone(), v.clone()));
+        let ans = exec_in(input, ctx).unwrap();
+        assert_eq!(ans, Value::Map(Map::from_iter(expected)));
+    }
+
+    #[rstest]
+    #[case("{1 + 1: 'x', 2: 'y'}", "duplicate map key 2")]
+    #[case("{2.0: 'x', 2: 'y'}", "duplicate map key 2")]
+    #[case("{[1, 'a']: 'x', [2 - 1, 'a']: 'y'}", "duplicate map key [1, 'a']")]
+    fn test_exec_duplicate_evaluated_map_keys(#[case] input: &str, #[case] message: &str) {
+        let ans = exec_with_options(input, &Value::None, EvalOptions::default()).unwrap();
+        let Value::Map(m) = ans else {
+            panic!("not a map")
+        };
+        assert_eq!(m.values().collect::<Vec<_>>(), [&Value::from("y")]);
+        let options = EvalOptions {
+            strict_map_keys: true,
+            ..EvalOptions::default()
+        };
+        let err = exec_with_options(input, &Value::None, options).unwrap_err();
+        assert_eq!(err.to_string(), message);
+        assert!(matches!(err, Error::DuplicateMapKey(_)));
+    }
+
+    fn strict_ctx() -> Context {
+        let mut ctx = Context::with_options(EvalOptions {
+            strict_references: true,
+            ..EvalOptions::default()
+        });
+        ctx.set_variable("total", 10.into());
+        ctx.set_variable("totals", Value::List(vec![]));
+        ctx.set_variable("count", 2.into());
+        ctx.set_func("tally", Arc::new(|_| Ok(1.into())));
+        ctx
+    }
+
+    #[rstest]
+    #[case(
+        "totl * 2",
+        "reference not exist: 'totl' — did you mean 'total' or 'totals'?"
+    )]
+    #[case("TOTAL", "reference not exist: 'TOTAL' — did you mean 'total'?")]
+    #[case("cont + total", "reference not exist: 'cont' — did you mean 'count'?")]
+    #[case(
+        "tota",
+        "reference not exist: 'tota' — did you mean 'total' or 'totals'?"
+    )]
+    #[case("price", "reference not exist: 'price'")]
+    #[case("x = cuont", "reference not exist: 'cuont' — did you mean 'count'?")]
+    #[case(
+        "taly(1)",
+        "inner function not registered: taly — did you mean 'tally'?"
+    )]
+    #[case(
+        "rond(total)",
+        "inner function not registered: rond — did you mean 'round' or 'ord'?"
+    )]
+    #[case(
+        "Round(total)",
+        "inner function not registered: Round — did you mean 'round'?"
+    )]
+    #[case("xyzzy(1)", "inner function not registered: xyzzy")]
+    fn test_exec_suggestions(#[case] input: &str, #[case] message: &str) {
+        assert_eq!(exec_in(input, strict_ctx).unwrap_err().to_string(), message);
+    }
+
+    #[rstest]
+    #[case("total * 2", 20.into())]
+    #[case("x = total; x + count", 12.into())]
+    #[case("m.a = 1; m", Value::Map(Map::from_iter([("a".into(), 1.into())])))]
+    #[case("tally + 1", 2.into())]
+    fn test_exec_strict_references(#[case] input: &str, #[case] output: Value) {
+        assert_eq!(exec_in(input, strict_ctx).unwrap(), output);
+        let lenient = exec_with_options("totl", &Value::None, EvalOptions::default());
+        assert_eq!(lenient.unwrap(), Value::None);
+    }
+
+    fn user_ctx() -> Context {
+        let mut ctx = Context::new();
+        let user = Map::from_iter([("name".into(), "Ada".into()), ("age".into(), 36.into())]);
+        ctx.set_variable("user", Value::Map(user));
+        ctx.set_variable("empty", Value::None);
+        ctx.set_variable("n", 5.into());
+        ctx.set_variable("ok", true.into());
+        ctx
+    }
+
+    #[rstest]
+    #[case("user?.name", "Ada".into())]
+    #[case("user?.address?.city", Value::None)]
+    #[case("user?.address.city", Value::None)]
+    #[case("empty?.address", Value::None)]
+    #[case("empty?.a.b.c", Value::None)]
+    #[case("nobody?.address?.city", Value::None)]
+    #[case("user?.age ?? 0", 36.into())]
+    #[case("user?.weight ?? 0", 0.into())]
+    #[case("user?.address?.city ?? 'unknown'", "unknown".into())]
+    #[case("empty ?? n ?? 1", 5.into())]
+    #[case("x = user?.weight ?? n * 2; x", 10.into())]
+    #[case("ok?user.name:1", "Ada".into())]
+    #[case("[ok ? user?.name : 0, empty ?? user?.age]", Value::List(vec!["Ada".into(), 36.into()]))]
+    fn test_exec_null_safe(#[case] input: &str, #[case] output: Value) {
+        assert_eq!(exec_in(input, user_ctx).unwrap(), output);
+    }
+
+    #[rstest]
+    #[case("n?.x", "n is Number, not a map")]
+    #[case("user?.name?.first", "user?.name is String, not a map")]
+    #[case("user.address?.city", "user exists but has no key 'address'")]
+    fn test_exec_null_safe_error(#[case] input: &str, #[case] message: &str) {
+        assert_eq!(exec_in(input, user_ctx).unwrap_err().to_string(), message);
+    }
+
+    #[test]
+    fn test_null_safe_assign() {
+        let err = Parser::new("user?.name = 'Bob'")
+            .unwrap()
+            .parse_stmt()
+            .unwrap_err();
+        assert!(matches!(err, Error::InvalidAssignTarget(11)));
+    }
+
+    #[test]
+    fn test_exec_cancelled() {
+        let token = Arc::new(AtomicBool::new(true));
+        let cancellable = || {
+            let mut ctx = Context::new();
+            ctx.set_cancellation(Some(token.clone()));
+            ctx
+        };
+        let input = format!("[{}]", vec!["1"; 300].join(", "));
+        let err = exec_in(&input, cancellable).unwrap_err();
+        assert!(matches!(err, Error::Cancelled));
+        assert_eq!(exec_in("1 + 2", cancellable).unwrap(), 3.into());
+    }
+
+    #[test]
+    fn test_exec_cancel_from_another_thread() {
+        let input = "n = 0;".to_string() + &" n = n + len(range(10000));".repeat(5000);
+        let ast = Parser::new(&input).unwrap().parse_stmt().unwrap();
+        let token = Arc::new(AtomicBool::new(false));
+        let mut ctx = Context::new();
+        ctx.set_cancellation(Some(token.clone()));
+        let start = Instant::now();
+        let err = std::thread::scope(|scope| {
+            let handle = scope.spawn(|| ast.exec(&mut ctx));
+            std::thread::sleep(Duration::from_millis(10));
+            token.store(true, Ordering::Relaxed);
+            handle.join().unwrap().unwrap_err()
+        });
+        assert!(matches!(err.root(), Error::Cancelled));
+        assert!(
+            start.elapsed() < Duration::from_secs(1),
+            "{:?}",
+            start.elapsed()
+        );
+        assert!(ctx.is_cancelled());
+    }
+
+    #[test]
+    fn test_function_sees_cancellation() {
+        InnerFunctionManager::new().register_with_context(
+            "cancelled",
+            Arc::new(|ctx, _| Ok(ctx.is_cancelled().into())),
+        );
+        let ans = exec_in("[cancelled(), cancel(), cancelled()]", || {
+            let token = Arc::new(AtomicBool::new(false));
+            let mut ctx = Context::new();
+            ctx.set_cancellation(Some(token.clone()));
+            ctx.set_func(
+                "cancel",
+                Arc::new(move |_| {
+                    token.store(true, Ordering::Relaxed);
+                    Ok(Value::None)
+                }),
+            );
+            ctx
+        });
+        let expected = vec![false.into(), Value::None, true.into()];
+        assert_eq!(ans.unwrap(), Value::List(expected));
+    }
+
+    fn limited_ctx() -> Context {
+        let mut ctx = Context::new();
+        ctx.set_limits(Limits {
+            max_elements: Some(100),
+            max_string_len: Some(64),
+            max_variables: Some(4),
+        });
+        ctx.set_variable("n", 3.into());
+        ctx
+    }
+
+    #[test]
+    fn test_exec_limits_doubling() {
+        let chain = |n| "a = [0]; ".to_string() + &"a = [...a, ...a]; ".repeat(n) + "a";
+        let ans = exec_in(&chain(6), limited_ctx).unwrap();
+        assert_eq!(ans, Value::List(vec![0.into(); 64]));
+        let err = exec_in(&chain(7), limited_ctx).unwrap_err();
+        assert_eq!(err.to_string(), "exceeded the limit of 100 elements");
+        assert!(matches!(
+            err,
+            Error::ResourceLimitExceeded {
+                kind: "elements",
+                limit: 100
+            }
+        ));
+    }
+
+    #[rstest]
+    #[case("s = 'abcdefgh'; s = format('{0}{0}', s); s = format('{0}{0}', s); s = format('{0}{0}', s); format('{0}{0}', s)", "chars")]
+    #[case("format('{0}{0}{0}', 'abcdefghijklmnopqrstuvwxyz')", "chars")]
+    #[case("repeat('ab', 33)", "chars")]
+    #[case("padLeft('ab', 65, '0')", "chars")]
+    #[case("repeat(0, 101)", "elements")]
+    #[case("[[1, 2], repeat(0, 98)]", "elements")]
+    #[case("{'k': repeat(0, 100)}", "elements")]
+    #[case("m = {}; m.a = repeat(0, 60); m.b = repeat(0, 60)", "elements")]
+    #[case("a = 1; b = 2; c = 3; d = 4", "variables")]
+    fn test_exec_limits(#[case] input: &str, #[case] kind: &str) {
+        let err = exec_in(input, limited_ctx).unwrap_err();
+        assert!(matches!(err, Error::ResourceLimitExceeded { kind: k, .. } if k == kind));
+    }
+
+    #[rstest]
+    #[case("s = 'abcdefgh'; s = format('{0}{0}', s); s = format('{0}{0}', s); len(format('{0}{0}', s))", 64.into())]
+    #[case("len({'k': repeat(0, 98), 'n': n})", 2.into())]
+    #[case("len(repeat('ab', 32)) + len(padRight('ab', 64))", 128.into())]
+    #[case("a = 1; b = 2; c = 3; n = n + a + b + c; n", 9.into())]
+    #[case("n > 2 ? 'big' : 'small'", "big".into())]
+    fn test_exec_within_limits(#[case] input: &str, #[case] output: Value) {
+        assert_eq!(exec_in(input, limited_ctx).unwrap(), output);
+    }
+
+    #[rstest]
+    #[case("1.5 & 1", "invalid integer")]
+    #[case("9223372036854775808 | 0", "invalid integer")]
+    #[case("~'a'", "invalid integer")]
+    #[case("1 << 64", "can not shift by 64, only 0 to 63")]
+    #[case("v >>= -1", "can not shift by -1, only 0 to 63")]
+    fn test_exec_bitwise_error(#[case] input: &str, #[case] message: &str) {
+        let err = exec_with_options(input, &Value::from(5), EvalOptions::default()).unwrap_err();
+        assert_eq!(err.to_string(), message);
+    }
+
+    #[rstest]
+    #[case("1 between 1 and 10", true.into())]
+    #[case("10 between 1 and 10", true.into())]
+    #[case("0.99 between 1 and 10", false.into())]
+    #[case("10.01 between 1 and 10", false.into())]
+    #[case("v between v and v", true.into())]
+    #[case("'b' between 'a' and 'c'", true.into())]
+    #[case("'abc' between 'ab' and 'b'", true.into())]
+    #[case("'c' between 'a' and 'b'", false.into())]
+    #[case("v + 1 between 2 * 3 and 3 * 3 && true", true.into())]
+    #[case("v not between 1 and 4", true.into())]
+    #[case("v between [1, 5]", true.into())]
+    #[case("v between 1 and 10 ? 'in' : 'out'", "in".into())]
+    #[case("and = 3; v between and and and + 2", true.into())]
+    fn test_exec_between(#[case] input: &str, #[case] output: Value) {
+        let ans = exec_with_options(input, &Value::from(5), EvalOptions::default());
+        assert_eq!(ans.unwrap(), output);
+    }
+
+    #[rstest]
+    #[case("v between 10 and 1", "lower bound 10 is greater than upper bound 1")]
+    #[case("v between 'a' and 'z'", "can not compare Number with String")]
+    #[case("v between [1]", "param invalid")]
+    #[case("v between 1", "should be list")]
+    fn test_exec_between_error(#[case] input: &str, #[case] message: &str) {
+        let err = exec_with_options(input, &Value::from(5), EvalOptions::default()).unwrap_err();
+        assert_eq!(err.to_string(), message);
+    }
+
+    #[rstest]
+    #[case("v contains 'vip'", true.into())]
+    #[case("v contains 'admin'", false.into())]
+    #[case("v not contains 'admin'", true.into())]
+    #[case("v containsAny ['admin', 'beta']", true.into())]
+    #[case("v containsAny ['admin', 'root']", false.into())]
+    #[case("v containsAll ['vip', 'beta']", true.into())]
+    #[case("v containsAll ['vip', 'root']", false.into())]
+    #[case("v containsAll 'vip'", true.into())]
+    #[case("v containsAll ['vip', 'vip', 'vip']", true.into())]
+    #[case("['vip', 'vip'] containsAll ['vip']", true.into())]
+    #[case("['vip', 'vip'] containsAny ['beta', 'beta']", false.into())]
+    #[case("v containsAll []", true.into())]
+    #[case("v containsAny []", false.into())]
+    #[case("[] contains 'vip'", false.into())]
+    #[case("[] containsAll []", true.into())]
+    #[case("[] containsAll ['vip']", false.into())]
+    #[case("[] containsAny []", false.into())]
+    #[case("[1, '1', true, null] contains '1'", true.into())]
+    #[case("[1, '1', true, null] containsAll [1.0, true, null]", true.into())]
+    #[case("[1, '1', true] containsAny ['true', 2, false]", false.into())]
+    #[case("[[1, 2], {'a': 1}] containsAll [[1, 2], {'a': 1}]", true.into())]
+    #[case("[[1, 2]] contains [1]", false.into())]
+    #[case("{'vip': 1, 'beta': 2} containsAll v", true.into())]
+    #[case("{'vip': 1} contains 1", false.into())]
+    #[case("v contains 'vip' == 'vip' in v", true.into())]
+    #[case("v containsAny ['beta'] ? 'yes' : 'no'", "yes".into())]
+    fn test_exec_contains(#[case] input: &str, #[case] output: Value) {
+        let tags = Value::from(vec!["vip".into(), "beta".into(), "vip".into()]);
+        let ans = exec_with_options(input, &tags, EvalOptions::default());
+        assert_eq!(ans.unwrap(), output);
+    }
+
+    #[rstest]
+    #[case("'vip' contains 'v'")]
+    #[case("1 containsAll [1]")]
+    #[case("null containsAny [1]")]
+    #[case("v containsAny 'vip'")]
+    fn test_exec_contains_error(#[case] input: &str) {
+        let tags = Value::from(vec!["vip".into()]);
+        let err = exec_with_options(input, &tags, EvalOptions::default()).unwrap_err();
+        assert_eq!(err.to_string(), "should be list");
+    }
+
+    fn register_deprecated() {
+        use crate::function::{Arity, FunctionMetadata};
+        InnerFunctionManager::new().register_with_metadata(
+            FunctionMetadata::new("oldDouble", Arity::Exact(1), &[("x", "Number")], "")
+                .deprecated("use x * 2"),
+            Arc::new(|_, params| Ok(Value::from(params[0].clone().decimal()? * Decimal::TWO))),
+        );
+    }
+
+    #[rstest]
+    #[case(
+        "v * 0.0000000000000001",
+        true,
+        false,
+        "W_LOSSY_FLOAT",
+        "v * 0.0000000000000001"
+    )]
+    #[case("1 + exp(-70)", true, false, "W_LOSSY_FLOAT", "exp(- 70)")]
+    #[case(
+        "v == '0.00000000000001'",
+        false,
+        false,
+        "W_MIXED_TYPE_COMPARISON",
+        "v == \"0.00000000000001\""
+    )]
+    #[case("[v] != {}", false, false, "W_MIXED_TYPE_COMPARISON", "[v] != {}")]
+    #[case(
+        "1 + oldDouble(v)",
+        false,
+        false,
+        "W_DEPRECATED_FUNCTION",
+        "oldDouble(v)"
+    )]
+    #[case("v ? 'yes' : 'no'", false, true, "W_TRUTHINESS_COERCION", "v")]
+    #[case("(v || 0) && true", false, true, "W_TRUTHINESS_COERCION", "v || 0")]
+    #[case("!v == false", false, true, "W_TRUTHINESS_COERCION", "! v")]
+    #[case(
+        "AND([true, v, 'a'])",
+        false,
+        true,
+        "W_TRUTHINESS_COERCION",
+        "AND [true,v,\"a\"]"
+    )]
+    fn test_exec_with_warnings(
+        #[case] input: &str,
+        #[case] float_math: bool,
+        #[case] coerce: bool,
+        #[case] code: &str,
+        #[case] span: &str,
+    ) {
+        register_deprecated();
+        let options = EvalOptions {
+            float_math,
+            truthiness: if coerce {
+                Truthiness::Coerce
+            } else {
+                Truthiness::Strict
+            },
+            ..EvalOptions::default()
+        };
+        let ast = Parser::new(input).unwrap().parse_stmt().unwrap();
+        let new_ctx = || {
+            let mut ctx = create_context!("v" => Value::from(Decimal::new(1, 14)));
+            ctx.set_options(options);
+            ctx
+        };
+        let mut ctx = new_ctx();
+        let (value, warnings) = ast.exec_with_warnings(&mut ctx).unwrap();
+        assert_eq!(value, ast.exec(&mut new_ctx()).unwrap());
+        assert_eq!(ctx.options(), options);
+        assert_eq!(warnings.len(), 1, "{:?}", warnings);
+        assert_eq!(warnings[0].code, code);
+        let Some(crate::token::Span(start, end)) = warnings[0].span else {
+            panic!("{:?}", warnings)
+        };
+        assert_eq!(&ast.expr()[start..end], span);
+        // Nothing is recorded unless asked for.
+        assert_eq!(ctx.take_warnings(), []);
+    }
+
+    #[rstest]
+    #[case("v * 0.5", true, false)]
+    #[case("v * 0.0000000000000001", false, false)]
+    #[case("v == 1 || v != null || null == 'a'", false, false)]
+    #[case("v > 0 ? !(v < 0) : (true || false)", false, true)]
+    fn test_exec_without_warnings(
+        #[case] input: &str,
+        #[case] float_math: bool,
+        #[case] coerce: bool,
+    ) {
+        let options = EvalOptions {
+            float_math,
+            truthiness: if coerce {
+                Truthiness::Coerce
+            } else {
+                Truthiness::Strict
+            },
+            ..EvalOptions::default()
+        };
+        let mut ctx = create_context!("v" => Value::from(Decimal::new(1, 14)));
+        ctx.set_options(options);
+        let ast = Parser::new(input).unwrap().parse_stmt().unwrap();
+        let (_, warnings) = ast.exec_with_warnings(&mut ctx).unwrap();
+        assert_eq!(warnings, []);
+    }
+
+    #[test]
+    fn test_warn_from_context_function() {
+        crate::register_context_function(
+            "legacyLookup",
+            Arc::new(|ctx, params| {
+                ctx.warn("W_LEGACY_LOOKUP", "legacyLookup reads a stale cache");
+                Ok(params.into_iter().next().unwrap_or(Value::None))
+            }),
+        );
+        let ast = Parser::new("legacyLookup(1) + legacyLookup(2)")
+            .unwrap()
+            .parse_stmt()
+            .unwrap();
+        let mut ctx = create_context!();
+        let (value, warnings) = ast.exec_with_warnings(&mut ctx).unwrap();
+        assert_eq!(value, 3.into());
+        let found: Vec<String> = warnings.iter().map(|w| w.to_string()).collect();
+        assert_eq!(
+            found,
+            [
+                "W_LEGACY_LOOKUP: legacyLookup reads a stale cache (at 0)",
+                "W_LEGACY_LOOKUP: legacyLookup reads a stale cache (at 18)",
+            ]
+        );
+        // Under the option itself they wait in the context, without spans.
+        ctx.set_options(EvalOptions {
+            collect_warnings: true,
+            ..EvalOptions::default()
+        });
+        ast.exec(&mut ctx).unwrap();
+        Vm::run(&Compiler::compile(&ast).unwrap(), &mut ctx).unwrap();
+        let warnings = ctx.take_warnings();
+        assert_eq!(warnings.len(), 4);
+        assert!(warnings.iter().all(|w| w.span.is_none()));
+        assert_eq!(ctx.take_warnings(), []);
+    }
+
+    #[test]
+    fn test_exec_list_too_long() {
+        let options = EvalOptions {
+            max_list_len: 3,
+            ..EvalOptions::default()
+        };
+        for input in ["range(4)", "repeat(v, 4)"] {
+            let err = exec_with_options(input, &Value::None, options).unwrap_err();
+            assert_eq!(err.to_string(), "list would exceed the limit of 3 elements");
+        }
+        assert!(exec_with_options("[range(3), repeat(v, 3)]", &Value::None, options).is_ok());
+    }
+
+    fn eval_as<T: TryFrom<Value, Error = Error>>(input: &str) -> Result<T> {
+        let ast = Parser::new(input).unwrap().parse_stmt().unwrap();
+        ast.eval_as(&mut create_context!("v" => 5, "name" => "ada"))
+    }
+
+    #[test]
+    fn test_eval_as() {
+        assert!(eval_as::<bool>("v > 3 ? true : false").unwrap());
+        assert_eq!(eval_as::<Decimal>("v / 2").unwrap(), Decimal::new(25, 1));
+        assert_eq!(eval_as::<String>("x = name; x").unwrap(), "ada");
+        assert_eq!(eval_as::<i64>("v * 2.0").unwrap(), 10);
+        assert_eq!(
+            eval_as::<i64>("-9223372036854775807 - 1").unwrap(),
+            i64::MIN
+        );
+        assert_eq!(eval_as::<u8>("255").unwrap(), 255);
+        assert_eq!(eval_as::<usize>("len(name)").unwrap(), 3);
+        assert_eq!(eval_as::<f64>("v / 4").unwrap(), 1.25);
+        assert_eq!(eval_as::<Vec<Value>>("[v]").unwrap(), vec![Value::from(5)]);
+        assert_eq!(eval_as::<Map>("{}").unwrap(), Map::default());
+    }
+
+    #[rstest]
+    #[case(eval_as::<bool>("v + 1"), "can not convert Number to bool")]
+    #[case(eval_as::<Decimal>("v > 1 ? name : 1"), "can not convert String to Decimal")]
+    #[case(eval_as::<String>("v = 1; None"), "can not convert None to String")]
+    #[case(eval_as::<i64>("v / 2"), "can not convert Number to i64")]
+    #[case(eval_as::<i64>("9223372036854775808"), "can not convert Number to i64")]
+    #[case(eval_as::<u32>("-1"), "can not convert Number to u32")]
+    #[case(eval_as::<f64>("[v]"), "can not convert List to f64")]
+    #[case(eval_as::<Vec<Value>>("name"), "can not convert String to Vec<Value>")]
+    #[case(eval_as::<bool>("missing(1)"), "inner function not registered: missing")]
+    fn test_eval_as_error<T: core::fmt::Debug>(#[case] ans: Result<T>, #[case] message: &str) {
+        let err = ans.unwrap_err();
+        assert_eq!(err.root().to_string(), message);
+    }
+
+    #[rstest]
+    #[case(Value::Bool(true), true)]
+    #[case(Value::Bool(false), false)]
+    #[case(Value::from(3), true)]
+    #[case(Value::from(-0.5), true)]
+    #[case(Value::from(0), false)]
+    #[case(Value::from("a"), true)]
+    #[case(Value::from(""), false)]
+    #[case(Value::List(vec![Value::None]), true)]
+    #[case(Value::List(vec![]), false)]
+    #[case(Value::Map(Map::from_iter([(Value::None, Value::None)])), true)]
+    #[case(Value::Map(Map::default()), false)]
+    #[case(Value::None, false)]
+    fn test_exec_truthiness(#[case] value: Value, #[case] truthy: bool) {
+        let coerce = EvalOptions {
+            truthiness: Truthiness::Coerce,
+            ..EvalOptions::default()
+        };
+        for (input, output) in [
+            ("v ? 1 : 2", Value::from(if truthy { 1 } else { 2 })),
+            ("v && true", truthy.into()),
+            ("v || false", truthy.into()),
+            ("!v", (!truthy).into()),
+            ("not v", (!truthy).into()),
+            ("AND[v, true]", truthy.into()),
+            ("OR[v, false]", truthy.into()),
+        ] {
+            let ans = exec_with_options(input, &value, coerce).unwrap();
+            assert_eq!(ans, output, "{} with {:?}", input, value);
+
+            let ans = exec_with_options(input, &value, EvalOptions::default());
+            match value {
+                Value::Bool(_) => assert_eq!(ans.unwrap(), output),
+                _ => assert_eq!(
+                    ans.unwrap_err().to_string(),
+                    format!("should be bool, got {}", value.type_name())
+                ),
+            }
+        }
     }
 
     #[rstest]
     #[case("5", "5")]
     #[case(" true ", "true")]
+    #[case(" None ", "null")]
+    #[case("[1, null]", "[1,null]")]
     #[case(" True ", "true")]
     #[case(" false ", "false")]
     #[case(" False ", "false")]
@@ -1031,12 +4138,196 @@ mod tests {
     #[case("a()++ * 2-7", "a() ++ * 2 - 7")]
     #[case("2++ + 3", "2 ++ + 3")]
     #[case("a()++ * 2-7", "a() ++ * 2 - 7")]
+    #[case(
+        "match  a+1 { 'x' : 1, 2: b ? 3 : 4 , _ : 0 }",
+        "match a + 1 {\"x\":1,2:b ? 3 : 4,_:0}"
+    )]
+    #[case("match a {}", "match a {}")]
+    #[case("match (a) {1: 2}", "match a {1:2}")]
+    #[case("match match {1: match b {2: 3}}", "match match {1:match b {2:3}}")]
+    #[case("[...defaults, 42, ...overrides]", "[...defaults,42,...overrides]")]
+    #[case("max(... scores)", "max(...scores)")]
+    #[case("[1, 2,]", "[1,2]")]
+    #[case("f(a, b,)", "f(a,b)")]
+    #[case("{'a': 1,}", "{\"a\":1}")]
+    #[case("f(...a + b, ...[1])", "f(...a + b,...[1])")]
+    #[case("x between 1 and a + 1", "x between [1,a + 1]")]
+    #[case("'say \"hi\"'", "'say \"hi\"'")]
+    #[case("\"it's\" + '''\"it's\"'''", "\"it's\" + '''\"it's\"'''")]
+    #[case("\"\"\"'''\"x\"\"\"", "\"\"\"'''\"x\"\"\"")]
+    #[case("(a ? 1 : 2) + 3", "(a ? 1 : 2) + 3")]
+    #[case("!(a && b)", "! (a && b)")]
+    #[case("-(a ? 1 : 2)", "- (a ? 1 : 2)")]
+    #[case("(a ? b : c) ? d : e", "(a ? b : c) ? d : e")]
+    #[case("a - (b - c)", "a - (b - c)")]
+    #[case("(a - b) - c", "a - b - c")]
     fn test_expression_expr(#[case] input: &str, #[case] output: &str) {
-        init();
         let parser = Parser::new(input);
         assert!(parser.is_ok());
         let expr_ast = parser.unwrap().parse_expression();
         assert!(expr_ast.is_ok());
-        assert_eq!(expr_ast.unwrap().expr(), output);
+        let expr_ast = expr_ast.unwrap();
+        assert_eq!(expr_ast.expr(), output);
+        assert_eq!(expr_ast.to_string(), output);
+    }
+
+    #[rstest]
+    #[case("a + 1", &["a + 1"], false)]
+    #[case("  a = 'x;y';\n b = \"1;2\"  ;\n\n c = a + b;", &["a = 'x;y'", "b = \"1;2\"", "c = a + b"], false)]
+    #[case("f('a;',\n b); [1, ';'];x", &["f('a;',\n b)", "[1, ';']", "x"], false)]
+    #[case("x = '''one;\ntwo'''; match x {'a;': 1, _: 2}", &["x = '''one;\ntwo'''", "match x {'a;': 1, _: 2}"], false)]
+    #[case("a = 1\nb = 2", &["a = 1", "b = 2"], false)]
+    #[case("a = 1; b = (2 +\n 3", &["a = 1", "b = (2 +\n 3"], true)]
+    #[case("a = 1; b = 'x;\ny", &["a = 1", "b = 'x;\ny"], true)]
+    #[case("a = 1;  'unterminated", &["a = 1", "'unterminated"], true)]
+    #[case("a; b +", &["a", "b +"], true)]
+    #[case("", &[], false)]
+    fn test_statement_spans(
+        #[case] input: &str,
+        #[case] statements: &[&str],
+        #[case] unterminated: bool,
+    ) {
+        let spans = Parser::statement_spans(input).unwrap();
+        let texts: Vec<&str> = spans.iter().map(|s| &input[s.span.0..s.span.1]).collect();
+        assert_eq!(texts, statements);
+        for (i, span) in spans.iter().enumerate() {
+            assert_eq!(
+                span.unterminated,
+                unterminated && i + 1 == spans.len(),
+                "{}",
+                i
+            );
+        }
+        // Complete statements parse on their own.
+        for (span, text) in spans.iter().zip(texts) {
+            let parsed = Parser::new(text).and_then(|mut parser| parser.parse_stmt());
+            assert_eq!(parsed.is_ok(), !span.unterminated);
+        }
+    }
+
+    #[test]
+    fn test_statement_spans_with_options() {
+        let input = "total = price *\n  qty\nlabel = 'a;b'\n-total";
+        let options = ParseOptions {
+            newline_statements: true,
+            ..Default::default()
+        };
+        let spans = Parser::statement_spans_with_options(input, options).unwrap();
+        let texts: Vec<&str> = spans.iter().map(|s| &input[s.span.0..s.span.1]).collect();
+        assert_eq!(texts, ["total = price *\n  qty", "label = 'a;b'", "-total"]);
+        // Without the option the line breaks are whitespace.
+        let spans = Parser::statement_spans(input).unwrap();
+        assert_eq!(spans.len(), 2);
+    }
+
+    #[rstest]
+    #[case("a + ); b")]
+    #[case("a = 1; 2 = b")]
+    #[case("a; b c")]
+    fn test_statement_spans_error(#[case] input: &str) {
+        let err = Parser::statement_spans(input).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            Parser::new(input)
+                .unwrap()
+                .parse_stmt()
+                .unwrap_err()
+                .to_string()
+        );
+    }
+
+    #[test]
+    fn test_dump() {
+        let ast = Parser::new("f(a) + 1").unwrap().parse_stmt().unwrap();
+        assert_eq!(
+            ast.dump(),
+            "Binary AST: Op: +, Lhs: Function AST: name: f, params: [Reference AST: reference: a,], Rhs: Literal AST: Number: 1"
+        );
+    }
+
+    #[rstest]
+    #[case("match status { 'new': 1, 'paid': 2, _: 0 }")]
+    #[case("[match a {1: 'a'}, match - 1]")]
+    #[case("[...a + b, 1, ...[2, ...c]]")]
+    #[case("sum(...range(3), ...[])")]
+    #[case("x + 1 between 2 * y and z && ok")]
+    #[case("user?.address?.city ?? 'n/a'")]
+    #[case("'''it's \"quoted\"'''")]
+    #[case("'''first\n  'second'\n\"third\"'''")]
+    #[case("\"\"\"line one\r\nline two\r\n\"\"\" + '''\r\n'''")]
+    #[case("'''''' + \"\"\"\"\"\" + '''\"'''")]
+    #[case("\"\"\"'''\"\"\" + '''\"\"\"'''")]
+    #[case("\"\"\"ends with '\"\"\"")]
+    #[case("{status: 'ok', (code): 1, code.x: 2, match: 3}")]
+    #[case("(a ? 1 : 2) + 3")]
+    #[case("!(a && b)")]
+    #[case("a || (b ? 1 : 2) && !(c || d)")]
+    #[case("a ? (b ? 1 : 2) : c ? 3 : 4")]
+    #[case("(-a)++ - -(b++)")]
+    #[case("a - (b - c) * (d / (e * f))")]
+    #[case("a not in [1] && (x = (y ? 1 : 2))")]
+    #[case("`order total` * 1.2 + `in` - `null`")]
+    #[case("``a`b`` + `a``b` + `my f`(`x y`)")]
+    #[case("{(`a b`): 1, `c d`: `true`}")]
+    fn test_expression_expr_round_trip(#[case] input: &str) {
+        let ast = Parser::new(input).unwrap().parse_stmt().unwrap();
+        let output = ast.expr();
+        let again = Parser::new(&output).unwrap().parse_stmt().unwrap();
+        assert_eq!(again, ast);
+    }
+
+    #[test]
+    fn test_describe_match() {
+        let input = "match status { 'new': 1, _: 0 }";
+        let ast = Parser::new(input).unwrap().parse_stmt().unwrap();
+        assert_eq!(ast.describe(), "match status {\"new\":1,_:0}");
+    }
+
+    fn parse(input: &str) -> ExprAST<'_> {
+        Parser::new(input).unwrap().parse_stmt().unwrap()
+    }
+
+    fn hash(expr: &ExprAST) -> u64 {
+        use std::hash::{Hash, Hasher};
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        expr.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    #[rstest]
+    #[case("a+1", "  a   +\n1 ")]
+    #[case("f(a,[1,2],{'k':b})", "f( a , [ 1 , 2, ] , { 'k' : b } )")]
+    #[case("x=1;x>0?'y':'n'", "x = 1;\n x > 0 ? 'y' : 'n'")]
+    #[case("match s {1: 'a', _: 'b'}", "match s{1:'a',_:'b'}")]
+    #[case("x between 1 and 10", "x between [1, 10]")]
+    #[case("1.0", "1.00")]
+    #[case("[1.50, 2]", "[1.5, 2.000]")]
+    fn test_fingerprint_same(#[case] a: &str, #[case] b: &str) {
+        let (a, b) = (parse(a), parse(b));
+        assert_eq!(a, b);
+        assert_eq!(a.fingerprint(), b.fingerprint());
+        assert_eq!(hash(&a), hash(&b));
+    }
+
+    #[rstest]
+    #[case("a+1", "a+2")]
+    #[case("a+1", "a-1")]
+    #[case("a+1", "b+1")]
+    #[case("f('x')", "f('y')")]
+    #[case("[1, 2]", "[[1, 2]]")]
+    #[case("[1, [2]]", "[[1], 2]")]
+    #[case("1", "'1'")]
+    #[case("true", "1")]
+    #[case("f(a)", "[a]")]
+    #[case("match s {1: 'a'}", "match s {1: 'a', _: None}")]
+    fn test_fingerprint_different(#[case] a: &str, #[case] b: &str) {
+        assert_ne!(parse(a).fingerprint(), parse(b).fingerprint());
+    }
+
+    #[test]
+    fn test_fingerprint_stable() {
+        // Pinned so that an accidental change to the encoding fails here.
+        assert_eq!(parse("a + 1").fingerprint(), 12486876856292848044);
+        assert_eq!(parse("f(x, [1.5, 'a'])").fingerprint(), 5886173454694504266);
     }
 }
@@ -0,0 +1,199 @@
+// Clocks and environments need an OS, so without `std` contexts carry no
+// providers and `now()` and `env()` aren't registered.
+#[cfg(feature = "std")]
+use std::collections::HashMap;
+#[cfg(feature = "std")]
+use std::sync::Arc;
+#[cfg(feature = "std")]
+use std::time::SystemTime;
+
+/// Where `now()` reads the current time from, see
+/// [`crate::Context::set_clock`].
+#[cfg(feature = "std")]
+pub trait ClockProvider: Send + Sync {
+    fn now(&self) -> SystemTime;
+}
+
+/// Where `env(name)` reads environment variables from, see
+/// [`crate::Context::set_env`].
+#[cfg(feature = "std")]
+pub trait EnvProvider: Send + Sync {
+    fn var(&self, name: &str) -> Option<String>;
+}
+
+/// The system clock, used unless a context has its own.
+#[cfg(feature = "std")]
+pub struct SystemClock;
+
+#[cfg(feature = "std")]
+impl ClockProvider for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// The environment of the process, used unless a context has its own.
+#[cfg(feature = "std")]
+pub struct ProcessEnv;
+
+#[cfg(feature = "std")]
+impl EnvProvider for ProcessEnv {
+    fn var(&self, name: &str) -> Option<String> {
+        std::env::var(name).ok()
+    }
+}
+
+/// A clock stopped at one instant.
+#[cfg(feature = "std")]
+pub struct FixedClock(pub SystemTime);
+
+#[cfg(feature = "std")]
+impl ClockProvider for FixedClock {
+    fn now(&self) -> SystemTime {
+        self.0
+    }
+}
+
+/// A fixed set of environment variables.
+#[cfg(feature = "std")]
+pub struct FixedEnv(pub HashMap<String, String>);
+
+#[cfg(feature = "std")]
+impl EnvProvider for FixedEnv {
+    fn var(&self, name: &str) -> Option<String> {
+        self.0.get(name).cloned()
+    }
+}
+
+// Contexts only allocate providers the host sets, the defaults are shared.
+#[derive(Clone, Default)]
+pub(crate) struct Providers {
+    #[cfg(feature = "std")]
+    pub(crate) clock: Option<Arc<dyn ClockProvider>>,
+    #[cfg(feature = "std")]
+    pub(crate) env: Option<Arc<dyn EnvProvider>>,
+    pub(crate) rng: Option<crate::random::Rng>,
+}
+
+#[cfg(feature = "std")]
+impl Providers {
+    pub(crate) fn clock(&self) -> &dyn ClockProvider {
+        self.clock.as_deref().unwrap_or(&SystemClock)
+    }
+
+    pub(crate) fn env(&self) -> &dyn EnvProvider {
+        self.env.as_deref().unwrap_or(&ProcessEnv)
+    }
+}
+
+#[cfg(feature = "builtin-env")]
+impl crate::function::InnerFunctionManager {
+    pub(crate) fn init_providers(&mut self) {
+        use crate::error::Error;
+        use crate::function::{Arity, FunctionMetadata};
+        use crate::value::Value;
+        use std::time::UNIX_EPOCH;
+        // Milliseconds since the Unix epoch, negative before it.
+        self.namespaced("date", |manager| {
+            manager.register_with_metadata(
+                FunctionMetadata::new(
+                    "now",
+                    Arity::Exact(0),
+                    &[],
+                    "The current time in milliseconds since the Unix epoch.",
+                ),
+                Arc::new(|ctx, params| {
+                    if !params.is_empty() {
+                        return Err(Error::ParamInvalid());
+                    }
+                    let millis = match ctx.clock().now().duration_since(UNIX_EPOCH) {
+                        Ok(since) => since.as_millis() as i128,
+                        Err(err) => -(err.duration().as_millis() as i128),
+                    };
+                    Ok(Value::from(millis))
+                }),
+            );
+        });
+
+        // Unset variables are `None`.
+        self.register_with_metadata(
+            FunctionMetadata::new(
+                "env",
+                Arity::Exact(1),
+                &[("name", "String")],
+                "The environment variable called name, or None if it isn't set.",
+            ),
+            Arc::new(|ctx, params| {
+                if params.len() != 1 {
+                    return Err(Error::ParamInvalid());
+                }
+                let name = params.into_iter().next().unwrap().string()?;
+                Ok(ctx.env().var(&name).map_or(Value::None, Value::String))
+            }),
+        );
+    }
+}
+
+#[cfg(all(test, feature = "builtin-env"))]
+mod tests {
+    use super::{FixedClock, FixedEnv};
+    use crate::{create_context, execute, Context, Error, Value};
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn fixed() -> Context {
+        let mut ctx = create_context!();
+        ctx.set_clock(Arc::new(FixedClock(
+            UNIX_EPOCH + Duration::from_millis(1_700_000_000_123),
+        )));
+        ctx.set_env(Arc::new(FixedEnv(HashMap::from([(
+            "REGION".to_string(),
+            "eu-west-1".to_string(),
+        )]))));
+        ctx
+    }
+
+    #[test]
+    fn test_now_frozen() {
+        assert_eq!(
+            execute("now()", fixed()).unwrap(),
+            Value::from(1_700_000_000_123i64)
+        );
+        assert_eq!(execute("now() == now()", fixed()).unwrap(), true.into());
+        assert_eq!(execute("now() - now()", fixed()).unwrap(), 0.into());
+    }
+
+    #[test]
+    fn test_now_system() {
+        let ans = execute("now()", create_context!())
+            .unwrap()
+            .integer()
+            .unwrap();
+        // Later than 2023-11-14.
+        assert!(ans > 1_700_000_000_000);
+    }
+
+    #[test]
+    fn test_env() {
+        assert_eq!(
+            execute("env('REGION')", fixed()).unwrap(),
+            "eu-west-1".into()
+        );
+        assert_eq!(execute("env('MISSING')", fixed()).unwrap(), Value::None);
+        assert_eq!(
+            execute("coalesce(env('MISSING'), 'local')", fixed()).unwrap(),
+            "local".into()
+        );
+    }
+
+    #[test]
+    fn test_provider_errors() {
+        for input in ["now(1)", "env()", "env('a', 'b')"] {
+            let err = execute(input, fixed()).unwrap_err();
+            assert!(matches!(err.root(), Error::ParamInvalid()), "{}", input);
+        }
+        let err = execute("env(1)", fixed()).unwrap_err();
+        assert!(matches!(err.root(), Error::ShouldBeString()));
+    }
+}
@@ -0,0 +1,296 @@
+// Only the seed is kept without `builtin-math`, which has the functions.
+#![cfg_attr(not(feature = "builtin-math"), allow(dead_code))]
+
+use crate::compat::{Fnv, Mutex};
+use crate::define::Result;
+#[cfg(any(feature = "builtin-math", not(feature = "std")))]
+use crate::error::Error;
+use crate::value::Value;
+use alloc::string::ToString;
+use alloc::sync::Arc;
+use core::hash::Hasher;
+
+const GOLDEN_GAMMA: u64 = 0x9e37_79b9_7f4a_7c15;
+
+// The SplitMix64 finalizer, which spreads every input bit over the output.
+fn mix(mut z: u64) -> u64 {
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+// A SplitMix64 generator, the one `random()` and `randInt()` draw from once
+// `Context::set_rng_seed` is called. Functions only see `&Context`, hence
+// the lock. A clone continues from the same state on its own, so a layered
+// context replays what its base would draw next, while `share` draws from
+// the same state.
+pub(crate) struct Rng(Arc<Mutex<u64>>);
+
+impl Rng {
+    pub(crate) fn new(seed: u64) -> Self {
+        Rng(Arc::new(Mutex::new(seed)))
+    }
+
+    pub(crate) fn share(&self) -> Self {
+        Rng(self.0.clone())
+    }
+
+    pub(crate) fn next(&self) -> u64 {
+        let mut state = self.0.lock().unwrap();
+        *state = state.wrapping_add(GOLDEN_GAMMA);
+        mix(*state)
+    }
+}
+
+impl Clone for Rng {
+    fn clone(&self) -> Self {
+        Rng::new(*self.0.lock().unwrap())
+    }
+}
+
+// A draw for a context without a seed. `RandomState` is keyed from the OS
+// and gives every instance new keys.
+#[cfg(feature = "std")]
+pub(crate) fn entropy() -> Result<u64> {
+    use std::hash::BuildHasher;
+    Ok(std::collections::hash_map::RandomState::new()
+        .build_hasher()
+        .finish())
+}
+
+#[cfg(not(feature = "std"))]
+pub(crate) fn entropy() -> Result<u64> {
+    Err(Error::NoRngSeed)
+}
+
+// A uniform integer below `bound`, or any one when `bound` is 0, rejecting
+// the draws past the last whole multiple of `bound` so there's no bias.
+fn below(mut next: impl FnMut() -> Result<u64>, bound: u64) -> Result<u64> {
+    if bound == 0 {
+        return next();
+    }
+    let zone = u64::MAX - u64::MAX % bound;
+    loop {
+        let draw = next()?;
+        if draw < zone {
+            return Ok(draw % bound);
+        }
+    }
+}
+
+// The bucket `hashBucket(value, buckets)` puts `value` in: the 64-bit
+// FNV-1a hash of its text, mixed with the SplitMix64 finalizer, modulo
+// `buckets`. The text of a string is the string, that of a number its
+// shortest form, so `1.0` and `1` agree, and that of anything else its
+// literal. It must not change between runs, releases or platforms.
+pub(crate) fn hash_bucket(value: &Value, buckets: u64) -> u64 {
+    let text = match value {
+        Value::String(s) => s.clone(),
+        Value::Number(n) => n.normalize().to_string(),
+        other => other.to_string(),
+    };
+    let mut hasher = Fnv::default();
+    hasher.write(text.as_bytes());
+    mix(hasher.finish()) % buckets
+}
+
+#[cfg(feature = "builtin-math")]
+impl crate::function::InnerFunctionManager {
+    pub(crate) fn init_random(&mut self) {
+        use crate::function::{Arity, FunctionMetadata};
+        use rust_decimal::Decimal;
+        // Multiples of 10^-15, so the values print short and none rounds
+        // up to 1.
+        self.register_with_metadata(
+            FunctionMetadata::new(
+                "random",
+                Arity::Exact(0),
+                &[],
+                "A random number from 0 up to but excluding 1, repeatable with a seed set on the context.",
+            ),
+            Arc::new(|ctx, params| {
+                if !params.is_empty() {
+                    return Err(Error::ParamInvalid());
+                }
+                let draw = below(|| ctx.next_random(), 1_000_000_000_000_000)?;
+                Ok(Value::Number(Decimal::new(draw as i64, 15).normalize()))
+            }),
+        );
+
+        self.register_with_metadata(
+            FunctionMetadata::new(
+                "randInt",
+                Arity::Exact(2),
+                &[("min", "Number"), ("max", "Number")],
+                "A random integer from min to max, both included, repeatable with a seed set on the context.",
+            ),
+            Arc::new(|ctx, params| {
+                let [min, max] = <[Value; 2]>::try_from(params).map_err(|_| Error::ParamInvalid())?;
+                let (min, max) = (min.integer()?, max.integer()?);
+                if min > max {
+                    return Err(Error::ParamInvalid());
+                }
+                // The span wraps to 0 for the whole range of an i64.
+                let span = (max.wrapping_sub(min) as u64).wrapping_add(1);
+                let draw = below(|| ctx.next_random(), span)?;
+                Ok(Value::from(min.wrapping_add(draw as i64)))
+            }),
+        );
+
+        self.builtin(
+            FunctionMetadata::new(
+                "hashBucket",
+                Arity::Exact(2),
+                &[("value", "Any"), ("buckets", "Number")],
+                "A stable bucket for value from 0 up to but excluding buckets, the same on every run, e.g. for A/B tests.",
+            )
+            .deterministic(),
+            Arc::new(|params| {
+                let [value, buckets] =
+                    <[Value; 2]>::try_from(params).map_err(|_| Error::ParamInvalid())?;
+                let buckets = buckets.integer()?;
+                if buckets <= 0 {
+                    return Err(Error::ParamInvalid());
+                }
+                Ok(Value::from(hash_bucket(&value, buckets as u64) as i64))
+            }),
+        );
+    }
+}
+
+#[cfg(all(test, feature = "default"))]
+mod tests {
+    use super::{hash_bucket, Rng};
+    use crate::{execute, parse_expression, Context, Error, Value};
+    use rstest::rstest;
+
+    fn seeded(seed: u64) -> Context {
+        let mut ctx = Context::new();
+        ctx.set_rng_seed(seed);
+        ctx
+    }
+
+    fn draws(input: &str, ctx: &mut Context) -> Vec<Value> {
+        let ast = parse_expression(input).unwrap();
+        (0..20).map(|_| ast.exec(ctx).unwrap()).collect()
+    }
+
+    #[test]
+    fn test_splitmix64() {
+        // The first outputs of SplitMix64 seeded with 1234567, from the
+        // reference implementation.
+        let rng = Rng::new(1234567);
+        assert_eq!(
+            [rng.next(), rng.next(), rng.next()],
+            [
+                6457827717110365317,
+                3203168211198807973,
+                9817491932198370423
+            ]
+        );
+    }
+
+    #[rstest]
+    #[case("random()")]
+    #[case("randInt(1, 6)")]
+    #[case("[random(), randInt(-5, 5), randInt(0, 1000000)]")]
+    fn test_seeded_repeatable(#[case] input: &str) {
+        let first = draws(input, &mut seeded(42));
+        assert_eq!(first, draws(input, &mut seeded(42)));
+        assert_ne!(first, draws(input, &mut seeded(43)));
+    }
+
+    #[test]
+    fn test_layered_continues_base() {
+        let base = seeded(7);
+        let mut copy = seeded(7);
+        let expected = draws("random()", &mut copy);
+        let mut layered = Context::with_base(base.into());
+        assert_eq!(draws("random()", &mut layered), expected);
+    }
+
+    #[test]
+    fn test_random_ranges() {
+        let mut ctx = seeded(1);
+        for value in draws("random()", &mut ctx) {
+            let value = value.decimal().unwrap();
+            assert!(value >= 0.into() && value < 1.into(), "{}", value);
+        }
+        let mut seen = [false; 6];
+        for _ in 0..50 {
+            for value in draws("randInt(1, 6)", &mut ctx) {
+                seen[value.integer().unwrap() as usize - 1] = true;
+            }
+        }
+        assert!(seen.iter().all(|seen| *seen));
+        let ans = execute("randInt(3, 3)", seeded(1)).unwrap();
+        assert_eq!(ans, 3.into());
+        let input = "randInt(-9223372036854775807 - 1, 9223372036854775807)";
+        assert!(execute(input, seeded(1)).is_ok());
+    }
+
+    #[test]
+    fn test_unseeded() {
+        let ast = parse_expression("random()").unwrap();
+        let mut ctx = Context::new();
+        let values: Vec<Value> = (0..10).map(|_| ast.exec(&mut ctx).unwrap()).collect();
+        assert!(values.iter().any(|value| *value != values[0]));
+    }
+
+    #[rstest]
+    #[case("random(1)")]
+    #[case("randInt(1)")]
+    #[case("randInt(2, 1)")]
+    #[case("randInt(1.5, 2)")]
+    #[case("hashBucket('a', 0)")]
+    #[case("hashBucket('a', -1)")]
+    #[case("hashBucket('a')")]
+    fn test_random_errors(#[case] input: &str) {
+        let err = execute(input, seeded(1)).unwrap_err();
+        assert!(
+            matches!(err.root(), Error::ParamInvalid() | Error::InvalidInteger),
+            "{:?}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_hash_bucket_stable() {
+        // Pinned, since buckets must not move between releases.
+        assert_eq!(hash_bucket(&Value::from("user-1"), 100), 24);
+        assert_eq!(hash_bucket(&Value::from("user-2"), 100), 95);
+        assert_eq!(hash_bucket(&Value::from(42), 1000), 770);
+        assert_eq!(
+            hash_bucket(&Value::from(1), 10),
+            execute("hashBucket(1.0, 10)", Context::new())
+                .unwrap()
+                .integer()
+                .unwrap() as u64
+        );
+        assert_eq!(
+            execute("hashBucket(user_id, 100) < 20", {
+                let mut ctx = Context::new();
+                ctx.set_variable("user_id", Value::from("user-1"));
+                ctx
+            })
+            .unwrap(),
+            false.into()
+        );
+    }
+
+    #[test]
+    fn test_hash_bucket_distribution() {
+        let mut counts = [0usize; 100];
+        for id in 0..10_000 {
+            counts[hash_bucket(&Value::from(format!("user-{}", id)), 100) as usize] += 1;
+        }
+        // 100 per bucket on average; a fair hash stays well within this.
+        assert!(
+            counts.iter().all(|count| (60..=140).contains(count)),
+            "{:?}",
+            counts
+        );
+        let under_20: usize = counts[..20].iter().sum();
+        assert!((1_800..=2_200).contains(&under_20), "{}", under_20);
+    }
+}
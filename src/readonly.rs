@@ -0,0 +1,154 @@
+use crate::context::Context;
+use crate::define::Result;
+use crate::error::Error;
+use crate::operator::InfixOpManager;
+use crate::parser::ExprAST;
+use crate::value::Value;
+use alloc::vec;
+
+impl<'a> ExprAST<'a> {
+    /// Evaluates the expression like [`ExprAST::exec`], but only borrows the
+    /// context, so one context behind an `Arc` can serve many threads at
+    /// once. The tree is scanned first and an expression that assigns, like
+    /// `x = 1` or `x += 1`, fails with [`Error::ReadonlyAssignment`] before
+    /// anything is evaluated. Memoization and the cancellation token work as
+    /// with `exec`, each call keeping its own cache and count.
+    pub fn exec_readonly(&self, ctx: &Context) -> Result<Value> {
+        if let Some(op) = self.setter() {
+            return Err(Error::ReadonlyAssignment(op.into()));
+        }
+        self.exec(&mut ctx.view())
+    }
+
+    // The first assigning operator in the tree, if any.
+    pub(crate) fn setter(&self) -> Option<&'a str> {
+        let infix = InfixOpManager::new();
+        let mut stack = vec![self];
+        while let Some(expr) = stack.pop() {
+            if let ExprAST::Binary(op, ..) = expr {
+                if infix.is_setter(op) {
+                    return Some(op);
+                }
+            }
+            stack.extend(expr.children().into_iter().rev());
+        }
+        None
+    }
+}
+
+#[cfg(all(test, feature = "default"))]
+mod tests {
+    use crate::{create_context, parse_expression, Context, Error, EvalOptions, Value};
+    use alloc::sync::Arc;
+    use rstest::rstest;
+    use std::thread;
+
+    fn context() -> Context {
+        create_context!(
+            "a" => 3,
+            "b" => 4,
+            "name" => "ada",
+            "user" => Value::Map([("age".into(), 36.into())].into_iter().collect())
+        )
+    }
+
+    #[rstest]
+    #[case("a + b * 2", 11.into())]
+    #[case("user.age >= 18 ? 'adult' : 'minor'", "adult".into())]
+    #[case("capitalize(name)", "Ada".into())]
+    #[case("a++ == 4 && b in [a, 4]", true.into())]
+    #[case("match a {3: 'three', _: 'other'}", "three".into())]
+    fn test_exec_readonly(#[case] input: &str, #[case] expected: Value) {
+        let ctx = context();
+        let ast = parse_expression(input).unwrap();
+        assert_eq!(ast.exec_readonly(&ctx).unwrap(), expected);
+        assert_eq!(ast.exec(&mut context()).unwrap(), expected);
+    }
+
+    #[rstest]
+    #[case("a = 1", "=")]
+    #[case("a + (b += 1)", "+=")]
+    #[case("c = 1; d = 2", "=")]
+    #[case("max(a, b) > 1 ? user.age = 1 : 0", "=")]
+    fn test_exec_readonly_assigns(#[case] input: &str, #[case] op: &str) {
+        let ctx = context();
+        let err = parse_expression(input)
+            .unwrap()
+            .exec_readonly(&ctx)
+            .unwrap_err();
+        assert!(
+            matches!(&err, Error::ReadonlyAssignment(found) if found == op),
+            "{:?}",
+            err
+        );
+        assert_eq!(ctx.value("a").unwrap(), 3.into());
+        assert_eq!(ctx.value("c").unwrap(), Value::None);
+    }
+
+    #[test]
+    fn test_exec_readonly_errors() {
+        let ctx = context();
+        let err = parse_expression("a + missing(1)")
+            .unwrap()
+            .exec_readonly(&ctx)
+            .unwrap_err();
+        assert!(
+            matches!(err.root(), Error::InnerFunctionNotRegistered(..)),
+            "{:?}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_exec_readonly_memoize() {
+        let mut ctx = context();
+        ctx.set_options(EvalOptions {
+            memoize: true,
+            ..EvalOptions::default()
+        });
+        let ast = parse_expression("max(a, b) + max(a, b)").unwrap();
+        assert_eq!(ast.exec_readonly(&ctx).unwrap(), 8.into());
+        assert_eq!(ast.exec_readonly(&ctx).unwrap(), 8.into());
+    }
+
+    #[test]
+    fn test_exec_readonly_seeded() {
+        // Draws go on from one call to the next, as with `exec`.
+        let mut ctx = Context::new();
+        ctx.set_rng_seed(9);
+        let mut copy = Context::new();
+        copy.set_rng_seed(9);
+        let ast = parse_expression("random()").unwrap();
+        for _ in 0..3 {
+            assert_eq!(
+                ast.exec_readonly(&ctx).unwrap(),
+                ast.exec(&mut copy).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn test_exec_readonly_threads() {
+        let ctx = Arc::new(context());
+        let handles: Vec<_> = (0..8)
+            .map(|i| {
+                let ctx = ctx.clone();
+                thread::spawn(move || {
+                    let input = format!("a * {} + b", i);
+                    let ast = parse_expression(&input).unwrap();
+                    (0..100)
+                        .map(|_| ast.exec_readonly(&ctx).unwrap())
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        for (i, handle) in handles.into_iter().enumerate() {
+            let expected = Value::from(3 * i as i64 + 4);
+            assert!(handle
+                .join()
+                .unwrap()
+                .iter()
+                .all(|value| *value == expected));
+        }
+    }
+}
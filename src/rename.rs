@@ -0,0 +1,110 @@
+use crate::compat::HashMap;
+use crate::parser::ExprAST;
+use alloc::string::String;
+
+impl<'a> ExprAST<'a> {
+    /// Renames every reference that is a key of `map` to its value, and
+    /// returns how many were renamed. Assignment targets are references too,
+    /// so `price = price * 2` is renamed on both sides. Names are matched
+    /// whole: `user.name` is only renamed if it's a key itself, not through
+    /// `user`, and string literals are never touched.
+    pub fn rename_references(&mut self, map: &'a HashMap<String, String>) -> usize {
+        let mut count = 0;
+        self.walk_mut(|expr| {
+            if let ExprAST::Reference(name) = expr {
+                if let Some(new) = map.get(*name) {
+                    *name = new;
+                    count += 1;
+                }
+            }
+        });
+        count
+    }
+
+    /// Renames the functions called like [`ExprAST::rename_references`]
+    /// renames references. A reference to a function of the context, like
+    /// `config` for `config()`, is a reference and isn't renamed.
+    pub fn rename_functions(&mut self, map: &'a HashMap<String, String>) -> usize {
+        let mut count = 0;
+        self.walk_mut(|expr| {
+            if let ExprAST::Function(name, _) = expr {
+                if let Some(new) = map.get(*name) {
+                    *name = new;
+                    count += 1;
+                }
+            }
+        });
+        count
+    }
+}
+
+#[cfg(all(test, feature = "default"))]
+mod tests {
+    use crate::compat::HashMap;
+    use crate::{create_context, parse_expression, Value};
+    use rstest::rstest;
+
+    fn renames(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(from, to)| (from.to_string(), to.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_rename_chain() {
+        let map = renames(&[("tenantA_price", "price"), ("tenantA_qty", "qty")]);
+        let mut ast = parse_expression(
+            "tenantA_price = tenantA_price * 2; tenantA_price += 1; tenantA_price * tenantA_qty",
+        )
+        .unwrap();
+        assert_eq!(ast.rename_references(&map), 5);
+        assert_eq!(ast.expr(), "price = price * 2;price += 1;price * qty");
+        let mut ctx = create_context!("price" => 10, "qty" => 3);
+        assert_eq!(ast.exec(&mut ctx).unwrap(), 63.into());
+        assert_eq!(ctx.get_variable("price"), Some(21.into()));
+    }
+
+    #[rstest]
+    #[case(
+        "format('tenantA_price: {}', tenantA_price)",
+        1,
+        "format(\"tenantA_price: {}\",price)"
+    )]
+    #[case(
+        "'tenantA_price' beginWith 'tenant'",
+        0,
+        "\"tenantA_price\" beginWith \"tenant\""
+    )]
+    #[case(
+        "tenantA_prices + tenantA_price.amount",
+        0,
+        "tenantA_prices + tenantA_price.amount"
+    )]
+    #[case("{(tenantA_price): 'tenantA_price'}", 1, "{(price):\"tenantA_price\"}")]
+    #[case(
+        "match x { tenantA_price: 1, _: tenantA_price }",
+        2,
+        "match x {price:1,_:price}"
+    )]
+    #[case("tenantA_price(1)", 0, "tenantA_price(1)")]
+    fn test_rename_references(#[case] input: &str, #[case] count: usize, #[case] output: &str) {
+        let map = renames(&[("tenantA_price", "price")]);
+        let mut ast = parse_expression(input).unwrap();
+        assert_eq!(ast.rename_references(&map), count);
+        assert_eq!(ast.expr(), output);
+    }
+
+    #[test]
+    fn test_rename_functions() {
+        let map = renames(&[("tenantA_fee", "fee"), ("fee", "unused")]);
+        let mut ast = parse_expression("tenantA_fee(tenantA_fee(2)) + tenantA_fee").unwrap();
+        assert_eq!(ast.rename_functions(&map), 2);
+        assert_eq!(ast.expr(), "fee(fee(2)) + tenantA_fee");
+        let mut ctx = create_context!(
+            "fee" => Arc::new(|params: Vec<Value>| Ok(params[0].clone())),
+            "tenantA_fee" => 1
+        );
+        assert_eq!(ast.exec(&mut ctx).unwrap(), 3.into());
+    }
+}
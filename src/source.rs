@@ -0,0 +1,305 @@
+use crate::define::Result;
+use crate::options::ParseOptions;
+use crate::parser::{Builder, ExprAST, Literal, Parser, TreeBuilder};
+use crate::token::Span;
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::mem;
+
+/// A parsed expression that keeps the text it was parsed from, so that a
+/// rule can be shown back exactly as it was written, where
+/// [`ExprAST::expr`] prints it anew. Built by [`crate::parse_with_source`].
+#[derive(Debug)]
+pub struct Expression<'a> {
+    source: &'a str,
+    // Boxed so that the root keeps its address, which keys its span like
+    // those of the other nodes.
+    ast: Box<ExprAST<'a>>,
+    spans: BTreeMap<usize, Span>,
+}
+
+impl<'a> Expression<'a> {
+    pub(crate) fn parse(source: &'a str, options: ParseOptions) -> Result<Self> {
+        let mut parser = Parser::with_builder(source, SourceBuilder, options)?;
+        let (ast, spans) = parser.parse_stmt()?;
+        let mut expression = Expression {
+            source,
+            ast: Box::new(ast),
+            spans: BTreeMap::new(),
+        };
+        let mut stack = vec![(expression.ast.as_ref(), spans)];
+        while let Some((expr, mut spans)) = stack.pop() {
+            let children = mem::take(&mut spans.children);
+            stack.extend(expr.children().into_iter().zip(children));
+            expression.spans.insert(address(expr), spans.span);
+        }
+        Ok(expression)
+    }
+
+    /// The whole text, as it was parsed.
+    pub fn source(&self) -> &'a str {
+        self.source
+    }
+
+    pub fn ast(&self) -> &ExprAST<'a> {
+        &self.ast
+    }
+
+    pub fn into_ast(self) -> ExprAST<'a> {
+        *self.ast
+    }
+
+    /// The text `node`, a node of [`Expression::ast`], was parsed from,
+    /// e.g. `max(a,  1.50)` with its spacing and the number as written. A
+    /// parenthesized expression leaves its parentheses out. `None` for a
+    /// node of another tree, a clone included.
+    pub fn source_of(&self, node: &ExprAST) -> Option<&'a str> {
+        let Span(start, end) = self.span_of(node)?;
+        Some(&self.source[start..end])
+    }
+
+    /// Where [`Expression::source_of`] is in [`Expression::source`].
+    pub fn span_of(&self, node: &ExprAST) -> Option<Span> {
+        self.spans.get(&address(node)).copied()
+    }
+}
+
+fn address(node: &ExprAST) -> usize {
+    node as *const ExprAST as usize
+}
+
+// The span of a node and the spans of its children, in the order of
+// `ExprAST::children`. The outer span takes in the parentheses around the
+// node, which its parent runs over too.
+#[derive(Clone)]
+pub(crate) struct Spans {
+    span: Span,
+    outer: Span,
+    children: Vec<Spans>,
+}
+
+impl Spans {
+    fn new(span: Span, children: Vec<Spans>) -> Self {
+        Self {
+            span,
+            outer: span,
+            children,
+        }
+    }
+
+    // Over its children, from the first to the last.
+    fn over(children: Vec<Spans>) -> Self {
+        let span = match (children.first(), children.last()) {
+            (Some(first), Some(last)) => Span(first.outer.0, last.outer.1),
+            _ => Span(0, 0),
+        };
+        Self::new(span, children)
+    }
+}
+
+// Like `ExprAST`, dropped without recursing, as deep as the trees are.
+impl Drop for Spans {
+    fn drop(&mut self) {
+        let mut stack = mem::take(&mut self.children);
+        while let Some(mut spans) = stack.pop() {
+            stack.append(&mut spans.children);
+        }
+    }
+}
+
+// Builds the nodes of `TreeBuilder`, each with its spans.
+pub(crate) struct SourceBuilder;
+
+type Node<'a> = (ExprAST<'a>, Spans);
+
+impl<'a> Builder<'a> for SourceBuilder {
+    type Node = Node<'a>;
+
+    fn literal(&mut self, literal: Literal<'a>) -> Node<'a> {
+        (TreeBuilder.literal(literal), Spans::over(Vec::new()))
+    }
+
+    fn reference(&mut self, name: &'a str) -> Node<'a> {
+        (TreeBuilder.reference(name), Spans::over(Vec::new()))
+    }
+
+    fn reference_name(&self, node: &Node<'a>) -> Option<&'a str> {
+        TreeBuilder.reference_name(&node.0)
+    }
+
+    fn unary(&mut self, op: &'a str, (rhs, spans): Node<'a>) -> Node<'a> {
+        (TreeBuilder.unary(op, rhs), Spans::over(vec![spans]))
+    }
+
+    fn binary(&mut self, op: &'a str, lhs: Node<'a>, rhs: Node<'a>) -> Node<'a> {
+        (
+            TreeBuilder.binary(op, lhs.0, rhs.0),
+            Spans::over(vec![lhs.1, rhs.1]),
+        )
+    }
+
+    fn postfix(&mut self, (lhs, spans): Node<'a>, op: &'a str) -> Node<'a> {
+        (TreeBuilder.postfix(lhs, op), Spans::over(vec![spans]))
+    }
+
+    fn ternary(&mut self, condition: Node<'a>, lhs: Node<'a>, rhs: Node<'a>) -> Node<'a> {
+        (
+            TreeBuilder.ternary(condition.0, lhs.0, rhs.0),
+            Spans::over(vec![condition.1, lhs.1, rhs.1]),
+        )
+    }
+
+    fn function(&mut self, name: &'a str, params: Vec<Node<'a>>, span: Span) -> Node<'a> {
+        let (params, children) = params.into_iter().unzip();
+        (
+            TreeBuilder.function(name, params, span),
+            Spans::new(span, children),
+        )
+    }
+
+    fn list(&mut self, params: Vec<Node<'a>>) -> Node<'a> {
+        let (params, children) = params.into_iter().unzip();
+        (TreeBuilder.list(params), Spans::over(children))
+    }
+
+    fn map(&mut self, m: Vec<(Node<'a>, Node<'a>)>) -> Node<'a> {
+        let mut children = Vec::new();
+        let m = m
+            .into_iter()
+            .map(|(k, v)| {
+                children.extend([k.1, v.1]);
+                (k.0, v.0)
+            })
+            .collect();
+        (TreeBuilder.map(m), Spans::over(children))
+    }
+
+    fn match_(
+        &mut self,
+        scrutinee: Node<'a>,
+        arms: Vec<(Node<'a>, Node<'a>)>,
+        default: Option<Node<'a>>,
+    ) -> Node<'a> {
+        let mut children = vec![scrutinee.1];
+        let arms = arms
+            .into_iter()
+            .map(|(k, v)| {
+                children.extend([k.1, v.1]);
+                (k.0, v.0)
+            })
+            .collect();
+        let default = default.map(|(default, spans)| {
+            children.push(spans);
+            default
+        });
+        (
+            TreeBuilder.match_(scrutinee.0, arms, default),
+            Spans::over(children),
+        )
+    }
+
+    fn stmt(&mut self, exprs: Vec<Node<'a>>) -> Node<'a> {
+        let (exprs, children) = exprs.into_iter().unzip();
+        (TreeBuilder.stmt(exprs), Spans::over(children))
+    }
+
+    fn spread(&mut self, (expr, spans): Node<'a>) -> Node<'a> {
+        (TreeBuilder.spread(expr), Spans::over(vec![spans]))
+    }
+
+    fn none(&mut self) -> Node<'a> {
+        (TreeBuilder.none(), Spans::over(Vec::new()))
+    }
+
+    fn located(
+        &mut self,
+        (node, mut spans): Node<'a>,
+        span: Span,
+        parenthesized: bool,
+    ) -> Node<'a> {
+        if !parenthesized {
+            spans.span = span;
+        }
+        spans.outer = span;
+        (node, spans)
+    }
+}
+
+#[cfg(all(test, feature = "default"))]
+mod tests {
+    use crate::token::Span;
+    use crate::{parse_with_source, ExprAST};
+    use rstest::rstest;
+
+    // The source of each node, parents first.
+    fn sources(input: &str) -> Vec<&str> {
+        let expression = parse_with_source(input).unwrap();
+        let mut ans = Vec::new();
+        let mut stack = vec![expression.ast()];
+        while let Some(expr) = stack.pop() {
+            ans.push(expression.source_of(expr).unwrap());
+            stack.extend(expr.children().into_iter().rev());
+        }
+        ans
+    }
+
+    #[rstest]
+    #[case("1.50", &["1.50"])]
+    #[case(" a  +\t2.50 ", &["a  +\t2.50", "a", "2.50"])]
+    #[case("(a + b) * c", &["(a + b) * c", "a + b", "a", "b", "c"])]
+    #[case("c * ( (a) + b )", &["c * ( (a) + b )", "c", "(a) + b", "a", "b"])]
+    #[case("-a ++", &["-a ++", "a ++", "a"])]
+    #[case("(-a)++", &["(-a)++", "-a", "a"])]
+    #[case("a not  in [1 ,2]", &["a not  in [1 ,2]", "a not  in [1 ,2]", "a", "[1 ,2]", "1", "2"])]
+    #[case("x ? 'y' : \"z\"", &["x ? 'y' : \"z\"", "x", "'y'", "\"z\""])]
+    #[case("{ k : 1, 'j':[ ...xs ] }", &[
+        "{ k : 1, 'j':[ ...xs ] }", "k", "1", "'j'", "[ ...xs ]", "...xs", "xs",
+    ])]
+    #[case("match  s { 1 : 'a', _ : 'b' }", &["match  s { 1 : 'a', _ : 'b' }", "s", "1", "'a'", "'b'"])]
+    #[case("1 < a <= 3", &["1 < a <= 3", "1 < a", "1", "a", "a <= 3", "a", "3"])]
+    #[case("n between 1 and 10", &["n between 1 and 10", "n", "1 and 10", "1", "10"])]
+    fn test_source_of(#[case] input: &str, #[case] expected: &[&str]) {
+        assert_eq!(sources(input), expected);
+    }
+
+    #[test]
+    fn test_source_of_nested_param() {
+        let input = "round( max(a ,  1.50 ),\n  2 )";
+        let expression = parse_with_source(input).unwrap();
+        assert_eq!(expression.source(), input);
+        let ExprAST::Function("round", params) = expression.ast() else {
+            panic!("{:?}", expression.ast());
+        };
+        assert_eq!(expression.source_of(&params[0]), Some("max(a ,  1.50 )"));
+        // The printed form is re-rendered.
+        assert_eq!(params[0].expr(), "max(a,1.50)");
+        let ExprAST::Function("max", params) = &params[0] else {
+            panic!("{:?}", params[0]);
+        };
+        assert_eq!(expression.source_of(&params[1]), Some("1.50"));
+        assert_eq!(expression.span_of(&params[1]), Some(Span(16, 20)));
+    }
+
+    #[test]
+    fn test_source_of_statements() {
+        let input = "  a = 1 ;b+=  2;\n\n  f( a ,b )  ;  ";
+        let expression = parse_with_source(input).unwrap();
+        let ExprAST::Stmt(stmts) = expression.ast() else {
+            panic!("{:?}", expression.ast());
+        };
+        let stmts: Vec<&str> = stmts
+            .iter()
+            .map(|stmt| expression.source_of(stmt).unwrap())
+            .collect();
+        assert_eq!(stmts, ["a = 1", "b+=  2", "f( a ,b )"]);
+        assert_eq!(
+            expression.source_of(expression.ast()),
+            Some("a = 1 ;b+=  2;\n\n  f( a ,b )")
+        );
+        // Only nodes of the tree itself have a source.
+        assert_eq!(expression.source_of(&expression.ast().clone()), None);
+        let ast = expression.into_ast();
+        assert_eq!(ast.expr(), "a = 1;b += 2;f(a,b)");
+    }
+}
@@ -0,0 +1,81 @@
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+/// Up to three of `candidates` that `name` is likely a typo of, closest
+/// first. A candidate differing only in case counts as the closest; others
+/// are kept when they are within two edits and the edits don't replace the
+/// whole name.
+pub(crate) fn suggestions<'c>(
+    name: &str,
+    candidates: impl IntoIterator<Item = &'c str>,
+) -> Vec<String> {
+    let len = name.chars().count();
+    let mut close: Vec<(usize, &str)> = candidates
+        .into_iter()
+        .filter(|candidate| *candidate != name)
+        .filter_map(|candidate| {
+            let distance = if candidate.to_lowercase() == name.to_lowercase() {
+                0
+            } else {
+                edit_distance(name, candidate)
+            };
+            (distance <= 2 && distance < len).then_some((distance, candidate))
+        })
+        .collect();
+    close.sort_unstable();
+    close.dedup();
+    close
+        .into_iter()
+        .take(3)
+        .map(|(_, candidate)| candidate.to_string())
+        .collect()
+}
+
+// The Levenshtein distance, over chars.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(ca != *cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
+#[cfg(all(test, feature = "default"))]
+mod tests {
+    use super::{edit_distance, suggestions};
+    use rstest::rstest;
+
+    #[rstest]
+    #[case("", "", 0)]
+    #[case("totl", "total", 1)]
+    #[case("roud", "round", 1)]
+    #[case("kitten", "sitting", 3)]
+    #[case("héllo", "hello", 1)]
+    fn test_edit_distance(#[case] a: &str, #[case] b: &str, #[case] distance: usize) {
+        assert_eq!(edit_distance(a, b), distance);
+        assert_eq!(edit_distance(b, a), distance);
+    }
+
+    #[rstest]
+    #[case("totl", &["total", "count", "tot"], &["tot", "total"])]
+    #[case("Total", &["total", "totals"], &["total", "totals"])]
+    #[case("ab", &["ac", "xy"], &["ac"])]
+    #[case("x", &["y", "X"], &["X"])]
+    #[case("totl", &["price", "count"], &[])]
+    #[case("total", &["total"], &[])]
+    #[case("aaaa", &["aaab", "aaac", "aaad", "aaae"], &["aaab", "aaac", "aaad"])]
+    fn test_suggestions(
+        #[case] name: &str,
+        #[case] candidates: &[&str],
+        #[case] expected: &[&str],
+    ) {
+        assert_eq!(suggestions(name, candidates.iter().copied()), expected);
+    }
+}
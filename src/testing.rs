@@ -0,0 +1,398 @@
+//! Helpers for testing rules, like checking that a rewritten rule still
+//! means the same as the original with [`assert_equivalent`].
+
+use crate::context::Context;
+use crate::define::Result;
+use crate::options::{EvalOptions, SemanticsVersion};
+use crate::parser::ExprAST;
+use crate::value::{Map, Value, ValueType};
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::fmt;
+use rust_decimal::Decimal;
+
+// The most contexts generated with every combination of values, before
+// falling back to varying one variable at a time. Three variables fit.
+const MAX_COMBINATIONS: usize = 4096;
+
+const TYPES: [ValueType; 6] = [
+    ValueType::Number,
+    ValueType::String,
+    ValueType::Bool,
+    ValueType::List,
+    ValueType::Map,
+    ValueType::None,
+];
+
+/// A context two expressions give different results on, found by
+/// [`find_divergence`].
+#[derive(Clone, PartialEq, Debug)]
+pub struct Divergence {
+    pub expr_a: String,
+    pub expr_b: String,
+    /// The names either expression references that the context resolves,
+    /// sorted, with their values before evaluating.
+    pub variables: Vec<(String, Value)>,
+    /// The value of `expr_a`, or the message of the error it failed with.
+    pub result_a: core::result::Result<Value, String>,
+    pub result_b: core::result::Result<Value, String>,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "'{}' gives {} but '{}' gives {}",
+            self.expr_a,
+            Outcome(&self.result_a),
+            self.expr_b,
+            Outcome(&self.result_b)
+        )?;
+        for (i, (name, value)) in self.variables.iter().enumerate() {
+            let sep = if i == 0 { " with " } else { ", " };
+            write!(f, "{}{} = {}", sep, name, value)?;
+        }
+        Ok(())
+    }
+}
+
+/// What an expression gives under [`SemanticsVersion::V1`] and
+/// [`SemanticsVersion::V2`], when they differ, found by
+/// [`compare_semantics`].
+#[derive(Clone, PartialEq, Debug)]
+pub struct SemanticsDivergence {
+    pub expr: String,
+    /// The value, or the message of the error evaluating failed with.
+    pub v1: core::result::Result<Value, String>,
+    pub v2: core::result::Result<Value, String>,
+}
+
+impl fmt::Display for SemanticsDivergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "'{}' gives {} under V1 but {} under V2",
+            self.expr,
+            Outcome(&self.v1),
+            Outcome(&self.v2)
+        )
+    }
+}
+
+// A result as `Divergence` and `SemanticsDivergence` write it.
+struct Outcome<'r>(&'r core::result::Result<Value, String>);
+
+impl fmt::Display for Outcome<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Ok(value) => write!(f, "{}", value),
+            Err(err) => write!(f, "error '{}'", err),
+        }
+    }
+}
+
+/// Evaluates `expr` on `ctx` under each [`SemanticsVersion`], with the
+/// other options of `ctx`, and gives both results if they differ, to audit
+/// rules before switching to V2. Unlike [`find_divergence`], failing with
+/// two different errors is a difference too. Assignments go into a layer
+/// over `ctx`, which is left as it was. Fails if `expr` doesn't parse.
+pub fn compare_semantics(expr: &str, ctx: &Context) -> Result<Option<SemanticsDivergence>> {
+    let ast = crate::parse_expression(expr)?;
+    let base = Arc::new(ctx.view());
+    let eval = |semantics: SemanticsVersion| {
+        let mut layer = Context::with_base(base.clone());
+        layer.set_options(EvalOptions {
+            semantics,
+            ..ctx.options()
+        });
+        ast.exec(&mut layer).map_err(|err| err.to_string())
+    };
+    let (v1, v2) = (eval(SemanticsVersion::V1), eval(SemanticsVersion::V2));
+    Ok((v1 != v2).then(|| SemanticsDivergence {
+        expr: expr.to_string(),
+        v1,
+        v2,
+    }))
+}
+
+/// Panics unless `expr_a` and `expr_b` are equivalent, as
+/// [`find_divergence`] decides, with the divergence it found.
+#[track_caller]
+pub fn assert_equivalent(expr_a: &str, expr_b: &str, fixtures: &[Context]) {
+    match find_divergence(expr_a, expr_b, fixtures) {
+        Ok(None) => {}
+        Ok(Some(divergence)) => panic!("not equivalent: {}", divergence),
+        Err(err) => panic!("not equivalent: {}", err),
+    }
+}
+
+/// The first context `expr_a` and `expr_b` give different results on, or
+/// `None` if there's none. Fails if either doesn't parse.
+///
+/// Expressions whose [`ExprAST::canonicalize`]d trees are equal are
+/// equivalent without evaluating them. Otherwise both are evaluated on each
+/// of `fixtures`, or without any, on the contexts [`generate_contexts`]
+/// makes for the names they reference. They agree when they give equal
+/// values or both fail, whatever the errors. Assignments go into a layer
+/// over the fixture, so each evaluation starts from the fixture as it was.
+pub fn find_divergence(
+    expr_a: &str,
+    expr_b: &str,
+    fixtures: &[Context],
+) -> Result<Option<Divergence>> {
+    let ast_a = crate::parse_expression(expr_a)?;
+    let ast_b = crate::parse_expression(expr_b)?;
+    if ast_a.clone().canonicalize() == ast_b.clone().canonicalize() {
+        return Ok(None);
+    }
+    let mut references = ast_a.references();
+    references.extend(ast_b.references());
+    references.sort_unstable();
+    references.dedup();
+    let generated;
+    let fixtures = match fixtures {
+        [] => {
+            generated = generate_contexts(&references);
+            &generated
+        }
+        _ => fixtures,
+    };
+    let eval = |ast: &ExprAST, base: &Arc<Context>| {
+        ast.exec(&mut Context::with_base(base.clone()))
+            .map_err(|err| err.to_string())
+    };
+    for fixture in fixtures {
+        let base = Arc::new(fixture.view());
+        let result_a = eval(&ast_a, &base);
+        let result_b = eval(&ast_b, &base);
+        let agree = match (&result_a, &result_b) {
+            (Ok(a), Ok(b)) => a == b,
+            (a, b) => a.is_err() && b.is_err(),
+        };
+        if !agree {
+            let ctx = Context::with_base(base);
+            let variables = references
+                .iter()
+                .filter_map(|name| {
+                    let value = match ctx.get_variable(name) {
+                        Some(value) => value,
+                        None if name.contains('.') => ctx.value(name).ok()?,
+                        None => return None,
+                    };
+                    Some((name.to_string(), value))
+                })
+                .collect();
+            return Ok(Some(Divergence {
+                expr_a: expr_a.to_string(),
+                expr_b: expr_b.to_string(),
+                variables,
+                result_a,
+                result_b,
+            }));
+        }
+    }
+    Ok(None)
+}
+
+/// The values contexts are generated from for a variable of type `ty`:
+/// zero, one, a negative and a fraction for numbers, the empty string,
+/// both bools, the empty list and map, and a few small ones of each.
+pub fn representative_values(ty: ValueType) -> Vec<Value> {
+    match ty {
+        ValueType::Number => vec![
+            0.into(),
+            1.into(),
+            (-1).into(),
+            Value::Number(Decimal::new(5, 1)),
+            100.into(),
+        ],
+        ValueType::String => vec!["".into(), "a".into(), "Ab c".into()],
+        ValueType::Bool => vec![true.into(), false.into()],
+        ValueType::List => vec![
+            Value::List(Vec::new()),
+            vec![0.into()].into(),
+            vec![1.into(), 2.into()].into(),
+        ],
+        ValueType::Map => vec![
+            Value::Map(Map::default()),
+            Value::Map([("a".into(), 1.into())].into_iter().collect()),
+        ],
+        ValueType::None => vec![Value::None],
+    }
+}
+
+/// Contexts that set each of `names` to the [`representative_values`] of
+/// every type, in every combination while there are at most 4096 of them.
+/// With more names, each name takes every value while the others share one,
+/// for each value. A dotted name like `user.age` is set as a key of a map,
+/// unless an earlier name already set its root to something else.
+pub fn generate_contexts(names: &[&str]) -> Vec<Context> {
+    let values: Vec<Value> = TYPES.into_iter().flat_map(representative_values).collect();
+    // The context setting each name to the value at its index in `picks`.
+    let context = |picks: &[usize]| {
+        let mut ctx = Context::new();
+        for (name, &pick) in names.iter().zip(picks) {
+            // The root holds a value that isn't a map, so the path is None.
+            let _ = ctx.set_path(&name.replace("?.", "."), values[pick].clone());
+        }
+        ctx
+    };
+    let combinations = u32::try_from(names.len())
+        .ok()
+        .and_then(|n| values.len().checked_pow(n))
+        .filter(|&count| count <= MAX_COMBINATIONS);
+    match combinations {
+        Some(count) => (0..count)
+            .map(|mut index| {
+                // The digits of `index` in base `values.len()`, the last
+                // name changing fastest.
+                let mut picks = vec![0; names.len()];
+                for pick in picks.iter_mut().rev() {
+                    *pick = index % values.len();
+                    index /= values.len();
+                }
+                context(&picks)
+            })
+            .collect(),
+        None => {
+            let mut contexts = Vec::new();
+            for shared in 0..values.len() {
+                for varied in 0..names.len() {
+                    for value in 0..values.len() {
+                        let mut picks = vec![shared; names.len()];
+                        picks[varied] = value;
+                        contexts.push(context(&picks));
+                    }
+                }
+            }
+            contexts
+        }
+    }
+}
+
+#[cfg(all(test, feature = "default"))]
+mod tests {
+    use super::{
+        assert_equivalent, compare_semantics, find_divergence, generate_contexts,
+        representative_values, TYPES,
+    };
+    use crate::{create_context, Value};
+    use rstest::rstest;
+
+    #[rstest]
+    #[case("a && b", "b && a")]
+    #[case("!(a == b)", "a != b")]
+    #[case("not (x > 1 || y)", "!y && !(x > 1)")]
+    #[case("{'b': 1, 'a': x}", "{'a': x, 'b': 1}")]
+    fn test_canonically_equivalent(#[case] a: &str, #[case] b: &str) {
+        // Equal canonical trees don't need a context.
+        assert_equivalent(a, b, &[create_context!("x" => "not a number")]);
+    }
+
+    #[rstest]
+    #[case("x ? a : b", "!x ? b : a")]
+    #[case("x > 1 ? 'big' : 'small'", "x <= 1 ? 'small' : 'big'")]
+    #[case("coalesce(a, b)", "a == null ? b : a")]
+    fn test_equivalent_on_generated(#[case] a: &str, #[case] b: &str) {
+        assert_eq!(find_divergence(a, b, &[]).unwrap(), None);
+    }
+
+    #[test]
+    fn test_division_by_zero_found() {
+        let divergence = find_divergence("b == 0 ? 0 : a / b", "a / b", &[])
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            divergence.variables,
+            [("a".to_string(), 0.into()), ("b".to_string(), 0.into())]
+        );
+        assert_eq!(divergence.result_a, Ok(0.into()));
+        assert!(divergence.result_b.is_err());
+        assert_eq!(
+            divergence.to_string(),
+            "'b == 0 ? 0 : a / b' gives 0 but 'a / b' gives error 'division by zero: in 'a / b'' \
+             with a = 0, b = 0"
+        );
+    }
+
+    #[test]
+    fn test_empty_list_found() {
+        let mean = "sum(xs) / len(xs)";
+        let guarded = "len(xs) > 0 ? sum(xs) / len(xs) : 0";
+        let lists = [
+            create_context!("xs" => vec![Value::from(1), 3.into()]),
+            create_context!("xs" => vec![Value::from(2)]),
+        ];
+        assert_eq!(find_divergence(mean, guarded, &lists).unwrap(), None);
+        let empty = create_context!("xs" => Vec::<Value>::new());
+        let divergence = find_divergence(mean, guarded, &[empty]).unwrap().unwrap();
+        assert_eq!(divergence.variables, [("xs".to_string(), vec![].into())]);
+        assert_eq!(divergence.result_b, Ok(0.into()));
+        // Among the generated contexts too.
+        assert!(find_divergence(mean, guarded, &[]).unwrap().is_some());
+    }
+
+    #[test]
+    #[should_panic(expected = "not equivalent: 'n + 1' gives 1 but 'n' gives 0 with n = 0")]
+    fn test_assert_equivalent_panics() {
+        assert_equivalent("n + 1", "n", &[]);
+    }
+
+    #[test]
+    fn test_fixture_left_unchanged() {
+        let fixture = create_context!("n" => 1);
+        assert_equivalent("n += 1; n", "n + 1", core::slice::from_ref(&fixture));
+        assert_eq!(fixture.value("n").unwrap(), 1.into());
+        assert!(find_divergence("n +", "n", &[]).is_err());
+    }
+
+    #[rstest]
+    #[case("n = 1", Some("'n = 1' gives null under V1 but 1 under V2"))]
+    #[case(
+        "label = 'n' + 'x'",
+        Some(
+            "'label = 'n' + 'x'' gives error 'should be number: in '\"n\" + \"x\"' within \
+              'label = \"n\" + \"x\"'' under V1 but 'nx' under V2"
+        )
+    )]
+    #[case("n = 1; n + 1", None)]
+    #[case("'n' + 1", None)]
+    fn test_compare_semantics(#[case] input: &str, #[case] divergence: Option<&str>) {
+        let ctx = create_context!("n" => 0);
+        let found = compare_semantics(input, &ctx).unwrap();
+        assert_eq!(found.map(|d| d.to_string()).as_deref(), divergence);
+        assert_eq!(ctx.value("n").unwrap(), 0.into());
+        assert_eq!(ctx.options(), crate::EvalOptions::default());
+    }
+
+    #[test]
+    fn test_representative_values() {
+        for ty in TYPES {
+            let values = representative_values(ty);
+            assert!(!values.is_empty());
+            assert!(values.iter().all(|value| value.value_type() == ty));
+        }
+    }
+
+    #[test]
+    fn test_generate_contexts() {
+        let count = TYPES
+            .into_iter()
+            .map(|ty| representative_values(ty).len())
+            .sum::<usize>();
+        assert_eq!(generate_contexts(&[]).len(), 1);
+        assert_eq!(generate_contexts(&["a", "b"]).len(), count * count);
+        // Too many combinations, so one name varies at a time.
+        assert_eq!(
+            generate_contexts(&["a", "b", "c", "d"]).len(),
+            count * 4 * count
+        );
+        let contexts = generate_contexts(&["user.age"]);
+        let ages: Vec<Value> = contexts
+            .iter()
+            .map(|ctx| ctx.value("user.age").unwrap())
+            .collect();
+        assert!(ages.contains(&0.into()) && ages.contains(&"".into()));
+    }
+}
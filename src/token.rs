@@ -1,7 +1,8 @@
 use crate::keyword;
+use alloc::string::{String, ToString};
 use core::clone::Clone;
+use core::fmt;
 use rust_decimal::Decimal;
-use std::fmt;
 
 #[derive(Clone, PartialEq, Debug, Copy)]
 pub enum DelimTokenType {
@@ -69,6 +70,35 @@ impl DelimTokenType {
 #[derive(Clone, PartialEq, Debug, Copy)]
 pub struct Span(pub usize, pub usize);
 
+#[derive(Clone, PartialEq, Eq, Debug, Copy)]
+pub enum TokenKind {
+    Operator,
+    Delim,
+    Number,
+    Comma,
+    Bool,
+    Null,
+    String,
+    Reference,
+    Function,
+    Semicolon,
+    // Whitespace between two tokens, only yielded in trivia mode. Comments
+    // will be surfaced as their own trivia kind once the language has them.
+    Whitespace,
+}
+
+/// A token together with the exact slice of input it was read from and its
+/// position, suitable for syntax highlighting and other editor tooling.
+/// `line` and `column` are 1-based and counted in chars.
+#[derive(Clone, PartialEq, Debug, Copy)]
+pub struct SpannedToken<'input> {
+    pub kind: TokenKind,
+    pub text: &'input str,
+    pub span: Span,
+    pub line: usize,
+    pub column: usize,
+}
+
 #[derive(Clone, PartialEq, Debug, Copy)]
 pub enum Token<'input> {
     Operator(&'input str, Span),
@@ -76,6 +106,7 @@ pub enum Token<'input> {
     Number(Decimal, Span),
     Comma(&'input str, Span),
     Bool(bool, Span),
+    Null(&'input str, Span),
     String(&'input str, Span),
     Reference(&'input str, Span),
     Function(&'input str, Span),
@@ -97,7 +128,7 @@ pub fn check_op(token: Token, expected: &str) -> bool {
         }
         _ => return false,
     }
-    return false;
+    false
 }
 
 impl<'input> Token<'input> {
@@ -134,17 +165,11 @@ impl<'input> Token<'input> {
     }
 
     pub fn is_eof(self) -> bool {
-        match self {
-            Self::EOF => true,
-            _ => false,
-        }
+        matches!(self, Self::EOF)
     }
 
     pub fn is_op_token(&self) -> bool {
-        match self {
-            Self::Operator(_, _) => true,
-            _ => false,
-        }
+        matches!(self, Self::Operator(_, _))
     }
 
     pub fn is_postfix_op_token(&self) -> bool {
@@ -154,6 +179,10 @@ impl<'input> Token<'input> {
         }
     }
 
+    pub fn is_spread_token(&self) -> bool {
+        matches!(self, Self::Operator("...", _))
+    }
+
     pub fn is_binop_token(&self) -> bool {
         match self {
             Self::Operator(op, _) => keyword::is_infix_op(op),
@@ -168,13 +197,63 @@ impl<'input> Token<'input> {
         }
     }
 
-    pub fn is_semicolon(&self) -> bool {
+    // Whether the token can begin an operand. Operators that are also infix
+    // ones don't count, since `a - 1` must stay a subtraction, and neither
+    // does `{`, so that `match match {...}` matches on a variable.
+    pub fn starts_expression(&self) -> bool {
         match self {
-            Self::Semicolon(..) => true,
+            Self::Number(..)
+            | Self::Bool(..)
+            | Self::Null(..)
+            | Self::String(..)
+            | Self::Reference(..)
+            | Self::Function(..) => true,
+            Self::Delim(ty, _) => {
+                matches!(ty, DelimTokenType::OpenParen | DelimTokenType::OpenBracket)
+            }
+            Self::Operator(op, _) => keyword::is_prefix_op(op) && !keyword::is_infix_op(op),
             _ => false,
         }
     }
 
+    pub fn is_semicolon(&self) -> bool {
+        matches!(self, Self::Semicolon(..))
+    }
+
+    pub fn span(&self) -> Option<Span> {
+        use Token::*;
+        match self {
+            Operator(_, span)
+            | Delim(_, span)
+            | Number(_, span)
+            | Comma(_, span)
+            | Bool(_, span)
+            | Null(_, span)
+            | String(_, span)
+            | Reference(_, span)
+            | Function(_, span)
+            | Semicolon(_, span) => Some(*span),
+            EOF => None,
+        }
+    }
+
+    pub fn kind(&self) -> Option<TokenKind> {
+        use Token::*;
+        match self {
+            Operator(..) => Some(TokenKind::Operator),
+            Delim(..) => Some(TokenKind::Delim),
+            Number(..) => Some(TokenKind::Number),
+            Comma(..) => Some(TokenKind::Comma),
+            Bool(..) => Some(TokenKind::Bool),
+            Null(..) => Some(TokenKind::Null),
+            String(..) => Some(TokenKind::String),
+            Reference(..) => Some(TokenKind::Reference),
+            Function(..) => Some(TokenKind::Function),
+            Semicolon(..) => Some(TokenKind::Semicolon),
+            EOF => None,
+        }
+    }
+
     #[cfg(not(tarpaulin_include))]
     pub fn string(self) -> String {
         use Token::*;
@@ -183,6 +262,7 @@ impl<'input> Token<'input> {
             Number(val, _) => val.to_string(),
             Comma(val, _) => val.to_string(),
             Bool(val, _) => val.to_string(),
+            Null(val, _) => val.to_string(),
             String(val, _) => val.to_string(),
             Reference(val, _) => val.to_string(),
             Function(val, _) => val.to_string(),
@@ -206,6 +286,7 @@ impl<'input> fmt::Display for Token<'input> {
         use Token::*;
         match self {
             Bool(val, span) => write!(f, "Bool Token: {}, {}", val, span),
+            Null(val, span) => write!(f, "Null Token: {}, {}", val, span),
             Comma(val, span) => write!(f, "Comma Token: {}, {}", val, span),
             Number(val, span) => write!(f, "Number Token: {}, {}", val, span),
             Operator(val, span) => write!(f, "Operator Token: {}, {}", val, span),
@@ -1,9 +1,13 @@
 use crate::define::Result;
 use crate::error::Error;
 use crate::keyword;
-use crate::token::{Span, Token};
+use crate::token::{Span, SpannedToken, Token, TokenKind};
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::str;
 use rust_decimal::prelude::*;
-use std::str;
+
+const MAX_OP_WORDS: usize = 3;
 
 #[derive(Clone)]
 pub struct Tokenizer<'a> {
@@ -12,19 +16,49 @@ pub struct Tokenizer<'a> {
     cur_char: char,
     pub cur_token: Token<'a>,
     pub prev_token: Token<'a>,
+    run_end: usize,
+    newlines: bool,
+    newline_before: bool,
+    max_token_len: usize,
+    max_string_len: usize,
 }
 
 impl<'a> Tokenizer<'a> {
-    pub fn new(input: &str) -> Tokenizer {
+    pub fn new(input: &str) -> Tokenizer<'_> {
         Tokenizer {
-            input: input,
+            input,
             chars: input.char_indices(),
             cur_char: ' ',
             cur_token: Token::EOF,
             prev_token: Token::EOF,
+            run_end: 0,
+            newlines: false,
+            newline_before: false,
+            max_token_len: usize::MAX,
+            max_string_len: usize::MAX,
         }
     }
 
+    // With newlines on, a name followed by `(` on the next line is a
+    // reference rather than a call, so that a line ending in a name is
+    // complete.
+    pub(crate) fn set_newlines(&mut self, newlines: bool) {
+        self.newlines = newlines;
+    }
+
+    // The `ParseOptions` limits, in bytes, of a token and of the body of a
+    // string literal. A tokenizer of its own has none.
+    pub(crate) fn set_limits(&mut self, max_token_len: usize, max_string_len: usize) {
+        self.max_token_len = max_token_len;
+        self.max_string_len = max_string_len;
+    }
+
+    /// Whether a line break separates the current token from the one
+    /// before it.
+    pub fn newline_before(&self) -> bool {
+        self.newline_before
+    }
+
     fn next_one(&mut self) -> Option<(usize, char)> {
         let (cur, cur_char) = self.chars.next()?;
         self.cur_char = cur_char;
@@ -41,13 +75,23 @@ impl<'a> Tokenizer<'a> {
         self.cur_token = match self.next_one() {
             Some((
                 start,
-                '+' | '-' | '*' | '/' | '^' | '%' | '&' | '!' | '=' | '?' | ':' | '>' | '<' | '|',
+                '+' | '-' | '*' | '/' | '^' | '%' | '&' | '!' | '=' | '?' | ':' | '>' | '<' | '|'
+                | '~',
             )) => self.special_op_token(start),
             Some((start, '(' | ')' | '[' | ']' | '{' | '}')) => self.delim_token(start),
             Some((start, _ch @ '0'..='9')) => self.number_token(start),
             Some((start, '"' | '\'')) => self.string_token(start),
+            Some((start, '`')) => self.quoted_name_token(start),
             Some((start, ';')) => self.semicolon_token(start),
             Some((start, ',')) => self.comma_token(start),
+            Some((start, '.')) if self.input[start..].starts_with("...") => {
+                self.spread_token(start)
+            }
+            Some((start, '.'))
+                if self.input[start + 1..].starts_with(|ch: char| ch.is_ascii_digit()) =>
+            {
+                self.number_token(start)
+            }
             None => Ok(Token::EOF),
             Some((start, ch)) => self.other_token(ch, start),
         }?;
@@ -55,16 +99,11 @@ impl<'a> Tokenizer<'a> {
     }
 
     fn special_op_token(&mut self, start: usize) -> Result<Token<'a>> {
-        loop {
-            match self.peek_one() {
-                Some((_, _ch)) => {
-                    if keyword::is_op(&(self.input[start..self.current() + 1].to_string())) {
-                        self.next_one();
-                    } else {
-                        break;
-                    }
-                }
-                None => break,
+        while let Some((_, ch)) = self.peek_one() {
+            if keyword::is_op(&self.input[start..self.current() + ch.len_utf8()]) {
+                self.next_one();
+            } else {
+                break;
             }
         }
         Ok(Token::Operator(
@@ -74,74 +113,118 @@ impl<'a> Tokenizer<'a> {
     }
 
     fn other_token(&mut self, _: char, start: usize) -> Result<Token<'a>> {
+        if let Some(end) = self.multi_word_op_end(start) {
+            while self.current() < end {
+                self.next_one();
+            }
+            return Ok(Token::Operator(&self.input[start..end], Span(start, end)));
+        }
         if self.try_parse_op(start) {
             return self.operator_token(start);
         }
-        let (atom, start) = self.parse_var(start);
+        let (atom, start) = self.parse_var(start)?;
         if atom == "True" || atom == "true" {
             return self.bool_token(start, true);
         } else if atom == "False" || atom == "false" {
             return self.bool_token(start, false);
+        } else if matches!(atom, "null" | "Null" | "none" | "None") {
+            return Ok(Token::Null(atom, Span(start, self.current())));
         }
-        return self.function_or_reference_token(atom, start);
+        self.function_or_reference_token(atom, start)
     }
 
-    fn try_parse_op(&self, start: usize) -> bool {
-        let mut tmp = self.clone();
-        loop {
-            match tmp.peek_one() {
-                Some((_, ch)) => {
-                    if is_whitespace_char(ch) || is_delim_char(ch) {
-                        break;
-                    }
-                    tmp.next_one();
-                }
-                None => break,
+    fn try_parse_op(&mut self, start: usize) -> bool {
+        let end = self.run_end(start);
+        end - start <= keyword::max_op_len() && keyword::is_op(&self.input[start..end])
+    }
+
+    // The end of the run of chars from `start` to the next whitespace or
+    // delimiter. Every token inside a run shares it, so a run like `a;a;a`
+    // is scanned once rather than once per token.
+    fn run_end(&mut self, start: usize) -> usize {
+        if start >= self.run_end {
+            let rest = &self.input[start..];
+            let len = rest
+                .find(|ch| is_whitespace_char(ch) || is_delim_char(ch))
+                .unwrap_or(rest.len());
+            self.run_end = start + len;
+        }
+        self.run_end
+    }
+
+    // Infix operators can be several words, like `is not`. The longest run
+    // of up to MAX_OP_WORDS words that is registered wins; when none is, the
+    // first word is tokenized on its own.
+    fn multi_word_op_end(&self, start: usize) -> Option<usize> {
+        let mut ends = [0; MAX_OP_WORDS];
+        let mut words = 0;
+        let mut end = start;
+        while words < MAX_OP_WORDS {
+            let rest = &self.input[end..];
+            let word_start = end + rest.len() - rest.trim_start_matches(is_whitespace_char).len();
+            if words > 0 && word_start == end {
+                break;
+            }
+            let word = &self.input[word_start..];
+            if !word.starts_with(is_key_start_char) {
+                break;
             }
+            end = word_start + word.find(|ch| !is_param_char(ch)).unwrap_or(word.len());
+            ends[words] = end;
+            words += 1;
         }
-        keyword::is_op(&tmp.input[start..tmp.current()])
+        (2..=words)
+            .rev()
+            .map(|n| ends[n - 1])
+            .find(|end| keyword::is_infix_op(&self.input[start..*end]))
     }
 
     fn operator_token(&mut self, start: usize) -> Result<Token<'a>> {
-        loop {
-            match self.peek_one() {
-                Some((_, ch)) => {
-                    if is_whitespace_char(ch) || is_delim_char(ch) {
-                        break;
-                    }
-                    self.next_one();
-                }
-                None => break,
-            }
+        let end = self.run_end(start);
+        while self.current() < end {
+            self.next_one();
         }
-        return Ok(Token::Operator(
+        Ok(Token::Operator(
             self.input[start..self.current()].into(),
             Span(start, self.current()),
-        ));
+        ))
     }
 
-    fn parse_var(&mut self, start: usize) -> (&'a str, usize) {
-        loop {
-            match self.peek_one() {
-                Some((_, ch)) => {
-                    if is_param_char(ch) {
-                        self.next_one();
-                        continue;
-                    }
-                    break;
-                }
-                None => break,
+    // A `?.` continues the name when a key follows, so `user?.name` is one
+    // reference while `a?.5:1` is still a ternary.
+    fn parse_var(&mut self, start: usize) -> Result<(&'a str, usize)> {
+        while let Some((_, ch)) = self.peek_one() {
+            let rest = &self.input[self.current()..];
+            if rest.starts_with("?.") && rest[2..].starts_with(is_key_start_char) {
+                self.next_one();
+            } else if !is_param_char(ch) {
+                break;
             }
+            self.next_one();
+            self.check_token_len(start)?;
         }
-        (self.input[start..self.current()].into(), start)
+        Ok((self.input[start..self.current()].into(), start))
     }
 
-    pub fn peek(&self) -> Result<Token> {
+    // Fails once the token from `start` runs past `max_token_len`, checked
+    // char by char so that a huge one isn't read to its end.
+    fn check_token_len(&self, start: usize) -> Result<()> {
+        match self.current() - start > self.max_token_len {
+            true => Err(Error::TokenTooLong {
+                start,
+                kind: "token",
+                limit: self.max_token_len,
+            }),
+            false => Ok(()),
+        }
+    }
+
+    pub fn peek(&self) -> Result<Token<'_>> {
         self.clone().next()
     }
 
     pub fn expect(&mut self, op: &str) -> Result<()> {
-        let token = self.cur_token.clone();
+        let token = self.cur_token;
         self.next()?;
         match token {
             Token::Delim(bracket, _) => {
@@ -173,6 +256,15 @@ impl<'a> Tokenizer<'a> {
         ))
     }
 
+    fn spread_token(&mut self, start: usize) -> Result<Token<'a>> {
+        self.next_one();
+        self.next_one();
+        Ok(Token::Operator(
+            &self.input[start..start + 3],
+            Span(start, start + 3),
+        ))
+    }
+
     fn comma_token(&mut self, start: usize) -> Result<Token<'a>> {
         Ok(Token::Comma(
             &self.input[start..start + 1],
@@ -187,33 +279,36 @@ impl<'a> Tokenizer<'a> {
         ))
     }
 
+    // Takes the whole run a number could be read from, letters and dots
+    // included, and checks it at once, so `1.2.3` or `2abc` fail as a whole
+    // rather than as a number and whatever follows. A number is digits with
+    // an optional fraction, like `12` or `1.50`, or a fraction alone, like
+    // `.5`; `1.` isn't one, a dot always comes with digits after it.
     fn number_token(&mut self, start: usize) -> Result<Token<'a>> {
-        loop {
-            match self.peek_one() {
-                Some((_, ch)) => {
-                    if (ch == '+' || ch == '-') && (self.cur_char != 'e' && self.cur_char != 'E') {
-                        break;
-                    }
-                    if is_digit_char(ch) {
-                        self.next_one();
-                    } else {
-                        break;
-                    }
-                }
-                None => break,
+        while let Some((_, ch)) = self.peek_one() {
+            let exponent_sign = (ch == '+' || ch == '-') && matches!(self.cur_char, 'e' | 'E');
+            if !is_param_char(ch) && !exponent_sign {
+                break;
             }
+            self.next_one();
+            self.check_token_len(start)?;
         }
-        match Decimal::from_str(&self.input[start..self.current()]) {
-            Ok(val) => Ok(Token::Number(val, Span(start, self.current()))),
-            Err(_) => Err(Error::InvalidNumber(
-                self.input[start..self.current()].to_string(),
-            )),
+        let literal = &self.input[start..self.current()];
+        match parse_number(literal) {
+            Some(val) => Ok(Token::Number(val, Span(start, self.current()))),
+            None => Err(Error::InvalidNumber(literal.to_string(), Some(start))),
         }
     }
 
+    // Looks at the next char rather than peeking the next token, which would
+    // recurse once per word of a long run of them.
     fn function_or_reference_token(&self, atom: &'a str, start: usize) -> Result<Token<'a>> {
-        let peek = self.peek()?;
-        if peek.is_open_paren() {
+        let rest = &self.input[self.current()..];
+        let rest = match self.newlines {
+            true => rest.trim_start_matches(|ch| ch != '\n' && is_whitespace_char(ch)),
+            false => rest.trim_start_matches(is_whitespace_char),
+        };
+        if rest.starts_with('(') {
             return Ok(Token::Function(atom, Span(start, self.current())));
         }
         Ok(Token::Reference(atom, Span(start, self.current())))
@@ -221,16 +316,21 @@ impl<'a> Tokenizer<'a> {
 
     fn string_token(&mut self, start: usize) -> Result<Token<'a>> {
         let identifier = self.cur_char;
+        if self.input.as_bytes()[start..].starts_with(&[identifier as u8; 3]) {
+            return self.triple_string_token(start);
+        }
         let mut string_termmited = false;
-        loop {
-            match self.next_one() {
-                Some((_, ch)) => {
-                    if ch == identifier {
-                        string_termmited = true;
-                        break;
-                    }
-                }
-                None => break,
+        while let Some((at, ch)) = self.next_one() {
+            if ch == identifier {
+                string_termmited = true;
+                break;
+            }
+            if at + ch.len_utf8() - (start + 1) > self.max_string_len {
+                return Err(Error::TokenTooLong {
+                    start,
+                    kind: "string",
+                    limit: self.max_string_len,
+                });
             }
         }
         if !string_termmited {
@@ -242,14 +342,86 @@ impl<'a> Tokenizer<'a> {
         ))
     }
 
+    // A `'''` or `"""` string runs to the next three quotes of the same
+    // kind, so it can hold single quotes of either kind. An unterminated one
+    // reports where it starts, since its end is the end of the input. The
+    // closing quotes are only looked for within `max_string_len`.
+    fn triple_string_token(&mut self, start: usize) -> Result<Token<'a>> {
+        let body = start + 3;
+        let bytes = self.input.as_bytes();
+        let window = body
+            .saturating_add(self.max_string_len)
+            .saturating_add(3)
+            .min(bytes.len());
+        let len = bytes[body..window]
+            .windows(3)
+            .position(|quotes| quotes == &bytes[start..body])
+            .ok_or(match window < bytes.len() {
+                true => Error::TokenTooLong {
+                    start,
+                    kind: "string",
+                    limit: self.max_string_len,
+                },
+                false => Error::UnterminatedString(start),
+            })?;
+        let end = body + len + 3;
+        while self.current() < end {
+            self.next_one();
+        }
+        Ok(Token::String(
+            &self.input[body..body + len],
+            Span(start, end),
+        ))
+    }
+
+    // A name between backticks is a reference, or a function before `(`,
+    // whatever it holds, like `order total` or `in`. There are no escapes:
+    // a name holding backticks is quoted with a run of them of another
+    // length, e.g. ``a`b``, and ends at the next run of the same length.
+    // An unterminated one reports where it starts, like a string. The
+    // closing run is only looked for within `max_token_len`.
+    fn quoted_name_token(&mut self, start: usize) -> Result<Token<'a>> {
+        let fence = backtick_run(&self.input[start..]);
+        let body = start + fence;
+        let window = body
+            .saturating_add(self.max_token_len)
+            .saturating_add(1)
+            .min(self.input.len());
+        let mut at = body;
+        let end = loop {
+            let next = self.input.as_bytes()[at.min(window)..window]
+                .iter()
+                .position(|&b| b == b'`')
+                .ok_or(match window < self.input.len() {
+                    true => Error::TokenTooLong {
+                        start,
+                        kind: "token",
+                        limit: self.max_token_len,
+                    },
+                    false => Error::UnterminatedString(start),
+                })?;
+            let run = backtick_run(&self.input[at + next..]);
+            if run == fence {
+                break at + next;
+            }
+            at += next + run;
+        };
+        while self.current() < end + fence {
+            self.next_one();
+        }
+        self.function_or_reference_token(&self.input[body..end], start)
+    }
+
     fn bool_token(&mut self, start: usize, val: bool) -> Result<Token<'a>> {
         Ok(Token::Bool(val, Span(start, self.current())))
     }
 
     fn eat_whitespace(&mut self) -> Option<()> {
+        self.newline_before = false;
         loop {
             let (_, ch) = self.peek_one()?;
             if is_whitespace_char(ch) {
+                self.newline_before |= ch == '\n';
                 self.next_one();
             } else {
                 break;
@@ -265,36 +437,209 @@ impl<'a> Tokenizer<'a> {
             .map(|i| i.0)
             .unwrap_or_else(|| self.input.len())
     }
+
+    /// Consumes the tokenizer, returning an iterator over the remaining tokens
+    /// with their source text and position.
+    pub fn spanned(self) -> SpannedTokens<'a> {
+        SpannedTokens::new(self, false)
+    }
+
+    /// Like [`Tokenizer::spanned`], but also yields the whitespace between
+    /// tokens, so that concatenating the text of every item reproduces the
+    /// input exactly.
+    pub fn spanned_with_trivia(self) -> SpannedTokens<'a> {
+        SpannedTokens::new(self, true)
+    }
 }
 
-fn is_digit_char(ch: char) -> bool {
-    return '0' <= ch && ch <= '9' || ch == '.' || ch == '-' || ch == 'e' || ch == 'E' || ch == '+';
+pub struct SpannedTokens<'a> {
+    tokenizer: Tokenizer<'a>,
+    trivia: bool,
+    pos: usize,
+    line: usize,
+    column: usize,
+    pending: Option<SpannedToken<'a>>,
+    done: bool,
+}
+
+impl<'a> SpannedTokens<'a> {
+    fn new(tokenizer: Tokenizer<'a>, trivia: bool) -> Self {
+        Self {
+            tokenizer,
+            trivia,
+            pos: 0,
+            line: 1,
+            column: 1,
+            pending: None,
+            done: false,
+        }
+    }
+
+    fn advance(&mut self, to: usize) {
+        for ch in self.tokenizer.input[self.pos..to].chars() {
+            if ch == '\n' {
+                self.line += 1;
+                self.column = 1;
+            } else {
+                self.column += 1;
+            }
+        }
+        self.pos = to;
+    }
+
+    fn spanned_token(&mut self, kind: TokenKind, span: Span) -> SpannedToken<'a> {
+        self.advance(span.0);
+        let (line, column) = (self.line, self.column);
+        self.advance(span.1);
+        SpannedToken {
+            kind,
+            text: &self.tokenizer.input[span.0..span.1],
+            span,
+            line,
+            column,
+        }
+    }
+}
+
+impl<'a> Iterator for SpannedTokens<'a> {
+    type Item = Result<SpannedToken<'a>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(token) = self.pending.take() {
+            return Some(Ok(token));
+        }
+        if self.done {
+            return None;
+        }
+        let token = match self.tokenizer.next() {
+            Ok(token) => token,
+            Err(err) => {
+                self.done = true;
+                return Some(Err(err));
+            }
+        };
+        let start = token
+            .span()
+            .map_or(self.tokenizer.input.len(), |span| span.0);
+        let whitespace = if self.trivia && start > self.pos {
+            Some(self.spanned_token(TokenKind::Whitespace, Span(self.pos, start)))
+        } else {
+            None
+        };
+        match (token.kind(), token.span()) {
+            (Some(kind), Some(span)) => {
+                let token = self.spanned_token(kind, span);
+                match whitespace {
+                    Some(whitespace) => {
+                        self.pending = Some(token);
+                        Some(Ok(whitespace))
+                    }
+                    None => Some(Ok(token)),
+                }
+            }
+            _ => {
+                self.done = true;
+                whitespace.map(Ok)
+            }
+        }
+    }
+}
+
+/// The quotes to write `val` between so that it tokenizes back to itself,
+/// trying `quote` first, then the other kind, then three of each. There are
+/// no escapes, so a string holding both `'''` and `"""`, or one of them
+/// and ending with the other kind of quote, has none.
+pub(crate) fn string_quotes(val: &str, quote: char) -> Option<&'static str> {
+    let (single, triple) = match quote {
+        '\'' => (["'", "\""], ["'''", "\"\"\""]),
+        _ => (["\"", "'"], ["\"\"\"", "'''"]),
+    };
+    let single = single.into_iter().find(|q| !val.contains(q));
+    // Quotes ending the string would run into the closing ones.
+    single.or_else(|| {
+        triple
+            .into_iter()
+            .find(|q| !val.contains(q) && !val.ends_with(&q[..1]))
+    })
+}
+
+/// The backticks to quote the reference `name` with so that it reads back
+/// as one, or `None` if it reads back as is, like `a.b`. A name made of
+/// backticks at either end can't be quoted, and is also `None`.
+pub(crate) fn name_quotes(name: &str) -> Option<String> {
+    let mut tokenizer = Tokenizer::new(name);
+    let plain = matches!(tokenizer.next(), Ok(Token::Reference(atom, _)) if atom == name)
+        && matches!(tokenizer.next(), Ok(Token::EOF));
+    if plain || name.is_empty() || name.starts_with('`') || name.ends_with('`') {
+        return None;
+    }
+    let mut runs = Vec::new();
+    let mut rest = name;
+    while let Some(at) = rest.find('`') {
+        let run = backtick_run(&rest[at..]);
+        runs.push(run);
+        rest = &rest[at + run..];
+    }
+    let fence = (1..).find(|len| !runs.contains(len)).unwrap_or(1);
+    Some("`".repeat(fence))
+}
+
+fn backtick_run(input: &str) -> usize {
+    input.len() - input.trim_start_matches('`').len()
+}
+
+// The value of `s` if it's written like a number literal, without a sign:
+// digits with an optional fraction, or a fraction alone, then an optional
+// exponent like `e-3`. `None` too for a number out of the range of a
+// `Decimal`.
+pub(crate) fn parse_number(s: &str) -> Option<Decimal> {
+    let digits = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+    let (mantissa, exponent) = match s.split_once(['e', 'E']) {
+        Some((mantissa, exponent)) => (mantissa, Some(exponent)),
+        None => (s, None),
+    };
+    let valid = match mantissa.split_once('.') {
+        Some((int, fraction)) => (int.is_empty() || digits(int)) && digits(fraction),
+        None => digits(mantissa),
+    };
+    match exponent {
+        _ if !valid => None,
+        Some(exponent) if digits(exponent.strip_prefix(['+', '-']).unwrap_or(exponent)) => {
+            Decimal::from_scientific(s).ok()
+        }
+        Some(_) => None,
+        None => Decimal::from_str(s).ok(),
+    }
 }
 
 fn is_whitespace_char(ch: char) -> bool {
-    return ch == ' ' || ch == '\t' || ch == '\r' || ch == '\n';
+    ch == ' ' || ch == '\t' || ch == '\r' || ch == '\n'
 }
 
 fn is_delim_char(ch: char) -> bool {
-    return ch == '(' || ch == ')' || ch == '[' || ch == ']' || ch == '{' || ch == '}';
+    ch == '(' || ch == ')' || ch == '[' || ch == ']' || ch == '{' || ch == '}'
+}
+
+fn is_key_start_char(ch: char) -> bool {
+    ch.is_ascii_alphabetic() || ch == '_'
 }
 
 fn is_param_char(ch: char) -> bool {
-    return ('0' <= ch && ch <= '9')
-        || ('a' <= ch && ch <= 'z')
-        || ('A' <= ch && ch <= 'Z')
+    ch.is_ascii_digit()
+        || ch.is_ascii_lowercase()
+        || ch.is_ascii_uppercase()
         || ch == '.'
-        || ch == '_';
+        || ch == '_'
 }
 
 #[cfg(test)]
 mod tests {
     use super::Tokenizer;
-    use crate::init::init;
     use crate::token::DelimTokenType;
     use crate::token::Span;
     use crate::token::Token;
     use crate::token::Token::*;
+    use crate::token::TokenKind;
     use rstest::rstest;
     use rust_decimal::prelude::*;
 
@@ -309,25 +654,42 @@ mod tests {
         #[case] start: usize,
         #[case] end: usize,
     ) {
-        init();
         let mut tokenizer = Tokenizer::new(input);
         let ans = tokenizer.next().unwrap();
         assert_eq!(ans, Bool(value, Span(start, end)))
     }
 
+    #[rstest]
+    #[case("null", 0, 4)]
+    #[case(" Null", 1, 5)]
+    #[case("none ", 0, 4)]
+    #[case(" None", 1, 5)]
+    fn test_null(#[case] input: &str, #[case] start: usize, #[case] end: usize) {
+        let mut tokenizer = Tokenizer::new(input);
+        let ans = tokenizer.next().unwrap();
+        assert_eq!(ans, Null(&input[start..end], Span(start, end)))
+    }
+
     #[rstest]
     #[case(" 1234 ", "1234", 1, 5)]
     #[case(" 5.678 ", "5.678", 1, 6)]
-    // #[case(" 10e-3 ", "10e-3", 1, 6)]
-    // #[case(" 10e03 ", "10e03", 1, 6)]
-    // #[case(" 2e+3 ", "2e+3", 1, 5)]
+    #[case(".5", "0.5", 0, 2)]
+    #[case(" .25+1", "0.25", 1, 4)]
+    #[case("007)", "7", 0, 3)]
+    #[case("1.5*2", "1.5", 0, 3)]
+    #[case("3-1", "3", 0, 1)]
+    #[case(" 10e-3 ", "0.010", 1, 6)]
+    #[case(" 10e03 ", "10000", 1, 6)]
+    #[case(" 2e+3 ", "2000", 1, 5)]
+    #[case("1e3", "1000", 0, 3)]
+    #[case("2.5E-2 + 1", "0.025", 0, 6)]
+    #[case(".5e1)", "5", 0, 4)]
     fn test_number(
         #[case] input: &str,
         #[case] value: &str,
         #[case] start: usize,
         #[case] end: usize,
     ) {
-        init();
         let mut tokenizer = Tokenizer::new(input);
         let ans = tokenizer.next().unwrap();
         assert_eq!(
@@ -339,6 +701,44 @@ mod tests {
         )
     }
 
+    #[rstest]
+    #[case("1.2.3", "1.2.3", 0)]
+    #[case(" 1..2 ", "1..2", 1)]
+    #[case("1.", "1.", 0)]
+    #[case("a + 1. ", "1.", 4)]
+    #[case("2abc", "2abc", 0)]
+    #[case("x = 10_000", "10_000", 4)]
+    #[case("0x1F", "0x1F", 0)]
+    #[case("1e", "1e", 0)]
+    #[case("a * 1e+ 2", "1e+", 4)]
+    #[case("1e-x", "1e-x", 0)]
+    #[case("1e2.5", "1e2.5", 0)]
+    #[case("1e2e3", "1e2e3", 0)]
+    #[case("1.e3", "1.e3", 0)]
+    #[case("1E99", "1E99", 0)]
+    #[case(".5.5", ".5.5", 0)]
+    #[case(
+        "99999999999999999999999999999999",
+        "99999999999999999999999999999999",
+        0
+    )]
+    fn test_invalid_number(#[case] input: &str, #[case] literal: &str, #[case] start: usize) {
+        let mut tokenizer = Tokenizer::new(input);
+        let err = loop {
+            match tokenizer.next() {
+                Ok(EOF) => panic!("{} tokenized", input),
+                Ok(_) => continue,
+                Err(err) => break err,
+            }
+        };
+        assert!(
+            matches!(&err, crate::error::Error::InvalidNumber(s, Some(p)) if s == literal && *p == start),
+            "{:?}",
+            err
+        );
+        assert_eq!(err.position(), Some(start));
+    }
+
     #[rstest]
     #[case(" { ", DelimTokenType::OpenBrace, 1, 2)]
     #[case(" } ", DelimTokenType::CloseBrace, 1, 2)]
@@ -352,7 +752,6 @@ mod tests {
         #[case] start: usize,
         #[case] end: usize,
     ) {
-        init();
         let mut tokenizer = Tokenizer::new(input);
         let ans = tokenizer.next().unwrap();
         assert_eq!(ans, Delim(typ, Span(start, end)))
@@ -368,7 +767,6 @@ mod tests {
     #[case(" beginWith", Operator("beginWith", Span(1, 10)))]
     #[case(" endWith", Operator("endWith", Span(1, 8)))]
     fn test_other(#[case] input: &str, #[case] output: Token) {
-        init();
         let mut tokenizer = Tokenizer::new(input);
         let ans = tokenizer.next().unwrap();
         assert_eq!(ans, output);
@@ -377,34 +775,147 @@ mod tests {
     #[rstest]
     #[case(" 'dsfasdfdsa' ", "dsfasdfdsa", 1, 13)]
     #[case("\"dffd\"", "dffd", 0, 6)]
+    #[case("''", "", 0, 2)]
+    #[case("'''it's'''", "it's", 0, 10)]
+    #[case("\"\"\"say \"hi\"\"\"\" ", "say \"hi", 0, 13)]
+    #[case("'''a\nb''' + 1", "a\nb", 0, 9)]
+    #[case("'''a\r\n  b\r\n'''", "a\r\n  b\r\n", 0, 14)]
+    #[case("''''''", "", 0, 6)]
+    #[case("''''a'''", "'a", 0, 8)]
     fn test_string(
         #[case] input: &str,
         #[case] value: &str,
         #[case] start: usize,
         #[case] end: usize,
     ) {
-        init();
         let mut tokenizer = Tokenizer::new(input);
         let ans = tokenizer.next().unwrap();
         assert_eq!(ans, String(value, Span(start, end)));
     }
 
+    #[rstest]
+    #[case("'abc", 4)]
+    #[case("'''abc", 0)]
+    #[case(" '''abc''", 1)]
+    #[case("1 + \"\"\"abc\n'''", 4)]
+    #[case("`abc", 0)]
+    #[case(" ``a`b`", 1)]
+    fn test_unterminated_string(#[case] input: &str, #[case] position: usize) {
+        let mut tokenizer = Tokenizer::new(input);
+        let err = loop {
+            match tokenizer.next() {
+                Ok(EOF) => panic!("{} tokenized", input),
+                Ok(_) => continue,
+                Err(err) => break err,
+            }
+        };
+        assert!(matches!(err, crate::error::Error::UnterminatedString(p) if p == position));
+    }
+
     #[rstest]
     #[case(" d09f_5 ", Reference("d09f_5", Span(1, 7)))]
     #[case(" d09f_5() ", Function("d09f_5", Span(1, 7)))]
     #[case(" d09f_>", Reference("d09f_", Span(1, 6)))]
+    #[case("a?.b?.c ", Reference("a?.b?.c", Span(0, 7)))]
+    #[case("a?.b??c", Reference("a?.b", Span(0, 4)))]
+    #[case("a?.5:1", Reference("a", Span(0, 1)))]
+    #[case("a?. b", Reference("a", Span(0, 1)))]
+    #[case("`order total` * 1.2", Reference("order total", Span(0, 13)))]
+    #[case(" `in`", Reference("in", Span(1, 5)))]
+    #[case("``a`b`` + 1", Reference("a`b", Span(0, 7)))]
+    #[case("`a``b` ", Reference("a``b", Span(0, 6)))]
+    #[case("`my f` (1)", Function("my f", Span(0, 6)))]
     fn test_reference_function(#[case] input: &str, #[case] output: Token) {
-        init();
         let mut tokenizer = Tokenizer::new(input);
         let ans = tokenizer.next().unwrap();
         assert_eq!(ans, output);
     }
 
+    #[rstest]
+    #[case("a", None)]
+    #[case("user?.name.x_1", None)]
+    #[case("order total", Some("`"))]
+    #[case("in", Some("`"))]
+    #[case("null", Some("`"))]
+    #[case("1st", Some("`"))]
+    #[case("a`b", Some("``"))]
+    #[case("a``b`c", Some("```"))]
+    #[case("`a", None)]
+    fn test_name_quotes(#[case] name: &str, #[case] quotes: Option<&str>) {
+        assert_eq!(super::name_quotes(name).as_deref(), quotes);
+    }
+
+    #[test]
+    fn test_newline_before() {
+        let mut tokenizer = Tokenizer::new("a +\n b\r\n\t-  c\n");
+        let mut newlines = Vec::new();
+        while !tokenizer.next().unwrap().is_eof() {
+            newlines.push(tokenizer.newline_before());
+        }
+        assert_eq!(newlines, [false, false, true, true, false]);
+        assert!(tokenizer.newline_before());
+    }
+
+    #[rstest]
+    #[case("f (1)", true, true)]
+    #[case("f\n(1)", true, false)]
+    #[case("f\n(1)", false, true)]
+    fn test_function_newlines(#[case] input: &str, #[case] newlines: bool, #[case] call: bool) {
+        let mut tokenizer = Tokenizer::new(input);
+        tokenizer.set_newlines(newlines);
+        let ans = tokenizer.next().unwrap();
+        assert_eq!(matches!(ans, Function(..)), call);
+    }
+
+    #[rstest]
+    #[case("")]
+    #[case("  ")]
+    #[case("a = 3;\n  b += a * (2 - 1)\t; f(b, 'x y')  ")]
+    #[case("\r\n{'k': [1, 2.5, true]} beginWith 'a'\n")]
+    fn test_spanned_with_trivia_roundtrip(#[case] input: &str) {
+        let tokens: Vec<_> = Tokenizer::new(input)
+            .spanned_with_trivia()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        let text: std::string::String = tokens.iter().map(|token| token.text).collect();
+        assert_eq!(text, input);
+    }
+
+    #[test]
+    fn test_spanned() {
+        let input = "a +\n  f('b')";
+        let tokens: Vec<_> = Tokenizer::new(input)
+            .spanned()
+            .map(|token| {
+                let token = token.unwrap();
+                (token.kind, token.text, token.span, token.line, token.column)
+            })
+            .collect();
+        assert_eq!(
+            tokens,
+            vec![
+                (TokenKind::Reference, "a", Span(0, 1), 1, 1),
+                (TokenKind::Operator, "+", Span(2, 3), 1, 3),
+                (TokenKind::Function, "f", Span(6, 7), 2, 3),
+                (TokenKind::Delim, "(", Span(7, 8), 2, 4),
+                (TokenKind::String, "'b'", Span(8, 11), 2, 5),
+                (TokenKind::Delim, ")", Span(11, 12), 2, 8),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_spanned_error() {
+        let mut tokens = Tokenizer::new("1 'b").spanned();
+        assert!(tokens.next().unwrap().is_ok());
+        assert!(tokens.next().unwrap().is_err());
+        assert!(tokens.next().is_none());
+    }
+
     #[rstest]
     #[case("\"jajd'")]
     #[case("0e.3")]
     fn test_err(#[case] input: &str) {
-        init();
         let mut tokenizer = Tokenizer::new(input);
         let ans = tokenizer.next();
         assert!(ans.is_err())
@@ -0,0 +1,154 @@
+use crate::compat::Mutex;
+use crate::context::Context;
+use crate::define::Result;
+use crate::parser::ExprAST;
+use crate::value::Value;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+/// Called by [`ExprAST::exec`] with each node it evaluated and its value,
+/// see [`Context::set_trace_hook`].
+pub type TraceHook = dyn Fn(&ExprAST, &Value) + Send + Sync;
+
+/// A node evaluated by [`ExprAST::exec_traced`], with the nodes evaluated
+/// to compute it. Nodes that were skipped, like the branch a ternary didn't
+/// take, have no event.
+#[derive(Clone, PartialEq, Debug)]
+pub struct TraceEvent {
+    pub expr: String,
+    pub value: Value,
+    pub children: Vec<TraceEvent>,
+}
+
+impl<'a> ExprAST<'a> {
+    /// Evaluates the expression like [`ExprAST::exec`] and also returns the
+    /// tree of intermediate values. Usually there is a single root event for
+    /// the whole expression; after an error, the roots are the sub-trees
+    /// that were completed before it. Any hook set on the context is
+    /// restored afterwards and isn't called.
+    pub fn exec_traced(&self, ctx: &mut Context) -> (Result<Value>, Vec<TraceEvent>) {
+        // Events are completed children first, so when a node completes its
+        // children are the most recent pending events, which are told apart
+        // by the address of their node.
+        let pending: Arc<Mutex<Vec<(usize, TraceEvent)>>> = Arc::default();
+        let sink = pending.clone();
+        let hook: Arc<TraceHook> = Arc::new(move |expr, value| {
+            let children: Vec<usize> = expr.children().into_iter().map(address).collect();
+            let mut pending = sink.lock().unwrap();
+            let mut start = pending.len();
+            while start > 0 && children.contains(&pending[start - 1].0) {
+                start -= 1;
+            }
+            let event = TraceEvent {
+                expr: expr.expr(),
+                value: value.clone(),
+                children: pending.drain(start..).map(|(_, event)| event).collect(),
+            };
+            pending.push((address(expr), event));
+        });
+
+        let ans = self.exec_with_hook(ctx, hook);
+        let events = core::mem::take(&mut *pending.lock().unwrap());
+        (ans, events.into_iter().map(|(_, event)| event).collect())
+    }
+
+    // Runs `exec` with `hook` in place of the context's own.
+    pub(crate) fn exec_with_hook(&self, ctx: &mut Context, hook: Arc<TraceHook>) -> Result<Value> {
+        let previous = ctx.trace_hook().cloned();
+        ctx.set_trace_hook(Some(hook));
+        let ans = self.exec(ctx);
+        ctx.set_trace_hook(previous);
+        ans
+    }
+}
+
+// Identifies a node of the tree being evaluated.
+pub(crate) fn address(expr: &ExprAST) -> usize {
+    expr as *const ExprAST as usize
+}
+
+#[cfg(all(test, feature = "default"))]
+mod tests {
+    use super::TraceEvent;
+    use crate::{create_context, parse_expression, Error, Value};
+    use std::sync::{Arc, Mutex};
+
+    fn event(expr: &str, value: Value, children: Vec<TraceEvent>) -> TraceEvent {
+        TraceEvent {
+            expr: expr.to_string(),
+            value,
+            children,
+        }
+    }
+
+    #[test]
+    fn test_exec_traced() {
+        let ast = parse_expression("2>3 || 4>1").unwrap();
+        let (ans, events) = ast.exec_traced(&mut create_context!());
+        assert_eq!(ans.unwrap(), true.into());
+        assert_eq!(
+            events,
+            vec![event(
+                "2 > 3 || 4 > 1",
+                true.into(),
+                vec![
+                    event(
+                        "2 > 3",
+                        false.into(),
+                        vec![event("2", 2.into(), vec![]), event("3", 3.into(), vec![])]
+                    ),
+                    event(
+                        "4 > 1",
+                        true.into(),
+                        vec![event("4", 4.into(), vec![]), event("1", 1.into(), vec![])]
+                    ),
+                ]
+            )]
+        );
+    }
+
+    #[test]
+    fn test_exec_traced_skipped_branch() {
+        let ast = parse_expression("a > 1 ? max(a, 3) : b").unwrap();
+        let (ans, events) = ast.exec_traced(&mut create_context!("a" => 2));
+        assert_eq!(ans.unwrap(), 3.into());
+        assert_eq!(events.len(), 1);
+        let children: Vec<&str> = events[0]
+            .children
+            .iter()
+            .map(|event| event.expr.as_str())
+            .collect();
+        assert_eq!(children, ["a > 1", "max(a,3)"]);
+    }
+
+    #[test]
+    fn test_exec_traced_error() {
+        let ast = parse_expression("[1 + 1, a - 'x']").unwrap();
+        let (ans, events) = ast.exec_traced(&mut create_context!("a" => 1));
+        assert!(matches!(ans.unwrap_err().root(), Error::ShouldBeNumber()));
+        let roots: Vec<&str> = events.iter().map(|event| event.expr.as_str()).collect();
+        assert_eq!(roots, ["1 + 1", "a", "\"x\""]);
+    }
+
+    #[test]
+    fn test_trace_hook() {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let sink = seen.clone();
+        let mut ctx = create_context!("a" => 1);
+        ctx.set_trace_hook(Some(Arc::new(move |expr, value| {
+            sink.lock()
+                .unwrap()
+                .push(format!("{} = {}", expr.expr(), value));
+        })));
+        let ast = parse_expression("a + 2").unwrap();
+        assert_eq!(ast.exec(&mut ctx).unwrap(), 3.into());
+        // exec_traced restores the hook afterwards.
+        ast.exec_traced(&mut ctx).0.unwrap();
+        ast.exec(&mut ctx).unwrap();
+        assert_eq!(
+            *seen.lock().unwrap(),
+            ["a = 1", "2 = 2", "a + 2 = 3", "a = 1", "2 = 2", "a + 2 = 3"]
+        );
+    }
+}
@@ -0,0 +1,249 @@
+use crate::define::Result;
+use crate::error::Error;
+use crate::function::{Arity, FunctionMetadata, InnerFunctionManager};
+use crate::value::Value;
+use alloc::string::ToString;
+use alloc::sync::Arc;
+use core::str::FromStr;
+use rust_decimal::Decimal;
+
+// Each unit with the milliseconds it stands for, matched ignoring case.
+const DURATION_UNITS: [(&str, Decimal); 8] = [
+    ("ns", Decimal::from_parts(1, 0, 0, false, 6)),
+    ("us", Decimal::from_parts(1, 0, 0, false, 3)),
+    ("µs", Decimal::from_parts(1, 0, 0, false, 3)),
+    ("μs", Decimal::from_parts(1, 0, 0, false, 3)),
+    ("ms", Decimal::ONE),
+    ("s", Decimal::ONE_THOUSAND),
+    ("m", Decimal::from_parts(60_000, 0, 0, false, 0)),
+    ("h", Decimal::from_parts(3_600_000, 0, 0, false, 0)),
+];
+
+impl InnerFunctionManager {
+    pub(crate) fn init_units(&mut self) {
+        self.builtin(
+            FunctionMetadata::new(
+                "parseDuration",
+                Arity::Exact(1),
+                &[("s", "String")],
+                "The milliseconds a duration like '250ms', '1.5s' or '1h 30m' stands for. The units are ns, us, ms, s, m and h.",
+            )
+            .deterministic(),
+            Arc::new(|params| {
+                let [s] = <[Value; 1]>::try_from(params).map_err(|_| Error::ParamInvalid())?;
+                Ok(Value::Number(parse_duration(&s.string()?)?))
+            }),
+        );
+
+        self.builtin(
+            FunctionMetadata::new(
+                "parseBytes",
+                Arity::Exact(1),
+                &[("s", "String")],
+                "The bytes a size like '512B', '10 MB' or '1.5GiB' stands for. KB, MB, GB and TB are powers of 1000, KiB, MiB, GiB and TiB powers of 1024.",
+            )
+            .deterministic(),
+            Arc::new(|params| {
+                let [s] = <[Value; 1]>::try_from(params).map_err(|_| Error::ParamInvalid())?;
+                Ok(Value::Number(parse_bytes(&s.string()?)?))
+            }),
+        );
+    }
+}
+
+// A sum of numbers with units, like `1h 30m`. Each unit may appear once,
+// from the largest down, so `30m1h` or `1s 1s` is rejected rather than
+// guessed at.
+fn parse_duration(input: &str) -> Result<Decimal> {
+    let invalid = || Error::InvalidQuantity("duration", input.to_string());
+    let mut rest = input.trim();
+    if rest.is_empty() {
+        return Err(invalid());
+    }
+    let mut total = Decimal::ZERO;
+    let mut last = None;
+    while !rest.is_empty() {
+        let (number, after) = number(rest).ok_or_else(invalid)?;
+        let after = after.trim_start();
+        let end = after
+            .find(|ch: char| ch.is_ascii_digit() || ch.is_whitespace() || ch == '.')
+            .unwrap_or(after.len());
+        let unit = &after[..end];
+        let (_, millis) = DURATION_UNITS
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(unit))
+            .ok_or_else(invalid)?;
+        if last.is_some_and(|last| *millis >= last) {
+            return Err(invalid());
+        }
+        last = Some(*millis);
+        total = number
+            .checked_mul(*millis)
+            .and_then(|part| total.checked_add(part))
+            .ok_or_else(invalid)?;
+        rest = after[end..].trim_start();
+    }
+    Ok(total.normalize())
+}
+
+// One number with one unit. The unit is matched ignoring case, except that
+// a lowercase `b` after an uppercase prefix, like `Gb`, reads as bits and
+// is rejected.
+fn parse_bytes(input: &str) -> Result<Decimal> {
+    let invalid = || Error::InvalidQuantity("size", input.to_string());
+    let (number, unit) = number(input.trim()).ok_or_else(invalid)?;
+    let unit = unit.trim_start();
+    let prefix = match (unit.strip_suffix('B'), unit.strip_suffix('b')) {
+        (Some(prefix), _) => prefix,
+        (_, Some(prefix)) if !prefix.chars().any(char::is_uppercase) => prefix,
+        _ => return Err(invalid()),
+    };
+    let (base, power) = match prefix.to_ascii_lowercase().as_str() {
+        "" => (1000, 0),
+        "k" => (1000, 1),
+        "m" => (1000, 2),
+        "g" => (1000, 3),
+        "t" => (1000, 4),
+        "ki" => (1024, 1),
+        "mi" => (1024, 2),
+        "gi" => (1024, 3),
+        "ti" => (1024, 4),
+        _ => return Err(invalid()),
+    };
+    let base = Decimal::from(base);
+    (0..power)
+        .try_fold(number, |bytes, _| bytes.checked_mul(base))
+        .map(|bytes| bytes.normalize())
+        .ok_or_else(invalid)
+}
+
+// A number of digits with an optional fraction at the start of `s`, and
+// what follows it.
+fn number(s: &str) -> Option<(Decimal, &str)> {
+    let end = s
+        .find(|ch: char| !ch.is_ascii_digit() && ch != '.')
+        .unwrap_or(s.len());
+    let digits = &s[..end];
+    if !digits.starts_with(|ch: char| ch.is_ascii_digit()) || digits.ends_with('.') {
+        return None;
+    }
+    Some((Decimal::from_str(digits).ok()?, &s[end..]))
+}
+
+#[cfg(all(test, feature = "default"))]
+mod tests {
+    use super::{parse_bytes, parse_duration};
+    use crate::{create_context, execute, Error, Value};
+    use rstest::rstest;
+    use rust_decimal::Decimal;
+    use std::str::FromStr;
+
+    #[rstest]
+    #[case("250ms", "250")]
+    #[case("1s", "1000")]
+    #[case("1.5s", "1500")]
+    #[case("2m", "120000")]
+    #[case("1h", "3600000")]
+    #[case("500us", "0.5")]
+    #[case("500µs", "0.5")]
+    #[case("1ns", "0.000001")]
+    #[case("0ms", "0")]
+    #[case("1h30m", "5400000")]
+    #[case("1h 30m 15s", "5415000")]
+    #[case("1m 0.5s 250ms", "60750")]
+    #[case("  250 ms  ", "250")]
+    #[case("250MS", "250")]
+    #[case("1H 2M", "3720000")]
+    #[case("3.25S", "3250")]
+    fn test_parse_duration(#[case] input: &str, #[case] millis: &str) {
+        assert_eq!(
+            parse_duration(input).unwrap(),
+            Decimal::from_str(millis).unwrap()
+        );
+    }
+
+    #[rstest]
+    #[case("")]
+    #[case("   ")]
+    #[case("250")]
+    #[case("ms")]
+    #[case("250 mss")]
+    #[case("1d")]
+    #[case("-5s")]
+    #[case("+5s")]
+    #[case(".5s")]
+    #[case("5.s")]
+    #[case("1.2.3s")]
+    #[case("30m 1h")]
+    #[case("1s 1s")]
+    #[case("1us 1µs")]
+    #[case("1h,30m")]
+    #[case("1e3ms")]
+    fn test_parse_duration_invalid(#[case] input: &str) {
+        assert!(
+            matches!(parse_duration(input), Err(Error::InvalidQuantity("duration", s)) if s == input),
+            "{}",
+            input
+        );
+    }
+
+    #[rstest]
+    #[case("512B", "512")]
+    #[case("0B", "0")]
+    #[case("1KB", "1000")]
+    #[case("1kb", "1000")]
+    #[case("1kB", "1000")]
+    #[case("1KiB", "1024")]
+    #[case("1kib", "1024")]
+    #[case("10 MB", "10000000")]
+    #[case("2MiB", "2097152")]
+    #[case("1.5GiB", "1610612736")]
+    #[case("1.5GB", "1500000000")]
+    #[case("1TB", "1000000000000")]
+    #[case("1TiB", "1099511627776")]
+    #[case("  4 GIB ", "4294967296")]
+    #[case("0.5KiB", "512")]
+    fn test_parse_bytes(#[case] input: &str, #[case] bytes: &str) {
+        assert_eq!(
+            parse_bytes(input).unwrap(),
+            Decimal::from_str(bytes).unwrap()
+        );
+    }
+
+    #[rstest]
+    #[case("")]
+    #[case("1024")]
+    #[case("KB")]
+    #[case("1Gb")]
+    #[case("1Mib")]
+    #[case("1 K")]
+    #[case("1KIBB")]
+    #[case("1PB")]
+    #[case("1.5.GB")]
+    #[case("-1KB")]
+    #[case("1 2KB")]
+    #[case("1iB")]
+    fn test_parse_bytes_invalid(#[case] input: &str) {
+        assert!(
+            matches!(parse_bytes(input), Err(Error::InvalidQuantity("size", s)) if s == input),
+            "{}",
+            input
+        );
+    }
+
+    #[rstest]
+    #[case("latency > parseDuration('250ms')", true.into())]
+    #[case("size > units.parseBytes('1.5GiB')", false.into())]
+    #[case("parseDuration('1m') / parseDuration('1s')", 60.into())]
+    fn test_units_functions(#[case] input: &str, #[case] expected: Value) {
+        let ctx = create_context!("latency" => 300, "size" => 1_000_000);
+        assert_eq!(execute(input, ctx).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_units_error_message() {
+        let err = execute("parseBytes('1Gb')", create_context!()).unwrap_err();
+        assert_eq!(err.root().to_string(), "invalid size: '1Gb'");
+        assert!(execute("parseDuration(250)", create_context!()).is_err());
+    }
+}
@@ -1,43 +1,161 @@
+use crate::compat::{DefaultHasher, IndexMap};
 use crate::define::Result;
 use crate::error::Error;
+use crate::tokenizer::string_quotes;
+use alloc::borrow::Cow;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::cmp::Ordering;
+use core::fmt;
+use core::hash::{Hash, Hasher};
 use rust_decimal::prelude::*;
-use std::fmt;
 
-#[derive(Clone, PartialEq, Debug)]
+/// An insertion-ordered map keyed by values. Equality ignores the order of
+/// the entries, and inserting an existing key replaces its value in place,
+/// so the last duplicate wins but keeps the position of the first.
+pub type Map = IndexMap<Value, Value>;
+
+#[derive(Clone, PartialEq, Eq, Debug)]
 pub enum Value {
     String(String),
     Number(Decimal),
     Bool(bool),
     List(Vec<Value>),
-    Map(Vec<(Value, Value)>),
+    Map(Map),
     None,
 }
 
-#[cfg(not(tarpaulin_include))]
+/// The type of a [`Value`], without its contents, for registering an
+/// operator for a pair of types with [`crate::register_typed_infix_op`].
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum ValueType {
+    String,
+    Number,
+    Bool,
+    List,
+    Map,
+    None,
+}
+
+impl ValueType {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::String => "String",
+            Self::Number => "Number",
+            Self::Bool => "Bool",
+            Self::List => "List",
+            Self::Map => "Map",
+            Self::None => "None",
+        }
+    }
+}
+
+impl Hash for Value {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        core::mem::discriminant(self).hash(state);
+        match self {
+            Self::String(val) => val.hash(state),
+            Self::Number(val) => val.hash(state),
+            Self::Bool(val) => val.hash(state),
+            Self::List(values) => values.hash(state),
+            Self::Map(m) => {
+                // Entries are combined with a commutative sum so that maps
+                // which are equal in any order hash the same.
+                let mut sum: u64 = 0;
+                for entry in m {
+                    let mut hasher = DefaultHasher::new();
+                    entry.hash(&mut hasher);
+                    sum = sum.wrapping_add(hasher.finish());
+                }
+                m.len().hash(state);
+                sum.hash(state);
+            }
+            Self::None => {}
+        }
+    }
+}
+
+// The value in the literal syntax of the language, which parses back to an
+// equal value: `{'a': 1, 'b': [2, "it's"]}`. Strings are quoted with `'`
+// unless they hold one, map entries keep their insertion order, numbers
+// keep their scale and `None` is `null`. The form is stable, so a logged
+// result diffs meaningfully; `Debug` is the diagnostic one.
 impl fmt::Display for Value {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Self::String(val) => write!(f, "value string: {}", val.clone()),
-            Self::Number(val) => write!(f, "value number: {}", val.clone()),
-            Self::Bool(val) => write!(f, "value bool: {}", val.clone()),
+            Self::String(val) => {
+                let quotes = string_quotes(val, '\'').unwrap_or("'");
+                write!(f, "{}{}{}", quotes, val, quotes)
+            }
+            Self::Number(val) => write!(f, "{}", val),
+            Self::Bool(val) => write!(f, "{}", val),
             Self::List(values) => {
-                let mut s = String::from("[");
-                for value in values {
-                    s.push_str(format!("{},", value.clone()).as_str());
+                f.write_str("[")?;
+                for (i, value) in values.iter().enumerate() {
+                    let sep = if i == 0 { "" } else { ", " };
+                    write!(f, "{}{}", sep, value)?;
+                }
+                f.write_str("]")
+            }
+            Self::Map(m) => {
+                f.write_str("{")?;
+                for (i, (k, v)) in m.iter().enumerate() {
+                    let sep = if i == 0 { "" } else { ", " };
+                    write!(f, "{}{}: {}", sep, k, v)?;
                 }
-                s.push_str("]");
-                write!(f, "value list: {}", s)
+                f.write_str("}")
             }
+            Self::None => f.write_str("null"),
+        }
+    }
+}
+
+// Values of the same type are ordered naturally: numbers by value, strings
+// lexicographically, `false` before `true` and lists element by element.
+// Maps only compare equal to an equal map, and different types don't compare.
+impl PartialOrd for Value {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        match (self, other) {
+            (Self::String(a), Self::String(b)) => a.partial_cmp(b),
+            (Self::Number(a), Self::Number(b)) => a.partial_cmp(b),
+            (Self::Bool(a), Self::Bool(b)) => a.partial_cmp(b),
+            (Self::List(a), Self::List(b)) => a.partial_cmp(b),
+            (Self::Map(a), Self::Map(b)) if a == b => Some(Ordering::Equal),
+            (Self::None, Self::None) => Some(Ordering::Equal),
+            _ => None,
+        }
+    }
+}
+
+// Numbers are written as integers when they fit an `i64` and as floats
+// otherwise. Keys that aren't strings are written as their literal text,
+// since most formats only have string keys.
+#[cfg(feature = "serde")]
+impl serde::Serialize for Value {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> core::result::Result<S::Ok, S::Error> {
+        use serde::ser::SerializeMap;
+        match self {
+            Self::String(val) => serializer.serialize_str(val),
+            Self::Number(val) => match val.normalize().to_i64() {
+                Some(int) if val.fract().is_zero() => serializer.serialize_i64(int),
+                _ => serializer.serialize_f64(val.to_f64().unwrap_or(f64::NAN)),
+            },
+            Self::Bool(val) => serializer.serialize_bool(*val),
+            Self::List(values) => serializer.collect_seq(values),
             Self::Map(m) => {
-                let mut s = String::from("{");
+                let mut map = serializer.serialize_map(Some(m.len()))?;
                 for (k, v) in m {
-                    s.push_str(format!("key: {},", k.clone()).as_str());
-                    s.push_str(format!("value: {}; ", v.clone()).as_str());
+                    match k {
+                        Self::String(key) => map.serialize_entry(key, v)?,
+                        key => map.serialize_entry(&key.to_string(), v)?,
+                    }
                 }
-                s.push_str("}");
-                write!(f, "value map: {}", s)
+                map.end()
             }
-            Self::None => write!(f, "None"),
+            Self::None => serializer.serialize_none(),
         }
     }
 }
@@ -66,6 +184,12 @@ impl From<Vec<Value>> for Value {
     }
 }
 
+impl From<Map> for Value {
+    fn from(value: Map) -> Self {
+        Value::Map(value)
+    }
+}
+
 impl From<Decimal> for Value {
     fn from(value: Decimal) -> Self {
         Value::Number(value)
@@ -73,6 +197,21 @@ impl From<Decimal> for Value {
 }
 
 impl Value {
+    pub fn type_name(&self) -> &'static str {
+        self.value_type().name()
+    }
+
+    pub fn value_type(&self) -> ValueType {
+        match self {
+            Self::String(_) => ValueType::String,
+            Self::Number(_) => ValueType::Number,
+            Self::Bool(_) => ValueType::Bool,
+            Self::List(_) => ValueType::List,
+            Self::Map(_) => ValueType::Map,
+            Self::None => ValueType::None,
+        }
+    }
+
     pub fn decimal(self) -> Result<rust_decimal::Decimal> {
         match self {
             Self::Number(val) => Ok(val),
@@ -90,38 +229,235 @@ impl Value {
     pub fn bool(self) -> Result<bool> {
         match self {
             Self::Bool(val) => Ok(val),
-            _ => Err(Error::ShouldBeBool()),
+            other => Err(Error::ShouldBeBool(other.type_name())),
+        }
+    }
+
+    /// Whether the value counts as true under [`crate::Truthiness::Coerce`]:
+    /// `None`, `false`, zero and empty strings, lists and maps are false,
+    /// everything else is true.
+    pub fn truthy(&self) -> bool {
+        match self {
+            Self::Bool(val) => *val,
+            Self::Number(val) => !val.is_zero(),
+            Self::String(val) => !val.is_empty(),
+            Self::List(val) => !val.is_empty(),
+            Self::Map(val) => !val.is_empty(),
+            Self::None => false,
         }
     }
 
+    /// The number as an `i64`. Numbers with a fractional part, or outside
+    /// the range of an `i64`, fail with [`Error::InvalidInteger`]; trailing
+    /// zeros like in `2.0` are fine.
     pub fn integer(self) -> Result<i64> {
         match self {
-            Self::Number(val) => val
-                .to_string()
-                .parse()
-                .map_or(Err(Error::InvalidInteger), |num| Ok(num)),
+            Self::Number(val) if val.fract().is_zero() => val.to_i64().ok_or(Error::InvalidInteger),
             _ => Err(Error::InvalidInteger),
         }
     }
 
     pub fn float(self) -> Result<f64> {
         match self {
-            Self::Number(val) => val
-                .to_string()
-                .parse()
-                .map_or(Err(Error::InvalidFloat), |num| Ok(num)),
+            Self::Number(val) => val.to_string().parse().map_or(Err(Error::InvalidFloat), Ok),
             _ => Err(Error::InvalidFloat),
         }
     }
 
+    /// Converts a float like `Value::from(f64)` does, rounding it to 15
+    /// significant digits, but fails with [`Error::UnrepresentableFloat`]
+    /// on NaN, infinities and magnitudes beyond a `Decimal` (about 7.9e28)
+    /// instead of turning them into `0`.
+    pub fn try_from_f64(value: f64) -> Result<Value> {
+        Decimal::from_f64(value)
+            .and_then(|val| val.round_sf(f64::DIGITS))
+            .map(|val| Value::Number(val.normalize()))
+            .ok_or(Error::UnrepresentableFloat(value))
+    }
+
+    /// The number as the nearest `f64`. Numbers of up to 15 significant
+    /// digits round-trip through [`Value::try_from_f64`] unchanged; longer
+    /// ones lose the digits a float can't hold.
+    pub fn to_f64(&self) -> Result<f64> {
+        match self {
+            Self::Number(val) => Ok(val.to_f64().unwrap_or(f64::NAN)),
+            _ => Err(Error::ShouldBeNumber()),
+        }
+    }
+
     pub fn list(self) -> Result<Vec<Value>> {
         match self {
             Self::List(list) => Ok(list),
             _ => Err(Error::ShouldBeList()),
         }
     }
+
+    // The elements a `...value` contributes to a list literal or call.
+    pub(crate) fn spread(self) -> Result<Vec<Value>> {
+        match self {
+            Self::List(list) => Ok(list),
+            other => Err(Error::NotSpreadable(other.type_name())),
+        }
+    }
+
+    pub fn map(self) -> Result<Map> {
+        match self {
+            Self::Map(m) => Ok(m),
+            _ => Err(Error::ShouldBeMap()),
+        }
+    }
+
+    /// The value under the string `key`, if this is a map that has it.
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Self::Map(m) => m.get(&Value::from(key)),
+            _ => None,
+        }
+    }
+
+    /// The element at `index`, if this is a list that long.
+    pub fn idx(&self, index: usize) -> Option<&Value> {
+        match self {
+            Self::List(list) => list.get(index),
+            _ => None,
+        }
+    }
+
+    /// The value a JSON Pointer (RFC 6901) refers to, e.g. `/items/0/price`.
+    /// Every segment after a `/` is a string key of a map or an index into
+    /// a list, and a key spells `/` as `~1` and `~` as `~0`. The empty
+    /// pointer is the value itself. `None` when a segment is missing, an
+    /// index is out of range or isn't a plain number like `01` or `-`, or
+    /// the pointer doesn't start with `/`.
+    pub fn pointer(&self, pointer: &str) -> Option<&Value> {
+        pointer_segments(pointer)?.try_fold(self, |value, segment| match value {
+            Self::List(list) => list.get(list_index(&segment)?),
+            _ => value.get(&segment),
+        })
+    }
+
+    /// [`Value::pointer`], mutably.
+    pub fn pointer_mut(&mut self, pointer: &str) -> Option<&mut Value> {
+        pointer_segments(pointer)?.try_fold(self, |value, segment| match value {
+            Self::Map(m) => m.get_mut(&Value::from(segment.as_ref())),
+            Self::List(list) => list.get_mut(list_index(&segment)?),
+            _ => None,
+        })
+    }
+}
+
+// The unescaped segments of a JSON Pointer, none for the empty one.
+fn pointer_segments(pointer: &str) -> Option<impl Iterator<Item = Cow<'_, str>>> {
+    let segments = match pointer {
+        "" => None,
+        _ => Some(pointer.strip_prefix('/')?.split('/')),
+    };
+    Some(
+        segments
+            .into_iter()
+            .flatten()
+            .map(|segment| match segment.contains('~') {
+                true => Cow::Owned(segment.replace("~1", "/").replace("~0", "~")),
+                false => Cow::Borrowed(segment),
+            }),
+    )
+}
+
+// A list index in a pointer is `0` or digits without a leading zero.
+fn list_index(segment: &str) -> Option<usize> {
+    let digits = !segment.is_empty() && segment.bytes().all(|b| b.is_ascii_digit());
+    match digits && (segment == "0" || !segment.starts_with('0')) {
+        true => segment.parse().ok(),
+        false => None,
+    }
+}
+
+// The conversions back, used by `ExprAST::eval_as`. Each type takes one
+// kind of value and fails with `Error::NotConvertible` on the others.
+macro_rules! impl_try_from_value {
+    ($([$target_type:ty, $variant:ident]),+) => {
+        $(
+            impl TryFrom<Value> for $target_type {
+                type Error = Error;
+
+                fn try_from(value: Value) -> Result<Self> {
+                    match value {
+                        Value::$variant(val) => Ok(val),
+                        other => Err(Error::NotConvertible(
+                            other.type_name(),
+                            stringify!($target_type),
+                        )),
+                    }
+                }
+            }
+        )+
+    };
+}
+
+impl_try_from_value!(
+    [bool, Bool],
+    [Decimal, Number],
+    [String, String],
+    [Vec<Value>, List],
+    [Map, Map]
+);
+
+// Only numbers without a fractional part that are in range convert, so
+// nothing is rounded or truncated on the way.
+macro_rules! impl_try_from_value_for_integer {
+    ($([$number_type:tt, $method_name: ident]),+) => {
+        $(
+            impl TryFrom<Value> for $number_type {
+                type Error = Error;
+
+                fn try_from(value: Value) -> Result<Self> {
+                    let err = Error::NotConvertible(value.type_name(), stringify!($number_type));
+                    match value {
+                        Value::Number(val) if val.fract().is_zero() => val.$method_name().ok_or(err),
+                        _ => Err(err),
+                    }
+                }
+            }
+        )+
+    };
+}
+
+impl_try_from_value_for_integer!(
+    [i128, to_i128],
+    [i64, to_i64],
+    [i32, to_i32],
+    [i16, to_i16],
+    [i8, to_i8],
+    [u128, to_u128],
+    [u64, to_u64],
+    [u32, to_u32],
+    [u16, to_u16],
+    [u8, to_u8],
+    [usize, to_usize],
+    [isize, to_isize]
+);
+
+// The nearest float, like `Value::to_f64`.
+macro_rules! impl_try_from_value_for_float {
+    ($([$float_type:tt, $method_name: ident]),+) => {
+        $(
+            impl TryFrom<Value> for $float_type {
+                type Error = Error;
+
+                fn try_from(value: Value) -> Result<Self> {
+                    let err = Error::NotConvertible(value.type_name(), stringify!($float_type));
+                    match value {
+                        Value::Number(val) => val.$method_name().ok_or(err),
+                        _ => Err(err),
+                    }
+                }
+            }
+        )+
+    };
 }
 
+impl_try_from_value_for_float!([f64, to_f64], [f32, to_f32]);
+
 macro_rules! impl_value_from_for_number {
     ($([$number_type:tt, $method_name: ident]),+) => {
         $(
@@ -145,6 +481,122 @@ impl_value_from_for_number!(
     [u32, from_u32],
     [u16, from_u16],
     [u8, from_u8],
-    [f64, from_f64],
-    [f32, from_f32]
+    [usize, from_usize],
+    [isize, from_isize]
 );
+
+// Floats are rounded to the number of significant digits they can represent
+// exactly (15 for f64, 6 for f32), so binary noise such as the trailing
+// digits of `4.35 * 100.0 == 434.99999999999994` doesn't leak into the value.
+// Floats a `Decimal` can't hold become `0`, see `Value::try_from_f64`.
+macro_rules! impl_value_from_for_float {
+    ($([$float_type:tt, $method_name: ident]),+) => {
+        $(
+            impl From<$float_type> for Value {
+                fn from(value: $float_type) -> Self {
+                    Value::Number(
+                        Decimal::$method_name(value)
+                            .and_then(|val| val.round_sf($float_type::DIGITS))
+                            .map(|val| val.normalize())
+                            .unwrap_or_default(),
+                    )
+                }
+            }
+        )+
+    };
+}
+
+impl_value_from_for_float!([f64, from_f64], [f32, from_f32]);
+
+#[cfg(all(test, feature = "default"))]
+mod tests {
+    use crate::{create_context, execute, Value};
+    use rstest::rstest;
+
+    fn order() -> Value {
+        let input = "{'id': 7, 'items': [{'price': 2.5, 'tags': ['a', 'b']}, {'price': 4}], \
+                     'a/b': 1, 'm~n': 2, '': 3, 'nested': {'': {'x': None}}}";
+        execute(input, create_context!()).unwrap()
+    }
+
+    #[rstest]
+    #[case("", Some(order()))]
+    #[case("/id", Some(7.into()))]
+    #[case("/items/0/price", Some(Value::from(2.5)))]
+    #[case("/items/1/price", Some(4.into()))]
+    #[case("/items/0/tags/1", Some("b".into()))]
+    #[case("/a~1b", Some(1.into()))]
+    #[case("/m~0n", Some(2.into()))]
+    #[case("/", Some(3.into()))]
+    #[case("/nested//x", Some(Value::None))]
+    #[case("/missing", None)]
+    #[case("/items/2", None)]
+    #[case("/items/01", None)]
+    #[case("/items/-", None)]
+    #[case("/items/+1", None)]
+    #[case("/items/0/price/x", None)]
+    #[case("/id/0", None)]
+    #[case("items", None)]
+    #[case("/a/b", None)]
+    fn test_pointer(#[case] pointer: &str, #[case] expected: Option<Value>) {
+        assert_eq!(order().pointer(pointer), expected.as_ref());
+    }
+
+    #[test]
+    fn test_pointer_mut() {
+        let mut value = order();
+        *value.pointer_mut("/items/1/price").unwrap() = 5.into();
+        value
+            .pointer_mut("/items/0/tags")
+            .unwrap()
+            .clone_from(&Value::List(Vec::new()));
+        assert_eq!(value.pointer("/items/1/price"), Some(&5.into()));
+        assert_eq!(
+            value.pointer("/items/0/tags"),
+            Some(&Value::List(Vec::new()))
+        );
+        assert!(value.pointer_mut("/items/9").is_none());
+        assert!(value.pointer_mut("/id/x").is_none());
+    }
+
+    #[test]
+    fn test_get_idx() {
+        let value = order();
+        let items = value.get("items").unwrap();
+        assert_eq!(
+            items.idx(1).and_then(|item| item.get("price")),
+            Some(&4.into())
+        );
+        assert_eq!(items.idx(2), None);
+        assert_eq!(items.get("0"), None);
+        assert_eq!(value.get("missing"), None);
+        assert_eq!(value.idx(0), None);
+        assert_eq!(Value::from(1).get("a"), None);
+    }
+
+    #[rstest]
+    #[case("'a, b'", "'a, b'")]
+    #[case("\"it's\"", "\"it's\"")]
+    #[case("'''it's \"x\"'''", "'''it's \"x\"'''")]
+    #[case("[1, 2.50, -3, true, null]", "[1, 2.50, -3, true, null]")]
+    #[case("{b: [2, 3], a: 1}", "{'b': [2, 3], 'a': 1}")]
+    #[case("{1: {'x': ['y', {}]}, true: []}", "{1: {'x': ['y', {}]}, true: []}")]
+    #[case(
+        "{'q': \"it's, 'quoted'\", 'l': [\"a,b\", '{c}']}",
+        "{'q': \"it's, 'quoted'\", 'l': ['a,b', '{c}']}"
+    )]
+    fn test_display(#[case] input: &str, #[case] expected: &str) {
+        let value = execute(input, create_context!()).unwrap();
+        assert_eq!(value.to_string(), expected);
+        assert_eq!(execute(expected, create_context!()).unwrap(), value);
+    }
+
+    #[test]
+    fn test_display_order() {
+        let mut m = crate::Map::default();
+        m.insert("z".into(), 1.into());
+        m.insert("a".into(), Value::None);
+        m.insert("z".into(), 2.into());
+        assert_eq!(Value::Map(m).to_string(), "{'z': 2, 'a': null}");
+    }
+}
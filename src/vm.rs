@@ -0,0 +1,309 @@
+use crate::compiler::{Instruction, Program};
+use crate::context::Context;
+use crate::define::Result;
+use crate::error::Error;
+use crate::function::{InnerFunction, InnerFunctionManager};
+use crate::options::EvalOptions;
+use crate::value::{Map, Value};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+/// Runs [`Program`]s produced by [`crate::compiler::Compiler`]. The results,
+/// errors and context writes are the same as evaluating the source
+/// expression with [`crate::ExprAST::exec`].
+pub struct Vm;
+
+impl Vm {
+    pub fn run(program: &Program, ctx: &mut Context) -> Result<Value> {
+        let mut machine = Machine {
+            program,
+            options: ctx.options(),
+            stack: Vec::new(),
+            resolved: vec![None; program.functions.len()],
+            pc: 0,
+        };
+        while let Some(instruction) = program.instructions.get(machine.pc) {
+            let at = machine.pc;
+            machine.pc += 1;
+            machine
+                .step(*instruction, ctx)
+                .map_err(|err| program.wrap_error(err, at))?;
+        }
+        Ok(machine.stack.pop().unwrap_or(Value::None))
+    }
+}
+
+// The state of one run of a program.
+struct Machine<'p> {
+    program: &'p Program,
+    options: EvalOptions,
+    stack: Vec<Value>,
+    // Context functions looked up so far in this run, by function slot.
+    resolved: Vec<Option<Option<Arc<InnerFunction>>>>,
+    pc: usize,
+}
+
+impl Machine<'_> {
+    fn step(&mut self, instruction: Instruction, ctx: &mut Context) -> Result<()> {
+        ctx.tick()?;
+        match instruction {
+            Instruction::Const(i) => self.stack.push(self.program.constants[i].clone()),
+            Instruction::Load(i) => self.stack.push(ctx.value(&self.program.names[i])?),
+            Instruction::LoadTarget(i) => {
+                self.stack.push(ctx.target_value(&self.program.names[i])?)
+            }
+            Instruction::PushNone => self.stack.push(Value::None),
+            Instruction::Pop => {
+                self.stack.pop();
+            }
+            Instruction::Prefix(i) => {
+                let (op, handler) = &self.program.prefix[i];
+                let rhs = self
+                    .options
+                    .prefix_operand(op, self.stack.pop().unwrap(), ctx);
+                self.stack.push(handler(rhs)?);
+            }
+            Instruction::Postfix(i) => {
+                let lhs = self.stack.pop().unwrap();
+                self.stack.push(self.program.postfix[i](lhs)?);
+            }
+            Instruction::Infix(i) => {
+                let rhs = self.stack.pop().unwrap();
+                let lhs = self.stack.pop().unwrap();
+                let (op, handler) = &self.program.infix[i];
+                self.stack
+                    .push(self.options.infix(op, handler, lhs, rhs, ctx)?);
+            }
+            Instruction::Assign(i, target) => {
+                let rhs = self.stack.pop().unwrap();
+                let lhs = self.stack.pop().unwrap();
+                let name = &self.program.names[target.ok_or(Error::NotReferenceExpr)?];
+                let (op, handler) = &self.program.infix[i];
+                let value = self.options.assign(op, handler, lhs, rhs, name, ctx)?;
+                // A variable can shadow a context function of the same name.
+                self.resolved.fill(None);
+                self.stack.push(value);
+            }
+            call @ (Instruction::Call(..) | Instruction::CallList(_)) => {
+                let (i, params) = match call {
+                    Instruction::Call(i, len) => (i, self.stack.split_off(self.stack.len() - len)),
+                    Instruction::CallList(i) => (i, self.stack.pop().unwrap().list()?),
+                    _ => unreachable!(),
+                };
+                let (name, inner) = &self.program.functions[i];
+                ctx.check_call(name)?;
+                let func = self.resolved[i].get_or_insert_with(|| ctx.get_func(name));
+                let ans = match func {
+                    Some(func) => func(params)?,
+                    None => match inner {
+                        Some(func) => func(ctx, params)?,
+                        None => InnerFunctionManager::new().resolve(name, ctx)?(ctx, params)?,
+                    },
+                };
+                self.stack.push(self.options.limits.check(ans)?);
+            }
+            Instruction::List(len) => {
+                let list = self.stack.split_off(self.stack.len() - len);
+                self.stack
+                    .push(self.options.limits.check(Value::List(list))?);
+            }
+            Instruction::Append => {
+                let value = self.stack.pop().unwrap();
+                if let Some(Value::List(list)) = self.stack.last_mut() {
+                    list.push(value);
+                }
+            }
+            Instruction::Extend => {
+                let values = self.stack.pop().unwrap().spread()?;
+                if let Some(Value::List(list)) = self.stack.last_mut() {
+                    list.extend(values);
+                }
+            }
+            Instruction::CheckLimits => {
+                self.options.limits.check_ref(self.stack.last().unwrap())?
+            }
+            Instruction::Map(len) => {
+                let mut flat = self.stack.split_off(self.stack.len() - 2 * len).into_iter();
+                let mut m = Map::with_capacity_and_hasher(len, Default::default());
+                while let (Some(k), Some(v)) = (flat.next(), flat.next()) {
+                    self.options.insert_key(&mut m, k, v)?;
+                }
+                self.stack.push(self.options.limits.check(Value::Map(m))?);
+            }
+            Instruction::Jump(to) => self.pc = to,
+            Instruction::JumpIfFalse(to) => {
+                if !self.options.condition(self.stack.pop().unwrap(), ctx)? {
+                    self.pc = to;
+                }
+            }
+            Instruction::JumpIfNoMatch(to) => {
+                let pattern = self.stack.pop().unwrap();
+                if self.stack.last() == Some(&pattern) {
+                    self.stack.pop();
+                } else {
+                    self.pc = to;
+                }
+            }
+            Instruction::NoMatch => {
+                let scrutinee = self.stack.pop().unwrap();
+                self.stack.push(self.options.no_match(&scrutinee)?);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "default"))]
+mod tests {
+    use crate::compiler::{Compiler, Instruction};
+    use crate::error::Error;
+    use crate::parser::{ExprAST, Literal};
+    use crate::vm::Vm;
+    use crate::{create_context, parse_expression, EvalOptions, Value};
+    use rstest::rstest;
+
+    #[test]
+    fn test_compile_ternary() {
+        let ast = parse_expression("a ? 1 : 2").unwrap();
+        let program = Compiler::compile(&ast).unwrap();
+        assert_eq!(
+            program.instructions(),
+            &[
+                Instruction::Load(0),
+                Instruction::JumpIfFalse(4),
+                Instruction::Const(0),
+                Instruction::Jump(5),
+                Instruction::Const(1),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_compile_spread() {
+        let ast = parse_expression("f(...a, 1)").unwrap();
+        let program = Compiler::compile(&ast).unwrap();
+        assert_eq!(
+            program.instructions(),
+            &[
+                Instruction::List(0),
+                Instruction::Load(0),
+                Instruction::Extend,
+                Instruction::Const(0),
+                Instruction::Append,
+                Instruction::CallList(0),
+            ]
+        );
+    }
+
+    #[rstest]
+    #[case("a ? 1 : 2", 1.into())]
+    #[case("!a ? 1 : 2", 2.into())]
+    #[case("b = 3; b += 4; [b, a]", vec![7.into(), true.into()].into())]
+    #[case("", Value::None)]
+    fn test_run(#[case] input: &str, #[case] output: Value) {
+        let ast = parse_expression(input).unwrap();
+        let program = Compiler::compile(&ast).unwrap();
+        let mut ctx = create_context!("a" => true);
+        assert_eq!(Vm::run(&program, &mut ctx).unwrap(), output);
+    }
+
+    #[test]
+    fn test_run_context_function_shadows_inner() {
+        let ast = parse_expression("max(1, 2)").unwrap();
+        let program = Compiler::compile(&ast).unwrap();
+        let mut ctx = create_context!("max" => Arc::new(|_| Ok(Value::from("ctx"))));
+        assert_eq!(Vm::run(&program, &mut ctx).unwrap(), "ctx".into());
+        assert_eq!(Vm::run(&program, &mut create_context!()).unwrap(), 2.into());
+    }
+
+    #[test]
+    fn test_run_variable_shadows_context_function() {
+        let ast = parse_expression("f(); f = 2; f()").unwrap();
+        let program = Compiler::compile(&ast).unwrap();
+        let mut ctx = create_context!("f" => Arc::new(|_| Ok(Value::from(1))));
+        assert!(Vm::run(&program, &mut ctx).is_err());
+    }
+
+    #[rstest]
+    #[case("1 ? 2 : 3")]
+    #[case("unknown()")]
+    fn test_run_error(#[case] input: &str) {
+        let ast = parse_expression(input).unwrap();
+        let program = Compiler::compile(&ast).unwrap();
+        assert!(Vm::run(&program, &mut create_context!()).is_err());
+    }
+
+    // The parser rejects `3 = 4`, but a tree built by hand still fails when
+    // run instead of assigning.
+    #[test]
+    fn test_run_assign_to_literal() {
+        let ast = ExprAST::Binary(
+            "=",
+            Box::new(ExprAST::Literal(Literal::Number(3.into()))),
+            Box::new(ExprAST::Literal(Literal::Number(4.into()))),
+        );
+        let program = Compiler::compile(&ast).unwrap();
+        let err = Vm::run(&program, &mut create_context!()).unwrap_err();
+        assert!(matches!(err.root(), Error::NotReferenceExpr));
+        let err = ast.exec(&mut create_context!()).unwrap_err();
+        assert!(matches!(err.root(), Error::NotReferenceExpr));
+        let err = ast.exec_iterative(&mut create_context!()).unwrap_err();
+        assert!(matches!(err.root(), Error::NotReferenceExpr));
+    }
+
+    #[rstest]
+    #[case("1 + true", "should be number: in '1 + true'")]
+    #[case(
+        "x = 1 + true",
+        "should be number: in '1 + true' within 'x = 1 + true'"
+    )]
+    #[case(
+        "b = 1; [a ? 2 : 3]; b",
+        "error in statement 2: should be bool, got Number: in 'a ? 2 : 3' within '[a ? 2 : 3]'"
+    )]
+    #[case(
+        "[1, ...a, 2]",
+        "can not spread Number, only a list: in '...a' within '[1,...a,2]'"
+    )]
+    #[case("match 2 {1: 'one'}", "no match arm for 2: in 'match 2 {1:\"one\"}'")]
+    #[case("[len(a)]", "Number has no length: in 'len(a)' within '[len(a)]'")]
+    fn test_run_error_context(#[case] input: &str, #[case] message: &str) {
+        let ast = parse_expression(input).unwrap();
+        let program = Compiler::compile(&ast).unwrap();
+        let ctx = || {
+            let mut ctx = create_context!("a" => 1);
+            ctx.set_options(EvalOptions {
+                strict_match: true,
+                ..EvalOptions::default()
+            });
+            ctx
+        };
+        let err = ast.exec(&mut ctx()).unwrap_err();
+        assert_eq!(err.to_string(), message);
+        let err = ast.exec_iterative(&mut ctx()).unwrap_err();
+        assert_eq!(err.to_string(), message);
+        let err = Vm::run(&program, &mut ctx()).unwrap_err();
+        assert_eq!(err.to_string(), message);
+    }
+
+    #[test]
+    fn test_compile_deep() {
+        let input = vec!["1"; 100_000].join("+") + " + true";
+        let ast = parse_expression(&input).unwrap();
+        let program = Compiler::compile(&ast).unwrap();
+        assert_eq!(
+            Vm::run(&program, &mut create_context!())
+                .unwrap_err()
+                .root()
+                .to_string(),
+            "should be number"
+        );
+        let input = vec!["1"; 100_000].join("+");
+        let ast = parse_expression(&input).unwrap();
+        let program = Compiler::compile(&ast).unwrap();
+        assert_eq!(
+            Vm::run(&program, &mut create_context!()).unwrap(),
+            100_000.into()
+        );
+    }
+}
@@ -0,0 +1,28 @@
+use crate::token::Span;
+use alloc::string::String;
+use core::fmt;
+
+/// Something evaluation reports without failing, collected under
+/// [`crate::EvalOptions::collect_warnings`]. The built-in codes are
+/// `W_LOSSY_FLOAT`, `W_MIXED_TYPE_COMPARISON`, `W_DEPRECATED_FUNCTION` and
+/// `W_TRUTHINESS_COERCION`; functions registered with the context can raise
+/// their own through [`crate::Context::warn`].
+#[derive(Clone, PartialEq, Debug)]
+pub struct Warning {
+    pub code: &'static str,
+    pub message: String,
+    /// The node that raised it, in [`crate::ExprAST::expr`], as found by
+    /// [`crate::ExprAST::exec_with_warnings`]. `None` from
+    /// [`crate::Context::take_warnings`].
+    pub span: Option<Span>,
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.code, self.message)?;
+        match self.span {
+            Some(span) => write!(f, " (at {})", span.0),
+            None => Ok(()),
+        }
+    }
+}
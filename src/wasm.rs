@@ -0,0 +1,158 @@
+//! JavaScript bindings built with `wasm-bindgen`, enabled by the `wasm` feature.
+//!
+//! Values cross the boundary as JSON. Numbers in results are rendered as
+//! strings so that no precision is lost in the conversion to JS floats.
+use crate::compiler::{Compiler, Program};
+use crate::context::Context;
+use crate::define::Result;
+use crate::error::Error;
+use crate::json::json_key;
+use crate::parse_expression;
+use crate::value::Value;
+use crate::vm::Vm;
+use wasm_bindgen::prelude::*;
+
+#[wasm_bindgen]
+pub struct CompiledExpression {
+    program: Program,
+    expr: String,
+}
+
+#[wasm_bindgen]
+impl CompiledExpression {
+    pub fn evaluate(&self, ctx_json: &str) -> std::result::Result<String, JsValue> {
+        run_json(&self.program, ctx_json).map_err(js_error)
+    }
+
+    pub fn expr(&self) -> String {
+        self.expr.clone()
+    }
+}
+
+#[wasm_bindgen]
+pub fn compile(expr: &str) -> std::result::Result<CompiledExpression, JsValue> {
+    let ast = parse_expression(expr).map_err(js_error)?;
+    Ok(CompiledExpression {
+        program: Compiler::compile(&ast).map_err(js_error)?,
+        expr: ast.expr(),
+    })
+}
+
+/// Compiles and runs `expr` once, the same way as
+/// `compile(expr).evaluate(ctxJson)`.
+#[wasm_bindgen]
+pub fn evaluate(expr: &str, ctx_json: &str) -> std::result::Result<String, JsValue> {
+    evaluate_json(expr, ctx_json).map_err(js_error)
+}
+
+#[wasm_bindgen]
+pub fn validate(expr: &str) -> JsValue {
+    js_sys::JSON::parse(&validate_json(expr)).unwrap_or(JsValue::NULL)
+}
+
+fn evaluate_json(expr: &str, ctx_json: &str) -> Result<String> {
+    let program = Compiler::compile(&parse_expression(expr)?)?;
+    run_json(&program, ctx_json)
+}
+
+fn run_json(program: &Program, ctx_json: &str) -> Result<String> {
+    let mut ctx = context_from_json(ctx_json)?;
+    Ok(js_json(Vm::run(program, &mut ctx)?).to_string())
+}
+
+fn context_from_json(ctx_json: &str) -> Result<Context> {
+    let mut ctx = Context::new();
+    if !ctx_json.trim().is_empty() {
+        ctx.set_variables_from_json(ctx_json)?;
+    }
+    Ok(ctx)
+}
+
+// Contexts only carry JSON variables here, so every function has to be an
+// inner one.
+fn validate_json(expr: &str) -> String {
+    let errors = crate::validate(expr, &Context::new())
+        .iter()
+        .map(error_json)
+        .collect();
+    serde_json::Value::Array(errors).to_string()
+}
+
+fn error_json(err: &Error) -> serde_json::Value {
+    let mut m = serde_json::Map::new();
+    m.insert("message".into(), err.to_string().into());
+    m.insert(
+        "position".into(),
+        err.position()
+            .map_or(serde_json::Value::Null, serde_json::Value::from),
+    );
+    serde_json::Value::Object(m)
+}
+
+fn js_json(value: Value) -> serde_json::Value {
+    match value {
+        Value::Number(val) => serde_json::Value::String(val.to_string()),
+        Value::List(values) => serde_json::Value::Array(values.into_iter().map(js_json).collect()),
+        Value::Map(m) => serde_json::Value::Object(
+            m.into_iter()
+                .map(|(k, v)| (json_key(k), js_json(v)))
+                .collect(),
+        ),
+        other => serde_json::Value::from(other),
+    }
+}
+
+fn js_error(err: Error) -> JsValue {
+    js_sys::Error::new(&err.to_string()).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{evaluate_json, run_json, validate_json};
+    use crate::{parse_expression, Compiler};
+    use rstest::rstest;
+
+    #[rstest]
+    #[case("a + 1", "{\"a\": 2}", "\"3\"")]
+    #[case("[a, 'x', b]", "{\"a\": 0.1, \"b\": null}", "[\"0.1\",\"x\",null]")]
+    #[case("{'k': a > 1}", "{\"a\": 2}", "{\"k\":true}")]
+    #[case("2 * 3", "", "\"6\"")]
+    fn test_evaluate_json(#[case] expr: &str, #[case] ctx: &str, #[case] output: &str) {
+        assert_eq!(evaluate_json(expr, ctx).unwrap(), output);
+        let program = Compiler::compile(&parse_expression(expr).unwrap()).unwrap();
+        assert_eq!(run_json(&program, ctx).unwrap(), output);
+    }
+
+    #[rstest]
+    #[case("a +", "{}")]
+    #[case("1", "[1]")]
+    #[case("1", "{")]
+    fn test_evaluate_json_error(#[case] expr: &str, #[case] ctx: &str) {
+        assert!(evaluate_json(expr, ctx).is_err());
+    }
+
+    #[rstest]
+    #[case("1 + true", "should be number: in '1 + true'")]
+    #[case("a ? 1 : 2", "should be bool, got Number: in 'a ? 1 : 2'")]
+    fn test_evaluate_json_error_message(#[case] expr: &str, #[case] message: &str) {
+        let err = evaluate_json(expr, "{\"a\": 1}").unwrap_err();
+        assert_eq!(err.to_string(), message);
+        let program = Compiler::compile(&parse_expression(expr).unwrap()).unwrap();
+        let err = run_json(&program, "{\"a\": 1}").unwrap_err();
+        assert_eq!(err.to_string(), message);
+    }
+
+    #[rstest]
+    #[case("a + 1", "[]")]
+    #[case("'abc", "[{\"message\":\"unterminated string: 4\",\"position\":4}]")]
+    #[case("(1", "[{\"message\":\"no close delim\",\"position\":2}]")]
+    #[case("(1 2", "[{\"message\":\"no close delim\",\"position\":3}]")]
+    #[case(
+        "roud(a) + nothing()",
+        "[{\"message\":\"unknown function 'roud', did you mean 'round' or 'ord'?\",\"position\":0},\
+          {\"message\":\"unknown function 'nothing'\",\"position\":10}]"
+    )]
+    fn test_validate_json(#[case] expr: &str, #[case] output: &str) {
+        assert_eq!(validate_json(expr), output);
+    }
+}
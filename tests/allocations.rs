@@ -0,0 +1,54 @@
+//! Counts the allocations of updating a context in place and evaluating
+//! against it again, which has to stay flat for rules re-evaluated on every
+//! change of their inputs.
+use expression_engine::{parse_expression, Context, Value};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+struct Counting;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+fn allocations(f: impl FnOnce()) -> usize {
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    f();
+    ALLOCATIONS.load(Ordering::Relaxed) - before
+}
+
+// The only test of this binary, so no other thread allocates meanwhile.
+#[test]
+fn test_tick_allocations() {
+    let mut ctx = Context::new();
+    ctx.reserve(50);
+    for i in 0..50 {
+        ctx.set_variable(&format!("v{}", i), Value::from(i));
+    }
+    let ast = parse_expression("v1 > 3 && v2 < 40 || v7 * 2 >= v12 + v30").unwrap();
+    ast.exec(&mut ctx).unwrap();
+    for tick in 0..100 {
+        let update = [
+            ("v1", Value::from(tick)),
+            ("v2", Value::from(tick * 2)),
+            ("v7", Value::from(tick % 5)),
+        ];
+        assert_eq!(allocations(|| ctx.update_from(update)), 0);
+        assert_eq!(allocations(|| drop(ast.exec(&mut ctx).unwrap())), 0);
+    }
+    assert_eq!(ctx.get_variable("v7"), Some(Value::from(4)));
+    // A new name needs its key.
+    assert_ne!(allocations(|| ctx.set_variable("v50", Value::from(1))), 0);
+}
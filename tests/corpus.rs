@@ -0,0 +1,70 @@
+//! Checks every expression of `tests/corpus` against its snapshot. After
+//! an intended change in behavior, `cargo run --example bless_corpus`
+//! rewrites the snapshots, and the diff shows what changed.
+#[path = "corpus/harness.rs"]
+mod harness;
+
+use std::fs;
+
+#[test]
+fn test_corpus() {
+    let files = harness::expr_files().unwrap();
+    assert!(!files.is_empty());
+    let mut failures = Vec::new();
+    for path in files {
+        let source = fs::read_to_string(&path).unwrap();
+        let actual = match harness::render(&source) {
+            Ok(actual) => actual,
+            Err(msg) => {
+                failures.push(msg);
+                continue;
+            }
+        };
+        let snap = harness::snap_path(&path);
+        let expected = fs::read_to_string(&snap).unwrap_or_default();
+        if let Some(block) = first_difference(&expected, &actual) {
+            failures.push(format!(
+                "{}: snapshot differs, got\n{}",
+                snap.display(),
+                block
+            ));
+        }
+    }
+    assert!(
+        failures.is_empty(),
+        "{}\n\nrun `cargo run --example bless_corpus` if the change is intended",
+        failures.join("\n\n")
+    );
+}
+
+// Every expression of the corpus that fails under `EvalOptions::safe()`
+// but not under the defaults is listed, with the restriction it hits.
+#[test]
+fn test_corpus_safe_mode() {
+    let actual = harness::render_safe_mode().unwrap();
+    let snap = harness::safe_mode_snap_path();
+    let expected = fs::read_to_string(&snap).unwrap_or_default();
+    if let Some(block) = first_difference(&expected, &actual) {
+        panic!(
+            "{}: snapshot differs, got\n{}\n\nrun `cargo run --example bless_corpus` if the change is intended",
+            snap.display(),
+            block
+        );
+    }
+}
+
+// The first block of `actual` that isn't in `expected` at the same place,
+// or a note that `expected` has more of them.
+fn first_difference(expected: &str, actual: &str) -> Option<String> {
+    let mut expected = expected.split("\n\n");
+    for block in actual.split("\n\n") {
+        match expected.next() {
+            Some(other) if other == block => {}
+            Some(other) => return Some(format!("{}\ninstead of\n{}", block, other)),
+            None => return Some(format!("{}\nwhich isn't in the snapshot", block)),
+        }
+    }
+    expected
+        .next()
+        .map(|block| format!("{}\nwhich is no longer in the corpus", block))
+}
@@ -0,0 +1,219 @@
+//! Renders the snapshots of the expression corpus, for the `corpus` test
+//! and the `bless_corpus` example.
+//!
+//! Every `*.expr` file in this directory holds one expression per line;
+//! blank lines and lines starting with `#` are skipped. Its `*.snap` file
+//! records, for each expression, the `dump()` of the parsed AST, the
+//! `expr()` round trip and the result of evaluating it in
+//! [`canonical_context`], each in a fresh context.
+use expression_engine::{
+    parse_expression, Compiler, Context, Error, EvalOptions, ExprAST, ExprArena, FixedClock,
+    FixedEnv, Value, Vm,
+};
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, UNIX_EPOCH};
+
+pub const HEADER: &str = "# Generated by `cargo run --example bless_corpus`, don't edit.\n";
+
+pub fn corpus_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/corpus")
+}
+
+/// The `*.expr` files of the corpus, sorted by name.
+pub fn expr_files() -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(corpus_dir())? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|ext| ext == "expr") {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+pub fn snap_path(expr_path: &Path) -> PathBuf {
+    expr_path.with_extension("snap")
+}
+
+/// The snapshot of the expressions that fail only in safe mode.
+pub fn safe_mode_snap_path() -> PathBuf {
+    corpus_dir().join("safe_mode.snap")
+}
+
+/// The variables and functions every expression of the corpus sees. The
+/// clock and the environment are fixed, so `now()` and `env()` give the
+/// same result on every machine.
+pub fn canonical_context() -> Context {
+    let mut ctx = Context::new();
+    ctx.set_variable("n", 7.into());
+    ctx.set_variable("x", Value::from(2.5));
+    ctx.set_variable("neg", (-3).into());
+    ctx.set_variable("s", "Hello".into());
+    ctx.set_variable("empty", "".into());
+    ctx.set_variable("flag", true.into());
+    ctx.set_variable("nothing", Value::None);
+    ctx.set_variable("list", Value::List(vec![1.into(), 2.into(), 3.into()]));
+    let mut user = expression_engine::Map::default();
+    user.insert("name".into(), "Ada".into());
+    user.insert("age".into(), 36.into());
+    user.insert(
+        "tags".into(),
+        Value::List(vec!["admin".into(), "dev".into()]),
+    );
+    ctx.set_variable("user", Value::Map(user));
+    ctx.set_func(
+        "double",
+        Arc::new(|params| Ok(Value::from(params[0].clone().integer()? * 2))),
+    );
+    ctx.set_clock(Arc::new(FixedClock(
+        UNIX_EPOCH + Duration::from_secs(1_700_000_000),
+    )));
+    ctx.set_env(Arc::new(FixedEnv(HashMap::from([(
+        "REGION".to_string(),
+        "eu-north".to_string(),
+    )]))));
+    ctx
+}
+
+/// [`canonical_context`] with [`EvalOptions::safe`].
+pub fn safe_context() -> Context {
+    let mut ctx = canonical_context();
+    ctx.set_options(EvalOptions::safe());
+    ctx
+}
+
+/// The snapshot of every expression in `source`, the text of an `*.expr`
+/// file. Fails with a message when the backends disagree about one of
+/// them, since blessing that would hide a bug.
+pub fn render(source: &str) -> Result<String, String> {
+    let mut out = HEADER.to_string();
+    for line in source.lines() {
+        let input = line.trim();
+        if input.is_empty() || input.starts_with('#') {
+            continue;
+        }
+        out.push('\n');
+        out.push_str(&snapshot(input)?);
+    }
+    Ok(out)
+}
+
+fn snapshot(input: &str) -> Result<String, String> {
+    let mut out = format!("{}\n", input);
+    let ast = match parse_expression(input) {
+        Ok(ast) => ast,
+        Err(err) => {
+            writeln!(out, "  ast: {}", format_error(&err)).unwrap();
+            return Ok(out);
+        }
+    };
+    writeln!(out, "  ast: {}", ast.dump()).unwrap();
+    writeln!(out, "  expr: {}", ast.expr()).unwrap();
+    let result = ast.exec(&mut canonical_context());
+    check_backends(input, &ast, &result, canonical_context)?;
+    match result {
+        Ok(value) => writeln!(
+            out,
+            "  exec: {} ({})",
+            format_value(&value),
+            value.type_name()
+        ),
+        Err(err) => writeln!(out, "  exec: {}", format_error(&err)),
+    }
+    .unwrap();
+    Ok(out)
+}
+
+/// The snapshot of the expressions of every `*.expr` file that evaluate
+/// in [`canonical_context`] but fail in [`safe_context`], with the error.
+pub fn render_safe_mode() -> Result<String, String> {
+    let mut out = HEADER.to_string();
+    let files = expr_files().map_err(|err| err.to_string())?;
+    for path in files {
+        let source = fs::read_to_string(&path).map_err(|err| err.to_string())?;
+        for line in source.lines() {
+            let input = line.trim();
+            if input.is_empty() || input.starts_with('#') {
+                continue;
+            }
+            let Ok(ast) = parse_expression(input) else {
+                continue;
+            };
+            if ast.exec(&mut canonical_context()).is_err() {
+                continue;
+            }
+            let result = ast.exec(&mut safe_context());
+            check_backends(input, &ast, &result, safe_context)?;
+            if let Err(err) = result {
+                write!(out, "\n{}\n  safe: {}\n", input, format_error(&err)).unwrap();
+            }
+        }
+    }
+    Ok(out)
+}
+
+// The other backends don't wrap errors in the same context, so only the
+// root errors are compared.
+fn check_backends(
+    input: &str,
+    ast: &ExprAST,
+    expected: &Result<Value, Error>,
+    context: fn() -> Context,
+) -> Result<(), String> {
+    let expected = outcome(expected);
+    let others = [
+        ("exec_iterative", ast.exec_iterative(&mut context())),
+        (
+            "vm",
+            Compiler::compile(ast).and_then(|program| Vm::run(&program, &mut context())),
+        ),
+        ("arena", ExprArena::from(ast).exec(&mut context())),
+    ];
+    for (backend, result) in others {
+        let actual = outcome(&result);
+        if actual != expected {
+            return Err(format!(
+                "{}: {} gives {} but exec gives {}",
+                input, backend, actual, expected
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn outcome(result: &Result<Value, Error>) -> String {
+    match result {
+        Ok(value) => format_value(value),
+        Err(err) => format_error(err.root()),
+    }
+}
+
+fn format_error(err: &Error) -> String {
+    format!("error {}: {}", err.code(), err)
+}
+
+fn format_value(value: &Value) -> String {
+    match value {
+        Value::String(val) => format!("{:?}", val),
+        Value::Number(val) => val.to_string(),
+        Value::Bool(val) => val.to_string(),
+        Value::List(values) => {
+            let values: Vec<String> = values.iter().map(format_value).collect();
+            format!("[{}]", values.join(", "))
+        }
+        Value::Map(m) => {
+            let entries: Vec<String> = m
+                .iter()
+                .map(|(k, v)| format!("{}: {}", format_value(k), format_value(v)))
+                .collect();
+            format!("{{{}}}", entries.join(", "))
+        }
+        Value::None => "None".to_string(),
+    }
+}
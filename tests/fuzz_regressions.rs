@@ -0,0 +1,45 @@
+//! Inputs that once crashed the tokenizer or the parser, found by the
+//! targets in `fuzz/`. They must give a result, most often an error, and
+//! what parses must print and parse again without a crash.
+use expression_engine::{parse_arena, parse_expression, Tokenizer};
+use rstest::rstest;
+
+#[rstest]
+// A multi-byte char right after an operator char was sliced in half.
+#[case("=\u{a0}")]
+#[case("+é")]
+#[case("a <€")]
+#[case("1 *𝟘")]
+#[case("!\u{3000}x")]
+#[case("x ?é: 1")]
+// A lone backslash, reported to panic the tokenizer.
+#[case("\\")]
+#[case("a \\ b")]
+#[case("'\\")]
+// Nesting that overflowed the stack before the parser limited it.
+#[case(&"(".repeat(20_000))]
+#[case(&"[".repeat(20_000))]
+#[case(&"{".repeat(20_000))]
+#[case(&"f(".repeat(20_000))]
+#[case(&"-".repeat(20_000))]
+#[case(&"not ".repeat(20_000))]
+#[case(&"a = ".repeat(20_000))]
+#[case(&"a ? ".repeat(20_000))]
+#[case(&"match ".repeat(20_000))]
+// A long run of words, which the tokenizer recursed over once per word.
+#[case(&"a ".repeat(20_000))]
+fn test_no_crash(#[case] input: &str) {
+    for token in Tokenizer::new(input).spanned_with_trivia() {
+        if token.is_err() {
+            break;
+        }
+    }
+    let _ = parse_arena(input);
+    if let Ok(ast) = parse_expression(input) {
+        let text = ast.expr();
+        let reparsed = parse_expression(&text);
+        if let Ok(reparsed) = &reparsed {
+            let _ = reparsed.expr();
+        }
+    }
+}
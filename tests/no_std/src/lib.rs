@@ -0,0 +1,39 @@
+// The tests run on the host, which links `std` for the harness, but the
+// engine is still built without it.
+#![cfg_attr(not(test), no_std)]
+
+extern crate alloc;
+
+use expression_engine::{create_context, execute, parse_expression, Result, Value};
+
+pub fn eligible(age: i32, country: &str) -> Result<bool> {
+    let ctx = create_context!("age" => age, "country" => country);
+    execute("age >= 18 && country in ['NO', 'SE']", ctx)?.bool()
+}
+
+pub fn total(price: f64, qty: i32) -> Result<Value> {
+    let ast = parse_expression("round(price * qty * (1 + tax()), 2)")?;
+    let mut ctx = create_context!(
+        "price" => price,
+        "qty" => qty,
+        "tax" => Arc::new(|_| Ok(Value::from(0.25)))
+    );
+    ast.exec(&mut ctx)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_eligible() {
+        assert!(!eligible(16, "NO").unwrap());
+        assert!(eligible(30, "SE").unwrap());
+        assert!(!eligible(30, "DK").unwrap());
+    }
+
+    #[test]
+    fn test_total() {
+        assert_eq!(total(9.99, 3).unwrap(), Value::from(37.46));
+    }
+}
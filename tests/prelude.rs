@@ -0,0 +1,40 @@
+//! The public API as a host sees it: nothing but the prelude is imported,
+//! and nothing is set up before the first call.
+use expression_engine::prelude::*;
+
+#[test]
+fn test_compile_and_eval() {
+    let ast = parse_expression("total = price * qty; total > limit ? 'review' : 'ok'").unwrap();
+    let program = Compiler::compile(&ast).unwrap();
+    let ctx = || create_context!("price" => 12.5, "qty" => 4, "limit" => 40);
+
+    let mut vm_ctx = ctx();
+    assert_eq!(
+        Vm::run(&program, &mut vm_ctx).unwrap(),
+        Value::from("review")
+    );
+    assert_eq!(vm_ctx.value("total").unwrap(), Value::from(50));
+
+    let mut tree_ctx = ctx();
+    assert_eq!(ast.exec(&mut tree_ctx).unwrap(), Value::from("review"));
+    assert_eq!(
+        execute("round(price / 3, 2)", ctx()).unwrap(),
+        Value::from(4.17)
+    );
+}
+
+#[test]
+fn test_functions_and_errors() {
+    let mut ctx = create_context!(
+        "name" => "ada",
+        "greet" => Arc::new(|params| Ok(Value::from(format!("hi {}", params[0].clone().string()?))))
+    );
+    let ast = parse_expression("greet(capitalize(name))").unwrap();
+    assert_eq!(ast.exec(&mut ctx).unwrap(), Value::from("hi Ada"));
+
+    ctx.set_options(EvalOptions::safe());
+    let err: Error = execute("x = 1", ctx).unwrap_err();
+    assert_eq!(err.code(), "E_LIMIT_ASSIGNMENT_FORBIDDEN");
+    let result: Result<ExprAST> = parse_expression("1 +");
+    assert!(result.is_err());
+}